@@ -28,6 +28,9 @@ extern crate simple_ipc;
 extern crate getopts;
 extern crate pmu_x86;
 extern crate mod_mgmt;
+extern crate preemption;
+extern crate tss;
+extern crate x86_64;
 
 use core::str;
 use alloc::vec::Vec;
@@ -79,6 +82,7 @@ pub fn main(args: Vec<String>) -> isize {
 
     opts.optflag("", "null", "null syscall");
     opts.optflag("", "ctx", "inter-thread context switching overhead");
+    opts.optflag("", "tss_rsp0", "setting the TSS RSP0 entry upon a switch to a user task, via a lookup vs. the cached TSS");
     opts.optflag("", "spawn", "process creation");
     opts.optflag("", "memory_map", "create and destroy a memory mapping");
     opts.optflag("", "ipc", "1-byte IPC round trip time. Need to specify channel type ('a' or 'r')");
@@ -143,6 +147,8 @@ pub fn main(args: Vec<String>) -> isize {
 			do_spawn()
 		} else if matches.opt_present("ctx") {
 			do_ctx()
+		} else if matches.opt_present("tss_rsp0") {
+			do_tss_rsp0()
 		} else if matches.opt_present("memory_map") {
 			if cfg!(bm_map) {
 				do_memory_map()
@@ -231,6 +237,64 @@ fn do_null() -> Result<(), &'static str> {
 	Ok(())
 }
 
+/// Measures the time to set the current CPU's TSS RSP0 entry, which is part of
+/// every context switch to a user task.
+/// Compares looking up and locking the CPU's TSS, which `tss_set_rsp0` used to do,
+/// with `tss_set_rsp0` itself, which uses a cached pointer to the CPU's TSS.
+fn do_tss_rsp0() -> Result<(), &'static str> {
+	let overhead_ct = hpet_timing_overhead()?;
+	print_header(TRIES, ITERATIONS*1000);
+
+	let mut lookup = Vec::with_capacity(TRIES);
+	let mut cached = Vec::with_capacity(TRIES);
+	for _ in 0..TRIES {
+		lookup.push(do_tss_rsp0_inner(overhead_ct, false)?);
+		cached.push(do_tss_rsp0_inner(overhead_ct, true)?);
+	}
+
+	let lookup_stats = calculate_stats(&lookup).ok_or("couldn't calculate stats")?;
+	let cached_stats = calculate_stats(&cached).ok_or("couldn't calculate stats")?;
+	printlninfo!("TSS RSP0 lookup result: ({} per 1000 iterations)", T_UNIT);
+	printlninfo!("{:?}", lookup_stats);
+	printlninfo!("TSS RSP0 cached result: ({} per 1000 iterations)", T_UNIT);
+	printlninfo!("{:?}", cached_stats);
+	printlninfo!("This test does not have an equivalent test in LMBench");
+	Ok(())
+}
+
+/// Internal function that sets the TSS RSP0 entry many times,
+/// either via the `cached` pointer or by looking up the TSS.
+fn do_tss_rsp0_inner(overhead_ct: u64, cached: bool) -> Result<u64, &'static str> {
+	let hpet = get_hpet().ok_or("Could not retrieve hpet counter")?;
+	let tmp_iterations = ITERATIONS *1000;
+
+	// The current CPU must not change, and its original RSP0 must be restored afterwards.
+	let _held_preemption = preemption::hold_preemption();
+	let original_rsp0 = tss::tss_rsp0().ok_or("the current CPU has no TSS")?;
+
+	let start_hpet = hpet.get_counter();
+	if cached {
+		for _ in 0..tmp_iterations {
+			tss::tss_set_rsp0(core::hint::black_box(original_rsp0));
+		}
+	} else {
+		for _ in 0..tmp_iterations {
+			let cpu_tss = tss::get_tss(cpu::current_cpu()).ok_or("the current CPU has no TSS")?;
+			cpu_tss.tss().lock().privilege_stack_table[0] = x86_64::VirtAddr::new(original_rsp0.value() as u64);
+		}
+	}
+	let end_hpet = hpet.get_counter();
+	tss::tss_set_rsp0(original_rsp0);
+
+	let mut delta_hpet: u64 = end_hpet - start_hpet;
+	if delta_hpet < overhead_ct { // Erroneous case
+		printlnwarn!("Ignore overhead for tss_rsp0 because overhead({}) > diff({})", overhead_ct, delta_hpet);
+	} else {
+		delta_hpet -= overhead_ct;
+	}
+	Ok(hpet_2_time("", delta_hpet) / (ITERATIONS as u64))
+}
+
 /// Internal function that actually calculates the time for null syscall.
 /// Measures this by calling `get_my_current_task_id` of the current task.
 fn do_null_inner(overhead_ct: u64, th: usize, nr: usize) -> Result<u64, &'static str> {
@@ -0,0 +1,150 @@
+//! Shows the detected CPU topology, and optionally benchmarks how it affects communication.
+//!
+//! * `cputopo` prints the same table as the `/cpu_topology` file.
+//! * `cputopo -b [ROUNDS]` runs a channel ping-pong between two tasks,
+//!   first with one task placed via [`Placement::SameLlcAs`] the other,
+//!   then with the two tasks on CPUs that are as far apart as possible.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use app_io::{print, println};
+use cpu::CpuId;
+use cpu_topology::{Distance, Placement};
+use getopts::Options;
+use spin::Mutex;
+use time::{Duration, Instant};
+
+/// The default number of round trips in the ping-pong benchmark.
+const DEFAULT_ROUNDS: usize = 10_000;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("b", "bench", "run a channel ping-pong benchmark between near and far CPUs");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    print!("{}", cpu_topology::report());
+
+    if matches.opt_present("b") {
+        let rounds = match matches.free.first().map(|r| r.parse::<usize>()) {
+            None => DEFAULT_ROUNDS,
+            Some(Ok(rounds)) if rounds > 0 => rounds,
+            Some(_) => {
+                println!("Error: invalid number of rounds");
+                return -1;
+            }
+        };
+        if let Err(e) = bench(rounds) {
+            println!("Error: {}", e);
+            return -1;
+        }
+    }
+
+    0
+}
+
+/// Measures the round-trip latency of a channel between a responder task
+/// on the current CPU and an initiator task placed near it or far from it.
+fn bench(rounds: usize) -> Result<(), &'static str> {
+    let home = cpu::current_cpu();
+    println!("\nChannel ping-pong, {} round trips, responder on CPU {}:", rounds, home);
+
+    let (cpu, latency) = ping_pong(home, Target::Placement(Placement::SameLlcAs(home)), rounds)?;
+    print_result("placed with SameLlcAs", home, cpu, latency);
+
+    // Pick the farthest CPU from the responder, if there is one other than itself.
+    match cpu_topology::cpus_by_distance(home).last() {
+        Some(&(far, _)) => {
+            let (cpu, latency) = ping_pong(home, Target::Cpu(far), rounds)?;
+            print_result("farthest CPU", home, cpu, latency);
+        }
+        None => println!("  (no other CPU to compare against)"),
+    }
+    Ok(())
+}
+
+/// Where to spawn the initiator task of a ping-pong benchmark.
+enum Target {
+    Placement(Placement),
+    Cpu(CpuId),
+}
+
+/// Returns the CPU that the initiator ran on and the average round-trip latency.
+fn ping_pong(home: CpuId, target: Target, rounds: usize) -> Result<(CpuId, Duration), &'static str> {
+    let (ping_tx, ping_rx) = sync_channel::new_channel::<usize>(1);
+    let (pong_tx, pong_rx) = sync_channel::new_channel::<usize>(1);
+    let result = Arc::new(Mutex::new(None));
+
+    let responder = spawn::new_task_builder(move |_: ()| {
+        for _ in 0..rounds {
+            let Ok(value) = ping_rx.receive() else { return };
+            if pong_tx.send(value).is_err() {
+                return;
+            }
+        }
+    }, ())
+        .name(String::from("cputopo_responder"))
+        .pin_on_cpu(home)
+        .spawn()?;
+
+    let initiator_result = Arc::clone(&result);
+    let initiator = spawn::new_task_builder(move |_: ()| {
+        let start = Instant::now();
+        for i in 0..rounds {
+            if ping_tx.send(i).is_err() || pong_rx.receive() != Ok(i) {
+                return;
+            }
+        }
+        let elapsed = start.elapsed();
+        *initiator_result.lock() = Some((cpu::current_cpu(), elapsed / rounds as u32));
+    }, ())
+        .name(String::from("cputopo_initiator"));
+    let initiator = match target {
+        Target::Placement(placement) => initiator.placement(placement),
+        Target::Cpu(cpu) => initiator.pin_on_cpu(cpu),
+    }
+        .spawn()?;
+
+    initiator.join()?;
+    responder.join()?;
+    let result = result.lock().take();
+    result.ok_or("the ping-pong tasks failed to communicate")
+}
+
+fn print_result(label: &str, home: CpuId, cpu: CpuId, latency: Duration) {
+    let distance = match cpu_topology::distance(home, cpu) {
+        Some(Distance::SameCpu) => "same CPU",
+        Some(Distance::SameCore) => "SMT sibling",
+        Some(Distance::SameLlc) => "shared LLC",
+        Some(Distance::SamePackage) => "same package",
+        Some(Distance::Remote) => "different package",
+        None => "unknown distance",
+    };
+    println!("  {:<24} CPU {:<4} ({:<17}) {:>8} ns per round trip",
+        label, cpu.value(), distance, latency.as_nanos(),
+    );
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: cputopo [-b [ROUNDS]]
+Shows which CPUs share a core (SMT siblings), a last-level cache, or a package.
+With -b, measures the latency of a channel between tasks on nearby and distant CPUs.";
@@ -0,0 +1,141 @@
+//! Controls the opt-in "deep crash context" features of the `crash_context` crate.
+//!
+//! * `crashctx` shows whether branch recording is supported and enabled, and any trace in progress.
+//! * `crashctx lbr <on | off> [ID]` enables or disables branch recording for all tasks or one task.
+//! * `crashctx trace <ID> <COUNT>` single-steps the next `COUNT` instructions of a task.
+//! * `crashctx trace cancel` stops the trace in progress.
+//! * `crashctx dump` prints the instructions recorded by the most recent trace.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+use memory::VirtualAddress;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    let result = match matches.free.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [] => {
+            print_status();
+            Ok(())
+        }
+        ["lbr", state] => parse_state(state)
+            .and_then(crash_context::set_enabled_for_all)
+            .map(|_| print_status()),
+        ["lbr", state, id] => parse_state(state).and_then(|enable| {
+            let task = get_task(id)?;
+            crash_context::set_enabled_for_task(&task, enable)?;
+            println!("Branch recording {} for task {}", if enable { "enabled" } else { "disabled" }, task.id);
+            Ok(())
+        }),
+        ["trace", "cancel"] => {
+            crash_context::cancel_trace();
+            Ok(())
+        }
+        ["trace", id, count] => count.parse::<usize>()
+            .map_err(|_| "invalid instruction count")
+            .and_then(|count| {
+                let task = get_task(id)?;
+                crash_context::trace_task(task.id, count)?;
+                println!("Tracing the next {} instructions of task {}; see `crashctx dump`",
+                    count.min(crash_context::TRACE_BUFFER_LEN), task.id,
+                );
+                Ok(())
+            }),
+        ["dump"] => {
+            print_trace();
+            Ok(())
+        }
+        _ => {
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+fn parse_state(state: &str) -> Result<bool, &'static str> {
+    match state {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err("expected `on` or `off`"),
+    }
+}
+
+fn get_task(id: &str) -> Result<task::TaskRef, &'static str> {
+    let id = id.parse::<usize>().map_err(|_| "invalid task ID")?;
+    task::get_task(id)
+        .and_then(|weak| weak.upgrade())
+        .ok_or("no task with that ID exists")
+}
+
+fn print_status() {
+    match crash_context::lbr_depth() {
+        Some(depth) => println!(
+            "Branch recording: supported ({} entries), {} for all tasks",
+            depth,
+            if crash_context::is_enabled_for_all() { "enabled" } else { "disabled" },
+        ),
+        None => println!("Branch recording: unsupported"),
+    }
+    match crash_context::trace_status() {
+        Some((id, remaining)) => println!("Tracing task {}: {} instructions remaining", id, remaining),
+        None => println!("Tracing: idle"),
+    }
+}
+
+fn print_trace() {
+    let trace = crash_context::trace_buffer();
+    if trace.is_empty() {
+        println!("The trace buffer is empty.");
+        return;
+    }
+    let namespace = task::with_current_task(|t| t.get_namespace().clone()).ok();
+    for rip in trace {
+        let symbol = namespace.as_ref()
+            .and_then(|ns| ns.get_section_containing_address(VirtualAddress::new_canonical(rip as usize), false))
+            .map(|(sec, offset)| format!("{} + {:#X}", sec.name, offset))
+            .unwrap_or_else(|| String::from("??"));
+        println!("  {:>#018X} in {}", rip, symbol);
+    }
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: crashctx
+       crashctx lbr <on | off> [ID]
+       crashctx trace <ID> <COUNT>
+       crashctx trace cancel
+       crashctx dump
+Controls the extra context recorded for detailed crash reports.
+While branch recording is enabled for a task, the crash report of a fatal exception
+in that task lists the branches that led up to it. Without an ID, it applies to all tasks.
+Tracing single-steps the next COUNT instructions of a task, which can then be printed with `dump`.";
@@ -0,0 +1,64 @@
+//! Shows the I/O statistics of all storage devices, and optionally their SMART health.
+//!
+//! * `diskstat` prints the same per-device table as the `/diskstats` file.
+//! * `diskstat -s` also reads and prints the SMART health and attributes of each device.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::{print, println};
+use getopts::Options;
+use storage_manager::StorageDevice;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("s", "smart", "read the SMART health and attributes of each device");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    print!("{}", storage_manager::report());
+
+    if matches.opt_present("s") {
+        for (i, device) in storage_manager::storage_devices().enumerate() {
+            println!();
+            // SMART data is read from the device on demand, which may take a while.
+            match device.lock().smart_data() {
+                Ok(smart) => {
+                    println!("Disk {}: SMART health {}", i, smart.health);
+                    println!("  {:>3} {:<36} {:>7} {:>5} {:>16}", "ID", "ATTRIBUTE", "CURRENT", "WORST", "RAW");
+                    for attr in &smart.attributes {
+                        println!("  {:>3} {:<36} {:>7} {:>5} {:>16}",
+                            attr.id, attr.name().unwrap_or("Unknown"), attr.current, attr.worst, attr.raw,
+                        );
+                    }
+                }
+                Err(e) => println!("Disk {}: SMART unavailable: {}", i, e),
+            }
+        }
+    }
+
+    0
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: diskstat [OPTIONS]
+Shows the I/O statistics of all storage devices.
+Latencies are in microseconds; HIT% is the block cache hit ratio.";
@@ -0,0 +1,71 @@
+//! Lists the drivers registered with the device manager and whether each one was initialized,
+//! followed by the optional drivers known to Theseus and the state of each one:
+//! whether it was compiled in, whether its device was found, and whether it's active.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    {
+        use device_manager::registry::InitState;
+
+        println!("{:<12} STATE", "DRIVER");
+        for (name, state) in device_manager::registry::driver_states() {
+            match state {
+                InitState::Pending     => println!("{:<12} pending", name),
+                InitState::Initialized => println!("{:<12} initialized", name),
+                InitState::Failed(e)   => println!("{:<12} failed to initialize: {}", name, e),
+                InitState::Skipped(e)  => println!("{:<12} skipped: {}", name, e),
+            }
+        }
+        println!();
+    }
+
+    #[cfg(target_arch = "x86_64")] {
+        use device_manager::DriverState;
+
+        println!("{:<10} STATE", "OPTIONAL");
+        for (name, state) in device_manager::optional_driver_states() {
+            match state {
+                DriverState::NotCompiled => println!("{:<10} not compiled", name),
+                DriverState::NotPresent  => println!("{:<10} compiled, device not present", name),
+                DriverState::Failed(e)   => println!("{:<10} compiled, failed to initialize: {}", name, e),
+                DriverState::Active      => println!("{:<10} active", name),
+            }
+        }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    println!("Optional drivers are not yet supported on this architecture.");
+
+    0
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: drivers [OPTIONS]
+Lists the registered drivers and whether each one was initialized,
+then the optional drivers and whether each one is not compiled in, compiled in but not present, or active.";
@@ -0,0 +1,67 @@
+//! Lists the regions of virtual memory mapped in the kernel's page table
+//! along with their permissions, and checks that no region is both
+//! writable and executable (W^X).
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("x", "violations", "only list regions that are both writable and executable");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    let Some(kernel_mmi_ref) = memory::get_kernel_mmi_ref() else {
+        println!("Error: couldn't get the kernel's memory management info");
+        return -1;
+    };
+    let regions = kernel_mmi_ref.lock().page_table.mapped_regions();
+
+    let only_violations = matches.opt_present("x");
+    let mut violations = 0;
+    for region in &regions {
+        let violates = region.writable && region.executable;
+        if violates {
+            violations += 1;
+        } else if only_violations {
+            continue;
+        }
+        println!("{:#018X} - {:#018X} {:>12} bytes  {}{}{}",
+            region.start,
+            region.start + region.size_in_bytes,
+            region.size_in_bytes,
+            if region.writable { "RW" } else { "R-" },
+            if region.executable { "X" } else { "-" },
+            if violates { "  <-- violates W^X" } else { "" },
+        );
+    }
+
+    println!("{} mapped regions, {} writable and executable.", regions.len(), violations);
+    if violations > 0 { -1 } else { 0 }
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: dump_mappings [OPTION]
+Lists the kernel's virtual memory mappings and their permissions.
+Returns an error if any mapping is both writable and executable.";
@@ -0,0 +1,79 @@
+//! Shows the health of the kernel's entropy pools and the contributions of each entropy source.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn run() -> Result<(), String> {
+    let stats = random::stats();
+    let health = if stats.rdseed.words != 0 || stats.rdrand.words != 0 || stats.reseeds != 0 {
+        "healthy"
+    } else if stats.tsc_seeded {
+        "WEAK: seeded from the TSC only, awaiting interrupt entropy"
+    } else {
+        "not yet seeded"
+    };
+    println!("Pool health: {}", health);
+    println!("Reseeds: {}, samples since last reseed: {}", stats.reseeds, stats.unfolded_samples);
+    println!();
+    println!("{:<10}  {:>9}  {:>12}  {:>9}", "SOURCE", "SUPPORTED", "CONTRIBUTED", "FAILURES");
+    for (name, source) in [("RDSEED", stats.rdseed), ("RDRAND", stats.rdrand)] {
+        println!("{:<10}  {:>9}  {:>6} words  {:>9}",
+            name, if source.supported { "yes" } else { "no" }, source.words, source.failures,
+        );
+    }
+    println!("{:<10}  {:>9}  {:>4} samples  {:>9}", "interrupts", "yes", stats.interrupt_samples, "-");
+    println!("{:<10}  {:>9}  {:>4} samples  {:>9}", "keyboard", "yes", stats.keyboard_samples, "-");
+    println!();
+    println!("{:<4}  {:>10}", "CPU", "SAMPLES");
+    for (cpu, samples) in random::cpu_pool_samples() {
+        println!("{:<4}  {:>10}", cpu, samples);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn run() -> Result<(), String> {
+    Err(String::from("entropy collection is only supported on x86_64"))
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: entropy [OPTION]
+Shows the health of the kernel's entropy pools and how much each entropy source has contributed.
+Interrupt and keyboard timings are collected into per-CPU pools, which are folded into
+the global pool whenever the random number generator is reseeded.";
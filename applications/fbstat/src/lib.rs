@@ -0,0 +1,128 @@
+//! Prints statistics about flushing the early framebuffer printer's back buffer
+//! to the real framebuffer, and optionally disables double buffering.
+//!
+//! With `-s`, this also measures how fast a scrolled back buffer can be copied
+//! into framebuffer-sized memory mapped with each [`MemoryType`],
+//! which shows the benefit of mapping the framebuffer as write-combining.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+use memory::{MemoryType, PteFlags, PteFlagsArch};
+use time::{Duration, Instant};
+
+/// The size of the simulated framebuffer used by the scroll benchmark: 1280x800 32-bit pixels.
+const SCROLL_BENCH_FB_SIZE: usize = 1280 * 800 * 4;
+/// The number of bytes that one scroll step moves the contents up by: one 16-pixel-high text row.
+const SCROLL_BENCH_ROW_SIZE: usize = 1280 * 16 * 4;
+/// The number of times the framebuffer is scrolled for each memory type.
+const SCROLL_BENCH_ITERATIONS: usize = 50;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("r", "rate", "sample the statistics over one second and print the flush rate");
+    opts.optopt("b", "double-buffering", "enable or disable double buffering", "on|off");
+    opts.optflag("s", "scroll-bench", "measure scrolling into memory mapped with each memory type");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    if let Some(setting) = matches.opt_str("b") {
+        match setting.as_str() {
+            "on" => early_printer::set_double_buffering(true),
+            "off" => early_printer::set_double_buffering(false),
+            _ => {
+                println!("invalid double buffering setting {:?}, expected \"on\" or \"off\"", setting);
+                return -1;
+            }
+        }
+    }
+
+    let before = early_printer::flush_stats();
+    println!("double buffering: {}",
+        if early_printer::is_double_buffering_enabled() { "enabled" } else { "disabled" }
+    );
+    println!("flushes:          {}", before.flushes);
+    println!("rects flushed:    {}", before.rects_flushed);
+    println!("rects coalesced:  {}", before.rects_coalesced);
+    println!("bytes copied:     {}", before.bytes_copied);
+
+    if matches.opt_present("r") {
+        if sleep::sleep(Duration::from_secs(1)).is_err() {
+            println!("failed to sleep");
+            return -1;
+        }
+        let after = early_printer::flush_stats();
+        println!("bytes copied/sec: {}", after.bytes_copied - before.bytes_copied);
+        println!("flushes/sec:      {}", after.flushes - before.flushes);
+    }
+
+    if matches.opt_present("s") {
+        if let Err(e) = scroll_bench() {
+            println!("Error: {}", e);
+            return -1;
+        }
+    }
+
+    0
+}
+
+/// Measures how long it takes to copy a back buffer, scrolled up by one text row,
+/// into framebuffer-sized memory mapped with each memory type.
+///
+/// This is the same pattern of writes as a double-buffered terminal scrolling its output,
+/// so the difference between the results shows the payoff of a write-combining framebuffer.
+fn scroll_bench() -> Result<(), &'static str> {
+    let mut back_buffer = alloc::vec![0u8; SCROLL_BENCH_FB_SIZE + SCROLL_BENCH_ROW_SIZE];
+    for (i, byte) in back_buffer.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+
+    println!("\nScrolling a {} KiB framebuffer {} times:", SCROLL_BENCH_FB_SIZE / 1024, SCROLL_BENCH_ITERATIONS);
+    for memory_type in [MemoryType::WriteBack, MemoryType::WriteCombining, MemoryType::Uncacheable] {
+        let flags = PteFlagsArch::from(PteFlags::new().valid(true).writable(true))
+            .memory_type(memory_type);
+        let mut mp = memory::create_mapping(SCROLL_BENCH_FB_SIZE, flags)?;
+        let fb = mp.as_slice_mut::<u8>(0, SCROLL_BENCH_FB_SIZE)?;
+
+        let start = Instant::now();
+        for i in 0 .. SCROLL_BENCH_ITERATIONS {
+            let offset = (i % 2) * SCROLL_BENCH_ROW_SIZE;
+            fb.copy_from_slice(&back_buffer[offset .. offset + SCROLL_BENCH_FB_SIZE]);
+        }
+        let elapsed = start.elapsed();
+
+        let total_bytes = (SCROLL_BENCH_FB_SIZE * SCROLL_BENCH_ITERATIONS) as u128;
+        let mib_per_sec = (total_bytes * 1_000_000 / elapsed.as_micros().max(1)) / (1024 * 1024);
+        println!("  {:<16} {:>8} us per scroll, {:>6} MiB/s",
+            alloc::format!("{:?}", memory_type),
+            elapsed.as_micros() / SCROLL_BENCH_ITERATIONS as u128,
+            mib_per_sec,
+        );
+    }
+    Ok(())
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: fbstat [OPTIONS]
+Prints statistics about copying damaged regions of the early printer's back buffer to the framebuffer.
+With -s, compares scrolling into write-back, write-combining, and uncacheable memory.";
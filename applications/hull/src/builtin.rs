@@ -1,11 +1,24 @@
 //! Builtin shell commands.
 
-use crate::{Error, Result, Shell};
-use alloc::string::ToString;
+use crate::{pager::PagedOutput, Error, Result, Shell};
+use alloc::{
+    borrow::ToOwned,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
 use app_io::println;
 
 // TODO: Decide which builtins we don't need.
 
+/// The names of the builtin commands.
+///
+/// This must be kept in sync with `Shell::execute_builtin`.
+pub(crate) const BUILTINS: &[&str] = &[
+    "alias", "bg", "cd", "exec", "exit", "export", "fc", "fg", "getopts", "hash", "help",
+    "history", "jobs", "set", "unalias", "unset", "wait",
+];
+
 impl Shell {
     pub(crate) fn alias(&self, _args: &[&str]) -> Result<()> {
         println!("not yet implemented");
@@ -116,11 +129,47 @@ impl Shell {
         Err(Error::Command(1))
     }
 
+    pub(crate) fn help(&self, _args: &[&str]) -> Result<()> {
+        let namespace_dir = task::get_my_current_task()
+            .map(|t| t.get_namespace().dir().clone())
+            .ok_or(Error::CurrentTaskUnavailable)?;
+        // Application object files are named `<crate name>-<hash>.o`.
+        let mut applications = namespace_dir
+            .get_file_and_dir_names_starting_with("")
+            .into_iter()
+            .filter_map(|name| name.rsplit_once('-').map(|(name, _)| name.to_owned()))
+            .collect::<Vec<_>>();
+        applications.sort_unstable();
+        applications.dedup();
+
+        let mut text = String::from("Builtin commands:\n");
+        for builtin in BUILTINS {
+            text.push_str(&format!("  {builtin}\n"));
+        }
+        text.push_str("Applications:\n");
+        for application in applications {
+            text.push_str(&format!("  {application}\n"));
+        }
+        self.print_paged(&text);
+        Ok(())
+    }
+
+    /// Prints the given text, paging it if it doesn't fit on one screen.
+    fn print_paged(&self, text: &str) {
+        let (Ok(console), Ok(keyboard)) = (app_io::stdout(), app_io::stdin()) else {
+            return;
+        };
+        let mut output = PagedOutput::new(None, console, keyboard, self.discipline.clone());
+        self.discipline.clear_events();
+        output.write(text.as_bytes());
+        output.finish();
+    }
+
     pub(crate) fn history(&self, _args: &[&str]) {
-        let num_column_max_length = self.history.len().to_string().len() + 1;
+        let history = self.discipline.history();
+        let num_column_max_length = history.len().to_string().len() + 1;
 
-        for (i, line) in self
-            .history
+        for (i, line) in history
             .iter()
             .enumerate()
             .map(|(i, line)| (i + 1, line))
@@ -25,20 +25,19 @@ extern crate alloc;
 mod builtin;
 mod error;
 mod job;
+mod pager;
 mod parse;
-mod wrapper;
 
 use crate::{
     job::{JobPart, State},
+    pager::PagedOutput,
     parse::{ParsedJob, ParsedLine, ParsedTask},
 };
 use alloc::{borrow::ToOwned, format, string::String, sync::Arc, vec::Vec};
-use app_io::{println, IoStreams};
-use core::fmt::Write;
+use app_io::{print, println, ImmutableWrite, IoStreams};
 use hashbrown::HashMap;
 use job::Job;
 use log::{error, warn};
-use noline::{builder::EditorBuilder, sync::embedded::IO as Io};
 use path::PathBuf;
 use stdio::Stdio;
 use sync_block::Mutex;
@@ -52,11 +51,9 @@ pub fn main(_: Vec<String>) -> isize {
         discipline: app_io::line_discipline().expect("no line discipline"),
         jobs: Arc::new(Mutex::new(HashMap::new())),
         stop_order: Vec::new(),
-        history: Vec::new(),
+        foreground_output: None,
     };
-    let result = shell.run();
-    shell.set_app_discipline();
-    if let Err(e) = result {
+    if let Err(e) = shell.run() {
         println!("{e:?}");
         -1
     } else {
@@ -71,46 +68,27 @@ pub struct Shell {
     // end. Removing a job would replace the job with None.
     jobs: Arc<Mutex<HashMap<usize, Job>>>,
     stop_order: Vec<usize>,
-    history: Vec<String>,
+    /// The output of the foreground job, which is paged if it doesn't fit on
+    /// one screen.
+    foreground_output: Option<PagedOutput>,
 }
 
 impl Shell {
-    /// Configures the line discipline for use by the shell.
-    fn set_shell_discipline(&self) {
-        self.discipline.set_raw();
-    }
-
-    /// Configures the line discipline for use by applications.
-    fn set_app_discipline(&self) -> AppDisciplineGuard {
-        self.discipline.set_sane();
-        AppDisciplineGuard {
-            discipline: self.discipline.clone(),
-        }
-    }
-
     fn run(&mut self) -> Result<()> {
-        self.set_shell_discipline();
-
-        let wrapper = wrapper::Wrapper {
-            stdin: app_io::stdin().expect("no stdin"),
-            stdout: app_io::stdout().expect("no stdout"),
-        };
-        let mut io = Io::new(wrapper);
-        let mut editor = EditorBuilder::new_unbounded()
-            .with_unbounded_history()
-            .build_sync(&mut io)
-            .expect("couldn't instantiate line editor");
+        // Line editing and history are handled by the line discipline in canonical mode.
+        self.discipline.set_sane();
+        let stdin = app_io::stdin().expect("no stdin");
 
         loop {
-            editor.dedup_history();
-            if let Ok(line) = editor.readline("> ", &mut io) {
-                match self.execute_line(line) {
+            print!("> ");
+            if let Ok(line) = stdin.read_line() {
+                match self.execute_line(&line) {
                     Ok(()) => {}
                     Err(Error::ExitRequested) => return Ok(()),
                     Err(e) => return Err(e),
                 };
             } else {
-                write!(io, "failed to read line").expect("failed to write output");
+                println!("failed to read line");
             }
         }
     }
@@ -122,9 +100,6 @@ impl Shell {
             return Ok(());
         }
 
-        // TODO: Use line editor history.
-        self.history.push(line.to_owned());
-
         for (job_str, job) in parsed_line.background {
             if let Err(error) = self.execute_cmd(job, job_str, false) {
                 error.print()?;
@@ -132,7 +107,6 @@ impl Shell {
         }
 
         if let Some((job_str, job)) = parsed_line.foreground {
-            let app_discipline_guard = self.set_app_discipline();
             match self.execute_cmd(job, job_str, true) {
                 Ok(Some(foreground_id)) => {
                     if let Err(error) = self.wait_on_job(foreground_id) {
@@ -142,7 +116,6 @@ impl Shell {
                 Ok(None) => {}
                 Err(error) => error.print()?,
             }
-            drop(app_discipline_guard);
         }
 
         Ok(())
@@ -158,6 +131,7 @@ impl Shell {
         let shell_streams = app_io::streams().unwrap();
 
         let stderr = shell_streams.stderr;
+        let keyboard = shell_streams.stdin.clone();
         let mut previous_output = shell_streams.stdin;
 
         let mut iter = parsed_job.into_iter().peekable();
@@ -187,14 +161,31 @@ impl Shell {
                     self.jobs.lock().remove(&job_id);
                     return result.map(|_| None);
                 } else {
+                    // The output of a foreground job that is written to the
+                    // console goes through a pipe, so that the shell can page it.
+                    let (stdout, output): (Arc<dyn ImmutableWrite>, _) =
+                        match shell_streams.discipline {
+                            Some(ref discipline) if current => {
+                                let pipe = Stdio::new();
+                                let output = PagedOutput::new(
+                                    Some(pipe.get_reader()),
+                                    shell_streams.stdout,
+                                    keyboard,
+                                    discipline.clone(),
+                                );
+                                (Arc::new(pipe.get_writer()), Some(output))
+                            }
+                            _ => (shell_streams.stdout, None),
+                        };
                     let streams = IoStreams {
                         stdin: previous_output,
-                        stdout: shell_streams.stdout,
+                        stdout,
                         stderr,
                         discipline: shell_streams.discipline,
                     };
                     let part = self.resolve_external(command, args, streams, job_id)?;
                     self.jobs.lock().get_mut(&job_id).unwrap().parts.push(part);
+                    self.foreground_output = output;
                     return Ok(Some(job_id));
                 }
             }
@@ -219,6 +210,7 @@ impl Shell {
     }
 
     fn wait_on_job(&mut self, num: usize) -> Result<()> {
+        let mut output = self.foreground_output.take();
         let jobs = self.jobs.lock();
         let Some(job) = jobs.get(&num) else {
             return Ok(())
@@ -236,6 +228,9 @@ impl Shell {
             if let Ok(event) = event_receiver.try_receive() {
                 match event {
                     Event::CtrlC => {
+                        if let Some(ref mut output) = output {
+                            output.quit();
+                        }
                         if let Some(mut job) = self.jobs.lock().remove(&num) {
                             job.kill()?;
                         } else {
@@ -247,11 +242,18 @@ impl Shell {
                     Event::CtrlZ => error!("received ctrl+z event"),
                 }
             } else {
+                if let Some(ref mut output) = output {
+                    output.poll(true);
+                }
                 let mut jobs = self.jobs.lock();
                 if let Some(job) = jobs.get_mut(&num)
                     && let Some(exit_value) = job.exit_value()
                 {
                         jobs.remove(&num);
+                        drop(jobs);
+                        if let Some(ref mut output) = output {
+                            output.finish();
+                        }
                         return match exit_value {
                             0 => Ok(()),
                             _ => Err(Error::Command(exit_value)),
@@ -262,6 +264,9 @@ impl Shell {
         }
     }
 
+    /// Executes a builtin command, returning `None` if `cmd` isn't one.
+    ///
+    /// The commands matched here must be kept in sync with [`builtin::BUILTINS`].
     fn execute_builtin(&mut self, cmd: &str, args: &[&str]) -> Option<Result<()>> {
         Some(match cmd {
             "" => Ok(()),
@@ -275,6 +280,7 @@ impl Shell {
             "fg" => self.fg(args),
             "getopts" => self.getopts(args),
             "hash" => self.hash(args),
+            "help" => self.help(args),
             "history" => {
                 self.history(args);
                 Ok(())
@@ -374,16 +380,6 @@ impl Shell {
     }
 }
 
-struct AppDisciplineGuard {
-    discipline: Arc<LineDiscipline>,
-}
-
-impl Drop for AppDisciplineGuard {
-    fn drop(&mut self) {
-        self.discipline.set_raw();
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
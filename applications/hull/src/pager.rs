@@ -0,0 +1,584 @@
+//! A pager for long output of foreground commands, similar to `less`.
+//!
+//! The output of a foreground command is passed straight through to the
+//! console until it no longer fits on one screen. The pager then takes over
+//! the terminal in raw mode. It shows one screenful at a time, with a status
+//! line giving the position in the output, and accepts the following keys:
+//! - space or page down: next page,
+//! - enter or down arrow: next line,
+//! - page up: previous page,
+//! - up arrow: previous line,
+//! - `/pattern` followed by enter: search forward for a substring,
+//! - `n`: search forward for the next match, and
+//! - `q`: quit, discarding the rest of the output.
+//!
+//! At most [`MAX_BUFFERED_BYTES`] of output are kept. Once more has been
+//! written, the oldest page of rows is discarded, which the status line
+//! indicates.
+//!
+//! The size of the terminal can't be queried, so a standard 80x24 terminal is
+//! assumed. Output that isn't written to the console, e.g. that of a task whose
+//! output is piped to another task, is never paged. Neither is the output of
+//! an application that puts the terminal into raw mode, as it controls the
+//! display itself.
+//!
+//! While paging, keys are read from the same terminal as the application's
+//! standard input, so an application that reads from it at the same time may
+//! receive some of them instead.
+
+use alloc::{collections::VecDeque, format, string::String, sync::Arc, vec::Vec};
+use app_io::{ImmutableRead, ImmutableWrite};
+use stdio::StdioReader;
+use tty::{Event, LineDiscipline, RawModeGuard};
+
+/// The assumed number of rows of the terminal.
+const SCREEN_ROWS: usize = 24;
+/// The assumed number of columns of the terminal.
+const SCREEN_COLUMNS: usize = 80;
+/// The number of rows of output shown on one screen, leaving one row for the
+/// status line.
+const PAGE_ROWS: usize = SCREEN_ROWS - 1;
+
+/// The maximum number of bytes of output that are kept for paging.
+const MAX_BUFFERED_BYTES: usize = 256 * 1024;
+/// The maximum length of a line that hasn't been terminated yet, after which
+/// it is wrapped as if it had been.
+const MAX_PARTIAL_LINE_BYTES: usize = 4096;
+
+const ESCAPE: u8 = 0x1b;
+const ERASE: u8 = 0x7f;
+const BACKSPACE: u8 = 0x8;
+
+/// Output written to the console, which is paged once it exceeds one screen.
+pub(crate) struct PagedOutput {
+    /// The pipe that the output is read from, if it's written by another task.
+    source: Option<StdioReader>,
+    console: Arc<dyn ImmutableWrite>,
+    keyboard: Arc<dyn ImmutableRead>,
+    discipline: Arc<LineDiscipline>,
+    rows: RowBuffer,
+    mode: Mode,
+}
+
+enum Mode {
+    /// The output is written straight to the console, as it fits on one
+    /// screen so far.
+    PassThrough,
+    /// The output is written straight to the console and never paged, as the
+    /// application put the terminal into raw mode.
+    Bypass,
+    Paging(Pager),
+    /// The user quit the pager, so the rest of the output is discarded.
+    Quit,
+}
+
+/// The state of the pager while it controls the terminal.
+struct Pager {
+    /// The index of the row at the top of the screen.
+    top: usize,
+    keys: KeyDecoder,
+    /// The search pattern being entered after `/`, if any.
+    prompt: Option<String>,
+    /// The most recently searched pattern.
+    pattern: Option<String>,
+    /// A message shown in the status line until the next key is pressed.
+    message: Option<&'static str>,
+    /// Whether the whole screen must be redrawn.
+    redraw: bool,
+    /// Whether the status line must be redrawn.
+    redraw_status: bool,
+    _raw_mode: RawModeGuard,
+}
+
+impl PagedOutput {
+    /// Creates a pager for output that is written to the given `console`, and
+    /// optionally read from the given `source` pipe.
+    ///
+    /// Keys are read from the given `keyboard`, which must be the slave end of
+    /// the same terminal as `console`.
+    pub(crate) fn new(
+        source: Option<StdioReader>,
+        console: Arc<dyn ImmutableWrite>,
+        keyboard: Arc<dyn ImmutableRead>,
+        discipline: Arc<LineDiscipline>,
+    ) -> Self {
+        Self {
+            source,
+            console,
+            keyboard,
+            discipline,
+            rows: RowBuffer::default(),
+            mode: Mode::PassThrough,
+        }
+    }
+
+    /// Returns `true` if the pager currently controls the terminal.
+    pub(crate) fn is_paging(&self) -> bool {
+        matches!(self.mode, Mode::Paging(_))
+    }
+
+    /// Writes the given output, starting the pager if it no longer fits on one
+    /// screen.
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        match self.mode {
+            Mode::PassThrough if !self.discipline.canonical() => {
+                self.rows = RowBuffer::default();
+                self.mode = Mode::Bypass;
+                let _ = self.console.write_all(bytes);
+            }
+            Mode::PassThrough => {
+                self.rows.push(bytes);
+                if self.rows.len() > PAGE_ROWS {
+                    self.mode = Mode::Paging(Pager {
+                        top: self.rows.discarded,
+                        keys: KeyDecoder::default(),
+                        prompt: None,
+                        pattern: None,
+                        message: None,
+                        redraw: true,
+                        redraw_status: true,
+                        _raw_mode: self.discipline.enter_raw_mode(),
+                    });
+                } else {
+                    let _ = self.console.write_all(bytes);
+                }
+            }
+            Mode::Bypass => {
+                let _ = self.console.write_all(bytes);
+            }
+            Mode::Paging(ref mut pager) => {
+                let old_len = self.rows.len();
+                self.rows.push(bytes);
+                if pager.top < self.rows.discarded {
+                    pager.top = self.rows.discarded;
+                    pager.redraw = true;
+                }
+                // Only rows that appear on the screen require a full redraw.
+                if old_len < pager.top + PAGE_ROWS {
+                    pager.redraw = true;
+                }
+                pager.redraw_status = true;
+            }
+            Mode::Quit => {}
+        }
+    }
+
+    /// Reads all output that is available from the source pipe, handles any
+    /// keys pressed while paging, and updates the screen.
+    ///
+    /// `running` indicates whether more output may still be written.
+    pub(crate) fn poll(&mut self, running: bool) {
+        if let Some(source) = self.source.take() {
+            let mut buf = [0; 256];
+            while let Ok(len) = source.try_read(&mut buf) && len > 0 {
+                self.write(&buf[..len]);
+            }
+            self.source = Some(source);
+        }
+
+        let Mode::Paging(ref mut pager) = self.mode else {
+            return;
+        };
+        let mut buf = [0; 16];
+        let mut quit = false;
+        while !quit && let Ok(len) = self.keyboard.try_read(&mut buf) && len > 0 {
+            quit = buf[..len].iter().any(|byte| {
+                pager.keys.decode(*byte).map_or(false, |key| pager.handle_key(key, &self.rows))
+            });
+        }
+        if quit {
+            self.quit();
+        } else {
+            pager.draw(&self.rows, &*self.console, running);
+        }
+    }
+
+    /// Pages the remaining output once no more will be written, returning when
+    /// the user quits the pager or presses Ctrl+C.
+    ///
+    /// If the output fits on one screen, this returns immediately.
+    pub(crate) fn finish(&mut self) {
+        let events = self.discipline.event_receiver();
+        loop {
+            self.poll(false);
+            if !self.is_paging() {
+                return;
+            }
+            if let Ok(Event::CtrlC) = events.try_receive() {
+                self.quit();
+                return;
+            }
+            scheduler::schedule();
+        }
+    }
+
+    /// Quits the pager, if it's running, and discards the rest of the output.
+    pub(crate) fn quit(&mut self) {
+        if self.is_paging() {
+            // Leave the screen as it is, but replace the status line with the
+            // shell's prompt. This also drops the raw mode guard.
+            let _ = self.console.write_all(b"\r\x1b[K");
+        }
+        self.mode = Mode::Quit;
+        self.rows = RowBuffer::default();
+    }
+}
+
+impl Pager {
+    /// Handles the given key, returning `true` if the user quit the pager.
+    fn handle_key(&mut self, key: Key, rows: &RowBuffer) -> bool {
+        self.message = None;
+        self.redraw_status = true;
+
+        if let Some(prompt) = self.prompt.as_mut() {
+            match key {
+                Key::Byte(b'\r' | b'\n') => {
+                    let pattern = core::mem::take(prompt);
+                    self.prompt = None;
+                    if !pattern.is_empty() {
+                        self.pattern = Some(pattern);
+                    }
+                    self.search_next(rows);
+                }
+                Key::Byte(ERASE | BACKSPACE) => {
+                    if prompt.pop().is_none() {
+                        self.prompt = None;
+                    }
+                }
+                Key::Escape => self.prompt = None,
+                Key::Byte(byte) if byte == b' ' || byte.is_ascii_graphic() => {
+                    prompt.push(byte as char);
+                }
+                _ => {}
+            }
+            return false;
+        }
+
+        match key {
+            Key::Byte(b' ') | Key::PageDown => self.scroll_down(rows, PAGE_ROWS),
+            Key::Byte(b'\r' | b'\n') | Key::Down => self.scroll_down(rows, 1),
+            Key::PageUp => self.scroll_up(rows, PAGE_ROWS),
+            Key::Up => self.scroll_up(rows, 1),
+            Key::Byte(b'/') => self.prompt = Some(String::new()),
+            Key::Byte(b'n') => self.search_next(rows),
+            Key::Byte(b'q') => return true,
+            _ => {}
+        }
+        false
+    }
+
+    /// Returns the index of the lowest row that can be shown at the top of the
+    /// screen.
+    fn max_top(rows: &RowBuffer) -> usize {
+        rows.len().saturating_sub(PAGE_ROWS).max(rows.discarded)
+    }
+
+    fn scroll_down(&mut self, rows: &RowBuffer, count: usize) {
+        let top = (self.top + count).min(Self::max_top(rows));
+        self.redraw |= top != self.top;
+        self.top = top;
+    }
+
+    fn scroll_up(&mut self, rows: &RowBuffer, count: usize) {
+        let top = self.top.saturating_sub(count).max(rows.discarded);
+        self.redraw |= top != self.top;
+        self.top = top;
+    }
+
+    /// Moves the next row after the top of the screen that contains the most
+    /// recently searched pattern to the top of the screen.
+    fn search_next(&mut self, rows: &RowBuffer) {
+        let Some(pattern) = self.pattern.as_deref() else {
+            self.message = Some("no previous search");
+            return;
+        };
+        match rows.find(pattern, self.top + 1) {
+            Some(index) => {
+                self.top = index.min(Self::max_top(rows));
+                self.redraw = true;
+            }
+            None => self.message = Some("pattern not found"),
+        }
+    }
+
+    /// Redraws the screen, or just the status line, if necessary.
+    fn draw(&mut self, rows: &RowBuffer, console: &dyn ImmutableWrite, running: bool) {
+        let mut screen = String::new();
+        if self.redraw {
+            // Move the cursor to the top left and clear the screen.
+            screen.push_str("\x1b[H\x1b[2J");
+            for index in self.top..self.top + PAGE_ROWS {
+                if let Some(row) = rows.row(index) {
+                    screen.push_str(&row);
+                }
+                screen.push('\n');
+            }
+        } else if self.redraw_status {
+            // Clear the status line in place.
+            screen.push_str("\r\x1b[K");
+        } else {
+            return;
+        }
+        self.redraw = false;
+        self.redraw_status = false;
+
+        let status = match &self.prompt {
+            Some(prompt) => format!("/{prompt}"),
+            None => {
+                let last = (self.top + PAGE_ROWS).min(rows.len());
+                let mut status = format!(
+                    "lines {}-{} of {}{}",
+                    self.top + 1,
+                    last,
+                    rows.len(),
+                    if running { "+" } else { "" },
+                );
+                if rows.discarded > 0 {
+                    status.push_str(&format!(" ({} oldest discarded)", rows.discarded));
+                }
+                if !running && last == rows.len() {
+                    status.push_str(" (END)");
+                }
+                match self.message {
+                    Some(message) => status.push_str(&format!(" - {message}")),
+                    None => status.push_str(" - q to quit"),
+                }
+                status
+            }
+        };
+        let status: String = status.chars().take(SCREEN_COLUMNS - 1).collect();
+        if self.prompt.is_some() {
+            screen.push_str(&status);
+        } else {
+            // Show the status line in reverse video.
+            screen.push_str(&format!("\x1b[7m{status}\x1b[0m"));
+        }
+        let _ = console.write_all(screen.as_bytes());
+    }
+}
+
+/// Output split into rows that fit the width of the screen, of which at most
+/// [`MAX_BUFFERED_BYTES`] are kept.
+#[derive(Default)]
+struct RowBuffer {
+    /// The rows that are kept, oldest first.
+    rows: VecDeque<String>,
+    /// The total length in bytes of the rows that are kept.
+    bytes: usize,
+    /// The number of oldest rows that have been discarded to bound memory use.
+    discarded: usize,
+    /// The last line of output, which hasn't been terminated yet.
+    partial: Vec<u8>,
+}
+
+impl RowBuffer {
+    /// Appends the given output, discarding the oldest page of rows while more
+    /// than [`MAX_BUFFERED_BYTES`] are kept.
+    fn push(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            match byte {
+                b'\n' => {
+                    let line = core::mem::take(&mut self.partial);
+                    self.push_line(&line);
+                }
+                b'\r' => {}
+                _ => {
+                    self.partial.push(*byte);
+                    if self.partial.len() >= MAX_PARTIAL_LINE_BYTES {
+                        let line = core::mem::take(&mut self.partial);
+                        self.push_line(&line);
+                    }
+                }
+            }
+        }
+        while self.bytes > MAX_BUFFERED_BYTES && self.rows.len() > PAGE_ROWS {
+            for row in self.rows.drain(..PAGE_ROWS) {
+                self.bytes -= row.len();
+                self.discarded += 1;
+            }
+        }
+    }
+
+    /// Wraps the given complete line into rows.
+    fn push_line(&mut self, line: &[u8]) {
+        for row in wrap(&String::from_utf8_lossy(line)) {
+            self.bytes += row.len();
+            self.rows.push_back(row);
+        }
+    }
+
+    /// Returns the number of rows that have been written, including discarded
+    /// rows and the last unterminated line.
+    fn len(&self) -> usize {
+        self.discarded + self.rows.len() + self.partial_rows().len()
+    }
+
+    /// Returns the rows of the last unterminated line, if any.
+    fn partial_rows(&self) -> Vec<String> {
+        if self.partial.is_empty() {
+            Vec::new()
+        } else {
+            wrap(&String::from_utf8_lossy(&self.partial))
+        }
+    }
+
+    /// Returns the row at the given index, unless it has been discarded.
+    fn row(&self, index: usize) -> Option<String> {
+        let index = index.checked_sub(self.discarded)?;
+        match self.rows.get(index) {
+            Some(row) => Some(row.clone()),
+            None => self.partial_rows().into_iter().nth(index - self.rows.len()),
+        }
+    }
+
+    /// Returns the index of the first row at or after `start` that contains the
+    /// given `pattern`.
+    fn find(&self, pattern: &str, start: usize) -> Option<usize> {
+        (start.max(self.discarded)..self.len())
+            .find(|index| self.row(*index).map_or(false, |row| row.contains(pattern)))
+    }
+}
+
+/// Splits the given line into rows of at most [`SCREEN_COLUMNS`] characters,
+/// expanding tabs to spaces.
+fn wrap(line: &str) -> Vec<String> {
+    let mut rows = Vec::new();
+    let mut row = String::new();
+    let mut columns = 0;
+    for c in line.chars() {
+        let (c, width) = if c == '\t' { (' ', 8 - columns % 8) } else { (c, 1) };
+        for _ in 0..width {
+            if columns == SCREEN_COLUMNS {
+                rows.push(core::mem::take(&mut row));
+                columns = 0;
+            }
+            row.push(c);
+            columns += 1;
+        }
+    }
+    rows.push(row);
+    rows
+}
+
+/// A key read from the terminal in raw mode.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Key {
+    Byte(u8),
+    Escape,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+}
+
+/// Decodes keys from raw terminal input, including the escape sequences sent
+/// by the arrow and page keys.
+#[derive(Default)]
+struct KeyDecoder {
+    state: EscapeState,
+    /// The last parameter byte of the control sequence being received.
+    parameter: Option<u8>,
+}
+
+/// How much of an escape sequence has been received.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum EscapeState {
+    #[default]
+    None,
+    /// Received `ESC`.
+    Escape,
+    /// Received `ESC [`, i.e. the start of a control sequence.
+    ControlSequence,
+}
+
+impl KeyDecoder {
+    /// Decodes the given input byte, returning a key if it completes one.
+    fn decode(&mut self, byte: u8) -> Option<Key> {
+        match self.state {
+            EscapeState::None if byte == ESCAPE => {
+                self.state = EscapeState::Escape;
+                None
+            }
+            EscapeState::None => Some(Key::Byte(byte)),
+            EscapeState::Escape if byte == b'[' => {
+                self.state = EscapeState::ControlSequence;
+                self.parameter = None;
+                None
+            }
+            // A lone escape key press is only recognized by the next key.
+            EscapeState::Escape => {
+                self.state = EscapeState::None;
+                Some(Key::Escape)
+            }
+            EscapeState::ControlSequence => {
+                // Parameter bytes precede the final byte of a control sequence.
+                if !(0x40..=0x7e).contains(&byte) {
+                    self.parameter = Some(byte);
+                    return None;
+                }
+                self.state = EscapeState::None;
+                match (byte, self.parameter) {
+                    (b'A', _) => Some(Key::Up),
+                    (b'B', _) => Some(Key::Down),
+                    (b'~', Some(b'5')) => Some(Key::PageUp),
+                    (b'~', Some(b'6')) => Some(Key::PageDown),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap(""), vec![""]);
+        assert_eq!(wrap("a\tb"), vec!["a       b"]);
+        let long = "x".repeat(SCREEN_COLUMNS + 1);
+        assert_eq!(wrap(&long), vec!["x".repeat(SCREEN_COLUMNS), "x".into()]);
+    }
+
+    #[test]
+    fn test_row_buffer() {
+        let mut rows = RowBuffer::default();
+        rows.push(b"first\r\nsecond\nthi");
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows.row(2).as_deref(), Some("thi"));
+        rows.push(b"rd\n");
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows.find("ir", 1), Some(2));
+        assert_eq!(rows.find("missing", 0), None);
+    }
+
+    #[test]
+    fn test_row_buffer_discards_oldest_page() {
+        let mut rows = RowBuffer::default();
+        let line = ["x".repeat(SCREEN_COLUMNS - 1).as_str(), "\n"].concat();
+        let count = 2 * MAX_BUFFERED_BYTES / line.len();
+        for _ in 0..count {
+            rows.push(line.as_bytes());
+        }
+        assert_eq!(rows.len(), count);
+        assert!(rows.bytes <= MAX_BUFFERED_BYTES);
+        assert_eq!(rows.discarded % PAGE_ROWS, 0);
+        assert_eq!(rows.row(0), None);
+        assert!(rows.row(count - 1).is_some());
+    }
+
+    #[test]
+    fn test_key_decoder() {
+        let mut keys = KeyDecoder::default();
+        let decoded: Vec<_> = b"q\x1b[A\x1b[6~\x1b[5~\x1bx"
+            .iter()
+            .filter_map(|byte| keys.decode(*byte))
+            .collect();
+        assert_eq!(
+            decoded,
+            vec![Key::Byte(b'q'), Key::Up, Key::PageDown, Key::PageUp, Key::Escape]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Lists interrupt vectors that were masked due to an interrupt storm,
+//! unmasks them once their driver has recovered,
+//! and views or changes the storm detection threshold.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("u", "unmask", "unmask the given interrupt vector, e.g., 0x2B", "VECTOR");
+    opts.optopt("t", "threshold", "set the storm threshold in interrupts per second (0 disables detection)", "RATE");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match run(matches) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn run(matches: getopts::Matches) -> Result<(), String> {
+    use alloc::format;
+    use interrupts::storm;
+
+    if let Some(v) = matches.opt_str("u") {
+        let vector = parse_vector(&v).ok_or_else(|| format!("invalid vector {:?}", v))?;
+        storm::unmask_storm_vector(vector)?;
+        println!("Unmasked vector {:#X}.", vector);
+        return Ok(());
+    }
+
+    if let Some(t) = matches.opt_str("t") {
+        let threshold = t.parse::<u32>().map_err(|_| format!("invalid threshold {:?}", t))?;
+        storm::set_storm_threshold(threshold);
+    }
+
+    println!("Storm threshold: {} interrupts/sec", storm::storm_threshold());
+    let mut any = false;
+    for vector in storm::masked_storm_vectors() {
+        println!("    vector {:#X} is masked due to an interrupt storm", vector);
+        any = true;
+    }
+    if !any {
+        println!("No vectors are masked due to an interrupt storm.");
+    }
+    Ok(())
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn run(_matches: getopts::Matches) -> Result<(), String> {
+    Err(String::from("interrupt storm detection is only supported on x86_64"))
+}
+
+/// Parses a vector given in either hexadecimal (with a `0x` prefix) or decimal.
+#[cfg(target_arch = "x86_64")]
+fn parse_vector(s: &str) -> Option<u8> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: irq_storm [OPTION]
+Lists interrupt vectors that were masked due to an interrupt storm.
+Use '-u VECTOR' to unmask a vector once its driver has recovered the device.";
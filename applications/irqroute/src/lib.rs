@@ -0,0 +1,95 @@
+//! Shows how each legacy ISA IRQ is routed to an interrupt vector:
+//! the GSI it's connected to (after any ACPI interrupt source override),
+//! the IOAPIC line for that GSI, and the contents of that line's redirection entry.
+//!
+//! It first lists every IOAPIC and the range of GSIs it handles.
+//!
+//! This is useful for diagnosing why a legacy device's interrupt never arrives on new hardware.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn run() -> Result<(), &'static str> {
+    use alloc::format;
+
+    if ioapic::get_ioapics().next().is_none() {
+        println!("No IOAPICs were found; ISA IRQs are delivered through the legacy PIC.");
+        return Ok(());
+    }
+    println!("{:<6} {:>10} {:>7} {:>7} {:>12}", "IOAPIC", "GSIS", "LINES", "VERSION", "ADDRESS");
+    for (_id, ioapic) in ioapic::get_ioapics() {
+        let mut ioapic = ioapic.lock();
+        let range = ioapic.gsi_range();
+        let version = ioapic.version() & 0xFF;
+        println!("{:<6} {:>10} {:>7} {:>#7X} {:>#12X}",
+            ioapic.id, format!("{}-{}", range.start, range.end - 1), ioapic.num_entries(), version, ioapic.phys_addr(),
+        );
+    }
+    println!();
+
+    println!("{:<4} {:>4} {:>10} {:>7} {:>11} {:>8} {:>6} {:>8}",
+        "ISA", "GSI", "IOAPIC:PIN", "VECTOR", "POLARITY", "TRIGGER", "DEST", "SOURCE",
+    );
+    for (isa_irq, route) in ioapic::isa_irq_routes().iter().enumerate() {
+        let source = if route.overridden { "override" } else { "identity" };
+        let Ok((ioapic_id, pin, entry)) = ioapic::gsi_redirection_entry(route.gsi) else {
+            println!("{:<4} {:>4} {:>10} {:>7} {:>11} {:>8} {:>6} {:>8}",
+                isa_irq, route.gsi, "-", "-", format!("{:?}", route.polarity), format!("{:?}", route.trigger), "-", source,
+            );
+            continue;
+        };
+        let vector = if entry.masked { String::from("masked") } else { format!("{:#04X}", entry.vector) };
+        println!("{:<4} {:>4} {:>10} {:>7} {:>11} {:>8} {:>6} {:>8}",
+            isa_irq, route.gsi, format!("{}:{}", ioapic_id, pin), vector,
+            format!("{:?}", entry.polarity), format!("{:?}", entry.trigger), entry.destination, source,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn run() -> Result<(), &'static str> {
+    Err("ISA IRQ routing only exists on x86_64")
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: irqroute
+Lists each IOAPIC's GSI range, then shows the ISA IRQ -> GSI -> IOAPIC line -> vector mapping
+of each legacy interrupt.
+The POLARITY and TRIGGER columns show what is programmed in the IOAPIC, if any.";
@@ -0,0 +1,51 @@
+//! Prints a snapshot of all kernel metrics, as defined by the `metrics` crate.
+//!
+//! * `kmetrics` prints the snapshot to this application's output.
+//! * `kmetrics --serial` also writes it to the logger's outputs, e.g., the serial port,
+//!   so that an automated test harness on the host can collect it.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::{print, println};
+use getopts::Options;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("s", "serial", "also write the snapshot to the logger's outputs, e.g., the serial port");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    print!("{}", metrics::snapshot());
+
+    if matches.opt_present("s") {
+        if let Err(e) = metrics::emit_snapshot() {
+            println!("Error: {}", e);
+            return -1;
+        }
+    }
+
+    0
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: kmetrics [OPTIONS]
+Prints a snapshot of all kernel metrics, one `key=value` entry per line.";
@@ -0,0 +1,52 @@
+//! Shows the system's load average, i.e., the average number of runnable tasks
+//! over the last 1, 5, and 15 minutes, like `uptime` on Unix.
+//!
+//! * `loadavg` prints the three averages and the current number of runnable tasks.
+//! * `loadavg -r` prints the averages in their raw fixed-point representation.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+use scheduler::loadavg::{self, FSHIFT};
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("r", "raw", "print the averages in fixed point");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    let load = scheduler::load_average();
+    if matches.opt_present("r") {
+        println!("{} {} {} (fixed point with {} fractional bits)", load.one, load.five, load.fifteen, FSHIFT);
+    } else {
+        println!("load average: {}", load);
+    }
+    println!("runnable tasks: {}", loadavg::runnable_tasks());
+    0
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: loadavg [OPTION]...
+Shows the system's load average, i.e., the average number of runnable tasks across all CPUs
+over the last 1, 5, and 15 minutes, not counting idle tasks.
+The averages are updated every 5 seconds.";
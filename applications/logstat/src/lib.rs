@@ -0,0 +1,101 @@
+//! Shows per-call-site log throttling statistics and configures log throttling.
+//!
+//! * `logstat` prints how many records each tracked call site has logged,
+//!   and how many were suppressed by rate limiting or collapsed as repeats.
+//! * `logstat -b <BURST> -r <RATE>` changes the per-call-site rate limit.
+//! * `logstat --off` and `logstat --on` disable and re-enable throttling altogether.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("b", "burst", "set the number of records a call site can log in a burst", "BURST");
+    opts.optopt("r", "rate", "set the number of records per second a call site can sustainably log", "RATE");
+    opts.optflag("", "on", "enable log throttling");
+    opts.optflag("", "off", "disable log throttling");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    let mut config = logger::throttle_config();
+    let mut changed = false;
+    if let Some(burst) = matches.opt_str("b") {
+        match burst.parse() {
+            Ok(burst) => config.burst = burst,
+            Err(_) => {
+                println!("invalid burst: {}", burst);
+                return -1;
+            }
+        }
+        changed = true;
+    }
+    if let Some(rate) = matches.opt_str("r") {
+        match rate.parse() {
+            Ok(rate) => config.per_second = rate,
+            Err(_) => {
+                println!("invalid rate: {}", rate);
+                return -1;
+            }
+        }
+        changed = true;
+    }
+    if matches.opt_present("on") && matches.opt_present("off") {
+        println!("--on and --off are mutually exclusive");
+        return -1;
+    }
+    if matches.opt_present("on") || matches.opt_present("off") {
+        config.enabled = matches.opt_present("on");
+        changed = true;
+    }
+    if changed {
+        logger::set_throttle_config(config);
+    }
+
+    println!(
+        "Throttling {}: bursts of {} records, then {} records per second per call site.",
+        if config.enabled { "enabled" } else { "disabled" },
+        config.burst,
+        config.per_second,
+    );
+
+    let mut sites = logger::throttle_stats();
+    sites.sort_unstable_by_key(|s| core::cmp::Reverse((s.suppressed, s.deduplicated)));
+    println!("{:>10} {:>10} {:>10}  SITE", "LOGGED", "SUPPRESSED", "REPEATS");
+    for site in &sites {
+        println!("{:>10} {:>10} {:>10}  {}:{}",
+            site.logged, site.suppressed, site.deduplicated, site.file, site.line,
+        );
+    }
+    if sites.len() >= logger::MAX_TRACKED_SITES {
+        println!("(only the first {} call sites are tracked)", logger::MAX_TRACKED_SITES);
+    }
+
+    0
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: logstat [OPTIONS]
+Shows per-call-site log throttling statistics, most suppressed first.
+SUPPRESSED counts records dropped by rate limiting;
+REPEATS counts identical consecutive records collapsed into a repeat count.";
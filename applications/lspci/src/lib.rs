@@ -15,6 +15,7 @@ use memory::PhysicalAddress;
 pub fn main(args: Vec<String>) -> isize {
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help menu");
+    opts.optflag("x", "config", "dump the configuration space of each device");
 
     let matches = match opts.parse(args) {
         Ok(m) => m,
@@ -30,14 +31,14 @@ pub fn main(args: Vec<String>) -> isize {
         return 0;
     }
 
-    if let Err(msg) = list_pci_devices() {
+    if let Err(msg) = list_pci_devices(matches.opt_present("x")) {
         println!("Error: {}", msg);
     }
 
     0
 }
 
-fn list_pci_devices() -> Result<(), &'static str> {
+fn list_pci_devices(dump_config: bool) -> Result<(), &'static str> {
     for dev in pci_device_iter()? {
         println!("{} -- {:04x}:{:04x}", dev.location, dev.vendor_id, dev.device_id);
         println!("- class, subclass, prog_if: {:x}, {:x}, {:x}", dev.class, dev.subclass, dev.prog_if);
@@ -60,6 +61,10 @@ fn list_pci_devices() -> Result<(), &'static str> {
         println!("- MSI-X interrupts: {}", supports(support.msix));
         println!("- INTx enabled: {}", dev.pci_intx_enabled());
         println!("- INTx status: {}", dev.pci_get_intx_status(false));
+
+        if dump_config {
+            print!("{}", pci::dump_config(dev));
+        }
     }
 
     Ok(())
@@ -71,5 +76,6 @@ fn print_usage(opts: Options) {
 }
 
 
-const USAGE: &str = "Usage: lspci
-An application which lists currently connected PCI devices.";
+const USAGE: &str = "Usage: lspci [-x]
+An application which lists currently connected PCI devices.
+With -x, also dumps each device's 256-byte configuration space with its header fields decoded.";
@@ -5,6 +5,10 @@ extern crate alloc;
 extern crate task;
 extern crate getopts;
 extern crate scheduler;
+#[cfg(target_arch = "x86_64")]
+extern crate cpu;
+#[cfg(target_arch = "x86_64")]
+extern crate interrupts;
 
 use getopts::Options;
 use alloc::vec::Vec;
@@ -30,14 +34,14 @@ pub fn main(args: Vec<String>) -> isize {
 
     // Print headers
     if matches.opt_present("b") {
-        println!("{0:<5}  {1}", "ID", "NAME");
+        println!("{0:<10}  {1}", "ID", "NAME");
     }
     else {
         #[cfg(any(epoch_scheduler, priority_scheduler))] {
-            println!("{0:<5}  {1:<10}  {2:<4}  {3:<4}  {4:<5}  {5:<10}  {6}", "ID", "RUNSTATE", "CPU", "PIN", "TYPE", "PRIORITY", "NAME");
+            println!("{0:<10}  {1:<10}  {2:<10}  {3:<4}  {4:<4}  {5:<5}  {6:<10}  {7:<10}  {8:<10}  {9}", "ID", "GROUP", "RUNSTATE", "CPU", "PIN", "TYPE", "MEM(KiB)", "LIMIT", "PRIORITY", "NAME");
         }
         #[cfg(not(any(epoch_scheduler, priority_scheduler)))] {
-            println!("{0:<5}  {1:<10}  {2:<4}  {3:<4}  {4:<5}  {5}", "ID", "RUNSTATE", "CPU", "PIN", "TYPE", "NAME");
+            println!("{0:<10}  {1:<10}  {2:<10}  {3:<4}  {4:<4}  {5:<5}  {6:<10}  {7:<10}  {8}", "ID", "GROUP", "RUNSTATE", "CPU", "PIN", "TYPE", "MEM(KiB)", "LIMIT", "NAME");
         }
     }
 
@@ -48,36 +52,59 @@ pub fn main(args: Vec<String>) -> isize {
         let Some(task) = wtask.upgrade() else { continue };
         num_tasks += 1;
         if matches.opt_present("b") {
-            writeln!(task_string, "{0:<5}  {1}", id, task.name).expect("Failed to write to task_string.");
+            writeln!(task_string, "{0:<10}  {1}", id, task.name).expect("Failed to write to task_string.");
         }
         else {
             // All printed fields below must be strings to ensure the width formatting specifier below works properly.
+            let group = format!("{}", task.group_id());
             let runstate = format!("{:?}", task.runstate());
             let cpu = task.running_on_cpu().map(|cpu| format!("{cpu}")).unwrap_or_else(|| String::from("-"));
             let pinned = task.pinned_cpu().map(|pin| format!("{pin}")).unwrap_or_else(|| String::from("-"));
             let task_type = if task.is_an_idle_task {"I"}
                 else if task.is_application() {"A"}
                 else {" "} ;
+            let memory = format!("{}", task.memory_usage() / 1024);
+            let limit = task.memory_limit().map(|limit| format!("{}", limit / 1024)).unwrap_or_else(|| String::from("-"));
 
             #[cfg(any(epoch_scheduler, priority_scheduler))] {
                 let priority = scheduler::priority(&task).map(|priority| format!("{}", priority)).unwrap_or_else(|| String::from("-"));
                 task_string.push_str(
-                    &format!("{0:<5}  {1:<10}  {2:<4}  {3:<4}  {4:<5}  {5:<10}  {6}\n", 
-                    id, runstate, cpu, pinned, task_type, priority, task.name)
+                    &format!("{0:<10}  {1:<10}  {2:<10}  {3:<4}  {4:<4}  {5:<5}  {6:<10}  {7:<10}  {8:<10}  {9}\n", 
+                    id, group, runstate, cpu, pinned, task_type, memory, limit, priority, task.name)
                 );
             }
             #[cfg(not(any(epoch_scheduler, priority_scheduler)))] {
-                writeln!(task_string, "{0:<5}  {1:<10}  {2:<4}  {3:<4}  {4:<5}  {5}", 
-                    id, runstate, cpu, pinned, task_type, task.name).expect("Failed to write to task_string.");
+                writeln!(task_string, "{0:<10}  {1:<10}  {2:<10}  {3:<4}  {4:<4}  {5:<5}  {6:<10}  {7:<10}  {8}", 
+                    id, group, runstate, cpu, pinned, task_type, memory, limit, task.name).expect("Failed to write to task_string.");
             }
         }
     }
     print!("{}", task_string);
     println!("Total number of tasks: {}", num_tasks);
+
+    #[cfg(target_arch = "x86_64")]
+    if !matches.opt_present("b") {
+        print_system_time();
+    }
     
     0
 }
 
+/// Prints the share of each CPU spent in interrupt handlers and deferred interrupt tasks.
+#[cfg(target_arch = "x86_64")]
+fn print_system_time() {
+    let mut line = String::from("System time (IRQ% / DEFERRED%):");
+    for cpu in cpu::cpus() {
+        if let Some(st) = interrupts::system_time::system_time(cpu) {
+            write!(line, "  CPU {}: {}.{}% / {}.{}%", cpu.value(),
+                st.irq_permille / 10, st.irq_permille % 10,
+                st.deferred_permille / 10, st.deferred_permille % 10,
+            ).expect("Failed to write to line.");
+        }
+    }
+    println!("{}", line);
+}
+
 fn print_usage(opts: Options) -> isize {
     println!("{}", opts.usage(BRIEF));
     0
@@ -87,7 +114,12 @@ const BRIEF: &str = "Usage: ps [options]\n
     TYPE:      'I' if an idle task, 'A' if an application task, '-' otherwise.
     CPU:       the cpu core the task is currently running on.
     PIN:       the core the task is pinned on, if any.
+    MEM(KiB):  the memory currently charged to the task, in KiB, which is shared by all tasks in its group.
+    LIMIT:     the task's memory limit in KiB, or '-' if it is unlimited.
     RUNSTATE:  runnability status of this task, e.g., whether it can be scheduled in.
-    ID:        the unique identifier for this task.
-    NAME:      the name of the task.";
+    ID:        the unique identifier for this task, which is never reused.
+    GROUP:     the ID of the task that leads this task's group of threads, which is its own ID if it isn't a thread.
+    NAME:      the name of the task.
+    The share of each CPU spent in interrupt handlers and deferred interrupt tasks
+    over the last measurement window is printed after the tasks.";
     
\ No newline at end of file
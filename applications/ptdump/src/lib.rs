@@ -0,0 +1,66 @@
+//! Lists every mapping of the kernel's page table that intersects a range of virtual memory,
+//! along with the physical address, size, permissions, and flags of each mapped page.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::{print, println};
+use getopts::Options;
+use memory::VirtualAddress;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.len() != 2 {
+        print_usage(opts);
+        return if matches.opt_present("h") { 0 } else { -1 };
+    }
+
+    let Some(start) = parse_usize(&matches.free[0]).and_then(VirtualAddress::new) else {
+        println!("Error: {:?} is not a valid virtual address", matches.free[0]);
+        return -1;
+    };
+    let Some(len) = parse_usize(&matches.free[1]) else {
+        println!("Error: {:?} is not a valid length", matches.free[1]);
+        return -1;
+    };
+
+    match page_table_audit::dump(start, len) {
+        Ok(dump) => {
+            print!("{}", dump);
+            0
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+/// Parses a number given in either hexadecimal (with a `0x` prefix) or decimal.
+fn parse_usize(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: ptdump [OPTION] ADDRESS LENGTH
+Lists the kernel's page table mappings that intersect LENGTH bytes of virtual memory starting at ADDRESS.
+Both may be given in decimal or in hexadecimal with a 0x prefix.";
@@ -0,0 +1,55 @@
+//! Shows statistics about the mappings of the kernel's page table,
+//! i.e., the same report as the `/page_tables` file.
+//!
+//! With `-x`, this also lists every page that is both writable and executable.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::{print, println};
+use getopts::Options;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("x", "violations", "also list pages that are both writable and executable");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    let stats = match memory::page_table_stats() {
+        Ok(stats) => stats,
+        Err(e) => {
+            println!("Error: {}", e);
+            return -1;
+        }
+    };
+    print!("{}", page_table_audit::format_stats(&stats));
+    if matches.opt_present("x") {
+        for mapping in &stats.writable_executable {
+            println!("{}", page_table_audit::format_mapping(mapping));
+        }
+    }
+    if stats.writable_executable.is_empty() { 0 } else { -1 }
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: ptstat [OPTION]
+Shows statistics about the kernel's page table mappings.
+Returns an error if any page is both writable and executable.";
@@ -3,10 +3,13 @@
 //! The application assumes it is running in a QEMU virtual machine and exits
 //! from QEMU with different exit codes depending on whether the tests passed or
 //! failed.
+//!
+//! Before exiting, it emits a [`metrics`] snapshot that includes each test's result,
+//! which is easier for automated tooling to consume than this runner's output.
 
 #![no_std]
 
-use alloc::{boxed::Box, string::String, vec::Vec};
+use alloc::{boxed::Box, format, string::String, vec::Vec};
 
 use app_io::{print, println};
 use qemu_exit::{QEMUExit, X86};
@@ -52,18 +55,26 @@ pub fn main(_: Vec<String>) -> isize {
 
     for (file_name, path) in test_paths.into_iter() {
         print!("test {} ... ", path);
-        if ignore(&file_name) {
+        let outcome = if ignore(&file_name) {
             num_ignored += 1;
             println!("ignored");
+            "ignored"
         } else {
             match run_test(&path) {
-                Ok(_) => println!("ok"),
+                Ok(_) => {
+                    println!("ok");
+                    "pass"
+                }
                 Err(_) => {
                     num_failed += 1;
                     println!("failed");
+                    "fail"
                 }
             }
-        }
+        };
+        // The object file's name is the crate name followed by a hash, e.g., `test_foo-<hash>.o`.
+        let crate_name = file_name.split('-').next().unwrap_or(&file_name);
+        let _ = metrics::set_label(&format!("test.{crate_name}"), outcome);
     }
 
     let result_str = if num_failed > 0 { "failed" } else { "ok" };
@@ -73,6 +84,13 @@ pub fn main(_: Vec<String>) -> isize {
          ignored",
     );
 
+    let _ = metrics::set_value("test.passed", (num_passed - num_ignored) as u64);
+    let _ = metrics::set_value("test.failed", num_failed as u64);
+    let _ = metrics::set_value("test.ignored", num_ignored as u64);
+    if let Err(e) = metrics::emit_snapshot() {
+        println!("failed to emit metrics snapshot: {e}");
+    }
+
     if num_failed == 0 {
         QEMU_EXIT_HANDLE.exit_success();
     } else {
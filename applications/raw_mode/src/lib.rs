@@ -16,7 +16,8 @@ pub fn main(_args: Vec<String>) -> isize {
 
 fn run() -> Result<(), &'static str> {
     let discipline = app_io::line_discipline()?;
-    discipline.set_raw();
+    // Even if this app is killed, e.g., via Ctrl+C, its terminal is reset to cooked mode.
+    let _raw_mode = discipline.enter_raw_mode();
 
     let stdin = app_io::stdin()?;
     let stdout = app_io::stdout()?;
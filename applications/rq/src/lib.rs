@@ -36,6 +36,9 @@ pub fn main(args: Vec<String>) -> isize {
         };
 
         println!("\n{} (CPU: {})", core_type, cpu);
+        if let Some(count) = task::scheduler::empty_runqueue_count(cpu) {
+            println!("  empty runqueue events: {}", count);
+        }
 
         let mut runqueue_contents = String::new();
         for task in task_list.iter() {
@@ -60,7 +63,8 @@ fn print_usage(opts: Options) -> isize {
 
     brief.push_str(
         "Prints each CPU's ID, the tasks on its runqueue ('*' identifies the currently running \
-         task), and whether it is the boot CPU or not",
+         task), whether it is the boot CPU or not, and how many times its scheduler found no \
+         runnable task at all",
     );
 
     println!("{} \n", opts.usage(&brief));
@@ -0,0 +1,115 @@
+//! Shows histograms of the scheduler's wakeup latency, i.e., the time from when a task
+//! becomes runnable until it's switched in, separately for each priority class.
+//!
+//! * `schedlat` prints the 50th and 99th percentile and the maximum latency of each class.
+//! * `schedlat -b` also prints the histogram buckets, and `-c` breaks everything down per CPU.
+//! * `schedlat reset` clears all histograms, e.g., before re-running a workload for comparison.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+use task::latency::{self, Histogram, PriorityClass, NUM_BUCKETS};
+use time::Duration;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("b", "buckets", "print the count of every histogram bucket");
+    opts.optflag("c", "cpus", "print the histograms of each CPU separately");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match matches.free.first().map(String::as_str) {
+        None => {}
+        Some("reset") => {
+            latency::reset();
+            println!("Cleared all wakeup latency histograms.");
+            return 0;
+        }
+        Some(other) => {
+            println!("unknown subcommand: {}", other);
+            print_usage(opts);
+            return -1;
+        }
+    }
+
+    let buckets = matches.opt_present("b");
+    if matches.opt_present("c") {
+        for cpu in cpu::cpus() {
+            println!("CPU {}:", cpu.value());
+            print_histograms(|class| latency::histogram(cpu, class).unwrap_or_default(), buckets);
+        }
+    } else {
+        print_histograms(latency::total_histogram, buckets);
+    }
+    0
+}
+
+/// Prints the summary of the histogram of each priority class, as obtained from `histogram`,
+/// and the count of each bucket if `buckets` is true.
+fn print_histograms(histogram: impl Fn(PriorityClass) -> Histogram, buckets: bool) {
+    let histograms: Vec<_> = PriorityClass::ALL.iter().map(|class| (*class, histogram(*class))).collect();
+
+    println!("  {:<8}  {:>10}  {:>9}  {:>9}  {:>9}", "CLASS", "WAKEUPS", "P50", "P99", "MAX");
+    for (class, histogram) in &histograms {
+        println!("  {:<8}  {:>10}  {:>9}  {:>9}  {:>9}",
+            class.name(),
+            histogram.count(),
+            histogram.percentile(50).map_or(String::from("-"), fmt_duration),
+            histogram.percentile(99).map_or(String::from("-"), fmt_duration),
+            if histogram.count() == 0 { String::from("-") } else { fmt_duration(histogram.max) },
+        );
+    }
+
+    if buckets {
+        println!("  {:<8}  {:>10}  {:>10}  {:>10}", "BUCKET", "high", "normal", "low");
+        for bucket in 0..NUM_BUCKETS {
+            let label = match Histogram::bucket_upper_bound(bucket) {
+                Some(bound) => format!("<{}", fmt_duration(bound)),
+                None => format!(">={}", fmt_duration(Histogram::bucket_upper_bound(bucket - 1).unwrap_or_default())),
+            };
+            println!("  {:<8}  {:>10}  {:>10}  {:>10}",
+                label,
+                histograms[0].1.buckets[bucket],
+                histograms[1].1.buckets[bucket],
+                histograms[2].1.buckets[bucket],
+            );
+        }
+    }
+}
+
+/// Formats the given duration in microseconds, or in milliseconds if it's at least 1 ms.
+fn fmt_duration(duration: Duration) -> String {
+    let micros = duration.as_micros();
+    if micros < 1000 {
+        format!("{}us", micros)
+    } else {
+        format!("{}.{}ms", micros / 1000, micros % 1000 / 100)
+    }
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: schedlat [OPTION]... [reset]
+Shows the scheduler's wakeup latency, i.e., the time from when a task is woken up or spawned
+until it runs, for each priority class: high (negative nice value), normal, and low.
+Percentiles are rounded up to the upper bound of the histogram bucket that contains them.
+The `reset` subcommand clears all histograms.";
@@ -21,6 +21,7 @@ extern crate app_io;
 extern crate fs_node;
 extern crate environment;
 extern crate libterm;
+extern crate events;
 
 #[macro_use] extern crate alloc;
 #[macro_use] extern crate log;
@@ -792,7 +793,7 @@ impl Shell {
     /// Try to match the incomplete command against all internal commands. Returns a
     /// vector that contains all matching results.
     fn find_internal_cmd_match(&mut self, incomplete_cmd: &String) -> Result<Vec<String>, &'static str> {
-        let internal_cmds = ["fg", "bg", "jobs", "clear"];
+        let internal_cmds = ["fg", "bg", "jobs", "clear", "events"];
         let mut match_cmds = Vec::new();
         for cmd in internal_cmds.iter() {
             if cmd.starts_with(incomplete_cmd) {
@@ -1344,6 +1345,7 @@ impl Shell {
                 "fg" => return true,
                 "bg" => return true,
                 "clear" => return true,
+                "events" => return true,
                 _ => return false
             }
         }
@@ -1361,6 +1363,7 @@ impl Shell {
                 "fg" => self.execute_internal_fg(),
                 "bg" => self.execute_internal_bg(),
                 "clear" => self.execute_internal_clear(),
+                "events" => self.execute_internal_events(),
                 _ => Ok(())
             }
         } else {
@@ -1440,6 +1443,35 @@ impl Shell {
         Ok(())
     }
 
+    /// Execute `events` command. `events list` lists all named events in the `events` registry,
+    /// with their number of subscribers and posting statistics.
+    fn execute_internal_events(&mut self) -> Result<(), &'static str> {
+        let cmdline_copy = self.cmdline.clone();
+        let mut iter = cmdline_copy.split_whitespace();
+        iter.next();
+        let args: Vec<&str> = iter.collect();
+        if args != ["list"] {
+            self.terminal.lock().print_to_terminal("Usage: events list\n".to_string());
+        } else {
+            let all_events = events::list();
+            let mut out = format!("{:<32} {:>6} {:>10} {:>10} {:>10}\n",
+                "NAME", "SUBS", "POSTED", "DELIVERED", "DROPPED",
+            );
+            for event in &all_events {
+                out.push_str(&format!("{:<32} {:>6} {:>10} {:>10} {:>10}\n",
+                    event.name, event.subscribers, event.posted, event.delivered, event.dropped,
+                ));
+            }
+            if all_events.is_empty() {
+                out.push_str("No events have been subscribed or posted to.\n");
+            }
+            self.terminal.lock().print_to_terminal(out);
+        }
+        self.clear_cmdline(false)?;
+        self.redisplay_prompt();
+        Ok(())
+    }
+
     /// Execute `jobs` command. It lists all jobs.
     fn execute_internal_jobs(&mut self) -> Result<(), &'static str> {
         for (job_num, job_ref) in self.jobs.iter() {
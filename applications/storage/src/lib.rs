@@ -0,0 +1,88 @@
+//! Lists, rescans, and safely removes storage devices.
+//!
+//! * `storage list` lists all storage devices, numbered as in `diskstat`.
+//! * `storage rescan` re-probes all storage controllers for devices that were plugged in or unplugged.
+//! * `storage remove [--force] DISK` safely removes a device, e.g., before unplugging it.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("f", "force", "remove the device even if the layers atop it are in use or it can't be flushed");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    let result = match matches.free.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["list"] => {
+            list();
+            Ok(())
+        }
+        ["rescan"] => {
+            rescan();
+            Ok(())
+        }
+        ["remove", disk] => remove(disk, matches.opt_present("f")),
+        _ => {
+            print_usage(opts);
+            return -1;
+        }
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+fn list() {
+    for (i, device) in storage_manager::storage_devices().enumerate() {
+        let device = device.lock();
+        println!("Disk {}: {} blocks", i, device.size_in_blocks());
+    }
+}
+
+fn rescan() {
+    let changes = storage_manager::rescan();
+    println!("Found {} new and lost {} storage devices", changes.added.len(), changes.removed.len());
+    list();
+}
+
+fn remove(disk: &str, force: bool) -> Result<(), &'static str> {
+    let index = disk.parse::<usize>().map_err(|_| "invalid disk number")?;
+    let device = storage_manager::storage_devices().nth(index).ok_or("no such disk")?;
+    storage_manager::remove_device(&device, force)?;
+    println!("Removed disk {}; it can now be unplugged", index);
+    Ok(())
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: storage list
+       storage rescan
+       storage remove [--force] DISK
+Lists storage devices, re-probes storage controllers for devices that were plugged in or unplugged,
+or safely removes a device by first flushing and detaching the layers atop it, such as block caches.
+Disks are numbered as in `diskstat`; removing or adding a disk may renumber others.";
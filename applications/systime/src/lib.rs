@@ -0,0 +1,80 @@
+//! Shows the share of each CPU's time spent handling interrupts,
+//! and views or changes the cap on the share that deferred interrupt tasks may use.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("c", "cap", "set the share of a CPU that deferred interrupt tasks may use, in percent (0 disables the cap)", "PERCENT");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match run(matches) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn run(matches: getopts::Matches) -> Result<(), String> {
+    use alloc::format;
+    use interrupts::system_time;
+
+    if let Some(c) = matches.opt_str("c") {
+        let cap = c.parse::<u32>().map_err(|_| format!("invalid cap {:?}", c))?;
+        system_time::set_deferred_work_cap(cap)?;
+    }
+
+    match system_time::deferred_work_cap() {
+        0 => println!("Deferred work cap: disabled"),
+        cap => println!("Deferred work cap: {}% of each CPU", cap),
+    }
+    println!("{:<4}  {:>7}  {:>9}  {:>9}", "CPU", "IRQ%", "DEFERRED%", "THROTTLES");
+    for cpu in cpu::cpus() {
+        let Some(st) = system_time::system_time(cpu) else { continue };
+        println!("{:<4}  {:>5}.{}  {:>7}.{}  {:>9}",
+            cpu.value(),
+            st.irq_permille / 10, st.irq_permille % 10,
+            st.deferred_permille / 10, st.deferred_permille % 10,
+            st.throttles,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn run(_matches: getopts::Matches) -> Result<(), String> {
+    Err(String::from("system time accounting is only supported on x86_64"))
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: systime [OPTION]
+Shows the share of each CPU's time spent handling interrupts over the last measurement window.
+IRQ% is the share spent in interrupt handlers;
+DEFERRED% is the share spent in deferred interrupt tasks;
+THROTTLES counts how often deferred tasks yielded because they exceeded the cap.";
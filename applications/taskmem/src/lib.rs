@@ -0,0 +1,139 @@
+//! Shows the memory charged to tasks and sets per-task memory limits.
+//!
+//! * `taskmem` lists the tasks that use the most memory.
+//! * `taskmem <id>` shows the memory usage and limit of one task.
+//! * `taskmem <id> limit <size>` sets that task's limit, e.g., `64M`, or removes it with `none`.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use app_io::println;
+use getopts::Options;
+
+/// The number of tasks listed by default.
+const DEFAULT_TOP_COUNT: usize = 10;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("n", "count", "the number of tasks to list (default 10)", "COUNT");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    match matches.free.as_slice() {
+        [] => {
+            let count = match matches.opt_str("n").map(|n| n.parse::<usize>()) {
+                None => DEFAULT_TOP_COUNT,
+                Some(Ok(n)) => n,
+                Some(Err(_)) => {
+                    println!("Error: invalid count");
+                    return -1;
+                }
+            };
+            print_top(count);
+            0
+        }
+        [id] => match get_task(id) {
+            Ok(task) => {
+                print_task(&task);
+                0
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                -1
+            }
+        },
+        [id, cmd, size] if cmd == "limit" => {
+            let task = match get_task(id) {
+                Ok(t) => t,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return -1;
+                }
+            };
+            let limit = if size == "none" {
+                None
+            } else {
+                match parse_size(size) {
+                    Ok(bytes) => Some(bytes),
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return -1;
+                    }
+                }
+            };
+            task.set_memory_limit(limit);
+            print_task(&task);
+            0
+        }
+        _ => {
+            print_usage(opts);
+            -1
+        }
+    }
+}
+
+fn get_task(id: &str) -> Result<task::TaskRef, &'static str> {
+    let id = id.parse::<usize>().map_err(|_| "invalid task ID")?;
+    task::get_task(id)
+        .and_then(|weak| weak.upgrade())
+        .ok_or("no task with that ID exists")
+}
+
+/// Parses a size in bytes with an optional `K`, `M`, or `G` suffix.
+fn parse_size(size: &str) -> Result<usize, &'static str> {
+    let (digits, shift) = match size.as_bytes().last() {
+        Some(b'K' | b'k') => (&size[..size.len() - 1], 10),
+        Some(b'M' | b'm') => (&size[..size.len() - 1], 20),
+        Some(b'G' | b'g') => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+    digits.parse::<usize>()
+        .map_err(|_| "invalid size")?
+        .checked_mul(1 << shift)
+        .ok_or("size is too large")
+}
+
+fn print_task(task: &task::TaskRef) {
+    println!("{:<10}  {:<12}  {:<12}  NAME", "ID", "MEM(KiB)", "LIMIT(KiB)");
+    print_row(task, task.memory_usage());
+}
+
+fn print_top(count: usize) {
+    println!("{:<10}  {:<12}  {:<12}  NAME", "ID", "MEM(KiB)", "LIMIT(KiB)");
+    for (task, usage) in task::top_memory_consumers(count) {
+        print_row(&task, usage);
+    }
+}
+
+fn print_row(task: &task::TaskRef, usage: usize) {
+    let limit = task.memory_limit()
+        .map(|limit| format!("{}", limit / 1024))
+        .unwrap_or_else(|| String::from("-"));
+    println!("{:<10}  {:<12}  {:<12}  {}", task.id, usage / 1024, limit, task.name);
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: taskmem [OPTION]
+       taskmem <ID>
+       taskmem <ID> limit <SIZE | none>
+Shows the memory charged to tasks, listing the tasks that use the most memory by default.
+SIZE is in bytes, with an optional K, M, or G suffix, e.g., 64M.
+Allocations made by a task beyond its limit fail with a memory quota error.";
@@ -13,6 +13,8 @@ use alloc::vec::Vec;
 use alloc::string::String;
 use app_io::println;
 use ata::AtaDrive;
+use async_block_io::{AsyncBlockDevice, CompletionHandle};
+use dma_buffer::DmaBuffer;
 use io::{ByteReader, ByteReaderWrapper, ByteReaderWriterWrapper, ByteWriter, ByteWriterWrapper, Reader, ReaderWriter};
 use log::{debug, error, info, trace};
 
@@ -38,6 +40,17 @@ pub fn main(_args: Vec<String>) -> isize {
             let sectors_read = ata_drive.read_pio(&mut initial_buf[..], 0).unwrap();
             debug!("[SUCCESS] sectors_read: {:?}", sectors_read);
             debug!("{:?}", core::str::from_utf8(&initial_buf));
+
+            // Read the same sectors again using the asynchronous API.
+            let completion = CompletionHandle::new();
+            let buffer = DmaBuffer::new(initial_buf.len()).unwrap();
+            ata_drive.submit_read(0, buffer, completion.clone()).map_err(|(e, _)| e).unwrap();
+            let buffer = completion.wait().map_err(|(e, _)| e).unwrap();
+            if buffer.as_slice(0, initial_buf.len()).unwrap() == initial_buf {
+                info!("AsyncBlockDevice read worked");
+            } else {
+                error!("AsyncBlockDevice read failed");
+            }
         }
     }
     // Read 10 sectors from the drive using the `StorageDevice` trait methods.
@@ -0,0 +1,115 @@
+//! Tests that [`task::scheduler::offline_cpu()`] drains a CPU's run queue
+//! and that [`task::scheduler::online_cpu()`] resumes the tasks pinned to it.
+//!
+//! A worker task pinned to a CPU other than the bootstrap CPU and the current CPU
+//! repeatedly increments a counter and yields.
+//! While that CPU is offline, the counter must not change,
+//! and once it's back online, the worker must run on it again.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use app_io::println;
+use cpu::CpuId;
+
+/// The number of times the worker has run its loop.
+static ITERATIONS: AtomicUsize = AtomicUsize::new(0);
+/// Set to stop the worker.
+static STOP: AtomicBool = AtomicBool::new(false);
+/// Set if the worker ever ran on a CPU other than the one it's pinned to.
+static WRONG_CPU: AtomicBool = AtomicBool::new(false);
+
+/// The number of times to yield while checking that the worker doesn't run.
+const YIELDS_WHILE_OFFLINE: usize = 1000;
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("test_cpu_hotplug failed: {}", e);
+            -1
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    let current = cpu::current_cpu();
+    let bootstrap = cpu::bootstrap_cpu();
+    let Some(target) = cpu::cpus().find(|cpu| *cpu != current && Some(*cpu) != bootstrap) else {
+        println!("skipped: CPU hotplug requires a CPU other than the bootstrap CPU and the current CPU");
+        return Ok(());
+    };
+
+    if task::scheduler::offline_cpu(current).is_ok() {
+        return Err(String::from("the current CPU was taken offline"));
+    }
+
+    let worker = spawn::new_task_builder(worker, target)
+        .name(String::from("test_cpu_hotplug_worker"))
+        .pin_on_cpu(target)
+        .spawn()
+        .map_err(|e| format!("couldn't spawn worker: {e}"))?;
+    wait_for_iterations_after(0);
+
+    let result = check_offline(target);
+    if result.is_err() {
+        // Don't leave the CPU offline, in which case this fails harmlessly.
+        let _ = task::scheduler::online_cpu(target);
+    }
+    STOP.store(true, Ordering::Release);
+    result?;
+
+    worker.join().map_err(|e| format!("couldn't join worker: {e}"))?;
+    if WRONG_CPU.load(Ordering::Acquire) {
+        return Err(format!("the worker ran on a CPU other than CPU {target}"));
+    }
+    println!("passed: took CPU {target} offline and brought it back online");
+    Ok(())
+}
+
+/// Takes `target` offline, checks that its pinned worker doesn't run, and brings it back online.
+fn check_offline(target: CpuId) -> Result<(), String> {
+    task::scheduler::offline_cpu(target).map_err(|e| format!("couldn't take CPU {target} offline: {e}"))?;
+    if task::scheduler::online_cpus().contains(&target) {
+        return Err(format!("CPU {target} is still listed as online"));
+    }
+    if task::scheduler::offline_cpu(target).is_ok() {
+        return Err(format!("CPU {target} was taken offline twice"));
+    }
+
+    let before = ITERATIONS.load(Ordering::Acquire);
+    for _ in 0..YIELDS_WHILE_OFFLINE {
+        task::schedule();
+    }
+    let after = ITERATIONS.load(Ordering::Acquire);
+    if after != before {
+        return Err(format!("the worker ran {} times while CPU {target} was offline", after - before));
+    }
+
+    let resumed = task::scheduler::online_cpu(target).map_err(|e| format!("couldn't bring CPU {target} online: {e}"))?;
+    if resumed != 1 {
+        return Err(format!("expected 1 task to be resumed on CPU {target}, but {resumed} were"));
+    }
+    wait_for_iterations_after(after);
+    Ok(())
+}
+
+/// Yields until the worker has run its loop more than `count` times.
+fn wait_for_iterations_after(count: usize) {
+    while ITERATIONS.load(Ordering::Acquire) <= count {
+        task::schedule();
+    }
+}
+
+fn worker(pinned: CpuId) {
+    while !STOP.load(Ordering::Acquire) {
+        if cpu::current_cpu() != pinned {
+            WRONG_CPU.store(true, Ordering::Release);
+        }
+        ITERATIONS.fetch_add(1, Ordering::AcqRel);
+        task::schedule();
+    }
+}
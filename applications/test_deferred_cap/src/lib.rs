@@ -0,0 +1,182 @@
+//! Tests that capping the share of a CPU used by deferred interrupt tasks
+//! keeps the scheduling latency of other tasks on that CPU bounded.
+//!
+//! A deferred task that busy-waits for a few milliseconds per activation
+//! is flooded with work by a task that unblocks it as soon as it blocks,
+//! emulating a device that raises interrupts faster than its driver can handle them.
+//! Meanwhile, a high-priority probe task on the same CPU repeatedly sleeps for one millisecond
+//! and measures how late it wakes up. The 99th percentile of the scheduler's wakeup latency
+//! for high-priority tasks, as measured by [`task::latency`], is also reported for each run.
+//!
+//! This runs once without a cap and once with a cap of 25%, and checks that
+//! with the cap in place, the deferred task's share of the CPU stays near the cap,
+//! it was actually throttled, and the probe's worst-case latency stays bounded.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("test_deferred_cap failed: {}", e);
+            -1
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn run() -> Result<(), &'static str> {
+    println!("skipped: system time accounting is only supported on x86_64");
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+use x86_64_impl::run;
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64_impl {
+    use alloc::string::String;
+    use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use app_io::println;
+    use cpu::CpuId;
+    use interrupts::system_time;
+    use sleep::Duration;
+    use task::{latency::{self, PriorityClass}, TaskRef};
+    use time::Instant;
+
+    /// The cap on the share of the CPU that deferred tasks may use in the capped run.
+    const CAP_PERCENT: u32 = 25;
+    /// How far above the cap the deferred task's measured share may be.
+    const CAP_SLACK_PERCENT: u32 = 15;
+    /// How long the deferred task busy-waits per activation.
+    const WORK_PER_ACTIVATION: Duration = Duration::from_millis(4);
+    /// How long each run lasts, which must span a few system time measurement windows.
+    const RUN_DURATION: Duration = Duration::from_secs(1);
+    /// How long the probe task sleeps in each iteration.
+    const PROBE_SLEEP: Duration = Duration::from_millis(1);
+    /// The nice value of the probe task, which puts it in the high priority class.
+    const PROBE_NICE: i8 = -10;
+    /// The worst-case latency that the probe may observe in the capped run.
+    const MAX_CAPPED_LATENCY: Duration = Duration::from_millis(50);
+
+    /// Whether the flooder task should keep unblocking the deferred task.
+    static FLOODING: AtomicBool = AtomicBool::new(false);
+    /// The worst-case latency observed by the probe task in the current run, in microseconds.
+    static MAX_LATENCY_US: AtomicU64 = AtomicU64::new(0);
+
+    /// The results of a single run.
+    struct RunResult {
+        max_latency_us: u64,
+        high_p99_us: u64,
+        deferred_permille: u32,
+        throttles: u64,
+    }
+
+    pub fn run() -> Result<(), &'static str> {
+        let cpu = cpu::current_cpu();
+        let original_cap = system_time::deferred_work_cap();
+
+        let deferred = deferred_interrupt_tasks::spawn_deferred_task(
+            deferred_work,
+            (),
+            Some("test_deferred_cap_worker"),
+            Some(cpu),
+        )?;
+
+        let uncapped = run_once(cpu, &deferred, 0);
+        let capped = run_once(cpu, &deferred, CAP_PERCENT);
+        system_time::set_deferred_work_cap(original_cap)?;
+
+        // The deferred task loops forever, so it must be killed once it's no longer needed.
+        deferred.kill(task::KillReason::Requested)?;
+        deferred.join()?;
+
+        let (uncapped, capped) = (uncapped?, capped?);
+        for (name, result) in [("uncapped", &uncapped), ("capped", &capped)] {
+            println!("{}: deferred work used {}.{}% of CPU {}, throttled {} times, max probe latency {} us, high-priority p99 wakeup latency {} us",
+                name, result.deferred_permille / 10, result.deferred_permille % 10,
+                cpu, result.throttles, result.max_latency_us, result.high_p99_us,
+            );
+        }
+
+        if capped.throttles == 0 {
+            return Err("the deferred task was never throttled despite exceeding the cap");
+        }
+        if capped.deferred_permille > (CAP_PERCENT + CAP_SLACK_PERCENT) * 10 {
+            return Err("the deferred task's share of the CPU exceeded the cap");
+        }
+        if capped.max_latency_us > MAX_CAPPED_LATENCY.as_micros() as u64 {
+            return Err("the probe task's scheduling latency wasn't bounded by the cap");
+        }
+        Ok(())
+    }
+
+    /// Floods the given `deferred` task with work for [`RUN_DURATION`] with the given cap,
+    /// while a probe task on the same `cpu` measures its scheduling latency.
+    fn run_once(cpu: CpuId, deferred: &TaskRef, cap_percent: u32) -> Result<RunResult, &'static str> {
+        system_time::set_deferred_work_cap(cap_percent)?;
+        let throttles_before = system_time::system_time(cpu).ok_or("CPU isn't tracked")?.throttles;
+        MAX_LATENCY_US.store(0, Ordering::Relaxed);
+        latency::reset();
+        FLOODING.store(true, Ordering::Release);
+
+        let flooder = spawn::new_task_builder(flooder, deferred.clone())
+            .name(String::from("test_deferred_cap_flooder"))
+            .pin_on_cpu(cpu)
+            .spawn()?;
+        let probe = spawn::new_task_builder(probe, ())
+            .name(String::from("test_deferred_cap_probe"))
+            .pin_on_cpu(cpu)
+            .spawn()?;
+
+        let probe_result = probe.join();
+        FLOODING.store(false, Ordering::Release);
+        let flooder_result = flooder.join();
+        probe_result?;
+        flooder_result?;
+
+        // The last complete measurement window fell within the flood.
+        let after = system_time::system_time(cpu).ok_or("CPU isn't tracked")?;
+        let high_p99 = latency::total_histogram(PriorityClass::High).percentile(99).unwrap_or_default();
+        Ok(RunResult {
+            max_latency_us: MAX_LATENCY_US.load(Ordering::Relaxed),
+            high_p99_us: high_p99.as_micros() as u64,
+            deferred_permille: after.deferred_permille,
+            throttles: after.throttles - throttles_before,
+        })
+    }
+
+    /// The deferred action, which emulates an expensive bottom half by busy-waiting.
+    fn deferred_work(_: &()) -> Result<(), ()> {
+        let start = Instant::now();
+        while start.elapsed() < WORK_PER_ACTIVATION {
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Unblocks the `deferred` task whenever it blocks, until the run is over.
+    fn flooder(deferred: TaskRef) {
+        while FLOODING.load(Ordering::Acquire) {
+            let _ = deferred.unblock();
+            task::scheduler::schedule();
+        }
+    }
+
+    /// Repeatedly sleeps for [`PROBE_SLEEP`] and records the worst-case wakeup latency.
+    fn probe(_: ()) {
+        task::scheduler::nice(PROBE_NICE);
+        let start = Instant::now();
+        while start.elapsed() < RUN_DURATION {
+            let before = Instant::now();
+            let _ = sleep::sleep(PROBE_SLEEP);
+            let latency = before.elapsed().saturating_sub(PROBE_SLEEP);
+            MAX_LATENCY_US.fetch_max(latency.as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Tests the named event registry in the `events` crate.
+//!
+//! * A posting is delivered to every subscriber, and can be polled without blocking.
+//! * A blocked subscriber is woken by a posting from another task.
+//! * A timed wait with no posting times out.
+//! * Postings beyond a subscriber's queue capacity are dropped and counted.
+//! * A subscriber whose task is killed is removed from the event.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use sleep::Duration;
+use time::Instant;
+
+/// The event name used by this test, which no other subsystem posts to.
+const EVENT: &str = "test_events.ping";
+/// How long a timed wait waits for in the timeout test.
+const TIMEOUT: Duration = Duration::from_millis(50);
+/// How long to wait before posting to a blocked subscriber.
+const POST_DELAY: Duration = Duration::from_millis(20);
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => {
+            println!("test_events: all tests passed.");
+            0
+        }
+        Err(e) => {
+            println!("test_events failed: {}", e);
+            -1
+        }
+    }
+}
+
+fn run() -> Result<(), &'static str> {
+    test_poll()?;
+    test_blocking_wait()?;
+    test_timeout()?;
+    test_overflow()?;
+    test_killed_subscriber()
+}
+
+fn subscribers() -> usize {
+    events::list().iter()
+        .find(|e| e.name == EVENT)
+        .map_or(0, |e| e.subscribers)
+}
+
+fn test_poll() -> Result<(), &'static str> {
+    let first = events::subscribe(EVENT);
+    let second = events::subscribe(EVENT);
+    if first.try_recv().is_some() {
+        return Err("a new subscription had a pending posting");
+    }
+    if events::post(EVENT, 1) != 2 {
+        return Err("a posting wasn't delivered to both subscribers");
+    }
+    if first.try_recv() != Some(1) || second.try_recv() != Some(1) {
+        return Err("a subscriber didn't receive the posting");
+    }
+    if first.try_recv().is_some() {
+        return Err("a posting was received twice");
+    }
+    drop((first, second));
+    if subscribers() != 0 {
+        return Err("dropped subscriptions were not removed");
+    }
+    Ok(())
+}
+
+fn test_blocking_wait() -> Result<(), &'static str> {
+    let waiter = spawn::new_task_builder(|_: ()| {
+        let subscription = events::subscribe(EVENT);
+        subscription.wait()
+    }, ())
+        .name(String::from("test_events_waiter"))
+        .spawn()?;
+
+    // Wait until the waiter has subscribed, then give it time to block.
+    while subscribers() == 0 {
+        task::schedule();
+    }
+    sleep::sleep(POST_DELAY).map_err(|_| "failed to sleep")?;
+    events::post(EVENT, 42);
+
+    match waiter.join()? {
+        task::ExitValue::Completed(value) if value.downcast_ref::<usize>() == Some(&42) => Ok(()),
+        task::ExitValue::Completed(_) => Err("the waiter received the wrong posting"),
+        task::ExitValue::Killed(_) => Err("the waiter task was killed"),
+    }
+}
+
+fn test_timeout() -> Result<(), &'static str> {
+    let subscription = events::subscribe(EVENT);
+    let start = Instant::now();
+    if subscription.wait_timeout(TIMEOUT).is_some() {
+        return Err("a timed wait received a posting that was never made");
+    }
+    if start.elapsed() < TIMEOUT {
+        return Err("a timed wait returned before its timeout elapsed");
+    }
+    events::post(EVENT, 7);
+    if subscription.wait_timeout(TIMEOUT) != Some(7) {
+        return Err("a timed wait didn't receive a pending posting");
+    }
+    Ok(())
+}
+
+fn test_overflow() -> Result<(), &'static str> {
+    const CAPACITY: usize = 4;
+    let subscription = events::subscribe_with_capacity(EVENT, CAPACITY);
+    for i in 0..CAPACITY + 3 {
+        events::post(EVENT, i);
+    }
+    if subscription.dropped() != 3 {
+        return Err("postings beyond the queue capacity were not counted as dropped");
+    }
+    let received: Vec<usize> = core::iter::from_fn(|| subscription.try_recv()).collect();
+    if received != (0..CAPACITY).collect::<Vec<_>>() {
+        return Err("the oldest postings were not the ones kept");
+    }
+    Ok(())
+}
+
+fn test_killed_subscriber() -> Result<(), &'static str> {
+    let waiter = spawn::new_task_builder(|_: ()| {
+        // This subscription is never dropped, because this task is killed while waiting.
+        let subscription = events::subscribe(EVENT);
+        subscription.wait()
+    }, ())
+        .name(String::from("test_events_killed_waiter"))
+        .spawn()?;
+
+    while subscribers() == 0 {
+        task::schedule();
+    }
+    waiter.kill(task::KillReason::Requested)?;
+    let _ = waiter.join()?;
+
+    if subscribers() != 0 {
+        return Err("a killed task's subscription was not removed");
+    }
+    // Posting must still work and reach no one.
+    if events::post(EVENT, 0) != 0 {
+        return Err("a posting was delivered to a killed task's subscription");
+    }
+    Ok(())
+}
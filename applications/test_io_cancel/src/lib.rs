@@ -0,0 +1,191 @@
+//! Tests that cancelling a large block read releases all of its requests and DMA buffers,
+//! both when its [`CancellationToken`] is cancelled explicitly and when its task is killed.
+//!
+//! The read is issued to a mock device that never completes any request by itself,
+//! like a wedged drive, but aborts its outstanding requests once they're cancelled.
+//!
+//! Note: DMA buffers are only tracked in debug builds, so the buffer leak check is skipped otherwise.
+//! Block cache pinning is not exercised, as the block cache doesn't support pinning.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec, vec::Vec};
+use app_io::println;
+use async_block_io::{AsyncBlockDevice, CancellationToken, CompletionHandle, RequestKind, RequestTable};
+use dma_buffer::DmaBuffer;
+use io::{BlockIo, IoError};
+use sleep::Duration;
+use spin::Mutex;
+use task::{ExitValue, KillReason};
+
+/// The block size of the mock device.
+const BLOCK_SIZE: usize = 512;
+/// The number of requests that the mock device accepts at once.
+const QUEUE_DEPTH: usize = 4;
+/// The number of blocks in each request of the large read.
+const BLOCKS_PER_REQUEST: usize = 8;
+/// The total number of blocks in the large read, which is far more than can be in flight at once.
+const TOTAL_BLOCKS: usize = 1024;
+/// How long to wait for the reader to fill the mock device's queue.
+const QUEUE_FILL_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub fn main(_args: Vec<String>) -> isize {
+    match rmain() {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+fn rmain() -> Result<(), &'static str> {
+    let baseline = Usage::now();
+
+    println!("Cancelling a large read via its token...");
+    let (table, device) = WedgedDevice::new();
+    let token = CancellationToken::new();
+    let reader = spawn::new_task_builder(reader, (device.clone(), Some(token.clone())))
+        .name(String::from("test_io_cancel_reader"))
+        .spawn()?;
+    wait_until_queue_full(&table, baseline)?;
+    token.cancel();
+    let result = match reader.join()? {
+        ExitValue::Completed(value) => *value.downcast::<Result<(), IoError>>()
+            .map_err(|_| "reader task returned an unexpected type")?,
+        ExitValue::Killed(_) => return Err("reader task was unexpectedly killed"),
+    };
+    if !matches!(result, Err(IoError::Cancelled)) {
+        println!("reader returned {:?}", result);
+        return Err("cancelled read didn't return IoError::Cancelled");
+    }
+    check_released(&table, baseline)?;
+
+    println!("Killing a task in the middle of a large read...");
+    let (table, device) = WedgedDevice::new();
+    let reader = spawn::new_task_builder(reader, (device.clone(), None))
+        .name(String::from("test_io_cancel_reader"))
+        .spawn()?;
+    wait_until_queue_full(&table, baseline)?;
+    reader.kill(KillReason::Requested)?;
+    match reader.join()? {
+        ExitValue::Killed(KillReason::Requested) => { }
+        _ => return Err("reader task exited for an unexpected reason"),
+    }
+    check_released(&table, baseline)?;
+
+    println!("Success!");
+    Ok(())
+}
+
+/// Reads the whole mock `device`, which never finishes, using the given `token`,
+/// or a token that's cancelled when this task exits if none is given.
+fn reader((device, token): (Arc<Mutex<WedgedDevice>>, Option<CancellationToken>)) -> Result<(), IoError> {
+    let token = token.unwrap_or_else(CancellationToken::for_current_task);
+    let mut buffer = vec![0u8; TOTAL_BLOCKS * BLOCK_SIZE];
+    async_block_io::read_range(&*device, 0, &mut buffer, BLOCKS_PER_REQUEST, &token)
+}
+
+/// The number of pending block I/O requests and live DMA buffers in the system.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Usage {
+    requests: usize,
+    buffers: Option<usize>,
+}
+
+impl Usage {
+    fn now() -> Usage {
+        Usage {
+            requests: async_block_io::pending_requests(),
+            buffers: dma_buffer::live_buffer_count(),
+        }
+    }
+}
+
+/// Waits until the reader has filled the queue of the mock device behind `table`,
+/// and checks that each outstanding request holds exactly one DMA buffer.
+fn wait_until_queue_full(table: &RequestTable, baseline: Usage) -> Result<(), &'static str> {
+    let mut waited = Duration::ZERO;
+    while table.outstanding() < QUEUE_DEPTH {
+        if waited >= QUEUE_FILL_TIMEOUT {
+            return Err("reader didn't fill the mock device's queue");
+        }
+        sleep::sleep(Duration::from_millis(1)).map_err(|_| "failed to sleep")?;
+        waited += Duration::from_millis(1);
+    }
+    let expected = Usage {
+        requests: baseline.requests + QUEUE_DEPTH,
+        buffers: baseline.buffers.map(|b| b + QUEUE_DEPTH),
+    };
+    if Usage::now() != expected {
+        println!("expected {:?} during the read, but found {:?}", expected, Usage::now());
+        return Err("large read allocated more requests or DMA buffers than it has in flight");
+    }
+    Ok(())
+}
+
+/// Checks that the cancelled read left no outstanding requests on the mock device behind `table`,
+/// and that the number of pending requests and live DMA buffers is back to the `baseline`.
+fn check_released(table: &RequestTable, baseline: Usage) -> Result<(), &'static str> {
+    if table.outstanding() != 0 {
+        return Err("cancelled requests are still outstanding on the device");
+    }
+    let usage = Usage::now();
+    if usage != baseline {
+        println!("expected {:?} after cancellation, but found {:?}", baseline, usage);
+        return Err("cancelled read leaked requests or DMA buffers");
+    }
+    Ok(())
+}
+
+/// A mock device that never completes a request by itself,
+/// but aborts all of its cancelled requests once any of them is cancelled.
+struct WedgedDevice {
+    table: Arc<RequestTable>,
+}
+
+impl WedgedDevice {
+    fn new() -> (Arc<RequestTable>, Arc<Mutex<WedgedDevice>>) {
+        let table = Arc::new(RequestTable::new(QUEUE_DEPTH));
+        let device = Arc::new(Mutex::new(WedgedDevice { table: table.clone() }));
+        (table, device)
+    }
+}
+
+impl BlockIo for WedgedDevice {
+    fn block_size(&self) -> usize { BLOCK_SIZE }
+}
+
+impl AsyncBlockDevice for WedgedDevice {
+    fn queue_depth(&self) -> usize { QUEUE_DEPTH }
+
+    fn submit_read(
+        &mut self,
+        block_offset: usize,
+        buffer: DmaBuffer,
+        completion: CompletionHandle,
+    ) -> Result<(), (IoError, DmaBuffer)> {
+        if completion.is_cancelled() {
+            completion.complete(Err((IoError::Cancelled, buffer)));
+            return Ok(());
+        }
+        self.table.insert(RequestKind::Read, block_offset, buffer, completion.clone())?;
+        // This device never touches the buffer, so it can always abort immediately.
+        let table = self.table.clone();
+        completion.on_cancel(Box::new(move || {
+            table.complete_cancelled();
+        }));
+        Ok(())
+    }
+
+    fn submit_write(
+        &mut self,
+        _block_offset: usize,
+        buffer: DmaBuffer,
+        _completion: CompletionHandle,
+    ) -> Result<(), (IoError, DmaBuffer)> {
+        Err((IoError::InvalidInput, buffer))
+    }
+}
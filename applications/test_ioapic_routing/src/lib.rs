@@ -0,0 +1,77 @@
+//! Tests that global system interrupts (GSIs) are routed to the IOAPIC that handles them.
+//!
+//! For each IOAPIC, this checks that:
+//! * its GSI range doesn't overlap that of any other IOAPIC,
+//! * the first and last GSIs in its range resolve to that IOAPIC at the expected lines, and
+//! * accessing a line past its last redirection entry is rejected.
+//!
+//! It also checks that a GSI beyond every IOAPIC's range is rejected.
+//! No redirection entries are modified, so this is safe to run on a live system.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("test_ioapic_routing failed: {}", e);
+            -1
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn run() -> Result<(), String> {
+    println!("skipped: IOAPICs only exist on x86_64");
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn run() -> Result<(), String> {
+    use alloc::format;
+
+    let ioapics: Vec<(u8, core::ops::Range<u32>)> = ioapic::get_ioapics()
+        .map(|(_id, ioapic)| {
+            let ioapic = ioapic.lock();
+            (ioapic.id, ioapic.gsi_range())
+        })
+        .collect();
+    if ioapics.is_empty() {
+        println!("skipped: no IOAPICs were found");
+        return Ok(());
+    }
+
+    for (i, (id, range)) in ioapics.iter().enumerate() {
+        println!("IOAPIC {} handles GSIs {:?}", id, range);
+        if range.is_empty() {
+            return Err(format!("IOAPIC {id} has no redirection entries"));
+        }
+        if let Some((other_id, _)) = ioapics[i + 1 ..].iter()
+            .find(|(_, other)| range.start < other.end && other.start < range.end)
+        {
+            return Err(format!("the GSI ranges of IOAPICs {id} and {other_id} overlap"));
+        }
+        for (gsi, expected_pin) in [(range.start, 0), (range.end - 1, range.end - 1 - range.start)] {
+            let (ioapic_id, pin, _entry) = ioapic::gsi_redirection_entry(gsi)?;
+            if ioapic_id != *id || pin as u32 != expected_pin {
+                return Err(format!("GSI {gsi} resolved to IOAPIC {ioapic_id} line {pin} instead of IOAPIC {id} line {expected_pin}"));
+            }
+        }
+        let past_last_line = (range.end - range.start) as u8;
+        let ioapic = ioapic::get_ioapic(*id).ok_or("an IOAPIC disappeared")?;
+        if ioapic.lock().redirection_entry(past_last_line).is_ok() {
+            return Err(format!("IOAPIC {id} accepted line {past_last_line}, past its last redirection entry"));
+        }
+    }
+
+    let beyond_all = ioapics.iter().map(|(_, range)| range.end).max().unwrap_or(0);
+    if ioapic::gsi_redirection_entry(beyond_all).is_ok() {
+        return Err(format!("GSI {beyond_all}, which no IOAPIC handles, was accepted"));
+    }
+    Ok(())
+}
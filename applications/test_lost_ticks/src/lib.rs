@@ -0,0 +1,119 @@
+//! Tests that timer ticks lost while a CPU has interrupts disabled are accounted for,
+//! and that a task whose sleep expired in the meantime still wakes up soon afterwards.
+//!
+//! A holder task on the bootstrap CPU, which receives timer interrupts in both APIC and PIC mode,
+//! lets a sleeper task on the same CPU go to sleep and then keeps interrupts disabled
+//! for several timeslice periods, well past the sleeper's deadline.
+//! Once interrupts are re-enabled, the sleeper must wake up within a bounded delay,
+//! and that CPU's lost tick count must have grown by about the number of periods that were missed.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use app_io::println;
+use sleep::Duration;
+use time::Instant;
+
+/// How many timeslice periods the holder keeps interrupts disabled.
+const HOLD_PERIODS: u32 = 6;
+/// How many timeslice periods the sleeper sleeps, which must end while interrupts are disabled.
+const SLEEP_PERIODS: u32 = 2;
+/// How many timeslice periods the sleeper may take to wake up after interrupts are re-enabled.
+const MAX_WAKE_DELAY_PERIODS: u32 = 3;
+/// How many fewer ticks than the number of missed periods may be counted as lost,
+/// which tolerates the jitter of the ticks right before and after the interrupt-disabled section.
+const LOST_TICKS_SLACK: u64 = 2;
+
+/// When the sleeper started and finished sleeping, in nanoseconds since [`Instant::ZERO`].
+static SLEPT_AT: AtomicU64 = AtomicU64::new(0);
+static WOKE_AT: AtomicU64 = AtomicU64::new(0);
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("test_lost_ticks failed: {}", e);
+            -1
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    let period = boot_args::timeslice_period();
+    let cpu = cpu::bootstrap_cpu().unwrap_or_else(cpu::current_cpu);
+    let lost_before = scheduler::lost_ticks::lost_ticks(cpu);
+
+    let holder = spawn::new_task_builder(hold_interrupts, period)
+        .name(String::from("test_lost_ticks_holder"))
+        .pin_on_cpu(cpu)
+        .spawn()?;
+    let (held_at, released_at) = match holder.join()? {
+        task::ExitValue::Completed(value) => value
+            .downcast_ref::<Result<(Instant, Instant), &'static str>>()
+            .copied()
+            .ok_or("holder task returned an unexpected value")??,
+        task::ExitValue::Killed(_) => return Err(String::from("holder task was killed")),
+    };
+    let lost = scheduler::lost_ticks::lost_ticks(cpu) - lost_before;
+
+    let deadline = instant(SLEPT_AT.load(Ordering::Acquire)) + period * SLEEP_PERIODS;
+    if deadline <= held_at || deadline >= released_at {
+        return Err(String::from("the sleeper's deadline didn't expire while interrupts were disabled"));
+    }
+    let wake_delay = instant(WOKE_AT.load(Ordering::Acquire)).duration_since(released_at);
+    println!("held interrupts for {:?}, lost {} ticks, sleeper woke {:?} after interrupts were re-enabled",
+        released_at.duration_since(held_at), lost, wake_delay,
+    );
+
+    let max_wake_delay = period * MAX_WAKE_DELAY_PERIODS;
+    if wake_delay > max_wake_delay {
+        return Err(format!("the sleeper woke {wake_delay:?} late (bound: {max_wake_delay:?})"));
+    }
+    let missed_periods = (released_at.duration_since(held_at).as_nanos() / period.as_nanos()) as u64;
+    let min_lost = missed_periods.saturating_sub(LOST_TICKS_SLACK);
+    if lost < min_lost {
+        return Err(format!("only {lost} ticks were counted as lost (expected at least {min_lost})"));
+    }
+    Ok(())
+}
+
+/// The entry point of the holder task, which returns when it disabled and re-enabled interrupts.
+fn hold_interrupts(period: Duration) -> Result<(Instant, Instant), &'static str> {
+    let sleeper = spawn::new_task_builder(sleeper_task, period * SLEEP_PERIODS)
+        .name(String::from("test_lost_ticks_sleeper"))
+        .pin_on_cpu(cpu::current_cpu())
+        .spawn()?;
+    // The sleeper must be asleep before interrupts are disabled, as it can't run afterwards.
+    while sleeper.is_runnable() {
+        task::schedule();
+    }
+
+    let held_interrupts = irq_safety::hold_interrupts();
+    let held_at = Instant::now();
+    while held_at.elapsed() < period * HOLD_PERIODS {
+        core::hint::spin_loop();
+    }
+    let released_at = Instant::now();
+    drop(held_interrupts);
+
+    sleeper.join()?;
+    Ok((held_at, released_at))
+}
+
+/// The entry point of the sleeper task.
+fn sleeper_task(duration: Duration) {
+    SLEPT_AT.store(nanos(Instant::now()), Ordering::Release);
+    let _ = sleep::sleep(duration);
+    WOKE_AT.store(nanos(Instant::now()), Ordering::Release);
+}
+
+fn nanos(instant: Instant) -> u64 {
+    instant.duration_since(Instant::ZERO).as_nanos() as u64
+}
+
+fn instant(nanos: u64) -> Instant {
+    Instant::ZERO + Duration::from_nanos(nanos)
+}
@@ -0,0 +1,174 @@
+//! Tests the decoding of machine check bank errors, and that MCA is set up on this machine.
+//!
+//! With `-w SECS`, also waits for a corrected error to be injected into bank 1 of CPU 0
+//! and checks that the polling task finds, decodes, and clears it.
+//! Errors can't be injected from within the guest, as writing a non-zero value to `IA32_MCi_STATUS`
+//! raises a general protection fault, so they must be injected from the QEMU monitor:
+//! ```text
+//! mce 0 1 0x940000000000009f 0 0x1234 0
+//! ```
+//!
+//! Note: the machine check exception path isn't exercised automatically, as it halts the machine.
+//! To check it manually, inject an uncorrected error with processor context corrupt:
+//! ```text
+//! mce 0 1 0xbe0000000000009f 0x7 0x1234 0
+//! ```
+//! which should print `CPU 0 bank 1: uncorrected memory controller error (memory read, unspecified channel),
+//! processor context corrupt [status 0xBE0000000000009F, addr 0x1234, misc 0x0]` in the crash report.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::{String, ToString}, vec::Vec};
+use app_io::println;
+use fs_node::Directory;
+use getopts::Options;
+use mca::{BankRecord, BankStatus, McaErrorCode};
+use sleep::Duration;
+
+/// The status of the corrected error that is expected to be injected, as given to the QEMU monitor.
+const INJECTED_STATUS: u64 = 0x9400_0000_0000_009F;
+/// The address of the corrected error that is expected to be injected, as given to the QEMU monitor.
+const INJECTED_ADDR: u64 = 0x1234;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("w", "wait", "wait up to SECS seconds for a corrected error to be injected from the QEMU monitor", "SECS");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+    let wait = match matches.opt_str("w").map(|s| s.parse::<u64>()) {
+        Some(Ok(secs)) => Some(Duration::from_secs(secs)),
+        Some(Err(_)) => {
+            println!("invalid number of seconds to wait");
+            print_usage(opts);
+            return -1;
+        }
+        None => None,
+    };
+
+    match rmain(wait) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+fn rmain(wait: Option<Duration>) -> Result<(), &'static str> {
+    println!("Checking the decoding of error codes...");
+    check_decoding()?;
+
+    if !mca::is_supported() {
+        println!("MCA is not supported on this machine, skipping the remaining tests.");
+        println!("Success!");
+        return Ok(());
+    }
+
+    println!("Checking that MCA has been initialized...");
+    if root::get_root().lock().get(mca::MCA_FILE_NAME).is_none() {
+        return Err("the /mca file doesn't exist");
+    }
+    // Polling banks with no errors, or with errors from before this test, must not fault.
+    mca::poll_current_cpu();
+
+    if let Some(timeout) = wait {
+        println!(
+            "Waiting {} seconds for a corrected error; from the QEMU monitor, run:\n    mce 0 1 {:#x} 0 {:#x} 0",
+            timeout.as_secs(), INJECTED_STATUS, INJECTED_ADDR,
+        );
+        let record = wait_for_injected_error(timeout)?;
+        println!("Found: {}", record);
+        if record.status.error_code().to_string() != "memory controller error (memory read, unspecified channel)" {
+            return Err("injected error was decoded incorrectly");
+        }
+    }
+
+    println!("Success!");
+    Ok(())
+}
+
+/// Checks that various bank statuses and error codes are decoded as described in the Intel SDM.
+fn check_decoding() -> Result<(), &'static str> {
+    let codes = [
+        (0x0000, "no error"),
+        (0x0400, "internal timer error"),
+        (0x0011, "L1 instruction TLB error"),
+        (0x0136, "L2 data cache error (data read)"),
+        (0x100F, "generic cache hierarchy error"),
+        (0x00A2, "memory controller error (memory write, channel 2)"),
+        (0x091F, "generic bus/interconnect error (local processor originated request, generic read, timed out)"),
+    ];
+    for (code, expected) in codes {
+        let decoded = McaErrorCode(code).to_string();
+        if decoded != expected {
+            println!("error code {:#06X} decoded as {:?}, expected {:?}", code, decoded, expected);
+            return Err("error code was decoded incorrectly");
+        }
+    }
+
+    let status = BankStatus(INJECTED_STATUS);
+    if !status.is_valid() || !status.is_corrected() || !status.addr_valid() || status.misc_valid() || status.context_corrupt() {
+        return Err("corrected error status flags were decoded incorrectly");
+    }
+    let status = BankStatus(0xBE00_0000_0000_009F);
+    if !status.is_valid() || status.is_corrected() || !status.misc_valid() || !status.context_corrupt() || status.overflowed() {
+        return Err("uncorrected error status flags were decoded incorrectly");
+    }
+
+    let cpu = cpu::current_cpu();
+    let record = BankRecord {
+        cpu,
+        bank: 1,
+        status: BankStatus(INJECTED_STATUS),
+        addr: Some(INJECTED_ADDR),
+        misc: None,
+    };
+    let expected = format!(
+        "CPU {} bank 1: corrected memory controller error (memory read, unspecified channel) [status 0x940000000000009F, addr 0x1234]",
+        cpu,
+    );
+    if record.to_string() != expected {
+        println!("bank record decoded as {:?}, expected {:?}", record.to_string(), expected);
+        return Err("bank record was decoded incorrectly");
+    }
+    Ok(())
+}
+
+/// Waits up to `timeout` for the polling task on CPU 0 to find the injected error.
+fn wait_for_injected_error(timeout: Duration) -> Result<BankRecord, &'static str> {
+    let injected = |record: &&BankRecord| {
+        record.cpu.value() == 0 && record.bank == 1 && record.status.0 == INJECTED_STATUS
+    };
+    let (already_found, _) = mca::error_counts(1);
+    let mut waited = Duration::ZERO;
+    while waited < timeout + mca::POLL_PERIOD {
+        if mca::error_counts(1).0 > already_found {
+            return mca::recent_errors().iter().rev().find(injected).copied()
+                .ok_or("a different corrected error was found in bank 1");
+        }
+        sleep::sleep(Duration::from_millis(100)).map_err(|_| "failed to sleep")?;
+        waited += Duration::from_millis(100);
+    }
+    Err("no injected error was found within the timeout")
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: test_mca [ARGS]
+Tests the decoding of machine check errors, and optionally the polling of one injected from the QEMU monitor.";
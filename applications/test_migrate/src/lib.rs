@@ -0,0 +1,145 @@
+//! Tests that a task's FP/SIMD registers survive being migrated between CPUs
+//! with [`task::scheduler::migrate_task()`].
+//!
+//! A worker task pinned to one CPU loads known values into several XMM registers
+//! and spins, without touching those registers, until it has been migrated.
+//! It then checks that the registers still hold the same values
+//! and that it is now running on the destination CPU.
+//!
+//! This requires an SSE-enabled build; on soft-float builds
+//! no task has any SIMD state to preserve, so the test is skipped.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use app_io::println;
+use cpu::CpuId;
+
+/// Set once the worker has been migrated, which releases it from its spin loop.
+static MIGRATED: AtomicBool = AtomicBool::new(false);
+/// Set once the worker has loaded its registers and begun spinning.
+static STARTED: AtomicBool = AtomicBool::new(false);
+/// The number of XMM registers whose values didn't survive the migration.
+static FP_MISMATCHES: AtomicUsize = AtomicUsize::new(0);
+/// Set if the worker wasn't running on the destination CPU after the migration.
+static WRONG_CPU: AtomicBool = AtomicBool::new(false);
+
+/// The values loaded into `xmm0` through `xmm3`.
+const FP_VALUES: [u64; 4] = [
+    0x3FF0_0000_0000_0000, // 1.0
+    0x4009_21FB_5444_2D18, // pi
+    0xC0FE_EDFA_CEB0_0C5E,
+    0x0123_4567_89AB_CDEF,
+];
+
+pub fn main(_args: Vec<String>) -> isize {
+    if !cfg!(target_feature = "sse2") {
+        println!("skipped: this build doesn't save SIMD registers on context switches");
+        return 0;
+    }
+
+    let mut cpus = cpu::cpus();
+    let (Some(from), Some(to)) = (cpus.next(), cpus.next()) else {
+        println!("skipped: migration requires at least 2 CPUs");
+        return 0;
+    };
+
+    let worker = match spawn::new_task_builder(fp_worker, to)
+        .name(String::from("test_migrate_worker"))
+        .pin_on_cpu(from)
+        .spawn()
+    {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Failed to spawn worker task: {}", e);
+            return -1;
+        }
+    };
+
+    while !STARTED.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+
+    if let Err(e) = task::scheduler::migrate_task(&worker, from, to) {
+        println!("Failed to migrate worker from CPU {} to CPU {}: {}", from, to, e);
+        MIGRATED.store(true, Ordering::Release);
+        let _ = worker.join();
+        return -1;
+    }
+
+    while worker.running_on_cpu() != Some(to) {
+        core::hint::spin_loop();
+    }
+    MIGRATED.store(true, Ordering::Release);
+
+    if worker.join().is_err() {
+        println!("Failed to join worker task");
+        return -1;
+    }
+
+    let mismatches = FP_MISMATCHES.load(Ordering::Acquire);
+    let wrong_cpu = WRONG_CPU.load(Ordering::Acquire);
+    if worker.pinned_cpu() != Some(to) {
+        println!("FAILED: worker was not re-pinned to CPU {}", to);
+        return -1;
+    }
+    if wrong_cpu {
+        println!("FAILED: worker didn't resume on CPU {}", to);
+        return -1;
+    }
+    if mismatches > 0 {
+        println!("FAILED: {} of {} XMM registers were corrupted by the migration", mismatches, FP_VALUES.len());
+        return -1;
+    }
+    println!("passed: migrated worker from CPU {} to CPU {} with its XMM registers intact", from, to);
+    0
+}
+
+#[cfg(target_feature = "sse2")]
+fn fp_worker(to: CpuId) {
+    let [mut a, mut b, mut c, mut d] = FP_VALUES;
+    // SAFETY: this only uses the named XMM registers, which are declared as clobbered.
+    unsafe {
+        core::arch::asm!(
+            "movq xmm0, {a}",
+            "movq xmm1, {b}",
+            "movq xmm2, {c}",
+            "movq xmm3, {d}",
+            "mov byte ptr [{started}], 1",
+            // Spin without touching the XMM registers, such that they are
+            // only preserved if the context switches out of and into this task save them.
+            "2:",
+            "pause",
+            "cmp byte ptr [{migrated}], 0",
+            "je 2b",
+            "movq {a}, xmm0",
+            "movq {b}, xmm1",
+            "movq {c}, xmm2",
+            "movq {d}, xmm3",
+            a = inout(reg) a,
+            b = inout(reg) b,
+            c = inout(reg) c,
+            d = inout(reg) d,
+            started = in(reg) STARTED.as_ptr(),
+            migrated = in(reg) MIGRATED.as_ptr(),
+            out("xmm0") _,
+            out("xmm1") _,
+            out("xmm2") _,
+            out("xmm3") _,
+        );
+    }
+    let mismatches = [a, b, c, d].iter()
+        .zip(FP_VALUES.iter())
+        .filter(|(actual, expected)| actual != expected)
+        .count();
+    FP_MISMATCHES.store(mismatches, Ordering::Release);
+    WRONG_CPU.store(cpu::current_cpu() != to, Ordering::Release);
+}
+
+#[cfg(not(target_feature = "sse2"))]
+fn fp_worker(_to: CpuId) {
+    STARTED.store(true, Ordering::Release);
+}
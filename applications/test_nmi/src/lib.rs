@@ -0,0 +1,106 @@
+//! Tests the NMI reason protocol implemented by the [`nmi`] crate.
+//!
+//! For each other CPU, this checks that:
+//! * probing it via an NMI reports the instruction and stack pointers it was interrupted at, and
+//! * an NMI sent with [`nmi::NmiReason::ProfileSample`] is dispatched to the registered handler
+//!   on that CPU along with its payload.
+//!
+//! It also checks that handlers cannot be registered for reasons handled within the `nmi` crate.
+//! The profiling handler remains registered afterwards, which is harmless.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("test_nmi failed: {}", e);
+            -1
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn run() -> Result<(), String> {
+    println!("skipped: NMIs are only supported on x86_64");
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+use x86_64_impl::run;
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64_impl {
+    use alloc::{format, string::String};
+    use core::{sync::atomic::{AtomicU32, AtomicU64, Ordering}, time::Duration};
+    use app_io::println;
+    use nmi::NmiReason;
+    use time::Instant;
+    use x86_64::structures::idt::InterruptStackFrame;
+
+    /// How long to wait for another CPU to handle an NMI.
+    const NMI_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// The number of samples taken by [`sample_handler()`].
+    static SAMPLES: AtomicU64 = AtomicU64::new(0);
+    /// The payload most recently received by [`sample_handler()`].
+    static LAST_PAYLOAD: AtomicU64 = AtomicU64::new(0);
+    /// The CPU that [`sample_handler()`] most recently ran on.
+    static LAST_CPU: AtomicU32 = AtomicU32::new(u32::MAX);
+
+    fn sample_handler(_stack_frame: &InterruptStackFrame, payload: u64) {
+        LAST_PAYLOAD.store(payload, Ordering::Relaxed);
+        LAST_CPU.store(cpu::current_cpu().value(), Ordering::Relaxed);
+        SAMPLES.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn run() -> Result<(), String> {
+        if nmi::register_handler(NmiReason::Halt, sample_handler).is_ok() {
+            return Err(String::from("a handler was registered for halting CPUs"));
+        }
+        // The handler is already registered if this test ran before during this boot.
+        let _ = nmi::register_handler(NmiReason::ProfileSample, sample_handler);
+
+        let me = cpu::current_cpu();
+        let others: alloc::vec::Vec<_> = cpu::cpus().filter(|&cpu| cpu != me).collect();
+        if others.is_empty() {
+            println!("skipped: there are no other CPUs to send NMIs to");
+            return Ok(());
+        }
+
+        for (i, cpu) in others.into_iter().enumerate() {
+            let probe = nmi::probe(cpu)?;
+            println!("CPU {} was interrupted at {:#X} with stack pointer {:#X}",
+                cpu, probe.instruction_pointer, probe.stack_pointer,
+            );
+            if probe.instruction_pointer == 0 || probe.stack_pointer == 0 {
+                return Err(format!("probing CPU {cpu} returned {probe:?}"));
+            }
+
+            let payload = 0x5A5A_0000 + i as u64;
+            let samples = SAMPLES.load(Ordering::Acquire);
+            nmi::send_nmi(cpu, NmiReason::ProfileSample, payload)?;
+            let start = Instant::now();
+            while SAMPLES.load(Ordering::Acquire) == samples {
+                if start.elapsed() > NMI_TIMEOUT {
+                    return Err(format!("CPU {cpu} didn't handle a profiling NMI"));
+                }
+                core::hint::spin_loop();
+            }
+            let (received, ran_on) = (LAST_PAYLOAD.load(Ordering::Relaxed), LAST_CPU.load(Ordering::Relaxed));
+            if received != payload || ran_on != cpu.value() {
+                return Err(format!(
+                    "a profiling NMI sent to CPU {cpu} with payload {payload:#X} \
+                    was handled on CPU {ran_on} with payload {received:#X}"
+                ));
+            }
+        }
+        println!("Success!");
+        Ok(())
+    }
+}
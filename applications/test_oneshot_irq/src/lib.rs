@@ -0,0 +1,129 @@
+//! Tests one-shot interrupt handlers installed via [`interrupts::oneshot()`].
+//!
+//! This registers a counting handler for an unused vector and sends interrupts
+//! to that vector via self-IPIs, checking that:
+//! * a one-shot handler handles the next interrupt in place of the counting handler,
+//! * the counting handler is restored afterwards and handles later interrupts, and
+//! * dropping a one-shot handler before its interrupt occurs restores the counting handler too.
+//!
+//! This only runs in APIC mode, as self-IPIs require a Local APIC.
+
+#![no_std]
+#![cfg_attr(target_arch = "x86_64", feature(abi_x86_interrupt))]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("test_oneshot_irq failed: {}", e);
+            -1
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn run() -> Result<(), &'static str> {
+    println!("skipped: one-shot interrupt handlers are only supported on x86_64");
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+use x86_64_impl::run;
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64_impl {
+    use core::{sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering}, time::Duration};
+    use apic::LapicIpiDestination;
+    use app_io::println;
+    use interrupts::{interrupt_handler, EoiBehaviour, OneShot};
+    use time::Instant;
+
+    /// How long to wait for a self-IPI to be handled.
+    const IPI_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// The vector of the counting handler.
+    static VECTOR: AtomicU8 = AtomicU8::new(0);
+    /// The number of interrupts handled by the counting handler.
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    /// Whether the most recent one-shot callback has run.
+    static CALLBACK_RAN: AtomicBool = AtomicBool::new(false);
+
+    interrupt_handler!(counting_handler, VECTOR.load(Ordering::Relaxed), _stack_frame, {
+        COUNT.fetch_add(1, Ordering::Relaxed);
+        EoiBehaviour::HandlerDidNotSendEoi
+    });
+
+    pub fn run() -> Result<(), &'static str> {
+        if apic::get_my_apic().is_none() {
+            println!("skipped: this CPU has no Local APIC to send self-IPIs with");
+            return Ok(());
+        }
+        let vector = interrupts::register_msi_interrupt(counting_handler)?;
+        VECTOR.store(vector, Ordering::Relaxed);
+        let result = check(vector);
+        interrupts::deregister_interrupt(vector, counting_handler)?;
+        result
+    }
+
+    fn check(vector: u8) -> Result<(), &'static str> {
+        println!("Handling one interrupt at vector {:#X} with a one-shot handler...", vector);
+        CALLBACK_RAN.store(false, Ordering::Relaxed);
+        let oneshot = interrupts::oneshot(vector, |_| CALLBACK_RAN.store(true, Ordering::Relaxed))?;
+        if interrupts::oneshot(vector, |_| {}).is_ok() {
+            return Err("a second one-shot handler was installed while another was pending");
+        }
+        let start = Instant::now();
+        self_ipi(vector)?;
+        wait_until(|| oneshot.has_fired())?;
+        println!("The one-shot handler fired after {:?}", start.elapsed());
+        if !CALLBACK_RAN.load(Ordering::Relaxed) {
+            return Err("the one-shot handler fired without running its callback");
+        }
+        if COUNT.load(Ordering::Relaxed) != 0 {
+            return Err("the registered handler also handled the one-shot interrupt");
+        }
+        drop(oneshot);
+
+        println!("Checking that the registered handler was restored...");
+        self_ipi(vector)?;
+        wait_until(|| COUNT.load(Ordering::Relaxed) == 1)?;
+
+        println!("Checking that dropping a pending one-shot handler restores the registered handler...");
+        CALLBACK_RAN.store(false, Ordering::Relaxed);
+        let oneshot: OneShot = interrupts::oneshot(vector, |_| CALLBACK_RAN.store(true, Ordering::Relaxed))?;
+        drop(oneshot);
+        self_ipi(vector)?;
+        wait_until(|| COUNT.load(Ordering::Relaxed) == 2)?;
+        if CALLBACK_RAN.load(Ordering::Relaxed) {
+            return Err("a dropped one-shot handler still ran its callback");
+        }
+
+        println!("Success!");
+        Ok(())
+    }
+
+    fn self_ipi(vector: u8) -> Result<(), &'static str> {
+        apic::get_my_apic()
+            .ok_or("couldn't get this CPU's Local APIC")?
+            .write()
+            .send_ipi(vector, LapicIpiDestination::Me);
+        Ok(())
+    }
+
+    /// Busy-waits until `condition` holds, or returns an error after [`IPI_TIMEOUT`].
+    fn wait_until(condition: impl Fn() -> bool) -> Result<(), &'static str> {
+        let start = Instant::now();
+        while !condition() {
+            if start.elapsed() > IPI_TIMEOUT {
+                return Err("timed out waiting for a self-IPI to be handled");
+            }
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+}
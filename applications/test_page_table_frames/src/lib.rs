@@ -0,0 +1,59 @@
+//! Tests that mapping and then fully unmapping a region returns the number of frames
+//! in use as page tables, as reported by [`memory::page_table_frame_count()`], to its starting value.
+//!
+//! This guards against leaking the page tables that were allocated to map a region.
+//! Other tasks that map or unmap memory while this test runs can skew the counts,
+//! so each check is retried a few times before it's considered a failure.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use app_io::println;
+use memory::PteFlags;
+
+/// The sizes, in pages, of the regions to map and unmap.
+///
+/// The largest one spans at least one whole P1 page table, which can't be shared with
+/// any other mapping, so mapping it must allocate at least one new page table.
+const REGION_SIZES: &[usize] = &[1, 3, 1024];
+/// How many times a check is retried before it's considered a failure.
+const ATTEMPTS: usize = 3;
+
+pub fn main(_args: Vec<String>) -> isize {
+    for &pages in REGION_SIZES {
+        let mut result = check(pages);
+        for _ in 1..ATTEMPTS {
+            if result.is_ok() {
+                break;
+            }
+            result = check(pages);
+        }
+        match result {
+            Ok(()) => println!("mapping and unmapping {} pages didn't leak any page tables", pages),
+            Err(e) => {
+                println!("test_page_table_frames failed: {}", e);
+                return -1;
+            }
+        }
+    }
+    0
+}
+
+/// Maps and unmaps a region of the given size, checking the page table frame count along the way.
+fn check(pages: usize) -> Result<(), String> {
+    let before = memory::page_table_frame_count();
+    let mapping = memory::create_mapping(pages, PteFlags::new().valid(true).writable(true))?;
+    let while_mapped = memory::page_table_frame_count();
+    drop(mapping);
+    let after = memory::page_table_frame_count();
+
+    if pages >= 1024 && while_mapped <= before {
+        return Err(format!("mapping {pages} pages didn't allocate any page tables ({before} before, {while_mapped} after)"));
+    }
+    if after != before {
+        return Err(format!("mapping and unmapping {pages} pages changed the page table frame count from {before} to {after}"));
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+//! Tests [`pit_clock::oneshot()`] countdowns against the monotonic clock.
+//!
+//! Each countdown must take about as long as the interval it reports,
+//! and the scheduler tick must keep running afterwards,
+//! which is checked by sleeping for a few timeslices.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("test_pit_oneshot failed: {}", e);
+            -1
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn run() -> Result<(), String> {
+    println!("skipped: the PIT only exists on x86_64");
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn run() -> Result<(), String> {
+    use alloc::format;
+    use core::time::Duration;
+    use time::Instant;
+
+    /// The PIT tick counts to count down, from about 1 ms to about 50 ms.
+    const COUNTS: &[u16] = &[1193, 11932, 59659];
+    /// How far a measured countdown may deviate from its reported interval, in percent.
+    const TOLERANCE_PERCENT: u128 = 10;
+    /// How long to sleep afterwards to check that the scheduler tick still runs.
+    const SLEEP: Duration = Duration::from_millis(50);
+
+    for &count in COUNTS {
+        let (reported, measured) = {
+            let _held_interrupts = irq_safety::hold_interrupts();
+            let start = Instant::now();
+            let reported = pit_clock::oneshot(count);
+            (Duration::from_nanos(reported), start.elapsed())
+        };
+        println!("counted down {} PIT ticks: reported {:?}, measured {:?}", count, reported, measured);
+        let deviation = measured.as_nanos().abs_diff(reported.as_nanos());
+        if deviation * 100 > reported.as_nanos() * TOLERANCE_PERCENT {
+            return Err(format!("a countdown of {count} PIT ticks took {measured:?}, but reported {reported:?}"));
+        }
+    }
+
+    let start = Instant::now();
+    sleep::sleep(SLEEP).map_err(|_| String::from("couldn't sleep"))?;
+    let slept = start.elapsed();
+    if slept > SLEEP * 3 {
+        return Err(format!("sleeping for {SLEEP:?} after the countdowns took {slept:?}"));
+    }
+    Ok(())
+}
@@ -0,0 +1,56 @@
+//! A set of basic tests for [`memory::Mapper::remap()`].
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    vec::Vec,
+    string::String,
+};
+use app_io::println;
+use memory::PteFlags;
+
+pub fn main(_args: Vec<String>) -> isize {
+    match rmain() {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+fn rmain() -> Result<(), &'static str> {
+    let kernel_mmi_ref = memory::get_kernel_mmi_ref().ok_or("couldn't get kernel MMI")?;
+    let writable = PteFlags::new().valid(true).writable(true);
+    let read_only = PteFlags::new().valid(true).writable(false);
+
+    let mut mp = memory::create_mapping(1, writable)?;
+    let page = *mp.start();
+    let vaddr = page.start_address();
+    let frame = memory::translate(vaddr).ok_or("new mapping wasn't translatable")?;
+    mp.as_slice_mut::<u8>(0, 1)?[0] = 0xAB;
+
+    println!("Remapping {:?} as read-only...", page);
+    kernel_mmi_ref.lock().page_table.remap(page, read_only)?;
+    let flags = memory::page_flags(vaddr).ok_or("page was unmapped by remap()")?;
+    assert!(!flags.is_writable());
+    assert!(flags.is_exclusive(), "remap() didn't preserve the EXCLUSIVE flag");
+    assert_eq!(memory::translate(vaddr), Some(frame), "remap() changed the mapped frame");
+
+    println!("Remapping {:?} as writable again...", page);
+    kernel_mmi_ref.lock().page_table.remap(page, writable)?;
+    assert!(memory::page_flags(vaddr).is_some_and(|flags| flags.is_writable()));
+    assert_eq!(memory::translate(vaddr), Some(frame), "remap() changed the mapped frame");
+    let slice = mp.as_slice_mut::<u8>(0, 1)?;
+    assert_eq!(slice[0], 0xAB, "remap() lost the page's contents");
+    slice[0] = 0xCD;
+
+    println!("Remapping an unmapped page...");
+    let unmapped = memory::allocate_pages(1).ok_or("couldn't allocate pages")?;
+    assert!(kernel_mmi_ref.lock().page_table.remap(*unmapped.start(), writable).is_err());
+
+    println!("Success!");
+    Ok(())
+}
@@ -0,0 +1,78 @@
+//! Tests that reading the RTC wall clock in a tight loop
+//! doesn't cause any RTC periodic interrupts to be dropped.
+//!
+//! Both operations access the CMOS registers, so if a wall-clock read
+//! selected the wrong register or left the periodic interrupt unacknowledged,
+//! the RTC would stop raising interrupts and the tick count would fall behind.
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use interrupts::{EoiBehaviour, IRQ_BASE_OFFSET, interrupt_handler};
+use time::{Duration, Instant};
+
+/// The RTC interrupt is ISA IRQ 8, as routed through the IOAPIC.
+const RTC_IRQ: u8 = IRQ_BASE_OFFSET + rtc::RTC_ISA_IRQ;
+/// The rate of the RTC periodic interrupt used during the test.
+const RTC_RATE_HZ: usize = 1024;
+/// How long to read the wall clock for.
+const TEST_DURATION: Duration = Duration::from_secs(5);
+/// The fraction (in percent) of expected RTC ticks that must be observed.
+const MIN_TICKS_PERCENT: u64 = 95;
+
+interrupt_handler!(rtc_interrupt_handler, RTC_IRQ, _stack_frame, {
+    rtc::rtc_ack_irq();
+    rtc::handle_rtc_interrupt();
+    EoiBehaviour::HandlerDidNotSendEoi
+});
+
+pub fn main(_args: Vec<String>) -> isize {
+    if let Err(_handler) = interrupts::register_isa_interrupt(rtc::RTC_ISA_IRQ, rtc_interrupt_handler) {
+        println!("RTC interrupt {:#X} was already in use by handler {:#X}", RTC_IRQ, _handler);
+        return -1;
+    }
+    let result = run();
+    rtc::disable_rtc_interrupt();
+    if let Err(e) = interrupts::deregister_interrupt(RTC_IRQ, rtc_interrupt_handler) {
+        println!("Failed to deregister RTC interrupt handler: {}", e);
+    }
+
+    match result {
+        Ok(()) => {
+            println!("test_rtc passed.");
+            0
+        }
+        Err(e) => {
+            println!("test_rtc failed: {}", e);
+            -1
+        }
+    }
+}
+
+fn run() -> Result<(), &'static str> {
+    rtc::set_rtc_frequency(RTC_RATE_HZ).map_err(|_| "invalid RTC rate")?;
+    rtc::enable_rtc_interrupt();
+
+    let start_ticks = rtc::get_rtc_ticks().ok_or("couldn't get RTC ticks")?;
+    let start = Instant::now();
+    let mut reads = 0u64;
+    while start.elapsed() < TEST_DURATION {
+        let _ = rtc::read_rtc();
+        reads += 1;
+    }
+    let elapsed = start.elapsed();
+    let ticks = rtc::get_rtc_ticks().ok_or("couldn't get RTC ticks")?.wrapping_sub(start_ticks);
+
+    let expected = RTC_RATE_HZ as u64 * elapsed.as_millis() as u64 / 1000;
+    println!("Read the wall clock {} times in {:?}: observed {} RTC ticks, expected {}.",
+        reads, elapsed, ticks, expected,
+    );
+    if ticks * 100 < expected * MIN_TICKS_PERCENT {
+        return Err("RTC ticks were dropped while reading the wall clock");
+    }
+    Ok(())
+}
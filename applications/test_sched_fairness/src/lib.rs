@@ -0,0 +1,256 @@
+//! Checks the scheduler's fairness and priority invariants under a mix of task configurations.
+//!
+//! Each phase spawns a set of worker tasks pinned to the current CPU, each with a given
+//! priority and nice value, and lets them yield to each other for a fixed number of rounds.
+//! A round is a single pick of any worker, so each worker records how often it was picked
+//! and the largest number of rounds that passed between two of its picks.
+//! Each phase then checks a subset of the following invariants:
+//! * No runnable worker goes more than a bounded number of rounds without being picked,
+//!   where the bound grows with the number of workers and their nice values.
+//! * A worker with a higher priority is picked at least as often as one with a lower priority.
+//! * A worker with a higher nice value isn't picked notably more often than one with a lower nice value.
+//!
+//! Phases that set priorities are skipped if the current scheduler policy doesn't support them.
+//! Those phases don't check for starvation, because a strict priority scheduler
+//! is meant to starve lower-priority tasks while higher-priority tasks are runnable.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use app_io::println;
+
+/// The number of rounds, i.e., picks of any worker, in each phase.
+const ROUNDS: usize = 10_000;
+/// The maximum number of workers in a phase.
+const MAX_WORKERS: usize = 8;
+/// A worker is starved if it isn't picked for more than this many times the number of rounds
+/// that a perfectly fair round-robin order would take, adjusted for its nice value.
+const STARVATION_FACTOR: usize = 4;
+/// How much more often, in percent, a worker may be picked than a worker with a lower nice value,
+/// which tolerates scheduler policies that ignore nice values.
+const NICE_TOLERANCE_PERCENT: usize = 10;
+/// The priorities used for high- and low-priority workers.
+const HIGH_PRIORITY: u8 = 30;
+const LOW_PRIORITY: u8 = 10;
+
+/// The configuration of a single worker task.
+#[derive(Clone, Copy, Debug)]
+struct WorkerConfig {
+    /// The worker's priority, or `None` to leave the scheduler's default priority.
+    priority: Option<u8>,
+    /// The worker's nice value.
+    nice: i8,
+}
+
+const fn worker(priority: Option<u8>, nice: i8) -> WorkerConfig {
+    WorkerConfig { priority, nice }
+}
+
+/// A set of workers that run together, and whether to check them for starvation.
+struct Phase {
+    name: &'static str,
+    workers: &'static [WorkerConfig],
+    check_starvation: bool,
+}
+
+const PHASES: &[Phase] = &[
+    Phase {
+        name: "equal",
+        workers: &[worker(None, 0), worker(None, 0), worker(None, 0), worker(None, 0)],
+        check_starvation: true,
+    },
+    Phase {
+        name: "nice",
+        workers: &[worker(None, 0), worker(None, 0), worker(None, 2), worker(None, 4)],
+        check_starvation: true,
+    },
+    Phase {
+        name: "priority",
+        workers: &[
+            worker(Some(HIGH_PRIORITY), 0),
+            worker(Some(HIGH_PRIORITY), 0),
+            worker(Some(LOW_PRIORITY), 0),
+            worker(Some(LOW_PRIORITY), 0),
+        ],
+        check_starvation: false,
+    },
+    Phase {
+        name: "mixed",
+        workers: &[
+            worker(Some(HIGH_PRIORITY), 0),
+            worker(Some(HIGH_PRIORITY), 3),
+            worker(Some(LOW_PRIORITY), 0),
+            worker(Some(LOW_PRIORITY), 3),
+        ],
+        check_starvation: false,
+    },
+];
+
+/// What a single worker recorded during a phase.
+struct Slot {
+    runs: AtomicUsize,
+    /// The round in which the worker was last picked, or `usize::MAX` if it was never picked.
+    last_round: AtomicUsize,
+    max_gap: AtomicUsize,
+}
+
+static SLOTS: [Slot; MAX_WORKERS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Slot = Slot {
+        runs: AtomicUsize::new(0),
+        last_round: AtomicUsize::new(usize::MAX),
+        max_gap: AtomicUsize::new(0),
+    };
+    [EMPTY; MAX_WORKERS]
+};
+/// The next round to be claimed by a worker.
+static ROUND: AtomicUsize = AtomicUsize::new(0);
+static READY: AtomicBool = AtomicBool::new(false);
+static DONE: AtomicBool = AtomicBool::new(false);
+
+pub fn main(_args: Vec<String>) -> isize {
+    for phase in PHASES {
+        match run_phase(phase) {
+            Ok(true) => println!("phase {:?} passed", phase.name),
+            Ok(false) => println!("phase {:?} skipped: the scheduler policy doesn't support priorities", phase.name),
+            Err(e) => {
+                println!("test_sched_fairness failed in phase {:?}: {}", phase.name, e);
+                return -1;
+            }
+        }
+    }
+    0
+}
+
+/// Runs the given phase and checks its invariants.
+///
+/// Returns `Ok(false)` if the phase was skipped.
+fn run_phase(phase: &Phase) -> Result<bool, String> {
+    let workers = phase.workers;
+    ROUND.store(0, Ordering::Relaxed);
+    READY.store(false, Ordering::Relaxed);
+    DONE.store(false, Ordering::Relaxed);
+    for slot in &SLOTS {
+        slot.runs.store(0, Ordering::Relaxed);
+        slot.last_round.store(usize::MAX, Ordering::Relaxed);
+        slot.max_gap.store(0, Ordering::Relaxed);
+    }
+
+    let cpu = cpu::current_cpu();
+    let tasks = workers.iter()
+        .enumerate()
+        .map(|(index, config)| spawn::new_task_builder(worker_task, (index, config.nice))
+            .name(format!("test_sched_fairness_{}_{}", phase.name, index))
+            .pin_on_cpu(cpu)
+            .block()
+            .spawn()
+        )
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Under a strict priority scheduler, this task must outrank the workers
+    // until it's done setting them up, or it would never run again.
+    let uses_priorities = workers.iter().any(|config| config.priority.is_some());
+    let original_priority = task::with_current_task(task::scheduler::priority).ok().flatten();
+    let mut supported = true;
+    if uses_priorities {
+        supported &= task::with_current_task(|current| task::scheduler::set_priority(current, u8::MAX))
+            .unwrap_or(false);
+        for (task, config) in tasks.iter().zip(workers) {
+            if let Some(priority) = config.priority {
+                supported &= task::scheduler::set_priority(task, priority);
+            }
+        }
+    }
+    if !supported {
+        // Let the workers exit right away.
+        DONE.store(true, Ordering::Release);
+    }
+
+    for task in tasks.iter() {
+        task.unblock().map_err(|_| "couldn't unblock a worker task")?;
+    }
+    READY.store(true, Ordering::Release);
+    for task in tasks {
+        task.join()?;
+    }
+    if let Some(priority) = original_priority {
+        task::with_current_task(|current| task::scheduler::set_priority(current, priority))
+            .map_err(|_| "couldn't restore this task's priority")?;
+    }
+    if !supported {
+        return Ok(false);
+    }
+
+    let results: Vec<(WorkerConfig, usize, usize)> = workers.iter()
+        .zip(&SLOTS)
+        .map(|(config, slot)| {
+            let last_round = slot.last_round.load(Ordering::Relaxed);
+            // The rounds after a worker's last pick also count towards its largest gap.
+            let trailing_gap = ROUNDS - last_round.wrapping_add(1);
+            (*config, slot.runs.load(Ordering::Relaxed), slot.max_gap.load(Ordering::Relaxed).max(trailing_gap))
+        })
+        .collect();
+    for (index, (config, runs, max_gap)) in results.iter().enumerate() {
+        println!("  worker {}: priority {:?}, nice {}, picked {} times, at most {} rounds apart",
+            index, config.priority, config.nice, runs, max_gap,
+        );
+    }
+    check_invariants(phase, &results)?;
+    Ok(true)
+}
+
+/// Checks the invariants described in the crate-level documentation
+/// against each worker's configuration, number of runs, and largest gap between runs.
+fn check_invariants(phase: &Phase, results: &[(WorkerConfig, usize, usize)]) -> Result<(), String> {
+    if phase.check_starvation {
+        for (index, (config, _, max_gap)) in results.iter().enumerate() {
+            let bound = STARVATION_FACTOR * results.len() * (config.nice.max(0) as usize + 1);
+            if *max_gap > bound {
+                return Err(format!("worker {index} was starved for {max_gap} rounds (bound: {bound})"));
+            }
+        }
+    }
+
+    for (a, (config_a, runs_a, _)) in results.iter().enumerate() {
+        for (b, (config_b, runs_b, _)) in results.iter().enumerate() {
+            if let (Some(priority_a), Some(priority_b)) = (config_a.priority, config_b.priority) {
+                if priority_a > priority_b && runs_a < runs_b && config_a.nice <= config_b.nice {
+                    return Err(format!("worker {a} has a higher priority but was picked less often than worker {b}"));
+                }
+            }
+            let tolerance = runs_a * NICE_TOLERANCE_PERCENT / 100;
+            if config_a.priority == config_b.priority && config_a.nice < config_b.nice && *runs_b > runs_a + tolerance {
+                return Err(format!("worker {b} has a higher nice value but was picked more often than worker {a}"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The entry point of each worker, which yields until the phase's rounds have all been claimed.
+fn worker_task((index, nice): (usize, i8)) {
+    if nice != 0 {
+        task::scheduler::nice(nice);
+    }
+    while !READY.load(Ordering::Acquire) {
+        task::schedule();
+    }
+
+    let slot = &SLOTS[index];
+    while !DONE.load(Ordering::Acquire) {
+        let round = ROUND.fetch_add(1, Ordering::Relaxed);
+        if round >= ROUNDS {
+            DONE.store(true, Ordering::Release);
+            break;
+        }
+        // A worker's first gap counts the rounds before it was first picked.
+        let previous = slot.last_round.swap(round, Ordering::Relaxed);
+        let gap = round - previous.wrapping_add(1);
+        slot.max_gap.fetch_max(gap, Ordering::Relaxed);
+        slot.runs.fetch_add(1, Ordering::Relaxed);
+        task::schedule();
+    }
+}
@@ -16,6 +16,12 @@ pub fn main(_args: Vec<String>) -> isize {
     test_pinned();
     println!("testing unpinned");
     test_unpinned();
+    println!("testing nice");
+    test_nice();
+    println!("testing interrupt state");
+    test_interrupt_state();
+    println!("testing empty runqueue");
+    test_empty_runqueue();
     0
 }
 
@@ -149,3 +155,179 @@ pub fn test_unpinned() {
         NUM_RUNNING.fetch_sub(1, Ordering::Relaxed);
     }
 }
+
+/// Spawn a default task and a niced-down task on the current CPU, and check
+/// that the niced-down task is picked less often over many scheduling rounds.
+pub fn test_nice() {
+    const NICE: i8 = 4;
+    const TOTAL_ROUNDS: usize = 10_000;
+
+    static READY: AtomicBool = AtomicBool::new(false);
+    static DONE: AtomicBool = AtomicBool::new(false);
+    static DEFAULT_RUNS: AtomicUsize = AtomicUsize::new(0);
+    static NICED_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    let cpu = cpu::current_cpu();
+    let default_task = spawn::new_task_builder(nice_worker, (0, &DEFAULT_RUNS))
+        .name(String::from("test-scheduler-nice-default"))
+        .pin_on_cpu(cpu)
+        .spawn()
+        .expect("failed to spawn task");
+    let niced_task = spawn::new_task_builder(nice_worker, (NICE, &NICED_RUNS))
+        .name(String::from("test-scheduler-nice-niced"))
+        .pin_on_cpu(cpu)
+        .spawn()
+        .expect("failed to spawn task");
+
+    READY.store(true, Ordering::Release);
+    while DEFAULT_RUNS.load(Ordering::Relaxed) + NICED_RUNS.load(Ordering::Relaxed) < TOTAL_ROUNDS {
+        task::schedule();
+    }
+    DONE.store(true, Ordering::Release);
+
+    default_task.join().unwrap();
+    niced_task.join().unwrap();
+
+    let default_runs = DEFAULT_RUNS.load(Ordering::Relaxed);
+    let niced_runs = NICED_RUNS.load(Ordering::Relaxed);
+    println!("default task ran {default_runs} times, niced task (nice {NICE}) ran {niced_runs} times");
+    assert!(
+        niced_runs * 2 < default_runs,
+        "niced task was not picked less often than the default task"
+    );
+
+    fn nice_worker((nice, runs): (i8, &'static AtomicUsize)) {
+        task::scheduler::nice(nice).unwrap();
+        while !READY.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+        while !DONE.load(Ordering::Acquire) {
+            runs.fetch_add(1, Ordering::Relaxed);
+            task::schedule();
+        }
+    }
+}
+
+/// Spawn tasks on every CPU that yield to each other, half of them with
+/// interrupts enabled and half with interrupts disabled, and check that each
+/// task's interrupt state is preserved across every task switch.
+///
+/// This is a regression test for interrupts being re-enabled by a task switch
+/// in code that expected them to remain disabled, or vice versa.
+pub fn test_interrupt_state() {
+    const TASKS_PER_CPU: usize = 4;
+    const ROUNDS: usize = 1000;
+
+    static READY: AtomicBool = AtomicBool::new(false);
+    static MISMATCHES: AtomicUsize = AtomicUsize::new(0);
+
+    let tasks = cpus()
+        .flat_map(|cpu| {
+            (0..TASKS_PER_CPU).map(move |id| {
+                let interrupts_enabled = id % 2 == 0;
+                spawn::new_task_builder(interrupt_state_worker, interrupts_enabled)
+                    .name(format!("test-scheduler-irq-{cpu}-{id}"))
+                    .pin_on_cpu(cpu)
+                    .spawn()
+                    .expect("failed to spawn task")
+            })
+        })
+        .collect::<Vec<_>>();
+
+    READY.store(true, Ordering::Release);
+
+    for task in tasks {
+        task.join().unwrap();
+    }
+
+    let mismatches = MISMATCHES.load(Ordering::Relaxed);
+    assert_eq!(mismatches, 0, "a task switch changed a task's interrupt state {mismatches} times");
+
+    fn interrupt_state_worker(interrupts_enabled: bool) {
+        while !READY.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+
+        let held_interrupts = (!interrupts_enabled).then(irq_safety::hold_interrupts);
+        for _ in 0..ROUNDS {
+            task::schedule();
+            if irq_safety::interrupts_enabled() != interrupts_enabled {
+                MISMATCHES.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        drop(held_interrupts);
+
+        if !irq_safety::interrupts_enabled() {
+            MISMATCHES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Empty another CPU's run queue, including blocking its idle task, and check
+/// that the CPU parks instead of repeatedly invoking the scheduler, and then
+/// recovers once a task is added to its run queue.
+pub fn test_empty_runqueue() {
+    const YIELDS_WHILE_PARKED: usize = 10_000;
+
+    static RAN_ON: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+    let current_cpu = cpu::current_cpu();
+    let Some(target) = cpus().find(|cpu| *cpu != current_cpu) else {
+        println!("skipping empty runqueue test: there is only one CPU");
+        return;
+    };
+    let idle_task = task::all_tasks()
+        .into_iter()
+        .filter_map(|(_, task)| task.upgrade())
+        .find(|task| task.is_an_idle_task && task.pinned_cpu() == Some(target))
+        .expect("couldn't find the target CPU's idle task");
+
+    let removed_tasks = task::scheduler::tasks()
+        .into_iter()
+        .find(|(cpu, _)| *cpu == target)
+        .map(|(_, tasks)| tasks)
+        .unwrap_or_default();
+    let events_before = task::scheduler::empty_runqueue_count(target).unwrap();
+    for task in removed_tasks.iter() {
+        task::scheduler::remove_task_from(task, target);
+    }
+    idle_task.block().unwrap();
+
+    // The target CPU's next timer interrupt will find its run queue empty.
+    while task::scheduler::empty_runqueue_count(target).unwrap() == events_before {
+        task::schedule();
+    }
+    // Once parked, it must not keep re-entering the scheduler.
+    for _ in 0..YIELDS_WHILE_PARKED {
+        task::schedule();
+    }
+    let events_while_parked = task::scheduler::empty_runqueue_count(target).unwrap();
+    assert_eq!(
+        events_while_parked,
+        events_before + 1,
+        "CPU {target} repeatedly found its run queue empty instead of parking"
+    );
+
+    let recovery_task = spawn::new_task_builder(empty_runqueue_worker, ())
+        .name(String::from("test-scheduler-empty-runqueue"))
+        .pin_on_cpu(target)
+        .spawn()
+        .expect("failed to spawn task");
+    recovery_task.join().unwrap();
+    assert_eq!(
+        RAN_ON.load(Ordering::Relaxed),
+        target.value() as usize,
+        "the task added to the parked CPU {target} did not run on it"
+    );
+
+    idle_task.unblock().unwrap();
+    for task in removed_tasks {
+        if !task.has_exited() {
+            task::scheduler::add_task_to(target, task);
+        }
+    }
+
+    fn empty_runqueue_worker(_: ()) {
+        RAN_ON.store(cpu::current_cpu().value() as usize, Ordering::Relaxed);
+    }
+}
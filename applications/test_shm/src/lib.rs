@@ -0,0 +1,133 @@
+//! Tests sharing data between tasks through named shared memory segments.
+//!
+//! This task creates a segment that only it may write to, fills it with a data block
+//! followed by that block's checksum, and spawns another task that maps the same segment
+//! as read-only and verifies the checksum.
+//!
+//! It then checks that a segment removed while it's still mapped remains accessible
+//! through that mapping, and that its frames are only deallocated once the mapping is dropped.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::{String, ToString}, vec::Vec};
+use app_io::println;
+
+/// The name of the segment that carries the data block.
+const DATA_SEGMENT: &str = "test_shm_data";
+/// The name of the segment that is removed while it's mapped.
+const TEARDOWN_SEGMENT: &str = "test_shm_teardown";
+/// The size of the data block, which deliberately isn't a whole number of pages.
+const DATA_LEN: usize = 3 * 4096 + 100;
+/// The size of the checksum that follows the data block.
+const CHECKSUM_LEN: usize = core::mem::size_of::<u64>();
+
+pub fn main(_args: Vec<String>) -> isize {
+    let result = run();
+    let _ = shm::remove(DATA_SEGMENT);
+    let _ = shm::remove(TEARDOWN_SEGMENT);
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("test_shm failed: {}", e);
+            -1
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    share_data_block()?;
+    remove_while_mapped()?;
+    println!("passed");
+    Ok(())
+}
+
+fn share_data_block() -> Result<(), String> {
+    shm::create(DATA_SEGMENT, DATA_LEN + CHECKSUM_LEN, shm::Access::ReadOnly)?;
+    let mut mapping = shm::map(DATA_SEGMENT, true)?;
+    let contents = mapping.as_slice_mut()?;
+    if contents.iter().any(|b| *b != 0) {
+        return Err("a new segment wasn't zeroed".to_string());
+    }
+    for (i, b) in contents[..DATA_LEN].iter_mut().enumerate() {
+        *b = (i * 31 + 7) as u8;
+    }
+    let checksum = fnv1a(&contents[..DATA_LEN]);
+    contents[DATA_LEN..][..CHECKSUM_LEN].copy_from_slice(&checksum.to_le_bytes());
+
+    let consumer = spawn::new_task_builder(|_: ()| consume_data_block(), ())
+        .name(String::from("test_shm_consumer"))
+        .spawn()?;
+    let result = match consumer.join()? {
+        task::ExitValue::Completed(value) => value
+            .downcast_ref::<Result<u64, String>>()
+            .cloned()
+            .ok_or("the consumer task returned an unexpected value")?,
+        task::ExitValue::Killed(reason) => return Err(format!("the consumer task was killed: {reason:?}")),
+    };
+    let consumed = result.map_err(|e| format!("consumer: {e}"))?;
+    println!("shared {} bytes with checksum {:#018X} through segment {:?}", DATA_LEN, consumed, DATA_SEGMENT);
+    Ok(())
+}
+
+/// Maps the data segment in another task, verifies its checksum, and returns it.
+fn consume_data_block() -> Result<u64, String> {
+    if shm::map(DATA_SEGMENT, true).is_ok() {
+        return Err("mapped a read-only segment as writable without having created it".to_string());
+    }
+    let mapping = shm::map(DATA_SEGMENT, false)?;
+    if mapping.is_writable() {
+        return Err("a read-only mapping is writable".to_string());
+    }
+    let contents = mapping.as_slice();
+    let mut expected = [0; CHECKSUM_LEN];
+    expected.copy_from_slice(&contents[DATA_LEN..][..CHECKSUM_LEN]);
+    let expected = u64::from_le_bytes(expected);
+    let actual = fnv1a(&contents[..DATA_LEN]);
+    if actual != expected {
+        return Err(format!("checksum {actual:#018X} doesn't match {expected:#018X}"));
+    }
+    Ok(actual)
+}
+
+fn remove_while_mapped() -> Result<(), String> {
+    let live_before = shm::live_segments();
+    shm::create(TEARDOWN_SEGMENT, 4096, shm::Access::ReadWrite)?;
+    let mut mapping = shm::map(TEARDOWN_SEGMENT, true)?;
+    mapping.as_slice_mut()?[0] = 0xAB;
+
+    shm::remove(TEARDOWN_SEGMENT)?;
+    if shm::map(TEARDOWN_SEGMENT, false).is_ok() {
+        return Err("mapped a segment after it was removed".to_string());
+    }
+    if shm::list().iter().any(|name| name == TEARDOWN_SEGMENT) {
+        return Err("a removed segment is still listed".to_string());
+    }
+    if mapping.as_slice()[0] != 0xAB {
+        return Err("a removed segment's contents changed while it was still mapped".to_string());
+    }
+    if shm::live_segments() != live_before + 1 {
+        return Err("a removed segment was deallocated while it was still mapped".to_string());
+    }
+
+    // The name can be reused right away, for a distinct segment.
+    shm::create(TEARDOWN_SEGMENT, 4096, shm::Access::ReadWrite)?;
+    let reused = shm::map(TEARDOWN_SEGMENT, false)?;
+    if reused.as_slice()[0] != 0 {
+        return Err("a segment that reused a removed segment's name shares its contents".to_string());
+    }
+    drop(reused);
+    shm::remove(TEARDOWN_SEGMENT)?;
+
+    drop(mapping);
+    if shm::live_segments() != live_before {
+        return Err("a removed segment wasn't deallocated once its last mapping was dropped".to_string());
+    }
+    Ok(())
+}
+
+/// Returns the 64-bit FNV-1a hash of the given bytes.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
@@ -0,0 +1,77 @@
+//! Tests that device shutdown hooks are run in the reverse of their dependency order.
+//!
+//! This registers two no-op shutdown hooks, the first of which depends on the second,
+//! and checks that the first is ordered before the second despite being registered first.
+//! It also checks that the logger is flushed before the storage devices that may hold its records.
+//!
+//! Shutdown itself isn't run, as it would quiesce the storage devices for the rest of this boot.
+//! The no-op hooks remain registered, which is harmless.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use core::time::Duration;
+use app_io::println;
+use device_manager::registry::{self, ShutdownHook, ShutdownMode};
+use time::Instant;
+
+const UPPER: &str = "test_shutdown_order_upper";
+const LOWER: &str = "test_shutdown_order_lower";
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("test_shutdown_order failed: {}", e);
+            -1
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    // The hooks are already registered if this test ran before during this boot.
+    if !registry::shutdown_hook_order().contains(&UPPER) {
+        registry::register_shutdown_hook(noop_hook(UPPER, &[LOWER]))?;
+        registry::register_shutdown_hook(noop_hook(LOWER, &["pci_devices"]))?;
+    }
+    if registry::register_shutdown_hook(noop_hook(UPPER, &[])).is_ok() {
+        return Err(String::from("a second shutdown hook with the same name was registered"));
+    }
+
+    let order = registry::shutdown_hook_order();
+    println!("Shutdown order: {:?}", order);
+    let position = |name: &str| order.iter().position(|n| *n == name);
+    check_before(position(UPPER), position(LOWER), UPPER, LOWER)?;
+    if position("storage").is_some() {
+        check_before(position("logger"), position("storage"), "logger", "storage")?;
+    }
+    Ok(())
+}
+
+/// Returns an error unless the hook at `first` is ordered before the one at `second`.
+fn check_before(first: Option<usize>, second: Option<usize>, first_name: &str, second_name: &str) -> Result<(), String> {
+    match (first, second) {
+        (Some(first), Some(second)) if first < second => Ok(()),
+        (Some(_), Some(_)) => Err(format!("{first_name} isn't shut down before {second_name}")),
+        _ => Err(format!("{first_name} or {second_name} is missing from the shutdown order")),
+    }
+}
+
+fn noop_hook(name: &'static str, depends_on: &'static [&'static str]) -> ShutdownHook {
+    ShutdownHook {
+        name,
+        depends_on,
+        quiesce: noop_quiesce,
+        flush: noop_flush,
+        timeout: Duration::from_millis(1),
+        best_effort: true,
+    }
+}
+
+fn noop_quiesce(_mode: ShutdownMode) { }
+
+fn noop_flush(_mode: ShutdownMode, _deadline: Instant) -> Result<(), &'static str> {
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Tests the handling of spurious IRQ7 and IRQ15 interrupts from the legacy PIC.
+//!
+//! This only runs in PIC mode, e.g., when booted with the `chip=pic` boot option.
+//!
+//! First, it injects IRQ7 and IRQ15 via software interrupts, which the PIC never marks
+//! as in service, and checks that both are counted as spurious and not as a real IRQ7.
+//! Then, it tries to provoke real spurious IRQ7s by masking the timer line
+//! while its interrupt is pending but can't yet be acknowledged by the CPU.
+//! Only some PICs produce a spurious interrupt in this case, e.g., Bochs but not QEMU,
+//! so the number provoked is only reported.
+//!
+//! Finally, it checks that neither the PIC's masks, e.g., for the keyboard and timer lines,
+//! nor its in-service state were disturbed, and that the timer still preempts tasks.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("test_spurious_irq failed: {}", e);
+            -1
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn run() -> Result<(), &'static str> {
+    println!("skipped: the legacy PIC only exists on x86_64");
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+use x86_64_impl::run;
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64_impl {
+    use alloc::string::String;
+    use core::{arch::asm, sync::atomic::{AtomicBool, Ordering}, time::Duration};
+    use app_io::println;
+    use interrupts::{spurious::spurious_counts, IRQ_BASE_OFFSET};
+    use irq_safety::hold_interrupts;
+    use time::Instant;
+
+    /// The PIC line of the PIT timer.
+    const TIMER_LINE: u8 = IRQ_BASE_OFFSET;
+    /// How many times to try provoking a spurious IRQ7 via the timer line.
+    const RACE_ATTEMPTS: usize = 20;
+    /// How long to wait for the timer line to be raised in each attempt.
+    const TIMER_PENDING_TIMEOUT: Duration = Duration::from_millis(100);
+    /// How long to keep the timer line masked after interrupts are re-enabled.
+    const MASKED_DURATION: Duration = Duration::from_micros(50);
+    /// How long a busy-waiting task may wait to be preempted by another task.
+    const PREEMPTION_TIMEOUT: Duration = Duration::from_millis(500);
+
+    /// Whether the task spawned by [`check_preemption()`] has run.
+    static PREEMPTED: AtomicBool = AtomicBool::new(false);
+
+    pub fn run() -> Result<(), &'static str> {
+        let Some(pic) = interrupts::pic() else {
+            println!("skipped: the system isn't in PIC mode; boot with `chip=pic` to run this test");
+            return Ok(());
+        };
+        let masks_before = pic.read_masks();
+        let before = spurious_counts();
+
+        // SAFE: the handlers for both vectors are either the default ones or device handlers
+        // that check whether the interrupt is spurious, which it is.
+        unsafe {
+            asm!("int 0x27");
+            asm!("int 0x2F");
+        }
+        let injected = spurious_counts();
+        if injected.master != before.master + 1 {
+            return Err("a software-injected IRQ7 wasn't counted as spurious");
+        }
+        if injected.slave != before.slave + 1 {
+            return Err("a software-injected IRQ15 wasn't counted as spurious");
+        }
+        if injected.real_irq7 != before.real_irq7 {
+            return Err("a software-injected IRQ7 was counted as a real IRQ7");
+        }
+
+        let attempts = provoke_spurious_irq7(pic);
+        let after = spurious_counts();
+        println!("provoked {} spurious IRQ7s in {} attempts; totals: {:?}",
+            after.master - injected.master, attempts, after,
+        );
+
+        if pic.read_masks() != masks_before {
+            return Err("the PIC's masks changed, e.g., for the keyboard or timer line");
+        }
+        let registers = {
+            let _held_interrupts = hold_interrupts();
+            pic.read_isr_irr()
+        };
+        if registers.master_isr != 0 || registers.slave_isr != 0 {
+            println!("PIC registers: {}", registers);
+            return Err("an IRQ was left in service, so an EOI was lost or misdirected");
+        }
+        check_preemption()
+    }
+
+    /// Masks the timer line while its interrupt is pending and interrupts are disabled,
+    /// then enables interrupts such that the CPU acknowledges an interrupt that's no longer there.
+    ///
+    /// Returns the number of attempts in which the timer line was pending.
+    fn provoke_spurious_irq7(pic: &pic::ChainedPics) -> usize {
+        let mut attempts = 0;
+        for _ in 0..RACE_ATTEMPTS {
+            let held_interrupts = hold_interrupts();
+            let start = Instant::now();
+            let mut pending = false;
+            while start.elapsed() < TIMER_PENDING_TIMEOUT {
+                if pic.read_isr_irr().master_irr & 1 != 0 {
+                    pending = true;
+                    break;
+                }
+                core::hint::spin_loop();
+            }
+            pic.set_irq_masked(TIMER_LINE, true);
+            drop(held_interrupts);
+
+            let start = Instant::now();
+            while start.elapsed() < MASKED_DURATION {
+                core::hint::spin_loop();
+            }
+            pic.set_irq_masked(TIMER_LINE, false);
+            attempts += pending as usize;
+        }
+        attempts
+    }
+
+    /// Checks that the timer still preempts tasks by busy-waiting until
+    /// another task on this CPU has run.
+    fn check_preemption() -> Result<(), &'static str> {
+        PREEMPTED.store(false, Ordering::Release);
+        let task = spawn::new_task_builder(|_: ()| PREEMPTED.store(true, Ordering::Release), ())
+            .name(String::from("test_spurious_irq_preemptor"))
+            .pin_on_cpu(cpu::current_cpu())
+            .spawn()?;
+
+        let start = Instant::now();
+        while !PREEMPTED.load(Ordering::Acquire) {
+            if start.elapsed() > PREEMPTION_TIMEOUT {
+                return Err("the timer no longer preempts tasks");
+            }
+            core::hint::spin_loop();
+        }
+        task.join()?;
+        Ok(())
+    }
+}
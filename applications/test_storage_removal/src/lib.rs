@@ -0,0 +1,134 @@
+//! Tests safely removing a storage device while another task reads from it.
+//!
+//! A reader task repeatedly reads from the first storage device, both directly and through
+//! a block cache registered as a layer atop that device. The device is then removed,
+//! which must detach the cache, end the reader with clean errors rather than a crash,
+//! post a removal event, and leave no references to the device behind.
+//! Finally, all storage controllers are rescanned, which re-adds the device if it's still present.
+//!
+//! This removes the first storage device until the rescan, so it shouldn't be run while it's in use.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::{String, ToString}, sync::{Arc, Weak}, vec, vec::Vec};
+use app_io::println;
+use block_cache::BlockCache;
+use core::{sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+use io::IoError;
+use spin::Mutex;
+use storage_manager::{StorageDeviceDependent, StorageDeviceRef};
+
+/// The number of reads that the reader task must complete before the device is removed.
+const READS_BEFORE_REMOVAL: usize = 16;
+/// The number of distinct blocks that the reader task reads.
+const BLOCKS_TO_READ: usize = 64;
+
+pub fn main(_args: Vec<String>) -> isize {
+    let Some(device) = storage_manager::storage_devices().next() else {
+        println!("no storage devices connected, skipping test");
+        return 0;
+    };
+    match run(device) {
+        Ok(()) => {
+            println!("passed");
+            0
+        }
+        Err(e) => {
+            println!("test_storage_removal failed: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(device: StorageDeviceRef) -> Result<(), String> {
+    let cache = Arc::new(Mutex::new(BlockCache::new(Arc::clone(&device))));
+    storage_manager::register_dependent(&device, Arc::downgrade(&cache) as Weak<dyn StorageDeviceDependent>);
+    let removed_events = events::subscribe(storage_manager::DEVICE_REMOVED_EVENT);
+
+    let reads = Arc::new(AtomicUsize::new(0));
+    let reader = spawn::new_task_builder(read_until_removed, (Arc::clone(&device), Arc::clone(&cache), Arc::clone(&reads)))
+        .name(String::from("test_storage_removal_reader"))
+        .spawn()?;
+    while reads.load(Ordering::Relaxed) < READS_BEFORE_REMOVAL {
+        if reader.has_exited() {
+            break;
+        }
+        let _ = sleep::sleep(Duration::from_millis(1));
+    }
+
+    storage_manager::remove_device(&device, false)?;
+    println!("removed the device after {} reads", reads.load(Ordering::Relaxed));
+
+    let reader_result = match reader.join()? {
+        task::ExitValue::Completed(value) => value
+            .downcast_ref::<Result<usize, String>>()
+            .cloned()
+            .ok_or("the reader task returned an unexpected value")?,
+        task::ExitValue::Killed(reason) => return Err(format!("the reader task was killed: {reason:?}")),
+    };
+    let total_reads = reader_result.map_err(|e| format!("reader: {e}"))?;
+    println!("the reader task ended cleanly after {} reads", total_reads);
+
+    if removed_events.try_recv() != Some(0) {
+        return Err("no removal event was posted for the device".to_string());
+    }
+    if !device.lock().is_detached() {
+        return Err("the removed device wasn't detached".to_string());
+    }
+    if storage_manager::storage_devices().any(|d| Arc::as_ptr(&d) as *const () == Arc::as_ptr(&device) as *const ()) {
+        return Err("the removed device is still listed".to_string());
+    }
+    let mut buf = vec![0u8; device.lock().block_size()];
+    match device.lock().read_blocks(&mut buf, 0) {
+        Err(IoError::DeviceRemoved) => {}
+        other => return Err(format!("reading the removed device returned {other:?}")),
+    }
+    if BlockCache::read_block(&mut cache.lock(), 0).is_ok() {
+        return Err("reading through the detached block cache succeeded".to_string());
+    }
+
+    drop(cache);
+    if Arc::strong_count(&device) != 1 {
+        return Err(format!("{} references to the removed device were leaked", Arc::strong_count(&device) - 1));
+    }
+
+    let changes = storage_manager::rescan();
+    println!("rescan found {} devices", changes.added.len());
+    for added in &changes.added {
+        let mut buf = vec![0u8; added.lock().block_size()];
+        added.lock().read_blocks(&mut buf, 0).map_err(|e| format!("reading a rescanned device failed: {e:?}"))?;
+    }
+    Ok(())
+}
+
+/// Reads blocks from the `device`, alternating between reading directly and through the `cache`,
+/// until the device is removed.
+///
+/// Returns the number of reads, or an error if any read failed other than because of the removal.
+fn read_until_removed((device, cache, reads): (StorageDeviceRef, Arc<Mutex<BlockCache>>, Arc<AtomicUsize>)) -> Result<usize, String> {
+    let block_size = device.lock().block_size();
+    let mut buf = vec![0u8; block_size];
+    let mut i = 0;
+    loop {
+        let block = i % BLOCKS_TO_READ;
+        if i % 2 == 0 {
+            match device.lock().read_blocks(&mut buf, block) {
+                Ok(_) => {}
+                Err(IoError::DeviceRemoved) => return Ok(i),
+                Err(e) => return Err(format!("reading block {block} failed: {e:?}")),
+            }
+        } else {
+            let mut locked_cache = cache.lock();
+            if let Err(e) = BlockCache::read_block(&mut locked_cache, block).map(|_| ()) {
+                if locked_cache.is_detached() {
+                    return Ok(i);
+                }
+                return Err(format!("reading block {block} through the cache failed: {e}"));
+            }
+        }
+        i += 1;
+        reads.fetch_add(1, Ordering::Relaxed);
+    }
+}
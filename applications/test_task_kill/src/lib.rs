@@ -0,0 +1,120 @@
+//! Tests that killing a task at arbitrary points releases the resources it holds.
+//!
+//! Each round spawns a worker task that repeatedly acquires a blocking mutex,
+//! sends a message over a channel, and sleeps, and then kills it after a random delay.
+//! After each kill, this test verifies that the mutex can still be acquired,
+//! that the worker's channel endpoint was disconnected, and that sleeping still works.
+//!
+//! Note: block cache pinning is not exercised, as the block cache doesn't support pinning.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use app_io::println;
+use sleep::Duration;
+use sync_block::{Mutex, MutexExt};
+use sync_channel::{Receiver, Sender};
+
+/// The default number of rounds to run.
+const DEFAULT_ROUNDS: usize = 100;
+/// The maximum delay before killing the worker task, in microseconds.
+const MAX_KILL_DELAY_US: u64 = 2000;
+
+pub fn main(args: Vec<String>) -> isize {
+    let rounds = match args.first().map(|s| s.parse::<usize>()) {
+        None => DEFAULT_ROUNDS,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            println!("Usage: test_task_kill [ROUNDS]");
+            return -1;
+        }
+    };
+
+    match run(rounds) {
+        Ok(()) => {
+            println!("test_task_kill: all {} rounds passed.", rounds);
+            0
+        }
+        Err(e) => {
+            println!("test_task_kill failed: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(rounds: usize) -> Result<(), &'static str> {
+    let lock = Arc::new(Mutex::new(0usize));
+    let mut poisoned_rounds = 0;
+
+    for _ in 0..rounds {
+        let (sender, receiver) = sync_channel::new_channel::<usize>(4);
+        let worker = spawn::new_task_builder(worker, (lock.clone(), sender))
+            .name(String::from("test_task_kill_worker"))
+            .spawn()?;
+
+        let delay = random::next_u64() % MAX_KILL_DELAY_US;
+        sleep::sleep(Duration::from_micros(delay)).map_err(|_| "failed to sleep")?;
+
+        // The worker never exits by itself, so it must still be running.
+        worker.kill(task::KillReason::Requested)?;
+        match worker.join()? {
+            task::ExitValue::Killed(task::KillReason::Requested) => { }
+            _ => return Err("worker task exited for an unexpected reason"),
+        }
+
+        // The mutex must have been released, even if the worker was killed while holding it.
+        if lock.is_poisoned() {
+            poisoned_rounds += 1;
+            lock.clear_poison();
+        }
+        *lock.lock() += 1;
+
+        check_disconnected(&receiver)?;
+
+        // Any timer armed by the worker must have been cancelled,
+        // otherwise the sleep subsystem would attempt to wake up a dead task.
+        sleep::sleep(Duration::from_millis(1)).map_err(|_| "failed to sleep after kill")?;
+    }
+
+    println!(
+        "test_task_kill: killed the worker while it held the mutex in {} of {} rounds.",
+        poisoned_rounds, rounds,
+    );
+    Ok(())
+}
+
+/// Checks that the worker's `Sender` was disconnected from the channel after it was killed.
+fn check_disconnected(receiver: &Receiver<usize>) -> Result<(), &'static str> {
+    loop {
+        match receiver.try_receive() {
+            Ok(_) => continue,
+            Err(sync_channel::Error::ChannelDisconnected) => return Ok(()),
+            Err(sync_channel::Error::WouldBlock) => return Err("killed worker's channel endpoint wasn't disconnected"),
+        }
+    }
+}
+
+fn worker((lock, sender): (Arc<Mutex<usize>>, Sender<usize>)) {
+    // Cleanup hooks are registered with the task that creates an endpoint,
+    // so this task must own its own `Sender` for it to be disconnected when killed.
+    let sender = {
+        let own_sender = sender.clone();
+        drop(sender);
+        own_sender
+    };
+
+    let mut i = 0;
+    loop {
+        {
+            let mut locked = lock.lock();
+            *locked += 1;
+            // Hold the lock for a while to increase the chance of being killed while holding it.
+            let _ = sleep::sleep(Duration::from_micros(50));
+        }
+        let _ = sender.try_send(i);
+        let _ = sleep::sleep(Duration::from_micros(100));
+        i += 1;
+    }
+}
@@ -0,0 +1,129 @@
+//! Stress tests the system-wide task list by creating and destroying
+//! thousands of short-lived tasks while another task continuously iterates over the list.
+//!
+//! This checks that:
+//! * no task that exists for the whole duration of an iteration is missing from it,
+//! * every task in an iteration has the ID it was listed under, and
+//! * the ID of a reaped task is never resolved to a live task,
+//!   even after a newer task has reused its slot in the task list.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use app_io::println;
+use time::{Duration, Instant};
+
+/// The number of short-lived tasks to create and destroy.
+const NUM_TASKS: usize = 5000;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+static ITERATIONS: AtomicUsize = AtomicUsize::new(0);
+static LOST_ENTRIES: AtomicUsize = AtomicUsize::new(0);
+static MISMATCHED_IDS: AtomicUsize = AtomicUsize::new(0);
+static MAX_ITERATION_NANOS: AtomicU64 = AtomicU64::new(0);
+
+pub fn main(_args: Vec<String>) -> isize {
+    let my_id = task::get_my_current_task_id();
+    let iterator = match spawn::new_task_builder(iterate_task_list, my_id)
+        .name(String::from("test_task_list_iterator"))
+        .spawn()
+    {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Failed to spawn iterator task: {}", e);
+            return -1;
+        }
+    };
+
+    let mut reaped_ids = Vec::with_capacity(NUM_TASKS);
+    let mut stale_hits = 0;
+    let mut reused_slots = 0;
+    let mut max_lookup = Duration::ZERO;
+
+    for _ in 0..NUM_TASKS {
+        let task = match spawn::new_task_builder(short_lived, ()).spawn() {
+            Ok(t) => t,
+            Err(e) => {
+                println!("Failed to spawn short-lived task: {}", e);
+                STOP.store(true, Ordering::Release);
+                return -1;
+            }
+        };
+        let id = task.id;
+        if task::task_id_generation(id) > 0 {
+            reused_slots += 1;
+        }
+        if task.join().is_err() {
+            println!("Failed to join short-lived task {}", id);
+        }
+        drop(task);
+
+        let start = Instant::now();
+        let found = task::get_task(id).and_then(|t| t.upgrade());
+        max_lookup = max_lookup.max(start.elapsed());
+        if found.is_some() {
+            stale_hits += 1;
+        }
+        reaped_ids.push(id);
+    }
+
+    // After all slots have been churned through, no old ID may resolve to a live task.
+    stale_hits += reaped_ids.iter()
+        .filter(|id| task::get_task(**id).and_then(|t| t.upgrade()).is_some())
+        .count();
+
+    STOP.store(true, Ordering::Release);
+    if iterator.join().is_err() {
+        println!("Failed to join iterator task");
+    }
+
+    let lost = LOST_ENTRIES.load(Ordering::Acquire);
+    let mismatched = MISMATCHED_IDS.load(Ordering::Acquire);
+    println!("Created and destroyed {} tasks ({} reused a task list slot).", NUM_TASKS, reused_slots);
+    println!("Iterated the task list {} times; the longest iteration took {} ns.",
+        ITERATIONS.load(Ordering::Acquire), MAX_ITERATION_NANOS.load(Ordering::Acquire),
+    );
+    println!("The longest lookup of a reaped task ID took {:?}.", max_lookup);
+    println!("Lost entries: {}, mismatched IDs: {}, stale IDs treated as live: {}",
+        lost, mismatched, stale_hits,
+    );
+
+    if lost == 0 && mismatched == 0 && stale_hits == 0 {
+        println!("test_task_list passed.");
+        0
+    } else {
+        println!("test_task_list failed.");
+        -1
+    }
+}
+
+fn short_lived(_: ()) {}
+
+/// Repeatedly iterates over the task list until told to stop,
+/// checking that the main test task (which outlives every iteration) is always present.
+fn iterate_task_list(main_task_id: usize) {
+    let my_id = task::get_my_current_task_id();
+    while !STOP.load(Ordering::Acquire) {
+        let start = Instant::now();
+        let tasks = task::all_tasks();
+        let nanos = start.elapsed().as_nanos() as u64;
+        MAX_ITERATION_NANOS.fetch_max(nanos, Ordering::Relaxed);
+        ITERATIONS.fetch_add(1, Ordering::Relaxed);
+
+        for expected in [main_task_id, my_id] {
+            if !tasks.iter().any(|(id, _)| *id == expected) {
+                LOST_ENTRIES.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        for (id, weak) in tasks {
+            if let Some(t) = weak.upgrade() {
+                if t.id != id {
+                    MISMATCHED_IDS.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Tests that threads spawned with [`spawn::create_thread()`] share memory and a task group.
+//!
+//! Several threads increment three shared counters with a non-atomic read-modify-write:
+//! one without any lock, in which some increments may be lost,
+//! one under a simple lock that yields while it is held by another thread,
+//! and one under a lock that blocks on a futex while it is held by another thread.
+//! Every increment under either lock must be counted.
+//! Each thread must also be in the spawning task's group and share its memory account.
+//!
+//! Each lock counts how many times a thread tried and failed to acquire it,
+//! i.e., the CPU time wasted on contention, which is reported for comparison:
+//! the yield lock retries whenever it's rescheduled, whereas the futex lock only retries once woken up.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::{cell::UnsafeCell, sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering}};
+use app_io::println;
+
+/// The number of threads to spawn.
+const THREADS: usize = 4;
+/// The number of times each thread increments each counter.
+const INCREMENTS: usize = 10_000;
+/// How often a thread yields while incrementing, to interleave the threads more often.
+const YIELD_EVERY: usize = 100;
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("test_threads failed: {}", e);
+            -1
+        }
+    }
+}
+
+/// The counters shared by all threads.
+struct Shared {
+    unlocked: AtomicUsize,
+    yield_locked: YieldLock,
+    futex_locked: FutexLock,
+}
+
+/// A counter protected by a lock that yields to other tasks while it is held.
+struct YieldLock {
+    held: AtomicBool,
+    count: UnsafeCell<usize>,
+    /// The number of failed attempts to acquire the lock.
+    retries: AtomicUsize,
+}
+
+// SAFETY: `count` is only accessed while `held` is acquired.
+unsafe impl Sync for YieldLock {}
+
+impl YieldLock {
+    fn increment(&self) {
+        while self.held.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            self.retries.fetch_add(1, Ordering::Relaxed);
+            task::schedule();
+        }
+        // SAFETY: the lock is held, so no other thread accesses `count`.
+        unsafe { *self.count.get() += 1; }
+        self.held.store(false, Ordering::Release);
+    }
+}
+
+/// A counter protected by a lock that blocks on a futex while it is held.
+struct FutexLock {
+    /// 0 if unlocked, 1 if locked, or 2 if locked and another thread may be waiting for it.
+    state: AtomicU32,
+    count: UnsafeCell<usize>,
+    /// The number of failed attempts to acquire the lock.
+    retries: AtomicUsize,
+}
+
+// SAFETY: `count` is only accessed while `state` is acquired.
+unsafe impl Sync for FutexLock {}
+
+impl FutexLock {
+    fn increment(&self) {
+        if self.state.compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            while self.state.swap(2, Ordering::Acquire) != 0 {
+                self.retries.fetch_add(1, Ordering::Relaxed);
+                // This returns immediately if the lock was released in the meantime.
+                let _ = futex::futex_wait(&self.state, 2, None);
+            }
+        }
+        // SAFETY: the lock is held, so no other thread accesses `count`.
+        unsafe { *self.count.get() += 1; }
+        if self.state.swap(0, Ordering::Release) == 2 {
+            let _ = futex::futex_wake(&self.state, 1);
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    let (my_group, my_account) = task::with_current_task(|t| (t.group_id(), Arc::clone(t.memory_account())))
+        .map_err(|_| "couldn't get current task")?;
+    let shared = Arc::new(Shared {
+        unlocked: AtomicUsize::new(0),
+        yield_locked: YieldLock {
+            held: AtomicBool::new(false),
+            count: UnsafeCell::new(0),
+            retries: AtomicUsize::new(0),
+        },
+        futex_locked: FutexLock {
+            state: AtomicU32::new(0),
+            count: UnsafeCell::new(0),
+            retries: AtomicUsize::new(0),
+        },
+    });
+
+    let mut threads = Vec::with_capacity(THREADS);
+    for _ in 0..THREADS {
+        threads.push(spawn::create_thread(increment_counters, Arc::clone(&shared))?);
+    }
+    for thread in &threads {
+        if thread.group_id() != my_group {
+            return Err(format!("thread {} is in group {} instead of {}", thread.id, thread.group_id(), my_group));
+        }
+        if !Arc::ptr_eq(thread.memory_account(), &my_account) {
+            return Err(format!("thread {} doesn't share its group's memory account", thread.id));
+        }
+    }
+    for thread in threads {
+        if let task::ExitValue::Killed(reason) = thread.join()? {
+            return Err(format!("a thread was killed: {reason:?}"));
+        }
+    }
+
+    let expected = THREADS * INCREMENTS;
+    let unlocked = shared.unlocked.load(Ordering::Relaxed);
+    // SAFETY: all threads have exited, so no other thread accesses the counters.
+    let (yield_locked, futex_locked) = unsafe { (*shared.yield_locked.count.get(), *shared.futex_locked.count.get()) };
+    println!("{} threads incremented each counter {} times: {} without a lock ({} lost), {} with a yield lock, {} with a futex lock",
+        THREADS, INCREMENTS, unlocked, expected - unlocked, yield_locked, futex_locked,
+    );
+    println!("failed lock attempts: {} with the yield lock, {} with the futex lock",
+        shared.yield_locked.retries.load(Ordering::Relaxed),
+        shared.futex_locked.retries.load(Ordering::Relaxed),
+    );
+    if unlocked > expected {
+        return Err(format!("the unlocked counter reached {unlocked}, more than the {expected} increments"));
+    }
+    if yield_locked != expected {
+        return Err(format!("the yield-locked counter reached {yield_locked} instead of {expected}"));
+    }
+    if futex_locked != expected {
+        return Err(format!("the futex-locked counter reached {futex_locked} instead of {expected}"));
+    }
+    Ok(())
+}
+
+fn increment_counters(shared: Arc<Shared>) {
+    for i in 0..INCREMENTS {
+        // A deliberately racy increment, which may lose updates made by other threads in between.
+        let value = shared.unlocked.load(Ordering::Relaxed);
+        shared.unlocked.store(value + 1, Ordering::Relaxed);
+        shared.yield_locked.increment();
+        shared.futex_locked.increment();
+        if i % YIELD_EVERY == 0 {
+            task::schedule();
+        }
+    }
+}
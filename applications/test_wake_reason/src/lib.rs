@@ -0,0 +1,84 @@
+//! Tests that an interruptible sleep reports why it returned.
+//!
+//! * A sleep that isn't disturbed times out normally.
+//! * A sleep whose task is unblocked without a reason keeps sleeping.
+//! * A sleep whose task is interrupted returns early with an error.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use sleep::{Duration, SleepError};
+use task::WakeReason;
+use time::Instant;
+
+/// How long the sleeper task sleeps for.
+const SLEEP_DURATION: Duration = Duration::from_millis(200);
+/// How long to wait before disturbing the sleeper task.
+const DISTURB_DELAY: Duration = Duration::from_millis(20);
+
+pub fn main(_args: Vec<String>) -> isize {
+    match run() {
+        Ok(()) => {
+            println!("test_wake_reason: all tests passed.");
+            0
+        }
+        Err(e) => {
+            println!("test_wake_reason failed: {}", e);
+            -1
+        }
+    }
+}
+
+fn run() -> Result<(), &'static str> {
+    let (result, elapsed) = sleep_and_disturb(None)?;
+    if result != Ok(()) || elapsed < SLEEP_DURATION {
+        return Err("an undisturbed sleep didn't time out normally");
+    }
+
+    let (result, elapsed) = sleep_and_disturb(Some(None))?;
+    if result != Ok(()) || elapsed < SLEEP_DURATION {
+        return Err("a spurious wakeup ended an interruptible sleep early");
+    }
+
+    let (result, elapsed) = sleep_and_disturb(Some(Some(WakeReason::Interrupted)))?;
+    if result != Err(SleepError::Interrupted) || elapsed >= SLEEP_DURATION {
+        return Err("an interrupted sleep didn't return early with an error");
+    }
+
+    Ok(())
+}
+
+/// Spawns a task that sleeps interruptibly, then optionally unblocks it
+/// with the given reason (or with no reason) while it sleeps.
+///
+/// Returns the result of the sleep and how long it took.
+fn sleep_and_disturb(
+    disturbance: Option<Option<WakeReason>>,
+) -> Result<(Result<(), SleepError>, Duration), &'static str> {
+    let sleeper = spawn::new_task_builder(|_: ()| {
+        let start = Instant::now();
+        let result = sleep::sleep_interruptible(SLEEP_DURATION);
+        (result, start.elapsed())
+    }, ())
+        .name(String::from("test_wake_reason_sleeper"))
+        .spawn()?;
+
+    if let Some(reason) = disturbance {
+        sleep::sleep(DISTURB_DELAY).map_err(|_| "failed to sleep")?;
+        let _ = match reason {
+            Some(reason) => sleeper.unblock_with_reason(reason),
+            None => sleeper.unblock(),
+        };
+    }
+
+    match sleeper.join()? {
+        task::ExitValue::Completed(value) => value
+            .downcast_ref::<(Result<(), SleepError>, Duration)>()
+            .copied()
+            .ok_or("sleeper task returned an unexpected value"),
+        task::ExitValue::Killed(_) => Err("sleeper task was killed"),
+    }
+}
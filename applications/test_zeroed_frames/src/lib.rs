@@ -0,0 +1,95 @@
+//! Tests that [`memory::allocate_zeroed_frames()`] and [`memory::Mapper::map_allocated_pages_zeroed()`]
+//! never expose the stale contents of previously-used frames,
+//! and that the frames of a dropped mapping are filled with [`memory::FREED_FRAME_POISON`] in debug builds.
+//!
+//! The frame of a dropped mapping may be reallocated by another task before this test can reclaim it,
+//! in which case the poison check is skipped.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use memory::{AllocatedFrames, MappedPages, PteFlags, PAGE_SIZE};
+
+/// The byte that frames are dirtied with before they're freed.
+const STALE_BYTE: u8 = 0xA5;
+
+pub fn main(_args: Vec<String>) -> isize {
+    match rmain() {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+fn rmain() -> Result<(), &'static str> {
+    let writable = PteFlags::new().valid(true).writable(true);
+
+    println!("Dirtying and freeing a frame...");
+    let mut mp = memory::create_mapping(PAGE_SIZE, writable)?;
+    mp.as_slice_mut::<u8>(0, PAGE_SIZE)?.fill(STALE_BYTE);
+    let paddr = memory::translate(mp.start_address()).ok_or("new mapping wasn't translatable")?;
+    drop(mp);
+
+    match memory::allocate_frames_at(paddr, 1) {
+        Ok(frames) => check_freed_frame(frames)?,
+        Err(_) => println!("The freed frame was reallocated by someone else, skipping the poison check."),
+    }
+
+    println!("Checking allocate_zeroed_frames()...");
+    for _ in 0..4 {
+        let frames = memory::allocate_zeroed_frames(2)?;
+        let mp = map(frames)?;
+        assert!(mp.as_slice::<u8>(0, 2 * PAGE_SIZE)?.iter().all(|&b| b == 0), "allocate_zeroed_frames() returned a non-zero frame");
+    }
+
+    println!("Checking map_allocated_pages_zeroed()...");
+    let kernel_mmi_ref = memory::get_kernel_mmi_ref().ok_or("couldn't get kernel MMI")?;
+    let read_only = PteFlags::new().valid(true);
+    let pages = memory::allocate_pages(4).ok_or("couldn't allocate pages")?;
+    let mp = kernel_mmi_ref.lock().page_table.map_allocated_pages_zeroed(pages, read_only)?;
+    assert!(!mp.flags().is_writable(), "map_allocated_pages_zeroed() didn't apply the requested flags");
+    assert!(mp.as_slice::<u8>(0, 4 * PAGE_SIZE)?.iter().all(|&b| b == 0), "map_allocated_pages_zeroed() mapped a non-zero frame");
+
+    println!("Success!");
+    Ok(())
+}
+
+/// Checks that the given frame, which was just freed after being dirtied, was poisoned in debug builds,
+/// and that zeroing it removes its stale contents.
+fn check_freed_frame(frames: AllocatedFrames) -> Result<(), &'static str> {
+    let mp = map(frames)?;
+    let words = mp.as_slice::<u32>(0, PAGE_SIZE / core::mem::size_of::<u32>())?;
+    if cfg!(debug_assertions) {
+        assert!(words.iter().all(|&w| w == memory::FREED_FRAME_POISON), "the freed frame wasn't poisoned");
+        println!("The freed frame was poisoned.");
+    } else {
+        assert!(words.iter().all(|&w| w == u32::from_ne_bytes([STALE_BYTE; 4])), "the freed frame was modified");
+    }
+
+    let kernel_mmi_ref = memory::get_kernel_mmi_ref().ok_or("couldn't get kernel MMI")?;
+    let mut kernel_mmi = kernel_mmi_ref.lock();
+    let (_pages, frames) = mp.unmap_into_parts(&mut kernel_mmi.page_table)
+        .map_err(|_| "couldn't unmap the freed frame")?;
+    let frames = kernel_mmi.page_table.zero_frames(frames.ok_or("unmapping didn't return the freed frame")?)?;
+    drop(kernel_mmi);
+
+    let mp = map(frames)?;
+    assert!(mp.as_slice::<u8>(0, PAGE_SIZE)?.iter().all(|&b| b == 0), "zero_frames() didn't zero the freed frame");
+    Ok(())
+}
+
+/// Maps the given frames as writable into the kernel's address space.
+fn map(frames: AllocatedFrames) -> Result<MappedPages, &'static str> {
+    let kernel_mmi_ref = memory::get_kernel_mmi_ref().ok_or("couldn't get kernel MMI")?;
+    let pages = memory::allocate_pages(frames.size_in_frames()).ok_or("couldn't allocate pages")?;
+    kernel_mmi_ref.lock().page_table.map_allocated_pages_to(
+        pages,
+        frames,
+        PteFlags::new().valid(true).writable(true),
+    )
+}
@@ -0,0 +1,49 @@
+//! Shows how far the Local APIC timer that drives the scheduler's ticks has drifted
+//! from the RTC, and the correction currently applied to its period.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+
+pub fn main(args: Vec<String>) -> isize {
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{}", USAGE);
+        return 0;
+    }
+
+    match run() {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn run() -> Result<(), &'static str> {
+    let drift = scheduler::apic_drift::drift()
+        .ok_or("no drift has been measured yet; the APIC timer may not be in use (PIC mode)")?;
+    println!("Measured drift:  {:+} ppm over the last {} s", drift.measured_ppm, drift.baseline_secs);
+    if drift.baseline_secs < scheduler::apic_drift::MIN_BASELINE_SECS {
+        println!("                 (too short to be accurate, corrections wait for {} s)",
+            scheduler::apic_drift::MIN_BASELINE_SECS,
+        );
+    }
+    println!("Correction:      {:+} ppm to the timer period ({} adjustments)", drift.correction_ppm, drift.corrections);
+    println!("Observed:        {} s", drift.observed_secs);
+    Ok(())
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn run() -> Result<(), &'static str> {
+    Err("APIC timer drift compensation is only supported on x86_64")
+}
+
+const USAGE: &str = "Usage: timerdrift
+Shows the drift of the tick clock driven by the bootstrap CPU's Local APIC timer,
+measured against the RTC in parts per million (positive means it runs fast),
+and the correction applied to every CPU's APIC timer period to compensate for it.";
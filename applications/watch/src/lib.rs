@@ -0,0 +1,356 @@
+//! Periodically re-runs a command, or samples kernel metrics, until stopped.
+//!
+//! * `watch INTERVAL_MS COMMAND [ARGS...]` re-runs the application `COMMAND` every `INTERVAL_MS`,
+//!   printing (at most [`MAX_OUTPUT_BYTES`] of) its output after a separator line.
+//! * `watch --expr INTERVAL_MS METRIC...` prints how much each of the given metrics,
+//!   as named in the `kmetrics` snapshot, changed over each interval, along with its rate per second.
+//!
+//! Pressing Enter (or Ctrl+D) stops watching, killing the command if it's still running.
+//! If this application is killed instead, e.g., by Ctrl+C, its cleanup hooks kill the command
+//! and the task that waits for input, and remove the command's streams,
+//! such that no task or sleep timer outlives the watch.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use app_io::{print, println, IoStreams, ImmutableRead};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use getopts::{Options, ParsingStyle};
+use stdio::{Stdio, StdioReader};
+use task::{CleanupGuard, CleanupReason, ExitValue, JoinableTaskRef, KillReason, TaskRef, WakeReason};
+use time::{now, Instant, Monotonic};
+
+/// The maximum number of bytes of a command's output that are printed per iteration.
+///
+/// Any further output is discarded as it's produced, so a chatty command can't exhaust memory.
+pub const MAX_OUTPUT_BYTES: usize = 4096;
+/// The shortest interval that can be watched.
+const MIN_INTERVAL: Duration = Duration::from_millis(10);
+/// How often a running command's output is collected.
+const OUTPUT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    // Options after the command name belong to that command.
+    opts.parsing_style(ParsingStyle::StopAtFirstFree);
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("e", "expr", "print the per-interval change of the given metrics instead of re-running a command");
+    opts.optopt("n", "count", "stop after the given number of intervals", "COUNT");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            print_usage(opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return 0;
+    }
+
+    let count = match matches.opt_get::<u64>("n") {
+        Ok(count) => count,
+        Err(e) => {
+            println!("Invalid count: {}", e);
+            return -1;
+        }
+    };
+    let Some((interval, targets)) = matches.free.split_first() else {
+        print_usage(opts);
+        return -1;
+    };
+    let interval = match interval.parse::<u64>() {
+        Ok(ms) if Duration::from_millis(ms) >= MIN_INTERVAL => Duration::from_millis(ms),
+        _ => {
+            println!("Invalid interval {:?}, expected at least {} milliseconds", interval, MIN_INTERVAL.as_millis());
+            return -1;
+        }
+    };
+    if targets.is_empty() {
+        print_usage(opts);
+        return -1;
+    }
+
+    let result = StopOnInput::new().and_then(|stop| {
+        if matches.opt_present("e") {
+            watch_metrics(interval, targets, count, &stop)
+        } else {
+            watch_command(interval, targets, count, &stop)
+        }
+    });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+/// Re-runs the command `cmd` every `interval`, printing its output after each run.
+fn watch_command(interval: Duration, cmd: &[String], count: Option<u64>, stop: &StopOnInput) -> Result<(), String> {
+    let app_path = find_app(&cmd[0])?;
+    let mut iteration = 0;
+    while !stop.is_stopped() {
+        iteration += 1;
+        let start = now::<Monotonic>();
+        let output = run_command(&app_path, &cmd[1..], stop)?;
+
+        println!("--- {} (every {} ms, #{}) ---", cmd.join(" "), interval.as_millis(), iteration);
+        print!("{}", String::from_utf8_lossy(&output.bytes));
+        if !output.bytes.is_empty() && !output.bytes.ends_with(b"\n") {
+            println!();
+        }
+        if output.discarded > 0 {
+            println!("[{} more bytes of output discarded]", output.discarded);
+        }
+        match output.exit {
+            ExitValue::Completed(value) => match value.downcast_ref::<isize>() {
+                Some(&0) | None => {}
+                Some(code) => println!("[exited with code {}]", code),
+            },
+            ExitValue::Killed(KillReason::Requested) if stop.is_stopped() => break,
+            ExitValue::Killed(reason) => println!("[killed: {:?}]", reason),
+        }
+        if count.is_some_and(|c| iteration >= c) {
+            break;
+        }
+        stop.sleep_until(start, interval);
+    }
+    Ok(())
+}
+
+/// Prints the change of each of the metrics with the given `names` every `interval`.
+fn watch_metrics(interval: Duration, names: &[String], count: Option<u64>, stop: &StopOnInput) -> Result<(), String> {
+    let mut previous = sample_metrics(names);
+    if let Some(missing) = names.iter().zip(&previous).find_map(|(name, value)| value.is_none().then_some(name)) {
+        return Err(format!("metric {:?} doesn't exist or isn't a number; see `kmetrics`", missing));
+    }
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+    let mut previous_time = now::<Monotonic>();
+    let mut iteration = 0;
+    loop {
+        stop.sleep_until(previous_time, interval);
+        if stop.is_stopped() {
+            break;
+        }
+        let current = sample_metrics(names);
+        let current_time = now::<Monotonic>();
+        let elapsed_nanos = current_time.duration_since(previous_time).as_nanos().max(1);
+        iteration += 1;
+
+        println!("--- changes over {} ms (#{}) ---", current_time.duration_since(previous_time).as_millis(), iteration);
+        for ((name, old), new) in names.iter().zip(&previous).zip(&current) {
+            match (old, new) {
+                (Some(old), Some(new)) => {
+                    let delta = *new as i128 - *old as i128;
+                    let rate = delta * 1_000_000_000 / elapsed_nanos as i128;
+                    println!("{:<width$}  {:>+12}  ({:+}/s)", name, delta, rate, width = width);
+                }
+                _ => println!("{:<width$}  {:>12}", name, "n/a", width = width),
+            }
+        }
+        previous = current;
+        previous_time = current_time;
+        if count.is_some_and(|c| iteration >= c) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the current value of each of the given metrics,
+/// or `None` for those that don't exist or don't have a numeric value.
+fn sample_metrics(names: &[String]) -> Vec<Option<u64>> {
+    let snapshot = metrics::snapshot();
+    let entries: Vec<(&str, &str)> = snapshot.lines()
+        .filter(|line| !line.starts_with("==="))
+        .filter_map(|line| line.split_once('='))
+        .collect();
+    names.iter()
+        .map(|name| entries.iter()
+            .find(|(key, _)| *key == name.as_str())
+            .and_then(|(_, value)| value.parse::<u64>().ok())
+        )
+        .collect()
+}
+
+/// Returns the absolute path of the application named `cmd` in the current namespace.
+fn find_app(cmd: &str) -> Result<String, String> {
+    let namespace_dir = task::with_current_task(|t| t.get_namespace().dir().clone())
+        .map_err(|_| "couldn't get the current task's namespace".to_string())?;
+    let mut matching_apps = namespace_dir.get_files_starting_with(&format!("{cmd}-")).into_iter();
+    let app_file = matching_apps.next();
+    let second_match = matching_apps.next();
+    app_file.xor(second_match)
+        .map(|f| f.lock().get_absolute_path())
+        .ok_or_else(|| format!("couldn't find a single application named {:?}", cmd))
+}
+
+/// The result of one run of a watched command.
+struct CommandOutput {
+    /// The first [`MAX_OUTPUT_BYTES`] of the command's stdout and stderr.
+    bytes: Vec<u8>,
+    /// The number of bytes of output beyond `bytes` that were discarded.
+    discarded: usize,
+    exit: ExitValue,
+}
+
+/// Runs the application at `app_path` with the given `args` to completion, capturing its output.
+///
+/// The command is killed if `stop` is triggered while it's running.
+fn run_command(app_path: &String, args: &[String], stop: &StopOnInput) -> Result<CommandOutput, String> {
+    let child = spawn::new_application_task_builder(app_path.as_ref(), None)
+        .and_then(|builder| builder.argument(args.to_vec()).block().spawn())
+        .map_err(|e| format!("couldn't spawn {}: {}", app_path, e))?;
+    if let Ok(env) = task::with_current_task(|t| t.get_env()) {
+        child.set_env(env);
+    }
+
+    // The command's stdin is empty, and its stderr is interleaved with its stdout.
+    let stdin = Stdio::new();
+    stdin.get_writer().lock().set_eof();
+    let stdout = Stdio::new();
+    app_io::insert_child_streams(child.id, IoStreams {
+        stdin: Arc::new(stdin.get_reader()),
+        stdout: Arc::new(stdout.get_writer()),
+        stderr: Arc::new(stdout.get_writer()),
+        discipline: None,
+    });
+    let _cleanup = ChildCleanup::new(&child);
+    child.unblock().map_err(|_| "couldn't start the command".to_string())?;
+
+    let reader = stdout.get_reader();
+    let mut output = CommandOutput { bytes: Vec::new(), discarded: 0, exit: ExitValue::Killed(KillReason::Requested) };
+    loop {
+        // Check before collecting output, such that all output is collected after the command exits.
+        let exited = child.has_exited();
+        collect_output(&reader, &mut output);
+        if exited {
+            break;
+        }
+        if stop.is_stopped() {
+            let _ = child.kill(KillReason::Requested);
+        }
+        let _ = sleep::sleep(OUTPUT_POLL_INTERVAL);
+    }
+    output.exit = child.join().map_err(|e| e.to_string())?;
+    Ok(output)
+}
+
+/// Moves all output available in `reader` into `output`, discarding any beyond [`MAX_OUTPUT_BYTES`].
+fn collect_output(reader: &StdioReader, output: &mut CommandOutput) {
+    let mut locked_reader = reader.lock();
+    let mut buf = [0u8; 256];
+    while let Ok(n) = locked_reader.try_read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        let kept = n.min(MAX_OUTPUT_BYTES - output.bytes.len());
+        output.bytes.extend_from_slice(&buf[..kept]);
+        output.discarded += n - kept;
+    }
+}
+
+/// Kills a watched command and removes its streams if this task exits while it's running,
+/// and otherwise just removes its streams once the command has been joined.
+struct ChildCleanup {
+    child_id: usize,
+    _guard: Option<CleanupGuard>,
+}
+
+impl ChildCleanup {
+    fn new(child: &JoinableTaskRef) -> ChildCleanup {
+        let child_ref: TaskRef = (**child).clone();
+        let child_id = child.id;
+        let guard = CleanupGuard::new(Box::new(move |_: CleanupReason| {
+            let _ = child_ref.kill(KillReason::Requested);
+            app_io::remove_child_streams(child_id);
+        }));
+        ChildCleanup { child_id, _guard: guard }
+    }
+}
+
+impl Drop for ChildCleanup {
+    fn drop(&mut self) {
+        app_io::remove_child_streams(self.child_id);
+    }
+}
+
+/// Stops the watch once a line (or the end of input) is read from this application's stdin.
+///
+/// As reading stdin blocks, it's done by a worker thread, which interrupts the watching task's
+/// sleep once input arrives. The worker is killed once watching ends, or by a cleanup hook
+/// if the watching task is killed first.
+struct StopOnInput {
+    stopped: Arc<AtomicBool>,
+    worker: JoinableTaskRef,
+    _cleanup: Option<CleanupGuard>,
+}
+
+impl StopOnInput {
+    fn new() -> Result<StopOnInput, String> {
+        let stdin = app_io::stdin().map_err(ToString::to_string)?;
+        let watcher = task::get_my_current_task().ok_or("couldn't get the current task")?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let worker = spawn::create_thread(wait_for_input, (stdin, Arc::clone(&stopped), watcher))
+            .map_err(|e| format!("couldn't spawn the input task: {}", e))?;
+        let worker_ref: TaskRef = (*worker).clone();
+        let cleanup = CleanupGuard::new(Box::new(move |_: CleanupReason| {
+            let _ = worker_ref.kill(KillReason::Requested);
+        }));
+        Ok(StopOnInput { stopped, worker, _cleanup: cleanup })
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+
+    /// Sleeps until `interval` after `start`, or until input arrives.
+    fn sleep_until(&self, start: Instant, interval: Duration) {
+        let remaining = interval.saturating_sub(now::<Monotonic>().duration_since(start));
+        if !self.is_stopped() && !remaining.is_zero() {
+            let _ = sleep::sleep_interruptible(remaining);
+        }
+    }
+}
+
+impl Drop for StopOnInput {
+    fn drop(&mut self) {
+        if !self.worker.has_exited() {
+            let _ = self.worker.kill(KillReason::Requested);
+        }
+        let _ = self.worker.join();
+    }
+}
+
+/// The entry point of [`StopOnInput`]'s worker thread.
+fn wait_for_input((stdin, stopped, watcher): (Arc<dyn ImmutableRead>, Arc<AtomicBool>, TaskRef)) {
+    let _ = stdin.read_line();
+    stopped.store(true, Ordering::Release);
+    let _ = watcher.unblock_with_reason(WakeReason::Interrupted);
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: watch [OPTIONS] INTERVAL_MS COMMAND [ARGS...]
+       watch --expr [OPTIONS] INTERVAL_MS METRIC...
+Re-runs COMMAND every INTERVAL_MS milliseconds, printing its output after a separator,
+or with --expr, prints how much each METRIC (as named by `kmetrics`) changed per interval.
+Press Enter to stop watching.";
@@ -6,7 +6,7 @@ use log::debug;
 use volatile::{Volatile, ReadOnly};
 use zerocopy::FromBytes;
 use spin::{Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
-use memory::{allocate_pages, allocate_frames_by_bytes_at, PageTable, PhysicalAddress, PteFlags, BorrowedMappedPages, Mutable};
+use memory::{allocate_pages, allocate_frames_by_bytes_at, PageTable, PhysicalAddress, PteFlags, PteFlagsArch, MemoryType, BorrowedMappedPages, Mutable};
 use sdt::{Sdt, GenericAddressStructure};
 use acpi_table::{AcpiTables, AcpiSignature};
 use time::Instant;
@@ -183,7 +183,7 @@ impl HpetAcpiTable {
         let hpet_mp = page_table.map_allocated_pages_to(
             pages,
             frames,
-            PteFlags::new().valid(true).writable(true).device_memory(true),
+            PteFlagsArch::from(PteFlags::new().valid(true).writable(true)).memory_type(MemoryType::Uncacheable),
         )?;
 
         let mut hpet = hpet_mp.into_borrowed_mut::<Hpet>(phys_addr.frame_offset())
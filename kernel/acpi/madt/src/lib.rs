@@ -7,7 +7,7 @@ extern crate alloc;
 
 use core::mem::size_of;
 use alloc::{boxed::Box, format};
-use log::{error, warn, trace};
+use log::{error, warn};
 use memory::{MappedPages, PageTable, PhysicalAddress}; 
 use apic::{LocalApic, bootstrap_cpu, LapicInitError, current_cpu};
 use sdt::Sdt;
@@ -353,20 +353,6 @@ fn handle_bsp_lapic_entry(madt_iter: MadtIter, page_table: &mut PageTable) -> Re
             let bsp_id = current_cpu();
             assert!(bsp_id.value() == lapic_entry.apic_id as u32);
 
-            // Redirect every IoApic's interrupts to the one BSP.
-            // TODO: long-term, we should distribute interrupts across CPUs more evenly.
-            for (_ioapic_id, ioapic) in ioapic::get_ioapics() {
-                let mut ioapic_ref = ioapic.lock();
-
-                // Set the BSP to receive regular PIC interrupts routed through the IoApic.
-                // Skip irq 2, since in the PIC that's the chained one (cascade line from PIC2 to PIC1) that isn't used.
-                for irq in (0x0 ..= 0x1).chain(0x3 ..= 0xF) {
-                    ioapic_ref.set_irq(irq, bsp_id, IRQ_BASE_OFFSET + irq)?;
-                }
-
-                // ioapic_ref.set_irq(0x1, 0xFF, IRQ_BASE_OFFSET + 0x1); 
-                // FIXME: the above line does indeed send the interrupt to all cores, but then they all handle it, instead of just one. 
-            }
             // there's only ever one BSP, so we can exit the loop here
             break;
         }
@@ -374,35 +360,63 @@ fn handle_bsp_lapic_entry(madt_iter: MadtIter, page_table: &mut PageTable) -> Re
 
     let bsp_id = bootstrap_cpu().ok_or("handle_bsp_lapic_entry(): Couldn't find BSP LocalApic in Madt!")?;
 
-    // now that we've established the BSP, go through the interrupt source override entries
+    // Now that we've established the BSP, record the interrupt source overrides,
+    // which describe ISA IRQs that aren't identity-mapped to GSIs with ISA signaling.
     for madt_entry in madt_iter {
         if let MadtEntry::IntSrcOverride(int_src) = madt_entry {
-            let mut handled = false;
-
-            // find the IoApic that should handle this interrupt source override entry
-            for (_id, ioapic) in ioapic::get_ioapics() {
-                let mut ioapic_ref = ioapic.lock();
-                if ioapic_ref.handles_irq(int_src.gsi) {
-                    // using BSP for now, but later we could redirect the IRQ to more (or all) cores
-                    ioapic_ref.set_irq(int_src.irq_source, bsp_id, int_src.gsi as u8 + IRQ_BASE_OFFSET)?;
-                    trace!("MadtIntSrcOverride (bus: {}, irq: {}, gsi: {}, flags {:#X}) handled by IoApic {}",
-                        int_src.bus_source, int_src.irq_source, &{ int_src.gsi }, &{ int_src.flags }, ioapic_ref.id
-                    );
-                    handled = true;
-                }
-            }
+            handle_int_src_override(int_src)?;
+        }
+    }
 
-            if !handled {
-                error!("MadtIntSrcOverride (bus: {}, irq: {}, gsi: {}, flags {:#X}) not handled by any IoApic!",
-                    int_src.bus_source, int_src.irq_source, &{ int_src.gsi }, &{ int_src.flags}
-                );
-            }
+    // Set the BSP to receive the legacy ISA interrupts routed through the IoApics,
+    // at the same vectors they would have with the remapped PIC.
+    // Skip irq 2, since in the PIC that's the chained one (cascade line from PIC2 to PIC1) that isn't used.
+    // TODO: long-term, we should distribute interrupts across CPUs more evenly.
+    for irq in (0x0 ..= 0x1).chain(0x3 .. ioapic::NUM_ISA_IRQS) {
+        if let Err(e) = ioapic::route_isa_irq(irq, bsp_id, IRQ_BASE_OFFSET + irq) {
+            warn!("Couldn't route ISA IRQ {} to the BSP: {}", irq, e);
         }
     }
     Ok(())
 }
 
 
+/// Records the translation given by an interrupt source override entry,
+/// such that the ISA IRQ it describes is later routed to the correct IoApic line
+/// with the correct polarity and trigger mode.
+fn handle_int_src_override(int_src: &MadtIntSrcOverride) -> Result<(), &'static str> {
+    use ioapic::{Polarity, TriggerMode};
+
+    let (gsi, flags) = ({ int_src.gsi }, { int_src.flags });
+    if int_src.bus_source != 0 || ioapic::get_ioapic_for_gsi(gsi).is_none() {
+        error!("MadtIntSrcOverride (bus: {}, irq: {}, gsi: {}, flags {:#X}) not handled by any IoApic!",
+            int_src.bus_source, int_src.irq_source, gsi, flags,
+        );
+        return Ok(());
+    }
+
+    // Bits [1:0] are the polarity and bits [3:2] are the trigger mode.
+    // A value of `0b00` means "conforms to the bus", which for ISA is active high and edge-triggered.
+    let polarity = match flags & 0b11 {
+        0b11 => Polarity::ActiveLow,
+        0b00 | 0b01 => Polarity::ActiveHigh,
+        _reserved => {
+            warn!("MadtIntSrcOverride for irq {} has reserved polarity, using active high", int_src.irq_source);
+            Polarity::ActiveHigh
+        }
+    };
+    let trigger = match (flags >> 2) & 0b11 {
+        0b11 => TriggerMode::Level,
+        0b00 | 0b01 => TriggerMode::Edge,
+        _reserved => {
+            warn!("MadtIntSrcOverride for irq {} has reserved trigger mode, using edge", int_src.irq_source);
+            TriggerMode::Edge
+        }
+    };
+    ioapic::set_isa_irq_override(int_src.irq_source, gsi, polarity, trigger)
+}
+
+
 /// Handles the IOAPIC entries in the given MADT iterator 
 /// by creating IoApic instances for them and initializing them appropriately.
 fn handle_ioapic_entries(madt_iter: MadtIter, page_table: &mut PageTable) -> Result<(), &'static str> {
@@ -54,6 +54,10 @@ pub fn kstart_ap(
     irq_safety::disable_interrupts();
     #[cfg(target_arch = "aarch64")]
     irq_safety::disable_fast_interrupts();
+    // This CPU's ID isn't known until its Local APIC is initialized below,
+    // so ensure nothing mistakes it for another CPU in the meantime.
+    #[cfg(target_arch = "x86_64")]
+    apic::invalidate_current_cpu();
 
     info!("Booted CPU {}, proc: {}, stack: {:#X} to {:#X}, nmi_lint: {}, nmi_flags: {:#X}",
         cpu_id, processor_id, _stack_start, _stack_end, nmi_lint, nmi_flags
@@ -62,10 +66,20 @@ pub fn kstart_ap(
     // set a flag telling the BSP that this AP has entered Rust code
     AP_READY_FLAG.store(true, Ordering::SeqCst);
 
+    // The BSP measures this CPU's TSC offset as soon as it sees the above flag.
+    #[cfg(target_arch = "x86_64")]
+    if let Err(e) = tsc::respond_to_sync() {
+        error!("CPU {} failed to synchronize its TSC with the BSP: {}", cpu_id, e);
+    }
+
     // The early TLS image has already been initialized by the bootstrap CPU,
     // so all we need to do here is to reload it on this CPU.
     early_tls::reload();
 
+    // The kernel's mappings are global, which must be enabled on each CPU.
+    #[cfg(target_arch = "x86_64")]
+    memory::enable_global_pages();
+
     // get the stack that was allocated for us (this AP) by the BSP.
     let this_ap_stack = take_ap_stack(cpu_id.value()).unwrap_or_else(
         || panic!("BUG: kstart_ap(): couldn't get stack created for CPU {}", cpu_id)
@@ -84,7 +98,7 @@ pub fn kstart_ap(
                     .expect("kstart_ap(): could not allocate privilege stack"),
             )
         };
-        let _idt = interrupts::init_ap(cpu_id, double_fault_stack.top_unusable(), privilege_stack.top_unusable())
+        let _idt = interrupts::init_ap(cpu_id, double_fault_stack, privilege_stack)
             .expect("kstart_ap(): failed to initialize interrupts!");
 
         // Initialize this CPU's Local APIC such that we can use everything that depends on APIC IDs.
@@ -109,6 +123,9 @@ pub fn kstart_ap(
         cpu::register_cpu(false).unwrap();
     }
 
+    // Record this CPU's topology before it can be chosen to run any tasks.
+    cpu_topology::detect_current_cpu();
+
     // Now that the Local APIC has been initialized for this CPU, we can initialize the
     // per-CPU storage, tasking, and create the idle task for this CPU.
     cls_allocator::reload_current_cpu();
@@ -9,14 +9,16 @@ use spin::Once;
 use raw_cpuid::CpuId as X86CpuIdInstr;
 use msr::*;
 use sync_irq::IrqSafeRwLock;
-use memory::{PageTable, PhysicalAddress, PteFlags, MappedPages, allocate_pages, allocate_frames_at, AllocatedFrames, BorrowedMappedPages, Mutable};
-use kernel_config::time::CONFIG_TIMESLICE_PERIOD_MICROSECONDS;
+use memory::{PageTable, PhysicalAddress, PteFlags, PteFlagsArch, MemoryType, MappedPages, allocate_pages, allocate_frames_at, AllocatedFrames, BorrowedMappedPages, Mutable};
 use atomic_linked_list::atomic_map::AtomicMap;
 use crossbeam_utils::atomic::AtomicCell;
 use pit_clock_basic::pit_wait;
 use bit_field::BitField;
 use log::{error, info, debug, trace};
 
+#[cfg(test)]
+mod test;
+
 // Values for the `IA32_APIC_BASE` MSR.
 const IA32_APIC_IS_BSP:                u64 = 1 << 8;
 const IA32_APIC_XAPIC_ENABLE:          u64 = 1 << 11;
@@ -54,6 +56,7 @@ impl TryFrom<u32> for ApicId {
         let apic_id = ApicId(raw_apic_id);
         match get_lapics().get(&apic_id) {
             Some(_) => Ok(apic_id),
+            None if pic_fallback_reason().is_some() && bootstrap_cpu() == Some(apic_id) => Ok(apic_id),
             None => Err(raw_apic_id),
         }
     }
@@ -95,9 +98,21 @@ pub fn bootstrap_cpu() -> Option<ApicId> {
 /// Returns true if the currently executing CPU is the bootstrap CPU, 
 /// i.e., the first procesor to run after system power-on.
 pub fn is_bootstrap_cpu() -> bool {
+    // In PIC mode, the bootstrap CPU is the only CPU running,
+    // and the `IA32_APIC_BASE` MSR may not even exist.
+    if pic_fallback_reason().is_some() {
+        return true;
+    }
     rdmsr(IA32_APIC_BASE) & IA32_APIC_IS_BSP == IA32_APIC_IS_BSP
 }
 
+/// Returns true if this CPU reports that it has a Local APIC.
+pub fn has_apic() -> bool {
+    X86CpuIdInstr::new()
+        .get_feature_info()
+        .is_some_and(|info| info.has_apic())
+}
+
 /// Returns true if the machine has support for x2apic
 pub fn has_x2apic() -> bool {
     static IS_X2APIC: Once<bool> = Once::new(); // cache the result
@@ -115,6 +130,15 @@ pub fn get_lapics() -> &'static AtomicMap<ApicId, IrqSafeRwLock<LocalApic>> {
 	&LOCAL_APICS
 }
 
+/// Returns an iterator over the IDs of all initialized CPUs.
+///
+/// In PIC mode (see [`init_pic_mode()`]), there are no Local APICs,
+/// so this only yields the bootstrap CPU.
+pub fn cpu_ids() -> impl Iterator<Item = ApicId> {
+    let pic_mode_cpu = bootstrap_cpu().filter(|_| pic_fallback_reason().is_some());
+    LOCAL_APICS.iter().map(|(apic_id, _)| *apic_id).chain(pic_mode_cpu)
+}
+
 /// Returns the number of CPUs (SMP cores) that exist 
 /// and are currently initialized on this system.
 #[doc(alias("cores", "numcpus"))]
@@ -122,12 +146,47 @@ pub fn cpu_count() -> u32 {
     CPU_COUNT.load(Ordering::Relaxed)
 }
 
+/// The ID that [`current_cpu()`] returns on a CPU whose ID isn't known yet.
+///
+/// This is the x2APIC broadcast ID, which is never assigned to an actual CPU.
+pub const INVALID_APIC_ID: ApicId = ApicId(u32::MAX);
+
 /// Returns the ID of the currently executing CPU.
+///
+/// Theseus stores each CPU's ID in its `IA32_TSC_AUX` MSR once that CPU's Local APIC
+/// has been initialized, or once the BSP has fallen back to PIC mode.
+/// Before that point in an AP's bringup, this returns [`INVALID_APIC_ID`]
+/// (see [`invalidate_current_cpu()`]), which is not the ID of any CPU.
+///
+/// Thus, per-CPU state must not be indexed by this ID without a bounds check:
+/// use [`try_current_cpu()`], or a lookup like `slice::get()` that misses for [`INVALID_APIC_ID`],
+/// and then skip or defer the operation rather than using another CPU's state.
 pub fn current_cpu() -> ApicId {
     ApicId(rdmsr(IA32_TSC_AUX) as u32)
 }
 
+/// Returns the ID of the currently executing CPU, or `None` if it isn't known yet.
+///
+/// See [`current_cpu()`].
+pub fn try_current_cpu() -> Option<ApicId> {
+    Some(current_cpu()).filter(|&id| id != INVALID_APIC_ID)
+}
+
+/// Marks the ID of the currently executing CPU as unknown
+/// until its Local APIC is initialized via [`LocalApic::init()`].
+///
+/// When a CPU starts, its `IA32_TSC_AUX` MSR holds whatever value the firmware left in it,
+/// which is usually 0 and thus the same as the BSP's ID.
+/// Each AP must invoke this before doing anything that queries [`current_cpu()`],
+/// such that it doesn't mistakenly operate on the BSP's per-CPU state.
+pub fn invalidate_current_cpu() {
+    // SAFETY: Theseus only uses this MSR to hold the current CPU's ID.
+    unsafe { wrmsr(IA32_TSC_AUX, INVALID_APIC_ID.0 as u64); }
+}
+
 /// Returns a reference to the LocalApic for the currently executing CPU core.
+///
+/// Returns `None` if this CPU's Local APIC hasn't been initialized yet, or in PIC mode.
 pub fn get_my_apic() -> Option<&'static IrqSafeRwLock<LocalApic>> {
     LOCAL_APICS.get(&current_cpu())
 }
@@ -224,6 +283,55 @@ impl LapicIpiDestination {
 }
 
 
+/// The reason why this system fell back to the legacy PIC, if it did.
+static PIC_FALLBACK_REASON: Once<&'static str> = Once::new();
+
+/// Returns the reason why this system fell back to using the legacy PIC
+/// instead of the Local APIC and IOAPIC, or `None` if it did not.
+///
+/// See [`init_pic_mode()`].
+pub fn pic_fallback_reason() -> Option<&'static str> {
+    PIC_FALLBACK_REASON.get().copied()
+}
+
+/// Falls back to using the legacy PIC instead of the Local APIC and IOAPIC
+/// for the given `reason`, e.g., because the APIC is absent or unresponsive.
+///
+/// This must be invoked on the BSP instead of [`LocalApic::init()`].
+/// The Local APIC is hardware-disabled (if it exists), such that the PIC's interrupts
+/// are delivered directly to this CPU, which is registered as the bootstrap CPU.
+/// Because other CPUs cannot be started or sent IPIs without a Local APIC,
+/// the BSP is the only CPU that will ever run, i.e., `nosmp` is implied.
+pub fn init_pic_mode(reason: &'static str) -> Result<(), &'static str> {
+    if bootstrap_cpu().is_some() {
+        return Err("cannot fall back to PIC mode after the BSP's Local APIC was initialized");
+    }
+    if PIC_FALLBACK_REASON.is_completed() {
+        return Err("already fell back to PIC mode");
+    }
+
+    let apic_id = if has_apic() {
+        // Clearing both enable bits at once is a valid transition from either xapic or x2apic mode.
+        unsafe { wrmsr(IA32_APIC_BASE, rdmsr(IA32_APIC_BASE) & !(IA32_APIC_XAPIC_ENABLE | IA32_APIC_X2APIC_ENABLE)); }
+        X86CpuIdInstr::new()
+            .get_feature_info()
+            .map_or(0, |info| info.initial_local_apic_id() as u32)
+    } else {
+        0
+    };
+    let apic_id = ApicId(apic_id);
+
+    // Theseus uses this MSR to hold each CPU's ID (which is an OS-chosen value).
+    unsafe { wrmsr(IA32_TSC_AUX, apic_id.0 as u64); }
+    BSP_PROCESSOR_ID.call_once(|| apic_id);
+    INTERRUPT_CHIP.store(InterruptChip::PIC);
+    PIC_FALLBACK_REASON.call_once(|| reason);
+    CPU_COUNT.store(1, Ordering::Relaxed);
+    info!("Initialized CPU {} in PIC mode", apic_id);
+    Ok(())
+}
+
+
 /// Determines whether this system contains an xapic or x2apic
 /// and enables the Local APIC hardware in the correct mode.
 pub fn init() {
@@ -260,7 +368,7 @@ fn map_apic(page_table: &mut PageTable) -> Result<MappedPages, &'static str> {
             page_table,
             new_page,
             frame,
-            PteFlags::new().valid(true).writable(true).device_memory(true),
+            PteFlagsArch::from(PteFlags::new().valid(true).writable(true)).memory_type(MemoryType::Uncacheable),
         )
     }
 }
@@ -355,6 +463,21 @@ pub struct RegisterArray {
     _padding7:                        [u32; 3],
 }
 const _: () = assert!(core::mem::size_of::<RegisterArray>() == 8 * (4 + 12));
+impl RegisterArray {
+    /// Reads the register at the given `index`, which must be from 0 to 7.
+    fn read(&self, index: usize) -> u32 {
+        match index {
+            0 => self.reg0.read(),
+            1 => self.reg1.read(),
+            2 => self.reg2.read(),
+            3 => self.reg3.read(),
+            4 => self.reg4.read(),
+            5 => self.reg5.read(),
+            6 => self.reg6.read(),
+            _ => self.reg7.read(),
+        }
+    }
+}
 
 /// The Local APIC's vector table local interrupt pins.
 #[doc(alias("lvt", "lint", "lint0", "lint1"))]
@@ -408,6 +531,9 @@ pub enum LapicInitError {
     MemoryMappingError(&'static str),
     /// The Local APIC already existed (BUG), given by the included `ApicId`.
     AlreadyExisted(ApicId),
+    /// The Local APIC didn't respond sanely once enabled,
+    /// e.g., its version register read as the included invalid value.
+    Unresponsive(u32),
 }
 
 
@@ -426,6 +552,9 @@ pub struct LocalApic {
     /// The value that should be written to the APIC timer's initial count register
     /// when enabling the LVT timer.
     initial_timer_count: u32,
+    /// The initial count that was calibrated to one timeslice period,
+    /// before any drift correction was applied; see [`LocalApic::set_timer_correction()`].
+    calibrated_timer_count: u32,
 }
 impl fmt::Debug for LocalApic {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -518,12 +647,25 @@ impl LocalApic {
         // Enable the xapic/x2apic hardware.
         unsafe { wrmsr(IA32_APIC_BASE, rdmsr(IA32_APIC_BASE) | enable_bitmask); }
 
+        // Ensure the Local APIC actually responds before we rely upon it,
+        // as its registers read as all zeros or all ones on some broken hardware and VMs.
+        // All integrated (non-82489DX) Local APICs have a version of `0x1X`.
+        let version = match &inner {
+            LapicType::X2Apic => (rdmsr(IA32_X2APIC_VERSION) & 0xFFFF_FFFF) as u32,
+            LapicType::XApic(regs) => regs.lapic_version.read(),
+        };
+        if version & 0xF0 != 0x10 {
+            error!("Local APIC is unresponsive: its version register read as {:#X}", version);
+            return Err(LapicInitError::Unresponsive(version));
+        }
+
 		let mut lapic = LocalApic {
             inner,
             processor_id,
-            apic_id: ApicId(u32::MAX), // placeholder, is replaced below.
+            apic_id: INVALID_APIC_ID, // placeholder, is replaced below.
             is_bootstrap_cpu,
             initial_timer_count: 0, // set in `calibrate_lapic_timer()`
+            calibrated_timer_count: 0, // set in `calibrate_lapic_timer()`
         };
 
         // Now that the APIC hardware is enabled, we can safely obtain this Local APIC's ID.
@@ -663,10 +805,11 @@ impl LocalApic {
             info!("apic_timer_fixed config: overriding LocalAPIC LVT timer period to {}", 1000000);
             1000000 // for bochs, which doesn't do apic periods right
         } else {
-            self.calibrate_lapic_timer(CONFIG_TIMESLICE_PERIOD_MICROSECONDS)
+            self.calibrate_lapic_timer(boot_args::timeslice_period().as_micros() as u32)
         };
         trace!("LocalApic {}, timer period count: {} ({:#X})", self.apic_id, apic_period, apic_period);
         self.initial_timer_count = apic_period;
+        self.calibrated_timer_count = apic_period;
 
         match &mut self.inner {
             LapicType::X2Apic => unsafe {
@@ -730,6 +873,32 @@ impl LocalApic {
         }
     }
 
+    /// Returns the APIC timer's initial count, i.e., the number of APIC timer ticks
+    /// in each period of its periodic interrupt, including any drift correction.
+    pub fn timer_period_count(&self) -> u32 { self.initial_timer_count }
+
+    /// Lengthens (if positive) or shortens (if negative) the APIC timer's period
+    /// by `ppm` parts per million relative to its calibrated period,
+    /// e.g., to compensate for calibration error measured against a more accurate clock.
+    ///
+    /// This replaces any previous correction. The new period starts immediately,
+    /// so the current period is cut short or extended once.
+    ///
+    /// Returns the new initial count of the APIC timer.
+    pub fn set_timer_correction(&mut self, ppm: i32) -> u32 {
+        let count = self.calibrated_timer_count as i64 * (1_000_000 + ppm as i64) / 1_000_000;
+        self.initial_timer_count = count.clamp(1, u32::MAX as i64) as u32;
+        match &mut self.inner {
+            LapicType::X2Apic => unsafe {
+                wrmsr(IA32_X2APIC_INIT_COUNT, self.initial_timer_count as u64);
+            }
+            LapicType::XApic(regs) => {
+                regs.timer_initial_count.write(self.initial_timer_count);
+            }
+        }
+        self.initial_timer_count
+    }
+
     /// Returns the ID of this Local APIC (fast).
     /// 
     /// Unlike [`LocalApic::read_apic_id()`], this does not read any hardware registers.
@@ -793,12 +962,16 @@ impl LocalApic {
             LapicType::X2Apic => unsafe { wrmsr(IA32_X2APIC_ICR, value) },
             LapicType::XApic(regs) => {
                 const ICR_DELIVERY_STATUS: u32 = 1 << 12;
-                while regs.interrupt_command_low.read() & ICR_DELIVERY_STATUS == ICR_DELIVERY_STATUS {} // wait until ready
+                while regs.interrupt_command_low.read() & ICR_DELIVERY_STATUS == ICR_DELIVERY_STATUS { // wait until ready
+                    core::hint::spin_loop();
+                }
                 let high = (value >> 32) as u32;
                 regs.interrupt_command_high.write(high); // sets part of ICR register, but doesn't yet issue the IPI
                 let low = value as u32;
                 regs.interrupt_command_low.write(low); // this actually issues the IPI
-                while regs.interrupt_command_low.read() & ICR_DELIVERY_STATUS == ICR_DELIVERY_STATUS {} // wait until finished
+                while regs.interrupt_command_low.read() & ICR_DELIVERY_STATUS == ICR_DELIVERY_STATUS { // wait until finished
+                    core::hint::spin_loop();
+                }
             }
         }
     }
@@ -837,6 +1010,45 @@ impl LocalApic {
         }
     }
 
+    /// Returns the priority class in this lapic's Task Priority Register (TPR).
+    ///
+    /// Interrupts whose [`priority_class()`] is less than or equal to this class
+    /// are held pending until the TPR is lowered; see [`LocalApic::set_tpr()`].
+    pub fn get_tpr(&self) -> u8 {
+        let raw = match &self.inner {
+            LapicType::X2Apic => rdmsr(IA32_X2APIC_TPR) as u32,
+            LapicType::XApic(regs) => regs.task_priority.read(),
+        };
+        ((raw >> 4) & 0xF) as u8
+    }
+
+    /// Sets the priority class in this lapic's Task Priority Register (TPR),
+    /// which defers all interrupts at or below that class on this CPU.
+    ///
+    /// An interrupt's priority class is the upper 4 bits of its vector, i.e., `vector >> 4`,
+    /// as returned by [`priority_class()`]. This lapic only delivers an interrupt
+    /// if its class is *greater* than the TPR class; others stay pending in the IRR
+    /// until the TPR is lowered again. Thus:
+    /// * a class of `0` allows all interrupts, which is the default,
+    /// * a class of `priority_class(vector)` defers `vector` and every vector below it,
+    ///   e.g., a handler for vector `v` can defer all other interrupts of its own or lower class,
+    /// * a class of `0xF` defers all maskable interrupts.
+    ///
+    /// Because the TPR only compares classes, vectors that must not defer each other
+    /// should be allocated in different classes, i.e., at least 16 vectors apart.
+    /// NMIs, SMIs, INITs, and exceptions are never affected by the TPR.
+    ///
+    /// Only the lower 4 bits of `priority_class` are used.
+    pub fn set_tpr(&mut self, priority_class: u8) {
+        debug_assert!(priority_class <= 0xF, "invalid TPR priority class {priority_class:#X}");
+        // The lower 4 bits are the priority sub-class, which doesn't affect interrupt delivery.
+        let value = ((priority_class & 0xF) as u32) << 4;
+        match &mut self.inner {
+            LapicType::X2Apic => unsafe { wrmsr(IA32_X2APIC_TPR, value as u64) },
+            LapicType::XApic(regs) => regs.task_priority.write(value),
+        }
+    }
+
     /// Set the NonMaskableInterrupt redirect for this LocalApic.
     ///
     /// Argument `lint` can be either 0 or 1, since each local APIC has two LVT LINTs
@@ -854,7 +1066,9 @@ impl LocalApic {
 
     /// Returns the values of the 8 in-service registers for this APIC,
     /// which is a series of bitmasks that shows which interrupt lines are currently being serviced. 
-    pub fn get_isr(&self) -> [u32; 8] {
+    ///
+    /// Use [`asserted_vectors()`] to obtain the vector numbers whose bits are set.
+    pub fn read_isr(&self) -> [u32; 8] {
         match &self.inner {
             LapicType::X2Apic => [
                 rdmsr(IA32_X2APIC_ISR0) as u32, 
@@ -879,10 +1093,30 @@ impl LocalApic {
         }
     }
 
+    /// Returns the highest interrupt vector that is currently in service on this APIC,
+    /// i.e., the vector of the interrupt currently being handled, if any.
+    ///
+    /// Unlike [`LocalApic::read_isr()`], this stops reading at the first non-zero
+    /// in-service register, starting from the highest vectors.
+    pub fn highest_in_service_vector(&self) -> Option<u8> {
+        for index in (0..8).rev() {
+            let bits = match &self.inner {
+                LapicType::X2Apic => rdmsr(IA32_X2APIC_ISR0 + index as u32) as u32,
+                LapicType::XApic(regs) => regs.in_service_registers.read(index),
+            };
+            if bits != 0 {
+                return Some((index * 32 + 31 - bits.leading_zeros() as usize) as u8);
+            }
+        }
+        None
+    }
+
     /// Returns the values of the 8 request registers for this APIC,
     /// which is a series of bitmasks that shows which interrupt lines are currently raised, 
     /// but not yet being serviced.
-    pub fn get_irr(&self) -> [u32; 8] {
+    ///
+    /// Use [`LocalApic::pending_vectors()`] to obtain the vector numbers whose bits are set.
+    pub fn read_irr(&self) -> [u32; 8] {
         match &self.inner {
             LapicType::X2Apic => [ 
                 rdmsr(IA32_X2APIC_IRR0) as u32, 
@@ -907,6 +1141,14 @@ impl LocalApic {
         }
     }
 
+    /// Returns an iterator over the vectors of all interrupts that are pending on this APIC,
+    /// i.e., raised but not yet being serviced, in ascending order.
+    ///
+    /// This is a snapshot of the request registers at the time this is called.
+    pub fn pending_vectors(&self) -> impl Iterator<Item = u8> + Clone {
+        asserted_vectors(self.read_irr())
+    }
+
     /// Clears the interrupt mask bit in the apic performance monitor register.
     pub fn clear_pmi_mask(&mut self) {
         // The 16th bit is set to 1 whenever a performance monitoring interrupt occurs. 
@@ -928,6 +1170,24 @@ impl LocalApic {
     }
 }
 
+/// Returns the priority class of the given interrupt `vector`, i.e., its upper 4 bits,
+/// which determines whether it can be delivered under the current [TPR](LocalApic::set_tpr()).
+///
+/// Higher classes have higher priority; vectors within the same class are
+/// prioritized by their lower 4 bits, but only relative to each other.
+pub const fn priority_class(vector: u8) -> u8 {
+    vector >> 4
+}
+
+/// Returns an iterator over the vector numbers whose bits are set in the given
+/// 256-bit APIC register bitmap, such as one returned by [`LocalApic::read_isr()`]
+/// or [`LocalApic::read_irr()`], in ascending order.
+///
+/// Bit `n` of `registers[i]` corresponds to vector `i * 32 + n`.
+pub fn asserted_vectors(registers: [u32; 8]) -> impl Iterator<Item = u8> + Clone {
+    (0..=u8::MAX).filter(move |&vector| registers[vector as usize / 32].get_bit(vector as usize % 32))
+}
+
 // Below: temporary functions for reading MSRs that aren't yet in the `x86_64` crate.
 
 fn rdmsr(msr: u32) -> u64 {
@@ -0,0 +1,38 @@
+//! Tests decoding of the 256-bit ISR/IRR register bitmaps into vector numbers,
+//! and of vectors into their TPR priority classes.
+
+extern crate std;
+
+use super::{asserted_vectors, priority_class};
+use std::vec::Vec;
+
+#[test]
+fn no_vectors_asserted() {
+    assert_eq!(asserted_vectors([0; 8]).count(), 0);
+}
+
+#[test]
+fn vectors_in_ascending_order() {
+    let mut registers = [0u32; 8];
+    registers[0] = 1 << 0;
+    registers[1] = (1 << 0) | (1 << 2); // vectors 0x20 and 0x22
+    registers[7] = 1 << 31;
+    let vectors: Vec<u8> = asserted_vectors(registers).collect();
+    assert_eq!(vectors, [0x00, 0x20, 0x22, 0xFF]);
+}
+
+#[test]
+fn all_vectors_asserted() {
+    let vectors: Vec<u8> = asserted_vectors([u32::MAX; 8]).collect();
+    assert_eq!(vectors.len(), 256);
+    assert!(vectors.iter().enumerate().all(|(i, &v)| i == v as usize));
+}
+
+#[test]
+fn priority_classes() {
+    assert_eq!(priority_class(0x00), 0x0);
+    assert_eq!(priority_class(0x20), 0x2);
+    assert_eq!(priority_class(0x2F), 0x2);
+    assert_eq!(priority_class(0x30), 0x3);
+    assert_eq!(priority_class(0xFF), 0xF);
+}
@@ -14,13 +14,38 @@
 extern crate alloc;
 extern crate logger;
 
-use alloc::{format, sync::Arc};
+use alloc::{format, string::String, sync::Arc, vec::Vec};
 use core2::io::{self, Error, ErrorKind, Read, Write};
 use stdio::{StdioReader, StdioWriter};
 use tty::{LineDiscipline, Slave};
 
 pub trait ImmutableRead: Send + Sync + 'static {
     fn read(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Reads the bytes that are immediately available without blocking,
+    /// returning `Ok(0)` if there are none.
+    fn try_read(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Reads a line, blocking until a line feed is read, and returns it without
+    /// its trailing line feed.
+    ///
+    /// If the end of the stream is reached first, the partial line is returned,
+    /// or an error if it's empty.
+    fn read_line(&self) -> io::Result<String> {
+        let mut line = Vec::new();
+        let mut byte = [0];
+        loop {
+            match self.read(&mut byte)? {
+                0 if line.is_empty() => {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "no more lines"));
+                }
+                0 => break,
+                _ if byte[0] == b'\n' => break,
+                _ => line.push(byte[0]),
+            }
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
 }
 
 pub trait ImmutableWrite: Send + Sync + 'static {
@@ -48,6 +73,10 @@ impl ImmutableRead for StdioReader {
     fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
         self.lock().read(buf)
     }
+
+    fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock().try_read(buf)
+    }
 }
 
 impl ImmutableWrite for StdioWriter {
@@ -60,6 +89,14 @@ impl ImmutableRead for Slave {
     fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
         self.read(buf)
     }
+
+    fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.try_read(buf)
+    }
+
+    fn read_line(&self) -> io::Result<String> {
+        self.read_line()
+    }
 }
 
 impl ImmutableWrite for Slave {
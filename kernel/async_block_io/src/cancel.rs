@@ -0,0 +1,163 @@
+//! Cancellation of block I/O requests and of the larger operations built from them.
+//!
+//! A [`CancellationToken`] is given to each request when its [`CompletionHandle`] is created
+//! via [`CompletionHandle::with_token()`], and can be shared by any number of requests.
+//! Cancelling the token marks all of them as cancelled, after which:
+//! * a driver completes any of them that it hasn't yet issued to its device
+//!   with [`IoError::Cancelled`] instead of issuing them,
+//! * a driver runs its device-specific abort path for those that are in flight,
+//!   as registered via [`CompletionHandle::on_cancel()`], and then completes them,
+//! * an operation that issues many requests, e.g., [`read_range()`](crate::read_range),
+//!   stops issuing new requests, waits for its outstanding ones, and returns [`IoError::Cancelled`].
+//!
+//! A request still completes exactly once, whether or not cancellation races with its
+//! normal completion, so the submitter always gets its buffer back.
+//!
+//! A token obtained from [`CancellationToken::for_current_task()`] is cancelled automatically
+//! if its task exits or is killed while the token is still alive.
+//! As a killed task never claims the results of its requests, those results, including their
+//! buffers, are dropped as soon as the requests complete instead of being leaked.
+//! During shutdown, [`cancel_all()`] cancels every live token.
+
+use alloc::{boxed::Box, sync::{Arc, Weak}, vec::Vec};
+use core::{fmt, sync::atomic::{AtomicBool, Ordering}};
+use io::IoError;
+use spin::Mutex;
+use sync_irq::IrqSafeMutex;
+use task::{CleanupGuard, CleanupReason};
+use crate::Completion;
+
+/// A hook that aborts an in-flight request on its device once the request is cancelled.
+pub type CancelHook = Box<dyn FnOnce() + Send>;
+
+/// Every token that has been created and not yet dropped, used by [`cancel_all()`].
+static TOKENS: IrqSafeMutex<Vec<Weak<TokenInner>>> = IrqSafeMutex::new(Vec::new());
+
+struct TokenInner {
+    cancelled: AtomicBool,
+    /// Whether nobody will ever claim the results of this token's requests,
+    /// because the task that owned it was killed.
+    abandoned: AtomicBool,
+    /// This token's requests, some of which may have already completed.
+    requests: IrqSafeMutex<Vec<Weak<Completion>>>,
+    /// The cleanup hook that cancels this token if its task exits while the token is alive.
+    cleanup: Mutex<Option<CleanupGuard>>,
+}
+
+/// A shareable token that cancels all of the block I/O requests and operations it was given to.
+///
+/// Drivers must complete a cancelled request that they haven't yet issued to their device
+/// with [`IoError::Cancelled`], and abort one that is in flight via the hook registered with
+/// [`CompletionHandle::on_cancel()`](crate::CompletionHandle::on_cancel), if their device supports it.
+/// Operations that issue many requests must stop issuing new ones once their token is cancelled.
+#[derive(Clone)]
+pub struct CancellationToken(Arc<TokenInner>);
+
+impl CancellationToken {
+    /// Creates a new token that hasn't been cancelled.
+    pub fn new() -> CancellationToken {
+        let inner = Arc::new(TokenInner {
+            cancelled: AtomicBool::new(false),
+            abandoned: AtomicBool::new(false),
+            requests: IrqSafeMutex::new(Vec::new()),
+            cleanup: Mutex::new(None),
+        });
+        let mut tokens = TOKENS.lock();
+        tokens.retain(|t| t.strong_count() > 0);
+        tokens.push(Arc::downgrade(&inner));
+        drop(tokens);
+        CancellationToken(inner)
+    }
+
+    /// Creates a new token that is cancelled if the current task exits or is killed
+    /// before every clone of the token has been dropped.
+    ///
+    /// If the current task can't be obtained, e.g., during early boot,
+    /// the token is only cancelled explicitly.
+    pub fn for_current_task() -> CancellationToken {
+        let token = CancellationToken::new();
+        let weak = Arc::downgrade(&token.0);
+        let guard = CleanupGuard::new(Box::new(move |reason: CleanupReason| {
+            if let Some(inner) = weak.upgrade() {
+                let token = CancellationToken(inner);
+                if reason == CleanupReason::Killed {
+                    token.0.abandoned.store(true, Ordering::Release);
+                }
+                token.cancel();
+            }
+        }));
+        *token.0.cleanup.lock() = guard;
+        token
+    }
+
+    /// Cancels all of this token's requests, both current and future,
+    /// running the abort hook of each one that is in flight.
+    ///
+    /// Cancelling a token more than once has no further effect.
+    pub fn cancel(&self) {
+        if self.0.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let requests = core::mem::take(&mut *self.0.requests.lock());
+        for completion in requests.iter().filter_map(Weak::upgrade) {
+            completion.cancel();
+        }
+    }
+
+    /// Returns `true` if this token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Returns [`IoError::Cancelled`] if this token has been cancelled.
+    ///
+    /// Long-running operations should call this between the requests they issue.
+    pub fn check(&self) -> Result<(), IoError> {
+        if self.is_cancelled() {
+            Err(IoError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns `true` if the results of this token's requests will never be claimed.
+    pub(crate) fn is_abandoned(&self) -> bool {
+        self.0.abandoned.load(Ordering::Acquire)
+    }
+
+    /// Associates the given request with this token, such that cancelling this token cancels it.
+    pub(crate) fn register(&self, completion: &Arc<Completion>) {
+        let mut requests = self.0.requests.lock();
+        requests.retain(|c| c.strong_count() > 0);
+        requests.push(Arc::downgrade(completion));
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Cancels every live [`CancellationToken`], e.g., when quiescing storage devices for shutdown.
+///
+/// Returns the number of tokens that were newly cancelled.
+pub fn cancel_all() -> usize {
+    let tokens: Vec<_> = TOKENS.lock().iter().filter_map(Weak::upgrade).collect();
+    let mut cancelled = 0;
+    for token in tokens.into_iter().map(CancellationToken) {
+        if !token.is_cancelled() {
+            token.cancel();
+            cancelled += 1;
+        }
+    }
+    cancelled
+}
@@ -0,0 +1,568 @@
+//! Asynchronous submission and completion of block device I/O requests.
+//!
+//! The [`BlockReader`] and [`BlockWriter`] traits are synchronous: the caller blocks
+//! for the entire transfer, so only one request per device can ever be in flight.
+//! Devices that implement [`AsyncBlockDevice`] instead accept requests that complete later,
+//! which allows a caller to keep multiple requests outstanding on devices that support it.
+//!
+//! Each request carries a [`DmaBuffer`] for the data and a [`CompletionHandle`],
+//! which the driver signals once the transfer has finished.
+//! The submitter can then block on that handle, poll it, or register a callback on it.
+//!
+//! Interrupt-driven drivers should track their outstanding requests in a [`RequestTable`],
+//! which assigns each request a tag that the driver can pass to its device
+//! and later use to complete the request.
+//! Requests must be completed from task context, typically the driver's deferred
+//! interrupt task (see the `deferred_interrupt_tasks` crate), not the interrupt handler itself,
+//! because completing a request may wake up tasks and run its callback.
+//!
+//! Requests can be cancelled via the [`CancellationToken`] they were created with,
+//! e.g., because the task that submitted them was killed or the system is shutting down.
+//! A cancelled request still completes exactly once, with [`IoError::Cancelled`]
+//! unless it had already finished on the device.
+//!
+//! [`BlockReader`]: io::BlockReader
+//! [`BlockWriter`]: io::BlockWriter
+
+#![no_std]
+
+extern crate alloc;
+
+mod cancel;
+pub use cancel::{cancel_all, CancelHook, CancellationToken};
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use dma_buffer::{DmaBuffer, DmaDirection, DmaOwner};
+use io::{BlockIo, IoError};
+use log::error;
+use spin::Mutex;
+use sync_irq::IrqSafeMutex;
+use wait_queue::WaitQueue;
+
+/// The result of a block I/O request.
+///
+/// The request's buffer is returned to the caller whether or not it succeeded.
+pub type BlockIoResult = Result<DmaBuffer, (IoError, DmaBuffer)>;
+
+/// A callback that is invoked with the result of a block I/O request once it completes.
+pub type CompletionCallback = Box<dyn FnOnce(BlockIoResult) + Send>;
+
+/// A block storage device that accepts I/O requests which complete asynchronously.
+///
+/// Both functions below return as soon as the request has been submitted to the device.
+/// If submission fails, the given `completion` is never signaled
+/// and the buffer is returned along with the error.
+pub trait AsyncBlockDevice: BlockIo {
+    /// Returns the maximum number of requests that can be outstanding on this device at once.
+    fn queue_depth(&self) -> usize;
+
+    /// Submits a request to read blocks from this device, starting at `block_offset`,
+    /// into the given `buffer`, whose length determines the number of blocks to read.
+    fn submit_read(
+        &mut self,
+        block_offset: usize,
+        buffer: DmaBuffer,
+        completion: CompletionHandle,
+    ) -> Result<(), (IoError, DmaBuffer)>;
+
+    /// Submits a request to write the contents of the given `buffer` to this device,
+    /// starting at `block_offset`.
+    fn submit_write(
+        &mut self,
+        block_offset: usize,
+        buffer: DmaBuffer,
+        completion: CompletionHandle,
+    ) -> Result<(), (IoError, DmaBuffer)>;
+}
+
+
+/// The number of [`CompletionHandle`]s whose requests haven't yet completed.
+static PENDING_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of block I/O requests that haven't yet completed,
+/// including those whose completion handles were created but not yet submitted.
+///
+/// This can be used to check that requests aren't leaked, e.g., after cancelling them.
+pub fn pending_requests() -> usize {
+    PENDING_REQUESTS.load(Ordering::Relaxed)
+}
+
+enum CompletionState {
+    /// The request hasn't completed yet; it may have a callback to run once it does,
+    /// and a hook that aborts it on its device if it's cancelled.
+    Pending {
+        callback: Option<CompletionCallback>,
+        on_cancel: Option<CancelHook>,
+    },
+    /// The request has completed; its result is `None` once it has been claimed.
+    Complete(Option<BlockIoResult>),
+}
+
+struct Completion {
+    state: IrqSafeMutex<CompletionState>,
+    waiters: WaitQueue,
+    token: Option<CancellationToken>,
+}
+
+impl Completion {
+    /// Runs this request's abort hook, if it hasn't yet completed; see [`CancellationToken::cancel()`].
+    fn cancel(&self) {
+        let on_cancel = match &mut *self.state.lock() {
+            CompletionState::Pending { on_cancel, .. } => on_cancel.take(),
+            CompletionState::Complete(_) => None,
+        };
+        if let Some(on_cancel) = on_cancel {
+            on_cancel();
+        }
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        if matches!(self.state.get_mut(), CompletionState::Pending { .. }) {
+            PENDING_REQUESTS.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A shareable handle that is signaled once a block I/O request completes.
+///
+/// The result of the request is delivered exactly once: either to the callback
+/// registered with [`on_complete()`](Self::on_complete), or to the first caller of
+/// [`poll()`](Self::poll) or [`wait()`](Self::wait) after the request completes.
+#[derive(Clone)]
+pub struct CompletionHandle(Arc<Completion>);
+
+impl CompletionHandle {
+    /// Creates a new handle for a request that hasn't yet been submitted and can't be cancelled.
+    pub fn new() -> CompletionHandle {
+        Self::create(None)
+    }
+
+    /// Creates a new handle for a request that hasn't yet been submitted,
+    /// which is cancelled along with the given `token`.
+    pub fn with_token(token: &CancellationToken) -> CompletionHandle {
+        let handle = Self::create(Some(token.clone()));
+        token.register(&handle.0);
+        handle
+    }
+
+    fn create(token: Option<CancellationToken>) -> CompletionHandle {
+        PENDING_REQUESTS.fetch_add(1, Ordering::Relaxed);
+        CompletionHandle(Arc::new(Completion {
+            state: IrqSafeMutex::new(CompletionState::Pending { callback: None, on_cancel: None }),
+            waiters: WaitQueue::new(),
+            token,
+        }))
+    }
+
+    /// Returns the token that this request is cancelled with, if any.
+    pub fn token(&self) -> Option<&CancellationToken> {
+        self.0.token.as_ref()
+    }
+
+    /// Returns `true` if this request has been cancelled.
+    ///
+    /// A driver should complete a cancelled request that it hasn't yet issued to its device
+    /// with [`IoError::Cancelled`] instead of issuing it.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.token.as_ref().map_or(false, CancellationToken::is_cancelled)
+    }
+
+    /// Registers a `hook` that aborts this request on its device once it's cancelled,
+    /// replacing any previously-registered hook.
+    ///
+    /// A driver should register this once it has issued the request to its device,
+    /// if that device supports aborting it.
+    /// The hook is invoked at most once, by the task that cancels the request,
+    /// or immediately by the current task if the request has already been cancelled.
+    /// It isn't invoked once the request has completed.
+    /// The hook itself must not complete the request unless it's certain that
+    /// the device won't also complete it; see [`RequestTable::complete_cancelled()`].
+    pub fn on_cancel(&self, hook: CancelHook) {
+        let mut state = self.0.state.lock();
+        if let CompletionState::Pending { on_cancel, .. } = &mut *state {
+            if self.is_cancelled() {
+                drop(state);
+                hook();
+            } else {
+                *on_cancel = Some(hook);
+            }
+        }
+    }
+
+    /// Returns `true` if the request has completed, even if its result has already been claimed.
+    pub fn is_complete(&self) -> bool {
+        matches!(*self.0.state.lock(), CompletionState::Complete(_))
+    }
+
+    /// Returns the result of the request if it has completed and its result hasn't yet been claimed.
+    ///
+    /// This never blocks.
+    pub fn poll(&self) -> Option<BlockIoResult> {
+        match &mut *self.0.state.lock() {
+            CompletionState::Complete(result) => result.take(),
+            CompletionState::Pending { .. } => None,
+        }
+    }
+
+    /// Blocks the current task until the request completes, and then returns its result.
+    ///
+    /// This must not be called on a handle that has a callback registered,
+    /// as the callback claims the result and this would block forever.
+    pub fn wait(&self) -> BlockIoResult {
+        self.0.waiters.wait_until(|| self.poll())
+    }
+
+    /// Registers a `callback` to be invoked with the result of the request once it completes,
+    /// replacing any previously-registered callback.
+    ///
+    /// The callback runs in the context of the task that completes the request,
+    /// so it must not block for long.
+    /// If the request has already completed, the callback is invoked immediately
+    /// by the current task.
+    pub fn on_complete(&self, callback: CompletionCallback) {
+        let mut state = self.0.state.lock();
+        match &mut *state {
+            CompletionState::Pending { callback: cb, .. } => *cb = Some(callback),
+            CompletionState::Complete(result) => {
+                if let Some(result) = result.take() {
+                    drop(state);
+                    callback(result);
+                }
+            }
+        }
+    }
+
+    /// Signals that the request has completed with the given `result`.
+    ///
+    /// This is invoked by a device driver, and must be called from task context.
+    /// Completing a request more than once is a bug; subsequent results are dropped.
+    ///
+    /// If the request's token was abandoned because its task was killed,
+    /// the result is dropped immediately, as it will never be claimed.
+    pub fn complete(&self, result: BlockIoResult) {
+        let mut state = self.0.state.lock();
+        let callback = match &mut *state {
+            CompletionState::Pending { callback, .. } => callback.take(),
+            CompletionState::Complete(_) => {
+                error!("BUG: CompletionHandle::complete(): request was already completed");
+                return;
+            }
+        };
+        PENDING_REQUESTS.fetch_sub(1, Ordering::Relaxed);
+        let abandoned = self.0.token.as_ref().map_or(false, CancellationToken::is_abandoned);
+        match callback {
+            _ if abandoned => {
+                *state = CompletionState::Complete(None);
+                drop(state);
+                drop(result);
+                self.0.waiters.notify_all();
+            }
+            Some(callback) => {
+                *state = CompletionState::Complete(None);
+                drop(state);
+                callback(result);
+            }
+            None => {
+                *state = CompletionState::Complete(Some(result));
+                drop(state);
+                self.0.waiters.notify_all();
+            }
+        }
+    }
+}
+
+impl Default for CompletionHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// The type of transfer performed by a [`BlockRequest`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RequestKind {
+    Read,
+    Write,
+}
+
+impl RequestKind {
+    /// Returns the direction in which the request's buffer is transferred.
+    fn dma_direction(self) -> DmaDirection {
+        match self {
+            RequestKind::Read => DmaDirection::FromDevice,
+            RequestKind::Write => DmaDirection::ToDevice,
+        }
+    }
+}
+
+/// An outstanding block I/O request that has been handed to a device.
+pub struct BlockRequest {
+    pub kind: RequestKind,
+    pub block_offset: usize,
+    /// The buffer for the request's data, which is owned by the device until the request completes.
+    pub buffer: DmaBuffer,
+    completion: CompletionHandle,
+}
+
+/// A table of the outstanding requests on one device, each identified by a tag.
+///
+/// A tag is the index of the request's slot in this table,
+/// so it is always less than the table's queue depth
+/// and can be reused once its request has completed.
+pub struct RequestTable {
+    slots: IrqSafeMutex<Vec<Option<BlockRequest>>>,
+}
+
+impl RequestTable {
+    /// Creates a new table that can hold up to `queue_depth` outstanding requests.
+    pub fn new(queue_depth: usize) -> RequestTable {
+        let mut slots = Vec::with_capacity(queue_depth);
+        slots.resize_with(queue_depth, || None);
+        RequestTable { slots: IrqSafeMutex::new(slots) }
+    }
+
+    /// Returns the maximum number of outstanding requests in this table.
+    pub fn queue_depth(&self) -> usize {
+        self.slots.lock().len()
+    }
+
+    /// Returns the number of requests that are currently outstanding.
+    pub fn outstanding(&self) -> usize {
+        self.slots.lock().iter().filter(|s| s.is_some()).count()
+    }
+
+    /// Adds a new request to this table, handing its `buffer` over to the device.
+    ///
+    /// Returns the new request's tag, or an error if the table is full.
+    /// The caller should then start the transfer on the device.
+    pub fn insert(
+        &self,
+        kind: RequestKind,
+        block_offset: usize,
+        mut buffer: DmaBuffer,
+        completion: CompletionHandle,
+    ) -> Result<u16, (IoError, DmaBuffer)> {
+        if buffer.owner() != DmaOwner::Cpu {
+            return Err((IoError::Other("request buffer is already owned by a device"), buffer));
+        }
+        let mut slots = self.slots.lock();
+        let Some((tag, slot)) = slots.iter_mut().enumerate().find(|(_, s)| s.is_none()) else {
+            return Err((IoError::Other("too many outstanding requests"), buffer));
+        };
+        let Ok(tag) = u16::try_from(tag) else {
+            return Err((IoError::Other("request tag is too large"), buffer));
+        };
+        buffer.prepare_for_device(kind.dma_direction());
+        *slot = Some(BlockRequest { kind, block_offset, buffer, completion });
+        Ok(tag)
+    }
+
+    /// Invokes the given `func` with a reference to the outstanding request with the given `tag`,
+    /// e.g., to obtain the physical address of its buffer.
+    ///
+    /// Returns `None` if there is no outstanding request with that tag.
+    pub fn with_request<F, R>(&self, tag: u16, func: F) -> Option<R>
+    where
+        F: FnOnce(&BlockRequest) -> R,
+    {
+        self.slots.lock().get(tag as usize)?.as_ref().map(func)
+    }
+
+    /// Completes the outstanding request with the given `tag`, returning its buffer to the CPU
+    /// and signaling its completion handle with the given `result`.
+    ///
+    /// This must be called from task context; see the crate-level docs.
+    pub fn complete(&self, tag: u16, result: Result<(), IoError>) -> Result<(), &'static str> {
+        let request = self.slots.lock()
+            .get_mut(tag as usize)
+            .and_then(Option::take)
+            .ok_or("no outstanding request with that tag")?;
+        request.complete(result);
+        Ok(())
+    }
+
+    /// Returns the tags of the outstanding requests that have been cancelled,
+    /// which the driver should abort on its device before completing them
+    /// via [`complete_cancelled()`](Self::complete_cancelled).
+    pub fn cancelled_tags(&self) -> Vec<u16> {
+        self.slots.lock().iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.as_ref().map_or(false, |r| r.completion.is_cancelled()))
+            .map(|(tag, _)| tag as u16)
+            .collect()
+    }
+
+    /// Completes every outstanding request that has been cancelled with [`IoError::Cancelled`],
+    /// returning the number of requests that were completed.
+    ///
+    /// This must only be called once the device can no longer complete those requests itself,
+    /// i.e., once they were aborted on the device or if they were never issued to it,
+    /// because their tags may be reused by new requests right afterwards.
+    /// A request is completed exactly once even if the device completes it concurrently,
+    /// as only one of the two can take it out of this table.
+    ///
+    /// This must be called from task context; see the crate-level docs.
+    pub fn complete_cancelled(&self) -> usize {
+        let cancelled: Vec<BlockRequest> = self.slots.lock().iter_mut()
+            .filter(|slot| slot.as_ref().map_or(false, |r| r.completion.is_cancelled()))
+            .filter_map(Option::take)
+            .collect();
+        let count = cancelled.len();
+        for request in cancelled {
+            request.complete(Err(IoError::Cancelled));
+        }
+        count
+    }
+}
+
+impl BlockRequest {
+    /// Returns this request's buffer to the CPU and signals its completion handle with the given `result`.
+    fn complete(self, result: Result<(), IoError>) {
+        let BlockRequest { mut buffer, completion, .. } = self;
+        buffer.complete_from_device();
+        completion.complete(match result {
+            Ok(()) => Ok(buffer),
+            Err(e) => Err((e, buffer)),
+        });
+    }
+}
+
+
+/// Reads blocks from the given `device` into `buffer`, starting at `block_offset`,
+/// and waits for the read to complete.
+///
+/// The device is only locked while the request is being submitted.
+pub fn read_and_wait<D>(device: &Mutex<D>, block_offset: usize, buffer: DmaBuffer) -> BlockIoResult
+where
+    D: AsyncBlockDevice + ?Sized,
+{
+    let completion = CompletionHandle::new();
+    device.lock().submit_read(block_offset, buffer, completion.clone())?;
+    completion.wait()
+}
+
+/// Writes the contents of `buffer` to the given `device`, starting at `block_offset`,
+/// and waits for the write to complete.
+///
+/// The device is only locked while the request is being submitted.
+pub fn write_and_wait<D>(device: &Mutex<D>, block_offset: usize, buffer: DmaBuffer) -> BlockIoResult
+where
+    D: AsyncBlockDevice + ?Sized,
+{
+    let completion = CompletionHandle::new();
+    device.lock().submit_write(block_offset, buffer, completion.clone())?;
+    completion.wait()
+}
+
+/// Reads a batch of possibly-scattered block ranges from the given `device`,
+/// keeping up to the device's queue depth of reads in flight at once.
+///
+/// Each of the `requests` is a starting block offset and the buffer to read into.
+/// Returns the result of each request, in the same order as the given `requests`.
+pub fn read_batch<D>(device: &Mutex<D>, requests: Vec<(usize, DmaBuffer)>) -> Vec<BlockIoResult>
+where
+    D: AsyncBlockDevice + ?Sized,
+{
+    let queue_depth = device.lock().queue_depth().max(1);
+    let mut results: Vec<Option<BlockIoResult>> = Vec::with_capacity(requests.len());
+    results.resize_with(requests.len(), || None);
+    let mut in_flight: VecDeque<(usize, CompletionHandle)> = VecDeque::with_capacity(queue_depth);
+
+    for (i, (block_offset, buffer)) in requests.into_iter().enumerate() {
+        if in_flight.len() >= queue_depth {
+            if let Some((j, completion)) = in_flight.pop_front() {
+                results[j] = Some(completion.wait());
+            }
+        }
+        let completion = CompletionHandle::new();
+        match device.lock().submit_read(block_offset, buffer, completion.clone()) {
+            Ok(()) => in_flight.push_back((i, completion)),
+            Err(e) => results[i] = Some(Err(e)),
+        }
+    }
+    for (j, completion) in in_flight {
+        results[j] = Some(completion.wait());
+    }
+
+    results.into_iter().flatten().collect()
+}
+
+/// Reads blocks from the given `device` into `buffer`, starting at `block_offset`,
+/// as a series of requests of up to `blocks_per_request` blocks each,
+/// keeping up to the device's queue depth of them in flight at once.
+///
+/// This is intended for large reads, e.g., of a whole file or a readahead batch.
+/// Each request's DMA buffer is only allocated when it's submitted and is freed once it completes.
+///
+/// The given `token` is checked before each request is submitted. Once it has been cancelled,
+/// no more requests are submitted, and this returns [`IoError::Cancelled`]
+/// once all outstanding requests have completed.
+/// The length of `buffer` must be a multiple of the device's block size.
+pub fn read_range<D>(
+    device: &Mutex<D>,
+    block_offset: usize,
+    buffer: &mut [u8],
+    blocks_per_request: usize,
+    token: &CancellationToken,
+) -> Result<(), IoError>
+where
+    D: AsyncBlockDevice + ?Sized,
+{
+    let (block_size, queue_depth) = {
+        let device = device.lock();
+        (device.block_size(), device.queue_depth().max(1))
+    };
+    if block_size == 0 || buffer.len() % block_size != 0 {
+        return Err(IoError::InvalidInput);
+    }
+    let request_len = blocks_per_request.max(1) * block_size;
+    let num_requests = buffer.len().div_ceil(request_len);
+
+    // Each in-flight request's starting offset into `buffer`, its length, and its completion handle.
+    let mut in_flight: VecDeque<(usize, usize, CompletionHandle)> = VecDeque::with_capacity(queue_depth);
+    let mut next_request = 0;
+    let mut result = Ok(());
+    loop {
+        if result.is_ok() && next_request < num_requests && in_flight.len() < queue_depth {
+            if let Err(e) = token.check() {
+                result = Err(e);
+                continue;
+            }
+            let start = next_request * request_len;
+            let len = request_len.min(buffer.len() - start);
+            next_request += 1;
+            let dma_buffer = match DmaBuffer::new(len) {
+                Ok(b) => b,
+                Err(e) => {
+                    result = Err(IoError::Other(e));
+                    continue;
+                }
+            };
+            let completion = CompletionHandle::with_token(token);
+            match device.lock().submit_read(block_offset + start / block_size, dma_buffer, completion.clone()) {
+                Ok(()) => in_flight.push_back((start, len, completion)),
+                Err((e, _buffer)) => result = Err(e),
+            }
+        } else if let Some((start, len, completion)) = in_flight.pop_front() {
+            match completion.wait() {
+                Ok(dma_buffer) if result.is_ok() => {
+                    match dma_buffer.as_slice(0, len) {
+                        Ok(data) => buffer[start .. start + len].copy_from_slice(data),
+                        Err(e) => result = Err(IoError::Other(e)),
+                    }
+                }
+                Ok(_) => { }
+                Err((e, _buffer)) => {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        } else {
+            return result;
+        }
+    }
+}
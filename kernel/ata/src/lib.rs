@@ -2,7 +2,11 @@
 //! 
 //! The primary struct of interest is [`AtaDrive`].
 //! 
-//! Support for DMA is not yet implemented, but the slower port-based I/O is fully supported.
+//! Support for DMA is not yet implemented, but the slower port-based I/O is fully supported,
+//! both synchronously and via the serialized [`async_block_io::AsyncBlockDevice`] interface.
+//! Recently-read sectors are kept in a small per-drive [`sector_cache`] to avoid repeating slow PIO reads.
+//!
+//! Drives may be plugged in or unplugged at runtime, which is detected by [`IdeController::rescan()`].
 
 #![no_std]
 #![feature(abi_x86_interrupt)]
@@ -10,20 +14,32 @@
 extern crate alloc;
 #[macro_use] extern crate log;
 
-use core::fmt;
+use core::{fmt, sync::atomic::{AtomicBool, Ordering}};
 use bitflags::bitflags;
 use spin::Mutex;
 use alloc::{
 	boxed::Box, 
 	format, 
 	string::{String, ToString}, 
-	sync::Arc
+	sync::Arc,
+	vec::Vec,
 };
+use irq_safety::hold_interrupts;
 use port_io::{Port, PortReadOnly, PortWriteOnly};
 use pci::PciDevice;
-use storage_device::{StorageDevice, StorageDeviceRef, StorageController};
+use storage_device::{
+	IoKind, IoStats, SmartAttribute, SmartData, SmartHealth,
+	DeviceChanges, StorageDevice, StorageDeviceRef, StorageController,
+};
 use io::{BlockIo, BlockReader, BlockWriter, IoError, KnownLength};
+use async_block_io::{AsyncBlockDevice, CancellationToken, CompletionHandle};
+use dma_buffer::DmaBuffer;
 use x86_64::structures::idt::InterruptStackFrame;
+use time::{Duration, Instant};
+use sector_cache::SectorCache;
+
+pub mod sector_cache;
+pub use sector_cache::DEFAULT_SECTOR_CACHE_CAPACITY;
 
 
 const SECTOR_SIZE_IN_BYTES: usize = 512;
@@ -35,6 +51,38 @@ const DEFAULT_SECONDARY_CHANNEL_CONTROL_PORT:    u16 = 0x376;
 
 const MAX_LBA_28_VALUE: usize = (1 << 28) - 1;
 
+/// How long to wait for a busy bus before giving up on it.
+/// Drives may take several seconds to spin up, so this is fairly generous.
+const ATA_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often to log a warning while waiting for a busy bus.
+const ATA_WARN_INTERVAL: Duration = Duration::from_secs(1);
+/// How long to spin while waiting for a busy bus before yielding the CPU between polls.
+const ATA_YIELD_AFTER: Duration = Duration::from_micros(100);
+/// How long the SRST bit must be held during a software reset.
+const ATA_SRST_HOLD_TIME: Duration = Duration::from_micros(5);
+/// How long to wait after a software reset before the status port is valid.
+const ATA_SRST_SETTLE_TIME: Duration = Duration::from_millis(2);
+
+/// Whether ATA drives have been quiesced for shutdown, after which they reject reads and writes.
+static QUIESCED: AtomicBool = AtomicBool::new(false);
+/// Whether waiting for a busy bus must only poll, never yielding the CPU, e.g., while panicking.
+static POLL_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Quiesces all ATA drives for shutdown: afterwards, they reject new reads and writes,
+/// but can still [flush](BlockWriter::flush) their write caches.
+///
+/// If `poll_only` is `true`, subsequent waits for a busy bus never yield the CPU,
+/// which is required when the scheduler can't be relied upon.
+pub fn quiesce(poll_only: bool) {
+	POLL_ONLY.fetch_or(poll_only, Ordering::Relaxed);
+	QUIESCED.store(true, Ordering::Release);
+}
+
+/// The error returned by all operations on a drive that was [detached](AtaDrive::detach).
+const DRIVE_REMOVED: &str = "the ATA drive was removed";
+/// The error returned by a command that was aborted because its [`CancellationToken`] was cancelled.
+const COMMAND_CANCELLED: &str = "the ATA command was cancelled";
+
 /// To use a BAR as a Port address, you must mask out the lowest 2 bits.
 const PCI_BAR_PORT_MASK: u16 = 0xFFFC;
 
@@ -120,8 +168,34 @@ enum AtaCommand {
 	IdentifyPacket  = 0xA1,
 	/// Get identifying details of an ATA drive.
 	IdentifyDevice  = 0xEC,
+	/// A SMART (Self-Monitoring, Analysis and Reporting Technology) command,
+	/// whose subcommand is given by a [`SmartFeature`] in the `features` port.
+	Smart           = 0xB0,
 }
 
+/// The subcommands of [`AtaCommand::Smart`], which are written to the `features` port.
+#[derive(Copy, Clone, Debug)]
+#[repr(u8)]
+enum SmartFeature {
+	/// Reads one sector of SMART attribute data.
+	ReadData     = 0xD0,
+	/// Reports whether any attribute has exceeded its threshold
+	/// via the `lba_mid` and `lba_high` ports.
+	ReturnStatus = 0xDA,
+}
+
+/// The values of the `lba_mid` and `lba_high` ports that must accompany every SMART command.
+const SMART_LBA_SIGNATURE: (u8, u8) = (0x4F, 0xC2);
+/// The values of the `lba_mid` and `lba_high` ports after a SMART RETURN STATUS command
+/// if an attribute has exceeded its threshold.
+const SMART_THRESHOLD_EXCEEDED_SIGNATURE: (u8, u8) = (0xF4, 0x2C);
+/// The number of attribute entries in the SMART data sector.
+const SMART_MAX_ATTRIBUTES: usize = 30;
+/// The size of each attribute entry in the SMART data sector.
+const SMART_ATTRIBUTE_SIZE: usize = 12;
+/// The offset of the first attribute entry in the SMART data sector.
+const SMART_ATTRIBUTES_OFFSET: usize = 2;
+
 
 /// The possible types of drive devices that can be attached to an IDE controller via ATA.
 pub enum AtaDeviceType {
@@ -154,6 +228,17 @@ impl AtaDeviceType {
 	}
 }
 
+/// How the LBA and sector count registers are programmed when issuing an [`AtaCommand`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum LbaMode {
+	/// 28-bit LBA, in which the upper 4 bits of the LBA go into the `drive_select` port.
+	Lba28,
+	/// 48-bit LBA, in which the high bytes are written before the low bytes.
+	Lba48,
+	/// The command doesn't address any sectors, so the LBA and sector count registers are zeroed.
+	None,
+}
+
 /// The two types of ATA drives that may exist on one bus.
 /// The value is the bitmask used to select either master or slave
 /// in the ATA drive's `drive_select` port.
@@ -194,6 +279,21 @@ struct AtaBusMaster {
 /// to whatever bus it is connected to, 
 /// which ensures that commands destined for two different drives 
 /// on the same bus do not interfere or interleave with each other. 
+/// The ways in which waiting for an [`AtaBus`] to become ready can fail.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum WaitError {
+	/// The status port indicated an error or a drive write fault.
+	DriveError,
+	/// The bus was still busy after [`ATA_TIMEOUT`].
+	TimedOut,
+	/// The [`CancellationToken`] of the command was cancelled while waiting.
+	Cancelled,
+}
+
+/// The result of a command on an [`AtaBus`]; the error includes a description
+/// of which step failed and the reason why it failed.
+type CommandResult<T> = Result<T, (WaitError, &'static str)>;
+
 #[derive(Debug)]
 struct AtaBus {
 	/// The port that holds the data to be written or the data from a read.
@@ -204,7 +304,7 @@ struct AtaBus {
 	error: PortReadOnly<u8>,
 	/// The features port, shared with the `error` port.
 	/// Located at `BAR0 + 1`.
-	_features: PortWriteOnly<u8>,
+	features: PortWriteOnly<u8>,
 	/// The number of sectors to read or write.
 	/// Located at `BAR0 + 2`.
 	sector_count: Port<u8>,
@@ -240,6 +340,10 @@ struct AtaBus {
 	/// `DEVADDRESS`, located at `BAR1 + 3`. 
 	/// Not sure what this is used for.
 	_drive_address: Port<u8>,
+
+	/// The cancellation token of the command currently running on this bus, if any,
+	/// which is checked while waiting for the bus.
+	cancel: Option<CancellationToken>,
 }
 
 impl AtaBus {
@@ -250,7 +354,7 @@ impl AtaBus {
 		AtaBus { 
 			data: Port::new(data_bar),
 			error: PortReadOnly::new(data_bar + 1),
-			_features: PortWriteOnly::new(data_bar + 1),
+			features: PortWriteOnly::new(data_bar + 1),
 			sector_count: Port::new(data_bar + 2),
 			lba_low: Port::new(data_bar + 3),
 			lba_mid: Port::new(data_bar + 4),
@@ -262,6 +366,7 @@ impl AtaBus {
 			alternate_status: PortReadOnly::new(control_bar + 2),
 			control: PortWriteOnly::new(control_bar + 2),
 			_drive_address: Port::new(control_bar + 3),
+			cancel: None,
 		}
 	}
 
@@ -273,7 +378,7 @@ impl AtaBus {
 		which: BusDriveSelect,
 		lba_start: usize,
 		sector_count: usize
-	) -> Result<usize, &'static str> {
+	) -> CommandResult<usize> {
 		if sector_count == 0 {
 			return Ok(0);
 		}
@@ -281,42 +386,20 @@ impl AtaBus {
 		// Use 28-bit LBAs, unless the LBA is too large, then we use 48-bit LBAs
 		let using_lba_28 = lba_start <= MAX_LBA_28_VALUE;
 
-		self.wait_for_data_done().map_err(|_| "error before issuing read pio command")?;
+		self.wait_for_data_done().map_err(|e| (e, "error before issuing read pio command"))?;
 
 		// Set up and issue the read command.
 		if using_lba_28 {
-			unsafe {
-				// bits [24:28] of the LBA need to go into the lower 4 bits of the `drive_select` port.
-				self.drive_select.write(0xE0 | (which as u8) | ((lba_start >> 24) as u8 & 0x0F));
-				self.sector_count.write(sector_count as u8);
-				self.lba_high.write((lba_start >> 16) as u8);
-				self.lba_mid.write( (lba_start >>  8) as u8);
-				self.lba_low.write(  lba_start        as u8);
-				self.command.write(AtaCommand::ReadPio as u8);
-			}
+			self.issue_command(AtaCommand::ReadPio, which, LbaMode::Lba28, lba_start, sector_count);
 		} else {
-			// When using 48-bit LBAs, the high bytes of the sector_count and LBA must be written *before* the low bytes.
-			unsafe {
-				self.drive_select.write(0x40 | (which as u8));
-				// write the high bytes
-				self.sector_count.write((sector_count >> 8) as u8);
-				self.lba_high.write((lba_start >> 40) as u8);
-				self.lba_mid.write( (lba_start >> 32) as u8);
-				self.lba_low.write( (lba_start >> 24) as u8);
-				// write the low bytes
-				self.sector_count.write(sector_count as u8);
-				self.lba_high.write((lba_start >> 16) as u8);
-				self.lba_mid.write( (lba_start >>  8) as u8);
-				self.lba_low.write(  lba_start        as u8);
-				self.command.write(AtaCommand::ReadPioExt as u8);
-			}
-		} 
+			self.issue_command(AtaCommand::ReadPioExt, which, LbaMode::Lba48, lba_start, sector_count);
+		}
 
 		// Read the actual data, one sector at a time.
 		let mut buffer_offset = 0;
 		for _lba in lba_start .. (lba_start + sector_count) {
 			// Before transferring each sector, we have to wait for the drive to be ready for data
-			self.wait_for_data_ready().map_err(|_| "error during data read")?;
+			self.wait_for_data_ready().map_err(|e| (e, "error during data read"))?;
 
 			for chunk in buffer[buffer_offset .. (buffer_offset + SECTOR_SIZE_IN_BYTES)].chunks_exact_mut(2) {
 				// ATA PIO works by reading one 16-bit word at a time, 
@@ -327,7 +410,7 @@ impl AtaBus {
 			}
 			buffer_offset += SECTOR_SIZE_IN_BYTES;
 		}
-		self.wait_for_data_done().map_err(|_| "error after data read")?;
+		self.wait_for_data_done().map_err(|e| (e, "error after data read"))?;
 		Ok(sector_count)
 	}
 
@@ -341,7 +424,7 @@ impl AtaBus {
 		which: BusDriveSelect,
 		lba_start: usize,
 		sector_count: usize
-	) -> Result<usize, &'static str> {
+	) -> CommandResult<usize> {
 		if sector_count == 0 {
 			return Ok(0);
 		}
@@ -349,42 +432,20 @@ impl AtaBus {
 		// Use 28-bit LBAs, unless the LBA is too large, then we use 48-bit LBAs
 		let using_lba_28 = lba_start <= MAX_LBA_28_VALUE;
 
-		self.wait_for_data_done().map_err(|_| "error before issuing write command")?;
+		self.wait_for_data_done().map_err(|e| (e, "error before issuing write command"))?;
 
 		// Set up and issue the write command.
 		if using_lba_28 {
-			unsafe {
-				// bits [24:28] of the LBA need to go into the lower 4 bits of the `drive_select` port.
-				self.drive_select.write(0xE0 | (which as u8) | ((lba_start >> 24) as u8 & 0x0F));
-				self.sector_count.write(sector_count as u8);
-				self.lba_high.write((lba_start >> 16) as u8);
-				self.lba_mid.write( (lba_start >>  8) as u8);
-				self.lba_low.write(  lba_start        as u8);
-				self.command.write(AtaCommand::WritePio as u8);
-			}
+			self.issue_command(AtaCommand::WritePio, which, LbaMode::Lba28, lba_start, sector_count);
 		} else {
-			// When using 48-bit LBAs, the high bytes of the sector_count and LBA must be written *before* the low bytes.
-			unsafe {
-				self.drive_select.write(0x40 | (which as u8));
-				// write the high bytes
-				self.sector_count.write((sector_count >> 8) as u8);
-				self.lba_high.write((lba_start >> 40) as u8);
-				self.lba_mid.write( (lba_start >> 32) as u8);
-				self.lba_low.write( (lba_start >> 24) as u8);
-				// write the low bytes
-				self.sector_count.write(sector_count as u8);
-				self.lba_high.write((lba_start >> 16) as u8);
-				self.lba_mid.write( (lba_start >>  8) as u8);
-				self.lba_low.write(  lba_start        as u8);
-				self.command.write(AtaCommand::WritePioExt as u8);
-			}
+			self.issue_command(AtaCommand::WritePioExt, which, LbaMode::Lba48, lba_start, sector_count);
 		}
 
 		// Write the actual data, one sector at a time. 
 		let mut buffer_offset = 0;
 		for _lba in lba_start .. (lba_start + sector_count) {
 			// Before transferring each sector, we have to wait for the drive to be ready for data
-			self.wait_for_data_ready().map_err(|_| "error during data write")?;
+			self.wait_for_data_ready().map_err(|e| (e, "error during data write"))?;
 
 			for chunk in buffer[buffer_offset .. (buffer_offset + SECTOR_SIZE_IN_BYTES)].chunks_exact(2) {
 				// ATA PIO works by writing one 16-bit word at a time, 
@@ -394,16 +455,72 @@ impl AtaBus {
 			}
 			buffer_offset += SECTOR_SIZE_IN_BYTES;
 		}
-		self.wait_for_data_done().map_err(|_| "error after data write")?;
+		self.wait_for_data_done().map_err(|e| (e, "error after data write"))?;
 
 		// Flush the drive's cache after each write command
 		let cache_flush_cmd = if using_lba_28 { AtaCommand::CacheFlush } else { AtaCommand::CacheFlushExt };
 		unsafe { self.command.write(cache_flush_cmd as u8) };
 
-		self.wait_for_data_done().map_err(|_| "error after cache flush after data write")?;
+		self.wait_for_data_done().map_err(|e| (e, "error after cache flush after data write"))?;
 		Ok(sector_count)
 	}
 
+	/// Programs the drive select, sector count, and LBA registers of this bus
+	/// and then issues the given `command`, all as one uninterruptible sequence.
+	///
+	/// Together, these port writes form a single logical command,
+	/// so interrupts are held for the whole sequence to prevent an interrupt handler
+	/// from observing or interleaving with a partially-programmed register state.
+	/// Exclusive access from other tasks and CPUs is already guaranteed by the
+	/// lock around this `AtaBus`, which the caller must hold via `&mut self`.
+	///
+	/// This does not wait for the bus to be ready before or after issuing the command.
+	fn issue_command(
+		&mut self,
+		command: AtaCommand,
+		which: BusDriveSelect,
+		lba_mode: LbaMode,
+		lba_start: usize,
+		sector_count: usize,
+	) {
+		let _held_interrupts = hold_interrupts();
+		unsafe {
+			match lba_mode {
+				LbaMode::Lba28 => {
+					// bits [24:28] of the LBA need to go into the lower 4 bits of the `drive_select` port.
+					self.drive_select.write(0xE0 | (which as u8) | ((lba_start >> 24) as u8 & 0x0F));
+					self.sector_count.write(sector_count as u8);
+					self.lba_high.write((lba_start >> 16) as u8);
+					self.lba_mid.write( (lba_start >>  8) as u8);
+					self.lba_low.write(  lba_start        as u8);
+				}
+				LbaMode::Lba48 => {
+					// When using 48-bit LBAs, the high bytes of the sector_count and LBA must be written *before* the low bytes.
+					self.drive_select.write(0x40 | (which as u8));
+					// write the high bytes
+					self.sector_count.write((sector_count >> 8) as u8);
+					self.lba_high.write((lba_start >> 40) as u8);
+					self.lba_mid.write( (lba_start >> 32) as u8);
+					self.lba_low.write( (lba_start >> 24) as u8);
+					// write the low bytes
+					self.sector_count.write(sector_count as u8);
+					self.lba_high.write((lba_start >> 16) as u8);
+					self.lba_mid.write( (lba_start >>  8) as u8);
+					self.lba_low.write(  lba_start        as u8);
+				}
+				LbaMode::None => {
+					self.drive_select.write(0xA0 | which as u8);
+					self.sector_count.write(0);
+					self.lba_high.write(0);
+					self.lba_mid.write(0);
+					self.lba_low.write(0);
+				}
+			}
+			// issue the actual command
+			self.command.write(command as u8);
+		}
+	}
+
 	/// Issues an ATA identify command to probe the drive
 	/// and query its characteristics. 
 	/// 
@@ -411,15 +528,7 @@ impl AtaBus {
 	fn identify_drive(&mut self, which: BusDriveSelect) -> Result<AtaIdentifyData, &'static str> {
 		self.wait_for_data_done().map_err(|_| "error before issuing identify command")?;
 
-		unsafe {
-			self.drive_select.write(0xA0 | which as u8);
-			self.sector_count.write(0);
-			self.lba_high.write(0);
-			self.lba_mid.write(0);
-			self.lba_low.write(0);
-			// issue the actual commannd
-			self.command.write(AtaCommand::IdentifyDevice as u8);
-		}
+		self.issue_command(AtaCommand::IdentifyDevice, which, LbaMode::None, 0, 0);
 
 		// a status of 0 means that a drive was not attached
 		if self.status().is_empty() {
@@ -432,6 +541,7 @@ impl AtaBus {
 			if self.lba_mid.read() != 0 || self.lba_high.read() != 0 {
 				return Err("drive was not ATA");
 			}
+			core::hint::spin_loop();
 		}
 
 		match AtaDeviceType::from_lba(self.lba_mid.read(), self.lba_high.read()) {
@@ -456,6 +566,60 @@ impl AtaBus {
 		Ok(AtaIdentifyData::new(buffer))
     }
 	
+	/// Issues the given SMART subcommand to the given drive, as one uninterruptible sequence
+	/// like [`issue_command()`](Self::issue_command).
+	fn issue_smart_command(&mut self, which: BusDriveSelect, feature: SmartFeature) {
+		let _held_interrupts = hold_interrupts();
+		unsafe {
+			self.drive_select.write(0xA0 | which as u8);
+			self.features.write(feature as u8);
+			self.sector_count.write(0);
+			self.lba_low.write(0);
+			self.lba_mid.write(SMART_LBA_SIGNATURE.0);
+			self.lba_high.write(SMART_LBA_SIGNATURE.1);
+			self.command.write(AtaCommand::Smart as u8);
+		}
+	}
+
+	/// Flushes the given drive's write cache to its media.
+	fn flush_cache(&mut self, which: BusDriveSelect) -> CommandResult<()> {
+		self.wait_for_data_done().map_err(|e| (e, "error before issuing cache flush command"))?;
+		self.issue_command(AtaCommand::CacheFlush, which, LbaMode::None, 0, 0);
+		self.wait_for_data_done().map_err(|e| (e, "error after issuing cache flush command"))
+	}
+
+	/// Reads the sector of SMART attribute data from the given drive.
+	fn smart_read_data(&mut self, which: BusDriveSelect) -> CommandResult<[u8; SECTOR_SIZE_IN_BYTES]> {
+		self.wait_for_data_done().map_err(|e| (e, "error before issuing SMART READ DATA command"))?;
+		self.issue_smart_command(which, SmartFeature::ReadData);
+
+		let mut buffer = [0u8; SECTOR_SIZE_IN_BYTES];
+		self.wait_for_data_ready().map_err(|e| (e, "error before SMART data read"))?;
+		for chunk in buffer.chunks_exact_mut(2) {
+			let word: u16 = self.data.read();
+			chunk[0] = word as u8;
+			chunk[1] = (word >> 8) as u8;
+		}
+		self.wait_for_data_done().map_err(|e| (e, "error after SMART data read"))?;
+		Ok(buffer)
+	}
+
+	/// Asks the given drive whether any of its SMART attributes has exceeded its threshold.
+	fn smart_return_status(&mut self, which: BusDriveSelect) -> CommandResult<SmartHealth> {
+		self.wait_for_data_done().map_err(|e| (e, "error before issuing SMART RETURN STATUS command"))?;
+		self.issue_smart_command(which, SmartFeature::ReturnStatus);
+		self.wait_for_data_done().map_err(|e| (e, "error after SMART RETURN STATUS command"))?;
+
+		let signature = (self.lba_mid.read(), self.lba_high.read());
+		Ok(if signature == SMART_LBA_SIGNATURE {
+			SmartHealth::Passed
+		} else if signature == SMART_THRESHOLD_EXCEEDED_SIGNATURE {
+			SmartHealth::ThresholdExceeded
+		} else {
+			SmartHealth::Unknown
+		})
+	}
+
 	/// Waits until the bus is ready to transfer data (either read or write).
 	/// This is intended to be used **after** commands have been issued.
 	/// 
@@ -465,24 +629,8 @@ impl AtaBus {
 	/// 
 	/// Returns an error if the `status` port indicates an error. 
 	/// Invoke [`error()`](#method.error) to obtain more details on what kind of error occurred.
-	fn wait_for_data_ready(&self) -> Result<(), ()> {
-		let mut _loop_counter = 0;
-		loop {
-			let status = self.status();
-			_loop_counter += 1;
-			if status.intersects(AtaStatus::ERROR | AtaStatus::DRIVE_WRITE_FAULT) {
-				return Err(());
-			}
-			if status.intersects(AtaStatus::BUSY) { 
-				if _loop_counter % 1_000_000 == 0 {
-					warn!("AtaBus::wait_for_data_ready() has been busy waiting for a long time... is there a device/driver problem? (status: {:?})", status);
-				}
-				continue;
-			}
-			if status.intersects(AtaStatus::DATA_REQUEST_READY) {
-				return Ok(()); // ready to go!
-			}
-		}
+	fn wait_for_data_ready(&self) -> Result<(), WaitError> {
+		self.wait_for_status("wait_for_data_ready", |status| status.intersects(AtaStatus::DATA_REQUEST_READY))
 	}
 
 	/// Waits until this bus is finished transferring data (either read or write),
@@ -495,23 +643,40 @@ impl AtaBus {
 	/// 
 	/// Returns an error if the `status` port indicates an error. 
 	/// Invoke [`error()`](#method.error) to obtain more details on what kind of error occurred.
-	fn wait_for_data_done(&self) -> Result<(), ()> {
-		let mut _loop_counter = 0;
+	fn wait_for_data_done(&self) -> Result<(), WaitError> {
+		self.wait_for_status("wait_for_data_done", |status| !status.intersects(AtaStatus::DATA_REQUEST_READY))
+	}
+
+	/// Polls the `status` port until the bus is no longer busy and `is_done` returns `true`.
+	///
+	/// A warning is logged every [`ATA_WARN_INTERVAL`] while waiting,
+	/// and an error is returned if the status indicates an error
+	/// or if the bus is still not done after [`ATA_TIMEOUT`].
+	fn wait_for_status(&self, name: &str, is_done: impl Fn(AtaStatus) -> bool) -> Result<(), WaitError> {
+		let start = Instant::now();
+		let mut next_warning = start + ATA_WARN_INTERVAL;
 		loop {
 			let status = self.status();
-			_loop_counter += 1;
 			if status.intersects(AtaStatus::ERROR | AtaStatus::DRIVE_WRITE_FAULT) {
-				return Err(());
-			}
-			if status.intersects(AtaStatus::BUSY) { 
-				if _loop_counter % 1_000_000 == 0 {
-					warn!("AtaBus::wait_for_data_done() has been busy waiting for a long time... is there a device/driver problem? (status: {:?})", status);
-				}
-				continue;
+				return Err(WaitError::DriveError);
 			}
-			if !status.intersects(AtaStatus::DATA_REQUEST_READY) {
+			if !status.intersects(AtaStatus::BUSY) && is_done(status) {
 				return Ok(()); // ready to go!
 			}
+			if self.cancel.as_ref().map_or(false, CancellationToken::is_cancelled) {
+				return Err(WaitError::Cancelled);
+			}
+			let now = Instant::now();
+			if now >= next_warning {
+				let waited = now.duration_since(start);
+				if waited >= ATA_TIMEOUT {
+					error!("AtaBus::{}() timed out after {:?} (status: {:?})", name, waited, status);
+					return Err(WaitError::TimedOut);
+				}
+				warn!("AtaBus::{}() has been busy waiting for {:?}... is there a device/driver problem? (status: {:?})", name, waited, status);
+				next_warning = now + ATA_WARN_INTERVAL;
+			}
+			poll_pause(start);
 		}
 	}
 
@@ -542,17 +707,89 @@ impl AtaBus {
 	/// This should only be used to clear leftover error values before identifying the drive,
 	/// or when the drive is stuck in the BUSY status.
 	///
+	/// After the reset, this waits until the drive is no longer busy and reports that it's ready.
+	/// Returns an error if no drive responds or if it's not ready after [`ATA_TIMEOUT`].
+	///
 	/// # Warning
 	/// This resets BOTH (master and slave) drives on this bus, so do not call this
 	/// unless you are certain the other drive has no in-progress transfers.
-	fn software_reset(&mut self) {
+	fn software_reset(&mut self) -> Result<(), &'static str> {
 		// Procedure is (1) set the SRST bit, (2) wait 5us, (3) clear the SRST bit.
 		unsafe { self.control.write(AtaControl::SRST.bits()); }
-		// We wait 5us by reading the status port 50 times (each read takes 100ns)
-		for _ in 0..10 {
-			self.status(); // reads status port 5 times.
-		}
+		spin_wait(ATA_SRST_HOLD_TIME);
 		unsafe { self.control.write(0); }
+		spin_wait(ATA_SRST_SETTLE_TIME);
+
+		let start = Instant::now();
+		loop {
+			let status = self.status();
+			// A floating bus reads as all ones, and an absent drive never sets any status bits.
+			if status.is_empty() || status == AtaStatus::all() {
+				return Err("no drive responded to the software reset");
+			}
+			if !status.intersects(AtaStatus::BUSY) && status.intersects(AtaStatus::DRIVE_READY) {
+				return Ok(());
+			}
+			if start.elapsed() >= ATA_TIMEOUT {
+				error!("AtaBus::software_reset() timed out after {:?} (status: {:?})", ATA_TIMEOUT, status);
+				return Err("drive was not ready after a software reset");
+			}
+			poll_pause(start);
+		}
+	}
+
+	/// Runs the given command `cmd` on this bus.
+	///
+	/// If the command times out, this issues a [software reset](Self::software_reset)
+	/// and retries the command once before giving up, counting the retry in `stats`.
+	///
+	/// If the command is cancelled via this bus's `cancel` token, this also issues a software reset,
+	/// as ATA has no way to abort a single command, and a drive that is in the middle of
+	/// a command would otherwise leave the bus busy or expecting more data.
+	fn run_with_reset_on_timeout<T>(
+		&mut self,
+		name: &str,
+		stats: &IoStats,
+		mut cmd: impl FnMut(&mut AtaBus) -> CommandResult<T>,
+	) -> Result<T, &'static str> {
+		let result = match cmd(self) {
+			Err((WaitError::TimedOut, _)) => {
+				warn!("AtaBus::{}() timed out, resetting the bus and retrying once...", name);
+				stats.record_retry();
+				self.software_reset()?;
+				cmd(self)
+			}
+			other => other,
+		};
+		match result {
+			Err((WaitError::Cancelled, _)) => {
+				warn!("AtaBus::{}() was cancelled, resetting the bus to abort it...", name);
+				self.software_reset()?;
+				Err(COMMAND_CANCELLED)
+			}
+			other => other.map_err(|(_, e)| e),
+		}
+	}
+}
+
+
+/// Pauses briefly between two polls of a busy bus, which began waiting at `start`.
+///
+/// Short waits just spin with a pause hint, but once a wait has lasted longer than
+/// [`ATA_YIELD_AFTER`], e.g., while a drive spins up, this also yields the CPU to other tasks,
+/// unless drives were [quiesced](quiesce) for a shutdown that must only poll.
+fn poll_pause(start: Instant) {
+	core::hint::spin_loop();
+	if start.elapsed() >= ATA_YIELD_AFTER && !POLL_ONLY.load(Ordering::Relaxed) {
+		task::schedule();
+	}
+}
+
+/// Busy-waits for the given `duration`.
+fn spin_wait(duration: Duration) {
+	let start = Instant::now();
+	while start.elapsed() < duration {
+		core::hint::spin_loop();
 	}
 }
 
@@ -568,6 +805,12 @@ pub struct AtaDrive {
 	identify_data: AtaIdentifyData,
 	/// Whether this drive is a master or slave on the bus.
 	master_slave: BusDriveSelect,
+	/// The I/O statistics of this drive.
+	stats: Arc<IoStats>,
+	/// The most recently read sectors of this drive.
+	sector_cache: SectorCache,
+	/// Whether this drive was removed, after which it never accesses the bus again.
+	detached: bool,
 }
 
 impl AtaDrive {
@@ -579,7 +822,10 @@ impl AtaDrive {
 	/// The caller can look for both by calling this twice: once with `which = Master` and once with `which = Slave`.
 	fn new(bus: Arc<Mutex<AtaBus>>, which: BusDriveSelect) -> Result<AtaDrive, &'static str> {
 		// Issue a preliminary software reset of the bus to clear out lingering errors.
-		bus.lock().software_reset(); 
+		// A failure here is not fatal; the identify command below determines whether the drive exists.
+		if let Err(_e) = bus.lock().software_reset() {
+			debug!("AtaDrive::new(): software reset failed: {}", _e);
+		}
 		// Then use an identify command to see if the drive exists.
 		let identify_data = bus.lock().identify_drive(which)?;
 
@@ -593,6 +839,9 @@ impl AtaDrive {
 			bus, 
 			identify_data,
 			master_slave: which,
+			stats: Arc::new(IoStats::new()),
+			sector_cache: SectorCache::new(DEFAULT_SECTOR_CACHE_CAPACITY),
+			detached: false,
 		})
 	}
 
@@ -605,9 +854,29 @@ impl AtaDrive {
 	/// 
 	/// Returns the number of sectors (*not bytes*) that were successfully written to the drive.
 	/// 
+	/// If all of the requested sectors were recently read, they're copied from this drive's
+	/// [`sector_cache`] instead of being read from the drive again.
+	/// 
 	/// # Note
 	/// This is slow, as it uses blocking port I/O instead of DMA. 
 	pub fn read_pio(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+		self.read_pio_cancellable(buffer, offset_in_sectors, None)
+	}
+
+	/// Like [`read_pio()`](Self::read_pio), but aborts the read via a software reset of the bus
+	/// if the given `token` is cancelled while waiting for the drive.
+	fn read_pio_cancellable(
+		&mut self,
+		buffer: &mut [u8],
+		offset_in_sectors: usize,
+		token: Option<&CancellationToken>,
+	) -> Result<usize, &'static str> {
+		if self.detached {
+			return Err(DRIVE_REMOVED);
+		}
+		if QUIESCED.load(Ordering::Acquire) {
+			return Err("ATA drives were quiesced for shutdown");
+		}
 		if offset_in_sectors > self.size_in_blocks() {
 			return Err("offset_in_sectors was out of bounds");
 		}
@@ -628,8 +897,31 @@ impl AtaDrive {
 			);
 			return Err("AtaDrive::read_pio(): cannot read more sectors than the drive's max");
 		}
+
+		if self.sector_cache.capacity() != 0 {
+			let hit = self.sector_cache.read(buffer, lba_start);
+			for _ in 0 .. sector_count {
+				if hit { self.stats.record_cache_hit() } else { self.stats.record_cache_miss() }
+			}
+			if hit {
+				return Ok(sector_count);
+			}
+		}
 		
-		self.bus.lock().read_pio(buffer, self.master_slave, lba_start, sector_count)
+		let which = self.master_slave;
+		let timer = self.stats.start();
+		let mut bus = self.bus.lock();
+		bus.cancel = token.cloned();
+		let result = bus.run_with_reset_on_timeout("read_pio", &self.stats, |bus|
+			bus.read_pio(buffer, which, lba_start, sector_count)
+		);
+		bus.cancel = None;
+		drop(bus);
+		timer.finish(IoKind::Read, sector_count, result.is_ok());
+		if let Ok(sectors_read) = result {
+			self.sector_cache.insert(&buffer[.. sectors_read * SECTOR_SIZE_IN_BYTES], lba_start);
+		}
+		result
 	}
 
 	/// Writes data from the provided `buffer` to this drive, starting at the given `offset_in_sectors` into the drive.
@@ -641,9 +933,31 @@ impl AtaDrive {
 	/// 
 	/// Returns the number of sectors (*not bytes*) that were successfully written to the drive.
 	/// 
+	/// Writes bypass this drive's [`sector_cache`], but invalidate any cached copies
+	/// of the sectors being written, even if the write fails partway through.
+	/// 
 	/// # Note
 	/// This is slow, as it uses blocking port I/O instead of DMA. 
 	pub fn write_pio(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+		self.write_pio_cancellable(buffer, offset_in_sectors, None)
+	}
+
+	/// Like [`write_pio()`](Self::write_pio), but aborts the write via a software reset of the bus
+	/// if the given `token` is cancelled while waiting for the drive.
+	///
+	/// The sectors being written have unspecified contents after an aborted write.
+	fn write_pio_cancellable(
+		&mut self,
+		buffer: &[u8],
+		offset_in_sectors: usize,
+		token: Option<&CancellationToken>,
+	) -> Result<usize, &'static str> {
+		if self.detached {
+			return Err(DRIVE_REMOVED);
+		}
+		if QUIESCED.load(Ordering::Acquire) {
+			return Err("ATA drives were quiesced for shutdown");
+		}
 		if offset_in_sectors > self.size_in_blocks() {
 			return Err("offset_in_sectors was out of bounds");
 		}
@@ -665,10 +979,103 @@ impl AtaDrive {
 			return Err("AtaDrive::write_pio(): cannot write more sectors than the drive's max");
 		}
 
-		self.bus.lock().write_pio(buffer, self.master_slave, lba_start, sector_count)
+		self.sector_cache.invalidate(lba_start, sector_count);
+		let which = self.master_slave;
+		let timer = self.stats.start();
+		let mut bus = self.bus.lock();
+		bus.cancel = token.cloned();
+		let result = bus.run_with_reset_on_timeout("write_pio", &self.stats, |bus|
+			bus.write_pio(buffer, which, lba_start, sector_count)
+		);
+		bus.cancel = None;
+		drop(bus);
+		timer.finish(IoKind::Write, sector_count, result.is_ok());
+		result
+	}
+
+	/// Flushes this drive's write cache to its media,
+	/// which is still allowed after drives are [quiesced](quiesce) for shutdown.
+	pub fn flush_cache(&mut self) -> Result<(), &'static str> {
+		if self.detached {
+			return Err(DRIVE_REMOVED);
+		}
+		let which = self.master_slave;
+		self.bus.lock().run_with_reset_on_timeout("flush_cache", &self.stats, |bus|
+			bus.flush_cache(which)
+		)
+	}
+
+	/// Returns the maximum number of recently-read sectors that this drive caches.
+	pub fn sector_cache_capacity(&self) -> usize {
+		self.sector_cache.capacity()
+	}
+
+	/// Sets the maximum number of recently-read sectors that this drive caches,
+	/// which defaults to [`DEFAULT_SECTOR_CACHE_CAPACITY`].
+	/// 
+	/// A capacity of `0` disables the cache, and shrinking it evicts the least recently used sectors.
+	pub fn set_sector_cache_capacity(&mut self, sectors: usize) {
+		self.sector_cache.set_capacity(sectors);
+	}
+
+	/// Issues a software reset to the bus that this drive is attached to,
+	/// which can be used to recover a drive that is stuck in the BUSY state.
+	///
+	/// Read and write commands already do this automatically (once) upon timing out.
+	///
+	/// # Warning
+	/// This resets both the master and slave drive on this drive's bus.
+	pub fn software_reset(&mut self) -> Result<(), &'static str> {
+		if self.detached {
+			return Err(DRIVE_REMOVED);
+		}
+		self.bus.lock().software_reset()
 	}
 
 
+	/// Reads this drive's SMART health status and attributes.
+	///
+	/// Returns an error if this drive doesn't support SMART or if SMART is disabled.
+	pub fn smart_data(&mut self) -> Result<SmartData, &'static str> {
+		if self.detached {
+			return Err(DRIVE_REMOVED);
+		}
+		// Bit 0 of words 82 and 85 of the identify data indicate SMART support and enablement.
+		if self.identify_data.command_set_support[0] & 0x1 == 0 {
+			return Err("drive does not support SMART");
+		}
+		if self.identify_data.command_set_active[0] & 0x1 == 0 {
+			return Err("SMART is disabled on this drive");
+		}
+		let which = self.master_slave;
+		let mut bus = self.bus.lock();
+		let health = bus.run_with_reset_on_timeout("smart_return_status", &self.stats, |bus|
+			bus.smart_return_status(which)
+		)?;
+		let data = bus.run_with_reset_on_timeout("smart_read_data", &self.stats, |bus|
+			bus.smart_read_data(which)
+		)?;
+		drop(bus);
+
+		let attributes = data[SMART_ATTRIBUTES_OFFSET ..]
+			.chunks_exact(SMART_ATTRIBUTE_SIZE)
+			.take(SMART_MAX_ATTRIBUTES)
+			// An attribute ID of 0 marks an unused entry.
+			.filter(|entry| entry[0] != 0)
+			.map(|entry| {
+				let mut raw = [0u8; 8];
+				raw[..6].copy_from_slice(&entry[5..11]);
+				SmartAttribute {
+					id: entry[0],
+					current: entry[3],
+					worst: entry[4],
+					raw: u64::from_le_bytes(raw),
+				}
+			})
+			.collect::<Vec<_>>();
+		Ok(SmartData { health, attributes })
+	}
+
 	/// Returns `true` if this drive is the master, or `false` if it is the slave 
 	/// on the IDE controller bus.
 	pub fn is_master(&self) -> bool {
@@ -677,6 +1084,21 @@ impl AtaDrive {
 			BusDriveSelect::Slave => false,
 		}
 	}
+
+	/// Detaches this drive after it was removed, after which all of its operations fail
+	/// without accessing the bus, which may now be used by a different drive.
+	pub fn detach(&mut self) {
+		self.detached = true;
+		self.sector_cache.set_capacity(0);
+	}
+
+	/// Returns `true` if the given identify data describes this drive,
+	/// rather than a different drive that replaced it.
+	fn is_same_drive(&self, identify_data: &AtaIdentifyData) -> bool {
+		let (serial, model) = (self.identify_data.serial_number, self.identify_data.model_number);
+		let (new_serial, new_model) = (identify_data.serial_number, identify_data.model_number);
+		serial.0 == new_serial.0 && model.0 == new_model.0
+	}
 }
 
 impl StorageDevice for AtaDrive {
@@ -687,6 +1109,22 @@ impl StorageDevice for AtaDrive {
 			self.identify_data.max_48_bit_lba as usize
 		}
 	}
+
+	fn io_stats(&self) -> Option<Arc<IoStats>> {
+		Some(Arc::clone(&self.stats))
+	}
+
+	fn smart_data(&mut self) -> Result<SmartData, &'static str> {
+		AtaDrive::smart_data(self)
+	}
+
+	fn detach(&mut self) {
+		AtaDrive::detach(self)
+	}
+
+	fn is_detached(&self) -> bool {
+		self.detached
+	}
 }
 impl BlockIo for AtaDrive {
 	fn block_size(&self) -> usize { SECTOR_SIZE_IN_BYTES }
@@ -696,17 +1134,90 @@ impl KnownLength for AtaDrive {
 }
 impl BlockReader for AtaDrive {
 	fn read_blocks(&mut self, buffer: &mut [u8], block_offset: usize) -> Result<usize, IoError> {
+		if self.detached {
+			return Err(IoError::DeviceRemoved);
+		}
 		// TODO: emit a more specific IoError from the read_pio function itself instead of a blind conversion here
 		self.read_pio(buffer, block_offset).map_err(|_e| IoError::InvalidInput)
 	}
 }
 impl BlockWriter for AtaDrive {
 	fn write_blocks(&mut self, buffer: &[u8], block_offset: usize) -> Result<usize, IoError> {
+		if self.detached {
+			return Err(IoError::DeviceRemoved);
+		}
 		// TODO: emit a more specific IoError from the read_pio function itself instead of a blind conversion here
 		self.write_pio(buffer, block_offset).map_err(|_e| IoError::InvalidInput)
 	}
 
-	fn flush(&mut self) -> Result<(), IoError> { Ok(()) }
+	fn flush(&mut self) -> Result<(), IoError> {
+		if self.detached {
+			return Err(IoError::DeviceRemoved);
+		}
+		self.flush_cache().map_err(IoError::from)
+	}
+}
+/// ATA drives are accessed with port I/O, so requests are serialized:
+/// each request is performed synchronously during submission,
+/// and its completion is signaled (and any callback invoked) before `submit_*` returns.
+///
+/// A request that was cancelled before being submitted completes with [`IoError::Cancelled`]
+/// without accessing the drive. One that is cancelled by another task while its transfer
+/// is in progress is aborted by a software reset of the bus, which also completes it as cancelled.
+impl AsyncBlockDevice for AtaDrive {
+	fn queue_depth(&self) -> usize { 1 }
+
+	fn submit_read(
+		&mut self,
+		block_offset: usize,
+		mut buffer: DmaBuffer,
+		completion: CompletionHandle,
+	) -> Result<(), (IoError, DmaBuffer)> {
+		if self.detached {
+			return Err((IoError::DeviceRemoved, buffer));
+		}
+		if completion.is_cancelled() {
+			completion.complete(Err((IoError::Cancelled, buffer)));
+			return Ok(());
+		}
+		let len = buffer.size_in_bytes();
+		let result = match buffer.as_slice_mut(0, len) {
+			Ok(slice) => self.read_pio_cancellable(slice, block_offset, completion.token()).map(|_| ()),
+			Err(e) => Err(e),
+		};
+		completion.complete(match result {
+			Ok(()) => Ok(buffer),
+			Err(_e) if completion.is_cancelled() => Err((IoError::Cancelled, buffer)),
+			Err(_e) => Err((IoError::InvalidInput, buffer)),
+		});
+		Ok(())
+	}
+
+	fn submit_write(
+		&mut self,
+		block_offset: usize,
+		buffer: DmaBuffer,
+		completion: CompletionHandle,
+	) -> Result<(), (IoError, DmaBuffer)> {
+		if self.detached {
+			return Err((IoError::DeviceRemoved, buffer));
+		}
+		if completion.is_cancelled() {
+			completion.complete(Err((IoError::Cancelled, buffer)));
+			return Ok(());
+		}
+		let len = buffer.size_in_bytes();
+		let result = match buffer.as_slice(0, len) {
+			Ok(slice) => self.write_pio_cancellable(slice, block_offset, completion.token()).map(|_| ()),
+			Err(e) => Err(e),
+		};
+		completion.complete(match result {
+			Ok(()) => Ok(buffer),
+			Err(_e) if completion.is_cancelled() => Err((IoError::Cancelled, buffer)),
+			Err(_e) => Err((IoError::InvalidInput, buffer)),
+		});
+		Ok(())
+	}
 }
 
 pub type AtaDriveRef = Arc<Mutex<AtaDrive>>;
@@ -720,6 +1231,8 @@ pub struct IdeController {
 	pub primary_slave:    Option<AtaDriveRef>,
 	pub secondary_master: Option<AtaDriveRef>,
 	pub secondary_slave:  Option<AtaDriveRef>,
+	/// The primary and secondary buses, which are re-probed by [`IdeController::rescan()`].
+	buses: [Arc<Mutex<AtaBus>>; 2],
 }
 
 impl IdeController {
@@ -759,13 +1272,13 @@ impl IdeController {
 
 		// Register interrupt handlers for the primary and secondary ATA buses.
 		// They're not yet used for anything but will determine when a DMA transfer has completed.
-		interrupts::register_interrupt(ATA_PRIMARY_IRQ, primary_ata_handler).map_err(|e| {
+		interrupts::register_isa_interrupt(ATA_PRIMARY_ISA_IRQ, primary_ata_handler).map_err(|e| {
 			error!("ATA Primary Bus IRQ {:#X} was already in use by handler {:#X}! Sharing IRQs is currently unsupported.", 
 				ATA_PRIMARY_IRQ, e,
 			);
 			"ATA Primary Bus IRQ was already in use! Sharing IRQs is currently unsupported."
 		})?;
-		interrupts::register_interrupt(ATA_SECONDARY_IRQ, secondary_ata_handler).map_err(|e| {
+		interrupts::register_isa_interrupt(ATA_SECONDARY_ISA_IRQ, secondary_ata_handler).map_err(|e| {
 			error!("ATA Secondary Bus IRQ {:#X} was already in use by handler {:#X}! Sharing IRQs is currently unsupported.", 
 				ATA_SECONDARY_IRQ, e,
 			);
@@ -776,9 +1289,9 @@ impl IdeController {
 		let secondary_bus = Arc::new(Mutex::new(AtaBus::new(secondary_bus_data_port, secondary_bus_control_port)));
 
 		let primary_master   = AtaDrive::new(Arc::clone(&primary_bus), BusDriveSelect::Master);
-		let primary_slave    = AtaDrive::new(primary_bus, BusDriveSelect::Slave);
+		let primary_slave    = AtaDrive::new(Arc::clone(&primary_bus), BusDriveSelect::Slave);
 		let secondary_master = AtaDrive::new(Arc::clone(&secondary_bus), BusDriveSelect::Master);
-		let secondary_slave  = AtaDrive::new(secondary_bus, BusDriveSelect::Slave);
+		let secondary_slave  = AtaDrive::new(Arc::clone(&secondary_bus), BusDriveSelect::Slave);
 		
 		let drive_fmt = |drive: &Result<AtaDrive, &str>| -> String {
 			match drive {
@@ -804,9 +1317,52 @@ impl IdeController {
 			primary_slave:    primary_slave.ok().map(|d| Arc::new(Mutex::new(d))),
 			secondary_master: secondary_master.ok().map(|d| Arc::new(Mutex::new(d))),
 			secondary_slave:  secondary_slave.ok().map(|d| Arc::new(Mutex::new(d))),
+			buses: [primary_bus, secondary_bus],
 		})
 	}
 
+	/// Re-probes all four drive positions on this controller's buses.
+	///
+	/// A known drive is considered removed if it no longer responds to an identify command,
+	/// or if a different drive (by serial and model number) responds in its place;
+	/// it's then [detached](AtaDrive::detach) and forgotten by this controller.
+	/// Any drive that's found in an empty position is initialized and added to this controller.
+	pub fn rescan(&mut self) -> DeviceChanges {
+		let [primary_bus, secondary_bus] = &self.buses;
+		let positions = [
+			(&mut self.primary_master,   primary_bus,   BusDriveSelect::Master, "primary master"),
+			(&mut self.primary_slave,    primary_bus,   BusDriveSelect::Slave,  "primary slave"),
+			(&mut self.secondary_master, secondary_bus, BusDriveSelect::Master, "secondary master"),
+			(&mut self.secondary_slave,  secondary_bus, BusDriveSelect::Slave,  "secondary slave"),
+		];
+
+		let mut changes = DeviceChanges::default();
+		for (position, bus, which, name) in positions {
+			if let Some(drive_ref) = position {
+				// Lock the drive before the bus, as its reads and writes do, such that none are in progress.
+				let mut drive = drive_ref.lock();
+				match bus.lock().identify_drive(which) {
+					Ok(identify_data) if drive.is_same_drive(&identify_data) => continue,
+					_ => {
+						info!("ATA drive at the {} position was removed", name);
+						drive.detach();
+					}
+				}
+				drop(drive);
+				if let Some(removed) = position.take() {
+					changes.removed.push(removed);
+				}
+			}
+			if let Ok(drive) = AtaDrive::new(Arc::clone(bus), which) {
+				info!("ATA drive found at the {} position, size: {} sectors", name, drive.size_in_blocks());
+				let drive_ref = Arc::new(Mutex::new(drive));
+				changes.added.push(Arc::clone(&drive_ref) as StorageDeviceRef);
+				*position = Some(drive_ref);
+			}
+		}
+		changes
+	}
+
 	/// Returns an `Iterator` over all of the `AtaDrive`s 
 	/// that exist (and are supported) in this `IdeController`.
 	/// The order of iteration is: 
@@ -826,6 +1382,26 @@ impl StorageController for IdeController {
 			self.iter().map(|ata_drive_ref| Arc::clone(ata_drive_ref) as StorageDeviceRef)
 		)
 	}
+
+	fn rescan(&mut self) -> Result<DeviceChanges, &'static str> {
+		Ok(IdeController::rescan(self))
+	}
+
+	fn remove_device(&mut self, device: &StorageDeviceRef) -> bool {
+		let target = Arc::as_ptr(device) as *const ();
+		for position in [
+			&mut self.primary_master,
+			&mut self.primary_slave,
+			&mut self.secondary_master,
+			&mut self.secondary_slave,
+		] {
+			if position.as_ref().is_some_and(|d| Arc::as_ptr(d) as *const () == target) {
+				*position = None;
+				return true;
+			}
+		}
+		false
+	}
 }
 
 /// The order in which `AtaDrive`s in an `IdeController` are iterated over.
@@ -887,12 +1463,14 @@ impl<'c> Iterator for IdeControllerIter<'c> {
 }
 
 
-/// The primary ATA interrupt is connected to IRQ 0xE by default.
+/// The primary ATA interrupt is connected to ISA IRQ 0xE by default.
+const ATA_PRIMARY_ISA_IRQ:   u8 = 0xE;
+/// The secondary ATA interrupt is connected to ISA IRQ 0xF by default.
+const ATA_SECONDARY_ISA_IRQ: u8 = 0xF;
 /// Because we perform the typical PIC remapping, the remapped IRQ vector number is 0x2E.
-const ATA_PRIMARY_IRQ:   u8 = interrupts::IRQ_BASE_OFFSET + 0xE;
-/// The secondary ATA interrupt is connected to IRQ 0xF by default.
+const ATA_PRIMARY_IRQ:   u8 = interrupts::IRQ_BASE_OFFSET + ATA_PRIMARY_ISA_IRQ;
 /// Because we perform the typical PIC remapping, the remapped IRQ vector number is 0x2F.
-const ATA_SECONDARY_IRQ: u8 = interrupts::IRQ_BASE_OFFSET + 0xF;
+const ATA_SECONDARY_IRQ: u8 = interrupts::IRQ_BASE_OFFSET + ATA_SECONDARY_ISA_IRQ;
 
 /// The primary ATA interrupt handler. Not yet used for anything, but useful for DMA.
 extern "x86-interrupt" fn primary_ata_handler(_stack_frame: InterruptStackFrame ) {
@@ -902,6 +1480,10 @@ extern "x86-interrupt" fn primary_ata_handler(_stack_frame: InterruptStackFrame
 
 /// The primary ATA interrupt handler. Not yet used for anything, but useful for DMA.
 extern "x86-interrupt" fn secondary_ata_handler(_stack_frame: InterruptStackFrame ) {
+    // The secondary bus shares its IRQ line with the slave PIC's spurious interrupts.
+    if interrupts::spurious::is_spurious(ATA_SECONDARY_IRQ) {
+        return;
+    }
     info!("Secondary ATA Interrupt ({:#X})", ATA_SECONDARY_IRQ);
     interrupts::eoi(ATA_SECONDARY_IRQ);
 }
@@ -0,0 +1,98 @@
+//! A small cache of recently-read sectors in front of an ATA drive's slow PIO read path.
+//!
+//! Filesystems tend to re-read the same metadata sectors over and over,
+//! and each PIO read is a full, blocking port I/O transaction.
+//! Each [`AtaDrive`](crate::AtaDrive) has its own cache, so cached sectors are
+//! implicitly keyed by the drive's channel (bus) and master/slave position as well as their LBA.
+//!
+//! The cache only holds clean copies of sectors: writes always go straight to the drive
+//! and invalidate any cached copies of the sectors they overwrite.
+
+use alloc::{boxed::Box, collections::VecDeque};
+use core::fmt;
+use crate::SECTOR_SIZE_IN_BYTES;
+
+/// The default number of sectors cached for each drive, i.e., 32 KiB.
+pub const DEFAULT_SECTOR_CACHE_CAPACITY: usize = 64;
+
+/// A least-recently-used cache of sectors, keyed by their LBA.
+pub(crate) struct SectorCache {
+	/// The maximum number of sectors to cache; `0` disables the cache.
+	capacity: usize,
+	/// The cached sectors, ordered from least to most recently used.
+	///
+	/// The cache is small, so a linear search is cheaper than maintaining a separate index.
+	entries: VecDeque<(usize, Box<[u8; SECTOR_SIZE_IN_BYTES]>)>,
+}
+
+impl SectorCache {
+	pub(crate) fn new(capacity: usize) -> SectorCache {
+		SectorCache { capacity, entries: VecDeque::new() }
+	}
+
+	pub(crate) fn capacity(&self) -> usize {
+		self.capacity
+	}
+
+	/// Changes the maximum number of cached sectors, evicting the least recently used ones if needed.
+	pub(crate) fn set_capacity(&mut self, capacity: usize) {
+		self.capacity = capacity;
+		while self.entries.len() > capacity {
+			self.entries.pop_front();
+		}
+	}
+
+	/// Copies the sectors starting at `lba` into `buffer` if all of them are cached,
+	/// marking them as most recently used.
+	///
+	/// Returns `false` without modifying `buffer` if any of those sectors isn't cached.
+	pub(crate) fn read(&mut self, buffer: &mut [u8], lba: usize) -> bool {
+		let sector_count = buffer.len() / SECTOR_SIZE_IN_BYTES;
+		let all_cached = (lba .. lba + sector_count)
+			.all(|l| self.entries.iter().any(|(cached, _)| *cached == l));
+		if !all_cached || sector_count == 0 {
+			return false;
+		}
+		for (l, chunk) in (lba ..).zip(buffer.chunks_exact_mut(SECTOR_SIZE_IN_BYTES)) {
+			if let Some(index) = self.entries.iter().position(|(cached, _)| *cached == l) {
+				let entry = self.entries.remove(index).unwrap();
+				chunk.copy_from_slice(&entry.1[..]);
+				self.entries.push_back(entry);
+			}
+		}
+		true
+	}
+
+	/// Caches the sectors starting at `lba` that were just read from the drive into `buffer`.
+	pub(crate) fn insert(&mut self, buffer: &[u8], lba: usize) {
+		if self.capacity == 0 {
+			return;
+		}
+		// If there are more sectors than fit in the cache, only the last ones are worth caching.
+		let sector_count = buffer.len() / SECTOR_SIZE_IN_BYTES;
+		let skip = sector_count.saturating_sub(self.capacity);
+		self.invalidate(lba + skip, sector_count - skip);
+		for (l, chunk) in (lba ..).zip(buffer.chunks_exact(SECTOR_SIZE_IN_BYTES)).skip(skip) {
+			if self.entries.len() >= self.capacity {
+				self.entries.pop_front();
+			}
+			let mut sector = Box::new([0u8; SECTOR_SIZE_IN_BYTES]);
+			sector.copy_from_slice(chunk);
+			self.entries.push_back((l, sector));
+		}
+	}
+
+	/// Removes any cached copies of the `sector_count` sectors starting at `lba`.
+	pub(crate) fn invalidate(&mut self, lba: usize, sector_count: usize) {
+		self.entries.retain(|(cached, _)| !(lba .. lba + sector_count).contains(cached));
+	}
+}
+
+impl fmt::Debug for SectorCache {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("SectorCache")
+			.field("capacity", &self.capacity)
+			.field("cached_sectors", &self.entries.len())
+			.finish()
+	}
+}
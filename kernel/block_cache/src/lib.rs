@@ -23,20 +23,27 @@
 //! instead of exposing a `StorageDevice`. I suppose the least disruptive way to implement this
 //! might be with a layer of indirection to an implementor of a BlockReader like trait,
 //! so that the underlying block reader can be switched out when a cache is enabled.
+//!
+//! A shared `Mutex<BlockCache>` is a [`StorageDeviceDependent`], so it can be registered
+//! with the storage manager to be flushed and detached before its device is removed.
 
 #![no_std]
 
 #[macro_use] extern crate alloc;
 extern crate hashbrown;
 extern crate storage_device;
+extern crate spin;
+extern crate async_block_io;
 
-use alloc::vec::Vec;
+use alloc::{sync::Arc, vec::Vec};
 use hashbrown::{
     HashMap,
     hash_map::Entry,
 };
-use storage_device::{StorageDevice, StorageDeviceRef};
+use spin::Mutex;
+use storage_device::{IoStats, StorageDevice, StorageDeviceDependent, StorageDeviceRef};
 use alloc::borrow::{Cow, ToOwned};
+use async_block_io::CancellationToken;
 
 /// A cache to store read and written blocks from a storage device.
 pub struct BlockCache {
@@ -45,21 +52,53 @@ pub struct BlockCache {
     cache: InternalCache,
     /// The underlying storage device from where the blocks are read/written.
     storage_device: StorageDeviceRef,
+    /// The I/O statistics of the underlying storage device, if it keeps any,
+    /// in which this cache's hits and misses are recorded.
+    stats: Option<Arc<IoStats>>,
+    /// Whether this cache was detached from its storage device, after which all accesses fail.
+    detached: bool,
 }
 
+/// The error returned by all accesses to a `BlockCache` that was detached from its storage device.
+const DETACHED: &str = "the block cache was detached from its removed storage device";
+/// The error returned by a multi-block read whose `CancellationToken` was cancelled.
+const CANCELLED: &str = "the block cache read was cancelled";
+
 impl BlockCache {
     /// Creates a new `BlockCache` device 
     pub fn new(storage_device: StorageDeviceRef) -> BlockCache {
+        let stats = storage_device.lock().io_stats();
         BlockCache {
             cache: HashMap::new(),
             storage_device,
+            stats,
+            detached: false,
         }
     }
 
+    /// Returns the storage device that this cache reads from and writes to.
+    pub fn storage_device(&self) -> &StorageDeviceRef {
+        &self.storage_device
+    }
+
+    /// Returns `true` if this cache was detached from its removed storage device,
+    /// after which all accesses to it fail.
+    pub fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    /// Drops all cached blocks, discarding any that haven't been flushed.
+    pub fn invalidate_all(&mut self) {
+        self.cache.clear();
+    }
+
     /// Flushes the given block to the backing storage device. 
     /// If the `block_to_flush` is None, all blocks in the entire cache
     /// will be written back to the storage device.
     pub fn flush(&mut self, block_num: Option<usize>) -> Result<(), &'static str> {
+        if self.detached {
+            return Err(DETACHED);
+        }
         let mut locked_device = self.storage_device.lock();
         if let Some(bn) = block_num {
             // Flush just one block
@@ -82,6 +121,9 @@ impl BlockCache {
     /// If that block exists in the cache, it is copied into the buffer. 
     /// If not, it is read from the storage device into the cache, and then copied into the buffer.
     pub fn read_block(cache: &mut BlockCache, block: usize) -> Result<&[u8], &'static str> {
+        if cache.detached {
+            return Err(DETACHED);
+        }
         let mut locked_device = cache.storage_device.lock();
         match cache.cache.entry(block) {
             Entry::Occupied(occ) => {
@@ -90,8 +132,12 @@ impl BlockCache {
                 // But if it's in the `Invalid` state, we have to re-read the block from the storage device.
                 let cached_block = occ.into_mut();
                 match cached_block.state {
-                    CacheState::Modified | CacheState::Shared => Ok(&cached_block.block),
+                    CacheState::Modified | CacheState::Shared => {
+                        if let Some(stats) = &cache.stats { stats.record_cache_hit(); }
+                        Ok(&cached_block.block)
+                    }
                     CacheState::Invalid => {
+                        if let Some(stats) = &cache.stats { stats.record_cache_miss(); }
                         locked_device.read_blocks(&mut cached_block.block, block)?;
                         cached_block.state = CacheState::Shared;
                         Ok(&cached_block.block)
@@ -101,6 +147,7 @@ impl BlockCache {
             Entry::Vacant(vacant) => {
                 // A vacant entry will be read from the backing storage device,
                 // so it will always start out in the `Shared` state.
+                if let Some(stats) = &cache.stats { stats.record_cache_miss(); }
                 let mut v = vec![0; locked_device.block_size()];
                 locked_device.read_blocks(&mut v, block)?;
                 let cb = CachedBlock {
@@ -113,10 +160,37 @@ impl BlockCache {
         }
     }
 
+    /// Reads the consecutive blocks starting at `first_block` through this cache into `buffer`,
+    /// e.g., for a large file read. The length of `buffer` must be a multiple of the block size.
+    ///
+    /// The given `token` is checked before each block is read; once it has been cancelled,
+    /// this stops and returns an error. The blocks that were already read remain cached.
+    pub fn read_blocks_cancellable(
+        &mut self,
+        first_block: usize,
+        buffer: &mut [u8],
+        token: &CancellationToken,
+    ) -> Result<(), &'static str> {
+        let block_size = self.storage_device.lock().block_size();
+        if block_size == 0 || buffer.len() % block_size != 0 {
+            return Err("the buffer length must be a multiple of the block size");
+        }
+        for (i, chunk) in buffer.chunks_exact_mut(block_size).enumerate() {
+            if token.is_cancelled() {
+                return Err(CANCELLED);
+            }
+            chunk.copy_from_slice(BlockCache::read_block(self, first_block + i)?);
+        }
+        Ok(())
+    }
+
     pub fn write_block(&mut self, block_num: usize, buffer_to_write: Cow<[u8]>)
     //pub fn write_block(&mut self, block_num: usize, buffer_to_write: Cow<[u8]>)
         -> Result<(), &'static str> 
         {
+            if self.detached {
+                return Err(DETACHED);
+            }
             let mut locked_device = self.storage_device.lock();
 
             let owned_buffer: Vec<u8> = match buffer_to_write {
@@ -150,6 +224,28 @@ impl BlockCache {
     }
 }
 
+/// Flushes all modified blocks to the storage device and drops all cached blocks,
+/// after which all accesses to this cache fail.
+impl StorageDeviceDependent for Mutex<BlockCache> {
+    fn name(&self) -> &str {
+        "block cache"
+    }
+
+    fn detach(&self, force: bool) -> Result<(), &'static str> {
+        let mut cache = self.lock();
+        if cache.detached {
+            return Ok(());
+        }
+        if let Err(e) = cache.flush(None) {
+            if !force {
+                return Err(e);
+            }
+        }
+        cache.invalidate_all();
+        cache.detached = true;
+        Ok(())
+    }
+}
 
 
 /// A block from a storage device stored in a cache.
@@ -0,0 +1,178 @@
+//! Parses the bootloader-provided kernel command line into [`BootArgs`],
+//! which override some of the compile-time defaults in [`kernel_config`]
+//! without rebuilding Theseus.
+//!
+//! The command line is a list of whitespace-separated `key=value` options:
+//! * `timeslice=<ms>`: the timeslice period in milliseconds, from 1 to 1000.
+//!   Defaults to [`CONFIG_TIMESLICE_PERIOD`].
+//! * `chip=pic|apic`: the interrupt controller to use. `pic` forces the legacy PIC,
+//!   just like the `force_pic` config option; `apic` is the default,
+//!   which still falls back to the PIC if the APIC is absent or broken.
+//! * `loglevel=error|warn|info|debug|trace`: the initial log level.
+//! * `smp=on|off`: whether to boot the secondary CPUs. Defaults to `on`.
+//! * `serialdebug=com1|com2|com3|com4`: the serial port on which to run
+//!   the `serial_debug` command interface. Disabled by default.
+//!
+//! Unknown or malformed options are logged and otherwise ignored,
+//! as is a leading kernel image path, which some bootloaders include.
+//!
+//! The command line is parsed by [`init()`] early in the boot process,
+//! before the heap is available, so parsing doesn't allocate.
+//! Until then, [`boot_args()`] returns the defaults.
+
+#![no_std]
+
+use core::time::Duration;
+use kernel_config::time::CONFIG_TIMESLICE_PERIOD;
+use log::{Level, warn};
+use spin::Once;
+
+/// The interrupt controller requested via the `chip` option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterruptChipArg {
+    /// Use the APIC, falling back to the PIC if the APIC is absent or broken.
+    Apic,
+    /// Use the legacy PIC, even if an APIC is present.
+    Pic,
+}
+
+/// The boot options parsed from the kernel command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BootArgs {
+    /// The timeslice period, if overridden by the `timeslice` option.
+    pub timeslice: Option<Duration>,
+    /// The interrupt controller requested by the `chip` option.
+    pub interrupt_chip: InterruptChipArg,
+    /// The initial log level, if overridden by the `loglevel` option.
+    pub log_level: Option<Level>,
+    /// Whether to boot the secondary CPUs, per the `smp` option.
+    pub smp: bool,
+    /// The number of the COM port (from 1 to 4) on which to run the serial debug
+    /// command interface, if enabled by the `serialdebug` option.
+    pub serial_debug: Option<u8>,
+}
+
+impl BootArgs {
+    /// The boot options used when the command line is absent or empty.
+    pub const DEFAULT: BootArgs = BootArgs {
+        timeslice: None,
+        interrupt_chip: InterruptChipArg::Apic,
+        log_level: None,
+        smp: true,
+        serial_debug: None,
+    };
+
+    /// Parses the given kernel `command_line`, logging and skipping any invalid options.
+    pub fn parse(command_line: &str) -> BootArgs {
+        let mut args = BootArgs::DEFAULT;
+        for option in command_line.split_ascii_whitespace() {
+            if let Err(e) = args.apply(option) {
+                warn!("Ignoring kernel command line option {:?}: {}", option, e);
+            }
+        }
+        args
+    }
+
+    /// Returns the timeslice period, either the overridden one or [`CONFIG_TIMESLICE_PERIOD`].
+    pub fn timeslice_period(&self) -> Duration {
+        self.timeslice.unwrap_or(CONFIG_TIMESLICE_PERIOD)
+    }
+
+    fn apply(&mut self, option: &str) -> Result<(), &'static str> {
+        let Some((key, value)) = option.split_once('=') else {
+            // Some bootloaders include the kernel image's path in the command line.
+            if option.starts_with('/') {
+                return Ok(());
+            }
+            return Err("expected an option of the form `key=value`");
+        };
+        match key {
+            "timeslice" => {
+                let millis = value.parse::<u64>().map_err(|_| "the timeslice must be an integer")?;
+                if !(1..=1000).contains(&millis) {
+                    return Err("the timeslice must be from 1 to 1000 milliseconds");
+                }
+                self.timeslice = Some(Duration::from_millis(millis));
+            }
+            "chip" => {
+                self.interrupt_chip = match value {
+                    "apic" => InterruptChipArg::Apic,
+                    "pic" => InterruptChipArg::Pic,
+                    _ => return Err("the interrupt chip must be `apic` or `pic`"),
+                };
+            }
+            "loglevel" => {
+                self.log_level = Some(value.parse().map_err(|_| "unknown log level")?);
+            }
+            "smp" => {
+                self.smp = match value {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err("smp must be `on` or `off`"),
+                };
+            }
+            "serialdebug" => {
+                let number = value.strip_prefix("com")
+                    .and_then(|n| n.parse::<u8>().ok())
+                    .filter(|n| (1..=4).contains(n))
+                    .ok_or("the serial debug port must be one of `com1` to `com4`")?;
+                self.serial_debug = Some(number);
+            }
+            _ => return Err("unknown option"),
+        }
+        Ok(())
+    }
+}
+
+static BOOT_ARGS: Once<BootArgs> = Once::new();
+
+/// Parses the given kernel `command_line` and records the resulting [`BootArgs`].
+///
+/// This should be invoked once, early in the boot process;
+/// subsequent invocations have no effect and return the originally-parsed options.
+pub fn init(command_line: &str) -> &'static BootArgs {
+    BOOT_ARGS.call_once(|| {
+        let args = BootArgs::parse(command_line);
+        log::info!("Kernel command line: {:?} => {:?}", command_line, args);
+        args
+    })
+}
+
+/// Returns the boot options parsed by [`init()`], or the defaults if it hasn't been invoked.
+pub fn boot_args() -> &'static BootArgs {
+    BOOT_ARGS.get().unwrap_or(&BootArgs::DEFAULT)
+}
+
+/// Returns the timeslice period in effect; see [`BootArgs::timeslice_period()`].
+pub fn timeslice_period() -> Duration {
+    boot_args().timeslice_period()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(BootArgs::parse(""), BootArgs::DEFAULT);
+        assert_eq!(BootArgs::parse("  /boot/kernel.bin "), BootArgs::DEFAULT);
+    }
+
+    #[test]
+    fn test_parse_all_options() {
+        let args = BootArgs::parse("timeslice=50 chip=pic loglevel=debug smp=off serialdebug=com2");
+        assert_eq!(args.timeslice, Some(Duration::from_millis(50)));
+        assert_eq!(args.interrupt_chip, InterruptChipArg::Pic);
+        assert_eq!(args.log_level, Some(Level::Debug));
+        assert!(!args.smp);
+        assert_eq!(args.serial_debug, Some(2));
+        assert_eq!(args.timeslice_period(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_parse_invalid_options_are_ignored() {
+        let args = BootArgs::parse("timeslice=0 chip=gic loglevel=loud smp=maybe serialdebug=com5 bogus=1 nokey");
+        assert_eq!(args, BootArgs::DEFAULT);
+        assert_eq!(args.timeslice_period(), CONFIG_TIMESLICE_PERIOD);
+    }
+}
@@ -11,10 +11,13 @@
 pub mod multiboot2;
 #[cfg(feature = "uefi")]
 pub mod uefi;
+mod validation;
 
 use core::iter::Iterator;
 use memory_structs::{PhysicalAddress, VirtualAddress};
 
+pub use validation::{Problem, Severity, ValidationReport, MAX_PROBLEMS};
+
 pub trait MemoryRegion {
     /// Returns the region's starting physical address.
     fn start(&self) -> PhysicalAddress;
@@ -205,6 +208,15 @@ pub trait BootInformation: 'static {
         self.len() == 0
     }
 
+    /// Checks the boot information for malformed or inconsistent contents.
+    ///
+    /// This should be invoked before anything else consumes the boot information.
+    /// If the returned report [is fatal](ValidationReport::is_fatal),
+    /// the other methods may return garbage or fault.
+    fn validate(&self) -> ValidationReport {
+        ValidationReport::new()
+    }
+
     /// Returns memory regions describing the physical memory.
     fn memory_regions(&self) -> Result<Self::MemoryRegions<'_>, &'static str>;
     /// Returns the kernel's ELF sections.
@@ -229,4 +241,7 @@ pub trait BootInformation: 'static {
 
     /// Returns information about the graphical framebuffer, if available.
     fn framebuffer_info(&self) -> Option<FramebufferInfo>;
+
+    /// Returns the kernel command line provided by the bootloader, if any.
+    fn command_line(&self) -> Option<&str>;
 }
@@ -1,4 +1,8 @@
-use crate::{ElfSectionFlags, ReservedMemoryRegion, FramebufferFormat};
+mod validation;
+
+pub use validation::check_info_pointer;
+
+use crate::{ElfSectionFlags, ReservedMemoryRegion, FramebufferFormat, ValidationReport};
 use core::{cmp, iter::Iterator};
 use kernel_config::memory::KERNEL_OFFSET;
 use memory_structs::{PhysicalAddress, VirtualAddress};
@@ -166,6 +170,10 @@ impl crate::BootInformation for multiboot2::BootInformation {
         self.total_size()
     }
 
+    fn validate(&self) -> ValidationReport {
+        validation::validate(self)
+    }
+
     fn memory_regions(&self) -> Result<Self::MemoryRegions<'_>, &'static str> {
         Ok(MemoryRegions {
             inner: self
@@ -261,4 +269,9 @@ impl crate::BootInformation for multiboot2::BootInformation {
             format,
         })
     }
+
+    fn command_line(&self) -> Option<&str> {
+        self.command_line_tag()
+            .and_then(|tag| tag.command_line().ok())
+    }
 }
@@ -0,0 +1,299 @@
+//! Validation of the raw multiboot2 information structure.
+//!
+//! The `multiboot2` crate trusts the bootloader: it follows tag sizes and string pointers
+//! without bounds checks, so a malformed structure causes a fault at some random later point.
+//! This module walks the raw bytes instead, and must run before anything else consumes them.
+
+use crate::{Problem, ValidationReport};
+use core::str;
+use kernel_config::memory::KERNEL_OFFSET;
+
+/// The size of the physical memory that the early boot code maps at `KERNEL_OFFSET`,
+/// which must contain the entire multiboot2 information structure.
+///
+/// See the `kernel_table` in the BIOS `boot.asm`, which maps one P2 table of 2MiB huge pages.
+const EARLY_MAPPED_SIZE: usize = 1 << 30;
+
+/// The size of the fixed header (`total_size` and `reserved`) of the information structure.
+const INFO_HEADER_SIZE: usize = 8;
+/// The size of the header (`type` and `size`) of each tag.
+const TAG_HEADER_SIZE: usize = 8;
+/// The size of the fixed fields at the start of the memory map tag.
+const MEMORY_MAP_HEADER_SIZE: usize = 16;
+/// The size of a memory map entry as of version 0 of the multiboot2 spec.
+const MIN_MEMORY_MAP_ENTRY_SIZE: usize = 24;
+/// The size of the fixed fields (`mod_start` and `mod_end`) after a module tag's header.
+const MODULE_FIELDS_SIZE: usize = 8;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_BOOTLOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_ELF_SECTIONS: u32 = 9;
+
+/// The memory map entry type of available RAM.
+const MEMORY_AVAILABLE: u32 = 1;
+
+/// Checks that the multiboot2 information structure at the given virtual address
+/// lies entirely within the memory mapped by the early boot code.
+///
+/// This must succeed before the structure is loaded, as loading it reads its header.
+pub fn check_info_pointer(vaddr: usize) -> Result<(), &'static str> {
+    if vaddr % 8 != 0 {
+        return Err("multiboot2 info is not 8-byte aligned");
+    }
+    let mapped_end = KERNEL_OFFSET + EARLY_MAPPED_SIZE;
+    if vaddr < KERNEL_OFFSET || vaddr > mapped_end - INFO_HEADER_SIZE {
+        return Err("multiboot2 info is outside of the early mapped memory");
+    }
+    // SAFETY: the header is aligned and within the early mapped memory, as checked above.
+    let total_size = unsafe { (vaddr as *const u32).read() } as usize;
+    if total_size < INFO_HEADER_SIZE + TAG_HEADER_SIZE {
+        return Err("multiboot2 info is too small to hold an end tag");
+    }
+    if total_size > mapped_end - vaddr {
+        return Err("multiboot2 info extends beyond the early mapped memory");
+    }
+    Ok(())
+}
+
+/// A tag's type and its bounds within the information structure.
+#[derive(Clone, Copy)]
+struct Tag {
+    tag_type: u32,
+    offset: usize,
+    size: usize,
+}
+
+/// Validates the given multiboot2 information structure.
+///
+/// The structure's header must have already been checked by [`check_info_pointer()`].
+pub(crate) fn validate(boot_info: &multiboot2::BootInformation) -> ValidationReport {
+    let mut report = ValidationReport::new();
+    // SAFETY: `check_info_pointer()` ensured that the whole structure is mapped.
+    let bytes = unsafe {
+        core::slice::from_raw_parts(boot_info.start_address() as *const u8, boot_info.total_size())
+    };
+
+    // First, check that the tags themselves are well-formed, since every later check walks them.
+    let mut has_end_tag = false;
+    let mut has_memory_map = false;
+    let mut has_elf_sections = false;
+    let mut tags = Tags::new(bytes);
+    for tag in tags.by_ref() {
+        match tag.tag_type {
+            TAG_END => has_end_tag = true,
+            TAG_CMDLINE | TAG_BOOTLOADER_NAME => check_string(bytes, tag, TAG_HEADER_SIZE, &mut report),
+            TAG_MODULE => check_string(bytes, tag, TAG_HEADER_SIZE + MODULE_FIELDS_SIZE, &mut report),
+            TAG_MEMORY_MAP => {
+                has_memory_map = true;
+                check_memory_map_geometry(bytes, tag, &mut report);
+            }
+            TAG_ELF_SECTIONS => has_elf_sections = true,
+            _ => { }
+        }
+    }
+    if let Some(problem) = tags.problem {
+        report.push(problem);
+    } else if !has_end_tag {
+        report.push(Problem::MissingEndTag);
+    }
+    if !has_memory_map {
+        report.push(Problem::MissingTag { name: "memory map" });
+    }
+    if !has_elf_sections {
+        report.push(Problem::MissingTag { name: "ELF sections" });
+    }
+    if report.is_fatal() {
+        return report;
+    }
+
+    // The tags are sound, so now check their contents for consistency.
+    check_memory_regions(bytes, &mut report);
+    check_modules(boot_info, bytes, &mut report);
+    report
+}
+
+/// An iterator over the well-formed tags, which stops at the end tag or the first malformed tag.
+struct Tags<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    done: bool,
+    /// The problem with the malformed tag that stopped this iterator, if any.
+    problem: Option<Problem>,
+}
+
+impl<'a> Tags<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: INFO_HEADER_SIZE, done: false, problem: None }
+    }
+}
+
+impl Iterator for Tags<'_> {
+    type Item = Tag;
+
+    fn next(&mut self) -> Option<Tag> {
+        if self.done {
+            return None;
+        }
+        self.done = true;
+        let offset = self.offset;
+        // If there's no room for another tag, the end tag is missing.
+        let tag_type = read_u32(self.bytes, offset)?;
+        let size = read_u32(self.bytes, offset + 4)? as usize;
+        if size < TAG_HEADER_SIZE || size > self.bytes.len() - offset {
+            self.problem = Some(Problem::TagOutOfBounds { tag_type, offset, size });
+            return None;
+        }
+        self.done = tag_type == TAG_END;
+        // Tags are padded such that the next one starts at an 8-byte aligned offset.
+        self.offset = (offset + size + 7) & !7;
+        Some(Tag { tag_type, offset, size })
+    }
+}
+
+/// Returns an iterator over the memory map entries as `(start, length, type)` tuples.
+///
+/// This must only be invoked once the memory map's geometry has been checked.
+fn memory_regions(bytes: &[u8]) -> impl Iterator<Item = (u64, u64, u32)> + '_ {
+    let memory_map = Tags::new(bytes).find(|tag| tag.tag_type == TAG_MEMORY_MAP);
+    let (entries_start, entries_end, entry_size) = memory_map
+        .and_then(|tag| Some((
+            tag.offset + MEMORY_MAP_HEADER_SIZE,
+            tag.offset + tag.size,
+            read_u32(bytes, tag.offset + TAG_HEADER_SIZE)? as usize,
+        )))
+        .unwrap_or((0, 0, MIN_MEMORY_MAP_ENTRY_SIZE));
+
+    (entries_start..entries_end)
+        .step_by(entry_size)
+        .take_while(move |offset| offset + MIN_MEMORY_MAP_ENTRY_SIZE <= entries_end)
+        .filter_map(move |offset| Some((
+            read_u64(bytes, offset)?,
+            read_u64(bytes, offset + 8)?,
+            read_u32(bytes, offset + 16)?,
+        )))
+}
+
+fn check_memory_map_geometry(bytes: &[u8], tag: Tag, report: &mut ValidationReport) {
+    let Some(entry_size) = read_u32(bytes, tag.offset + TAG_HEADER_SIZE).filter(|_| tag.size >= MEMORY_MAP_HEADER_SIZE) else {
+        report.push(Problem::TagOutOfBounds { tag_type: tag.tag_type, offset: tag.offset, size: tag.size });
+        return;
+    };
+    let entry_size = entry_size as usize;
+    if entry_size < MIN_MEMORY_MAP_ENTRY_SIZE || entry_size % 8 != 0 {
+        report.push(Problem::MemoryMapEntrySize { entry_size });
+        return;
+    }
+    let entries_size = tag.size - MEMORY_MAP_HEADER_SIZE;
+    if entries_size % entry_size != 0 {
+        report.push(Problem::MemoryMapTruncated { entries_size, entry_size });
+    }
+}
+
+fn check_memory_regions(bytes: &[u8], report: &mut ValidationReport) {
+    for (index, (start, len, region_type)) in memory_regions(bytes).enumerate() {
+        let Some(end) = start.checked_add(len) else {
+            report.push(Problem::MemoryRegionOverflow { start, len });
+            continue;
+        };
+        // Compare against each later region, such that each overlapping pair is reported once.
+        for (other_start, other_len, other_type) in memory_regions(bytes).skip(index + 1) {
+            let Some(other_end) = other_start.checked_add(other_len) else { continue };
+            if start < other_end && other_start < end {
+                report.push(Problem::MemoryRegionsOverlap {
+                    first: (start, end),
+                    second: (other_start, other_end),
+                    conflicting_types: (region_type == MEMORY_AVAILABLE) != (other_type == MEMORY_AVAILABLE),
+                });
+            }
+        }
+    }
+}
+
+fn check_modules(boot_info: &multiboot2::BootInformation, bytes: &[u8], report: &mut ValidationReport) {
+    let kernel = super::kernel_memory_region(boot_info)
+        .map(|region| (region.start.value() as u64, (region.start.value() + region.len) as u64))
+        .ok();
+
+    // Validation runs before the heap is available, so the module ranges are collected into an array.
+    let mut ranges = [(0, 0); MAX_CHECKED_MODULES];
+    let mut num_modules = 0;
+    let module_ranges = Tags::new(bytes)
+        .filter(|tag| tag.tag_type == TAG_MODULE)
+        .filter_map(|tag| Some((
+            read_u32(bytes, tag.offset + TAG_HEADER_SIZE)?,
+            read_u32(bytes, tag.offset + TAG_HEADER_SIZE + 4)?,
+        )));
+    for (slot, range) in ranges.iter_mut().zip(module_ranges) {
+        *slot = range;
+        num_modules += 1;
+    }
+
+    let modules = &ranges[..num_modules];
+    for (index, &(start, end)) in modules.iter().enumerate() {
+        if end < start {
+            report.push(Problem::ModuleInvalidRange { index, start, end });
+            continue;
+        }
+        if !is_available_ram(bytes, start as u64, end as u64) {
+            report.push(Problem::ModuleOutsideRam { index, start, end });
+        }
+        if let Some((kernel_start, kernel_end)) = kernel {
+            if (start as u64) < kernel_end && kernel_start < end as u64 {
+                report.push(Problem::ModuleOverlapsKernel { index, start, end });
+            }
+        }
+        for (other, &(other_start, other_end)) in modules.iter().enumerate().skip(index + 1) {
+            if start < other_end && other_start < end {
+                report.push(Problem::ModulesOverlap { first: index, second: other });
+            }
+        }
+    }
+}
+
+/// The maximum number of modules that are checked; any further modules are ignored.
+const MAX_CHECKED_MODULES: usize = 256;
+
+/// Returns `true` if the given physical range is covered by available memory regions,
+/// which may be adjacent to one another.
+fn is_available_ram(bytes: &[u8], start: u64, end: u64) -> bool {
+    let mut covered_until = start;
+    while covered_until < end {
+        let next = memory_regions(bytes)
+            .filter(|&(_, _, region_type)| region_type == MEMORY_AVAILABLE)
+            .filter_map(|(region_start, len, _)| Some((region_start, region_start.checked_add(len)?)))
+            .find(|&(region_start, region_end)| region_start <= covered_until && covered_until < region_end);
+        match next {
+            Some((_, region_end)) => covered_until = region_end,
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Checks that the string starting at `string_offset` within the given tag
+/// is NUL-terminated within that tag and is valid UTF-8.
+fn check_string(bytes: &[u8], tag: Tag, string_offset: usize, report: &mut ValidationReport) {
+    let string_bytes = bytes.get(tag.offset + string_offset .. tag.offset + tag.size).unwrap_or(&[]);
+    let Some(nul) = string_bytes.iter().position(|&b| b == 0) else {
+        report.push(Problem::UnterminatedString { tag_type: tag.tag_type, offset: tag.offset });
+        return;
+    };
+    if str::from_utf8(&string_bytes[..nul]).is_err() {
+        report.push(Problem::InvalidString { tag_type: tag.tag_type, offset: tag.offset });
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    bytes.get(offset .. offset.checked_add(4)?)
+        .and_then(|b| b.try_into().ok())
+        .map(u32::from_le_bytes)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    bytes.get(offset .. offset.checked_add(8)?)
+        .and_then(|b| b.try_into().ok())
+        .map(u64::from_le_bytes)
+}
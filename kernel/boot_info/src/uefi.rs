@@ -220,4 +220,9 @@ impl crate::BootInformation for &'static uefi_bootloader_api::BootInformation {
             format,
         })
     }
+
+    fn command_line(&self) -> Option<&str> {
+        // The UEFI bootloader doesn't pass a command line to the kernel.
+        None
+    }
 }
@@ -0,0 +1,167 @@
+//! Problems found while validating the boot information handed off by the bootloader.
+
+use core::fmt;
+
+/// The maximum number of problems that a [`ValidationReport`] can hold.
+///
+/// Validation runs before the heap is available, so the report has a fixed capacity.
+/// Any further problems are counted but not recorded.
+pub const MAX_PROBLEMS: usize = 32;
+
+/// How severe a [`Problem`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The boot information is suspicious, but can still be used.
+    Warning,
+    /// The boot information can't be trusted, so booting must be aborted.
+    Fatal,
+}
+
+/// A single problem found in the boot information.
+///
+/// Offsets are relative to the start of the boot information structure,
+/// and addresses are physical addresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// A tag's size is too small for its header or extends beyond the end of the boot information.
+    TagOutOfBounds { tag_type: u32, offset: usize, size: usize },
+    /// The boot information doesn't end with an end tag.
+    MissingEndTag,
+    /// A required tag is absent.
+    MissingTag { name: &'static str },
+    /// The memory map's entries are too small or misaligned.
+    MemoryMapEntrySize { entry_size: usize },
+    /// The memory map's entries don't evenly fill its tag, so the remainder is ignored.
+    MemoryMapTruncated { entries_size: usize, entry_size: usize },
+    /// A memory region's end address overflows.
+    MemoryRegionOverflow { start: u64, len: u64 },
+    /// Two memory regions overlap.
+    ///
+    /// This is fatal if only one of them is available RAM,
+    /// since the same memory can't be both usable and reserved.
+    MemoryRegionsOverlap { first: (u64, u64), second: (u64, u64), conflicting_types: bool },
+    /// A string in a tag isn't NUL-terminated within that tag.
+    UnterminatedString { tag_type: u32, offset: usize },
+    /// A string in a tag isn't valid UTF-8.
+    InvalidString { tag_type: u32, offset: usize },
+    /// A module's end address is before its start address.
+    ModuleInvalidRange { index: usize, start: u32, end: u32 },
+    /// A module isn't entirely within available RAM.
+    ModuleOutsideRam { index: usize, start: u32, end: u32 },
+    /// A module overlaps the kernel image.
+    ModuleOverlapsKernel { index: usize, start: u32, end: u32 },
+    /// Two modules overlap each other.
+    ModulesOverlap { first: usize, second: usize },
+}
+
+impl Problem {
+    /// Returns how severe this problem is.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::MemoryMapTruncated { .. } => Severity::Warning,
+            Self::MemoryRegionsOverlap { conflicting_types, .. } if !conflicting_types => Severity::Warning,
+            // Only module names are consumed by the kernel; other strings are merely informative.
+            Self::UnterminatedString { tag_type, .. } | Self::InvalidString { tag_type, .. }
+                if *tag_type != MODULE_TAG_TYPE => Severity::Warning,
+            _ => Severity::Fatal,
+        }
+    }
+}
+
+/// The multiboot2 tag type of a module, whose name must be valid.
+const MODULE_TAG_TYPE: u32 = 3;
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::TagOutOfBounds { tag_type, offset, size } =>
+                write!(f, "tag (type {tag_type}) at offset {offset:#X} has invalid size {size:#X}"),
+            Self::MissingEndTag =>
+                write!(f, "missing end tag"),
+            Self::MissingTag { name } =>
+                write!(f, "missing required {name} tag"),
+            Self::MemoryMapEntrySize { entry_size } =>
+                write!(f, "memory map has invalid entry size {entry_size}"),
+            Self::MemoryMapTruncated { entries_size, entry_size } =>
+                write!(f, "memory map entries ({entries_size} bytes) are not a multiple of the entry size {entry_size}"),
+            Self::MemoryRegionOverflow { start, len } =>
+                write!(f, "memory region at {start:#X} with length {len:#X} overflows"),
+            Self::MemoryRegionsOverlap { first, second, conflicting_types } =>
+                write!(f, "memory regions {:#X}..{:#X} and {:#X}..{:#X} overlap{}",
+                    first.0, first.1, second.0, second.1,
+                    if conflicting_types { " with conflicting types" } else { "" },
+                ),
+            Self::UnterminatedString { tag_type, offset } =>
+                write!(f, "string in tag (type {tag_type}) at offset {offset:#X} is not NUL-terminated"),
+            Self::InvalidString { tag_type, offset } =>
+                write!(f, "string in tag (type {tag_type}) at offset {offset:#X} is not valid UTF-8"),
+            Self::ModuleInvalidRange { index, start, end } =>
+                write!(f, "module {index} has an invalid range {start:#X}..{end:#X}"),
+            Self::ModuleOutsideRam { index, start, end } =>
+                write!(f, "module {index} at {start:#X}..{end:#X} is not within available RAM"),
+            Self::ModuleOverlapsKernel { index, start, end } =>
+                write!(f, "module {index} at {start:#X}..{end:#X} overlaps the kernel image"),
+            Self::ModulesOverlap { first, second } =>
+                write!(f, "modules {first} and {second} overlap"),
+        }
+    }
+}
+
+/// The problems found by validating the boot information.
+#[derive(Clone, Debug)]
+pub struct ValidationReport {
+    problems: [Option<Problem>; MAX_PROBLEMS],
+    len: usize,
+    num_unrecorded: usize,
+    is_fatal: bool,
+}
+
+impl ValidationReport {
+    /// Returns an empty report.
+    pub const fn new() -> Self {
+        Self {
+            problems: [None; MAX_PROBLEMS],
+            len: 0,
+            num_unrecorded: 0,
+            is_fatal: false,
+        }
+    }
+
+    /// Adds the given problem to this report.
+    pub fn push(&mut self, problem: Problem) {
+        self.is_fatal |= problem.severity() == Severity::Fatal;
+        match self.problems.get_mut(self.len) {
+            Some(slot) => {
+                *slot = Some(problem);
+                self.len += 1;
+            }
+            None => self.num_unrecorded += 1,
+        }
+    }
+
+    /// Returns the recorded problems in the order they were found.
+    pub fn problems(&self) -> impl Iterator<Item = &Problem> {
+        self.problems[..self.len].iter().flatten()
+    }
+
+    /// Returns the number of problems that were found but didn't fit in this report.
+    pub fn num_unrecorded(&self) -> usize {
+        self.num_unrecorded
+    }
+
+    /// Returns `true` if no problems were found.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0 && self.num_unrecorded == 0
+    }
+
+    /// Returns `true` if any problem, including unrecorded ones, is fatal.
+    pub fn is_fatal(&self) -> bool {
+        self.is_fatal
+    }
+}
+
+impl Default for ValidationReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
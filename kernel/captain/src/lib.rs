@@ -114,7 +114,7 @@ pub fn init(
                     .ok_or("could not allocate privilege stack")?,
             )
         };
-        interrupts::init(double_fault_stack.top_unusable(), privilege_stack.top_unusable())?
+        interrupts::init(double_fault_stack, privilege_stack)?
     };
 
     #[cfg(target_arch = "aarch64")] {
@@ -125,8 +125,12 @@ pub fn init(
         cpu::register_cpu(true)?;
     }
     
+    // By now, a monotonic clock source has been registered, which log throttling can use.
+    logger::set_throttle_clock(|| time::Instant::now().duration_since(time::Instant::ZERO));
+
     // get BSP's CPU ID
     let bsp_id = cpu::bootstrap_cpu().ok_or("captain::init(): couldn't get ID of bootstrap CPU!")?;
+    cpu_topology::detect_current_cpu();
     cls_allocator::reload_current_cpu();
 
     // Initialize the scheduler and create the initial `Task`,
@@ -149,6 +153,21 @@ pub fn init(
 
     let cpu_count = ap_count + 1;
     info!("Finished booting all {} AP cores; {} total CPUs are running.", ap_count, cpu_count);
+    #[cfg(target_arch = "x86_64")]
+    if let Some(reason) = interrupt_controller::pic_fallback_reason() {
+        log::warn!("Running in legacy PIC mode on a single CPU (nosmp), because {}", reason);
+    }
+    register_metrics(cpu_count)?;
+    metrics::set_value("boot.smp_ready_ms", uptime_ms())?;
+
+    // Every CPU now has a runqueue with an idle task, so from here on,
+    // a CPU that finds no runnable task at all is parked instead of continuing on.
+    task::scheduler::set_tasking_initialized();
+
+    // Now that every CPU has loaded its GDT, TSS, and IDT, make sure they're consistent
+    // before any CPU depends on them, e.g., upon a double fault or privilege change.
+    #[cfg(target_arch = "x86_64")]
+    interrupts::descriptor_tables::check_all_cpus(cpu_count)?;
     info!("Proceeding with system initialization, please wait...");
 
     // arch-gate: no framebuffer support on aarch64 at the moment
@@ -162,6 +181,10 @@ pub fn init(
     // Now that other CPUs are fully booted, init TLB shootdowns,
     // which rely on Local APICs to broadcast an IPI to all running CPUs.
     tlb_shootdown::init();
+
+    // If requested, wait for gdb to connect now that all CPUs are running and can be frozen.
+    #[cfg(all(gdbstub, target_arch = "x86_64"))]
+    gdbstub::init(idt)?;
     
     // Initialize the per-core heaps.
     // arch-gate: no multicore support on aarch64 at the moment
@@ -194,6 +217,16 @@ pub fn init(
     device_manager::init()?;
 
     task_fs::init()?;
+    cpu_topology::init_file()?;
+
+    // Now that all CPUs' TSCs have been synchronized, report the results
+    // and start periodically checking for drift between them.
+    #[cfg(target_arch = "x86_64")]
+    tsc_watchdog::init()?;
+
+    // Start polling each CPU's machine check banks for corrected hardware errors.
+    #[cfg(target_arch = "x86_64")]
+    mca::init()?;
 
     // create a SIMD personality
     #[cfg(simd_personality)] {
@@ -210,11 +243,20 @@ pub fn init(
     // Now that key subsystems are initialized, we can:
     // 1. Drop the items that needed to be held through initialization,
     drop_after_init.drop_all();
+    //    and give the physical memory that was only needed during boot back to the frame allocator,
+    consolidate_boot_memory()?;
+    //    and audit the kernel's page table now that its boot-time mappings are gone.
+    page_table_audit::init()?;
 
     // 2. Spawn various system tasks/daemons,
+    //    starting with the serial debug interface such that it claims its port before a console can.
+    if let Err(e) = serial_debug::start() {
+        error!("Couldn't start the serial debug command interface: {}", e);
+    }
     console::start_connection_detection()?;
 
     // 3. Start the first application(s).
+    metrics::set_value("boot.init_done_ms", uptime_ms())?;
     first_application::start()?;
 
     info!("captain::init(): initialization done! Spawning an idle task on BSP core {} and enabling interrupts...", bsp_id);
@@ -236,3 +278,42 @@ pub fn init(
         error!("BUG: captain::init(): captain's bootstrap task was rescheduled after being dead!");
     }
 }
+
+
+/// Returns the time elapsed since boot, in milliseconds.
+fn uptime_ms() -> u64 {
+    time::Instant::now().duration_since(time::Instant::ZERO).as_millis() as u64
+}
+
+/// Reclaims the physical memory that was only needed during boot, e.g., the AP trampoline,
+/// and merges contiguous free chunks, recording how fragmented physical memory is afterwards.
+fn consolidate_boot_memory() -> Result<(), &'static str> {
+    let reclaimed = memory::reclaim_boot_frames();
+    let merges = memory::coalesce_free_frames();
+    let report = memory::fragmentation_report();
+    info!("Reclaimed {} boot-time frames and merged {} free chunks; physical memory: {}", reclaimed, merges, report);
+    metrics::set_value("boot.reclaimed_frames", reclaimed as u64)?;
+    metrics::set_value("boot.free_extents", report.free_extents as u64)?;
+    metrics::set_value("boot.largest_free_extent_frames", report.largest_extent_frames as u64)?;
+    Ok(())
+}
+
+/// Registers metrics about kernel-wide state that isn't owned by any single subsystem,
+/// as reported in [`metrics::snapshot()`].
+fn register_metrics(cpu_count: u32) -> Result<(), &'static str> {
+    metrics::set_value("boot.cpus", cpu_count as u64)?;
+    metrics::register_gauge("mem.free_frames", || memory::free_frame_count() as u64)?;
+    metrics::register_gauge("tasks.count", || task::all_tasks().len() as u64)?;
+    metrics::register_gauge("log.records_suppressed", || {
+        logger::throttle_stats().iter().map(|site| site.suppressed).sum()
+    })?;
+    metrics::register_gauge("log.records_deduplicated", || {
+        logger::throttle_stats().iter().map(|site| site.deduplicated).sum()
+    })?;
+    metrics::register_gauge("fb.flushes", || early_printer::flush_stats().flushes)?;
+    metrics::register_gauge("fb.bytes_copied", || early_printer::flush_stats().bytes_copied)?;
+    #[cfg(target_arch = "x86_64")]
+    metrics::register_gauge("tsc.max_drift", tsc::max_observed_drift)?;
+    metrics::register_group("kassert", kassert::for_each_failed_site)?;
+    Ok(())
+}
@@ -40,8 +40,10 @@ impl ContextRegular {
             // it's used by the `ret` instruction
             x30_link_register: start_address,
 
-            // interrupts are initially unmasked/enabled
-            pstate: 0,
+            // IRQs and FIQs are initially masked (the DAIF `I` and `F` bits).
+            // When a task is first run, interrupts must remain disabled until
+            // its entry trampoline has completed the task switch and enables them.
+            pstate: (1 << 7) | (1 << 6),
         }
     }
 
@@ -32,9 +32,10 @@ impl ContextRegular {
     /// Task containing it to begin its execution at the given `rip`.
     pub fn new(rip: usize) -> ContextRegular {
         ContextRegular {
-            // The ninth bit is the interrupt enable flag. When a task is first
-            // run, interrupts should already be enabled.
-            rflags: 1 << 9,
+            // The ninth bit is the interrupt enable flag, which is cleared here.
+            // When a task is first run, interrupts must remain disabled until
+            // its entry trampoline has completed the task switch and enables them.
+            rflags: 0,
             r15: 0,
             r14: 0,
             r13: 0,
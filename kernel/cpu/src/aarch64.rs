@@ -68,6 +68,13 @@ pub fn current_cpu() -> CpuId {
     MpidrValue(MPIDR_EL1.get()).into()
 }
 
+/// Returns the ID of the currently executing CPU.
+///
+/// This always succeeds on aarch64, as the ID is read from the `MPIDR_EL1` register.
+pub fn try_current_cpu() -> Option<CpuId> {
+    Some(current_cpu())
+}
+
 /// A unique identifier for a CPU, read from the `MPIDR_EL1` register on aarch64.
 #[derive(
     Clone, Copy, Debug, Display, PartialEq, Eq, PartialOrd, Ord,
@@ -24,7 +24,9 @@ use derive_more::*;
 /// A unique identifier for a CPU core.
 ///
 /// A `CpuId` is a known-valid value that is guaranteed to correspond
-/// to a single CPU that actually exists on the current system.
+/// to a single CPU that actually exists on the current system,
+/// with one exception: on x86_64, [`current_cpu()`] returns an invalid ID
+/// early in an AP's bringup; see [`try_current_cpu()`].
 #[derive(
     Clone, Copy, Debug, Display, PartialEq, Eq, PartialOrd, Ord,
     Hash, Binary, Octal, LowerHex, UpperHex,
@@ -28,7 +28,7 @@ impl TryFrom<u32> for CpuId {
 
 // Returns an iterator over the available CPUs.
 pub fn cpus() -> impl Iterator<Item = CpuId> {
-    apic::get_lapics().iter().map(|(apic_id, _)| (*apic_id).into())
+    apic::cpu_ids().map(Into::into)
 }
 
 /// Returns the number of CPUs (SMP cores) that exist and
@@ -50,10 +50,19 @@ pub fn is_bootstrap_cpu() -> bool {
 }
 
 /// Returns the ID of the currently executing CPU.
+///
+/// Early in an AP's bringup, before its Local APIC has been initialized,
+/// the returned ID is [`apic::INVALID_APIC_ID`], which doesn't correspond to any CPU.
+/// See [`apic::current_cpu()`] for how per-CPU lookups must handle that.
 pub fn current_cpu() -> CpuId {
     apic::current_cpu().into()
 }
 
+/// Returns the ID of the currently executing CPU, or `None` if it isn't known yet.
+pub fn try_current_cpu() -> Option<CpuId> {
+    apic::try_current_cpu().map(Into::into)
+}
+
 /// A wrapper around `Option<CpuId>` with a forced type alignment of 8 bytes,
 /// which guarantees that it compiles down to lock-free native atomic instructions
 /// when using it inside of an atomic type like [`AtomicCell`].
@@ -0,0 +1,16 @@
+//! Topology detection is not yet supported on aarch64,
+//! so each CPU is treated as a separate core in a single package.
+
+use cpu::CpuId;
+use crate::CpuTopology;
+
+pub(crate) fn detect(cpu: CpuId) -> CpuTopology {
+    CpuTopology {
+        cpu,
+        apic_id: cpu.value(),
+        core: cpu.value(),
+        package: 0,
+        l2: None,
+        llc: None,
+    }
+}
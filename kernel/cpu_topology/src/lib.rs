@@ -0,0 +1,233 @@
+//! Detects the topology of the CPUs on this system, i.e., which CPUs are
+//! SMT siblings on the same physical core, which share a last-level cache (LLC),
+//! and which are in the same package.
+//!
+//! Each CPU detects its own topology via [`detect_current_cpu()`] as it is brought up.
+//! The scheduler uses the resulting map to resolve [`Placement`] hints for new tasks
+//! and to avoid placing tasks on SMT siblings of busy CPUs while whole cores are idle.
+//!
+//! The detected topology is available at any time by reading the `/cpu_topology` file.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{collections::{BTreeMap, BTreeSet}, format, string::String, sync::Arc, vec::Vec};
+use core::fmt::Write;
+use cpu::CpuId;
+use fs_node::{DirRef, File, FileOrDir, FsNode, WeakDirRef};
+use io::{ByteReader, ByteWriter, IoError, KnownLength};
+use memory::MappedPages;
+use spin::Mutex;
+
+#[cfg_attr(target_arch = "x86_64", path = "x86_64.rs")]
+#[cfg_attr(target_arch = "aarch64", path = "aarch64.rs")]
+mod arch;
+
+/// The name of the file in the root directory that reports the CPU topology.
+pub const CPU_TOPOLOGY_FILE_NAME: &str = "cpu_topology";
+
+/// The detected topology of every CPU that has been brought up.
+static TOPOLOGY: Mutex<BTreeMap<CpuId, CpuTopology>> = Mutex::new(BTreeMap::new());
+
+/// The position of a single CPU in the system's topology.
+///
+/// All IDs are unique system-wide, so two CPUs share a core, cache, or package
+/// if and only if their corresponding IDs are equal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuTopology {
+    pub cpu: CpuId,
+    /// The full (x2)APIC ID of this CPU, from which the other IDs are derived.
+    pub apic_id: u32,
+    /// The ID of the physical core that this CPU is a hardware thread of.
+    pub core: u32,
+    /// The ID of the package (socket) that this CPU is in.
+    pub package: u32,
+    /// The ID of the L2 cache used by this CPU, if known.
+    pub l2: Option<u32>,
+    /// The level and ID of the last-level cache used by this CPU, if known.
+    pub llc: Option<(u8, u32)>,
+}
+
+/// How close two CPUs are to each other, from nearest to farthest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Distance {
+    /// The two CPUs are the same CPU.
+    SameCpu,
+    /// The two CPUs are SMT siblings on the same physical core.
+    SameCore,
+    /// The two CPUs are on different cores that share a last-level cache.
+    SameLlc,
+    /// The two CPUs are in the same package but don't share a last-level cache.
+    SamePackage,
+    /// The two CPUs are in different packages.
+    Remote,
+}
+
+/// A hint for where to place a new task, relative to the topology of a given CPU.
+///
+/// The task is placed on the least busy CPU that satisfies the hint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placement {
+    /// On the given CPU or one of its SMT siblings.
+    SameCoreAs(CpuId),
+    /// On a CPU that shares the given CPU's last-level cache,
+    /// or the same package if the cache topology is unknown.
+    SameLlcAs(CpuId),
+    /// On the CPU whose entire physical core is the least busy,
+    /// such that it doesn't compete with other tasks on SMT siblings.
+    Spread,
+}
+
+impl Placement {
+    /// Returns whether the given CPU satisfies this hint.
+    ///
+    /// If the topology of either CPU is unknown, this only accepts the same CPU.
+    pub fn allows(&self, cpu: CpuId) -> bool {
+        match *self {
+            Placement::SameCoreAs(other) => distance(cpu, other)
+                .map_or(cpu == other, |d| d <= Distance::SameCore),
+            Placement::SameLlcAs(other) => distance(cpu, other)
+                .map_or(cpu == other, |d| d <= Distance::SameLlc),
+            Placement::Spread => true,
+        }
+    }
+}
+
+/// Detects the topology of the current CPU and records it.
+///
+/// This must be invoked on each CPU as it is brought up.
+pub fn detect_current_cpu() -> CpuTopology {
+    let topology = arch::detect(cpu::current_cpu());
+    TOPOLOGY.lock().insert(topology.cpu, topology);
+    topology
+}
+
+/// Returns the topology of the given CPU, if it has been detected.
+pub fn get(cpu: CpuId) -> Option<CpuTopology> {
+    TOPOLOGY.lock().get(&cpu).copied()
+}
+
+/// Returns the topology of all CPUs that have been detected, ordered by CPU ID.
+pub fn all() -> Vec<CpuTopology> {
+    TOPOLOGY.lock().values().copied().collect()
+}
+
+/// Returns how close the two given CPUs are, or `None` if either's topology is unknown.
+pub fn distance(a: CpuId, b: CpuId) -> Option<Distance> {
+    let map = TOPOLOGY.lock();
+    let (a, b) = (map.get(&a)?, map.get(&b)?);
+    Some(if a.cpu == b.cpu {
+        Distance::SameCpu
+    } else if a.core == b.core {
+        Distance::SameCore
+    } else if a.llc.is_some() && a.llc == b.llc {
+        Distance::SameLlc
+    } else if a.package == b.package {
+        // If the cache topology is unknown, a package is the best approximation of an LLC.
+        if a.llc.is_none() || b.llc.is_none() { Distance::SameLlc } else { Distance::SamePackage }
+    } else {
+        Distance::Remote
+    })
+}
+
+/// Returns the SMT siblings of the given CPU, excluding the CPU itself.
+pub fn smt_siblings(cpu: CpuId) -> Vec<CpuId> {
+    let map = TOPOLOGY.lock();
+    let core = match map.get(&cpu) {
+        Some(t) => t.core,
+        None => return Vec::new(),
+    };
+    map.values()
+        .filter(|t| t.core == core && t.cpu != cpu)
+        .map(|t| t.cpu)
+        .collect()
+}
+
+/// Returns all other CPUs ordered from nearest to farthest from the given CPU.
+///
+/// CPUs whose topology is unknown are considered the farthest.
+/// This is useful for a load balancer that prefers to take work from nearby CPUs,
+/// whose caches are more likely to still hold that work's data.
+pub fn cpus_by_distance(cpu: CpuId) -> Vec<(CpuId, Option<Distance>)> {
+    let mut others = cpu::cpus()
+        .filter(|&other| other != cpu)
+        .map(|other| (other, distance(cpu, other)))
+        .collect::<Vec<_>>();
+    others.sort_by_key(|&(other, d)| (d.unwrap_or(Distance::Remote), d.is_none(), other));
+    others
+}
+
+/// Returns a human-readable table of the topology of every CPU.
+pub fn report() -> String {
+    let map = TOPOLOGY.lock();
+    let mut out = String::new();
+    let packages = map.values().map(|t| t.package).collect::<BTreeSet<_>>().len();
+    let cores = map.values().map(|t| t.core).collect::<BTreeSet<_>>().len();
+    let _ = writeln!(out, "{} CPUs, {} cores, {} packages", map.len(), cores, packages);
+    let _ = writeln!(out, "{:<6} {:>10} {:>8} {:>8} {:>8} {:>12}", "CPU", "APIC_ID", "PACKAGE", "CORE", "L2", "LLC");
+    for t in map.values() {
+        let l2 = t.l2.map_or_else(|| String::from("-"), |id| format!("{id}"));
+        let llc = t.llc.map_or_else(|| String::from("-"), |(level, id)| format!("L{level}:{id}"));
+        let _ = writeln!(out, "{:<6} {:>10} {:>8} {:>8} {:>8} {:>12}",
+            t.cpu.value(), t.apic_id, t.package, t.core, l2, llc,
+        );
+    }
+    out
+}
+
+/// Creates the `/cpu_topology` file, which reports the topology of every CPU.
+pub fn init_file() -> Result<(), &'static str> {
+    let file = Arc::new(Mutex::new(CpuTopologyFile)) as fs_node::FileRef;
+    root::get_root().lock().insert(FileOrDir::File(file))?;
+    Ok(())
+}
+
+
+/// A lazily-generated file that reports the current CPU topology.
+struct CpuTopologyFile;
+
+impl FsNode for CpuTopologyFile {
+    fn get_name(&self) -> String {
+        String::from(CPU_TOPOLOGY_FILE_NAME)
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        Some(root::get_root().clone())
+    }
+
+    fn set_parent_dir(&mut self, _: WeakDirRef) {
+        // do nothing
+    }
+}
+
+impl ByteReader for CpuTopologyFile {
+    fn read_at(&mut self, buf: &mut [u8], offset: usize) -> Result<usize, IoError> {
+        let output = report();
+        if offset > output.len() {
+            return Err(IoError::InvalidInput);
+        }
+        let count = core::cmp::min(buf.len(), output.len() - offset);
+        buf[..count].copy_from_slice(&output.as_bytes()[offset..(offset + count)]);
+        Ok(count)
+    }
+}
+
+impl ByteWriter for CpuTopologyFile {
+    fn write_at(&mut self, _buffer: &[u8], _offset: usize) -> Result<usize, IoError> {
+        Err(IoError::from("the CPU topology report is read-only"))
+    }
+    fn flush(&mut self) -> Result<(), IoError> { Ok(()) }
+}
+
+impl KnownLength for CpuTopologyFile {
+    fn len(&self) -> usize {
+        report().len()
+    }
+}
+
+impl File for CpuTopologyFile {
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("the CPU topology report is autogenerated, cannot be memory mapped")
+    }
+}
@@ -0,0 +1,119 @@
+//! Topology detection via CPUID.
+//!
+//! The x2APIC ID of each CPU is composed of bit fields that identify its SMT thread,
+//! core, and package; the extended topology leaves (0x1F or 0xB) give the width of each field.
+//! The deterministic cache parameters leaves (0x4 on Intel, 0x8000001D on AMD)
+//! give the number of CPUs sharing each cache, from which a cache's ID is derived the same way.
+
+use core::arch::x86_64::{CpuidResult, __cpuid_count};
+use cpu::CpuId;
+use crate::CpuTopology;
+
+/// The level type of the SMT level in the extended topology leaves.
+const LEVEL_TYPE_SMT: u32 = 1;
+/// The cache type of a data cache in the cache parameters leaves.
+const CACHE_TYPE_DATA: u32 = 1;
+/// The cache type of a unified cache in the cache parameters leaves.
+const CACHE_TYPE_UNIFIED: u32 = 3;
+
+pub(crate) fn detect(cpu: CpuId) -> CpuTopology {
+    let max_leaf = cpuid(0, 0).eax;
+    let (apic_id, smt_shift, package_shift) = extended_topology(0x1F, max_leaf)
+        .or_else(|| extended_topology(0xB, max_leaf))
+        .unwrap_or_else(legacy_topology);
+
+    let mut l2 = None;
+    let mut llc = None;
+    let max_extended_leaf = cpuid(0x8000_0000, 0).eax;
+    let cache_leaf = if max_leaf >= 0x4 && cpuid(0x4, 0).eax & 0x1F != 0 {
+        Some(0x4)
+    } else if max_extended_leaf >= 0x8000_001D {
+        Some(0x8000_001D)
+    } else {
+        None
+    };
+    if let Some(leaf) = cache_leaf {
+        for subleaf in 0.. {
+            let eax = cpuid(leaf, subleaf).eax;
+            let cache_type = eax & 0x1F;
+            if cache_type == 0 {
+                break;
+            }
+            if cache_type != CACHE_TYPE_DATA && cache_type != CACHE_TYPE_UNIFIED {
+                continue;
+            }
+            let level = ((eax >> 5) & 0x7) as u8;
+            let sharing = ((eax >> 14) & 0xFFF) + 1;
+            let id = apic_id >> ceil_log2(sharing);
+            if level == 2 {
+                l2 = Some(id);
+            }
+            if llc.map_or(true, |(llc_level, _)| level > llc_level) {
+                llc = Some((level, id));
+            }
+        }
+    }
+
+    CpuTopology {
+        cpu,
+        apic_id,
+        core: apic_id >> smt_shift,
+        package: apic_id >> package_shift,
+        l2,
+        llc,
+    }
+}
+
+/// Parses the given extended topology leaf (0x1F or 0xB),
+/// returning the x2APIC ID, the width of its SMT field, and the width of all sub-package fields.
+fn extended_topology(leaf: u32, max_leaf: u32) -> Option<(u32, u32, u32)> {
+    if max_leaf < leaf {
+        return None;
+    }
+    let first = cpuid(leaf, 0);
+    // A leaf that reports no logical processors at its first level is unsupported.
+    if first.ebx & 0xFFFF == 0 {
+        return None;
+    }
+    let apic_id = first.edx;
+    let mut smt_shift = 0;
+    let mut package_shift = 0;
+    for subleaf in 0.. {
+        let result = cpuid(leaf, subleaf);
+        let level_type = (result.ecx >> 8) & 0xFF;
+        if level_type == 0 {
+            break;
+        }
+        let shift = result.eax & 0x1F;
+        if level_type == LEVEL_TYPE_SMT {
+            smt_shift = shift;
+        }
+        // Each level's shift covers all of the levels below it,
+        // so the last level's shift gives the width of everything below the package.
+        package_shift = shift;
+    }
+    Some((apic_id, smt_shift, package_shift))
+}
+
+/// Derives the topology from leaf 0x1 on CPUs that lack the extended topology leaves,
+/// which can't distinguish SMT threads from cores.
+fn legacy_topology() -> (u32, u32, u32) {
+    let result = cpuid(0x1, 0);
+    let apic_id = result.ebx >> 24;
+    let has_multiple_threads = result.edx & (1 << 28) != 0;
+    let package_shift = if has_multiple_threads {
+        ceil_log2((result.ebx >> 16) & 0xFF)
+    } else {
+        0
+    };
+    (apic_id, 0, package_shift)
+}
+
+fn ceil_log2(n: u32) -> u32 {
+    n.max(1).next_power_of_two().trailing_zeros()
+}
+
+fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    // SAFETY: CPUID is always available on x86_64.
+    unsafe { __cpuid_count(leaf, subleaf) }
+}
@@ -0,0 +1,186 @@
+//! Recording of the most recent branches in the Last Branch Record (LBR) MSRs.
+//!
+//! Only the LBR formats of Intel CPUs from Nehalem through Ice Lake are supported,
+//! which all share the same MSR addresses and differ only in their depth.
+//! Architectural LBRs (`CPUID.07H:EDX[19]`) use different MSRs and are not yet supported.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use msr::{IA32_DEBUGCTL, MSR_LASTBRANCH_0_TO_IP};
+use raw_cpuid::CpuId as X86CpuIdInstr;
+use spin::Once;
+use x86_64::registers::model_specific::Msr;
+
+/// The error returned when this CPU's LBRs can't be used.
+pub(crate) const UNSUPPORTED: &str = "branch recording (LBR) is unsupported on this CPU";
+
+/// The `LBR` bit in `IA32_DEBUGCTL`, which enables branch recording.
+const DEBUGCTL_LBR: u64 = 1 << 0;
+/// The LBR top-of-stack MSR on Nehalem and later.
+///
+/// Note that `msr::MSR_LASTBRANCH_TOS` is the address used by older NetBurst CPUs.
+const MSR_LBR_TOS: u32 = 0x1C9;
+/// The first "from" address MSR on Nehalem and later.
+const MSR_LBR_FROM_IP_BASE: u32 = 0x680;
+/// The first "to" address MSR on Nehalem and later.
+const MSR_LBR_TO_IP_BASE: u32 = MSR_LASTBRANCH_0_TO_IP;
+
+/// The maximum number of branches recorded by any supported CPU.
+pub const MAX_LBR_DEPTH: usize = 32;
+
+/// The maximum number of CPUs whose branch recording state is tracked.
+const MAX_CPUS: usize = 256;
+
+/// Whether branch recording is currently enabled on each CPU, indexed by CPU ID.
+///
+/// This avoids accessing `IA32_DEBUGCTL` on task switches that don't change it.
+static LBR_ACTIVE: [AtomicBool; MAX_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FALSE: AtomicBool = AtomicBool::new(false);
+    [FALSE; MAX_CPUS]
+};
+
+/// Whether each CPU's LBRs hold branches preserved by [`stop_branch_recording()`].
+static LBR_FROZEN: [AtomicBool; MAX_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FALSE: AtomicBool = AtomicBool::new(false);
+    [FALSE; MAX_CPUS]
+};
+
+static LBR_DEPTH: Once<Option<usize>> = Once::new();
+
+/// A single recorded branch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BranchRecord {
+    /// The address of the branch instruction.
+    pub from: u64,
+    /// The address that the branch jumped to.
+    pub to: u64,
+}
+
+/// A snapshot of the branches most recently recorded on a CPU.
+#[derive(Clone, Debug)]
+pub struct BranchRecords {
+    records: [BranchRecord; MAX_LBR_DEPTH],
+    len: usize,
+}
+
+impl BranchRecords {
+    /// Returns the recorded branches from the most recent to the oldest.
+    pub fn iter(&self) -> impl Iterator<Item = &BranchRecord> {
+        self.records[..self.len].iter()
+    }
+
+    /// Returns the number of recorded branches.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no branches were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Returns the number of branches this CPU records, or `None` if its LBRs are unsupported.
+pub fn lbr_depth() -> Option<usize> {
+    *LBR_DEPTH.call_once(detect_lbr_depth)
+}
+
+fn detect_lbr_depth() -> Option<usize> {
+    let cpuid = X86CpuIdInstr::new();
+    if cpuid.get_vendor_info()?.as_str() != "GenuineIntel" {
+        return None;
+    }
+    let feature_info = cpuid.get_feature_info()?;
+    // Hypervisors often don't virtualize the LBR MSRs, in which case accessing them would fault.
+    if feature_info.has_hypervisor() || feature_info.family_id() != 6 {
+        return None;
+    }
+    match feature_info.model_id() {
+        // Nehalem, Westmere, Sandy Bridge, Ivy Bridge, Haswell, Broadwell
+        0x1A | 0x1E | 0x1F | 0x2E | 0x25 | 0x2C | 0x2F | 0x2A | 0x2D | 0x3A | 0x3E |
+        0x3C | 0x3F | 0x45 | 0x46 | 0x3D | 0x47 | 0x4F | 0x56 => Some(16),
+        // Skylake, Kaby Lake, Coffee Lake, Cascade Lake, Ice Lake
+        0x4E | 0x5E | 0x8E | 0x9E | 0x55 | 0x66 | 0x7D | 0x7E | 0x6A | 0x6C => Some(32),
+        _ => None,
+    }
+}
+
+/// Enables or disables branch recording on the current CPU.
+///
+/// This must be invoked with preemption disabled.
+pub(crate) fn set_recording(enable: bool) {
+    if lbr_depth().is_none() {
+        return;
+    }
+    let Some(active) = LBR_ACTIVE.get(cpu::current_cpu().value() as usize) else {
+        return;
+    };
+    if active.load(Ordering::Relaxed) != enable {
+        write_debugctl_lbr(enable);
+        active.store(enable, Ordering::Relaxed);
+    }
+}
+
+/// Stops branch recording on the current CPU, preserving the branches recorded so far
+/// for [`take_branch_records()`].
+///
+/// This should be the first thing a fatal exception handler does,
+/// since every branch taken before recording stops displaces an older one.
+/// Recording resumes at the next task switch, if the next task has opted in.
+pub fn stop_branch_recording() {
+    if lbr_depth().is_none() {
+        return;
+    }
+    let cpu = cpu::current_cpu().value() as usize;
+    if let (Some(active), Some(frozen)) = (LBR_ACTIVE.get(cpu), LBR_FROZEN.get(cpu)) {
+        if active.load(Ordering::Relaxed) {
+            write_debugctl_lbr(false);
+            active.store(false, Ordering::Relaxed);
+            frozen.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Returns the branches preserved on the current CPU by [`stop_branch_recording()`].
+pub fn take_branch_records() -> Result<BranchRecords, &'static str> {
+    let depth = lbr_depth().ok_or(UNSUPPORTED)?;
+    let was_frozen = LBR_FROZEN.get(cpu::current_cpu().value() as usize)
+        .map_or(false, |frozen| frozen.swap(false, Ordering::Relaxed));
+    if !was_frozen {
+        return Err("branch recording was not enabled for the current task");
+    }
+
+    let mut records = BranchRecords { records: [BranchRecord::default(); MAX_LBR_DEPTH], len: 0 };
+    // SAFETY: `lbr_depth()` confirmed that this CPU has these MSRs.
+    let tos = unsafe { Msr::new(MSR_LBR_TOS).read() } as usize;
+    for i in 0..depth {
+        // Walk backwards from the top of the stack, i.e., from the most recent branch.
+        let index = ((tos + depth - i) % depth) as u32;
+        // SAFETY: `index` is less than this CPU's LBR depth.
+        let (from, to) = unsafe {
+            (Msr::new(MSR_LBR_FROM_IP_BASE + index).read(), Msr::new(MSR_LBR_TO_IP_BASE + index).read())
+        };
+        if from == 0 && to == 0 {
+            break;
+        }
+        records.records[i] = BranchRecord { from: canonicalize(from), to: canonicalize(to) };
+        records.len += 1;
+    }
+    Ok(records)
+}
+
+fn write_debugctl_lbr(enable: bool) {
+    let mut debugctl = Msr::new(IA32_DEBUGCTL);
+    // SAFETY: `IA32_DEBUGCTL` exists on all CPUs that support LBRs,
+    //         and only the LBR bit is changed.
+    unsafe {
+        let value = debugctl.read();
+        debugctl.write(if enable { value | DEBUGCTL_LBR } else { value & !DEBUGCTL_LBR });
+    }
+}
+
+/// Strips the flags that some LBR formats store in the upper bits of an address.
+fn canonicalize(address: u64) -> u64 {
+    (((address << 16) as i64) >> 16) as u64
+}
@@ -0,0 +1,98 @@
+//! Opt-in "deep crash context" that records how execution reached a fault.
+//!
+//! A crash report normally only captures the state at the time of the fault,
+//! which says little about sporadic faults like an invalid opcode
+//! or a general protection fault at a garbage instruction pointer.
+//! This crate offers two opt-in features for such cases:
+//!
+//! * **Branch recording**: the CPU continuously records its most recent branches
+//!   in the Last Branch Record (LBR) MSRs, which `exceptions_full` reads and symbolizes
+//!   into the crash report to show the control-flow path that led to the fault.
+//!   This is enabled for all tasks via [`set_enabled_for_all()`]
+//!   or for a single task via [`set_enabled_for_task()`].
+//! * **Single-step tracing**: [`trace_task()`] uses the trap flag to record the
+//!   instruction pointer of each of the next `n` instructions executed by a given task
+//!   into a trace buffer, which can be read via [`trace_buffer()`].
+//!
+//! Both features are detected at runtime and return an error if they are unsupported,
+//! rather than faulting on an MSR access.
+//! The LBR enable bit in `IA32_DEBUGCTL` is swapped on every task switch,
+//! so only the tasks that opted in pay the cost of branch recording.
+//!
+//! The `crashctx` application controls both features from the shell.
+
+#![no_std]
+
+extern crate alloc;
+
+mod lbr;
+mod single_step;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use task::Task;
+
+pub use lbr::{
+    lbr_depth, stop_branch_recording, take_branch_records, BranchRecord, BranchRecords, MAX_LBR_DEPTH,
+};
+pub use single_step::{
+    trace_task, cancel_trace, trace_status, trace_buffer, handle_debug_exception, TRACE_BUFFER_LEN,
+};
+
+/// Whether branch recording is enabled for all tasks, regardless of their own setting.
+static ENABLED_FOR_ALL: AtomicBool = AtomicBool::new(false);
+
+/// Whether any deep crash context feature has ever been requested.
+///
+/// Until then, task switches skip all of the work in [`on_task_switch()`].
+static ARMED: AtomicBool = AtomicBool::new(false);
+
+/// Registers this crate's task switch hook and marks single-step tracing as available.
+///
+/// This must be invoked by whichever crate installs the `#DB` handler
+/// that calls [`handle_debug_exception()`].
+pub fn init() {
+    task::set_task_switch_debug_func(on_task_switch);
+    single_step::set_handler_installed();
+}
+
+/// Enables or disables branch recording for all tasks.
+///
+/// This takes effect on each CPU at its next task switch.
+pub fn set_enabled_for_all(enable: bool) -> Result<(), &'static str> {
+    lbr::lbr_depth().ok_or(lbr::UNSUPPORTED)?;
+    ENABLED_FOR_ALL.store(enable, Ordering::Relaxed);
+    arm();
+    Ok(())
+}
+
+/// Returns whether branch recording is enabled for all tasks.
+pub fn is_enabled_for_all() -> bool {
+    ENABLED_FOR_ALL.load(Ordering::Relaxed)
+}
+
+/// Enables or disables branch recording for the given task.
+///
+/// This takes effect the next time that task is switched to.
+pub fn set_enabled_for_task(task: &Task, enable: bool) -> Result<(), &'static str> {
+    lbr::lbr_depth().ok_or(lbr::UNSUPPORTED)?;
+    task.set_deep_crash_context(enable);
+    arm();
+    Ok(())
+}
+
+/// Marks this crate as armed such that task switches start invoking [`on_task_switch()`].
+pub(crate) fn arm() {
+    ARMED.store(true, Ordering::Relaxed);
+}
+
+/// Swaps in the newly-current task's branch recording and single-step state.
+fn on_task_switch() {
+    if !ARMED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Ok((task_id, opted_in)) = task::with_current_task(|t| (t.id, t.deep_crash_context())) else {
+        return;
+    };
+    lbr::set_recording(ENABLED_FOR_ALL.load(Ordering::Relaxed) || opted_in);
+    single_step::on_task_switch(task_id);
+}
@@ -0,0 +1,152 @@
+//! Tracing the instructions executed by a task by single-stepping it with the trap flag.
+//!
+//! Once [`trace_task()`] is invoked, the target task's trap flag (`RFLAGS.TF`) is set
+//! the next time it is switched to. Each instruction it then executes raises a `#DB`,
+//! whose handler records the instruction pointer via [`handle_debug_exception()`].
+//! Since `RFLAGS` is saved and restored by each context switch, the trap flag follows
+//! the target task across preemptions and migrations until the trace completes.
+//! Interrupt handlers aren't traced, because the CPU clears the trap flag when entering them.
+
+use alloc::vec::Vec;
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// The maximum number of instruction pointers that a single trace can record.
+pub const TRACE_BUFFER_LEN: usize = 4096;
+
+/// The trap flag in `RFLAGS`, which causes a `#DB` after each instruction.
+const RFLAGS_TF: u64 = 1 << 8;
+/// The `BS` bit in `DR6`, which indicates that a `#DB` was caused by single-stepping.
+const DR6_BS: u64 = 1 << 14;
+/// The value of [`TARGET`] when no task is being traced.
+const NO_TARGET: usize = usize::MAX;
+
+/// The ID of the task being traced, or [`NO_TARGET`].
+static TARGET: AtomicUsize = AtomicUsize::new(NO_TARGET);
+/// The number of instructions that remain to be traced.
+static REMAINING: AtomicUsize = AtomicUsize::new(0);
+/// The recorded instruction pointers.
+static TRACE_BUFFER: [AtomicU64; TRACE_BUFFER_LEN] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; TRACE_BUFFER_LEN]
+};
+/// The number of valid entries in [`TRACE_BUFFER`].
+static TRACE_LEN: AtomicUsize = AtomicUsize::new(0);
+/// Whether a `#DB` handler that invokes [`handle_debug_exception()`] has been installed.
+static HANDLER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn set_handler_installed() {
+    HANDLER_INSTALLED.store(true, Ordering::Release);
+}
+
+/// Starts tracing the next `count` instructions executed by the task with the given ID,
+/// up to [`TRACE_BUFFER_LEN`] instructions.
+///
+/// The trace begins the next time that task is switched to,
+/// and replaces the contents of the trace buffer.
+/// Only one task can be traced at a time.
+pub fn trace_task(task_id: usize, count: usize) -> Result<(), &'static str> {
+    if !HANDLER_INSTALLED.load(Ordering::Acquire) {
+        return Err("single-step tracing is unsupported: no #DB handler supports it");
+    }
+    if count == 0 {
+        return Err("the number of instructions to trace must be nonzero");
+    }
+    if task_id == task::current_id() {
+        return Err("a task cannot trace itself");
+    }
+    TARGET.compare_exchange(NO_TARGET, task_id, Ordering::AcqRel, Ordering::Acquire)
+        .map_err(|_| "another task is already being traced")?;
+    TRACE_LEN.store(0, Ordering::Release);
+    REMAINING.store(count.min(TRACE_BUFFER_LEN), Ordering::Release);
+    crate::arm();
+    Ok(())
+}
+
+/// Stops the current trace, if any, keeping what was recorded so far.
+///
+/// The target's trap flag is cleared at its next single-step exception.
+pub fn cancel_trace() {
+    REMAINING.store(0, Ordering::Release);
+    TARGET.store(NO_TARGET, Ordering::Release);
+}
+
+/// Returns the ID of the task being traced and the number of instructions that remain,
+/// or `None` if no trace is in progress.
+pub fn trace_status() -> Option<(usize, usize)> {
+    match TARGET.load(Ordering::Acquire) {
+        NO_TARGET => None,
+        target => Some((target, REMAINING.load(Ordering::Acquire))),
+    }
+}
+
+/// Returns the instruction pointers recorded by the most recent trace, in execution order.
+pub fn trace_buffer() -> Vec<u64> {
+    let len = TRACE_LEN.load(Ordering::Acquire).min(TRACE_BUFFER_LEN);
+    TRACE_BUFFER[..len].iter().map(|rip| rip.load(Ordering::Relaxed)).collect()
+}
+
+/// Sets the trap flag if the newly-current task is the one being traced.
+pub(crate) fn on_task_switch(task_id: usize) {
+    if TARGET.load(Ordering::Acquire) == task_id && REMAINING.load(Ordering::Acquire) > 0 {
+        // SAFETY: setting the trap flag only causes `#DB` exceptions,
+        //         which are handled by `handle_debug_exception()`.
+        unsafe {
+            asm!(
+                "pushfq",
+                "or qword ptr [rsp], {tf}",
+                "popfq",
+                tf = const RFLAGS_TF,
+            );
+        }
+    }
+}
+
+/// Handles a `#DB` exception caused by single-stepping a traced task.
+///
+/// This must be invoked by the `#DB` handler, which can pass along its `stack_frame`.
+///
+/// ## Return
+/// Returns `true` if this `#DB` was caused by single-stepping, `false` otherwise.
+pub fn handle_debug_exception(stack_frame: &mut InterruptStackFrame) -> bool {
+    let dr6: u64;
+    // SAFETY: reading and clearing DR6 has no side effects besides resetting its sticky bits.
+    unsafe {
+        asm!("mov {}, dr6", out(reg) dr6, options(nomem, nostack, preserves_flags));
+        asm!("mov dr6, {}", in(reg) 0u64, options(nomem, nostack, preserves_flags));
+    }
+    if dr6 & DR6_BS == 0 {
+        return false;
+    }
+
+    let target = TARGET.load(Ordering::Acquire);
+    if target != NO_TARGET && target == task::current_id() {
+        let remaining = REMAINING.load(Ordering::Acquire);
+        if remaining > 0 {
+            let index = TRACE_LEN.load(Ordering::Relaxed);
+            if let Some(slot) = TRACE_BUFFER.get(index) {
+                slot.store(stack_frame.instruction_pointer.as_u64(), Ordering::Relaxed);
+                TRACE_LEN.store(index + 1, Ordering::Release);
+            }
+            REMAINING.store(remaining - 1, Ordering::Release);
+            if remaining > 1 {
+                return true;
+            }
+            TARGET.store(NO_TARGET, Ordering::Release);
+        }
+    } else if target != NO_TARGET {
+        // A traced task is still being switched to or from, so keep its trap flag set.
+        return true;
+    }
+
+    // The trace has completed or was cancelled, so stop single-stepping.
+    // SAFETY: only the trap flag is cleared, which doesn't affect the interrupted code.
+    unsafe {
+        stack_frame.as_mut().update(|frame| frame.cpu_flags &= !RFLAGS_TF);
+    }
+    true
+}
@@ -42,6 +42,12 @@
 //! It is typically best to use a lock-free queue or an interrupt-safe mutex
 //! to share such information between the interrupt handler and deferred task.
 //!
+//! On x86_64, the time spent in each deferred task is accounted to its CPU's
+//! [system time](interrupts::system_time). If deferred tasks exceed that module's
+//! cap on a CPU's time, e.g., during a flood of interrupts, each deferred task
+//! sleeps for a timeslice before handling more work, such that
+//! ordinary tasks on that CPU keep making progress.
+//!
 
 #![no_std]
 #![cfg_attr(target_arch = "x86_64", feature(abi_x86_interrupt))]
@@ -51,6 +57,7 @@ extern crate alloc;
 use log::error;
 use debugit::debugit;
 use alloc::string::String;
+use cpu::CpuId;
 use task::{get_my_current_task, JoinableTaskRef};
 use interrupts::{InterruptHandler, InterruptNumber};
 
@@ -126,16 +133,42 @@ pub fn register_interrupt_handler<DIA, Arg, Success, Failure, S>(
             }
         })?;
 
+    spawn_deferred_task(deferred_interrupt_action, deferred_action_argument, deferred_task_name, None)
+        .map_err(InterruptRegistrationError::SpawnError)
+}
+
+
+/// Spawns a deferred task that isn't tied to any interrupt handler,
+/// which is otherwise identical to the deferred task spawned by [`register_interrupt_handler()`].
+///
+/// This is useful when the deferred task's work arrives from a source other than
+/// a single interrupt, e.g., from several interrupt handlers or from a test.
+/// The task is initially blocked, and it must be unblocked whenever it has work to do.
+///
+/// If `pin_on_cpu` is `Some`, the task is pinned to that CPU.
+pub fn spawn_deferred_task<DIA, Arg, Success, Failure, S>(
+    deferred_action: DIA,
+    deferred_action_argument: Arg,
+    deferred_task_name: Option<S>,
+    pin_on_cpu: Option<CpuId>,
+) -> Result<JoinableTaskRef, &'static str>
+    where DIA: Fn(&Arg) -> Result<Success, Failure> + Send + 'static,
+          Arg: Send + 'static,
+          S: Into<String>,
+{
     // Spawn the deferred task, which should be initially blocked from running.
     // It will be unblocked by the interrupt handler whenever it needs to run.
     let mut tb = spawn::new_task_builder(
         deferred_task_entry_point::<DIA, Arg, Success, Failure>,
-        (deferred_interrupt_action, deferred_action_argument),
+        (deferred_action, deferred_action_argument),
     ).block();
     if let Some(name) = deferred_task_name {
         tb = tb.name(name.into());
     }
-    tb.spawn().map_err(InterruptRegistrationError::SpawnError)
+    if let Some(cpu) = pin_on_cpu {
+        tb = tb.pin_on_cpu(cpu);
+    }
+    tb.spawn()
 }
 
 
@@ -157,6 +190,18 @@ fn deferred_task_entry_point<DIA, Arg, Success, Failure>(
     // );
 
     loop {
+        // If deferred tasks have used more than their share of this CPU,
+        // give other tasks a chance to run before handling more work.
+        #[cfg(target_arch = "x86_64")]
+        if interrupts::system_time::deferred_work_over_cap() {
+            let _ = sleep::sleep(boot_args::timeslice_period());
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        let _res = interrupts::system_time::account_deferred_work(
+            || deferred_interrupt_action(&deferred_action_argument)
+        );
+        #[cfg(not(target_arch = "x86_64"))]
         let _res = deferred_interrupt_action(&deferred_action_argument);
         
         // Note: here, upon failure, we could return from this loop task entirely instead of just logging the error.
@@ -0,0 +1,175 @@
+//! The drivers built into the device manager, which are registered in the [`registry`]
+//! by [`crate::init()`].
+//!
+//! [`registry`]: crate::registry
+
+use core::time::Duration;
+use log::*;
+use time::Instant;
+use crate::registry::{Driver, ShutdownHook, ShutdownMode};
+
+#[cfg(target_arch = "x86_64")]
+use {
+    crate::{optional_drivers, KEY_PRODUCER},
+    serial_port::{SerialPortAddress, init_serial_port, take_serial_port_basic},
+};
+
+/// The built-in drivers, in registration order.
+pub(crate) static DRIVERS: &[Driver] = &[
+    // COM1 is the only UART on aarch64; it's used for logging as well as for the console.
+    #[cfg(target_arch = "x86_64")]
+    Driver { name: "serial", depends_on: &[], init: init_serial_ports },
+    // PS/2 is x86_64 only
+    #[cfg(target_arch = "x86_64")]
+    Driver { name: "ps2", depends_on: &[], init: init_ps2 },
+    #[cfg(target_arch = "x86_64")]
+    Driver { name: "keyboard", depends_on: &["ps2"], init: init_keyboard },
+    #[cfg(target_arch = "x86_64")]
+    Driver { name: "platform", depends_on: &["ps2"], init: init_platform_devices },
+    Driver { name: "pci", depends_on: &[], init: init_pci },
+    Driver { name: "pci_devices", depends_on: &["pci"], init: init_pci_devices },
+];
+
+/// The shutdown hooks of the built-in drivers and other core components,
+/// which are registered in the [`registry`](crate::registry) once drivers are initialized.
+pub(crate) static SHUTDOWN_HOOKS: &[ShutdownHook] = &[
+    // The final log records must be written out before the devices that may hold them stop.
+    ShutdownHook {
+        name: "logger",
+        depends_on: &["serial", "storage"],
+        quiesce: quiesce_logger,
+        flush: flush_logger,
+        timeout: Duration::from_millis(100),
+        best_effort: true,
+    },
+    // No storage device support on aarch64 at the moment
+    #[cfg(target_arch = "x86_64")]
+    ShutdownHook {
+        name: "storage",
+        depends_on: &["pci_devices"],
+        quiesce: quiesce_storage,
+        flush: flush_storage,
+        timeout: Duration::from_secs(5),
+        best_effort: true,
+    },
+];
+
+/// The logger keeps accepting records during shutdown, so that shutdown itself can be logged.
+fn quiesce_logger(_mode: ShutdownMode) { }
+
+/// Writes out any log records that are still pending, e.g., suppressed repeats.
+fn flush_logger(_mode: ShutdownMode, _deadline: Instant) -> Result<(), &'static str> {
+    log::logger().flush();
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn quiesce_storage(mode: ShutdownMode) {
+    storage_manager::quiesce(mode == ShutdownMode::BestEffort);
+}
+
+#[cfg(target_arch = "x86_64")]
+fn flush_storage(_mode: ShutdownMode, deadline: Instant) -> Result<(), &'static str> {
+    storage_manager::flush_all(deadline)
+}
+
+/// Ensures that both COM1 and COM2 are initialized, for logging and/or headless operation.
+///
+/// If a serial port was used for logging (as configured in [`logger::early_init()`]),
+/// its inputs are ignored for purposes of starting new console instances.
+#[cfg(target_arch = "x86_64")]
+fn init_serial_ports() -> Result<(), &'static str> {
+    let init_serial_port = |spa: SerialPortAddress| {
+        if let Some(sp) = take_serial_port_basic(spa) {
+            init_serial_port(spa, sp);
+        } else {
+            console::ignore_serial_port_input(spa as u16);
+            info!("Ignoring input on {:?} because it is being used for logging.", spa);
+        }
+    };
+    init_serial_port(SerialPortAddress::COM1);
+    init_serial_port(SerialPortAddress::COM2);
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn init_ps2() -> Result<(), &'static str> {
+    ps2::init().map(|_controller| ())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn init_keyboard() -> Result<(), &'static str> {
+    let Some(keyboard) = ps2::controller().and_then(|c| c.keyboard_ref()) else {
+        info!("No PS/2 keyboard is attached.");
+        return Ok(());
+    };
+    let producer = KEY_PRODUCER.lock()
+        .take()
+        .ok_or("BUG: the keyboard event queue producer was missing")?;
+    keyboard::init(keyboard, producer)
+}
+
+/// Probes and initializes the compiled-in optional drivers for platform (non-PCI) devices.
+#[cfg(target_arch = "x86_64")]
+fn init_platform_devices() -> Result<(), &'static str> {
+    optional_drivers::init_platform_drivers();
+    Ok(())
+}
+
+/// Initializes/scans the PCI bus to discover PCI devices.
+fn init_pci() -> Result<(), &'static str> {
+    for dev in pci::pci_device_iter()? {
+        debug!("Found PCI device: {:X?}", dev);
+    }
+    Ok(())
+}
+
+/// Iterates over all PCI devices and initializes the drivers for the devices we support.
+fn init_pci_devices() -> Result<(), &'static str> {
+    for dev in pci::pci_device_iter()? {
+        // Currently we skip Bridge devices, since we have no use for them yet.
+        if dev.class == 0x06 {
+            continue;
+        }
+
+        // If this is a storage device, initialize it as such.
+        // No storage device support on aarch64 at the moment
+        #[cfg(target_arch = "x86_64")]
+        match storage_manager::init_device(dev) {
+            // Successfully initialized this storage device.
+            Ok(Some(_storage_controller)) => continue,
+
+            // Not a storage device, so fall through and let another handler deal with it.
+            Ok(None) => { }
+
+            // Error initializing this device, so skip it.
+            Err(e) => {
+                error!("Failed to initialize storage device, it will be unavailable.\n{:?}\nError: {}", dev, e);
+                continue;
+            }
+        }
+
+        // Check whether one of the compiled-in optional drivers supports this device.
+        // No NIC support on aarch64 at the moment
+        #[cfg(target_arch = "x86_64")]
+        if optional_drivers::init_pci_device(dev) {
+            continue;
+        }
+
+        warn!("Ignoring PCI device with no handler. {:X?}", dev);
+    }
+
+    // No storage device support on aarch64 at the moment
+    #[cfg(target_arch = "x86_64")]
+    if let Err(e) = storage_manager::init_stats_file() {
+        error!("Failed to create the disk statistics file: {}", e);
+    }
+
+    // Once all devices have been initialized, let the optional drivers complete their setup,
+    // e.g., adding all of their NICs to the list of network interfaces.
+    // No NIC support on aarch64 at the moment
+    #[cfg(target_arch = "x86_64")]
+    optional_drivers::finish_all();
+
+    Ok(())
+}
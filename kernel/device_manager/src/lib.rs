@@ -5,18 +5,29 @@ extern crate alloc;
 
 use log::*;
 
+#[cfg(target_arch = "x86_64")]
+mod optional_drivers;
+#[cfg(target_arch = "x86_64")]
+pub use optional_drivers::{optional_driver_states, DriverState, ALL_OPTIONAL_DRIVERS};
+
+pub mod registry;
+mod builtin_drivers;
+
 #[cfg(target_arch = "x86_64")]
 use {
+    spin::Mutex,
     mpmc::Queue,
     event_types::Event,
     memory::MemoryManagementInfo,
-    alloc::vec::Vec,
     io::{ByteReaderWriterWrapper, LockableIo, ReaderWriter},
     storage_manager::StorageDevice,
     memory::PhysicalAddress,
-    serial_port::{SerialPortAddress, init_serial_port, take_serial_port_basic},
 };
 
+/// The producer end of the keyboard event queue, consumed when the keyboard driver is initialized.
+#[cfg(target_arch = "x86_64")]
+static KEY_PRODUCER: Mutex<Option<Queue<Event>>> = Mutex::new(None);
+
 /// Performs early-stage initialization for simple devices needed during early boot.
 ///
 /// This includes:
@@ -35,11 +46,20 @@ pub fn early_init(
 
 /// Initializes all other devices not initialized during [`early_init()`]. 
 ///
-/// Devices include:
+/// This first initializes the fully-featured system [`logger`],
+/// and then registers the following built-in drivers in the [`registry`]
+/// before initializing all registered drivers in dependency order:
 /// * At least one [`serial_port`] (e.g., `COM1`) with full interrupt support,
-/// * The fully-featured system [`logger`],
-/// * The legacy PS2 controller and any connected devices: [`keyboard`] and [`mouse`],
-/// * All other devices discovered on the [`pci`] bus.
+/// * The legacy PS2 controller and any connected devices: [`keyboard`] and the optional `mouse`,
+/// * All other devices discovered on the [`pci`] bus,
+///   including those supported by the compiled-in optional drivers.
+///
+/// A driver that fails to initialize doesn't cause this to fail;
+/// see [`registry::driver_states()`].
+/// Once drivers are initialized, the shutdown hooks of the logger and storage devices are registered;
+/// see [`registry::shutdown_all()`].
+/// Afterwards, any early DMA reservations that no driver claimed are released;
+/// see [`dma_buffer::reserve_early()`].
 pub fn init(
     #[cfg(target_arch = "x86_64")]
     key_producer: Queue<Event>,
@@ -53,133 +73,25 @@ pub fn init(
         .filter_map(|sp| serial_port::init_serial_port(sp.base_port_address(), sp))
         .cloned();
 
-    logger::init(None, logger_writers);
+    logger::init(boot_args::boot_args().log_level, logger_writers);
     info!("Initialized full logger.");
 
-    // COM1 is the only UART on aarch64; it's used for logging as well as for the console.
-    #[cfg(target_arch = "x86_64")] {
-        // Ensure that both COM1 and COM2 are initialized, for logging and/or headless operation.
-        // If a serial port was used for logging (as configured in [`logger::early_init()`]),
-        // ignore its inputs for purposes of starting new console instances.
-        let init_serial_port = |spa: SerialPortAddress| {
-            if let Some(sp) = take_serial_port_basic(spa) {
-                init_serial_port(spa, sp);
-            } else {
-                console::ignore_serial_port_input(spa as u16);
-                info!("Ignoring input on {:?} because it is being used for logging.", spa);
-            }
-        };
-        init_serial_port(SerialPortAddress::COM1);
-        init_serial_port(SerialPortAddress::COM2);
-    }
-
-    // PS/2 is x86_64 only
     #[cfg(target_arch = "x86_64")] {
-        let ps2_controller = ps2::init()?;
-        if let Some(kb) = ps2_controller.keyboard_ref() {
-            keyboard::init(kb, key_producer)?;
-        }
-        if let Some(m) = ps2_controller.mouse_ref() {
-            mouse::init(m, mouse_producer)?;
-        }
+        *KEY_PRODUCER.lock() = Some(key_producer);
+        optional_drivers::init(mouse_producer);
     }
 
-    // Initialize/scan the PCI bus to discover PCI devices
-    for dev in pci::pci_device_iter()? {
-        debug!("Found PCI device: {:X?}", dev);
+    for driver in builtin_drivers::DRIVERS {
+        registry::register(*driver)?;
     }
-
-    // store all the initialized ixgbe NICs here to be added to the network interface list
-    // No NIC support on aarch64 at the moment
-    #[cfg(target_arch = "x86_64")]
-    let mut ixgbe_devs = Vec::new();
-
-    // Iterate over all PCI devices and initialize the drivers for the devices we support.
-
-    for dev in pci::pci_device_iter()? {
-        // Currently we skip Bridge devices, since we have no use for them yet. 
-        if dev.class == 0x06 {
-            continue;
-        }
-
-        // If this is a storage device, initialize it as such.
-        // No storage device support on aarch64 at the moment
-        #[cfg(target_arch = "x86_64")]
-        match storage_manager::init_device(dev) {
-            // Successfully initialized this storage device.
-            Ok(Some(_storage_controller)) => continue,
-
-            // Not a storage device, so fall through and let another handler deal with it.
-            Ok(None) => { }
-            
-            // Error initializing this device, so skip it.
-            Err(e) => {
-                error!("Failed to initialize storage device, it will be unavailable.\n{:?}\nError: {}", dev, e);
-                continue;
-            }
-        }
-
-        // If this is a network device, initialize it as such.
-        // Look for networking controllers, specifically ethernet cards
-        // No NIC support on aarch64 at the moment
-        #[cfg(target_arch = "x86_64")]
-        if dev.class == 0x02 && dev.subclass == 0x00 {
-            if dev.vendor_id == e1000::INTEL_VEND && dev.device_id == e1000::E1000_DEV {
-                info!("e1000 PCI device found at: {:?}", dev.location);
-                let nic = e1000::E1000Nic::init(dev)?;
-                let interface = net::register_device(nic);
-                nic.lock().init_interrupts(interface)?;
-
-                continue;
-            }
-            if dev.vendor_id == ixgbe::INTEL_VEND && dev.device_id == ixgbe::INTEL_82599 {
-                info!("ixgbe PCI device found at: {:?}", dev.location);
-                
-                // Initialization parameters of the NIC.
-                // These can be changed according to the requirements specified in the ixgbe init function.
-                const VIRT_ENABLED: bool = true;
-                const RSS_ENABLED: bool = false;
-                const RX_DESCS: u16 = 8;
-                const TX_DESCS: u16 = 8;
-                
-                let ixgbe_nic = ixgbe::IxgbeNic::init(
-                    dev, 
-                    dev.location,
-                    VIRT_ENABLED, 
-                    None, 
-                    RSS_ENABLED, 
-                    ixgbe::RxBufferSizeKiB::Buffer2KiB,
-                    RX_DESCS,
-                    TX_DESCS
-                )?;
-
-                ixgbe_devs.push(ixgbe_nic);
-                continue;
-            }
-            if dev.vendor_id == mlx5::MLX_VEND && (dev.device_id == mlx5::CONNECTX5_DEV || dev.device_id == mlx5::CONNECTX5_EX_DEV) {
-                info!("mlx5 PCI device found at: {:?}", dev.location);
-                const RX_DESCS: usize = 512;
-                const TX_DESCS: usize = 8192;
-                const MAX_MTU:  u16 = 9000;
-
-                mlx5::ConnectX5Nic::init(dev, TX_DESCS, RX_DESCS, MAX_MTU)?;
-                continue;
-            }
-
-            // here: check for and initialize other ethernet cards
-        }
-
-        warn!("Ignoring PCI device with no handler. {:X?}", dev);
-    }
-
-    // Once all the NICs have been initialized, we can store them and add them to the list of network interfaces.
-    // No NIC support on aarch64 at the moment
-    #[cfg(target_arch = "x86_64")] {
-        let ixgbe_nics = ixgbe::IXGBE_NICS.call_once(|| ixgbe_devs);
-        for ixgbe_nic_ref in ixgbe_nics.iter() {
-            net::register_device(ixgbe_nic_ref);
-        }
+    registry::init_all();
+    registry::register_metrics()?;
+    for hook in builtin_drivers::SHUTDOWN_HOOKS {
+        registry::register_shutdown_hook(*hook)?;
     }
+    // Drivers claim their early DMA reservations when they probe their device,
+    // so any reservation left over now belongs to a device that's absent or failed to initialize.
+    dma_buffer::release_unclaimed_reservations();
 
     // Convenience notification for developers to inform them of no networking devices
     // No NIC support on aarch64 at the moment
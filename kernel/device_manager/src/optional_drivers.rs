@@ -0,0 +1,288 @@
+//! Registration and conditional initialization of optional drivers.
+//!
+//! Optional drivers are those that aren't needed by every Theseus image,
+//! e.g., NIC drivers or the PS/2 mouse driver.
+//! Each one is gated behind a Cargo feature of this crate of the same name,
+//! which controls whether the driver is compiled in at all.
+//!
+//! Each compiled-in driver registers a descriptor with a probe function and
+//! an init function via [`register_optional_driver!`]. During [`crate::init()`],
+//! the list of registered drivers is walked, and each driver is only initialized
+//! if its probe finds that its device is actually present.
+//!
+//! Because Theseus crates are loaded as separate object files at runtime
+//! rather than being statically linked into one image, the registered descriptors
+//! are collected into a static table instead of a dedicated linker section.
+
+use alloc::vec::Vec;
+use log::*;
+use mpmc::Queue;
+use event_types::Event;
+use pci::PciDevice;
+use spin::Mutex;
+
+/// The names of all optional drivers known to Theseus,
+/// regardless of whether they have been compiled in.
+pub const ALL_OPTIONAL_DRIVERS: &[&str] = &["e1000", "ixgbe", "mlx5", "mouse"];
+
+/// The kind of device that an optional driver supports,
+/// which determines how it is probed and initialized.
+pub enum DriverKind {
+    /// A driver for a device on the PCI bus.
+    ///
+    /// The `probe` function is invoked for every discovered PCI device,
+    /// and `init` is invoked for each device that the probe matched.
+    Pci {
+        probe: fn(&PciDevice) -> bool,
+        init: fn(&PciDevice) -> Result<(), &'static str>,
+    },
+    /// A driver for a non-PCI platform device, e.g., one discovered via ACPI or the PS/2 controller.
+    ///
+    /// The `probe` function is invoked once, and `init` is invoked only if the probe succeeded.
+    Platform {
+        probe: fn() -> bool,
+        init: fn() -> Result<(), &'static str>,
+    },
+}
+
+/// A descriptor of a compiled-in optional driver.
+pub struct OptionalDriver {
+    /// The name of this driver, which matches the name of its Cargo feature.
+    pub name: &'static str,
+    /// How this driver is probed and initialized.
+    pub kind: DriverKind,
+    /// An optional function invoked once after all devices have been probed,
+    /// if at least one device was successfully initialized by this driver.
+    pub finish: Option<fn() -> Result<(), &'static str>>,
+}
+
+/// The state of an optional driver, as reported by [`optional_driver_states()`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DriverState {
+    /// The driver's Cargo feature was disabled, so it wasn't compiled in.
+    NotCompiled,
+    /// The driver was compiled in, but its probe didn't find a device.
+    NotPresent,
+    /// The driver was compiled in and its device was found, but initialization failed.
+    Failed(&'static str),
+    /// The driver was compiled in and has initialized at least one device.
+    Active,
+}
+
+/// Creates an [`OptionalDriver`] descriptor for inclusion in [`OPTIONAL_DRIVERS`].
+///
+/// # Usage
+/// ```ignore
+/// register_optional_driver!(e1000, Pci, e1000_probe, e1000_init)
+/// register_optional_driver!(ixgbe, Pci, ixgbe_probe, ixgbe_init, finish = ixgbe_finish)
+/// register_optional_driver!(mouse, Platform, mouse_probe, mouse_init)
+/// ```
+macro_rules! register_optional_driver {
+    ($name:ident, $kind:ident, $probe:path, $init:path) => {
+        OptionalDriver {
+            name: stringify!($name),
+            kind: DriverKind::$kind { probe: $probe, init: $init },
+            finish: None,
+        }
+    };
+    ($name:ident, $kind:ident, $probe:path, $init:path, finish = $finish:path) => {
+        OptionalDriver {
+            name: stringify!($name),
+            kind: DriverKind::$kind { probe: $probe, init: $init },
+            finish: Some($finish),
+        }
+    };
+}
+
+/// The table of all optional drivers that have been compiled in.
+static OPTIONAL_DRIVERS: &[OptionalDriver] = &[
+    #[cfg(feature = "e1000")]
+    register_optional_driver!(e1000, Pci, e1000_driver::probe, e1000_driver::init),
+    #[cfg(feature = "ixgbe")]
+    register_optional_driver!(ixgbe, Pci, ixgbe_driver::probe, ixgbe_driver::init, finish = ixgbe_driver::finish),
+    #[cfg(feature = "mlx5")]
+    register_optional_driver!(mlx5, Pci, mlx5_driver::probe, mlx5_driver::init),
+    #[cfg(feature = "mouse")]
+    register_optional_driver!(mouse, Platform, mouse_driver::probe, mouse_driver::init),
+];
+
+/// The current state of each compiled-in optional driver, in the same order as [`OPTIONAL_DRIVERS`].
+static DRIVER_STATES: Mutex<Vec<DriverState>> = Mutex::new(Vec::new());
+
+/// The producer end of the mouse event queue, consumed when the mouse driver is initialized.
+static MOUSE_PRODUCER: Mutex<Option<Queue<Event>>> = Mutex::new(None);
+
+/// Returns the state of every known optional driver, including those that weren't compiled in.
+pub fn optional_driver_states() -> Vec<(&'static str, DriverState)> {
+    let states = DRIVER_STATES.lock();
+    ALL_OPTIONAL_DRIVERS.iter()
+        .map(|&name| {
+            let state = OPTIONAL_DRIVERS.iter()
+                .position(|d| d.name == name)
+                .map(|i| states.get(i).copied().unwrap_or(DriverState::NotPresent))
+                .unwrap_or(DriverState::NotCompiled);
+            (name, state)
+        })
+        .collect()
+}
+
+/// Prepares the list of optional drivers before any devices are probed.
+pub(crate) fn init(mouse_producer: Queue<Event>) {
+    *MOUSE_PRODUCER.lock() = Some(mouse_producer);
+    *DRIVER_STATES.lock() = OPTIONAL_DRIVERS.iter().map(|_| DriverState::NotPresent).collect();
+}
+
+/// Probes and initializes all compiled-in drivers for platform (non-PCI) devices.
+pub(crate) fn init_platform_drivers() {
+    for (i, driver) in OPTIONAL_DRIVERS.iter().enumerate() {
+        if let DriverKind::Platform { probe, init } = driver.kind {
+            if probe() {
+                record_init_result(i, driver.name, init());
+            }
+        }
+    }
+}
+
+/// Probes all compiled-in PCI drivers against the given `dev` and initializes
+/// the first driver whose probe matches.
+///
+/// Returns `true` if a driver claimed this device, even if its initialization failed.
+pub(crate) fn init_pci_device(dev: &PciDevice) -> bool {
+    for (i, driver) in OPTIONAL_DRIVERS.iter().enumerate() {
+        if let DriverKind::Pci { probe, init } = driver.kind {
+            if probe(dev) {
+                info!("{} PCI device found at: {:?}", driver.name, dev.location);
+                record_init_result(i, driver.name, init(dev));
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Invokes the `finish` function of each driver that has initialized at least one device.
+pub(crate) fn finish_all() {
+    for (i, driver) in OPTIONAL_DRIVERS.iter().enumerate() {
+        let is_active = DRIVER_STATES.lock().get(i) == Some(&DriverState::Active);
+        if let (true, Some(finish)) = (is_active, driver.finish) {
+            record_init_result(i, driver.name, finish());
+        }
+    }
+}
+
+fn record_init_result(index: usize, name: &'static str, result: Result<(), &'static str>) {
+    let mut states = DRIVER_STATES.lock();
+    match result {
+        Ok(()) => states[index] = DriverState::Active,
+        Err(e) => {
+            error!("Failed to initialize optional driver {}: {}", name, e);
+            // Don't overwrite the state of a driver that successfully initialized another device.
+            if states[index] != DriverState::Active {
+                states[index] = DriverState::Failed(e);
+            }
+        }
+    }
+}
+
+
+#[cfg(feature = "e1000")]
+mod e1000_driver {
+    use super::*;
+
+    pub(super) fn probe(dev: &PciDevice) -> bool {
+        dev.class == 0x02 && dev.subclass == 0x00
+            && dev.vendor_id == e1000::INTEL_VEND && dev.device_id == e1000::E1000_DEV
+    }
+
+    pub(super) fn init(dev: &PciDevice) -> Result<(), &'static str> {
+        let nic = e1000::E1000Nic::init(dev)?;
+        let interface = net::register_device(nic);
+        nic.lock().init_interrupts(interface)
+    }
+}
+
+#[cfg(feature = "ixgbe")]
+mod ixgbe_driver {
+    use super::*;
+    use sync_irq::IrqSafeMutex;
+
+    /// The ixgbe NICs initialized so far, which are registered all at once in [`finish()`].
+    static IXGBE_DEVS: Mutex<Vec<IrqSafeMutex<ixgbe::IxgbeNic>>> = Mutex::new(Vec::new());
+
+    pub(super) fn probe(dev: &PciDevice) -> bool {
+        dev.class == 0x02 && dev.subclass == 0x00
+            && dev.vendor_id == ixgbe::INTEL_VEND && dev.device_id == ixgbe::INTEL_82599
+    }
+
+    pub(super) fn init(dev: &PciDevice) -> Result<(), &'static str> {
+        // Initialization parameters of the NIC.
+        // These can be changed according to the requirements specified in the ixgbe init function.
+        const VIRT_ENABLED: bool = true;
+        const RSS_ENABLED: bool = false;
+        const RX_DESCS: u16 = 8;
+        const TX_DESCS: u16 = 8;
+
+        let ixgbe_nic = ixgbe::IxgbeNic::init(
+            dev,
+            dev.location,
+            VIRT_ENABLED,
+            None,
+            RSS_ENABLED,
+            ixgbe::RxBufferSizeKiB::Buffer2KiB,
+            RX_DESCS,
+            TX_DESCS
+        )?;
+        IXGBE_DEVS.lock().push(ixgbe_nic);
+        Ok(())
+    }
+
+    /// Once all the NICs have been initialized, we can store them
+    /// and add them to the list of network interfaces.
+    pub(super) fn finish() -> Result<(), &'static str> {
+        let ixgbe_devs = core::mem::take(&mut *IXGBE_DEVS.lock());
+        let ixgbe_nics = ixgbe::IXGBE_NICS.call_once(|| ixgbe_devs);
+        for ixgbe_nic_ref in ixgbe_nics.iter() {
+            net::register_device(ixgbe_nic_ref);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mlx5")]
+mod mlx5_driver {
+    use super::*;
+
+    pub(super) fn probe(dev: &PciDevice) -> bool {
+        dev.class == 0x02 && dev.subclass == 0x00
+            && dev.vendor_id == mlx5::MLX_VEND
+            && (dev.device_id == mlx5::CONNECTX5_DEV || dev.device_id == mlx5::CONNECTX5_EX_DEV)
+    }
+
+    pub(super) fn init(dev: &PciDevice) -> Result<(), &'static str> {
+        const RX_DESCS: usize = 512;
+        const TX_DESCS: usize = 8192;
+        const MAX_MTU:  u16 = 9000;
+
+        mlx5::ConnectX5Nic::init(dev, TX_DESCS, RX_DESCS, MAX_MTU)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mouse")]
+mod mouse_driver {
+    use super::*;
+
+    pub(super) fn probe() -> bool {
+        ps2::controller().and_then(|c| c.mouse_ref()).is_some()
+    }
+
+    pub(super) fn init() -> Result<(), &'static str> {
+        let mouse = ps2::controller()
+            .and_then(|c| c.mouse_ref())
+            .ok_or("PS/2 mouse was not present")?;
+        let producer = MOUSE_PRODUCER.lock()
+            .take()
+            .ok_or("BUG: the mouse event queue producer was missing")?;
+        mouse::init(mouse, producer)
+    }
+}
@@ -0,0 +1,388 @@
+//! A registry of drivers and their ordered initialization.
+//!
+//! Each driver registers an init function along with the names of the drivers
+//! that must be initialized before it, via [`register()`].
+//! Then, [`init_all()`] initializes all registered drivers in dependency order,
+//! preserving registration order among drivers that don't depend on each other.
+//! This occurs once, during [`crate::init()`], after ACPI tables have been parsed
+//! in [`crate::early_init()`]; drivers must be registered before then.
+//!
+//! A driver whose init function fails is logged and doesn't abort the others,
+//! but any drivers that depend on it (directly or transitively) are skipped.
+//! Likewise, drivers with a missing or circular dependency are skipped.
+//!
+//! Once drivers are initialized, drivers and other components that hold state
+//! which must reach a device before the system goes down can register a [`ShutdownHook`]
+//! via [`register_shutdown_hook()`]. Shutdown happens in two phases:
+//! first, every hook *quiesces* its component so that it stops accepting new work,
+//! and then every hook *flushes* its component's outstanding work within that hook's timeout.
+//! Both phases visit hooks in the reverse of the init-time dependency order,
+//! so a component is flushed before anything it depends on stops,
+//! e.g., the logger before the storage devices that may hold its records.
+//! [`shutdown_all()`] runs both phases for an orderly shutdown or reboot,
+//! while [`shutdown_best_effort()`] runs a reduced variant that's safe to use when panicking.
+
+use alloc::vec::Vec;
+use core::time::Duration;
+use log::*;
+use spin::Mutex;
+use time::Instant;
+
+/// A driver's registration in the registry.
+#[derive(Clone, Copy, Debug)]
+pub struct Driver {
+    /// The unique name of this driver.
+    pub name: &'static str,
+    /// The names of the drivers that must be initialized before this one.
+    pub depends_on: &'static [&'static str],
+    /// The function that initializes this driver.
+    pub init: fn() -> Result<(), &'static str>,
+}
+
+/// The initialization state of a registered driver, as reported by [`driver_states()`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InitState {
+    /// The driver has not yet been initialized.
+    Pending,
+    /// The driver's init function succeeded.
+    Initialized,
+    /// The driver's init function returned the given error.
+    Failed(&'static str),
+    /// The driver wasn't initialized because of the given problem with its dependencies.
+    Skipped(&'static str),
+}
+
+struct Registry {
+    drivers: Vec<(Driver, InitState)>,
+    /// Whether [`init_all()`] has already run.
+    initialized: bool,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    drivers: Vec::new(),
+    initialized: false,
+});
+
+/// Registers the given `driver` to be initialized by [`init_all()`].
+///
+/// Returns an error if a driver with the same name was already registered,
+/// or if [`init_all()`] has already run.
+pub fn register(driver: Driver) -> Result<(), &'static str> {
+    let mut registry = REGISTRY.lock();
+    if registry.initialized {
+        return Err("drivers were already initialized");
+    }
+    if registry.drivers.iter().any(|(d, _)| d.name == driver.name) {
+        error!("A driver named {:?} was already registered", driver.name);
+        return Err("a driver with that name was already registered");
+    }
+    registry.drivers.push((driver, InitState::Pending));
+    Ok(())
+}
+
+/// Returns the name and initialization state of every registered driver, in registration order.
+pub fn driver_states() -> Vec<(&'static str, InitState)> {
+    REGISTRY.lock().drivers.iter()
+        .map(|(driver, state)| (driver.name, *state))
+        .collect()
+}
+
+/// Returns the number of registered drivers whose state matches the given predicate.
+fn count_drivers(predicate: fn(&InitState) -> bool) -> u64 {
+    REGISTRY.lock().drivers.iter().filter(|(_, state)| predicate(state)).count() as u64
+}
+
+/// Registers the driver registry's metrics, as reported in [`metrics::snapshot()`].
+pub(crate) fn register_metrics() -> Result<(), &'static str> {
+    metrics::register_gauge("drivers.initialized", || {
+        count_drivers(|state| matches!(state, InitState::Initialized))
+    })?;
+    metrics::register_gauge("drivers.failed", || {
+        count_drivers(|state| matches!(state, InitState::Failed(_)))
+    })
+}
+
+/// Initializes all registered drivers in dependency order.
+///
+/// This doesn't return an error if a driver fails to initialize;
+/// instead, each failure is logged and recorded in [`driver_states()`].
+pub fn init_all() {
+    let drivers: Vec<Driver> = {
+        let mut registry = REGISTRY.lock();
+        if registry.initialized {
+            warn!("device_manager::registry::init_all() was called more than once");
+            return;
+        }
+        registry.initialized = true;
+        registry.drivers.iter().map(|(driver, _)| *driver).collect()
+    };
+
+    for (index, driver) in drivers.iter().enumerate() {
+        if let Some(missing) = driver.depends_on.iter().find(|dep| !drivers.iter().any(|d| d.name == **dep)) {
+            error!("Skipping driver {}: its dependency {} was never registered", driver.name, missing);
+            set_state(index, InitState::Skipped("a dependency was never registered"));
+        }
+    }
+
+    // Repeatedly initialize the first pending driver whose dependencies are all initialized,
+    // which preserves registration order among independent drivers.
+    loop {
+        let states = driver_states();
+        let state_of = |name: &str| states.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, state)| *state);
+
+        let mut progressed = false;
+        for (index, driver) in drivers.iter().enumerate() {
+            if states[index].1 != InitState::Pending {
+                continue;
+            }
+            let dep_states: Vec<_> = driver.depends_on.iter().map(|dep| state_of(dep)).collect();
+            if let Some((dep, _)) = driver.depends_on.iter()
+                .zip(dep_states.iter())
+                .find(|(_, state)| matches!(state, Some(InitState::Failed(_) | InitState::Skipped(_))))
+            {
+                warn!("Skipping driver {}: its dependency {} was not initialized", driver.name, dep);
+                set_state(index, InitState::Skipped("a dependency was not initialized"));
+                progressed = true;
+                break;
+            }
+            if dep_states.iter().all(|state| *state == Some(InitState::Initialized)) {
+                debug!("Initializing driver {}", driver.name);
+                let state = match (driver.init)() {
+                    Ok(()) => InitState::Initialized,
+                    Err(e) => {
+                        error!("Failed to initialize driver {}: {}", driver.name, e);
+                        InitState::Failed(e)
+                    }
+                };
+                set_state(index, state);
+                progressed = true;
+                break;
+            }
+        }
+
+        if !progressed {
+            break;
+        }
+    }
+
+    // Any drivers that are still pending must be part of a dependency cycle.
+    for (index, (name, state)) in driver_states().into_iter().enumerate() {
+        if state == InitState::Pending {
+            error!("Skipping driver {}: it has a circular dependency", name);
+            set_state(index, InitState::Skipped("circular dependency"));
+        }
+    }
+}
+
+fn set_state(index: usize, state: InitState) {
+    REGISTRY.lock().drivers[index].1 = state;
+}
+
+
+/// The mode in which the phases of a [`ShutdownHook`] are run.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShutdownMode {
+    /// An orderly shutdown or reboot, in which hooks may block or yield the CPU.
+    Orderly,
+    /// A best-effort shutdown from a panic, in which hooks must not block or yield the CPU
+    /// and may only poll for a bounded time.
+    BestEffort,
+}
+
+/// The shutdown hook of a driver or another component, registered via [`register_shutdown_hook()`].
+#[derive(Clone, Copy, Debug)]
+pub struct ShutdownHook {
+    /// The name of the driver that this hook belongs to, or the unique name of another component.
+    pub name: &'static str,
+    /// The names of the drivers or components that must be shut down after this one,
+    /// in addition to the `depends_on` list of the registered driver with the same name, if any.
+    pub depends_on: &'static [&'static str],
+    /// Stops the component from accepting new work.
+    pub quiesce: fn(ShutdownMode),
+    /// Completes the component's outstanding work, giving up once the given deadline has passed.
+    pub flush: fn(ShutdownMode, Instant) -> Result<(), &'static str>,
+    /// How long `flush` may take before it's considered to have timed out.
+    pub timeout: Duration,
+    /// Whether this hook's phases can run in [`ShutdownMode::BestEffort`];
+    /// if not, they're skipped in that mode.
+    pub best_effort: bool,
+}
+
+/// The result of flushing a component during shutdown.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlushOutcome {
+    /// The component's outstanding work was completed.
+    Flushed,
+    /// The hook's flush function returned the given error.
+    Failed(&'static str),
+    /// The hook's flush function didn't complete before its timeout.
+    TimedOut,
+    /// The hook wasn't run, e.g., because it can't run in [`ShutdownMode::BestEffort`].
+    Skipped,
+}
+
+struct ShutdownHooks {
+    hooks: Vec<ShutdownHook>,
+    /// The indices of `hooks` in the order in which they're run.
+    order: Vec<usize>,
+    /// Whether shutdown has already begun.
+    started: bool,
+}
+
+static SHUTDOWN_HOOKS: Mutex<ShutdownHooks> = Mutex::new(ShutdownHooks {
+    hooks: Vec::new(),
+    order: Vec::new(),
+    started: false,
+});
+
+/// Registers the given shutdown `hook` to be run by [`shutdown_all()`] and [`shutdown_best_effort()`].
+///
+/// Returns an error if drivers haven't been initialized yet, which fixes the dependency graph,
+/// if a hook with the same name was already registered, or if shutdown has already begun.
+pub fn register_shutdown_hook(hook: ShutdownHook) -> Result<(), &'static str> {
+    let drivers: Vec<Driver> = {
+        let registry = REGISTRY.lock();
+        if !registry.initialized {
+            return Err("shutdown hooks cannot be registered before drivers are initialized");
+        }
+        registry.drivers.iter().map(|(driver, _)| *driver).collect()
+    };
+    let mut shutdown_hooks = SHUTDOWN_HOOKS.lock();
+    if shutdown_hooks.started {
+        return Err("shutdown has already begun");
+    }
+    if shutdown_hooks.hooks.iter().any(|h| h.name == hook.name) {
+        error!("A shutdown hook named {:?} was already registered", hook.name);
+        return Err("a shutdown hook with that name was already registered");
+    }
+    shutdown_hooks.hooks.push(hook);
+    // The order is computed here so that the best-effort shutdown doesn't have to allocate.
+    shutdown_hooks.order = shutdown_order(&shutdown_hooks.hooks, &drivers);
+    Ok(())
+}
+
+/// Returns the names of the registered shutdown hooks in the order in which they're run.
+pub fn shutdown_hook_order() -> Vec<&'static str> {
+    let shutdown_hooks = SHUTDOWN_HOOKS.lock();
+    shutdown_hooks.order.iter().map(|&i| shutdown_hooks.hooks[i].name).collect()
+}
+
+/// Returns the indices of the given `hooks` in the reverse of the order in which
+/// their drivers or components would be initialized, based on the `drivers`' dependencies
+/// together with those of the `hooks`.
+///
+/// Hooks that are part of a dependency cycle are run first.
+fn shutdown_order(hooks: &[ShutdownHook], drivers: &[Driver]) -> Vec<usize> {
+    let mut nodes: Vec<(&'static str, Vec<&'static str>)> = drivers.iter()
+        .map(|driver| (driver.name, driver.depends_on.to_vec()))
+        .collect();
+    for hook in hooks {
+        match nodes.iter_mut().find(|(name, _)| *name == hook.name) {
+            Some((_, depends_on)) => depends_on.extend_from_slice(hook.depends_on),
+            None => nodes.push((hook.name, hook.depends_on.to_vec())),
+        }
+    }
+
+    // As in `init_all()`, repeatedly pick the first node whose dependencies have all been picked.
+    // Dependencies that don't exist don't constrain the order.
+    let mut picked = alloc::vec![false; nodes.len()];
+    let mut init_order = Vec::with_capacity(nodes.len());
+    while let Some(next) = (0..nodes.len()).find(|&i| !picked[i] && nodes[i].1.iter().all(|dep|
+        nodes.iter().position(|(name, _)| name == dep).map_or(true, |d| picked[d])
+    )) {
+        picked[next] = true;
+        init_order.push(next);
+    }
+    for (i, (name, _)) in nodes.iter().enumerate() {
+        if !picked[i] {
+            warn!("Shutdown order of {} is unconstrained: it has a circular dependency", name);
+            init_order.push(i);
+        }
+    }
+
+    init_order.into_iter()
+        .rev()
+        .filter_map(|i| hooks.iter().position(|hook| hook.name == nodes[i].0))
+        .collect()
+}
+
+/// Runs the given `hook`'s flush phase in the given `mode`.
+fn flush(hook: &ShutdownHook, mode: ShutdownMode) -> FlushOutcome {
+    let start = Instant::now();
+    let deadline = start + hook.timeout;
+    match (hook.flush)(mode, deadline) {
+        Ok(()) if Instant::now() > deadline => {
+            warn!("Flushing {} took {:?}, longer than its timeout of {:?}", hook.name, start.elapsed(), hook.timeout);
+            FlushOutcome::Flushed
+        }
+        Ok(()) => FlushOutcome::Flushed,
+        Err(_) if Instant::now() >= deadline => FlushOutcome::TimedOut,
+        Err(e) => FlushOutcome::Failed(e),
+    }
+}
+
+/// Quiesces and then flushes every registered driver and component, in dependency order,
+/// in preparation for an orderly shutdown or reboot.
+///
+/// Returns the name and flush outcome of each shutdown hook in the order they were run.
+/// A hook that fails or times out is logged but doesn't stop the others.
+/// Shutdown can only happen once; subsequent calls do nothing.
+pub fn shutdown_all() -> Vec<(&'static str, FlushOutcome)> {
+    let hooks: Vec<ShutdownHook> = {
+        let mut shutdown_hooks = SHUTDOWN_HOOKS.lock();
+        if shutdown_hooks.started {
+            warn!("device_manager::registry::shutdown_all() was called more than once");
+            return Vec::new();
+        }
+        shutdown_hooks.started = true;
+        shutdown_hooks.order.iter().map(|&i| shutdown_hooks.hooks[i]).collect()
+    };
+
+    for hook in &hooks {
+        debug!("Quiescing {}", hook.name);
+        (hook.quiesce)(ShutdownMode::Orderly);
+    }
+    hooks.iter().map(|hook| {
+        debug!("Flushing {}", hook.name);
+        let outcome = flush(hook, ShutdownMode::Orderly);
+        if outcome != FlushOutcome::Flushed {
+            error!("Failed to flush {} during shutdown: {:?}", hook.name, outcome);
+        }
+        (hook.name, outcome)
+    }).collect()
+}
+
+/// Quiesces and then flushes every registered driver and component that supports
+/// [`ShutdownMode::BestEffort`], in the same order as [`shutdown_all()`].
+///
+/// This is intended for the panic path: it doesn't allocate or block on the registry's lock,
+/// and each hook that runs must only poll until its timeout, but may allocate.
+/// The name and flush outcome of each hook are passed to `report`,
+/// including [`FlushOutcome::Skipped`] for hooks that couldn't run.
+///
+/// Returns `false` if the shutdown hooks were locked, in which case no hooks ran at all.
+pub fn shutdown_best_effort(mut report: impl FnMut(&'static str, FlushOutcome)) -> bool {
+    let Some(mut shutdown_hooks) = SHUTDOWN_HOOKS.try_lock() else {
+        return false;
+    };
+    shutdown_hooks.started = true;
+
+    for &i in &shutdown_hooks.order {
+        let hook = &shutdown_hooks.hooks[i];
+        if hook.best_effort {
+            (hook.quiesce)(ShutdownMode::BestEffort);
+        }
+    }
+    for &i in &shutdown_hooks.order {
+        let hook = &shutdown_hooks.hooks[i];
+        let outcome = if hook.best_effort {
+            flush(hook, ShutdownMode::BestEffort)
+        } else {
+            FlushOutcome::Skipped
+        };
+        report(hook.name, outcome);
+    }
+    true
+}
@@ -0,0 +1,345 @@
+//! A buffer of physically-contiguous memory used for Direct Memory Access (DMA).
+//!
+//! A [`DmaBuffer`] owns its mapping and the frames that back it,
+//! so a device can never be handed the physical address of memory that has been freed,
+//! as long as the buffer outlives its use by the device.
+//!
+//! Ownership of the buffer's contents passes back and forth between the CPU and the device
+//! via explicit state transitions:
+//! * [`DmaBuffer::prepare_for_device()`] hands the buffer to the device,
+//! * [`DmaBuffer::complete_from_device()`] returns it to the CPU.
+//!
+//! On x86_64, DMA is cache-coherent, so these transitions are mostly just memory fences.
+//! If devices don't snoop the CPU caches (see [`set_dma_coherent()`]),
+//! these transitions also flush cacheable buffers from the CPU caches.
+//! However, in debug builds, accessing the buffer's contents from the CPU
+//! while it is owned by a device will panic, which catches races in which the CPU
+//! reads a buffer that the device is still writing to (or vice versa).
+//!
+//! Also in debug builds, every live `DmaBuffer` is recorded such that drivers can use
+//! [`assert_live_dma_address()`] to check that a physical address they're about to
+//! program into a device register or descriptor actually belongs to a live buffer,
+//! and tests can use [`live_buffer_count()`] to check that no buffers were leaked.
+//!
+//! Drivers that need large buffers can reserve physically-contiguous memory early in boot,
+//! before it becomes fragmented; see the [`reservation`] module.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod reservation;
+pub use reservation::{reserve_early, take_early_reservation, release_unclaimed_reservations, DmaReservation};
+
+use core::{marker::PhantomData, sync::atomic::{fence, AtomicBool, Ordering}};
+use memory::{create_contiguous_mapping, MappedPages, PhysicalAddress, MMIO_FLAGS};
+
+/// Whether devices snoop the CPU caches when accessing memory via DMA.
+///
+/// This is always true on x86_64, so it is the default there.
+static DMA_COHERENT: AtomicBool = AtomicBool::new(cfg!(target_arch = "x86_64"));
+
+/// Sets whether devices snoop the CPU caches when accessing memory via DMA.
+///
+/// If not, [`DmaBuffer::prepare_for_device()`] and [`DmaBuffer::complete_from_device()`]
+/// will flush buffers that are mapped as cacheable from the CPU caches.
+/// Buffers created by [`DmaBuffer::new()`] are uncacheable, so they never need flushing.
+pub fn set_dma_coherent(coherent: bool) {
+    DMA_COHERENT.store(coherent, Ordering::Relaxed);
+}
+
+/// Returns whether devices snoop the CPU caches when accessing memory via DMA.
+pub fn is_dma_coherent() -> bool {
+    DMA_COHERENT.load(Ordering::Relaxed)
+}
+
+/// The direction in which data is transferred by a DMA operation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DmaDirection {
+    /// The device reads from the buffer, e.g., a packet to be transmitted.
+    ToDevice,
+    /// The device writes into the buffer, e.g., a packet being received.
+    FromDevice,
+    /// The device may both read from and write to the buffer.
+    Bidirectional,
+}
+
+/// Which party currently owns the contents of a [`DmaBuffer`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DmaOwner {
+    /// The CPU may freely read and write the buffer.
+    Cpu,
+    /// The buffer has been handed to a device for a transfer in the given direction,
+    /// so the CPU must not access it.
+    Device(DmaDirection),
+}
+
+/// A buffer of physically-contiguous memory that can be used for DMA.
+///
+/// See the crate-level documentation for more details.
+pub struct DmaBuffer {
+    mp: MappedPages,
+    phys_addr: PhysicalAddress,
+    size_in_bytes: usize,
+    owner: DmaOwner,
+}
+
+impl DmaBuffer {
+    /// Allocates and maps a new `DmaBuffer` of the given size that is contiguous in physical memory.
+    pub fn new(size_in_bytes: usize) -> Result<DmaBuffer, &'static str> {
+        let (mp, phys_addr) = create_contiguous_mapping(size_in_bytes, MMIO_FLAGS)?;
+        Ok(Self::new_internal(mp, phys_addr, size_in_bytes))
+    }
+
+    /// Creates a `DmaBuffer` from an existing mapping that is contiguous in physical memory,
+    /// starting at `phys_addr`.
+    ///
+    /// Returns an error if `mp` isn't writable or isn't actually mapped to
+    /// physically-contiguous memory starting at `phys_addr`.
+    pub fn from_contiguous_mapping(mp: MappedPages, phys_addr: PhysicalAddress) -> Result<DmaBuffer, &'static str> {
+        if !mp.flags().is_writable() {
+            return Err("DmaBuffer: mapped pages aren't writable");
+        }
+        let size_in_bytes = mp.size_in_bytes();
+        if size_in_bytes == 0 {
+            return Err("DmaBuffer: mapped pages were empty");
+        }
+        for offset in (0 .. size_in_bytes).step_by(memory::PAGE_SIZE) {
+            let expected = phys_addr + offset;
+            if memory::translate(mp.start_address() + offset) != Some(expected) {
+                log::error!("DmaBuffer: {:?} wasn't mapped contiguously to {:#X}", mp, phys_addr);
+                return Err("DmaBuffer: mapped pages weren't mapped to contiguous physical memory at the given address");
+            }
+        }
+        Ok(Self::new_internal(mp, phys_addr, size_in_bytes))
+    }
+
+    /// Returns an empty `DmaBuffer` that isn't backed by any memory.
+    ///
+    /// Can be used as a placeholder, but will not permit any real usage.
+    pub const fn empty() -> DmaBuffer {
+        DmaBuffer {
+            mp: MappedPages::empty(),
+            phys_addr: PhysicalAddress::zero(),
+            size_in_bytes: 0,
+            owner: DmaOwner::Cpu,
+        }
+    }
+
+    fn new_internal(mp: MappedPages, phys_addr: PhysicalAddress, size_in_bytes: usize) -> DmaBuffer {
+        #[cfg(debug_assertions)]
+        live::insert(phys_addr, size_in_bytes);
+        DmaBuffer { mp, phys_addr, size_in_bytes, owner: DmaOwner::Cpu }
+    }
+
+    /// Returns the starting physical address of this buffer.
+    pub fn phys_addr(&self) -> PhysicalAddress {
+        self.phys_addr
+    }
+
+    /// Returns the size in bytes of this buffer.
+    pub fn size_in_bytes(&self) -> usize {
+        self.size_in_bytes
+    }
+
+    /// Returns which party currently owns the contents of this buffer.
+    pub fn owner(&self) -> DmaOwner {
+        self.owner
+    }
+
+    /// Hands ownership of this buffer's contents to a device
+    /// for a transfer in the given `direction`.
+    ///
+    /// This must be invoked *before* the buffer's physical address is given to the device.
+    /// After this, the CPU must not access the buffer's contents until
+    /// [`complete_from_device()`](Self::complete_from_device) is invoked.
+    pub fn prepare_for_device(&mut self, direction: DmaDirection) {
+        debug_assert_eq!(
+            self.owner, DmaOwner::Cpu,
+            "DmaBuffer at {:#X} was prepared for a device while already owned by a device",
+            self.phys_addr,
+        );
+        // Ensure that all prior CPU writes to the buffer are visible before the device accesses it.
+        fence(Ordering::Release);
+        self.flush_caches_if_incoherent();
+        self.owner = DmaOwner::Device(direction);
+    }
+
+    /// Returns ownership of this buffer's contents to the CPU
+    /// once the device has finished its transfer.
+    pub fn complete_from_device(&mut self) {
+        debug_assert_ne!(
+            self.owner, DmaOwner::Cpu,
+            "DmaBuffer at {:#X} was completed from a device while already owned by the CPU",
+            self.phys_addr,
+        );
+        // Ensure that subsequent CPU reads observe everything the device wrote.
+        fence(Ordering::Acquire);
+        if self.owner != DmaOwner::Device(DmaDirection::ToDevice) {
+            // Discard any stale cache lines that the CPU may have speculatively loaded.
+            self.flush_caches_if_incoherent();
+        }
+        self.owner = DmaOwner::Cpu;
+    }
+
+    /// Writes back and invalidates this buffer's contents in the CPU caches,
+    /// if DMA isn't cache-coherent and this buffer is mapped as cacheable.
+    fn flush_caches_if_incoherent(&self) {
+        if self.size_in_bytes > 0 && !is_dma_coherent() && !self.mp.flags().is_device_memory() {
+            memory::cache_flush_range(self.mp.start_address(), self.size_in_bytes);
+        }
+    }
+
+    /// Returns a sub-region of this buffer that carries its own physical address,
+    /// which allows one large buffer to back multiple DMA descriptors.
+    ///
+    /// Returns an error if the given range is out of bounds.
+    pub fn slice(&self, offset: usize, length: usize) -> Result<DmaSlice<'_>, &'static str> {
+        match offset.checked_add(length) {
+            Some(end) if end <= self.size_in_bytes => Ok(DmaSlice {
+                phys_addr: self.phys_addr + offset,
+                offset,
+                length,
+                _buffer: PhantomData,
+            }),
+            _ => Err("DmaBuffer::slice(): offset + length was out of bounds"),
+        }
+    }
+
+    /// Returns a reference to `length` bytes of this buffer starting at `offset`.
+    ///
+    /// # Panics
+    /// In debug builds, this panics if the buffer is currently owned by a device.
+    pub fn as_slice(&self, offset: usize, length: usize) -> Result<&[u8], &'static str> {
+        self.debug_assert_cpu_owned();
+        self.mp.as_slice(offset, length)
+    }
+
+    /// Returns a mutable reference to `length` bytes of this buffer starting at `offset`.
+    ///
+    /// # Panics
+    /// In debug builds, this panics if the buffer is currently owned by a device.
+    pub fn as_slice_mut(&mut self, offset: usize, length: usize) -> Result<&mut [u8], &'static str> {
+        self.debug_assert_cpu_owned();
+        self.mp.as_slice_mut(offset, length)
+    }
+
+    fn debug_assert_cpu_owned(&self) {
+        debug_assert_eq!(
+            self.owner, DmaOwner::Cpu,
+            "CPU accessed DmaBuffer at {:#X} while it was owned by a device",
+            self.phys_addr,
+        );
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        if self.owner != DmaOwner::Cpu {
+            log::warn!("DmaBuffer at {:#X} was dropped while owned by a device", self.phys_addr);
+        }
+        #[cfg(debug_assertions)]
+        if self.size_in_bytes != 0 {
+            live::remove(self.phys_addr);
+        }
+    }
+}
+
+impl core::fmt::Debug for DmaBuffer {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("DmaBuffer")
+            .field("phys_addr", &self.phys_addr)
+            .field("size_in_bytes", &self.size_in_bytes)
+            .field("owner", &self.owner)
+            .finish()
+    }
+}
+
+/// A sub-region of a [`DmaBuffer`], obtained via [`DmaBuffer::slice()`].
+///
+/// This borrows the buffer it came from, so the buffer cannot be dropped
+/// while the slice's physical address is still in use.
+#[derive(Copy, Clone, Debug)]
+pub struct DmaSlice<'b> {
+    phys_addr: PhysicalAddress,
+    offset: usize,
+    length: usize,
+    _buffer: PhantomData<&'b DmaBuffer>,
+}
+
+impl<'b> DmaSlice<'b> {
+    /// Returns the starting physical address of this slice.
+    pub fn phys_addr(&self) -> PhysicalAddress {
+        self.phys_addr
+    }
+
+    /// Returns the offset in bytes of this slice from the start of its buffer.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the length in bytes of this slice.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+}
+
+/// Checks that the range of `length` bytes starting at `phys_addr` lies entirely
+/// within a live [`DmaBuffer`].
+///
+/// This should be invoked before programming a physical address into a device register or descriptor.
+///
+/// # Panics
+/// In debug builds, this panics if the range isn't within a live `DmaBuffer`.
+/// In release builds, this does nothing.
+pub fn assert_live_dma_address(phys_addr: PhysicalAddress, length: usize) {
+    #[cfg(debug_assertions)]
+    assert!(
+        live::contains(phys_addr, length),
+        "physical address range {:#X} + {:#X} given to a device isn't within a live DmaBuffer",
+        phys_addr, length,
+    );
+    #[cfg(not(debug_assertions))]
+    let _ = (phys_addr, length);
+}
+
+/// Returns the number of live [`DmaBuffer`]s, e.g., to check that none were leaked,
+/// or `None` in release builds, which don't track them.
+pub fn live_buffer_count() -> Option<usize> {
+    #[cfg(debug_assertions)]
+    return Some(live::count());
+    #[cfg(not(debug_assertions))]
+    None
+}
+
+/// Tracking of all live `DmaBuffer`s, only used in debug builds.
+#[cfg(debug_assertions)]
+mod live {
+    use alloc::collections::BTreeMap;
+    use memory::PhysicalAddress;
+    use sync_irq::IrqSafeMutex;
+
+    /// A map from the starting physical address of each live buffer to its size in bytes.
+    static LIVE_DMA_BUFFERS: IrqSafeMutex<BTreeMap<PhysicalAddress, usize>> = IrqSafeMutex::new(BTreeMap::new());
+
+    pub(crate) fn insert(phys_addr: PhysicalAddress, size_in_bytes: usize) {
+        LIVE_DMA_BUFFERS.lock().insert(phys_addr, size_in_bytes);
+    }
+
+    pub(crate) fn remove(phys_addr: PhysicalAddress) {
+        LIVE_DMA_BUFFERS.lock().remove(&phys_addr);
+    }
+
+    pub(crate) fn count() -> usize {
+        LIVE_DMA_BUFFERS.lock().len()
+    }
+
+    pub(crate) fn contains(phys_addr: PhysicalAddress, length: usize) -> bool {
+        LIVE_DMA_BUFFERS.lock()
+            .range(..=phys_addr)
+            .next_back()
+            .map_or(false, |(start, size)| {
+                phys_addr.value() + length <= start.value() + size
+            })
+    }
+}
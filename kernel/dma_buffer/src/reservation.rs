@@ -0,0 +1,90 @@
+//! Early reservations of physically-contiguous memory for DMA.
+//!
+//! Large physically-contiguous buffers get harder to allocate the longer the system runs,
+//! as the free physical memory becomes fragmented.
+//! Drivers that are known at compile time can therefore reserve the contiguous memory
+//! they'll need via [`reserve_early()`] during boot, before other subsystems fragment it,
+//! and later turn it into a [`DmaBuffer`] via [`take_early_reservation()`] when they probe their device.
+//!
+//! A reservation that a driver doesn't claim, e.g., because its device wasn't found or
+//! failed to initialize, is released back to the frame allocator by
+//! [`release_unclaimed_reservations()`] once all drivers have been initialized.
+
+use alloc::vec::Vec;
+use log::{debug, warn};
+use memory::{AllocatedFrames, FrameKind, PhysicalAddress, MMIO_FLAGS};
+use sync_irq::IrqSafeMutex;
+use crate::DmaBuffer;
+
+/// The early reservations that haven't yet been claimed, along with the name of their owner.
+static EARLY_RESERVATIONS: IrqSafeMutex<Vec<(&'static str, DmaReservation)>> = IrqSafeMutex::new(Vec::new());
+
+/// A range of physically-contiguous frames reserved for DMA but not yet mapped.
+///
+/// Dropping a `DmaReservation` releases its frames back to the frame allocator.
+#[derive(Debug)]
+pub struct DmaReservation {
+    frames: AllocatedFrames,
+    size_in_bytes: usize,
+}
+
+impl DmaReservation {
+    /// Returns the starting physical address of the reserved memory.
+    pub fn phys_addr(&self) -> PhysicalAddress {
+        self.frames.start_address()
+    }
+
+    /// Returns the size in bytes that was requested for this reservation.
+    pub fn size_in_bytes(&self) -> usize {
+        self.size_in_bytes
+    }
+
+    /// Maps the reserved memory into the kernel's address space as a new [`DmaBuffer`].
+    pub fn into_buffer(self) -> Result<DmaBuffer, &'static str> {
+        let kernel_mmi_ref = memory::get_kernel_mmi_ref()
+            .ok_or("DmaReservation::into_buffer(): KERNEL_MMI was not yet initialized!")?;
+        let pages = memory::allocate_pages_by_bytes(self.size_in_bytes)
+            .ok_or("DmaReservation::into_buffer(): couldn't allocate contiguous pages")?;
+        let phys_addr = self.phys_addr();
+        let mp = kernel_mmi_ref.lock().page_table.map_allocated_pages_to_kind(
+            pages, self.frames, MMIO_FLAGS, FrameKind::Ram,
+        )?;
+        Ok(DmaBuffer::new_internal(mp, phys_addr, self.size_in_bytes))
+    }
+}
+
+/// Reserves `size_in_bytes` of physically-contiguous memory for the given `owner`,
+/// which can later claim it via [`take_early_reservation()`].
+///
+/// Returns an error if the memory couldn't be allocated or if `owner` already has a reservation.
+pub fn reserve_early(owner: &'static str, size_in_bytes: usize) -> Result<(), &'static str> {
+    let mut reservations = EARLY_RESERVATIONS.lock();
+    if reservations.iter().any(|(o, _)| *o == owner) {
+        return Err("reserve_early(): the owner already has an early DMA reservation");
+    }
+    let frames = memory::allocate_frames_by_bytes(size_in_bytes)
+        .ok_or("reserve_early(): couldn't allocate contiguous frames")?;
+    debug!("Reserved {:#X} bytes of DMA memory at {:#X} for {:?}", size_in_bytes, frames.start_address(), owner);
+    reservations.push((owner, DmaReservation { frames, size_in_bytes }));
+    Ok(())
+}
+
+/// Removes and returns the early reservation made by the given `owner`, if any.
+pub fn take_early_reservation(owner: &str) -> Option<DmaReservation> {
+    let mut reservations = EARLY_RESERVATIONS.lock();
+    let index = reservations.iter().position(|(o, _)| *o == owner)?;
+    Some(reservations.swap_remove(index).1)
+}
+
+/// Releases every early reservation that hasn't been claimed via [`take_early_reservation()`].
+///
+/// Returns the number of bytes that were released.
+pub fn release_unclaimed_reservations() -> usize {
+    let unclaimed = core::mem::take(&mut *EARLY_RESERVATIONS.lock());
+    unclaimed.into_iter()
+        .map(|(owner, reservation)| {
+            warn!("Releasing unclaimed early DMA reservation of {:#X} bytes for {:?}", reservation.size_in_bytes, owner);
+            reservation.size_in_bytes
+        })
+        .sum()
+}
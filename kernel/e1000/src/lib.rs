@@ -456,7 +456,15 @@ impl net::NetworkDevice for E1000Nic {
     }
 }
 
+/// The e1000 interrupt handler, which only acknowledges the interrupt and
+/// collects received frames from the receive queue.
+///
+/// This receive path runs in interrupt context, so like all of the base kernel
+/// it must not use FP/SIMD instructions, which is checked in debug builds.
+/// All further packet processing happens in [`poll_interface()`],
+/// which runs in a deferred interrupt task and is thus free to use them.
 extern "x86-interrupt" fn e1000_handler(_stack_frame: InterruptStackFrame) {
+    interrupts::fpu::snapshot_on_irq_entry();
     if let Some(e1000_nic_ref) = E1000_NIC.get() {
         let mut e1000_nic = e1000_nic_ref.lock();
         if let Err(e) = e1000_nic.handle_interrupt() {
@@ -0,0 +1,111 @@
+//! Tracking of regions of the back buffer that have been modified
+//! but not yet copied ("flushed") to the real framebuffer.
+
+/// The maximum number of distinct damaged regions tracked at once.
+///
+/// If another disjoint region is damaged when the list is already full,
+/// all regions are coalesced into a single bounding region.
+const MAX_DAMAGE_RECTS: usize = 8;
+
+/// A rectangular region of pixels, in which `(x, y)` is the top-left corner.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct DamageRect {
+    pub(crate) x: u32,
+    pub(crate) y: u32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl DamageRect {
+    /// Returns the x coordinate one past the right edge of this rect.
+    fn end_x(&self) -> u32 {
+        self.x + self.width
+    }
+
+    /// Returns the y coordinate one past the bottom edge of this rect.
+    fn end_y(&self) -> u32 {
+        self.y + self.height
+    }
+
+    /// Returns `true` if this rect overlaps with or is directly adjacent to `other`,
+    /// meaning that their union does not cover any pixels outside of the two rects.
+    fn touches(&self, other: &DamageRect) -> bool {
+        let x_overlaps = self.x <= other.end_x() && other.x <= self.end_x();
+        let y_overlaps = self.y <= other.end_y() && other.y <= self.end_y();
+        let same_columns = self.x == other.x && self.width == other.width;
+        let same_rows    = self.y == other.y && self.height == other.height;
+        (x_overlaps && same_rows) || (y_overlaps && same_columns) || self.contains(other) || other.contains(self)
+    }
+
+    /// Returns `true` if `other` lies entirely within this rect.
+    fn contains(&self, other: &DamageRect) -> bool {
+        self.x <= other.x && other.end_x() <= self.end_x()
+            && self.y <= other.y && other.end_y() <= self.end_y()
+    }
+
+    /// Returns the smallest rect that covers both this rect and `other`.
+    fn union(&self, other: &DamageRect) -> DamageRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        DamageRect {
+            x,
+            y,
+            width: self.end_x().max(other.end_x()) - x,
+            height: self.end_y().max(other.end_y()) - y,
+        }
+    }
+}
+
+/// A fixed-capacity list of damaged regions.
+///
+/// This doesn't use any heap allocation, as the early printer
+/// is used before the heap has been initialized.
+pub(crate) struct DamageList {
+    rects: [DamageRect; MAX_DAMAGE_RECTS],
+    len: usize,
+}
+
+impl DamageList {
+    pub(crate) const fn new() -> Self {
+        DamageList {
+            rects: [DamageRect { x: 0, y: 0, width: 0, height: 0 }; MAX_DAMAGE_RECTS],
+            len: 0,
+        }
+    }
+
+    /// Adds the given `rect` to this list of damaged regions,
+    /// merging it into an existing region if possible.
+    ///
+    /// Returns the number of regions that were coalesced as part of this addition.
+    pub(crate) fn add(&mut self, rect: DamageRect) -> usize {
+        if rect.width == 0 || rect.height == 0 {
+            return 0;
+        }
+        if let Some(existing) = self.rects[..self.len].iter_mut().find(|r| r.touches(&rect)) {
+            *existing = existing.union(&rect);
+            return 1;
+        }
+        if self.len < MAX_DAMAGE_RECTS {
+            self.rects[self.len] = rect;
+            self.len += 1;
+            return 0;
+        }
+        // The list is full, so coalesce everything into one bounding region.
+        let bounding = self.rects.iter().fold(rect, |acc, r| acc.union(r));
+        let coalesced = self.len;
+        self.rects[0] = bounding;
+        self.len = 1;
+        coalesced
+    }
+
+    /// Removes and returns all damaged regions from this list.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = DamageRect> + '_ {
+        let len = core::mem::replace(&mut self.len, 0);
+        self.rects[..len].iter().copied()
+    }
+
+    /// Returns `true` if there are no damaged regions.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
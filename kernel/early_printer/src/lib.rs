@@ -2,6 +2,11 @@
 //! or text-mode VGA display.
 //!
 //! Does not support user scrolling, cursors, or any other advanced features.
+//!
+//! When possible, text is rendered into a back buffer in regular memory,
+//! and only the regions damaged by each batch of output are copied ("flushed")
+//! to the real framebuffer memory, which avoids visible tearing and slow reads
+//! from device memory. See [`flush_stats()`] and [`set_double_buffering()`].
 
 #![no_std]
 #![feature(let_chains)]
@@ -9,10 +14,13 @@
 #[cfg(all(feature = "bios", not(target_arch = "x86_64")))]
 compile_error!("The `bios` feature can only be used on x86_64");
 
-use core::{fmt::{self, Write}, slice, ops::{Deref, DerefMut}};
+mod damage;
+
+use core::{fmt::{self, Write}, slice, ops::{Deref, DerefMut}, sync::atomic::{AtomicBool, AtomicU64, Ordering}};
+use damage::{DamageList, DamageRect};
 use boot_info::{FramebufferInfo, FramebufferFormat};
 use font::FONT_BASIC;
-use memory::{BorrowedSliceMappedPages, Mutable, PteFlags, PhysicalAddress, PteFlagsArch, PageTable};
+use memory::{BorrowedSliceMappedPages, MemoryType, Mutable, PteFlags, PhysicalAddress, PteFlagsArch, PageTable};
 use spin::Mutex;
 
 /// The height in pixels that each character occupies, not including any padding.
@@ -32,6 +40,68 @@ static EARLY_FRAMEBUFFER_PRINTER: Mutex<Option<EarlyPrinter>> = {
     }
 };
 
+/// Whether the early framebuffer printer should render into a back buffer.
+static DOUBLE_BUFFERING_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// The number of times that the back buffer has been flushed to the real framebuffer.
+static FLUSH_COUNT: AtomicU64 = AtomicU64::new(0);
+/// The number of damaged regions copied to the real framebuffer.
+static RECTS_FLUSHED: AtomicU64 = AtomicU64::new(0);
+/// The number of damaged regions that were merged into other regions before being flushed.
+static RECTS_COALESCED: AtomicU64 = AtomicU64::new(0);
+/// The total number of bytes copied from the back buffer to the real framebuffer.
+static BYTES_COPIED: AtomicU64 = AtomicU64::new(0);
+
+/// Statistics about flushing the back buffer to the real framebuffer.
+///
+/// All values are cumulative since boot.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FlushStats {
+    /// The number of times that the back buffer has been flushed.
+    pub flushes: u64,
+    /// The number of damaged regions that were copied to the real framebuffer.
+    pub rects_flushed: u64,
+    /// The number of damaged regions that were merged into other regions
+    /// instead of being copied separately.
+    pub rects_coalesced: u64,
+    /// The total number of bytes copied to the real framebuffer.
+    pub bytes_copied: u64,
+}
+
+/// Returns the current statistics about flushing the early printer's back buffer.
+pub fn flush_stats() -> FlushStats {
+    FlushStats {
+        flushes: FLUSH_COUNT.load(Ordering::Relaxed),
+        rects_flushed: RECTS_FLUSHED.load(Ordering::Relaxed),
+        rects_coalesced: RECTS_COALESCED.load(Ordering::Relaxed),
+        bytes_copied: BYTES_COPIED.load(Ordering::Relaxed),
+    }
+}
+
+/// Returns whether the early printer renders into a back buffer.
+pub fn is_double_buffering_enabled() -> bool {
+    DOUBLE_BUFFERING_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Enables or disables rendering into a back buffer.
+///
+/// The back buffer is the same size as the real framebuffer,
+/// which can be several MiB at high resolutions, so memory-constrained
+/// configurations may wish to disable it.
+///
+/// Disabling double buffering flushes and then frees the current back buffer.
+/// Enabling it only takes effect the next time the early printer is initialized
+/// with a page table, i.e., in [`init()`].
+pub fn set_double_buffering(enable: bool) {
+    DOUBLE_BUFFERING_ENABLED.store(enable, Ordering::Relaxed);
+    if !enable {
+        if let Some(EarlyPrinter::Framebuffer(efb)) = EARLY_FRAMEBUFFER_PRINTER.lock().as_mut() {
+            efb.flush();
+            efb.staging_fb = None;
+        }
+    }
+}
+
 /// The early printer can either use a graphical framebuffer or text-mode VGA.
 enum EarlyPrinter {
     Framebuffer(EarlyFramebufferPrinter),
@@ -108,23 +178,12 @@ pub fn init(
         let num_pages = frames.size_in_frames();
         let pages = memory::allocate_pages(num_pages)
             .ok_or("couldn't allocate pages for early framebuffer printer")?;
-        let mut flags: PteFlagsArch = PteFlags::new()
-            .valid(true)
-            .writable(true)
-            .into();
-
-        #[cfg(target_arch = "x86_64")] {
-            if page_attribute_table::init().is_ok() {
-                flags = flags.pat_index(
-                    page_attribute_table::MemoryCachingType::WriteCombining.pat_slot_index()
-                );
-            } else {
-                flags = flags.device_memory(true);
-            }
-        }
-        #[cfg(not(target_arch = "x86_64"))] {
-            flags = flags.device_memory(true);
-        }
+        // The PAT must be set up on this CPU before we can use it to map the framebuffer
+        // as write-combining; if unsupported, `memory_type()` falls back to uncacheable.
+        #[cfg(target_arch = "x86_64")]
+        let _ = page_attribute_table::init();
+        let flags = PteFlagsArch::from(PteFlags::new().valid(true).writable(true))
+            .memory_type(MemoryType::WriteCombining);
 
         flags_used = Some(flags);
         let mp = pg_tbl.map_allocated_pages_to(pages, frames, flags)?;
@@ -132,9 +191,12 @@ pub fn init(
             mp.into_borrowed_slice_mut(0, fb_pixel_count).map_err(|(_mp, s)| s)?
         );
 
-        // Attempt to allocate a staging framebuffer, which is used to significantly
-        // accelerate scrolling by not having to read from the framebuffer memory.
-        let staging_fb = memory::allocate_pages(num_pages)
+        // Attempt to allocate a staging framebuffer (back buffer), which is used to
+        // significantly accelerate scrolling by not having to read from the framebuffer memory,
+        // and to avoid tearing by only copying fully-rendered regions to the framebuffer.
+        let staging_fb = is_double_buffering_enabled()
+            .then(|| memory::allocate_pages(num_pages))
+            .flatten()
             .and_then(|pages|
                 pg_tbl.map_allocated_pages(
                     pages,
@@ -172,6 +234,7 @@ pub fn init(
         stride: info.stride,
         format: info.format,
         curr_pixel,
+        damage: DamageList::new(),
     };
 
     let _res = early_fb.write_fmt(format_args!(
@@ -195,7 +258,8 @@ pub fn init(
 /// allowing it to be re-used elsewhere.
 #[doc(alias("deinit", "clean up"))]
 pub fn take() -> Option<EarlyFramebufferPrinter> {
-    if let Some(EarlyPrinter::Framebuffer(early_fb)) = EARLY_FRAMEBUFFER_PRINTER.lock().take() {
+    if let Some(EarlyPrinter::Framebuffer(mut early_fb)) = EARLY_FRAMEBUFFER_PRINTER.lock().take() {
+        early_fb.flush();
         Some(early_fb)
     } else {
         None
@@ -251,6 +315,8 @@ pub struct EarlyFramebufferPrinter {
     pub format: FramebufferFormat,
     /// The current pixel coordinate where the next character will be printed.
     curr_pixel: PixelCoord,
+    /// The regions of the staging fb that haven't yet been copied to the main fb.
+    damage: DamageList,
 }
 
 impl EarlyFramebufferPrinter {
@@ -263,6 +329,9 @@ impl EarlyFramebufferPrinter {
     }
 
     /// Prints the given character to the current location in this framebuffer.
+    ///
+    /// If this printer has a staging fb, the character will not be visible
+    /// until [`flush()`](Self::flush) is called.
     pub fn print_char(
         &mut self,
         ch: char,
@@ -292,6 +361,12 @@ impl EarlyFramebufferPrinter {
             dest_fb[fb_row_range].copy_from_slice(&pixel_row);
         }
 
+        self.add_damage(DamageRect {
+            x: self.curr_pixel.x,
+            y: self.curr_pixel.y,
+            width: CHARACTER_WIDTH,
+            height: CHARACTER_HEIGHT,
+        });
         self.advance_by_one_char(background_pixel_color);
     }
 
@@ -312,8 +387,6 @@ impl EarlyFramebufferPrinter {
         self.fill_character_line(self.curr_pixel, background_pixel_color);
         self.curr_pixel.x = 0;
 
-        self.copy_line_from_staging_fb();
-
         let next_row = self.curr_pixel.y + CHARACTER_HEIGHT;
         if next_row >= self.height {
             return self.scroll(background_pixel_color);
@@ -338,12 +411,9 @@ impl EarlyFramebufferPrinter {
         }
         let start_of_last_line = self.height - CHARACTER_HEIGHT;
         self.curr_pixel = PixelCoord { x: 0, y: start_of_last_line };
+        // Every line has moved, so the whole width and height of the staging fb is damaged.
+        self.add_damage(DamageRect { x: 0, y: 0, width: self.width, height: self.height });
         self.fill_character_line(self.curr_pixel, background_pixel_color);
-
-        if let Some(staging_fb) = self.staging_fb.as_deref() {
-            // Copy from the staging fb (if we have one) to the main fb.
-            self.fb.copy_from_slice(staging_fb);
-        }
     }
 
     /// Fills a full character line's worth of pixels from the `start_pixel` coordinate
@@ -363,18 +433,46 @@ impl EarlyFramebufferPrinter {
             let dest_fb = self.staging_fb.as_deref_mut().unwrap_or(self.fb.deref_mut());
             dest_fb[start_idx as usize .. end_idx as usize].fill(background_pixel_color);
         }
+        self.add_damage(DamageRect {
+            x: start_pixel.x,
+            y: start_pixel.y,
+            width: row_remainder_len,
+            height: CHARACTER_HEIGHT,
+        });
     }
 
-    /// Copies a full character line's worth of pixels from the staging fb to the main fb.
+    /// Records that the given region of the staging fb has been modified.
     ///
-    /// If there is no staging fb, this does nothing.
-    fn copy_line_from_staging_fb(&mut self) {
-        if let Some(staging_fb) = self.staging_fb.as_deref() {
-            let start_idx = self.curr_pixel.y * self.stride;
-            let end_idx = start_idx + (CHARACTER_HEIGHT * self.stride);
-            let range = start_idx as usize .. end_idx as usize;
-            self.fb[range.clone()].copy_from_slice(&staging_fb[range]);
+    /// If there is no staging fb, this does nothing, as all writes go directly to the main fb.
+    fn add_damage(&mut self, rect: DamageRect) {
+        if self.staging_fb.is_some() {
+            let coalesced = self.damage.add(rect);
+            RECTS_COALESCED.fetch_add(coalesced as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Copies all damaged regions of the staging fb to the main fb.
+    ///
+    /// If there is no staging fb or nothing has been damaged, this does nothing.
+    pub fn flush(&mut self) {
+        let Some(staging_fb) = self.staging_fb.as_deref() else { return };
+        if self.damage.is_empty() {
+            return;
+        }
+        let mut rects = 0;
+        let mut bytes = 0;
+        for rect in self.damage.drain() {
+            for row in rect.y .. rect.y + rect.height {
+                let start_idx = (row * self.stride + rect.x) as usize;
+                let range = start_idx .. start_idx + rect.width as usize;
+                self.fb[range.clone()].copy_from_slice(&staging_fb[range]);
+            }
+            rects += 1;
+            bytes += rect.width as u64 * rect.height as u64 * core::mem::size_of::<u32>() as u64;
         }
+        FLUSH_COUNT.fetch_add(1, Ordering::Relaxed);
+        RECTS_FLUSHED.fetch_add(rects, Ordering::Relaxed);
+        BYTES_COPIED.fetch_add(bytes, Ordering::Relaxed);
     }
 }
 
@@ -386,6 +484,7 @@ impl Write for EarlyFramebufferPrinter {
         for ch in s.chars() {
             self.print_char(ch, BLUE, LIGHT_GRAY);
         }
+        self.flush();
         Ok(())
     }
 }
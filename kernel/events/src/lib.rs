@@ -0,0 +1,256 @@
+//! A registry of named events, for signaling loosely-coupled subscribers
+//! that something happened without passing channel handles between them.
+//!
+//! A producer posts to an event by name, e.g., `"storage.device_added"`,
+//! via [`post()`], and every task that has subscribed to that name via [`subscribe()`]
+//! receives the posting's `usize` argument through its own [`EventSubscription`].
+//! Event names are created on demand by either subscribing or posting to them.
+//!
+//! Each subscription has a bounded queue of pending postings.
+//! If a subscriber falls behind and its queue is full, further postings to it
+//! are dropped and counted rather than blocking the poster;
+//! see [`EventSubscription::dropped()`].
+//!
+//! [`post()`] never blocks, so it can be called from any context, including
+//! interrupt handlers and deferred interrupt tasks.
+//! Posting to an event name for the first time allocates that event's registry entry.
+//!
+//! A subscription is removed when its [`EventSubscription`] is dropped,
+//! or when the task that created it exits or is killed, whichever comes first.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    sync::{Arc, Weak},
+    task::Wake,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use sync_irq::{DisableIrq, IrqSafeMutex};
+use task::{CleanupGuard, CleanupReason};
+use time::{now, Duration, Monotonic};
+use wait_queue::WaitQueue;
+
+/// The default number of pending postings that a subscription can hold before
+/// further postings to it are dropped.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 16;
+
+/// All event names that have been subscribed or posted to, and their state.
+static EVENTS: IrqSafeMutex<BTreeMap<String, Event>> = IrqSafeMutex::new(BTreeMap::new());
+
+/// The ID of the next subscription to be created, used to remove it from its event.
+static NEXT_SUBSCRIBER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The state of a single named event.
+#[derive(Default)]
+struct Event {
+    subscribers: Vec<Arc<Subscriber>>,
+    /// The number of times this event has been posted.
+    posted: u64,
+    /// The number of postings that were queued for a subscriber.
+    delivered: u64,
+    /// The number of postings that were dropped because a subscriber's queue was full.
+    dropped: u64,
+}
+
+/// A single subscriber to a named event.
+struct Subscriber {
+    id: usize,
+    capacity: usize,
+    pending: IrqSafeMutex<VecDeque<usize>>,
+    /// The number of postings dropped because `pending` was full.
+    dropped: AtomicU64,
+    /// The tasks blocked waiting for a posting to this subscriber.
+    waiters: WaitQueue<DisableIrq>,
+}
+
+/// A subscription to a named event, created by [`subscribe()`].
+///
+/// Postings to the event are queued for this subscription until they are received
+/// via [`wait()`](Self::wait), [`wait_timeout()`](Self::wait_timeout),
+/// or [`try_recv()`](Self::try_recv).
+///
+/// Dropping this unsubscribes from the event.
+/// If the task that created this subscription exits or is killed
+/// without dropping it, it is unsubscribed by that task's cleanup hooks.
+pub struct EventSubscription {
+    name: String,
+    subscriber: Arc<Subscriber>,
+    _cleanup: Option<CleanupGuard>,
+}
+
+/// Statistics about a named event, as returned by [`list()`].
+#[derive(Clone, Debug)]
+pub struct EventStats {
+    pub name: String,
+    /// The number of current subscriptions to this event.
+    pub subscribers: usize,
+    /// The number of times this event has been posted.
+    pub posted: u64,
+    /// The number of postings that were queued for a subscriber,
+    /// counting each subscriber separately.
+    pub delivered: u64,
+    /// The number of postings that were dropped because a subscriber's queue was full.
+    pub dropped: u64,
+}
+
+/// Subscribes the current task to the event with the given `name`,
+/// with a queue of up to [`DEFAULT_QUEUE_CAPACITY`] pending postings.
+///
+/// Only postings made after this returns are delivered to the new subscription.
+pub fn subscribe(name: &str) -> EventSubscription {
+    subscribe_with_capacity(name, DEFAULT_QUEUE_CAPACITY)
+}
+
+/// Subscribes the current task to the event with the given `name`,
+/// with a queue of up to `capacity` pending postings.
+///
+/// A `capacity` of zero is treated as one.
+pub fn subscribe_with_capacity(name: &str, capacity: usize) -> EventSubscription {
+    let capacity = capacity.max(1);
+    let id = NEXT_SUBSCRIBER_ID.fetch_add(1, Ordering::Relaxed);
+    let subscriber = Arc::new(Subscriber {
+        id,
+        capacity,
+        pending: IrqSafeMutex::new(VecDeque::with_capacity(capacity)),
+        dropped: AtomicU64::new(0),
+        waiters: WaitQueue::new(),
+    });
+    EVENTS.lock()
+        .entry(String::from(name))
+        .or_default()
+        .subscribers
+        .push(Arc::clone(&subscriber));
+
+    let hook_name = String::from(name);
+    let cleanup = CleanupGuard::new(Box::new(move |_: CleanupReason| unsubscribe(&hook_name, id)));
+    EventSubscription {
+        name: String::from(name),
+        subscriber,
+        _cleanup: cleanup,
+    }
+}
+
+/// Removes the subscriber with the given `id` from the event with the given `name`, if present.
+fn unsubscribe(name: &str, id: usize) {
+    if let Some(event) = EVENTS.lock().get_mut(name) {
+        event.subscribers.retain(|s| s.id != id);
+    }
+}
+
+/// Posts the event with the given `name`, delivering `arg` to all of its current subscribers
+/// and waking any tasks waiting on those subscriptions.
+///
+/// This never blocks. If a subscriber's queue of pending postings is full,
+/// the posting is dropped for that subscriber and counted.
+///
+/// Returns the number of subscribers that the posting was queued for.
+pub fn post(name: &str, arg: usize) -> usize {
+    let mut events = EVENTS.lock();
+    // Avoid allocating a new name unless this event has never been used before.
+    if !events.contains_key(name) {
+        events.insert(String::from(name), Event::default());
+    }
+    let Some(event) = events.get_mut(name) else { return 0 };
+    event.posted += 1;
+    let mut delivered = 0;
+    for subscriber in &event.subscribers {
+        let mut pending = subscriber.pending.lock();
+        if pending.len() < subscriber.capacity {
+            pending.push_back(arg);
+            delivered += 1;
+        } else {
+            subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+            event.dropped += 1;
+        }
+        drop(pending);
+        subscriber.waiters.notify_all();
+    }
+    event.delivered += delivered as u64;
+    delivered
+}
+
+/// Returns statistics about every event that has been subscribed or posted to,
+/// in order of event name.
+pub fn list() -> Vec<EventStats> {
+    EVENTS.lock()
+        .iter()
+        .map(|(name, event)| EventStats {
+            name: name.clone(),
+            subscribers: event.subscribers.len(),
+            posted: event.posted,
+            delivered: event.delivered,
+            dropped: event.dropped,
+        })
+        .collect()
+}
+
+impl EventSubscription {
+    /// Returns the name of the event that this subscription is for.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the argument of the oldest pending posting, without blocking,
+    /// or `None` if there are no pending postings.
+    pub fn try_recv(&self) -> Option<usize> {
+        self.subscriber.pending.lock().pop_front()
+    }
+
+    /// Blocks the current task until a posting is pending, and returns its argument.
+    pub fn wait(&self) -> usize {
+        self.subscriber.waiters.wait_until(|| self.try_recv())
+    }
+
+    /// Blocks the current task until a posting is pending or the given `timeout` elapses.
+    ///
+    /// Returns the posting's argument, or `None` if the timeout elapsed first.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<usize> {
+        if let Some(arg) = self.try_recv() {
+            return Some(arg);
+        }
+        let deadline = now::<Monotonic>() + timeout;
+        // Arm a timer that wakes this subscription's waiters at the deadline,
+        // such that they re-check the deadline even if nothing is posted.
+        let alarm = Arc::new(TimeoutAlarm(Arc::downgrade(&self.subscriber)));
+        sleep::future::sleep(timeout, alarm.into());
+
+        self.subscriber.waiters.wait_until(|| match self.try_recv() {
+            Some(arg) => Some(Some(arg)),
+            None if now::<Monotonic>() >= deadline => Some(None),
+            None => None,
+        })
+    }
+
+    /// Returns the number of postings that were dropped for this subscription
+    /// because its queue of pending postings was full.
+    pub fn dropped(&self) -> u64 {
+        self.subscriber.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for EventSubscription {
+    fn drop(&mut self) {
+        unsubscribe(&self.name, self.subscriber.id);
+    }
+}
+
+/// Wakes the waiters of a subscription when a [`EventSubscription::wait_timeout()`] expires.
+struct TimeoutAlarm(Weak<Subscriber>);
+
+impl Wake for TimeoutAlarm {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Some(subscriber) = self.0.upgrade() {
+            subscriber.waiters.notify_all();
+        }
+    }
+}
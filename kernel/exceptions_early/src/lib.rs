@@ -4,15 +4,9 @@
 #![feature(abi_x86_interrupt)]
 
 use spin::Mutex;
-use x86_64::{
-    structures::{
-        idt::{InterruptStackFrame, PageFaultErrorCode},
-        tss::TaskStateSegment,
-    },
-    instructions::{
-        segmentation::{CS, DS, SS, Segment}, 
-        tables::load_tss,
-    },
+use x86_64::structures::{
+    idt::{InterruptStackFrame, PageFaultErrorCode},
+    tss::TaskStateSegment,
 };
 use locked_idt::LockedIdt;
 use gdt::{Gdt, create_gdt};
@@ -44,21 +38,13 @@ pub fn init(double_fault_stack_top_unusable: Option<memory::VirtualAddress>) {
     println!("exceptions_early(): double_fault_stack_top_unusable: {:X?}", double_fault_stack_top_unusable);
     if let Some(df_stack_top) = double_fault_stack_top_unusable {
         // Create and load an initial TSS and GDT so we can handle early exceptions such as double faults. 
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[tss::DOUBLE_FAULT_IST_INDEX] = x86_64::VirtAddr::new(df_stack_top.value() as u64);
+        let tss = tss::new_tss(df_stack_top, None);
         println!("exceptions_early(): Created TSS: {:?}", tss);
         *EARLY_TSS.lock() = tss;
         
         let (gdt, kernel_cs, kernel_ds, _user_cs_32, _user_ds_32, _user_cs_64, _user_ds_64, tss_segment) = create_gdt(&EARLY_TSS.lock());
         *EARLY_GDT.lock() = gdt;
-        EARLY_GDT.lock().load();
-
-        unsafe {
-            CS::set_reg(kernel_cs);          // reload code segment register
-            load_tss(tss_segment);           // load TSS
-            SS::set_reg(kernel_ds);          // unsure if necessary, but doesn't hurt
-            DS::set_reg(kernel_ds);          // unsure if necessary, but doesn't hurt
-        }
+        EARLY_GDT.lock().load_with_segments(kernel_cs, kernel_ds, tss_segment);
     }
 
     { 
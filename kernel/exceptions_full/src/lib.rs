@@ -3,6 +3,9 @@
 #![no_std]
 #![feature(abi_x86_interrupt)]
 
+extern crate alloc;
+
+use alloc::{format, string::String};
 use log::{warn, debug, trace};
 use memory::{VirtualAddress, Page};
 use signal_handler::{Signal, SignalContext, ErrorCode};
@@ -57,6 +60,8 @@ pub fn init(idt_ref: &'static LockedIdt) {
         idt.security_exception.set_handler_fn(security_exception_handler);
         // reserved: 0x1F
     }
+    // The above `#DB` handler supports single-step tracing.
+    crash_context::init();
 
     idt_ref.load();
 }
@@ -95,6 +100,9 @@ fn kill_and_halt(
     error_code: Option<ErrorCode>,
     print_stack_trace: bool
 ) {
+    // None of the diagnostics below may be suppressed or collapsed by log throttling.
+    let bypass = logger::bypass_throttling();
+
     // First, log the exception that merits a kill operation.
     {
         let (err, addr) = match error_code {
@@ -162,6 +170,21 @@ fn kill_and_halt(
         println_both!("---------------------- End of Stack Trace ------------------------");
     }
 
+    // print the branches that led up to the exception, if they were recorded
+    if let Ok(branches) = crash_context::take_branch_records() {
+        let namespace = task::with_current_task(|t| t.get_namespace().clone()).ok();
+        let symbolize = |address: u64| namespace.as_ref()
+            .and_then(|ns| ns.get_section_containing_address(VirtualAddress::new_canonical(address as usize), false))
+            .map(|(sec, offset)| format!("{} + {:#X}", sec.name, offset))
+            .unwrap_or_else(|| String::from("??"));
+        println_both!("------------------ Last Branch Records (most recent first) -------");
+        for branch in branches.iter() {
+            println_both!("  {:>#018X} in {}", branch.from, symbolize(branch.from));
+            println_both!("    -> {:>#018X} in {}", branch.to, symbolize(branch.to));
+        }
+        println_both!("-------------------- End of Last Branch Records ------------------");
+    }
+
     let cause = task::KillReason::Exception(exception_number);
 
     // Call this task's kill handler, if it has one.
@@ -189,6 +212,9 @@ fn kill_and_halt(
         }
     }
 
+    // Neither unwinding nor killing this task returns here, so stop bypassing throttling now.
+    drop(bypass);
+
     // Unwind the current task that failed due to the given exception.
     // This doesn't always work perfectly, so it's disabled by default for now.
     #[cfg(unwind_exceptions)] {
@@ -208,25 +234,16 @@ fn kill_and_halt(
             }
         }
     }
-    #[cfg(not(unwind_exceptions))] {
-        let res = task::with_current_task(|t| {
-            let kill_result = t.kill(cause);
-            match kill_result {
-                Ok(()) => { println_both!("Task {:?} killed itself successfully", t); }
-                Err(e) => { println_both!("Task {:?} was unable to kill itself. Error: {:?}", t, e); }
-            }
-            kill_result
-        });
-        if res.is_err() {
-            println_both!("BUG: kill_and_halt(): Couldn't get current task in order to kill it.");
-        }
-    }
+    // Without unwinding, release the locks this task holds and exit it; this never returns.
+    #[cfg(not(unwind_exceptions))]
+    task::force_unwind_and_exit(cause);
 
     // If we failed to handle the exception and unwind the task, there's not really much we can do about it,
     // other than just let the thread spin endlessly (which doesn't hurt correctness but is inefficient). 
     // But in general, this task should have already been marked as killed and thus no longer schedulable,
     // so it should not reach this point. 
     // Only exceptions during the early OS initialization process will get here, meaning that the OS will basically stop.
+    #[cfg(unwind_exceptions)]
     loop { core::hint::spin_loop() }
 }
 
@@ -253,19 +270,24 @@ fn exception_to_signal(exception_number: u8) -> Option<Signal> {
 
 /// exception 0x00
 extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: DIVIDE ERROR\n{:#X?}\n", stack_frame);
     kill_and_halt(0x0, &stack_frame, None, true)
 }
 
 /// exception 0x01
-extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn debug_handler(mut stack_frame: InterruptStackFrame) {
+    if crash_context::handle_debug_exception(&mut stack_frame) {
+        return;
+    }
     println_both!("\nEXCEPTION: DEBUG EXCEPTION\n{:#X?}", stack_frame);
     // don't halt here, this isn't a fatal/permanent failure, just a brief pause.
 }
 
 /// Exception 0x02 is a Non-Maskable Interrupt (NMI).
 ///
-/// Theseus uses this for TLB Shootdown IPIs and sampling interrupts.
+/// Theseus uses this for the NMIs that CPUs send each other via the `nmi` crate,
+/// e.g., TLB Shootdown IPIs, and for sampling interrupts.
 ///
 /// # Important Note
 /// Acquiring ANY locks in this function, even irq-safe ones, could cause a deadlock
@@ -276,10 +298,12 @@ extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
     // trace!("nmi_handler (CPU {})", cpu::current_cpu());
     let mut expected_nmi = false;
 
-    if tlb_shootdown::handle_tlb_shootdown_ipi() {
+    // NMIs sent by other CPUs carry their reasons in this CPU's NMI mailbox.
+    if nmi::handle_nmi(&stack_frame) {
         return;
     }
 
+    // Otherwise, this NMI came from hardware.
     // Performance monitoring hardware uses NMIs to trigger a sampling interrupt.
     match pmu_x86::handle_sample(&stack_frame) {
         // A PMU sample did occur and was properly handled, so this NMI was expected. 
@@ -297,7 +321,10 @@ extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
         return;
     }
 
-    println_both!("\nEXCEPTION: NON-MASKABLE INTERRUPT at {:#X}\n{:#X?}\n",
+    crash_context::stop_branch_recording();
+    let status = nmi::hardware_nmi_status();
+    println_both!("\nEXCEPTION: NON-MASKABLE INTERRUPT from hardware ({}) at {:#X}\n{:#X?}\n",
+        status.description(),
         stack_frame.instruction_pointer,
         stack_frame,
     );
@@ -315,18 +342,21 @@ extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
 
 /// exception 0x04
 extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: OVERFLOW\n{:#X?}", stack_frame);
     kill_and_halt(0x4, &stack_frame, None, true)
 }
 
 // exception 0x05
 extern "x86-interrupt" fn bound_range_exceeded_handler(stack_frame: InterruptStackFrame) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: BOUND RANGE EXCEEDED\n{:#X?}", stack_frame);
     kill_and_halt(0x5, &stack_frame, None, true)
 }
 
 /// exception 0x06
 extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: INVALID OPCODE\n{:#X?}", stack_frame);
     kill_and_halt(0x6, &stack_frame, None, true)
 }
@@ -336,12 +366,14 @@ extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFram
 /// For more information about "spurious interrupts", 
 /// see [here](http://wiki.osdev.org/I_Cant_Get_Interrupts_Working#I_keep_getting_an_IRQ7_for_no_apparent_reason).
 extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: DEVICE NOT AVAILABLE\n{:#X?}", stack_frame);
     kill_and_halt(0x7, &stack_frame, None, true)
 }
 
 /// exception 0x08
 extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) -> ! {
+    crash_context::stop_branch_recording();
     let accessed_vaddr = Cr2::read_raw();
     println_both!("\nEXCEPTION: DOUBLE FAULT\n{:#X?}\nTried to access {:#X}
         Note: double faults in Theseus are typically caused by stack overflow, is the stack large enough?",
@@ -350,6 +382,7 @@ extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame,
     if is_stack_overflow(VirtualAddress::new_canonical(accessed_vaddr as usize)) {
         println_both!("--> This double fault was definitely caused by stack overflow, tried to access {:#X}.\n", accessed_vaddr);
     }
+    pit_clock_basic::speaker::fatal_beep(pit_clock_basic::speaker::BeepCode::DoubleFault);
     
     kill_and_halt(0x8, &stack_frame, Some(error_code.into()), false);
     loop { core::hint::spin_loop() }
@@ -357,30 +390,35 @@ extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame,
 
 /// exception 0x0A
 extern "x86-interrupt" fn invalid_tss_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: INVALID TSS\n{:#X?}\nError code: {:#b}", stack_frame, error_code);
     kill_and_halt(0xA, &stack_frame, Some(error_code.into()), true)
 }
 
 /// exception 0x0B
 extern "x86-interrupt" fn segment_not_present_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: SEGMENT NOT PRESENT\n{:#X?}\nError code: {:#b}", stack_frame, error_code);
     kill_and_halt(0xB, &stack_frame, Some(error_code.into()), true)
 }
 
 /// exception 0x0C
 extern "x86-interrupt" fn stack_segment_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: STACK SEGMENT FAULT\n{:#X?}\nError code: {:#b}", stack_frame, error_code);
     kill_and_halt(0xC, &stack_frame, Some(error_code.into()), true)
 }
 
 /// exception 0x0D
 extern "x86-interrupt" fn general_protection_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: GENERAL PROTECTION FAULT\n{:#X?}\nError code: {:#b}", stack_frame, error_code);
     kill_and_halt(0xD, &stack_frame, Some(error_code.into()), true)
 }
 
 /// exception 0x0E
 extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
+    crash_context::stop_branch_recording();
     let accessed_vaddr = Cr2::read_raw() as usize;
 
     println_both!("\nEXCEPTION: PAGE FAULT while accessing {:#x}\n\
@@ -399,43 +437,57 @@ extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, e
 
 /// exception 0x10
 extern "x86-interrupt" fn x87_floating_point_handler(stack_frame: InterruptStackFrame) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: x87 FLOATING POINT\n{:#X?}", stack_frame);
     kill_and_halt(0x10, &stack_frame, None, true)
 }
 
 /// exception 0x11
 extern "x86-interrupt" fn alignment_check_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: ALIGNMENT CHECK\n{:#X?}\nError code: {:#b}", stack_frame, error_code);
     kill_and_halt(0x11, &stack_frame, Some(error_code.into()), true)
 }
 
 /// exception 0x12
 extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: MACHINE CHECK\n{:#X?}", stack_frame);
+    let mcg_status = mca::dump_banks(|record| {
+        println_both!("  {}", record);
+    });
+    match mcg_status {
+        Some(mcg_status) => println_both!("IA32_MCG_STATUS: {:#X}", mcg_status),
+        None => println_both!("Machine check architecture is not supported, no banks to decode."),
+    }
     kill_and_halt(0x12, &stack_frame, None, true);
     loop { core::hint::spin_loop() }
 }
 
 /// exception 0x13
 extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: SIMD FLOATING POINT\n{:#X?}", stack_frame);
     kill_and_halt(0x13, &stack_frame, None, true)
 }
 
 /// exception 0x14
 extern "x86-interrupt" fn virtualization_handler(stack_frame: InterruptStackFrame) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: VIRTUALIZATION\n{:#X?}", stack_frame);
     kill_and_halt(0x14, &stack_frame, None, true)
 }
 
 /// exception 0x1D
 extern "x86-interrupt" fn vmm_communication_exception_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: VMM COMMUNICATION EXCEPTION\n{:#X?}\nError code: {:#b}", stack_frame, error_code);
     kill_and_halt(0x1D, &stack_frame, Some(error_code.into()),true)
 }
 
 /// exception 0x1E
 extern "x86-interrupt" fn security_exception_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    crash_context::stop_branch_recording();
     println_both!("\nEXCEPTION: SECURITY EXCEPTION\n{:#X?}\nError code: {:#b}", stack_frame, error_code);
     kill_and_halt(0x1E, &stack_frame, Some(error_code.into()), true)
 }
@@ -135,8 +135,8 @@ fn update_and_insert_fault_entry_internal(
     instruction_pointer: Option<usize>, 
 ) {
 
-    // Add the core the fault was detected
-    fe.cpu = Some(cpu::current_cpu());
+    // Add the core the fault was detected, if it's known.
+    fe.cpu = cpu::try_current_cpu();
 
     // If current task cannot be obtained we will just add `fault_entry` to 
     // the `fault_log` and return.
@@ -0,0 +1,229 @@
+//! A buddy-system allocator for naturally-aligned, physically-contiguous blocks of frames.
+//!
+//! Free memory is tracked as blocks of `2^order` frames, each aligned to its own size,
+//! with one free list per order. A request is satisfied by the smallest free block
+//! that fits, which is split in halves ("buddies") until it has the requested order.
+//! When a block is freed, it is merged with its buddy for as long as that buddy is also free.
+//! Thus, allocating and freeing a block of any order takes `O(MAX_ORDER * log n)` time,
+//! where `n` is the number of free blocks, regardless of how fragmented memory is.
+//!
+//! Because every block is aligned to its own size, a block of order 9 or 18
+//! can be mapped directly as a 2 MiB or 1 GiB huge page, respectively.
+//!
+//! A `BuddyAllocator` manages only the frames that are explicitly given to it,
+//! so it is independent of the system-wide frame allocator in this crate.
+//! It requires heap allocation to track its free lists.
+
+use alloc::{collections::BTreeSet, vec::Vec};
+use kernel_config::memory::PAGE_SIZE;
+use memory_structs::{Frame, FrameRange, PhysicalAddress};
+
+/// The largest supported order: a block of `2^MAX_ORDER` 4KiB frames is 1 GiB.
+const MAX_ORDER: u32 = 18;
+
+const NUM_ORDERS: usize = MAX_ORDER as usize + 1;
+
+/// A buddy-system allocator of physical frames.
+///
+/// See the [module-level documentation](self) for more details.
+pub struct BuddyAllocator {
+    /// For each order, the set of starting frame numbers of free blocks of that order.
+    free_lists: [BTreeSet<usize>; NUM_ORDERS],
+    /// The total number of free frames across all free lists.
+    free_frames: usize,
+}
+
+impl BuddyAllocator {
+    /// The largest order of block that can be allocated: `2^18` 4KiB frames, i.e., 1 GiB.
+    pub const MAX_ORDER: u32 = MAX_ORDER;
+
+    /// Creates a new allocator that has no free frames.
+    pub const fn new() -> BuddyAllocator {
+        const EMPTY: BTreeSet<usize> = BTreeSet::new();
+        BuddyAllocator {
+            free_lists: [EMPTY; NUM_ORDERS],
+            free_frames: 0,
+        }
+    }
+
+    /// Creates a new allocator from the given lists of available and reserved physical memory regions,
+    /// e.g., those parsed from the bootloader-provided memory map.
+    ///
+    /// As with [`init()`](crate::init), any of the regions may overlap,
+    /// and reserved regions take priority over available regions.
+    pub fn from_regions<F, R>(free_regions: F, reserved_regions: R) -> BuddyAllocator
+    where
+        F: IntoIterator<Item = FrameRange>,
+        R: IntoIterator<Item = FrameRange>,
+    {
+        let mut allocator = BuddyAllocator::new();
+        for region in free_regions {
+            allocator.add_free_range(region);
+        }
+        for region in reserved_regions {
+            allocator.remove_free_range(region);
+        }
+        allocator
+    }
+
+    /// Returns the total number of free frames.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Returns the number of free blocks of the given `order`.
+    pub fn free_blocks(&self, order: u32) -> usize {
+        self.free_lists.get(order as usize).map_or(0, BTreeSet::len)
+    }
+
+    /// Returns the order of the largest free block, i.e., the largest request
+    /// that can currently be satisfied, or `None` if there are no free frames.
+    pub fn largest_free_order(&self) -> Option<u32> {
+        (0..=MAX_ORDER).rev().find(|&order| self.free_blocks(order) > 0)
+    }
+
+    /// Allocates a single frame, i.e., a block of order 0.
+    pub fn allocate_frame(&mut self) -> Option<Frame> {
+        self.allocate_block(0).map(|range| *range.start())
+    }
+
+    /// Allocates a block of `2^order` contiguous frames that is aligned to its own size.
+    ///
+    /// The block must later be returned via [`deallocate_frames()`](Self::deallocate_frames).
+    pub fn allocate_block(&mut self, order: u32) -> Option<FrameRange> {
+        if order > MAX_ORDER {
+            return None;
+        }
+        // Find the smallest free block that's big enough.
+        let (found_order, start) = (order..=MAX_ORDER).find_map(|o|
+            self.free_lists[o as usize].pop_first().map(|start| (o, start))
+        )?;
+        // Split it until it has the requested order, freeing the upper half each time.
+        for o in (order..found_order).rev() {
+            self.free_lists[o as usize].insert(start + (1 << o));
+        }
+        self.free_frames -= 1 << order;
+        Some(range_of(start, 1 << order))
+    }
+
+    /// Allocates `num_frames` contiguous frames.
+    ///
+    /// The returned range starts at a block aligned to the next power of two
+    /// of `num_frames`; the unneeded frames at the end of that block are freed immediately.
+    pub fn allocate_frames(&mut self, num_frames: usize) -> Option<FrameRange> {
+        if num_frames == 0 {
+            return None;
+        }
+        let order = num_frames.next_power_of_two().trailing_zeros();
+        let block = self.allocate_block(order)?;
+        let start = block.start().number();
+        let unused = (1usize << order) - num_frames;
+        if unused > 0 {
+            self.deallocate_frames(range_of(start + num_frames, unused));
+        }
+        Some(range_of(start, num_frames))
+    }
+
+    /// Returns the given frames to this allocator, merging them with their free buddies.
+    ///
+    /// The frames need not be a single block; any range previously returned by this allocator,
+    /// or a subset of one, may be freed.
+    /// The frames must not currently be free, i.e., they must not be freed twice.
+    pub fn deallocate_frames(&mut self, frames: FrameRange) {
+        for (start, order) in blocks_in(&frames) {
+            self.insert_block(start, order);
+        }
+    }
+
+    /// Adds the given frames to this allocator, ignoring any that are already free.
+    pub fn add_free_range(&mut self, frames: FrameRange) {
+        self.remove_free_range(frames.clone());
+        self.deallocate_frames(frames);
+    }
+
+    /// Removes the given frames from this allocator such that they will never be allocated,
+    /// ignoring any that aren't currently free.
+    pub fn remove_free_range(&mut self, frames: FrameRange) {
+        let (first, last) = match bounds(&frames) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        for order in 0..=MAX_ORDER {
+            let size = 1usize << order;
+            // Only blocks that start within `size` frames before `first` can overlap the range.
+            let overlapping = self.free_lists[order as usize]
+                .range(first.saturating_sub(size - 1) ..= last)
+                .copied()
+                .collect::<Vec<_>>();
+            for start in overlapping {
+                let end = start + size - 1;
+                self.free_lists[order as usize].remove(&start);
+                self.free_frames -= size;
+                // Free the parts of the block that lie outside of the removed range.
+                if start < first {
+                    self.deallocate_frames(range_of(start, first - start));
+                }
+                if end > last {
+                    self.deallocate_frames(range_of(last + 1, end - last));
+                }
+            }
+        }
+    }
+
+    /// Inserts the free block of `2^order` frames at `start`, merging it with its buddy
+    /// for as long as that buddy is also free.
+    fn insert_block(&mut self, mut start: usize, mut order: u32) {
+        self.free_frames += 1 << order;
+        while order < MAX_ORDER {
+            let buddy = start ^ (1 << order);
+            if !self.free_lists[order as usize].remove(&buddy) {
+                break;
+            }
+            start &= !(1 << order);
+            order += 1;
+        }
+        self.free_lists[order as usize].insert(start);
+    }
+}
+
+impl Default for BuddyAllocator {
+    fn default() -> Self {
+        BuddyAllocator::new()
+    }
+}
+
+/// Returns the first and last frame numbers of the given range, if it's non-empty.
+fn bounds(frames: &FrameRange) -> Option<(usize, usize)> {
+    let (first, last) = (frames.start().number(), frames.end().number());
+    (first <= last).then_some((first, last))
+}
+
+/// Returns the range of `count` frames starting at frame number `start`.
+fn range_of(start: usize, count: usize) -> FrameRange {
+    FrameRange::new(frame_number(start), frame_number(start + count - 1))
+}
+
+fn frame_number(number: usize) -> Frame {
+    Frame::containing_address(PhysicalAddress::new_canonical(number * PAGE_SIZE))
+}
+
+/// Returns an iterator over the largest naturally-aligned blocks that exactly cover the given range,
+/// as `(starting frame number, order)` pairs.
+fn blocks_in(frames: &FrameRange) -> impl Iterator<Item = (usize, u32)> {
+    let (mut next, end) = match bounds(frames) {
+        Some((first, last)) => (first, last + 1),
+        None => (0, 0),
+    };
+    core::iter::from_fn(move || {
+        if next >= end {
+            return None;
+        }
+        // The block must be aligned to its size and must not extend beyond the end of the range.
+        let max_by_alignment = if next == 0 { MAX_ORDER } else { next.trailing_zeros().min(MAX_ORDER) };
+        let max_by_size = (usize::BITS - 1) - (end - next).leading_zeros();
+        let order = max_by_alignment.min(max_by_size);
+        let start = next;
+        next += 1 << order;
+        Some((start, order))
+    })
+}
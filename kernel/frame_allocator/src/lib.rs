@@ -18,6 +18,12 @@
 //! free chunks for de-fragmentation. It does not iteratively merge adjacent chunks in order to
 //! maximally combine separate chunks into the biggest single chunk.
 //! Instead, free chunks are merged only when they are dropped or when needed to fulfill a specific request.
+//!
+//! For pools of frames that need frequent, naturally-aligned contiguous allocations,
+//! e.g., for DMA buffers or huge pages, see the separate [`BuddyAllocator`].
+//!
+//! Frames can be shared by multiple owners, e.g., for copy-on-write mappings,
+//! by reference counting them via [`frame_ref_inc()`] and [`frame_ref_dec()`].
 
 #![no_std]
 #![allow(clippy::blocks_in_if_conditions)]
@@ -30,9 +36,15 @@ mod test;
 
 mod static_array_rb_tree;
 // mod static_array_linked_list;
+mod buddy;
+mod refcount;
+
+pub use buddy::BuddyAllocator;
+pub use refcount::{frame_ref_inc, frame_ref_dec, frame_ref_count};
 
 use core::{borrow::Borrow, cmp::{Ordering, min, max}, fmt, mem, ops::{Deref, DerefMut}};
 use intrusive_collections::Bound;
+use kassert::kassert_once;
 use kernel_config::memory::*;
 use log::{error, warn, debug, trace};
 use memory_structs::{PhysicalAddress, Frame, FrameRange, MemoryState, PageSize, Page4K, Page2M, Page1G};
@@ -57,6 +69,11 @@ static GENERAL_REGIONS: Mutex<StaticArrayRBTree<PhysicalMemoryRegion>> = Mutex::
 /// This does not indicate whether these regions are currently allocated, 
 /// rather just where they exist and which regions are known to this allocator.
 static RESERVED_REGIONS: Mutex<StaticArrayRBTree<PhysicalMemoryRegion>> = Mutex::new(StaticArrayRBTree::empty());
+/// The fixed list of regions that were reserved when the frame allocator was initialized,
+/// e.g., memory used by the bootloader or the kernel image.
+/// Unlike [`RESERVED_REGIONS`], this never includes regions that were added later at runtime,
+/// e.g., device memory discovered by device drivers.
+static BOOT_RESERVED_REGIONS: Mutex<StaticArrayRBTree<PhysicalMemoryRegion>> = Mutex::new(StaticArrayRBTree::empty());
 
 
 /// Initialize the frame allocator with the given list of available and reserved physical memory regions.
@@ -175,6 +192,7 @@ pub fn init<F, R, P>(
     *FREE_GENERAL_FRAMES_LIST.lock()  = StaticArrayRBTree::new(free_list_w_frames);
     *FREE_RESERVED_FRAMES_LIST.lock() = StaticArrayRBTree::new(reserved_list_w_frames);
     *GENERAL_REGIONS.lock()           = StaticArrayRBTree::new(free_list);
+    *BOOT_RESERVED_REGIONS.lock()     = StaticArrayRBTree::new(reserved_list.clone());
     *RESERVED_REGIONS.lock()          = StaticArrayRBTree::new(reserved_list);
 
     Ok(into_unmapped_frames)
@@ -323,6 +341,39 @@ pub enum MemoryRegionType {
     Unknown,
 }
 
+/// The kind of physical memory that a range of frames is expected to refer to.
+///
+/// This is used to sanity check mappings to caller-chosen frames,
+/// e.g., to catch a device's MMIO region being mapped as if it were RAM, or vice versa.
+/// See [`frames_are_kind()`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    /// Physical memory (RAM) known to this allocator since it was initialized,
+    /// either as a general-purpose region or a region reserved at boot time.
+    Ram,
+    /// Device memory, e.g., a PCI BAR or other MMIO region,
+    /// which must not overlap any general-purpose RAM region.
+    Device,
+}
+
+/// Returns `true` if all of the given `frames` are consistent with the given [`FrameKind`].
+///
+/// * For [`FrameKind::Ram`], every frame must lie within a general-purpose region
+///   or a region that was reserved when this allocator was initialized.
+/// * For [`FrameKind::Device`], no frame may lie within a general-purpose region.
+pub fn frames_are_kind(frames: &FrameRange<Page4K>, kind: FrameKind) -> bool {
+    match kind {
+        FrameKind::Ram => {
+            // Regions never overlap each other, so the frames are fully covered
+            // if the number of overlapping frames is equal to the number of frames.
+            let covered = count_overlapping_frames(&GENERAL_REGIONS.lock(), frames)
+                + count_overlapping_frames(&BOOT_RESERVED_REGIONS.lock(), frames);
+            covered == frames.size_in_frames()
+        }
+        FrameKind::Device => !contains_any(&GENERAL_REGIONS.lock(), frames),
+    }
+}
+
 /// A range of contiguous frames in physical memory.
 ///
 /// Each `Frames` object is globally unique, meaning that the owner of a `Frames` object
@@ -508,6 +559,20 @@ impl<const S: MemoryState, P: PageSize> Drop for Frames<S, P> {
                     // with an existing contiguously-adjacent chunk or if we need to insert a new chunk.
                     Inner::RBTree(ref mut tree) => {
                         let mut cursor_mut = tree.lower_bound_mut(Bound::Included(free_frames.start()));
+                        // Deallocated frames that overlap a free chunk have been freed twice.
+                        // Inserting them would corrupt the free list, so they're leaked instead.
+                        // They must be forgotten, as dropping them would re-enter this handler.
+                        let overlaps_next = cursor_mut.get()
+                            .map_or(false, |next_frames_ref| next_frames_ref.start() <= free_frames.end());
+                        let overlaps_prev = cursor_mut.peek_prev().get()
+                            .map_or(false, |prev_frames_ref| prev_frames_ref.end() >= free_frames.start());
+                        if !kassert_once!(
+                            !overlaps_next && !overlaps_prev,
+                            "double free: deallocated {:?} overlaps frames that are already free", free_frames,
+                        ) {
+                            mem::forget(free_frames);
+                            return;
+                        }
                         if let Some(next_frames_ref) = cursor_mut.get() {
                             if *free_frames.end() + 1 == *next_frames_ref.start() {
                                 // extract the next chunk from the list
@@ -557,15 +622,16 @@ impl<const S: MemoryState, P: PageSize> Drop for Frames<S, P> {
                 }
                 log::error!("BUG: couldn't insert deallocated {:?} into free frames list", self.frame_range);
             }
-            // Dropping allocated frames converts them into a 4K-sized `FreeFrames`,
-            // which itself is then dropped.
+            // Dropping allocated frames converts them into 4K-sized `FreeFrames`,
+            // which themselves are then dropped.
+            // Frames that are shared with other owners are only freed by their last owner.
             MemoryState::Allocated => { 
                 // trace!("Converting AllocatedFrames to FreeFrames. Drop handler will be called again {:?}", self.frame_range);
-                let frame_range = mem::take(&mut self.frame_range);
-                let _to_drop = Frames::<{MemoryState::Free}, P> {
-                    typ: self.typ,
-                    frame_range,
-                };
+                let frame_range = mem::take(&mut self.frame_range).into_4k_frames();
+                let typ = self.typ;
+                refcount::release(frame_range, |frame_range| {
+                    let _to_drop = Frames::<{MemoryState::Free}, Page4K> { typ, frame_range };
+                });
             }
             // Dropping mapped frames currently should not ever happen.
             MemoryState::Mapped => panic!("We should never drop a mapped frame! It should be forgotten instead."),
@@ -1109,6 +1175,40 @@ fn contains_any(
     false
 }
 
+/// Returns the number of the given `frames` that are covered by regions in the given list.
+fn count_overlapping_frames(
+    list: &StaticArrayRBTree<PhysicalMemoryRegion>,
+    frames: &FrameRange<Page4K>,
+) -> usize {
+    let mut count = 0;
+    match &list.0 {
+        Inner::Array(ref arr) => {
+            for chunk in arr.iter().flatten() {
+                if let Some(overlap) = chunk.overlap(frames) {
+                    count += overlap.size_in_frames();
+                }
+            }
+        }
+        Inner::RBTree(ref tree) => {
+            let mut cursor = tree.upper_bound(Bound::Included(frames.start()));
+            // No region starts before `frames`, but later regions may still overlap it.
+            if cursor.is_null() {
+                cursor = tree.front();
+            }
+            while let Some(chunk) = cursor.get() {
+                if chunk.start() > frames.end() {
+                    break;
+                }
+                if let Some(overlap) = chunk.overlap(frames) {
+                    count += overlap.size_in_frames();
+                }
+                cursor.move_next();
+            }
+        }
+    }
+    count
+}
+
 /// Adds the given `frames` to the given `regions_list` and `frames_list` as a chunk of reserved frames. 
 /// 
 /// Returns the range of **new** frames that were added to the lists, 
@@ -1362,6 +1462,238 @@ where
 }
 
 
+/// Returns the number of general-purpose frames that are currently free,
+/// not including free frames in reserved regions.
+pub fn free_frame_count() -> usize {
+    FREE_GENERAL_FRAMES_LIST.lock().iter().map(|frames| frames.size_in_frames()).sum()
+}
+
+/// The number of size classes in a [`FragmentationReport`]'s histogram of free extents.
+pub const FRAGMENTATION_SIZE_CLASSES: usize = 16;
+/// The alignment, in frames, of the runs counted by [`FragmentationReport::largest_64k_aligned_frames`].
+const FRAMES_PER_64K: usize = 0x1_0000 / FRAME_4K_SIZE_IN_BYTES;
+
+/// A summary of how fragmented the free general-purpose physical memory is.
+///
+/// Free chunks that are contiguous are counted as a single extent,
+/// even if the allocator currently tracks them as separate chunks.
+#[derive(Clone, Debug, Default)]
+pub struct FragmentationReport {
+    /// The total number of free frames.
+    pub free_frames: usize,
+    /// The number of free extents, i.e., maximal runs of contiguous free frames.
+    pub free_extents: usize,
+    /// The size of the largest free extent, in frames.
+    pub largest_extent_frames: usize,
+    /// The size of the largest run of free frames that starts at a 64 KiB-aligned address,
+    /// which bounds the largest naturally-aligned contiguous buffer a driver can still allocate.
+    pub largest_64k_aligned_frames: usize,
+    /// The number of free extents in each size class:
+    /// entry `i` counts extents of `2^i` up to `2^(i+1) - 1` frames,
+    /// and the last entry also counts all larger extents.
+    pub extents_by_size: [usize; FRAGMENTATION_SIZE_CLASSES],
+}
+
+impl fmt::Display for FragmentationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} free frames ({} KiB) in {} extents, largest extent {} frames, largest 64 KiB-aligned run {} frames",
+            self.free_frames,
+            self.free_frames * FRAME_4K_SIZE_IN_BYTES / 1024,
+            self.free_extents,
+            self.largest_extent_frames,
+            self.largest_64k_aligned_frames,
+        )
+    }
+}
+
+/// Returns a report of how fragmented the free general-purpose physical memory currently is.
+pub fn fragmentation_report() -> FragmentationReport {
+    let mut chunks: alloc::vec::Vec<(usize, usize)> = FREE_GENERAL_FRAMES_LIST.lock()
+        .iter()
+        .map(|frames| (frames.start().number(), frames.end().number()))
+        .collect();
+    // The early array-based list isn't sorted.
+    chunks.sort_unstable();
+
+    let mut report = FragmentationReport::default();
+    let mut add_extent = |start: usize, end: usize| {
+        let size = end - start + 1;
+        report.free_frames += size;
+        report.free_extents += 1;
+        report.largest_extent_frames = max(report.largest_extent_frames, size);
+        let aligned_start = (start + FRAMES_PER_64K - 1) / FRAMES_PER_64K * FRAMES_PER_64K;
+        if aligned_start <= end {
+            report.largest_64k_aligned_frames = max(report.largest_64k_aligned_frames, end - aligned_start + 1);
+        }
+        let class = min(size.ilog2() as usize, FRAGMENTATION_SIZE_CLASSES - 1);
+        report.extents_by_size[class] += 1;
+    };
+
+    let mut current: Option<(usize, usize)> = None;
+    for (start, end) in chunks {
+        current = match current {
+            Some((cur_start, cur_end)) if cur_end + 1 == start => Some((cur_start, end)),
+            Some((cur_start, cur_end)) => {
+                add_extent(cur_start, cur_end);
+                Some((start, end))
+            }
+            None => Some((start, end)),
+        };
+    }
+    if let Some((start, end)) = current {
+        add_extent(start, end);
+    }
+    report
+}
+
+/// Merges every pair of contiguous free chunks in the free lists into a single chunk.
+///
+/// Deallocated frames are only merged with their immediate neighbors,
+/// so contiguous free chunks can accumulate over time, e.g., during boot.
+/// Merging them lets a single allocation request be satisfied from the combined chunk.
+///
+/// Returns the number of merges performed.
+/// This does nothing before the frame allocator has been converted to use the heap.
+pub fn coalesce_free_frames() -> usize {
+    let mut merges = 0;
+    for list in [&FREE_GENERAL_FRAMES_LIST, &FREE_RESERVED_FRAMES_LIST] {
+        let mut list = list.lock();
+        let Inner::RBTree(ref mut tree) = list.0 else { continue };
+        let mut cursor_mut = tree.front_mut();
+        while let Some(chunk) = cursor_mut.get() {
+            let (end, typ) = (*chunk.end(), chunk.typ());
+            let next_is_contiguous = cursor_mut.peek_next().get()
+                .map_or(false, |next| end + 1 == *next.start() && next.typ() == typ);
+            if !next_is_contiguous {
+                cursor_mut.move_next();
+                continue;
+            }
+            // Removing an element moves the cursor to the next one.
+            let mut merged = cursor_mut.remove()
+                .expect("BUG: couldn't remove chunk from free list while coalescing")
+                .into_inner();
+            let next = cursor_mut.remove()
+                .expect("BUG: couldn't remove next chunk from free list while coalescing")
+                .into_inner();
+            if merged.merge(next).is_err() {
+                panic!("BUG: couldn't merge contiguous free chunks while coalescing");
+            }
+            cursor_mut.insert_before(Wrapper::new_link(merged));
+            // Move back to the merged chunk, which may also be contiguous with the next one.
+            cursor_mut.move_prev();
+            merges += 1;
+        }
+    }
+    merges
+}
+
+/// Ranges of boot-reserved frames that are no longer needed once the system has booted,
+/// registered via [`mark_reclaimable_after_boot()`].
+static RECLAIMABLE_AFTER_BOOT: Mutex<alloc::vec::Vec<FrameRange<Page4K>>> = Mutex::new(alloc::vec::Vec::new());
+
+/// Registers the given `frames`, which must lie within a region that was reserved
+/// when this allocator was initialized, as only being needed during boot.
+///
+/// This is intended for memory that the bootloader or early boot code used temporarily,
+/// e.g., the AP startup trampoline or the bootloader modules after they've been copied elsewhere.
+/// Those frames are moved into the general-purpose free list by [`reclaim_boot_frames()`].
+///
+/// This must only be called after heap allocation is available.
+pub fn mark_reclaimable_after_boot(frames: FrameRange<Page4K>) {
+    if frames.size_in_frames() != 0 {
+        RECLAIMABLE_AFTER_BOOT.lock().push(frames);
+    }
+}
+
+/// Moves every free frame within the ranges registered via [`mark_reclaimable_after_boot()`]
+/// from the reserved free list into the general-purpose free list.
+///
+/// Frames that are still allocated at this point are skipped,
+/// and will return to the reserved free list when they're deallocated.
+/// This should be called once, after all boot phases have completed.
+///
+/// Returns the number of frames that were reclaimed.
+pub fn reclaim_boot_frames() -> usize {
+    let ranges = core::mem::take(&mut *RECLAIMABLE_AFTER_BOOT.lock());
+    let mut reclaimed = 0;
+    for range in ranges {
+        // Only the parts of the range that are both reserved at boot and currently free can be reclaimed.
+        let free_parts: alloc::vec::Vec<FrameRange<Page4K>> = FREE_RESERVED_FRAMES_LIST.lock()
+            .iter()
+            .filter_map(|chunk| chunk.overlap(&range))
+            .collect();
+        let boot_reserved_parts = free_parts.into_iter().filter(|part|
+            count_overlapping_frames(&BOOT_RESERVED_REGIONS.lock(), part) == part.size_in_frames()
+        );
+        for part in boot_reserved_parts {
+            match reclaim_reserved_frames(part.clone()) {
+                Ok(n) => reclaimed += n,
+                Err(e) => warn!("Couldn't reclaim boot-reserved frames {:X?}: {}", part, e),
+            }
+        }
+    }
+    reclaimed
+}
+
+/// Converts the given free reserved `frames` into general-purpose frames,
+/// updating the region lists accordingly.
+fn reclaim_reserved_frames(frames: FrameRange<Page4K>) -> Result<usize, &'static str> {
+    let num_frames = frames.size_in_frames();
+    // Removing the frames from the reserved free list fails if any of them are no longer free.
+    let (mut allocated, deferred_action) = {
+        let mut free_reserved_frames_list = FREE_RESERVED_FRAMES_LIST.lock();
+        find_specific_chunk(&mut free_reserved_frames_list, *frames.start(), num_frames)
+            .map_err(<&'static str>::from)?
+    };
+    drop(deferred_action);
+
+    remove_frames_from_regions(&mut RESERVED_REGIONS.lock(), &frames)?;
+    remove_frames_from_regions(&mut BOOT_RESERVED_REGIONS.lock(), &frames)?;
+    GENERAL_REGIONS.lock()
+        .insert(PhysicalMemoryRegion::new(frames, MemoryRegionType::Free))
+        .map_err(|_| "BUG: couldn't insert reclaimed frames into the general regions list")?;
+
+    // Dropping the frames returns them to the free list that matches their type.
+    allocated.typ = MemoryRegionType::Free;
+    drop(allocated);
+    Ok(num_frames)
+}
+
+/// Removes the given `frames` from every region in the given `list`,
+/// splitting regions that extend beyond `frames` on either side.
+fn remove_frames_from_regions(
+    list: &mut StaticArrayRBTree<PhysicalMemoryRegion>,
+    frames: &FrameRange<Page4K>,
+) -> Result<(), &'static str> {
+    let overlapping: alloc::vec::Vec<PhysicalMemoryRegion> = list.iter()
+        .filter(|region| region.overlap(frames).is_some())
+        .cloned()
+        .collect();
+    for region in overlapping {
+        match &mut list.0 {
+            Inner::Array(ref mut arr) => {
+                for elem in arr.iter_mut() {
+                    if elem.as_ref() == Some(&region) {
+                        *elem = None;
+                    }
+                }
+            }
+            Inner::RBTree(ref mut tree) => {
+                tree.find_mut(region.start()).remove();
+            }
+        }
+        if region.start() < frames.start() {
+            list.insert(PhysicalMemoryRegion::new(FrameRange::new(*region.start(), *frames.start() - 1), region.typ))
+                .map_err(|_| "BUG: couldn't insert the lower part of a split region")?;
+        }
+        if region.end() > frames.end() {
+            list.insert(PhysicalMemoryRegion::new(FrameRange::new(*frames.end() + 1, *region.end()), region.typ))
+                .map_err(|_| "BUG: couldn't insert the upper part of a split region")?;
+        }
+    }
+    Ok(())
+}
+
 /// Converts the frame allocator from using static memory (a primitive array) to dynamically-allocated memory.
 /// 
 /// Call this function once heap allocation is available. 
@@ -0,0 +1,97 @@
+//! Reference counts for frames that are shared by multiple owners, e.g., copy-on-write mappings.
+//!
+//! Normally, each allocated frame has exactly one owner, e.g., an `AllocatedFrames`
+//! or an exclusive mapping, which returns it to the allocator when dropped.
+//! A frame passed to [`frame_ref_inc()`] becomes shared: dropping one of its owners
+//! only decrements its reference count, and the frame is only returned to the allocator
+//! once its count drops to zero, i.e., when its last owner is dropped.
+//!
+//! Only shared frames are tracked, in a table indexed by frame number,
+//! so frames that are never shared cost nothing when they are deallocated.
+
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use memory_structs::{Frame, FrameRange};
+use spin::Mutex;
+
+/// The reference count of each shared frame, which is always at least 2.
+/// Frames that aren't in this table have a single owner.
+static SHARED_FRAMES: Mutex<BTreeMap<Frame, usize>> = Mutex::new(BTreeMap::new());
+/// The number of entries in `SHARED_FRAMES`, which allows deallocation
+/// to skip locking it when no frames are shared.
+static NUM_SHARED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// Adds a reference to the given allocated `frame`, which must be owned by the caller,
+/// e.g., before mapping it into another page table.
+///
+/// Returns the frame's new reference count.
+pub fn frame_ref_inc(frame: Frame) -> usize {
+    let mut shared = SHARED_FRAMES.lock();
+    let count = shared.entry(frame).or_insert_with(|| {
+        NUM_SHARED_FRAMES.fetch_add(1, Ordering::Relaxed);
+        1
+    });
+    *count += 1;
+    *count
+}
+
+/// Removes a reference to the given shared `frame` without deallocating it,
+/// e.g., after a copy-on-write fault replaced one of its mappings with a private copy.
+///
+/// Returns the frame's remaining reference count, which is at least 1,
+/// or an error if the frame isn't shared, as its last reference must be released
+/// by dropping the frame's owner instead.
+pub fn frame_ref_dec(frame: Frame) -> Result<usize, &'static str> {
+    let mut shared = SHARED_FRAMES.lock();
+    let count = shared.get_mut(&frame).ok_or("frame_ref_dec(): the frame isn't shared")?;
+    *count -= 1;
+    let remaining = *count;
+    if remaining == 1 {
+        shared.remove(&frame);
+        NUM_SHARED_FRAMES.fetch_sub(1, Ordering::Relaxed);
+    }
+    Ok(remaining)
+}
+
+/// Returns the reference count of the given allocated `frame`,
+/// which is 1 unless it has been shared via [`frame_ref_inc()`].
+pub fn frame_ref_count(frame: Frame) -> usize {
+    SHARED_FRAMES.lock().get(&frame).copied().unwrap_or(1)
+}
+
+/// Removes one reference to each frame in the given `frames`, which are being deallocated,
+/// and invokes `deallocate` for each contiguous run of frames whose count dropped to zero.
+pub(crate) fn release(frames: FrameRange, mut deallocate: impl FnMut(FrameRange)) {
+    if frames.size_in_frames() == 0 {
+        return;
+    }
+    if NUM_SHARED_FRAMES.load(Ordering::Relaxed) == 0 {
+        deallocate(frames);
+        return;
+    }
+
+    let (start, end) = (*frames.start(), *frames.end());
+    let mut shared = SHARED_FRAMES.lock();
+    let mut unshared_start = start;
+    loop {
+        let next_shared = shared.range_mut(unshared_start..=end).next().map(|(frame, count)| {
+            *count -= 1;
+            (*frame, *count)
+        });
+        let Some((shared_frame, remaining)) = next_shared else {
+            break;
+        };
+        if remaining == 1 {
+            shared.remove(&shared_frame);
+            NUM_SHARED_FRAMES.fetch_sub(1, Ordering::Relaxed);
+        }
+        if shared_frame > unshared_start {
+            deallocate(FrameRange::new(unshared_start, shared_frame - 1));
+        }
+        if shared_frame == end {
+            return;
+        }
+        unshared_start = shared_frame + 1;
+    }
+    deallocate(FrameRange::new(unshared_start, end));
+}
@@ -1,4 +1,4 @@
-//! Tests for the `Frames` type, mainly the `split` method.
+//! Tests for the `Frames` type, mainly the `split` method, and for the `BuddyAllocator`.
 
 extern crate std;
 
@@ -225,3 +225,157 @@ fn split_at_beginning_max() {
     assert_eq!(result2.start(), second.start());
     assert_eq!(result2.end(), second.end());
 }
+
+fn frames(start_frame: usize, num_frames: usize) -> FrameRange {
+    FrameRange::new(
+        frame_addr(start_frame * FRAME_4K_SIZE_IN_BYTES),
+        frame_addr((start_frame + num_frames - 1) * FRAME_4K_SIZE_IN_BYTES),
+    )
+}
+
+#[test]
+fn buddy_split_and_coalesce() {
+    let mut buddy = BuddyAllocator::from_regions([frames(0, 16)], []);
+    assert_eq!(buddy.free_frames(), 16);
+    assert_eq!(buddy.free_blocks(4), 1);
+
+    // Allocating one frame splits the block of 16 into blocks of 8, 4, 2, and 1.
+    let single = buddy.allocate_block(0).unwrap();
+    assert_eq!(single, frames(0, 1));
+    assert_eq!(buddy.free_frames(), 15);
+    for order in 0..4 {
+        assert_eq!(buddy.free_blocks(order), 1);
+    }
+    assert_eq!(buddy.largest_free_order(), Some(3));
+
+    // Freeing it merges all of the buddies back together.
+    buddy.deallocate_frames(single);
+    assert_eq!(buddy.free_frames(), 16);
+    assert_eq!(buddy.free_blocks(4), 1);
+    for order in 0..4 {
+        assert_eq!(buddy.free_blocks(order), 0);
+    }
+}
+
+#[test]
+fn buddy_blocks_are_naturally_aligned() {
+    // Start at an odd frame such that the region isn't aligned to any block size.
+    let mut buddy = BuddyAllocator::from_regions([frames(3, 61)], []);
+    assert_eq!(buddy.free_frames(), 61);
+
+    let block = buddy.allocate_block(5).unwrap();
+    assert_eq!(block, frames(32, 32));
+    let block = buddy.allocate_block(3).unwrap();
+    assert_eq!(block.start().number() % 8, 0);
+    assert!(buddy.allocate_block(5).is_none());
+}
+
+#[test]
+fn buddy_allocate_non_power_of_two() {
+    let mut buddy = BuddyAllocator::from_regions([frames(0, 16)], []);
+    let allocated = buddy.allocate_frames(5).unwrap();
+    assert_eq!(allocated, frames(0, 5));
+    // The other 3 frames of the block of 8 are returned immediately.
+    assert_eq!(buddy.free_frames(), 11);
+    let pair = buddy.allocate_block(1).unwrap();
+    assert_eq!(pair, frames(6, 2));
+
+    buddy.deallocate_frames(pair);
+    buddy.deallocate_frames(allocated);
+    assert_eq!(buddy.free_frames(), 16);
+    assert_eq!(buddy.free_blocks(4), 1);
+}
+
+#[test]
+fn buddy_reserved_regions_take_priority() {
+    let mut buddy = BuddyAllocator::from_regions(
+        [frames(0, 32), frames(16, 32)],
+        [frames(10, 4), frames(40, 8)],
+    );
+    // Overlapping free regions are only counted once.
+    assert_eq!(buddy.free_frames(), 48 - 4 - 8);
+
+    let mut allocated = std::vec::Vec::new();
+    while let Some(frame) = buddy.allocate_frame() {
+        assert!(!frames(10, 4).contains_range(&FrameRange::new(frame, frame)));
+        assert!(!frames(40, 8).contains_range(&FrameRange::new(frame, frame)));
+        allocated.push(frame);
+    }
+    assert_eq!(allocated.len(), 36);
+}
+
+#[test]
+fn buddy_fragmentation() {
+    let mut buddy = BuddyAllocator::from_regions([frames(0, 64)], []);
+    let singles: std::vec::Vec<_> = (0..64).map(|_| buddy.allocate_frame().unwrap()).collect();
+    assert_eq!(buddy.free_frames(), 0);
+
+    // Freeing every other frame leaves 32 free frames, none of which are contiguous.
+    for frame in singles.iter().step_by(2) {
+        buddy.deallocate_frames(FrameRange::new(*frame, *frame));
+    }
+    assert_eq!(buddy.free_frames(), 32);
+    assert_eq!(buddy.largest_free_order(), Some(0));
+    assert!(buddy.allocate_block(1).is_none());
+
+    // Freeing the rest coalesces everything back into a single block.
+    for frame in singles.iter().skip(1).step_by(2) {
+        buddy.deallocate_frames(FrameRange::new(*frame, *frame));
+    }
+    assert_eq!(buddy.free_frames(), 64);
+    assert_eq!(buddy.free_blocks(6), 1);
+    assert_eq!(buddy.largest_free_order(), Some(6));
+}
+
+#[test]
+fn buddy_max_order() {
+    let max_frames = 1 << BuddyAllocator::MAX_ORDER;
+    let mut buddy = BuddyAllocator::from_regions([frames(0, 2 * max_frames)], []);
+    // Blocks never merge beyond the maximum order.
+    assert_eq!(buddy.free_blocks(BuddyAllocator::MAX_ORDER), 2);
+    assert!(buddy.allocate_block(BuddyAllocator::MAX_ORDER + 1).is_none());
+    assert_eq!(buddy.allocate_block(BuddyAllocator::MAX_ORDER), Some(frames(0, max_frames)));
+}
+
+fn frame_num(n: usize) -> Frame {
+    frame_addr(n * FRAME_4K_SIZE_IN_BYTES)
+}
+
+fn released_runs(range: FrameRange) -> std::vec::Vec<FrameRange> {
+    let mut runs = std::vec::Vec::new();
+    refcount::release(range, |run| runs.push(run));
+    runs
+}
+
+#[test]
+fn refcount_unshared_frames_are_released_at_once() {
+    assert_eq!(frame_ref_count(frame_num(0x1000)), 1);
+    assert_eq!(released_runs(frames(0x1000, 8)), [frames(0x1000, 8)]);
+    assert!(frame_ref_dec(frame_num(0x1000)).is_err());
+}
+
+#[test]
+fn refcount_shared_frames_are_released_by_last_owner() {
+    assert_eq!(frame_ref_inc(frame_num(0x2003)), 2);
+    assert_eq!(frame_ref_inc(frame_num(0x2007)), 2);
+    assert_eq!(frame_ref_inc(frame_num(0x2007)), 3);
+
+    // The shared frames are skipped, splitting the released range.
+    assert_eq!(released_runs(frames(0x2000, 8)), [frames(0x2000, 3), frames(0x2004, 3)]);
+    assert_eq!(frame_ref_count(frame_num(0x2003)), 1);
+    assert_eq!(frame_ref_count(frame_num(0x2007)), 2);
+
+    assert_eq!(released_runs(frames(0x2000, 8)), [frames(0x2000, 7)]);
+    assert_eq!(frame_ref_count(frame_num(0x2007)), 1);
+    assert_eq!(released_runs(frames(0x2000, 8)), [frames(0x2000, 8)]);
+}
+
+#[test]
+fn refcount_dec_keeps_frame_allocated() {
+    frame_ref_inc(frame_num(0x3000));
+    frame_ref_inc(frame_num(0x3000));
+    assert_eq!(frame_ref_dec(frame_num(0x3000)), Ok(2));
+    assert_eq!(frame_ref_dec(frame_num(0x3000)), Ok(1));
+    assert!(frame_ref_dec(frame_num(0x3000)).is_err());
+    assert_eq!(released_runs(frames(0x3000, 1)), [frames(0x3000, 1)]);
+}
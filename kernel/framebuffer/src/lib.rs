@@ -6,7 +6,7 @@
 pub mod pixel;
 use core::{ops::{DerefMut, Deref}, hash::{Hash, Hasher}};
 use log::{info, debug};
-use memory::{PteFlags, PteFlagsArch, PhysicalAddress, Mutable, BorrowedSliceMappedPages};
+use memory::{MemoryType, PteFlags, PteFlagsArch, PhysicalAddress, Mutable, BorrowedSliceMappedPages};
 use shapes::Coord;
 pub use pixel::*;
 
@@ -82,28 +82,11 @@ impl<P: Pixel> Framebuffer<P> {
             .ok_or("could not allocate pages for a new framebuffer")?;
 
         let mapped_framebuffer = if let Some(address) = physical_address {
-            // For best performance, we map the real physical framebuffer memory
-            // as write-combining using the PAT (on x86 only).
-            // If PAT isn't available, fall back to disabling caching altogether.
-            let mut flags: PteFlagsArch = PteFlags::new()
-                .valid(true)
-                .writable(true)
-                .into();
-
-            #[cfg(target_arch = "x86_64")] {
-                if page_attribute_table::is_supported() {
-                    flags = flags.pat_index(
-                        page_attribute_table::MemoryCachingType::WriteCombining.pat_slot_index()
-                    );
-                    info!("Using PAT write-combining mapping for real physical framebuffer memory");
-                } else {
-                    flags = flags.device_memory(true);
-                    info!("Falling back to cache-disable mapping for real physical framebuffer memory");
-                }
-            }
-            #[cfg(not(target_arch = "x86_64"))] {
-                flags = flags.device_memory(true);
-            }
+            // For best performance, we map the real physical framebuffer memory as write-combining.
+            // On x86, this falls back to disabling caching altogether if PAT isn't available.
+            let flags = PteFlagsArch::from(PteFlags::new().valid(true).writable(true))
+                .memory_type(MemoryType::WriteCombining);
+            info!("Using write-combining mapping for real physical framebuffer memory");
 
             let frames = memory::allocate_frames_by_bytes_at(address, size)
                 .map_err(|_e| "Couldn't allocate frames for the final framebuffer")?;
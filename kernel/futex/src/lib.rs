@@ -0,0 +1,195 @@
+//! Futex-style wait and wake operations, which let tasks block on the value of a shared 32-bit word.
+//!
+//! A task calls [`futex_wait()`] to block until another task calls [`futex_wake()`] on the same word,
+//! but only if the word still holds the value that the waiting task expected.
+//! This lets lock and condition variable implementations spin in userspace-style fast paths
+//! using only atomic operations, and block in the kernel only when they're contended.
+//!
+//! Waiters are kept in a fixed table of hash buckets, keyed by the physical address of the word.
+//! As Theseus runs every task in a single address space, the physical address uniquely identifies the word:
+//! two mappings of the same shared frame wake each other's waiters,
+//! whereas two distinct words are never confused, even if they were once mapped at the same virtual address.
+//!
+//! Spurious wakeups are possible, e.g., if a timeout expires at the same time as a wakeup,
+//! or if a task is unblocked by something other than this crate,
+//! so callers must always re-check their condition after [`futex_wait()`] returns.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, sync::Arc, task::Wake, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use memory::{PhysicalAddress, VirtualAddress};
+use sync_preemption::PreemptionSafeMutex;
+use task::{CleanupGuard, CleanupReason, TaskRef, WakeReason, WeakTaskRef};
+use time::Duration;
+
+/// The number of buckets in the wait table, which must be a power of two.
+const NUM_BUCKETS: usize = 64;
+
+/// The table of waiters, indexed by a hash of the key of the word they're waiting on.
+#[allow(clippy::declare_interior_mutable_const)]
+static BUCKETS: [PreemptionSafeMutex<Vec<Waiter>>; NUM_BUCKETS] = {
+    const EMPTY: PreemptionSafeMutex<Vec<Waiter>> = PreemptionSafeMutex::new(Vec::new());
+    [EMPTY; NUM_BUCKETS]
+};
+
+/// A task that is blocked in [`futex_wait()`].
+struct Waiter {
+    key: PhysicalAddress,
+    task: TaskRef,
+}
+
+/// The error returned by [`futex_wait()`] and [`futex_wake()`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FutexError {
+    /// The word didn't hold the expected value, so the task didn't block.
+    WouldBlock,
+    /// The timeout expired before the task was woken up.
+    TimedOut,
+    /// The word isn't mapped in the current page table.
+    NotMapped,
+    /// The current task couldn't be obtained or blocked.
+    InvalidTask,
+}
+
+/// Blocks the current task until another task wakes it with [`futex_wake()`] on the same word,
+/// if `word` still holds the `expected` value, or until the `timeout` expires.
+///
+/// The value is checked while holding the lock of the word's bucket in the wait table,
+/// and the current task is blocked under that same lock, so a wakeup that follows a change
+/// of the value can't be lost in between checking the value and blocking.
+///
+/// Returns `Ok(())` once woken up, which may be spurious;
+/// see the [crate-level documentation](crate).
+pub fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>) -> Result<(), FutexError> {
+    let current = task::get_my_current_task().ok_or(FutexError::InvalidTask)?;
+    // Discard any stale reason from an earlier wakeup that was never taken.
+    current.take_wake_reason();
+    let alarm = timeout.map(|timeout| {
+        let alarm = Arc::new(TimeoutAlarm { task: current.downgrade(), armed: AtomicBool::new(true) });
+        sleep::future::sleep(timeout, Arc::clone(&alarm).into());
+        alarm
+    });
+
+    let result = wait(&current, word, expected);
+    if let Some(alarm) = alarm {
+        alarm.armed.store(false, Ordering::Release);
+    }
+    result
+}
+
+fn wait(current: &TaskRef, word: &AtomicU32, expected: u32) -> Result<(), FutexError> {
+    let addr = word as *const AtomicU32 as usize;
+    let (key, _cancel_guard) = loop {
+        let key = key_of(addr)?;
+        let mut bucket = bucket_of(key).lock();
+        // The word may have been remapped to a different frame before the bucket was locked.
+        if key_of(addr)? != key {
+            continue;
+        }
+        if word.load(Ordering::SeqCst) != expected {
+            return Err(FutexError::WouldBlock);
+        }
+        current.block().map_err(|_| FutexError::InvalidTask)?;
+        // If the timeout expired before this task blocked, that didn't unblock it.
+        if let Some(reason) = current.take_wake_reason() {
+            let _ = current.unblock();
+            return match reason {
+                WakeReason::TimedOut => Err(FutexError::TimedOut),
+                _ => Ok(()),
+            };
+        }
+        bucket.push(Waiter { key, task: current.clone() });
+        // If this task is killed while waiting, it must be removed from the wait table.
+        let task_id = current.id;
+        let cancel_guard = CleanupGuard::new(Box::new(move |_: CleanupReason| {
+            remove_waiter(key, task_id);
+        }));
+        break (key, cancel_guard);
+    };
+
+    task::schedule();
+
+    let reason = current.take_wake_reason();
+    if !remove_waiter(key, current.id) {
+        // A waker already removed this task from the wait table.
+        return Ok(());
+    }
+    match reason {
+        Some(WakeReason::TimedOut) => Err(FutexError::TimedOut),
+        _ => Ok(()),
+    }
+}
+
+/// Wakes up to `max_waiters` tasks that are blocked in [`futex_wait()`] on the given `word`,
+/// in the order that they began waiting.
+///
+/// Returns the number of tasks that were woken up.
+pub fn futex_wake(word: &AtomicU32, max_waiters: usize) -> Result<usize, FutexError> {
+    let key = key_of(word as *const AtomicU32 as usize)?;
+    let mut bucket = bucket_of(key).lock();
+    let mut woken = 0;
+    let mut i = 0;
+    while woken < max_waiters && i < bucket.len() {
+        if bucket[i].key != key {
+            i += 1;
+            continue;
+        }
+        let waiter = bucket.remove(i);
+        // A waiter that was killed in the meantime can't be woken up.
+        if waiter.task.unblock_with_reason(WakeReason::Condition).is_ok() {
+            woken += 1;
+        }
+    }
+    Ok(woken)
+}
+
+/// Returns the number of tasks that are blocked in [`futex_wait()`] on the given `word`.
+pub fn waiters(word: &AtomicU32) -> Result<usize, FutexError> {
+    let key = key_of(word as *const AtomicU32 as usize)?;
+    Ok(bucket_of(key).lock().iter().filter(|w| w.key == key).count())
+}
+
+/// Returns the key of the word at the given virtual address, i.e., its physical address.
+fn key_of(addr: usize) -> Result<PhysicalAddress, FutexError> {
+    VirtualAddress::new(addr)
+        .and_then(memory::translate)
+        .ok_or(FutexError::NotMapped)
+}
+
+fn bucket_of(key: PhysicalAddress) -> &'static PreemptionSafeMutex<Vec<Waiter>> {
+    // Words are 4-byte aligned, so the lowest bits carry no information.
+    let hash = (key.value() >> 2).wrapping_mul(0x9E37_79B9_7F4A_7C15_u64 as usize);
+    &BUCKETS[hash >> (usize::BITS - NUM_BUCKETS.trailing_zeros())]
+}
+
+/// Removes the given task from the wait table, returning whether it was still there.
+fn remove_waiter(key: PhysicalAddress, task_id: usize) -> bool {
+    let mut bucket = bucket_of(key).lock();
+    let len = bucket.len();
+    bucket.retain(|w| !(w.key == key && w.task.id == task_id));
+    bucket.len() != len
+}
+
+/// Wakes a task blocked in [`futex_wait()`] when its timeout expires,
+/// unless it has already returned by then.
+struct TimeoutAlarm {
+    task: WeakTaskRef,
+    armed: AtomicBool,
+}
+
+impl Wake for TimeoutAlarm {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.armed.swap(false, Ordering::AcqRel) {
+            if let Some(task) = self.task.upgrade() {
+                let _ = task.unblock_with_reason(WakeReason::TimedOut);
+            }
+        }
+    }
+}
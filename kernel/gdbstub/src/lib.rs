@@ -0,0 +1,772 @@
+//! A minimal stub for the gdb remote serial protocol (RSP), used for
+//! source-level debugging of the kernel over a serial port.
+//!
+//! If Theseus is built with the `gdbstub` cfg option, e.g., `THESEUS_CONFIG="gdbstub"`,
+//! the `captain` invokes [`init()`] once all CPUs have been brought up.
+//! This takes ownership of the `COM2` serial port, installs this crate's own
+//! breakpoint (`#BP`) and debug (`#DB`) exception handlers,
+//! and then traps into the stub, which waits for gdb to connect. For example:
+//! ```text
+//! make run SERIAL2=pty THESEUS_CONFIG="gdbstub"
+//! rust-gdb <nano_core binary> -ex "target remote /dev/pts/<N>"
+//! ```
+//!
+//! The following packets are supported:
+//! * `?`: report why execution stopped (always `SIGTRAP`).
+//! * `g`/`G` and `p`/`P`: read and write the registers of the stopped context.
+//! * `m`/`M`: read and write memory. Addresses that aren't mapped in the current page table
+//!   are refused with an error reply instead of page faulting within the stub.
+//! * `Z0`/`z0`: insert and remove software breakpoints by patching in an `int3` instruction.
+//! * `s`: single-step one instruction using the trap flag (`RFLAGS.TF`).
+//! * `c`: continue execution.
+//! * `D`/`k`: detach from the stub, which resumes execution.
+//!
+//! While the stub is active on one CPU, all other CPUs are frozen by an NMI
+//! sent for [`nmi::NmiReason::Freeze`].
+//!
+//! The stub deliberately doesn't log anything or allocate once it has been entered,
+//! since a breakpoint may have been hit while this CPU held the relevant locks.
+
+#![no_std]
+#![feature(naked_functions)]
+
+use core::{
+    arch::asm,
+    fmt::{self, Write},
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use locked_idt::LockedIdt;
+use log::info;
+use memory::VirtualAddress;
+use nmi::NmiReason;
+use serial_port_basic::{take_serial_port, SerialPort, SerialPortAddress};
+use spin::Mutex;
+use x86_64::{
+    registers::control::{Cr0, Cr0Flags},
+    structures::idt::InterruptStackFrame,
+    VirtAddr,
+};
+
+/// The maximum size of a packet's data, which is advertised to gdb.
+const MAX_PACKET_SIZE: usize = 4096;
+/// The maximum number of software breakpoints that can be inserted at once.
+const MAX_BREAKPOINTS: usize = 32;
+/// The `int3` instruction, which is patched into code to insert a breakpoint.
+const INT3: u8 = 0xCC;
+/// The trap flag in `RFLAGS`, which causes a `#DB` after each instruction.
+const RFLAGS_TF: u64 = 1 << 8;
+/// The signal reported to gdb whenever execution stops.
+const SIGTRAP: u8 = 5;
+/// The number of registers in gdb's `i386:x86-64` register layout that we support.
+const NUM_REGISTERS: usize = 24;
+/// How many times to spin while waiting for other CPUs to freeze,
+/// in case one of them is unable to handle an NMI right now.
+const FREEZE_TIMEOUT_SPINS: usize = 100_000_000;
+
+/// The stub's state, which also serializes entry into the stub across CPUs.
+static STUB: Mutex<Option<GdbStub>> = Mutex::new(None);
+
+/// Whether other CPUs should remain frozen in [`handle_freeze_nmi()`].
+static FREEZE_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// The number of CPUs that are currently frozen.
+static FROZEN_CPUS: AtomicU32 = AtomicU32::new(0);
+
+
+/// Initializes the gdb stub on the `COM2` serial port and then
+/// breaks into it, waiting for gdb to connect before returning.
+///
+/// This must be invoked after `exceptions_full::init()`,
+/// as it replaces the `#BP` and `#DB` handlers in the given `idt`.
+pub fn init(idt: &'static LockedIdt) -> Result<(), &'static str> {
+    let port = take_serial_port(SerialPortAddress::COM2)
+        .ok_or("gdbstub: couldn't take the COM2 serial port")?;
+    nmi::register_handler(NmiReason::Freeze, handle_freeze_nmi)?;
+    *STUB.lock() = Some(GdbStub {
+        port,
+        breakpoints: [None; MAX_BREAKPOINTS],
+        resumed: false,
+        packet: [0; MAX_PACKET_SIZE],
+        reply: Reply::new(),
+    });
+
+    {
+        let mut idt = idt.lock();
+        // SAFETY: the entry points below are valid exception handlers
+        //         that save and restore all general-purpose registers.
+        unsafe {
+            idt.breakpoint.set_handler_addr(VirtAddr::new(breakpoint_entry as usize as u64));
+            idt.debug.set_handler_addr(VirtAddr::new(debug_entry as usize as u64));
+        }
+    }
+
+    info!("gdbstub: waiting for gdb to connect on COM2...");
+    x86_64::instructions::interrupts::int3();
+    info!("gdbstub: resumed execution");
+    Ok(())
+}
+
+
+/// Handles an NMI sent by the stub to freeze this CPU.
+///
+/// It only touches atomics, so it is safe to run regardless of which locks are held.
+fn handle_freeze_nmi(_stack_frame: &InterruptStackFrame, _payload: u64) {
+    FROZEN_CPUS.fetch_add(1, Ordering::AcqRel);
+    while FREEZE_REQUESTED.load(Ordering::Acquire) {
+        spin_loop();
+    }
+    FROZEN_CPUS.fetch_sub(1, Ordering::AcqRel);
+}
+
+/// Freezes all other CPUs by sending them an NMI.
+///
+/// Returns `true` if other CPUs were asked to freeze.
+fn freeze_other_cpus() -> bool {
+    if cpu::cpu_count() <= 1 {
+        return false;
+    }
+
+    FREEZE_REQUESTED.store(true, Ordering::Release);
+    let others = match nmi::send_nmi_to_others(NmiReason::Freeze, 0) {
+        Ok(others) if others > 0 => others,
+        _ => {
+            FREEZE_REQUESTED.store(false, Ordering::Release);
+            return false;
+        }
+    };
+
+    // A CPU that is already handling another NMI won't freeze until that one completes,
+    // so don't wait forever. Such a CPU may keep running while we're in the stub.
+    let mut spins = 0;
+    while FROZEN_CPUS.load(Ordering::Acquire) < others && spins < FREEZE_TIMEOUT_SPINS {
+        spin_loop();
+        spins += 1;
+    }
+    true
+}
+
+/// Releases all CPUs frozen by [`freeze_other_cpus()`].
+fn thaw_other_cpus() {
+    FREEZE_REQUESTED.store(false, Ordering::Release);
+    while FROZEN_CPUS.load(Ordering::Acquire) > 0 {
+        spin_loop();
+    }
+}
+
+
+/// The register state of the interrupted context, as saved by the entry points below.
+#[repr(C)]
+#[derive(Debug)]
+struct SavedRegisters {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+    // The remainder is the interrupt stack frame pushed by the CPU.
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+impl SavedRegisters {
+    /// Returns the size in bytes of register number `n` in gdb's register layout.
+    fn register_size(n: usize) -> usize {
+        if n <= 16 { 8 } else { 4 }
+    }
+
+    /// Returns the value of register number `n` in gdb's register layout.
+    fn register(&self, n: usize) -> Option<u64> {
+        Some(match n {
+            0 => self.rax,
+            1 => self.rbx,
+            2 => self.rcx,
+            3 => self.rdx,
+            4 => self.rsi,
+            5 => self.rdi,
+            6 => self.rbp,
+            7 => self.rsp,
+            8 => self.r8,
+            9 => self.r9,
+            10 => self.r10,
+            11 => self.r11,
+            12 => self.r12,
+            13 => self.r13,
+            14 => self.r14,
+            15 => self.r15,
+            16 => self.rip,
+            17 => self.rflags,
+            18 => self.cs,
+            19 => self.ss,
+            // ds, es, fs, and gs are unused in 64-bit mode.
+            20..=23 => 0,
+            _ => return None,
+        })
+    }
+
+    /// Sets register number `n` in gdb's register layout to the given `value`.
+    ///
+    /// Writes to segment registers are ignored.
+    fn set_register(&mut self, n: usize, value: u64) -> bool {
+        let reg = match n {
+            0 => &mut self.rax,
+            1 => &mut self.rbx,
+            2 => &mut self.rcx,
+            3 => &mut self.rdx,
+            4 => &mut self.rsi,
+            5 => &mut self.rdi,
+            6 => &mut self.rbp,
+            7 => &mut self.rsp,
+            8 => &mut self.r8,
+            9 => &mut self.r9,
+            10 => &mut self.r10,
+            11 => &mut self.r11,
+            12 => &mut self.r12,
+            13 => &mut self.r13,
+            14 => &mut self.r14,
+            15 => &mut self.r15,
+            16 => &mut self.rip,
+            17 => &mut self.rflags,
+            18..=23 => return true,
+            _ => return false,
+        };
+        *reg = value;
+        true
+    }
+}
+
+/// Defines a naked exception entry point that saves all general-purpose registers
+/// into a [`SavedRegisters`] on the stack and passes it to the given handler.
+///
+/// This is used instead of an `x86-interrupt` function because gdb needs to
+/// read and modify every register of the interrupted context.
+/// Both `#BP` and `#DB` are exceptions that don't push an error code.
+macro_rules! exception_entry {
+    ($name:ident, $handler:ident) => {
+        #[naked]
+        unsafe extern "C" fn $name() {
+            // The CPU aligns the stack to 16 bytes before pushing the 5-word interrupt frame,
+            // so after pushing 15 more registers, the stack is 16-byte aligned for the call.
+            asm!(
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "mov rdi, rsp",
+                "cld",
+                "call {handler}",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                "iretq",
+                handler = sym $handler,
+                options(noreturn),
+            );
+        }
+    };
+}
+
+exception_entry!(breakpoint_entry, breakpoint_handler);
+exception_entry!(debug_entry, debug_handler);
+
+extern "C" fn breakpoint_handler(regs: &mut SavedRegisters) {
+    enter_stub(regs, true);
+}
+
+extern "C" fn debug_handler(regs: &mut SavedRegisters) {
+    enter_stub(regs, false);
+}
+
+/// Stops the current CPU and all others, and then lets gdb inspect and modify
+/// the stopped context until it resumes execution.
+fn enter_stub(regs: &mut SavedRegisters, from_breakpoint: bool) {
+    let mut locked = STUB.lock();
+    let Some(stub) = locked.as_mut() else { return };
+
+    if from_breakpoint {
+        // `int3` is a trap, so `rip` points just past it. If it was one of our breakpoints,
+        // rewind so that execution resumes at the original instruction once it's restored.
+        // A breakpoint that was removed while this CPU waited for the stub is also rewound,
+        // whereas an `int3` that is actually part of the code is left alone.
+        let addr = regs.rip.wrapping_sub(1);
+        let mut byte = [0];
+        let is_ours = stub.breakpoints.iter().flatten().any(|bp| bp.addr == addr);
+        let was_removed = read_memory(addr, &mut byte).is_ok() && byte[0] != INT3;
+        if is_ours || was_removed {
+            regs.rip = addr;
+        }
+    }
+    regs.rflags &= !RFLAGS_TF;
+
+    let frozen = freeze_other_cpus();
+    stub.run(regs);
+    if frozen {
+        thaw_other_cpus();
+    }
+}
+
+
+/// A software breakpoint inserted by gdb.
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    addr: u64,
+    original: u8,
+}
+
+/// What to do after handling a packet.
+enum Action {
+    /// Remain in the stub and wait for the next packet.
+    Stay,
+    /// Leave the stub and resume execution.
+    Resume,
+}
+
+struct GdbStub {
+    port: SerialPort,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    /// Whether gdb resumed execution and is thus waiting for a stop reply.
+    resumed: bool,
+    packet: [u8; MAX_PACKET_SIZE],
+    reply: Reply,
+}
+
+impl GdbStub {
+    /// Handles packets from gdb until it resumes execution.
+    fn run(&mut self, regs: &mut SavedRegisters) {
+        let GdbStub { port, breakpoints, resumed, packet, reply } = self;
+
+        if *resumed {
+            *resumed = false;
+            reply.clear();
+            let _ = write!(reply, "S{:02x}", SIGTRAP);
+            send_packet(port, reply.as_bytes());
+        }
+
+        loop {
+            let len = read_packet(port, packet);
+            reply.clear();
+            let action = handle_packet(&mut packet[..len], regs, breakpoints, reply);
+            match action {
+                Action::Stay => send_packet(port, reply.as_bytes()),
+                Action::Resume => {
+                    *resumed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Handles one packet from gdb, writing the response into `reply`.
+///
+/// An empty reply tells gdb that the packet isn't supported.
+fn handle_packet(
+    packet: &mut [u8],
+    regs: &mut SavedRegisters,
+    breakpoints: &mut [Option<Breakpoint>],
+    reply: &mut Reply,
+) -> Action {
+    let Some((&command, args)) = packet.split_first_mut() else {
+        return Action::Stay;
+    };
+
+    match command {
+        b'?' => {
+            let _ = write!(reply, "S{:02x}", SIGTRAP);
+        }
+        b'g' => {
+            for n in 0..NUM_REGISTERS {
+                let value = regs.register(n).unwrap_or(0);
+                reply.push_le(value, SavedRegisters::register_size(n));
+            }
+        }
+        b'G' => {
+            let mut rest = &args[..];
+            for n in 0..NUM_REGISTERS {
+                let size = SavedRegisters::register_size(n);
+                let Some(value) = rest.get(..size * 2).and_then(parse_le) else { break };
+                regs.set_register(n, value);
+                rest = &rest[size * 2..];
+            }
+            reply.push_str("OK");
+        }
+        b'p' => {
+            let n = parse_hex(args).and_then(|n| regs.register(n as usize).map(|v| (n, v)));
+            match n {
+                Some((n, value)) => reply.push_le(value, SavedRegisters::register_size(n as usize)),
+                None => reply.push_str("E01"),
+            }
+        }
+        b'P' => {
+            let parsed = split_once(args, b'=').and_then(|(n, value)| {
+                let n = parse_hex(n)? as usize;
+                let value = value.get(..SavedRegisters::register_size(n) * 2).and_then(parse_le)?;
+                Some((n, value))
+            });
+            match parsed {
+                Some((n, value)) if regs.set_register(n, value) => reply.push_str("OK"),
+                _ => reply.push_str("E01"),
+            }
+        }
+        b'm' => {
+            let parsed = split_once(args, b',')
+                .and_then(|(addr, len)| Some((parse_hex(addr)?, parse_hex(len)? as usize)));
+            let Some((addr, len)) = parsed else {
+                reply.push_str("E01");
+                return Action::Stay;
+            };
+            let mut buf = [0u8; 256];
+            let mut offset = 0;
+            // Each byte takes two hex digits in the reply.
+            let len = len.min(MAX_PACKET_SIZE / 2);
+            while offset < len {
+                let chunk = &mut buf[..(len - offset).min(256)];
+                if read_memory(addr + offset as u64, chunk).is_err() {
+                    reply.clear();
+                    reply.push_str("E14");
+                    break;
+                }
+                for byte in chunk.iter() {
+                    reply.push_le(*byte as u64, 1);
+                }
+                offset += chunk.len();
+            }
+        }
+        b'M' => {
+            let parsed = split_once_mut(args, b':').and_then(|(range, data)| {
+                let (addr, len) = split_once(range, b',')?;
+                Some((parse_hex(addr)?, parse_hex(len)? as usize, data))
+            });
+            let Some((addr, len, data)) = parsed else {
+                reply.push_str("E01");
+                return Action::Stay;
+            };
+            match decode_hex_in_place(data) {
+                Some(bytes) if bytes.len() == len => match write_memory(addr, bytes) {
+                    Ok(()) => reply.push_str("OK"),
+                    Err(()) => reply.push_str("E14"),
+                },
+                _ => reply.push_str("E01"),
+            }
+        }
+        b'Z' | b'z' => {
+            // Only software breakpoints (type 0) are supported.
+            let Some(rest) = args.strip_prefix(b"0,") else {
+                return Action::Stay;
+            };
+            let Some(addr) = split_once(rest, b',').and_then(|(addr, _kind)| parse_hex(addr)) else {
+                reply.push_str("E01");
+                return Action::Stay;
+            };
+            let result = if command == b'Z' {
+                insert_breakpoint(breakpoints, addr)
+            } else {
+                remove_breakpoint(breakpoints, addr)
+            };
+            match result {
+                Ok(()) => reply.push_str("OK"),
+                Err(()) => reply.push_str("E14"),
+            }
+        }
+        b's' | b'c' => {
+            if let Some(addr) = parse_hex(args) {
+                regs.rip = addr;
+            }
+            if command == b's' {
+                regs.rflags |= RFLAGS_TF;
+            }
+            return Action::Resume;
+        }
+        b'D' => {
+            reply.push_str("OK");
+            return Action::Resume;
+        }
+        // There is nothing to kill, so just resume.
+        b'k' => return Action::Resume,
+        // There is only one "thread": the context that was stopped.
+        b'H' => reply.push_str("OK"),
+        b'q' => {
+            if args.starts_with(b"Supported") {
+                let _ = write!(reply, "PacketSize={:x}", MAX_PACKET_SIZE);
+            } else if args.starts_with(b"Attached") {
+                reply.push_str("1");
+            }
+        }
+        _ => { }
+    }
+    Action::Stay
+}
+
+fn insert_breakpoint(breakpoints: &mut [Option<Breakpoint>], addr: u64) -> Result<(), ()> {
+    if breakpoints.iter().flatten().any(|bp| bp.addr == addr) {
+        return Ok(());
+    }
+    let slot = breakpoints.iter_mut().find(|bp| bp.is_none()).ok_or(())?;
+    let mut original = [0];
+    read_memory(addr, &mut original)?;
+    write_memory(addr, &[INT3])?;
+    *slot = Some(Breakpoint { addr, original: original[0] });
+    Ok(())
+}
+
+fn remove_breakpoint(breakpoints: &mut [Option<Breakpoint>], addr: u64) -> Result<(), ()> {
+    let Some(slot) = breakpoints.iter_mut().find(|bp| bp.map_or(false, |bp| bp.addr == addr)) else {
+        return Ok(());
+    };
+    let bp = slot.take().unwrap();
+    write_memory(bp.addr, &[bp.original])
+}
+
+
+/// Checks that every page overlapping the given range is mapped.
+///
+/// Returns whether every such page is also writable, or `Err` if any page is unmapped.
+fn check_mapped(addr: u64, len: usize) -> Result<bool, ()> {
+    if len == 0 {
+        return Ok(true);
+    }
+    let end = addr.checked_add(len as u64 - 1).ok_or(())?;
+    let mut writable = true;
+    let mut page = addr & !(memory::PAGE_SIZE as u64 - 1);
+    loop {
+        let vaddr = VirtualAddress::new(page as usize).ok_or(())?;
+        let flags = memory::page_flags(vaddr).ok_or(())?;
+        if !flags.is_valid() {
+            return Err(());
+        }
+        writable &= flags.is_writable();
+        match page.checked_add(memory::PAGE_SIZE as u64) {
+            Some(next) if next <= end => page = next,
+            _ => return Ok(writable),
+        }
+    }
+}
+
+/// Reads memory at `addr` into `buf`, refusing to access any unmapped address.
+fn read_memory(addr: u64, buf: &mut [u8]) -> Result<(), ()> {
+    check_mapped(addr, buf.len())?;
+    for (i, byte) in buf.iter_mut().enumerate() {
+        // SAFETY: we checked above that this address is mapped.
+        *byte = unsafe { core::ptr::read_volatile((addr as usize + i) as *const u8) };
+    }
+    Ok(())
+}
+
+/// Writes `data` to memory at `addr`, refusing to access any unmapped address.
+///
+/// Read-only pages, e.g., kernel text, are written by temporarily clearing `CR0.WP`,
+/// which is necessary for patching in breakpoints.
+fn write_memory(addr: u64, data: &[u8]) -> Result<(), ()> {
+    let writable = check_mapped(addr, data.len())?;
+    let cr0 = Cr0::read();
+    if !writable {
+        // SAFETY: interrupts are disabled and other CPUs are frozen while in the stub.
+        unsafe { Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT) };
+    }
+    for (i, byte) in data.iter().enumerate() {
+        // SAFETY: we checked above that this address is mapped.
+        unsafe { core::ptr::write_volatile((addr as usize + i) as *mut u8, *byte) };
+    }
+    if !writable {
+        // SAFETY: this restores the original value of CR0.
+        unsafe { Cr0::write(cr0) };
+    }
+    Ok(())
+}
+
+
+/// Reads the next packet with a valid checksum into `buf`, acknowledging it.
+///
+/// Returns the length of the packet's data.
+fn read_packet(port: &mut SerialPort, buf: &mut [u8]) -> usize {
+    loop {
+        // Skip everything before the start of a packet, e.g., acks and interrupt requests.
+        while port.in_byte() != b'$' {
+            core::hint::spin_loop();
+        }
+
+        let mut len = 0;
+        let mut checksum = 0u8;
+        let mut overflowed = false;
+        loop {
+            let byte = port.in_byte();
+            if byte == b'#' {
+                break;
+            }
+            checksum = checksum.wrapping_add(byte);
+            match buf.get_mut(len) {
+                Some(b) => {
+                    *b = byte;
+                    len += 1;
+                }
+                None => overflowed = true,
+            }
+        }
+
+        let received = hex_digit(port.in_byte())
+            .zip(hex_digit(port.in_byte()))
+            .map(|(hi, lo)| hi << 4 | lo);
+        if !overflowed && received == Some(checksum) {
+            port.out_byte(b'+');
+            return len;
+        }
+        port.out_byte(b'-');
+    }
+}
+
+/// Sends a packet with the given data, retransmitting it until gdb acknowledges it.
+fn send_packet(port: &mut SerialPort, data: &[u8]) {
+    let checksum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    loop {
+        port.out_byte(b'$');
+        port.out_bytes(data);
+        port.out_byte(b'#');
+        port.out_byte(HEX_DIGITS[(checksum >> 4) as usize]);
+        port.out_byte(HEX_DIGITS[(checksum & 0xF) as usize]);
+        loop {
+            match port.in_byte() {
+                b'+' => return,
+                b'-' => break,
+                _ => continue,
+            }
+        }
+    }
+}
+
+
+/// A fixed-size buffer for a reply packet's data.
+struct Reply {
+    buf: [u8; MAX_PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    const fn new() -> Self {
+        Reply { buf: [0; MAX_PACKET_SIZE], len: 0 }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        if let Some(b) = self.buf.get_mut(self.len) {
+            *b = byte;
+            self.len += 1;
+        }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        for byte in s.bytes() {
+            self.push_byte(byte);
+        }
+    }
+
+    /// Pushes the lowest `size` bytes of `value` as hex digits in little-endian order,
+    /// which is how gdb expects register and memory contents.
+    fn push_le(&mut self, value: u64, size: usize) {
+        for byte in value.to_le_bytes().iter().take(size) {
+            self.push_byte(HEX_DIGITS[(byte >> 4) as usize]);
+            self.push_byte(HEX_DIGITS[(byte & 0xF) as usize]);
+        }
+    }
+}
+
+impl fmt::Write for Reply {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parses a big-endian hex number, as used for addresses, lengths, and register numbers.
+fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter().try_fold(0u64, |acc, c| Some(acc << 4 | hex_digit(*c)? as u64))
+}
+
+/// Parses a little-endian sequence of hex-encoded bytes, as used for register contents.
+fn parse_le(s: &[u8]) -> Option<u64> {
+    if s.len() % 2 != 0 || s.len() > 16 {
+        return None;
+    }
+    s.chunks(2).rev().try_fold(0u64, |acc, pair| {
+        Some(acc << 8 | (hex_digit(pair[0])? << 4 | hex_digit(pair[1])?) as u64)
+    })
+}
+
+/// Decodes hex-encoded bytes in place, returning the decoded prefix of `s`.
+fn decode_hex_in_place(s: &mut [u8]) -> Option<&[u8]> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    let len = s.len() / 2;
+    for i in 0..len {
+        s[i] = hex_digit(s[2 * i])? << 4 | hex_digit(s[2 * i + 1])?;
+    }
+    Some(&s[..len])
+}
+
+fn split_once(s: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let i = s.iter().position(|b| *b == separator)?;
+    Some((&s[..i], &s[i + 1..]))
+}
+
+fn split_once_mut(s: &mut [u8], separator: u8) -> Option<(&[u8], &mut [u8])> {
+    let i = s.iter().position(|b| *b == separator)?;
+    let (before, after) = s.split_at_mut(i);
+    Some((before, &mut after[1..]))
+}
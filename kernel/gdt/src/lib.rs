@@ -1,11 +1,14 @@
 #![no_std]
 
+extern crate alloc;
+
+use alloc::boxed::Box;
 use core::ops::Deref;
 use atomic_linked_list::atomic_map::AtomicMap;
 use x86_64::{
     instructions::{
         segmentation::{CS, DS, SS, Segment},
-        tables::load_tss,
+        tables::{load_tss, DescriptorTablePointer},
     },
     PrivilegeLevel,
     structures::{
@@ -15,12 +18,15 @@ use x86_64::{
     VirtAddr, 
 };
 use spin::Once;
-use memory::VirtualAddress;
+use stack::Stack;
 use cpu::CpuId;
 
 
 /// The GDT list, one per CPU core.
-static GDT: AtomicMap<CpuId, Gdt> = AtomicMap::new();
+///
+/// Like each TSS, each GDT is individually heap-allocated and never freed,
+/// because the CPU's GDTR register refers to its address directly.
+static GDT: AtomicMap<CpuId, &'static Gdt> = AtomicMap::new();
 
 
 static KERNEL_CODE_SELECTOR:  Once<SegmentSelector> = Once::new();
@@ -59,24 +65,31 @@ impl AvailableSegmentSelector {
 }
 
 
-/// This function first creates and sets up a new TSS with the given double fault stack and privilege stack.
+/// This function first creates and sets up a new TSS with the given double fault stack and privilege stack,
+/// which the TSS takes ownership of.
 ///
 /// It then creates a new GDT with an entry that references that TSS and loads that new GDT into memory. 
 ///
 /// Finally, it switches the various code and segment selectors to use that new GDT.
 ///
+/// Returns an error if the given stacks or the new TSS are not mapped as writable,
+/// in which case the GDT is not loaded; see [`tss::create_tss()`].
+///
 /// # Important Note
 /// The GDT entries (segment descriptors) are only created **once** upon first invocation of this function,
 /// such that the segment selectors are usable 
 /// Future invocations will not change those initial values and load the same GDT based on them.
 pub fn create_and_load_tss_gdt(
     cpu_id: CpuId,
-    double_fault_stack_top_unusable: VirtualAddress,
-    privilege_stack_top_unusable: VirtualAddress
-) { 
-    let tss_ref = tss::create_tss(cpu_id, double_fault_stack_top_unusable, privilege_stack_top_unusable);
+    double_fault_stack: Stack,
+    privilege_stack: Stack,
+) -> Result<(), &'static str> {
+    let tss_ref = tss::create_tss(cpu_id, double_fault_stack, privilege_stack)?;
     let (gdt, kernel_cs, kernel_ds, user_cs_32, user_ds_32, user_cs_64, user_ds_64, tss_segment) 
         = create_gdt(tss_ref.lock().deref());
+    // Catch a mis-ordered sequence of `add_entry()` calls here rather than at the first privilege change.
+    #[cfg(debug_assertions)]
+    gdt.verify_layout()?;
 
     KERNEL_CODE_SELECTOR .call_once(|| kernel_cs);
     KERNEL_DATA_SELECTOR .call_once(|| kernel_ds);
@@ -86,20 +99,38 @@ pub fn create_and_load_tss_gdt(
     USER_DATA_64_SELECTOR.call_once(|| user_ds_64);
     TSS_SELECTOR         .call_once(|| tss_segment);
 
-    GDT.insert(cpu_id, gdt);
-    let gdt_ref = GDT.get(&cpu_id).unwrap(); // safe to unwrap since we just added it to the list
-    gdt_ref.load();
+    let gdt_ref: &'static Gdt = Box::leak(Box::new(gdt));
+    GDT.insert(cpu_id, gdt_ref);
+    gdt_ref.load_with_segments(kernel_cs, kernel_ds, tss_segment);
     // log::debug!("Loaded GDT for CPU {}: {}", cpu_id, gdt_ref);
+    Ok(())
+}
+
 
-    unsafe {
-        CS::set_reg(kernel_cs);  // reload code segment register
-        load_tss(tss_segment);   // load TSS
-        SS::set_reg(kernel_ds);  // unsure if necessary, but doesn't hurt
-        DS::set_reg(kernel_ds);  // unsure if necessary, but doesn't hurt
+/// Returns the GDT of the given CPU, if one has been created.
+pub fn get_gdt(cpu_id: CpuId) -> Option<&'static Gdt> {
+    GDT.get(&cpu_id).copied()
+}
+
+
+/// Logs the GDT of the given CPU as a table of its decoded entries.
+pub fn dump_gdt(cpu_id: CpuId) {
+    match GDT.get(&cpu_id) {
+        Some(gdt) => log::info!("GDT for CPU {}: {}", cpu_id, gdt),
+        None => log::warn!("dump_gdt(): CPU {} has no GDT", cpu_id),
     }
 }
 
 
+// The index of each segment descriptor in the GDT created by `create_gdt()`.
+const KERNEL_CODE_INDEX:  usize = 1;
+const KERNEL_DATA_INDEX:  usize = 2;
+const USER_CODE_32_INDEX: usize = 3;
+const USER_DATA_32_INDEX: usize = 4;
+const USER_CODE_64_INDEX: usize = 5;
+const USER_DATA_64_INDEX: usize = 6;
+const TSS_INDEX:          usize = 7; // the TSS takes up two entries, 7 and 8
+
 /// Creates and sets up a new GDT that refers to the given `TSS`. 
 ///
 /// Returns a tuple including:
@@ -180,32 +211,156 @@ impl Gdt {
         }
     }
 
-    pub fn load(&self) {
-        use x86_64::instructions::tables::{DescriptorTablePointer, lgdt};
+    /// Checks that this GDT has the exact layout created by [`create_gdt()`],
+    /// i.e., that each segment descriptor is at its expected index.
+    ///
+    /// The order of descriptors matters because instructions like `syscall`/`sysret`
+    /// derive segment selectors from fixed offsets relative to one another.
+    pub fn verify_layout(&self) -> Result<(), &'static str> {
+        use bit_field::BitField;
+
+        let expected_user_segments = [
+            (KERNEL_CODE_INDEX,  Descriptor::kernel_code_segment(),  "GDT: kernel code segment is not at index 1"),
+            (KERNEL_DATA_INDEX,  Descriptor::kernel_data_segment(),  "GDT: kernel data segment is not at index 2"),
+            (USER_CODE_32_INDEX, Descriptor::user_code_32_segment(), "GDT: user 32-bit code segment is not at index 3"),
+            (USER_DATA_32_INDEX, Descriptor::user_data_32_segment(), "GDT: user 32-bit data segment is not at index 4"),
+            (USER_CODE_64_INDEX, Descriptor::user_code_64_segment(), "GDT: user 64-bit code segment is not at index 5"),
+            (USER_DATA_64_INDEX, Descriptor::user_data_64_segment(), "GDT: user 64-bit data segment is not at index 6"),
+        ];
+        for (index, descriptor, err) in expected_user_segments {
+            if let Descriptor::UserSegment(value) = descriptor {
+                if self.table[index] != value {
+                    log::error!("GDT entry {} is {:#018X}, expected {:#018X}. {}", index, self.table[index], value, self);
+                    return Err(err);
+                }
+            }
+        }
+
+        let tss_low = self.table[TSS_INDEX];
+        let is_tss = tss_low.get_bit(47)            // present
+            && !tss_low.get_bit(44)                 // system segment
+            && tss_low.get_bits(40..44) & 0b1101 == 0b1001; // available or busy 64-bit TSS
+        if !is_tss {
+            log::error!("GDT entry {} is not a TSS descriptor. {}", TSS_INDEX, self);
+            return Err("GDT: TSS segment is not at indices 7-8");
+        }
+
+        if self.next_free != TSS_INDEX + 2 {
+            return Err("GDT: unexpected number of entries");
+        }
+        Ok(())
+    }
+
+    /// Returns the base and limit that the GDTR register holds once this GDT is loaded.
+    pub fn pointer(&self) -> DescriptorTablePointer {
         use core::mem::size_of;
 
-        let ptr = DescriptorTablePointer {
+        DescriptorTablePointer {
             base: VirtAddr::new(self.table.as_ptr() as u64),
             limit: (self.table.len() * size_of::<u64>() - 1) as u16,
-        };
+        }
+    }
 
-        unsafe { lgdt(&ptr) };
+    /// Returns the base address of the TSS that this GDT's TSS descriptor refers to.
+    pub fn tss_base(&self) -> u64 {
+        system_segment_base(self.table[TSS_INDEX], self.table[TSS_INDEX + 1])
+    }
+
+    pub fn load(&self) {
+        unsafe { x86_64::instructions::tables::lgdt(&self.pointer()) };
+    }
+
+    /// Loads this GDT and then switches the code, stack, and data segment registers
+    /// and the task register to the given selectors, which must be entries in this GDT.
+    ///
+    /// This is the only place that segment registers are changed,
+    /// for both the early boot GDT and each CPU's final GDT.
+    pub fn load_with_segments(
+        &self,
+        kernel_cs: SegmentSelector,
+        kernel_ds: SegmentSelector,
+        tss_segment: SegmentSelector,
+    ) {
+        self.load();
+        unsafe {
+            CS::set_reg(kernel_cs);  // reload code segment register
+            load_tss(tss_segment);   // load TSS
+            SS::set_reg(kernel_ds);  // unsure if necessary, but doesn't hurt
+            DS::set_reg(kernel_ds);  // unsure if necessary, but doesn't hurt
+        }
     }
 }
 
 use core::fmt;
+/// Displays a table of this GDT's entries, decoding each entry's base, limit,
+/// descriptor privilege level (DPL), and type.
 impl fmt::Display for Gdt {
     fn fmt(&self, fmtr: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        fmtr.write_fmt(format_args!("\nGdt: [\n"))?;
-        for (index, entry) in self.table.iter().enumerate() {
-            #[allow(clippy::uninlined_format_args)]
-            fmtr.write_fmt(format_args!("  {}:  {:#016x}\n", index, entry))?;
+        use bit_field::BitField;
+
+        writeln!(fmtr, "\nGdt: [")?;
+        writeln!(fmtr, "  idx  raw                 base                limit    dpl  type")?;
+        let mut index = 0;
+        while index < self.table.len() {
+            let entry = self.table[index];
+            write!(fmtr, "  {:<3}  {:#018x}  ", index, entry)?;
+            if index == 0 || index >= self.next_free {
+                writeln!(fmtr, "{}", if index == 0 { "null" } else { "unused" })?;
+                index += 1;
+                continue;
+            }
+
+            let mut base = user_segment_base(entry);
+            let limit = entry.get_bits(0..16) | (entry.get_bits(48..52) << 16);
+            let dpl = entry.get_bits(45..47);
+            let present = entry.get_bit(47);
+            let is_user_segment = entry.get_bit(44);
+            let kind = if is_user_segment {
+                match (entry.get_bit(43), entry.get_bit(53), entry.get_bit(54)) {
+                    (true, true, _)      => "code (64-bit)",
+                    (true, false, true)  => "code (32-bit)",
+                    (true, false, false) => "code (16-bit)",
+                    (false, _, _)        => "data",
+                }
+            } else {
+                match entry.get_bits(40..44) {
+                    0b1001 => "tss (available)",
+                    0b1011 => "tss (busy)",
+                    _      => "system (unknown)",
+                }
+            };
+            // System segments take up two entries, the second of which holds the upper 32 bits of the base.
+            let is_system_segment = !is_user_segment && index + 1 < self.table.len();
+            if is_system_segment {
+                base = system_segment_base(entry, self.table[index + 1]);
+            }
+
+            writeln!(fmtr, "{:#018x}  {:#07x}  {}    {}{}",
+                base, limit, dpl, kind, if present { "" } else { " (not present)" },
+            )?;
+            if is_system_segment {
+                writeln!(fmtr, "  {:<3}  {:#018x}  (upper half of entry {})", index + 1, self.table[index + 1], index)?;
+                index += 1;
+            }
+            index += 1;
         }
-        fmtr.write_fmt(format_args!("]"))?;
-        Ok(())
+        write!(fmtr, "]")
     }
 }
 
+/// Returns the lower 32 bits of a segment's base address, as encoded in its (first) descriptor entry.
+fn user_segment_base(entry: u64) -> u64 {
+    use bit_field::BitField;
+    entry.get_bits(16..40) | (entry.get_bits(56..64) << 24)
+}
+
+/// Returns the base address of a system segment (e.g., a TSS),
+/// which is encoded across the two descriptor entries `low` and `high`.
+pub fn system_segment_base(low: u64, high: u64) -> u64 {
+    use bit_field::BitField;
+    user_segment_base(low) | (high.get_bits(0..32) << 32)
+}
+
 /// The two kinds of descriptor entries in the GDT.
 pub enum Descriptor {
     /// UserSegment is used for both code and data segments, 
@@ -109,6 +109,7 @@ impl RedistRegsP1 {
         };
         let mut counter = 0;
         while children_asleep() {
+            core::hint::spin_loop();
             counter += 1;
             if counter >= TIMEOUT_ITERATIONS {
                 break;
@@ -11,6 +11,8 @@ extern crate spin;
 extern crate memory;
 extern crate kernel_config;
 extern crate block_allocator;
+extern crate cpu;
+extern crate irq_safety;
 
 use alloc::alloc::{GlobalAlloc, Layout};
 use memory::PteFlags;
@@ -19,6 +21,8 @@ use sync_irq::IrqSafeMutex;
 use spin::Once;
 use alloc::boxed::Box;
 use block_allocator::FixedSizeBlockAllocator;
+use core::sync::atomic::{AtomicU8, Ordering};
+use irq_safety::{hold_interrupts, HeldInterrupts};
 
 
 #[global_allocator]
@@ -43,7 +47,74 @@ pub const HEAP_FLAGS: PteFlags = PteFlags::from_bits_truncate(
 );
 
 /// The ending address of the initial heap. It is used to determine which heap should be used during deallocation.
-const INITIAL_HEAP_END_ADDR: usize = KERNEL_HEAP_START + KERNEL_HEAP_INITIAL_SIZE;
+const INITIAL_HEAP_END_ADDR: usize = KERNEL_HEAP_START + KERNEL_HEAP_INITIAL_SIZE.value();
+
+
+/// The maximum number of CPUs whose allocation-forbidden state can be tracked.
+/// Allocations on CPUs with a higher ID are never checked.
+const MAX_TRACKED_CPUS: usize = 256;
+
+/// For each CPU, the number of [`AllocForbiddenGuard`]s currently held on that CPU.
+static ALLOC_FORBIDDEN: [AtomicU8; MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU8 = AtomicU8::new(0);
+    [ZERO; MAX_TRACKED_CPUS]
+};
+
+fn alloc_forbidden_count() -> Option<&'static AtomicU8> {
+    ALLOC_FORBIDDEN.get(cpu::current_cpu().value() as usize)
+}
+
+/// Forbids heap allocation on the current CPU until the returned guard is dropped.
+///
+/// This is meant for code paths that must work even when the heap is exhausted or corrupted,
+/// such as out-of-memory reports and panic output.
+/// In debug builds, any allocation while this guard is held will panic,
+/// which catches regressions that reintroduce allocation into such paths.
+/// In release builds, this only disables interrupts.
+///
+/// Interrupts are disabled while the guard is held to ensure
+/// the current task isn't preempted or migrated to a different CPU.
+pub fn forbid_allocation() -> AllocForbiddenGuard {
+    let held_interrupts = hold_interrupts();
+    if let Some(count) = alloc_forbidden_count() {
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+    AllocForbiddenGuard { _held_interrupts: held_interrupts }
+}
+
+/// Returns `true` if heap allocation is currently forbidden on this CPU.
+pub fn is_allocation_forbidden() -> bool {
+    alloc_forbidden_count().map_or(false, |count| count.load(Ordering::Relaxed) > 0)
+}
+
+/// A guard that forbids heap allocation on the current CPU while it is held.
+///
+/// See [`forbid_allocation()`].
+pub struct AllocForbiddenGuard {
+    _held_interrupts: HeldInterrupts,
+}
+
+impl Drop for AllocForbiddenGuard {
+    fn drop(&mut self) {
+        if let Some(count) = alloc_forbidden_count() {
+            let _ = count.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| c.checked_sub(1));
+        }
+    }
+}
+
+/// Panics if allocation is forbidden on this CPU, in debug builds only.
+///
+/// The flag is cleared before panicking, because the panic handler itself may allocate.
+#[inline]
+fn check_allocation_allowed(layout: &Layout) {
+    if cfg!(debug_assertions) && is_allocation_forbidden() {
+        if let Some(count) = alloc_forbidden_count() {
+            count.store(0, Ordering::Relaxed);
+        }
+        panic!("BUG: heap allocation of {:?} in a path where allocation is forbidden", layout);
+    }
+}
 
 
 /// Initializes the single heap, which is the first heap used by the system.
@@ -78,6 +149,7 @@ impl Heap {
 unsafe impl GlobalAlloc for Heap {
 
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        check_allocation_allowed(&layout);
         match DEFAULT_ALLOCATOR.get() {
             Some(allocator) => {
                 allocator.alloc(layout)
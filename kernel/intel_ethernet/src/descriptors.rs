@@ -138,6 +138,7 @@ impl TxDescriptor for LegacyTxDescriptor {
     fn wait_for_packet_tx(&self) {
         while (self.status.read() & TX_STATUS_DD) == 0 {
             // debug!("tx desc status: {}", self.status.read());
+            core::hint::spin_loop();
         } 
     }
 }
@@ -380,6 +381,7 @@ impl TxDescriptor for AdvancedTxDescriptor {
     fn wait_for_packet_tx(&self) {
         while (self.paylen_popts_cc_idx_sta.read() as u8 & TX_STATUS_DD) == 0 {
             // error!("tx desc status: {:#X}", self.paylen_popts_cc_idx_sta.read());
+            core::hint::spin_loop();
         } 
     }
 }
@@ -26,6 +26,9 @@ pub use arch::{
 #[cfg(target_arch = "aarch64")]
 pub use arch::AArch64LocalInterruptControllerApi;
 
+#[cfg(target_arch = "x86_64")]
+pub use arch::pic_fallback_reason;
+
 pub type InterruptNumber = u8;
 
 
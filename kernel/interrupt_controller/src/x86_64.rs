@@ -17,18 +17,50 @@ pub struct Priority;
 
 /// Initializes the interrupt controller(s), including the Local APIC for the BSP
 /// (bootstrap processor) and the system-wide IOAPIC(s).
+///
+/// If the APIC is absent or doesn't respond sanely, or the MADT is missing or invalid,
+/// this falls back to the legacy PIC instead; see [`apic::init_pic_mode()`].
+/// The `force_pic` config option or the `chip=pic` boot option forces this fallback, e.g., for testing.
 pub fn init(kernel_mmi: &memory::MmiRef) -> Result<(), &'static str> {
-    apic::init();
+    match init_apic(kernel_mmi) {
+        Ok(()) => {
+            log::info!("Using the {:?} interrupt controller", apic::INTERRUPT_CHIP.load());
+            Ok(())
+        }
+        // Once the BSP's Local APIC is up and running, it's too late to fall back.
+        Err(e) if apic::bootstrap_cpu().is_some() => Err(e),
+        Err(reason) => {
+            log::warn!("Falling back to the legacy PIC on a single CPU because {}", reason);
+            apic::init_pic_mode(reason)
+        }
+    }
+}
 
-    // Use the MADT ACPI table to initialize more interrupt controller details.
-    {
-        let acpi_tables = acpi::get_acpi_tables().lock();
-        let madt = Madt::get(&acpi_tables)
-            .ok_or("The required MADT ACPI table wasn't found (signature 'APIC')")?;
-        madt.bsp_init(&mut kernel_mmi.lock().page_table)?;
+/// Initializes the Local APIC for the BSP and the IOAPIC(s) based on the MADT.
+fn init_apic(kernel_mmi: &memory::MmiRef) -> Result<(), &'static str> {
+    if cfg!(force_pic) {
+        return Err("the `force_pic` config option was set");
+    }
+    if boot_args::boot_args().interrupt_chip == boot_args::InterruptChipArg::Pic {
+        return Err("the `chip=pic` boot option was given");
     }
+    if !apic::has_apic() {
+        return Err("CPUID reports that there is no Local APIC");
+    }
+
+    // Use the MADT ACPI table to initialize more interrupt controller details.
+    let acpi_tables = acpi::get_acpi_tables().lock();
+    let madt = Madt::get(&acpi_tables)
+        .ok_or("the required MADT ACPI table wasn't found (signature 'APIC')")?;
+
+    apic::init();
+    madt.bsp_init(&mut kernel_mmi.lock().page_table)
+}
 
-    Ok(())
+/// Returns the reason why this system fell back to the legacy PIC,
+/// or `None` if it uses the APIC.
+pub fn pic_fallback_reason() -> Option<&'static str> {
+    apic::pic_fallback_reason()
 }
 
 /// Structure representing a top-level/system-wide interrupt controller chip,
@@ -65,6 +97,10 @@ impl SystemInterruptControllerApi for SystemInterruptController {
         // no support for priority on x86_64
         let _ = priority;
 
+        if apic::pic_fallback_reason().is_some() {
+            return Err("IRQ affinity is unavailable in PIC mode");
+        }
+
         if let Some(destination) = destination {
             self.0.lock().set_irq(sys_int_num, destination.into(), sys_int_num)
         } else {
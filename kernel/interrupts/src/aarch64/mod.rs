@@ -1,5 +1,6 @@
 use core::arch::global_asm;
 use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use crate::EoiBehaviour;
 
@@ -132,8 +133,13 @@ pub fn init_ap() {
 
 /// Initializes the generic system timer and the system-wide list of interrupt handlers.
 ///
-/// This only needs to be invoked once, system-wide.
+/// This must only be invoked once, system-wide.
 pub fn init() -> Result<(), &'static str> {
+    static INITIALIZED: AtomicBool = AtomicBool::new(false);
+    if INITIALIZED.swap(true, Ordering::AcqRel) {
+        error!("BUG: interrupts::init() was called more than once!");
+        return Err("BUG: interrupts::init() was called more than once; use init_ap() on APs");
+    }
     generic_timer_aarch64::init();
     set_vbar_el1();
 
@@ -0,0 +1,105 @@
+//! A nesting-aware RAII guard that keeps interrupts disabled on the current CPU.
+//!
+//! Each [`InterruptGuard`] records whether interrupts were enabled when it was created,
+//! and the number of guards alive on each CPU is tracked. Interrupts are only re-enabled
+//! once the last guard on a CPU is dropped, and only if one of the guards had found them enabled.
+//! Thus, dropping an inner guard never re-enables interrupts that an outer guard disabled,
+//! even if the guards are dropped in a different order than they were created.
+//!
+//! A guard must not be held across a task switch, e.g., by blocking or yielding,
+//! as the nesting depth is tracked per CPU rather than per task.
+
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use cpu::CpuId;
+use kassert::kassert_once;
+
+/// The maximum number of CPUs whose interrupt guards can be tracked.
+const MAX_TRACKED_CPUS: usize = 256;
+
+/// The number of [`InterruptGuard`]s currently alive on each CPU.
+static DEPTH: [AtomicUsize; MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_TRACKED_CPUS]
+};
+/// Whether interrupts should be re-enabled on each CPU once its last [`InterruptGuard`] is dropped.
+static RESTORE_ENABLED: [AtomicBool; MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FALSE: AtomicBool = AtomicBool::new(false);
+    [FALSE; MAX_TRACKED_CPUS]
+};
+
+/// Disables interrupts on the current CPU until it's dropped,
+/// at which point the interrupt state from before the outermost guard is restored.
+///
+/// Guards may be nested freely: interrupts are only re-enabled once the last guard
+/// on this CPU is dropped, regardless of the order in which the guards are dropped.
+///
+/// This type is not `Send`, as it must be dropped on the same CPU that created it,
+/// and it must not be held across a task switch, e.g., by blocking or yielding.
+#[must_use = "interrupts are re-enabled as soon as the guard is dropped"]
+pub struct InterruptGuard {
+    cpu: CpuId,
+    was_enabled: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl InterruptGuard {
+    /// Disables interrupts on the current CPU, recording whether they were enabled.
+    pub fn new() -> InterruptGuard {
+        let was_enabled = irq_safety::interrupts_enabled();
+        irq_safety::disable_interrupts();
+        let cpu = cpu::current_cpu();
+        if let Some(depth) = DEPTH.get(cpu.value() as usize) {
+            depth.fetch_add(1, Ordering::Relaxed);
+        }
+        InterruptGuard { cpu, was_enabled, _not_send: PhantomData }
+    }
+
+    /// Returns whether interrupts were enabled when this guard was created.
+    pub fn interrupts_were_enabled(&self) -> bool {
+        self.was_enabled
+    }
+
+    /// Returns the number of guards currently alive on the current CPU.
+    ///
+    /// This is only meaningful while interrupts are disabled, e.g., while holding a guard.
+    pub fn nesting_depth() -> usize {
+        DEPTH.get(cpu::current_cpu().value() as usize)
+            .map_or(0, |depth| depth.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for InterruptGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        kassert_once!(
+            cpu::current_cpu() == self.cpu,
+            "BUG: an InterruptGuard created on CPU {} was dropped on CPU {}", self.cpu, cpu::current_cpu(),
+        );
+        let cpu = self.cpu.value() as usize;
+        let (Some(depth), Some(restore_enabled)) = (DEPTH.get(cpu), RESTORE_ENABLED.get(cpu)) else {
+            // Untracked CPUs can't be nesting-aware, so just restore this guard's own state.
+            if self.was_enabled {
+                irq_safety::enable_interrupts();
+            }
+            return;
+        };
+
+        if self.was_enabled {
+            restore_enabled.store(true, Ordering::Relaxed);
+        }
+        let remaining = depth.fetch_sub(1, Ordering::Relaxed).saturating_sub(1);
+        if remaining == 0 && restore_enabled.swap(false, Ordering::Relaxed) {
+            irq_safety::enable_interrupts();
+        }
+    }
+}
@@ -4,11 +4,16 @@
 #![cfg_attr(target_arch = "x86_64", feature(abi_x86_interrupt))]
 #![cfg_attr(target_arch = "x86_64", allow(dead_code))]
 
+#[cfg(target_arch = "x86_64")]
+extern crate alloc;
+
 #[cfg_attr(target_arch = "x86_64", path = "x86_64/mod.rs")]
 #[cfg_attr(target_arch = "aarch64", path = "aarch64/mod.rs")]
 mod arch;
+mod guard;
 
 pub use arch::*;
+pub use guard::InterruptGuard;
 
 #[derive(Debug, PartialEq, Eq)]
 #[repr(C)]
@@ -0,0 +1,201 @@
+//! A boot-time consistency check of every CPU's GDT, TSS, and IDT.
+//!
+//! Each CPU records the descriptor table registers it actually loaded, via [`record_current_cpu()`],
+//! as the last step of [`init()`](super::init) or [`init_ap()`](super::init_ap).
+//! Once all CPUs have booted, [`check_all_cpus()`] cross-checks those records
+//! against the kernel's own bookkeeping:
+//! * each CPU's GDTR must point to the GDT created for that CPU,
+//! * the TSS descriptor selected by each CPU's TR must point to the TSS created for that CPU,
+//! * each TSS's double fault IST and RSP0 entries must point to the top of
+//!   the stacks owned by that TSS, which must still be mapped as writable, and
+//! * each CPU's IDTR must point to the system-wide [`IDT`].
+//!
+//! These mismatches would otherwise only surface as a triple fault upon
+//! that CPU's first double fault or privilege change.
+
+use alloc::collections::BTreeMap;
+use core::{arch::asm, mem::size_of};
+use cpu::CpuId;
+use log::{error, info};
+use memory::VirtualAddress;
+use stack::Stack;
+use sync_irq::IrqSafeMutex;
+use x86_64::{
+    instructions::tables::DescriptorTablePointer,
+    structures::{idt::InterruptDescriptorTable, tss::TaskStateSegment},
+    VirtAddr,
+};
+use super::IDT;
+
+/// The descriptor table state that each CPU recorded for itself.
+static RECORDS: IrqSafeMutex<BTreeMap<CpuId, LoadedDescriptorTables>> = IrqSafeMutex::new(BTreeMap::new());
+
+/// The descriptor table state that a CPU actually loaded, as read from its registers.
+#[derive(Clone, Copy, Debug)]
+pub struct LoadedDescriptorTables {
+    pub gdtr_base: u64,
+    pub gdtr_limit: u16,
+    pub idtr_base: u64,
+    pub idtr_limit: u16,
+    /// The task register, i.e., the selector of the TSS descriptor in the GDT.
+    pub tr: u16,
+    /// The TSS base address encoded in the GDT entry that `tr` selects.
+    pub tss_base: u64,
+    /// The TSS's double fault IST entry, read through `tss_base`.
+    pub double_fault_ist: u64,
+    /// The TSS's privilege stack 0 (RSP0) entry, read through `tss_base`.
+    pub rsp0: u64,
+}
+
+/// Reads the current CPU's descriptor table registers and records them
+/// to be checked later by [`check_all_cpus()`].
+pub fn record_current_cpu(cpu_id: CpuId) {
+    let gdtr = sgdt();
+    let idtr = sidt();
+    let tr = read_tr();
+
+    // Follow the same path through memory that the hardware would: GDTR -> TSS descriptor -> TSS.
+    let index = (tr >> 3) as u64;
+    let (tss_base, double_fault_ist, rsp0) = if (index + 1) * 8 + 7 <= gdtr.limit as u64 {
+        let descriptor = (gdtr.base.as_u64() + index * 8) as *const u64;
+        // SAFETY: the descriptor lies within the GDT that this CPU currently has loaded.
+        let (low, high) = unsafe { (descriptor.read(), descriptor.add(1).read()) };
+        let tss_base = gdt::system_segment_base(low, high);
+        // SAFETY: this is the TSS that this CPU currently has loaded,
+        // which is checked to be mapped in `tss::create_tss()`.
+        let tss = unsafe { &*(tss_base as *const TaskStateSegment) };
+        (
+            tss_base,
+            tss.interrupt_stack_table[tss::DOUBLE_FAULT_IST_INDEX].as_u64(),
+            tss.privilege_stack_table[0].as_u64(),
+        )
+    } else {
+        (0, 0, 0)
+    };
+
+    RECORDS.lock().insert(cpu_id, LoadedDescriptorTables {
+        gdtr_base: gdtr.base.as_u64(),
+        gdtr_limit: gdtr.limit,
+        idtr_base: idtr.base.as_u64(),
+        idtr_limit: idtr.limit,
+        tr,
+        tss_base,
+        double_fault_ist,
+        rsp0,
+    });
+}
+
+/// Cross-checks the descriptor tables that every CPU loaded against those
+/// that the kernel created for it.
+///
+/// Every mismatch is logged along with the offending CPU and structure.
+/// Returns an error if there was at least one mismatch,
+/// or if other than `expected_cpus` CPUs recorded their descriptor tables.
+pub fn check_all_cpus(expected_cpus: usize) -> Result<(), &'static str> {
+    let records = RECORDS.lock().clone();
+    let mut mismatches = 0;
+
+    if records.len() != expected_cpus {
+        error!("descriptor table check: {} CPUs recorded their descriptor tables, expected {}",
+            records.len(), expected_cpus,
+        );
+        mismatches += 1;
+    }
+
+    let idt_base = &*IDT.lock() as *const InterruptDescriptorTable as u64;
+    let idt_limit = (size_of::<InterruptDescriptorTable>() - 1) as u16;
+    let tss_selector = gdt::AvailableSegmentSelector::Tss.get().map(|sel| sel.0);
+
+    for (&cpu, loaded) in records.iter() {
+        match gdt::get_gdt(cpu) {
+            Some(gdt) => {
+                let pointer = gdt.pointer();
+                mismatches += compare(cpu, "GDTR base", pointer.base.as_u64(), loaded.gdtr_base);
+                mismatches += compare(cpu, "GDTR limit", pointer.limit as u64, loaded.gdtr_limit as u64);
+                mismatches += compare(cpu, "GDT's TSS descriptor base", gdt.tss_base(), loaded.tss_base);
+            }
+            None => {
+                error!("descriptor table check: CPU {} has no GDT", cpu);
+                mismatches += 1;
+            }
+        }
+        if let Some(selector) = tss_selector {
+            mismatches += compare(cpu, "TR", selector as u64, loaded.tr as u64);
+        }
+
+        match tss::get_tss(cpu) {
+            Some(cpu_tss) => {
+                let tss_base = &*cpu_tss.tss().lock() as *const TaskStateSegment as u64;
+                mismatches += compare(cpu, "TSS address", tss_base, loaded.tss_base);
+                mismatches += check_stack(cpu, "TSS double fault IST entry", cpu_tss.double_fault_stack(), loaded.double_fault_ist);
+                mismatches += check_stack(cpu, "TSS RSP0 entry", cpu_tss.privilege_stack(), loaded.rsp0);
+            }
+            None => {
+                error!("descriptor table check: CPU {} has no TSS", cpu);
+                mismatches += 1;
+            }
+        }
+
+        mismatches += compare(cpu, "IDTR base", idt_base, loaded.idtr_base);
+        mismatches += compare(cpu, "IDTR limit", idt_limit as u64, loaded.idtr_limit as u64);
+    }
+
+    if mismatches == 0 {
+        info!("descriptor table check: the GDT, TSS, and IDT of all {} CPUs are consistent", records.len());
+        Ok(())
+    } else {
+        error!("descriptor table check: found {} mismatches", mismatches);
+        Err("descriptor table check: some CPUs' GDT, TSS, or IDT are inconsistent; see the log")
+    }
+}
+
+/// Logs a mismatch if the `actual` value of the given CPU's `structure` differs from the `expected` value.
+///
+/// Returns the number of mismatches, i.e., 0 or 1.
+fn compare(cpu: CpuId, structure: &str, expected: u64, actual: u64) -> usize {
+    if expected == actual {
+        return 0;
+    }
+    error!("descriptor table check: CPU {} {} is {:#X}, expected {:#X}", cpu, structure, actual, expected);
+    1
+}
+
+/// Checks that the given TSS `entry` points to the top of the given `stack`,
+/// and that the stack is still mapped as writable.
+///
+/// Returns the number of mismatches.
+fn check_stack(cpu: CpuId, entry: &str, stack: &Stack, actual: u64) -> usize {
+    let mut mismatches = compare(cpu, entry, stack.top_unusable().value() as u64, actual);
+    if !is_mapped_writable(stack.top_usable()) {
+        error!("descriptor table check: CPU {} {} points to a stack whose top {:#X} is not mapped as writable",
+            cpu, entry, stack.top_usable(),
+        );
+        mismatches += 1;
+    }
+    mismatches
+}
+
+fn is_mapped_writable(vaddr: VirtualAddress) -> bool {
+    memory::page_flags(vaddr).is_some_and(|flags| flags.is_writable())
+}
+
+fn sgdt() -> DescriptorTablePointer {
+    let mut pointer = DescriptorTablePointer { limit: 0, base: VirtAddr::zero() };
+    // SAFETY: `sgdt` only writes the GDTR to the given pointer.
+    unsafe { asm!("sgdt [{}]", in(reg) &mut pointer, options(nostack, preserves_flags)) };
+    pointer
+}
+
+fn sidt() -> DescriptorTablePointer {
+    let mut pointer = DescriptorTablePointer { limit: 0, base: VirtAddr::zero() };
+    // SAFETY: `sidt` only writes the IDTR to the given pointer.
+    unsafe { asm!("sidt [{}]", in(reg) &mut pointer, options(nostack, preserves_flags)) };
+    pointer
+}
+
+fn read_tr() -> u16 {
+    let tr: u16;
+    // SAFETY: `str` only reads the task register.
+    unsafe { asm!("str {0:x}", out(reg) tr, options(nomem, nostack, preserves_flags)) };
+    tr
+}
@@ -0,0 +1,144 @@
+//! Rules and helpers for FP/SIMD register usage in interrupt context.
+//!
+//! Interrupt entry does not save the interrupted task's FP/SIMD registers,
+//! so any interrupt handler (or anything it calls synchronously) that touches
+//! those registers would corrupt the state of the task it interrupted.
+//! Theseus avoids this by building the base kernel, which contains every
+//! interrupt handler and the IRQ paths of all drivers, with the soft-float
+//! `x86_64-unknown-theseus` target, so the compiler never emits SSE instructions
+//! for them, e.g., in `memcpy` or formatting code.
+//! Only the SIMD personality crates are built with SSE/AVX enabled,
+//! and those only ever run in task context.
+//!
+//! Two tools uphold this rule:
+//! * In debug builds of the soft-float kernel, [`interrupt_handler!`](crate::interrupt_handler)
+//!   snapshots a couple of XMM registers upon entry, and [`eoi()`](super::eoi)
+//!   checks that they're unchanged, logging an error that identifies the
+//!   offending vector if they're not.
+//!   Handlers that don't use that macro can opt in by calling [`snapshot_on_irq_entry()`].
+//! * The rare handler that genuinely needs vector instructions must run that code
+//!   within [`with_fpu_in_irq()`], which saves and restores the full FP/SSE state around it.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use log::error;
+
+/// The maximum number of CPUs whose XMM register snapshots can be tracked.
+const MAX_TRACKED_CPUS: usize = 256;
+
+/// The values of `xmm0` and `xmm1` on each CPU upon entry to the current interrupt handler.
+static SNAPSHOTS: [[AtomicU64; 2]; MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    #[allow(clippy::declare_interior_mutable_const)]
+    const PAIR: [AtomicU64; 2] = [ZERO; 2];
+    [PAIR; MAX_TRACKED_CPUS]
+};
+/// Whether each CPU's entry in [`SNAPSHOTS`] belongs to the interrupt handler currently running on it.
+static SNAPSHOT_VALID: [AtomicBool; MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FALSE: AtomicBool = AtomicBool::new(false);
+    [FALSE; MAX_TRACKED_CPUS]
+};
+
+/// Whether the FP/SIMD usage check is performed in this build.
+///
+/// This is only meaningful in the soft-float kernel;
+/// crates built for a SIMD personality are expected to use SIMD registers.
+const CHECK_ENABLED: bool = cfg!(all(debug_assertions, not(target_feature = "sse")));
+
+/// The layout of the memory area used by `fxsave64` and `fxrstor64`.
+#[repr(C, align(16))]
+struct FxSaveArea([u8; 512]);
+
+/// Runs the given closure with the current FP/SSE state saved beforehand
+/// and restored afterwards, allowing it to use vector instructions in interrupt context.
+///
+/// The closure must not task switch, and if it panics, the FP/SSE state is not restored.
+/// This saves the x87, MMX, and SSE registers but not the upper halves of AVX registers,
+/// so the closure must not use AVX instructions.
+pub fn with_fpu_in_irq<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let mut area = FxSaveArea([0; 512]);
+    // SAFETY: `area` is a 16-byte-aligned, 512-byte area, as `fxsave64` requires.
+    unsafe {
+        core::arch::asm!(
+            "fxsave64 [{}]",
+            in(reg) &mut area,
+            options(nostack, preserves_flags),
+        );
+    }
+    let ret = f();
+    // SAFETY: `area` holds the valid state saved above.
+    unsafe {
+        core::arch::asm!(
+            "fxrstor64 [{}]",
+            in(reg) &area,
+            options(nostack, preserves_flags, readonly),
+        );
+    }
+    ret
+}
+
+/// Records the current values of a couple of XMM registers on this CPU
+/// so that [`eoi()`](super::eoi) can check that this interrupt handler didn't change them.
+///
+/// This is called automatically by handlers defined with [`interrupt_handler!`](crate::interrupt_handler)
+/// and does nothing unless the check is enabled for this build, i.e.,
+/// in debug builds of the soft-float kernel.
+#[inline(always)]
+pub fn snapshot_on_irq_entry() {
+    if !CHECK_ENABLED {
+        return;
+    }
+    let cpu = cpu::current_cpu().value() as usize;
+    let (Some(snapshot), Some(valid)) = (SNAPSHOTS.get(cpu), SNAPSHOT_VALID.get(cpu)) else {
+        return;
+    };
+    let (xmm0, xmm1) = read_xmm0_xmm1();
+    snapshot[0].store(xmm0, Ordering::Relaxed);
+    snapshot[1].store(xmm1, Ordering::Relaxed);
+    valid.store(true, Ordering::Relaxed);
+}
+
+/// Checks that the XMM registers recorded by [`snapshot_on_irq_entry()`] on this CPU
+/// are unchanged, and logs an error if the handler for `vector` clobbered them.
+#[inline(always)]
+pub(crate) fn verify_on_irq_exit(vector: u8) {
+    if !CHECK_ENABLED {
+        return;
+    }
+    let cpu = cpu::current_cpu().value() as usize;
+    let (Some(snapshot), Some(valid)) = (SNAPSHOTS.get(cpu), SNAPSHOT_VALID.get(cpu)) else {
+        return;
+    };
+    if !valid.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let (xmm0, xmm1) = read_xmm0_xmm1();
+    if xmm0 != snapshot[0].load(Ordering::Relaxed) || xmm1 != snapshot[1].load(Ordering::Relaxed) {
+        error!("BUG: interrupt handler for vector {:#X} on CPU {} clobbered SIMD registers; \
+            it must not use FP/SIMD instructions outside of `with_fpu_in_irq()`",
+            vector, cpu,
+        );
+    }
+}
+
+/// Returns the low 64 bits of `xmm0` and `xmm1`.
+#[inline(always)]
+fn read_xmm0_xmm1() -> (u64, u64) {
+    let xmm0: u64;
+    let xmm1: u64;
+    // SAFETY: SSE is always enabled in CR4 during boot, and this only reads the registers.
+    unsafe {
+        core::arch::asm!(
+            "movq {}, xmm0",
+            "movq {}, xmm1",
+            out(reg) xmm0,
+            out(reg) xmm1,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+    (xmm0, xmm1)
+}
@@ -1,15 +1,25 @@
 pub use pic::IRQ_BASE_OFFSET;
 
 // use rtc;
+use core::sync::atomic::{AtomicBool, Ordering};
 use apic::{INTERRUPT_CHIP, InterruptChip};
 use cpu::CpuId;
+use kassert::{kassert_count, kassert_once};
 use locked_idt::LockedIdt;
 use log::{error, warn, info, debug};
-use memory::VirtualAddress;
+use stack::Stack;
 use spin::Once;
 use early_printer::println;
 
+pub mod descriptor_tables;
+pub mod fpu;
+pub mod oneshot;
+pub mod spurious;
+pub mod storm;
+pub mod system_time;
+
 pub use x86_64::structures::idt::{InterruptStackFrame, HandlerFunc as InterruptHandler};
+pub use oneshot::{oneshot, OneShot};
 pub type InterruptNumber = u8;
 
 /// The IRQ number reserved for CPU-local timer interrupts,
@@ -21,14 +31,16 @@ pub const CPU_LOCAL_TIMER_IRQ: u8 = apic::LOCAL_APIC_LVT_IRQ;
 /// Note: this could be per-core instead of system-wide, if needed.
 pub static IDT: LockedIdt = LockedIdt::new();
 
+/// Whether [`init()`] has already been invoked, which must only happen once.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
 /// The single system-wide Programmable Interrupt Controller (PIC) chip.
 static PIC: Once<pic::ChainedPics> = Once::new();
 
 /// The list of IRQs reserved for Theseus-specific usage that cannot be
 /// used for general device interrupt handlers.
 /// These cannot be removed in [`deregister_interrupt()`].
-static RESERVED_IRQ_LIST: [u8; 3] = [
-    pic::PIC_SPURIOUS_INTERRUPT_IRQ,
+static RESERVED_IRQ_LIST: [u8; 2] = [
     CPU_LOCAL_TIMER_IRQ,
     apic::APIC_SPURIOUS_INTERRUPT_IRQ,
 ];
@@ -42,6 +54,11 @@ macro_rules! interrupt_handler {
     };
     ($name:ident, $x86_64_eoi_param:expr, $stack_frame:ident, $code:block) => {
         extern "x86-interrupt" fn $name(sf: $crate::InterruptStackFrame) {
+            if $crate::spurious::is_spurious($x86_64_eoi_param) {
+                return;
+            }
+            $crate::fpu::snapshot_on_irq_entry();
+            $crate::system_time::on_irq_entry();
             let $stack_frame = &sf;
             if let $crate::EoiBehaviour::HandlerDidNotSendEoi = $code {
                 $crate::eoi($x86_64_eoi_param);
@@ -82,17 +99,25 @@ pub fn is_exception_handler_with_error_code(address: u64) -> bool {
 /// a default placeholder handler, which is useful to catch interrupts that need to be implemented.
 ///
 /// # Arguments: 
-/// * `double_fault_stack_top_unusable`: the address of the top of a newly allocated stack,
+/// * `double_fault_stack`: a newly allocated stack,
 ///    to be used as the double fault exception handler stack.
-/// * `privilege_stack_top_unusable`: the address of the top of a newly allocated stack,
+/// * `privilege_stack`: a newly allocated stack,
 ///    to be used as the privilege stack (Ring 3 -> Ring 0 stack).
+///
+/// Both stacks are owned by the BSP's TSS from then on.
 pub fn init(
-    double_fault_stack_top_unusable: VirtualAddress,
-    privilege_stack_top_unusable: VirtualAddress
+    double_fault_stack: Stack,
+    privilege_stack: Stack,
 ) -> Result<&'static LockedIdt, &'static str> {
+    // A second invocation would re-create the BSP's TSS and GDT and reload the IDT,
+    // leaving the interrupt subsystem half-reconfigured.
+    if INITIALIZED.swap(true, Ordering::AcqRel) {
+        error!("BUG: interrupts::init() was called more than once!");
+        return Err("BUG: interrupts::init() was called more than once; use init_ap() on APs");
+    }
     let bsp_id = cpu::bootstrap_cpu().ok_or("couldn't get BSP's id")?;
     info!("Setting up TSS & GDT for BSP (id {})", bsp_id);
-    gdt::create_and_load_tss_gdt(bsp_id, double_fault_stack_top_unusable, privilege_stack_top_unusable);
+    gdt::create_and_load_tss_gdt(bsp_id, double_fault_stack, privilege_stack)?;
 
     // Before loading this new IDT, we must copy over all exception handlers from the early IDT.
     // However, we can't just clone `EARLY_IDT` into `IDT`, because we must 
@@ -130,22 +155,22 @@ pub fn init(
             double_fault_options.set_stack_index(tss::DOUBLE_FAULT_IST_INDEX as u16);
         }
 
-        // Fill only *missing* IDT entries with a default unimplemented interrupt handler.
-        for (_idx, new_entry) in new_idt.slice_mut(32..=255).iter_mut().enumerate() {
+        // Fill only *missing* IDT entries with a default handler, which is the unimplemented
+        // interrupt handler except on the lines that can receive spurious PIC interrupts.
+        for (idx, new_entry) in new_idt.slice_mut(32..=255).iter_mut().enumerate() {
+            let vector = (idx + IRQ_BASE_OFFSET as usize) as u8;
             if new_entry.handler_addr().as_u64() != 0 {
                 debug!("Preserved early registered interrupt handler for IRQ {:#X} at address {:#X}", 
-                    _idx + IRQ_BASE_OFFSET as usize, new_entry.handler_addr(),
+                    vector, new_entry.handler_addr(),
                 );
             } else {
-                new_entry.set_handler_fn(unimplemented_interrupt_handler);
+                new_entry.set_handler_fn(default_handler(vector));
             }
         }
         // This crate has a fixed dependency on the `pic` and `apic` crates,
         // because they are required to implement certain functions, e.g., `eoi()`.
         // Thus, we statically reserve some non-registerable IDT entries that should only be used
-        // by the PIC & APIC instead of dynamically registering them elsewhere.
-        new_idt[pic::PIC_SPURIOUS_INTERRUPT_IRQ as usize]
-            .set_handler_fn(pic_spurious_interrupt_handler);
+        // by the APIC instead of dynamically registering them elsewhere.
         new_idt[apic::APIC_SPURIOUS_INTERRUPT_IRQ as usize]
             .set_handler_fn(apic_spurious_interrupt_handler);
     }
@@ -154,10 +179,16 @@ pub fn init(
     info!("trying to load IDT for BSP...");
     IDT.load();
     info!("loaded IDT for BSP.");
+    descriptor_tables::record_current_cpu(bsp_id);
 
-    // Use the APIC instead of the old PIC
-    disable_pic();
+    // Use the APIC instead of the old PIC, unless we had to fall back to the PIC.
+    match INTERRUPT_CHIP.load() {
+        InterruptChip::APIC | InterruptChip::X2APIC => disable_pic(),
+        InterruptChip::PIC => enable_pic(),
+    }
 
+    storm::register_metrics()?;
+    spurious::register_metrics()?;
     Ok(&IDT)
 }
 
@@ -165,38 +196,62 @@ pub fn init(
 /// Similar to `init()`, but for APs to call after the BSP has already invoked `init()`.
 pub fn init_ap(
     cpu_id: CpuId, 
-    double_fault_stack_top_unusable: VirtualAddress, 
-    privilege_stack_top_unusable: VirtualAddress,
+    double_fault_stack: Stack,
+    privilege_stack: Stack,
 ) -> Result<&'static LockedIdt, &'static str> {
     info!("Setting up TSS & GDT for CPU {}", cpu_id);
-    gdt::create_and_load_tss_gdt(cpu_id, double_fault_stack_top_unusable, privilege_stack_top_unusable);
+    gdt::create_and_load_tss_gdt(cpu_id, double_fault_stack, privilege_stack)?;
 
     // We've already created the IDT initially (currently all CPUs share the initial IDT),
     // so we only need to re-load it here for each AP (each secondary CPU).
     IDT.load();
     info!("loaded IDT for CPU {}.", cpu_id);
+    descriptor_tables::record_current_cpu(cpu_id);
     Ok(&IDT)
 }
 
+/// Returns the legacy PIC if the system is in PIC mode, e.g., for diagnostics and tests.
+///
+/// Returns `None` if an APIC is in use, in which case the PIC is disabled.
+pub fn pic() -> Option<&'static pic::ChainedPics> {
+    if INTERRUPT_CHIP.load() == InterruptChip::PIC {
+        PIC.get()
+    } else {
+        None
+    }
+}
+
 /// Disables the PIC by masking all of its interrupts, indicating this system uses an APIC.
 fn disable_pic() {
     PIC.call_once(|| pic::ChainedPics::init(0xFF, 0xFF)); // disable all PIC IRQs
 }
 
-/// Enable the PIC by enabling all of its interrupts.
-/// This indicates the system does not have an APIC or that we don't wish to use it.
-/// 
-/// Note: currently we assume all systems have an APIC, so this is not used.
-///       If we ever did re-enable it, we would also need to set up PIT/RTC timer interrupts
-///       for preemptive task switching instead of the APIC LVT timer.
-fn _enable_pic() {
-    let master_pic_mask: u8 = 0x0; // allow every interrupt
-    let slave_pic_mask: u8 = 0b0000_1000; // everything is allowed except 0x2B 
-    PIC.call_once(|| pic::ChainedPics::init(master_pic_mask, slave_pic_mask));
-
-    // pit_clock::init(CONFIG_PIT_FREQUENCY_HZ);
-    // let rtc_handler = rtc::init(CONFIG_RTC_FREQUENCY_HZ, rtc_interrupt_func);
-    // IDT.lock()[0x28].set_handler_fn(rtc_handler.unwrap());
+/// Enables the PIC, indicating this system fell back to the PIC because it has no usable APIC.
+///
+/// Only the cascade line from the slave PIC is enabled at first;
+/// each IRQ line is enabled once a handler is registered for its vector
+/// via [`register_interrupt()`], including those registered before now.
+fn enable_pic() {
+    let pic = PIC.call_once(|| pic::ChainedPics::init(0b1111_1011, 0xFF));
+
+    let idt = IDT.lock();
+    for vector in IRQ_BASE_OFFSET .. IRQ_BASE_OFFSET + 16 {
+        let handler_addr = idt[vector as usize].handler_addr().as_u64() as usize;
+        if !is_unregistered(vector, handler_addr) {
+            pic.set_irq_masked(vector, false);
+        }
+    }
+}
+
+/// Returns the handler installed for the given `vector` when no other handler is registered.
+fn default_handler(vector: u8) -> InterruptHandler {
+    spurious::default_handler(vector).unwrap_or(unimplemented_interrupt_handler)
+}
+
+/// Returns whether the given `handler_addr` in the IDT entry for `vector`
+/// signifies that no handler is registered for that vector.
+fn is_unregistered(vector: u8, handler_addr: usize) -> bool {
+    handler_addr == 0 || handler_addr == default_handler(vector) as usize
 }
 
 /// Registers an interrupt handler at the given IRQ interrupt number.
@@ -217,22 +272,80 @@ pub fn register_interrupt(interrupt_num: u8, func: InterruptHandler) -> Result<(
     // or is the default handler, that signifies the interrupt number is available.
     let idt_entry = &mut idt[interrupt_num as usize];
     let existing_handler_addr = idt_entry.handler_addr().as_u64() as usize;
-    if existing_handler_addr == 0 || existing_handler_addr == unimplemented_interrupt_handler as usize {
+    if is_unregistered(interrupt_num, existing_handler_addr) {
         idt_entry.set_handler_fn(func);
+        // With the APIC, all ISA IRQs are routed to the BSP up front, but PIC lines start out masked.
+        if INTERRUPT_CHIP.load() == InterruptChip::PIC {
+            if let Some(pic) = PIC.get() {
+                pic.set_irq_masked(interrupt_num, false);
+            }
+        }
         Ok(())
     } else {
         error!("register_interrupt: the requested interrupt IRQ {} was already in use", interrupt_num);
         Err(existing_handler_addr)
     }
-} 
+}
+
+/// Registers an interrupt handler for the given legacy ISA IRQ number, e.g., `1` for the PS/2 keyboard,
+/// and routes that ISA IRQ to the handler.
+///
+/// The handler is registered at the vector `IRQ_BASE_OFFSET + isa_irq`, as with the remapped PIC.
+/// If an APIC is in use, this also programs the IoApic redirection entry for the ISA IRQ,
+/// applying any ACPI interrupt source override that connects it to a different GSI
+/// or with a different polarity or trigger mode (see [`ioapic::isa_irq_route()`]).
+///
+/// # Return
+/// * `Ok(vector)` if successfully registered, or
+/// * `Err(existing_handler_address)` if the ISA IRQ's vector was already in use.
+pub fn register_isa_interrupt(isa_irq: u8, func: InterruptHandler) -> Result<u8, usize> {
+    let vector = IRQ_BASE_OFFSET + isa_irq;
+    register_interrupt(vector, func)?;
+
+    if matches!(INTERRUPT_CHIP.load(), InterruptChip::APIC | InterruptChip::X2APIC) {
+        let result = apic::bootstrap_cpu()
+            .ok_or("couldn't get BSP's APIC ID")
+            .and_then(|bsp| ioapic::route_isa_irq(isa_irq, bsp, vector));
+        if let Err(e) = result {
+            warn!("register_isa_interrupt: couldn't route ISA IRQ {} to vector {:#X}: {}", isa_irq, vector, e);
+        }
+    }
+    Ok(vector)
+}
+
+/// Installs `handler` for the given interrupt `vector`, e.g., for a driver discovered at runtime.
+///
+/// This is like [`register_interrupt()`], but it also rejects the vectors reserved
+/// for CPU exceptions (0 to 31) and for Theseus itself, and returns a descriptive error.
+///
+/// The handler takes effect immediately on all CPUs, because they all share the same `IDT`,
+/// so there is no need to reload it.
+/// The caller is still responsible for routing its device's interrupt to `vector`,
+/// e.g., via an IOAPIC redirection entry or MSI.
+pub fn register_handler(vector: u8, handler: InterruptHandler) -> Result<(), &'static str> {
+    if vector < IRQ_BASE_OFFSET {
+        return Err("register_handler: cannot register a handler for a CPU exception vector");
+    }
+    if RESERVED_IRQ_LIST.contains(&vector) {
+        return Err("register_handler: cannot register a handler for a reserved interrupt vector");
+    }
+    register_interrupt(vector, handler)
+        .map_err(|_existing_handler| "register_handler: the interrupt vector is already in use")
+}
 
 /// Allocates and returns an unused interrupt number and sets its handler function.
 ///
-/// Returns an error if there are no unused interrupt number, which is highly unlikely.
+/// Returns an error if there are no unused interrupt number, which is highly unlikely,
+/// or if the system is in PIC mode, in which MSIs cannot be delivered.
 ///
 /// # Arguments
 /// * `func`: the handler for the assigned interrupt number.
 pub fn register_msi_interrupt(func: InterruptHandler) -> Result<u8, &'static str> {
+    // MSIs are delivered as writes to a Local APIC's address.
+    if INTERRUPT_CHIP.load() == InterruptChip::PIC {
+        return Err("register_msi_interrupt: MSI is unavailable in PIC mode");
+    }
+
     let mut idt = IDT.lock();
 
     // try to find an unused interrupt number in the IDT
@@ -267,7 +380,12 @@ pub fn deregister_interrupt(interrupt_num: u8, func: InterruptHandler) -> Result
     // check if the handler stored is the same as the one provided
     // this is to make sure no other application can deregister your interrupt
     if idt[interrupt_num as usize].handler_addr().as_u64() as usize == func as usize {
-        idt[interrupt_num as usize].set_handler_fn(unimplemented_interrupt_handler);
+        idt[interrupt_num as usize].set_handler_fn(default_handler(interrupt_num));
+        if INTERRUPT_CHIP.load() == InterruptChip::PIC {
+            if let Some(pic) = PIC.get() {
+                pic.set_irq_masked(interrupt_num, true);
+            }
+        }
         Ok(())
     }
     else {
@@ -284,21 +402,39 @@ pub fn deregister_interrupt(interrupt_num: u8, func: InterruptHandler) -> Result
 ///
 /// The `irq` argument is only used if the legacy `PIC` chip is active on this system;
 /// newer APIC chips do not use this.
+///
+/// This also counts the interrupt's arrival for [`storm`] detection,
+/// accounts the time spent in its handler to this CPU's [`system_time`],
+/// and adds the interrupt's timing to this CPU's [entropy pool](random::pool).
 pub fn eoi(irq: InterruptNumber) {
+    system_time::on_irq_exit();
     match INTERRUPT_CHIP.load() {
         InterruptChip::APIC | InterruptChip::X2APIC => {
-            if let Some(my_apic) = apic::get_my_apic() {
-                my_apic.write().eoi();
-            } else {
-                error!("BUG: couldn't get my LocalApic instance to send EOI!");
+            let Some(my_apic) = apic::get_my_apic() else {
+                kassert_once!(false, "couldn't get my LocalApic instance to send EOI for IRQ {:#X}", irq);
+                return;
+            };
+            let mut my_apic = my_apic.write();
+            let vector = my_apic.highest_in_service_vector();
+            // Each EOI must retire an in-service interrupt; an EOI without one means
+            // a handler sent more than one EOI, or sent one for a spurious interrupt.
+            kassert_count!(vector.is_some(), "EOI for IRQ {:#X} sent with no interrupt in service", irq);
+            if let Some(vector) = vector {
+                storm::record_arrival(vector);
+                random::pool::add_interrupt_timing(vector);
             }
+            fpu::verify_on_irq_exit(vector.unwrap_or(irq));
+            my_apic.eoi();
         }
         InterruptChip::PIC => {
-            if let Some(_pic) = PIC.get() {
-                _pic.notify_end_of_interrupt(irq);
-            } else {
-                error!("BUG: couldn't get PIC instance to send EOI!");
-            }  
+            let Some(pic) = PIC.get() else {
+                kassert_once!(false, "couldn't get PIC instance to send EOI for IRQ {:#X}", irq);
+                return;
+            };
+            storm::record_arrival(irq);
+            random::pool::add_interrupt_timing(irq);
+            fpu::verify_on_irq_exit(irq);
+            pic.notify_end_of_interrupt(irq);
         }
     }
 }
@@ -319,13 +455,11 @@ extern "x86-interrupt" fn unimplemented_interrupt_handler(_stack_frame: Interrup
         apic::InterruptChip::APIC | apic::InterruptChip::X2APIC => {
             if let Some(lapic_ref) = apic::get_my_apic() {
                 let lapic = lapic_ref.read();
-                let isr = lapic.get_isr(); 
-                let irr = lapic.get_irr();
-                println!("APIC ISR: {:#x} {:#x} {:#x} {:#x}, {:#x} {:#x} {:#x} {:#x}\n \
-                    IRR: {:#x} {:#x} {:#x} {:#x},{:#x} {:#x} {:#x} {:#x}", 
-                    isr[0], isr[1], isr[2], isr[3], isr[4], isr[5], isr[6], isr[7],
-                    irr[0], irr[1], irr[2], irr[3], irr[4], irr[5], irr[6], irr[7],
-                );
+                let isr = lapic.read_isr();
+                let irr = lapic.read_irr();
+                println!("APIC ISR: {:x?}\n     IRR: {:x?}", isr, irr);
+                println!("In-service vectors:{}", VectorList(apic::asserted_vectors(isr)));
+                println!("Pending vectors:   {}", VectorList(lapic.pending_vectors()));
             }
             else {
                 println!("APIC ISR and IRR were unknown.");
@@ -338,35 +472,22 @@ extern "x86-interrupt" fn unimplemented_interrupt_handler(_stack_frame: Interrup
 
 
 
-/// The Spurious interrupt handler for the PIC. 
-/// This has given us a lot of problems on bochs emulator and on some real hardware, but not on QEMU.
-/// Spurious interrupts occur a lot when using PIC on real hardware, but only occurs once when using apic/x2apic. 
-/// See here for more: https://mailman.linuxchix.org/pipermail/techtalk/2002-August/012697.html.
-/// We handle it according to this advice: https://wiki.osdev.org/8259_PIC#Spurious_IRQs
-extern "x86-interrupt" fn pic_spurious_interrupt_handler(_stack_frame: InterruptStackFrame ) {
-    if let Some(pic) = PIC.get() {
-        let irq_regs = pic.read_isr_irr();
-        // check if this was a real IRQ7 (parallel port) (bit 7 will be set)
-        // (pretty sure this will never happen)
-        // if it was a real IRQ7, we do need to ack it by sending an EOI
-        if irq_regs.master_isr & 0x80 == 0x80 {
-            println!("\nGot real IRQ7, not spurious! (Unexpected behavior)");
-            error!("Got real IRQ7, not spurious! (Unexpected behavior)");
-            eoi(IRQ_BASE_OFFSET + 0x7);
+/// Displays a list of interrupt vectors, e.g., those in service or pending on a Local APIC.
+struct VectorList<I>(I);
+impl<I: Iterator<Item = u8> + Clone> core::fmt::Display for VectorList<I> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut any = false;
+        for vector in self.0.clone() {
+            write!(f, " {:#04X}", vector)?;
+            any = true;
         }
-        else {
-            // do nothing. Do not send an EOI. 
-            // see https://wiki.osdev.org/8259_PIC#Spurious_IRQs
+        if !any {
+            write!(f, " none")?;
         }
+        Ok(())
     }
-    else {
-        error!("pic_spurious_interrupt_handler(): PIC wasn't initialized!");
-    }
-
 }
 
-
-
 // fn rtc_interrupt_func(rtc_ticks: Option<usize>) {
 //     trace!("rtc_interrupt_func: rtc_ticks = {:?}", rtc_ticks);
 // }
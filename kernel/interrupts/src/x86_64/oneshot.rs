@@ -0,0 +1,182 @@
+//! One-shot interrupt handlers, which handle a single occurrence of an interrupt
+//! in place of that interrupt's registered handler.
+//!
+//! This is useful for bring-up and testing, e.g., to check that a device's interrupt
+//! actually arrives at the expected vector, or to measure interrupt latency.
+//!
+//! [`oneshot()`] temporarily replaces the handler for a given vector with a trampoline.
+//! The next time that interrupt occurs, the trampoline restores the vector's previous handler,
+//! invokes the one-shot callback, and then wakes any task waiting on the returned [`OneShot`].
+//! Only one one-shot handler can be pending at a time.
+
+use alloc::boxed::Box;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll, Waker},
+};
+use apic::{INTERRUPT_CHIP, InterruptChip};
+use sync_irq::IrqSafeMutex;
+use x86_64::VirtAddr;
+use crate::{EoiBehaviour, InterruptStackFrame};
+use super::{IDT, IRQ_BASE_OFFSET, PIC, RESERVED_IRQ_LIST, default_handler, is_unregistered};
+
+/// A one-shot handler that is waiting for its interrupt.
+struct Pending {
+    vector: u8,
+    /// The address of the handler that the trampoline replaced, which may be unregistered.
+    previous_handler_addr: usize,
+    callback: Box<dyn FnOnce(&InterruptStackFrame) + Send>,
+}
+
+struct State {
+    pending: Option<Pending>,
+    /// The generation of the most recently installed one-shot handler.
+    generation: u64,
+    /// The generation of the most recent one-shot handler whose callback has returned.
+    fired_generation: u64,
+    /// The waker of the task waiting for the pending one-shot handler, if any.
+    waker: Option<Waker>,
+}
+
+static STATE: IrqSafeMutex<State> = IrqSafeMutex::new(State {
+    pending: None,
+    generation: 0,
+    fired_generation: 0,
+    waker: None,
+});
+
+/// The vector of the pending one-shot handler, or of the last one if none is pending,
+/// which the trampoline uses to send its EOI.
+static VECTOR: AtomicU8 = AtomicU8::new(0);
+
+/// Handles the next occurrence of the interrupt at the given `vector` by invoking `callback`
+/// instead of the vector's current handler, which is restored before `callback` is invoked.
+///
+/// The vector's current handler doesn't see that occurrence of the interrupt,
+/// so `callback` must acknowledge the interrupt at its device if that's needed.
+/// The EOI is sent after `callback` returns.
+/// The caller is responsible for routing the interrupt to `vector` if no handler is registered for it.
+///
+/// Returns a [`OneShot`] that can be awaited or polled via [`OneShot::has_fired()`],
+/// or an error if `vector` is reserved or another one-shot handler is already pending.
+pub fn oneshot<F>(vector: u8, callback: F) -> Result<OneShot, &'static str>
+where
+    F: FnOnce(&InterruptStackFrame) + Send + 'static,
+{
+    if vector < IRQ_BASE_OFFSET {
+        return Err("oneshot: cannot replace the handler for a CPU exception vector");
+    }
+    if RESERVED_IRQ_LIST.contains(&vector) {
+        return Err("oneshot: cannot replace the handler for a reserved interrupt vector");
+    }
+    let callback = Box::new(callback);
+
+    let mut state = STATE.lock();
+    if state.pending.is_some() {
+        return Err("oneshot: another one-shot handler is already pending");
+    }
+    VECTOR.store(vector, Ordering::Relaxed);
+    let previous_handler_addr = {
+        let mut idt = IDT.lock();
+        let previous_handler_addr = idt[vector as usize].handler_addr().as_u64() as usize;
+        idt[vector as usize].set_handler_fn(oneshot_trampoline);
+        previous_handler_addr
+    };
+    // As with `register_interrupt()`, a PIC line starts out masked until it has a handler.
+    if INTERRUPT_CHIP.load() == InterruptChip::PIC && is_unregistered(vector, previous_handler_addr) {
+        if let Some(pic) = PIC.get() {
+            pic.set_irq_masked(vector, false);
+        }
+    }
+
+    state.generation += 1;
+    state.pending = Some(Pending { vector, previous_handler_addr, callback });
+    state.waker = None;
+    Ok(OneShot { generation: state.generation })
+}
+
+/// Restores the handler that a one-shot handler replaced at the given `vector`.
+fn restore_handler(vector: u8, previous_handler_addr: usize) {
+    let mut idt = IDT.lock();
+    if is_unregistered(vector, previous_handler_addr) {
+        idt[vector as usize].set_handler_fn(default_handler(vector));
+        if INTERRUPT_CHIP.load() == InterruptChip::PIC {
+            if let Some(pic) = PIC.get() {
+                pic.set_irq_masked(vector, true);
+            }
+        }
+    } else {
+        // SAFE: this was the address of a valid handler for this vector until it was replaced.
+        unsafe { idt[vector as usize].set_handler_addr(VirtAddr::new(previous_handler_addr as u64)); }
+    }
+}
+
+crate::interrupt_handler!(oneshot_trampoline, VECTOR.load(Ordering::Relaxed), stack_frame, {
+    let mut state = STATE.lock();
+    // If the one-shot handler was removed after this interrupt had already been dispatched to it,
+    // there is nothing to do but send the EOI.
+    if let Some(Pending { vector, previous_handler_addr, callback }) = state.pending.take() {
+        let generation = state.generation;
+        restore_handler(vector, previous_handler_addr);
+        // The callback may install another one-shot handler, so it must not run with the lock held.
+        drop(state);
+
+        callback(stack_frame);
+
+        let mut state = STATE.lock();
+        state.fired_generation = generation;
+        let waker = state.waker.take();
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+    EoiBehaviour::HandlerDidNotSendEoi
+});
+
+/// A one-shot interrupt handler installed by [`oneshot()`].
+///
+/// As a future, this completes once the interrupt has occurred and the one-shot callback has returned.
+/// Dropping it before the interrupt occurs removes the one-shot handler
+/// and restores the vector's previous handler.
+#[must_use = "dropping a `OneShot` removes its one-shot handler"]
+#[derive(Debug)]
+pub struct OneShot {
+    generation: u64,
+}
+
+impl OneShot {
+    /// Returns whether the interrupt has occurred and the one-shot callback has returned.
+    pub fn has_fired(&self) -> bool {
+        STATE.lock().fired_generation >= self.generation
+    }
+}
+
+impl Future for OneShot {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = STATE.lock();
+        if state.fired_generation >= self.generation {
+            Poll::Ready(())
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for OneShot {
+    fn drop(&mut self) {
+        let mut state = STATE.lock();
+        if state.generation != self.generation {
+            return;
+        }
+        state.waker = None;
+        if let Some(Pending { vector, previous_handler_addr, .. }) = state.pending.take() {
+            restore_handler(vector, previous_handler_addr);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Handling of spurious interrupts from the legacy PIC.
+//!
+//! When an IRQ line is deasserted or masked after the PIC raised it but before the CPU
+//! acknowledged it, the PIC delivers its lowest-priority line instead:
+//! IRQ 7 for the master PIC and IRQ 15 for the slave PIC.
+//! This happens a lot on Bochs and on some real hardware, but rarely on QEMU.
+//! Both lines can also be raised by real devices, e.g., a parallel port or the secondary ATA bus,
+//! so their handlers are registered like any other, and [`is_spurious()`] tells the two cases apart
+//! by checking whether the PIC actually marked the line as in service.
+//! See <https://wiki.osdev.org/8259_PIC#Spurious_IRQs>.
+//!
+//! Handlers defined with [`interrupt_handler!`](crate::interrupt_handler) perform this check
+//! automatically if they pass their IRQ number as the `$x86_64_eoi_param`.
+//! When no handler is registered for IRQ 7 or IRQ 15, a default handler performs it instead.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use apic::{INTERRUPT_CHIP, InterruptChip};
+use log::warn;
+use pic::{PIC_SPURIOUS_INTERRUPT_IRQ, PIC_SPURIOUS_SLAVE_INTERRUPT_IRQ};
+use crate::{interrupt_handler, EoiBehaviour, InterruptHandler, InterruptNumber};
+use super::PIC;
+
+/// The number of spurious interrupts from the master PIC (IRQ 7).
+static SPURIOUS_MASTER: AtomicU64 = AtomicU64::new(0);
+/// The number of spurious interrupts from the slave PIC (IRQ 15).
+static SPURIOUS_SLAVE: AtomicU64 = AtomicU64::new(0);
+/// The number of real (non-spurious) IRQ 7 interrupts in PIC mode.
+static REAL_IRQ7: AtomicU64 = AtomicU64::new(0);
+
+/// The number of interrupts on each of the PIC's spurious-capable lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpuriousCounts {
+    /// Spurious interrupts from the master PIC (IRQ 7).
+    pub master: u64,
+    /// Spurious interrupts from the slave PIC (IRQ 15).
+    pub slave: u64,
+    /// Real IRQ 7 interrupts, e.g., from a parallel port.
+    pub real_irq7: u64,
+}
+
+/// Returns the number of spurious and real interrupts counted since boot.
+pub fn spurious_counts() -> SpuriousCounts {
+    SpuriousCounts {
+        master: SPURIOUS_MASTER.load(Ordering::Relaxed),
+        slave: SPURIOUS_SLAVE.load(Ordering::Relaxed),
+        real_irq7: REAL_IRQ7.load(Ordering::Relaxed),
+    }
+}
+
+/// Returns whether the interrupt at the given `irq` is a spurious interrupt from the PIC,
+/// in which case it has already been fully handled and the caller must return immediately
+/// without sending an EOI.
+///
+/// This is always `false` unless the system is in PIC mode and `irq` is IRQ 7 or IRQ 15.
+/// * A spurious IRQ 7 must not be acknowledged at all.
+/// * A spurious IRQ 15 must be acknowledged only at the master PIC,
+///   which this function does via [`pic::ChainedPics::notify_end_of_spurious_slave_interrupt()`].
+///
+/// This must be invoked at the start of any handler for IRQ 7 or IRQ 15
+/// that isn't defined with [`interrupt_handler!`](crate::interrupt_handler).
+#[inline(always)]
+pub fn is_spurious(irq: InterruptNumber) -> bool {
+    if irq != PIC_SPURIOUS_INTERRUPT_IRQ && irq != PIC_SPURIOUS_SLAVE_INTERRUPT_IRQ {
+        return false;
+    }
+    check_spurious(irq)
+}
+
+#[inline(never)]
+fn check_spurious(irq: InterruptNumber) -> bool {
+    if INTERRUPT_CHIP.load() != InterruptChip::PIC {
+        return false;
+    }
+    let Some(pic) = PIC.get() else { return false };
+
+    match (irq, pic.is_in_service(irq)) {
+        (PIC_SPURIOUS_INTERRUPT_IRQ, true) => {
+            REAL_IRQ7.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+        (PIC_SPURIOUS_INTERRUPT_IRQ, false) => {
+            SPURIOUS_MASTER.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+        (_, true) => false,
+        (_, false) => {
+            SPURIOUS_SLAVE.fetch_add(1, Ordering::Relaxed);
+            pic.notify_end_of_spurious_slave_interrupt();
+            true
+        }
+    }
+}
+
+/// Returns the default handler for the given `vector` if it can receive spurious PIC interrupts.
+///
+/// This handler is installed whenever no other handler is registered for that vector.
+pub(crate) fn default_handler(vector: u8) -> Option<InterruptHandler> {
+    match vector {
+        PIC_SPURIOUS_INTERRUPT_IRQ => Some(default_irq7_handler),
+        PIC_SPURIOUS_SLAVE_INTERRUPT_IRQ => Some(default_irq15_handler),
+        _ => None,
+    }
+}
+
+/// Registers the spurious interrupt counters, as reported in [`metrics::snapshot()`].
+pub(crate) fn register_metrics() -> Result<(), &'static str> {
+    metrics::register_counter("irq.spurious_master", &SPURIOUS_MASTER)?;
+    metrics::register_counter("irq.spurious_slave", &SPURIOUS_SLAVE)?;
+    metrics::register_counter("irq.real_irq7", &REAL_IRQ7)
+}
+
+interrupt_handler!(default_irq7_handler, PIC_SPURIOUS_INTERRUPT_IRQ, _stack_frame, {
+    warn!("Got real IRQ7 with no registered handler, e.g., from a parallel port");
+    EoiBehaviour::HandlerDidNotSendEoi
+});
+
+interrupt_handler!(default_irq15_handler, PIC_SPURIOUS_SLAVE_INTERRUPT_IRQ, _stack_frame, {
+    warn!("Got real IRQ15 with no registered handler");
+    EoiBehaviour::HandlerDidNotSendEoi
+});
@@ -0,0 +1,240 @@
+//! Detection of interrupt storms, in which a single interrupt vector fires
+//! so often that the system can no longer make forward progress.
+//!
+//! Arrivals are counted per vector in [`eoi()`](super::eoi), which costs
+//! a single relaxed atomic increment per interrupt.
+//! Once per [`CONFIG_IRQ_STORM_WINDOW`], the bootstrap CPU's timer tick
+//! calls [`tick()`], which compares each vector's arrival count in that window
+//! against the current threshold using only integer arithmetic.
+//!
+//! A vector that exceeds the threshold for [`CONFIG_IRQ_STORM_SUSTAIN`] is masked
+//! at the IOAPIC or PIC, and the callback registered for it via
+//! [`register_storm_callback()`] is invoked such that its driver can reset the device.
+//! Vectors that aren't routed through the IOAPIC or PIC, e.g., MSI vectors,
+//! can only be silenced by that callback.
+//! Once a driver has recovered, the vector can be re-enabled with [`unmask_storm_vector()`].
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use apic::{INTERRUPT_CHIP, InterruptChip};
+use kernel_config::time::{
+    CONFIG_IRQ_STORM_SUSTAIN, CONFIG_IRQ_STORM_THRESHOLD_PER_SEC, CONFIG_IRQ_STORM_WINDOW,
+};
+use log::{error, warn};
+use sync_irq::IrqSafeMutex;
+use super::{IDT, PIC, RESERVED_IRQ_LIST};
+
+/// The number of timer ticks in each measurement window.
+///
+/// This depends on the timeslice period, which can be overridden at boot.
+fn window_ticks() -> u64 {
+    kernel_config::time::timeslice_ticks_in(CONFIG_IRQ_STORM_WINDOW, boot_args::timeslice_period())
+}
+/// The actual duration of each measurement window, in microseconds.
+fn window_micros() -> u64 {
+    window_ticks() * boot_args::timeslice_period().as_micros() as u64
+}
+/// The number of consecutive windows a vector must exceed the threshold in.
+fn sustain_windows() -> u8 {
+    let window_micros = window_micros();
+    let windows = (CONFIG_IRQ_STORM_SUSTAIN.as_micros() as u64 + window_micros - 1) / window_micros;
+    if windows == 0 { 1 } else if windows > u8::MAX as u64 { u8::MAX } else { windows as u8 }
+}
+
+/// The number of arrivals of each interrupt vector in the current window.
+static ARRIVALS: [AtomicU32; 256] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU32 = AtomicU32::new(0);
+    [ZERO; 256]
+};
+/// The number of consecutive windows in which each vector has exceeded the threshold.
+static WINDOWS_OVER_THRESHOLD: [AtomicU8; 256] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU8 = AtomicU8::new(0);
+    [ZERO; 256]
+};
+/// Whether each vector has been masked due to a storm.
+static MASKED: [AtomicBool; 256] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FALSE: AtomicBool = AtomicBool::new(false);
+    [FALSE; 256]
+};
+/// The total number of interrupt arrivals of all vectors since boot.
+static TOTAL_ARRIVALS: AtomicU64 = AtomicU64::new(0);
+/// The number of timer ticks counted on the bootstrap CPU.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// The value of [`TICKS`] at which the current window started.
+static WINDOW_START: AtomicU64 = AtomicU64::new(0);
+/// The current storm threshold, in interrupts per second.
+static THRESHOLD_PER_SEC: AtomicU32 = AtomicU32::new(CONFIG_IRQ_STORM_THRESHOLD_PER_SEC);
+
+/// The function signature of a storm callback, which receives the storming vector.
+///
+/// This is invoked from within the timer interrupt handler,
+/// so it must not block and should do as little work as possible.
+pub type StormCallback = fn(vector: u8);
+
+#[derive(Clone, Copy)]
+struct StormOwner {
+    name: &'static str,
+    callback: StormCallback,
+}
+
+/// The owner and storm callback registered for each vector.
+static OWNERS: IrqSafeMutex<[Option<StormOwner>; 256]> = IrqSafeMutex::new([None; 256]);
+
+
+/// Registers the given `callback` to be invoked if the given `vector` storms.
+///
+/// The `owner` name is included in the storm warning, and is typically the driver's name.
+/// Any existing callback for this vector is replaced.
+pub fn register_storm_callback(vector: u8, owner: &'static str, callback: StormCallback) {
+    OWNERS.lock()[vector as usize] = Some(StormOwner { name: owner, callback });
+}
+
+/// Removes the storm callback registered for the given `vector`, if any.
+pub fn deregister_storm_callback(vector: u8) {
+    OWNERS.lock()[vector as usize] = None;
+}
+
+/// Returns the current storm threshold, in interrupts per second.
+pub fn storm_threshold() -> u32 {
+    THRESHOLD_PER_SEC.load(Ordering::Relaxed)
+}
+
+/// Sets the storm threshold, in interrupts per second.
+///
+/// A threshold of `0` disables storm detection.
+pub fn set_storm_threshold(per_sec: u32) {
+    THRESHOLD_PER_SEC.store(per_sec, Ordering::Relaxed);
+}
+
+/// Returns an iterator over all vectors that are currently masked due to a storm.
+pub fn masked_storm_vectors() -> impl Iterator<Item = u8> {
+    (0..=u8::MAX).filter(|v| MASKED[*v as usize].load(Ordering::Relaxed))
+}
+
+/// Counts one arrival of the given interrupt `vector`.
+#[inline(always)]
+pub(crate) fn record_arrival(vector: u8) {
+    ARRIVALS[vector as usize].fetch_add(1, Ordering::Relaxed);
+    TOTAL_ARRIVALS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Registers the storm detector's metrics, as reported in [`metrics::snapshot()`].
+pub(crate) fn register_metrics() -> Result<(), &'static str> {
+    metrics::register_counter("irq.arrivals", &TOTAL_ARRIVALS)?;
+    metrics::register_gauge("irq.storm_masked", || masked_storm_vectors().count() as u64)
+}
+
+/// Advances storm detection by one timer tick.
+///
+/// This must be called from the CPU-local timer interrupt handler;
+/// only the bootstrap CPU's ticks are counted, so it is harmless to call on all CPUs.
+pub fn tick() {
+    if !cpu::is_bootstrap_cpu() {
+        return;
+    }
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+    // This remains correct even if the tick count wraps around.
+    if ticks.wrapping_sub(WINDOW_START.load(Ordering::Relaxed)) < window_ticks() {
+        return;
+    }
+    WINDOW_START.store(ticks, Ordering::Relaxed);
+
+    let threshold = storm_threshold();
+    let window_micros = window_micros();
+    let sustain_windows = sustain_windows();
+    // Scale the per-second threshold to a per-window arrival count.
+    let max_per_window = (threshold as u64 * window_micros / 1_000_000) as u32;
+
+    for vector in 0..=u8::MAX {
+        let arrivals = ARRIVALS[vector as usize].swap(0, Ordering::Relaxed);
+        let over = &WINDOWS_OVER_THRESHOLD[vector as usize];
+        if threshold == 0
+            || arrivals <= max_per_window
+            || RESERVED_IRQ_LIST.contains(&vector)
+            || MASKED[vector as usize].load(Ordering::Relaxed)
+        {
+            over.store(0, Ordering::Relaxed);
+            continue;
+        }
+        let windows = over.load(Ordering::Relaxed) + 1;
+        if windows < sustain_windows {
+            over.store(windows, Ordering::Relaxed);
+            continue;
+        }
+        over.store(0, Ordering::Relaxed);
+        let rate = arrivals as u64 * 1_000_000 / window_micros;
+        handle_storm(vector, rate);
+    }
+}
+
+/// Reports, masks, and notifies the owner of the given storming `vector`.
+fn handle_storm(vector: u8, rate: u64) {
+    let owner = OWNERS.lock()[vector as usize];
+    let handler_addr = IDT.lock()[vector as usize].handler_addr();
+    warn!("!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!");
+    warn!("INTERRUPT STORM on vector {:#X}: {} interrupts/sec (threshold {}/sec)",
+        vector, rate, storm_threshold(),
+    );
+    warn!("    owner: {}, handler: {:#X}",
+        owner.map_or("<unknown>", |o| o.name), handler_addr,
+    );
+
+    if set_vector_masked(vector, true) {
+        warn!("    masked vector {:#X} at the interrupt controller.", vector);
+        MASKED[vector as usize].store(true, Ordering::Relaxed);
+    } else if owner.is_some() {
+        warn!("    vector {:#X} isn't routed through the IOAPIC or PIC; relying on its owner to disable it.", vector);
+        MASKED[vector as usize].store(true, Ordering::Relaxed);
+    } else {
+        error!("    couldn't mask vector {:#X}, and it has no storm callback to disable it!", vector);
+    }
+    warn!("!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!");
+
+    if let Some(o) = owner {
+        (o.callback)(vector);
+    }
+}
+
+/// Unmasks the given `vector` that was previously masked due to an interrupt storm,
+/// which should only be done once its driver has recovered the device.
+pub fn unmask_storm_vector(vector: u8) -> Result<(), &'static str> {
+    if !MASKED[vector as usize].swap(false, Ordering::Relaxed) {
+        return Err("that vector was not masked due to an interrupt storm");
+    }
+    ARRIVALS[vector as usize].store(0, Ordering::Relaxed);
+    WINDOWS_OVER_THRESHOLD[vector as usize].store(0, Ordering::Relaxed);
+    set_vector_masked(vector, false);
+    Ok(())
+}
+
+/// Masks or unmasks the interrupt line that delivers the given `vector`.
+///
+/// Returns `false` if `vector` isn't delivered by the IOAPIC or PIC.
+fn set_vector_masked(vector: u8, masked: bool) -> bool {
+    match INTERRUPT_CHIP.load() {
+        InterruptChip::APIC | InterruptChip::X2APIC => {
+            for (_id, ioapic) in ioapic::get_ioapics() {
+                // This runs in the timer interrupt handler, so we must not spin
+                // on a lock that the interrupted task may already hold.
+                let Some(mut ioapic) = ioapic.try_lock() else { continue };
+                if let Some(irq) = ioapic.find_irq_for_vector(vector) {
+                    // `irq` was just found on this IoApic, so it's always a valid line.
+                    let _ = if masked { ioapic.mask_irq(irq) } else { ioapic.unmask_irq(irq) };
+                    return true;
+                }
+            }
+            false
+        }
+        InterruptChip::PIC => {
+            match PIC.get() {
+                Some(pic) if (pic::IRQ_BASE_OFFSET .. pic::IRQ_BASE_OFFSET + 16).contains(&vector) => {
+                    pic.set_irq_masked(vector, masked);
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Accounting of the "system time" that each CPU spends handling interrupts,
+//! either in interrupt handlers themselves or in deferred interrupt tasks,
+//! and a cap on the share of each CPU that deferred interrupt tasks may use.
+//!
+//! Time in interrupt handlers is measured from entry to an
+//! [`interrupt_handler!`](crate::interrupt_handler) until its [`eoi()`](super::eoi).
+//! Time in deferred interrupt tasks is reported by those tasks via [`account_deferred_work()`],
+//! and includes any interrupts or preemptions that occurred while the deferred work ran.
+//! All times are measured in TSC cycles, so they can be compared without knowing the TSC frequency.
+//!
+//! Once per [`CONFIG_SYSTEM_TIME_WINDOW`], each CPU's timer tick calls [`tick()`],
+//! which converts that CPU's totals into the shares reported by [`system_time()`].
+//!
+//! If deferred interrupt tasks use more than [`deferred_work_cap()`] percent of a CPU
+//! in its current window, [`deferred_work_over_cap()`] returns `true`,
+//! upon which a deferred task should yield its CPU before handling more work,
+//! such that ordinary tasks on that CPU keep making progress during an interrupt flood.
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use cpu::CpuId;
+use kernel_config::time::{CONFIG_DEFERRED_WORK_CAP_PERCENT, CONFIG_SYSTEM_TIME_WINDOW};
+
+/// The maximum number of CPUs whose system time can be tracked.
+const MAX_TRACKED_CPUS: usize = 256;
+
+/// The number of timer ticks in each measurement window.
+///
+/// This depends on the timeslice period, which can be overridden at boot.
+fn window_ticks() -> u64 {
+    kernel_config::time::timeslice_ticks_in(CONFIG_SYSTEM_TIME_WINDOW, boot_args::timeslice_period())
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO_U64: AtomicU64 = AtomicU64::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO_U32: AtomicU32 = AtomicU32::new(0);
+
+/// The TSC value upon entry to the current interrupt handler on each CPU,
+/// or `0` if no interrupt handler that records its entry is running.
+static IRQ_ENTRY: [AtomicU64; MAX_TRACKED_CPUS] = [ZERO_U64; MAX_TRACKED_CPUS];
+/// The cycles spent in interrupt handlers on each CPU in its current window.
+static IRQ_CYCLES: [AtomicU64; MAX_TRACKED_CPUS] = [ZERO_U64; MAX_TRACKED_CPUS];
+/// The cycles spent in deferred interrupt tasks on each CPU in its current window.
+static DEFERRED_CYCLES: [AtomicU64; MAX_TRACKED_CPUS] = [ZERO_U64; MAX_TRACKED_CPUS];
+/// The TSC value at which each CPU's current window started, or `0` if it hasn't started yet.
+static WINDOW_START: [AtomicU64; MAX_TRACKED_CPUS] = [ZERO_U64; MAX_TRACKED_CPUS];
+/// The number of timer ticks counted on each CPU in its current window.
+static WINDOW_TICKS_SEEN: [AtomicU64; MAX_TRACKED_CPUS] = [ZERO_U64; MAX_TRACKED_CPUS];
+/// The share of each CPU spent in interrupt handlers in its last complete window, in permille.
+static LAST_IRQ_PERMILLE: [AtomicU32; MAX_TRACKED_CPUS] = [ZERO_U32; MAX_TRACKED_CPUS];
+/// The share of each CPU spent in deferred interrupt tasks in its last complete window, in permille.
+static LAST_DEFERRED_PERMILLE: [AtomicU32; MAX_TRACKED_CPUS] = [ZERO_U32; MAX_TRACKED_CPUS];
+/// The number of times that deferred interrupt tasks on each CPU yielded because they were over the cap.
+static THROTTLES: [AtomicU64; MAX_TRACKED_CPUS] = [ZERO_U64; MAX_TRACKED_CPUS];
+/// The current cap on the share of a CPU that deferred interrupt tasks may use, in percent.
+static DEFERRED_WORK_CAP: AtomicU32 = AtomicU32::new(CONFIG_DEFERRED_WORK_CAP_PERCENT);
+
+
+/// The shares of a CPU's time spent handling interrupts in its last complete measurement window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SystemTime {
+    /// The share of time spent in interrupt handlers, in permille (tenths of a percent).
+    pub irq_permille: u32,
+    /// The share of time spent in deferred interrupt tasks, in permille (tenths of a percent).
+    pub deferred_permille: u32,
+    /// The number of times that deferred interrupt tasks on this CPU yielded
+    /// because they were over the [`deferred_work_cap()`], since boot.
+    pub throttles: u64,
+}
+
+/// Returns the system time of the given CPU over its last complete measurement window,
+/// or `None` if that CPU isn't tracked.
+pub fn system_time(cpu: CpuId) -> Option<SystemTime> {
+    let cpu = cpu.value() as usize;
+    Some(SystemTime {
+        irq_permille: LAST_IRQ_PERMILLE.get(cpu)?.load(Ordering::Relaxed),
+        deferred_permille: LAST_DEFERRED_PERMILLE.get(cpu)?.load(Ordering::Relaxed),
+        throttles: THROTTLES.get(cpu)?.load(Ordering::Relaxed),
+    })
+}
+
+/// Returns the current cap on the share of a CPU that deferred interrupt tasks may use, in percent.
+///
+/// A cap of `0` means that deferred interrupt tasks are never throttled.
+pub fn deferred_work_cap() -> u32 {
+    DEFERRED_WORK_CAP.load(Ordering::Relaxed)
+}
+
+/// Sets the cap on the share of a CPU that deferred interrupt tasks may use, in percent.
+///
+/// A cap of `0` disables throttling of deferred interrupt tasks.
+pub fn set_deferred_work_cap(percent: u32) -> Result<(), &'static str> {
+    if percent > 100 {
+        return Err("the deferred work cap must be a percentage from 0 to 100");
+    }
+    DEFERRED_WORK_CAP.store(percent, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Records that an interrupt handler has been entered on the current CPU.
+///
+/// This is invoked by [`interrupt_handler!`](crate::interrupt_handler);
+/// handlers that don't use that macro can opt in to being accounted by calling this.
+#[inline(always)]
+pub fn on_irq_entry() {
+    if let Some(entry) = IRQ_ENTRY.get(cpu::current_cpu().value() as usize) {
+        entry.store(read_tsc(), Ordering::Relaxed);
+    }
+}
+
+/// Accounts the time since [`on_irq_entry()`] on the current CPU, if it was invoked.
+#[inline(always)]
+pub(crate) fn on_irq_exit() {
+    let cpu = cpu::current_cpu().value() as usize;
+    let (Some(entry), Some(cycles)) = (IRQ_ENTRY.get(cpu), IRQ_CYCLES.get(cpu)) else {
+        return;
+    };
+    let start = entry.swap(0, Ordering::Relaxed);
+    if start != 0 {
+        cycles.fetch_add(read_tsc().saturating_sub(start), Ordering::Relaxed);
+    }
+}
+
+/// Runs the given deferred interrupt work and accounts the time it took
+/// to the CPU that it finished on.
+pub fn account_deferred_work<R>(work: impl FnOnce() -> R) -> R {
+    let start = read_tsc();
+    let result = work();
+    let elapsed = read_tsc().saturating_sub(start);
+    if let Some(cycles) = DEFERRED_CYCLES.get(cpu::current_cpu().value() as usize) {
+        cycles.fetch_add(elapsed, Ordering::Relaxed);
+    }
+    result
+}
+
+/// Returns whether deferred interrupt tasks have exceeded the [`deferred_work_cap()`]
+/// so far in the current CPU's current measurement window.
+///
+/// If so, this also counts a throttling event for this CPU, as the caller is expected
+/// to yield its CPU before handling more deferred work.
+pub fn deferred_work_over_cap() -> bool {
+    let cap = deferred_work_cap() as u64;
+    let cpu = cpu::current_cpu().value() as usize;
+    let (Some(start), Some(cycles), Some(throttles)) =
+        (WINDOW_START.get(cpu), DEFERRED_CYCLES.get(cpu), THROTTLES.get(cpu))
+    else {
+        return false;
+    };
+    let start = start.load(Ordering::Relaxed);
+    if cap == 0 || start == 0 {
+        return false;
+    }
+    let elapsed = read_tsc().saturating_sub(start);
+    let over = cycles.load(Ordering::Relaxed).saturating_mul(100) > elapsed.saturating_mul(cap);
+    if over {
+        throttles.fetch_add(1, Ordering::Relaxed);
+    }
+    over
+}
+
+/// Advances the current CPU's system time measurement by one timer tick.
+///
+/// This must be called from the CPU-local timer interrupt handler on every CPU.
+pub fn tick() {
+    let cpu = cpu::current_cpu().value() as usize;
+    let (Some(start), Some(ticks)) = (WINDOW_START.get(cpu), WINDOW_TICKS_SEEN.get(cpu)) else {
+        return;
+    };
+    let now = read_tsc();
+    let window_start = start.load(Ordering::Relaxed);
+    if window_start == 0 {
+        start.store(now, Ordering::Relaxed);
+        return;
+    }
+    if ticks.fetch_add(1, Ordering::Relaxed) + 1 < window_ticks() {
+        return;
+    }
+    ticks.store(0, Ordering::Relaxed);
+    start.store(now, Ordering::Relaxed);
+
+    let elapsed = now.saturating_sub(window_start).max(1);
+    let permille = |cycles: u64| (cycles.saturating_mul(1000) / elapsed).min(1000) as u32;
+    let irq = IRQ_CYCLES[cpu].swap(0, Ordering::Relaxed);
+    let deferred = DEFERRED_CYCLES[cpu].swap(0, Ordering::Relaxed);
+    LAST_IRQ_PERMILLE[cpu].store(permille(irq), Ordering::Relaxed);
+    LAST_DEFERRED_PERMILLE[cpu].store(permille(deferred), Ordering::Relaxed);
+}
+
+#[inline(always)]
+fn read_tsc() -> u64 {
+    // SAFETY: `rdtsc` has no side effects and is available on all x86_64 CPUs.
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
@@ -63,6 +63,10 @@ pub enum IoError {
     InvalidInput,
     /// The I/O operation timed out and was canceled.
     TimedOut,
+    /// The device was removed from the system, so it can no longer perform I/O.
+    DeviceRemoved,
+    /// The I/O operation was canceled by its submitter before it completed.
+    Cancelled,
     /// A miscellaneous error occurred.
     Other(&'static str),
 }
@@ -73,6 +77,8 @@ impl From<IoError> for core2::io::Error {
         match io_error {
             IoError::InvalidInput => ErrorKind::InvalidInput.into(),
             IoError::TimedOut     => ErrorKind::TimedOut.into(),
+            IoError::DeviceRemoved => ErrorKind::NotConnected.into(),
+            IoError::Cancelled    => ErrorKind::Interrupted.into(),
             IoError::Other(_)     => ErrorKind::Other.into(),
         }
     }
@@ -96,6 +102,8 @@ impl From<IoError> for &'static str {
         match io_error {
             IoError::InvalidInput => "invalid input",
             IoError::TimedOut     => "timed out",
+            IoError::DeviceRemoved => "device was removed",
+            IoError::Cancelled    => "cancelled",
             IoError::Other(s)     => s,
         }
     }
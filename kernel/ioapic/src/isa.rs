@@ -0,0 +1,107 @@
+//! Translation of legacy ISA IRQ numbers to IoApic interrupt lines.
+//!
+//! Drivers for legacy devices (PIT, PS/2 keyboard, RTC, ATA, etc.) know their
+//! interrupt by its ISA IRQ number, which is identical to its global system interrupt (GSI)
+//! and uses the ISA default of active-high, edge-triggered signaling,
+//! *unless* the ACPI MADT contains an interrupt source override for that IRQ.
+//! For example, many chipsets connect ISA IRQ 0 (the PIT) to GSI 2,
+//! and some use active-low or level-triggered signaling for certain IRQs.
+//!
+//! The MADT parser records each override via [`set_isa_irq_override()`];
+//! drivers then route their interrupts via [`route_isa_irq()`], which applies the translation.
+//! If ACPI is unavailable, no overrides are recorded and the identity translation is used.
+
+use log::{info, warn};
+use spin::Mutex;
+use apic::ApicId;
+use crate::{Polarity, TriggerMode, get_ioapic_for_gsi, set_gsi_with_mode};
+
+/// The number of legacy ISA IRQs, i.e., those of the two chained 8259 PICs.
+pub const NUM_ISA_IRQS: u8 = 16;
+
+/// How a legacy ISA IRQ is connected to the IoApics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IsaIrqRoute {
+    /// The global system interrupt that this ISA IRQ is connected to.
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+    /// Whether this route came from an ACPI interrupt source override,
+    /// as opposed to the default identity translation.
+    pub overridden: bool,
+}
+
+impl IsaIrqRoute {
+    /// The default route of the given ISA IRQ, used if no override exists for it.
+    const fn identity(isa_irq: u8) -> IsaIrqRoute {
+        IsaIrqRoute {
+            gsi: isa_irq as u32,
+            polarity: Polarity::ActiveHigh,
+            trigger: TriggerMode::Edge,
+            overridden: false,
+        }
+    }
+}
+
+/// The translation table from each ISA IRQ number to its route.
+static ISA_IRQ_ROUTES: Mutex<[IsaIrqRoute; NUM_ISA_IRQS as usize]> = Mutex::new({
+    let mut routes = [IsaIrqRoute::identity(0); NUM_ISA_IRQS as usize];
+    let mut irq = 0;
+    while irq < NUM_ISA_IRQS {
+        routes[irq as usize] = IsaIrqRoute::identity(irq);
+        irq += 1;
+    }
+    routes
+});
+
+/// Records that the given ISA IRQ is connected to `gsi` with the given polarity and trigger mode,
+/// e.g., as specified by an interrupt source override entry in the ACPI MADT.
+///
+/// Each distinct override is logged once, when it is first recorded.
+pub fn set_isa_irq_override(
+    isa_irq: u8,
+    gsi: u32,
+    polarity: Polarity,
+    trigger: TriggerMode,
+) -> Result<(), &'static str> {
+    let mut routes = ISA_IRQ_ROUTES.lock();
+    let route = routes.get_mut(isa_irq as usize)
+        .ok_or("set_isa_irq_override(): ISA IRQ number must be less than 16")?;
+    let new_route = IsaIrqRoute { gsi, polarity, trigger, overridden: true };
+    if *route != new_route {
+        info!("Applying interrupt source override: ISA IRQ {} -> GSI {} ({:?}, {:?})",
+            isa_irq, gsi, polarity, trigger,
+        );
+        *route = new_route;
+    }
+    Ok(())
+}
+
+/// Returns the route of the given ISA IRQ, or `None` if it isn't a valid ISA IRQ number.
+pub fn isa_irq_route(isa_irq: u8) -> Option<IsaIrqRoute> {
+    ISA_IRQ_ROUTES.lock().get(isa_irq as usize).copied()
+}
+
+/// Returns the routes of all ISA IRQs, indexed by ISA IRQ number.
+pub fn isa_irq_routes() -> [IsaIrqRoute; NUM_ISA_IRQS as usize] {
+    *ISA_IRQ_ROUTES.lock()
+}
+
+/// Programs the IoApic redirection entry for the given ISA IRQ
+/// such that it is delivered as `vector` to the CPU with the given `apic_id`.
+///
+/// This applies the ISA IRQ's route: it programs the line for its GSI,
+/// which may differ from the ISA IRQ number, with its polarity and trigger mode,
+/// on whichever IoApic handles that GSI.
+///
+/// Returns an error if no IoApic handles the ISA IRQ's GSI,
+/// e.g., if the system uses the legacy PIC instead of IoApics.
+pub fn route_isa_irq(isa_irq: u8, apic_id: ApicId, vector: u8) -> Result<(), &'static str> {
+    let route = isa_irq_route(isa_irq)
+        .ok_or("route_isa_irq(): ISA IRQ number must be less than 16")?;
+    if get_ioapic_for_gsi(route.gsi).is_none() {
+        warn!("route_isa_irq(): no IoApic handles GSI {} for ISA IRQ {}", route.gsi, isa_irq);
+        return Err("route_isa_irq(): no IoApic handles the ISA IRQ's GSI");
+    }
+    set_gsi_with_mode(route.gsi, apic_id, vector, route.polarity, route.trigger)
+}
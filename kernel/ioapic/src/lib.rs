@@ -1,13 +1,25 @@
+//! Support for IoApics, the x86 interrupt chips that route I/O device interrupts to CPUs.
+//!
+//! A system may have multiple IoApics, as declared in the ACPI MADT,
+//! each of which handles a contiguous range of global system interrupts (GSIs)
+//! starting at its GSI base, with as many lines as its version register reports.
+//! The `*_gsi()` functions route each request to the IoApic that handles the given GSI,
+//! whereas the methods of [`IoApic`] take a line (pin) number local to that IoApic.
+
 #![no_std]
 
-use log::debug;
+use core::ops::Range;
+use log::{debug, error};
 use spin::Mutex;
 use volatile::{Volatile, WriteOnly};
 use zerocopy::FromBytes;
-use memory::{PageTable, PhysicalAddress, PteFlags, allocate_pages, allocate_frames_at, BorrowedMappedPages, Mutable};
+use memory::{PageTable, PhysicalAddress, PteFlags, PteFlagsArch, MemoryType, allocate_pages, allocate_frames_at, BorrowedMappedPages, Mutable};
 use atomic_linked_list::atomic_map::{AtomicMap, AtomicMapIter};
 use apic::ApicId;
 
+mod isa;
+pub use isa::{IsaIrqRoute, NUM_ISA_IRQS, isa_irq_route, isa_irq_routes, route_isa_irq, set_isa_irq_override};
+
 
 /// The system-wide list of all `IoApic`s, of which there is usually one, 
 /// but larger systems can have multiple IoApic chips.
@@ -25,6 +37,93 @@ pub fn get_ioapic(ioapic_id: u8) -> Option<&'static Mutex<IoApic>> {
 	IOAPICS.get(&ioapic_id)
 }
 
+/// Returns the `IoApic` that handles the given global system interrupt (GSI), if any.
+pub fn get_ioapic_for_gsi(gsi: u32) -> Option<&'static Mutex<IoApic>> {
+	get_ioapics()
+		.map(|(_id, ioapic)| ioapic)
+		.find(|ioapic| ioapic.lock().handles_irq(gsi))
+}
+
+/// Invokes `f` with the locked `IoApic` that handles the given `gsi`
+/// and the line (pin) on that IoApic that corresponds to `gsi`.
+///
+/// Returns an error if no IoApic handles `gsi`.
+fn with_ioapic_for_gsi<T>(
+	gsi: u32,
+	f: impl FnOnce(&mut IoApic, u8) -> Result<T, &'static str>,
+) -> Result<T, &'static str> {
+	let ioapic = get_ioapic_for_gsi(gsi).ok_or("no IoApic handles the given GSI")?;
+	let mut ioapic = ioapic.lock();
+	let pin = (gsi - ioapic.gsi_base) as u8;
+	f(&mut ioapic, pin)
+}
+
+/// Programs the redirection entry for the given global system interrupt (GSI)
+/// on whichever IoApic handles it, as in [`IoApic::set_irq()`].
+pub fn set_gsi(gsi: u32, apic_id: ApicId, vector: u8) -> Result<(), &'static str> {
+	with_ioapic_for_gsi(gsi, |ioapic, pin| ioapic.set_irq(pin, apic_id, vector))
+}
+
+/// Programs the redirection entry for the given global system interrupt (GSI)
+/// on whichever IoApic handles it, as in [`IoApic::set_irq_with_mode()`].
+pub fn set_gsi_with_mode(
+	gsi: u32,
+	apic_id: ApicId,
+	vector: u8,
+	polarity: Polarity,
+	trigger: TriggerMode,
+) -> Result<(), &'static str> {
+	with_ioapic_for_gsi(gsi, |ioapic, pin| ioapic.set_irq_with_mode(pin, apic_id, vector, polarity, trigger))
+}
+
+/// Masks (disables) the given global system interrupt (GSI) on whichever IoApic handles it.
+pub fn mask_gsi(gsi: u32) -> Result<(), &'static str> {
+	with_ioapic_for_gsi(gsi, |ioapic, pin| ioapic.mask_irq(pin))
+}
+
+/// Unmasks (enables) the given global system interrupt (GSI) on whichever IoApic handles it.
+pub fn unmask_gsi(gsi: u32) -> Result<(), &'static str> {
+	with_ioapic_for_gsi(gsi, |ioapic, pin| ioapic.unmask_irq(pin))
+}
+
+/// Reads the redirection entry for the given global system interrupt (GSI)
+/// from whichever IoApic handles it, along with that IoApic's ID and the GSI's line (pin) on it.
+pub fn gsi_redirection_entry(gsi: u32) -> Result<(u8, u8, RedirectionEntry), &'static str> {
+	with_ioapic_for_gsi(gsi, |ioapic, pin| Ok((ioapic.id, pin, ioapic.redirection_entry(pin)?)))
+}
+
+
+/// The polarity of an interrupt line, i.e., which signal level means it is asserted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    /// Asserted when the line is high. This is the default for ISA interrupts.
+    ActiveHigh,
+    /// Asserted when the line is low. This is the default for PCI interrupts.
+    ActiveLow,
+}
+
+/// The trigger mode of an interrupt line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Triggered by a transition of the line. This is the default for ISA interrupts.
+    Edge,
+    /// Triggered for as long as the line is asserted. This is the default for PCI interrupts.
+    Level,
+}
+
+/// The decoded contents of an IoApic redirection table entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RedirectionEntry {
+    /// The interrupt vector delivered to the destination CPU.
+    pub vector: u8,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+    /// Whether the interrupt is masked (disabled).
+    pub masked: bool,
+    /// The APIC ID of the destination CPU (in physical destination mode).
+    pub destination: u8,
+}
+
 
 #[derive(FromBytes)]
 #[repr(C)]
@@ -38,8 +137,9 @@ struct IoApicRegisters {
 }
 
 
-/// Each IoApic handles a maximum of 24 interrupt redirection entries. 
-const INTERRUPT_ENTRIES_PER_IOAPIC: u32 = 24; 
+/// The index of the IoApic version register, whose bits [16:23] hold the index
+/// of the last redirection entry, i.e., the number of entries minus one.
+const IOAPIC_VERSION_REGISTER: u32 = 0x1;
 
 
 /// A representation of an IoApic (x86-specific interrupt chip for I/O devices).
@@ -47,35 +147,55 @@ pub struct IoApic {
     regs: BorrowedMappedPages<IoApicRegisters, Mutable>,
     /// The ID of this IoApic.
     pub id: u8,
-    /// not yet used.
-    _phys_addr: PhysicalAddress,
+    /// The physical address of this IoApic's registers.
+    phys_addr: PhysicalAddress,
     /// The first global interrupt number handled by this IoApic.
-    /// Each IoApic only handles 24 interrupts, 
-    /// so the last interrupt number supported by thie IoApic is `gsi_base + 23`.
     gsi_base: u32,
+    /// The number of redirection entries (interrupt lines) of this IoApic,
+    /// so the last interrupt number handled by this IoApic is `gsi_base + num_entries - 1`.
+    num_entries: u32,
 }
 
 impl IoApic {
     /// Creates a new IoApic struct from the given `id`, `PhysicalAddress`, and `gsi_base`,
     /// and then adds it to the system-wide list of all IOAPICs.
+    ///
+    /// The number of interrupts it handles is read from its version register.
+    /// Returns an error if another IoApic already has the same `id`
+    /// or handles any of the same interrupts.
     pub fn create(page_table: &mut PageTable, id: u8, phys_addr: PhysicalAddress, gsi_base: u32) -> Result<(), &'static str> {
         let new_page = allocate_pages(1).ok_or("IoApic::new(): couldn't allocate_pages!")?;
         let frame = allocate_frames_at(phys_addr, 1).map_err(|_e| "Couldn't allocate physical frame for IOAPIC")?;
         let ioapic_mapped_page = page_table.map_allocated_pages_to(
             new_page,
             frame, 
-            PteFlags::new().valid(true).writable(true).device_memory(true),
+            PteFlagsArch::from(PteFlags::new().valid(true).writable(true)).memory_type(MemoryType::Uncacheable),
         )?;
 
         let ioapic_regs = ioapic_mapped_page.into_borrowed_mut(0).map_err(|(_mp, err)| err)?;
-        let ioapic = IoApic {
+        let mut ioapic = IoApic {
             regs: ioapic_regs,
 			id,
-            _phys_addr: phys_addr,
+            phys_addr,
             gsi_base,
+            num_entries: 0,
 		};
+        ioapic.num_entries = ((ioapic.read_reg(IOAPIC_VERSION_REGISTER) >> 16) & 0xFF) + 1;
+
+        if IOAPICS.get(&id).is_some() {
+            error!("IoApic {} at {:#X} has the same ID as an existing IoApic", id, phys_addr);
+            return Err("an IoApic with the same ID already exists");
+        }
+        let gsi_range = ioapic.gsi_range();
+        if let Some((other_id, _)) = get_ioapics().find(|(_, other)| {
+            let other_range = other.lock().gsi_range();
+            gsi_range.start < other_range.end && other_range.start < gsi_range.end
+        }) {
+            error!("IoApic {} handles GSIs {:?}, which overlap those of IoApic {}", id, gsi_range, other_id);
+            return Err("an IoApic's GSI range overlaps that of an existing IoApic");
+        }
 
-        debug!("Created new IoApic, id: {}, gsi_base: {}, phys_addr: {:#X}", id, gsi_base, phys_addr);
+        debug!("Created new IoApic, id: {}, GSIs: {:?}, phys_addr: {:#X}", id, gsi_range, phys_addr);
         IOAPICS.insert(id, Mutex::new(ioapic));
         Ok(())
     }
@@ -83,8 +203,37 @@ impl IoApic {
     /// Returns whether this IoApic handles the given `irq_num`, i.e.,
     /// whether it's within the range of IRQs handled by this `IoApic`.
     pub fn handles_irq(&self, irq_num: u32) -> bool {
-        (irq_num >= self.gsi_base) && 
-        (irq_num < (self.gsi_base + INTERRUPT_ENTRIES_PER_IOAPIC))
+        self.gsi_range().contains(&irq_num)
+    }
+
+    /// Returns the first global system interrupt (GSI) number handled by this IoApic.
+    pub fn gsi_base(&self) -> u32 {
+        self.gsi_base
+    }
+
+    /// Returns the range of global system interrupt (GSI) numbers handled by this IoApic.
+    pub fn gsi_range(&self) -> Range<u32> {
+        self.gsi_base .. self.gsi_base + self.num_entries
+    }
+
+    /// Returns the number of redirection entries (interrupt lines) of this IoApic.
+    pub fn num_entries(&self) -> u32 {
+        self.num_entries
+    }
+
+    /// Returns the physical address of this IoApic's registers.
+    pub fn phys_addr(&self) -> PhysicalAddress {
+        self.phys_addr
+    }
+
+    /// Returns an error if this IoApic has no redirection entry for the given `ioapic_irq` line.
+    fn check_irq(&self, ioapic_irq: u8) -> Result<(), &'static str> {
+        if (ioapic_irq as u32) < self.num_entries {
+            Ok(())
+        } else {
+            error!("IoApic {} has no line {}: it only has {} lines", self.id, ioapic_irq, self.num_entries);
+            Err("IoApic line number exceeds its number of redirection entries")
+        }
     }
 
     fn read_reg(&mut self, register_index: u32) -> u32 {
@@ -108,7 +257,7 @@ impl IoApic {
 
     /// gets this IoApic's version.
     pub fn version(&mut self) -> u32 {
-        self.read_reg(0x1)
+        self.read_reg(IOAPIC_VERSION_REGISTER)
     }
 
     /// gets this IoApic's arbitration id.
@@ -117,11 +266,51 @@ impl IoApic {
     }
 
     /// Masks (disables) the given IRQ line. 
-    /// NOTE: this function is UNTESTED!
-    pub fn mask_irq(&mut self, irq: u8) {
+    ///
+    /// Returns an error if this IoApic doesn't have that line.
+    pub fn mask_irq(&mut self, irq: u8) -> Result<(), &'static str> {
+        self.check_irq(irq)?;
         let irq_reg: u32 = 0x10 + (2 * irq as u32);
         let direction = self.read_reg(irq_reg);
         self.write_reg(irq_reg, direction | (1 << 16));
+        Ok(())
+    }
+
+    /// Unmasks (enables) the given IRQ line, which was previously masked by [`IoApic::mask_irq()`].
+    ///
+    /// Returns an error if this IoApic doesn't have that line.
+    pub fn unmask_irq(&mut self, irq: u8) -> Result<(), &'static str> {
+        self.check_irq(irq)?;
+        let irq_reg: u32 = 0x10 + (2 * irq as u32);
+        let direction = self.read_reg(irq_reg);
+        self.write_reg(irq_reg, direction & !(1 << 16));
+        Ok(())
+    }
+
+    /// Returns the IRQ line on this IoApic whose redirection entry
+    /// delivers the given system-wide interrupt `vector`, if any.
+    pub fn find_irq_for_vector(&mut self, vector: u8) -> Option<u8> {
+        (0..self.num_entries as u8).find(|irq| {
+            let low = self.read_reg(0x10 + (2 * *irq as u32));
+            (low & 0xff) as u8 == vector
+        })
+    }
+
+    /// Reads and decodes the redirection table entry for the given IRQ line on this IoApic.
+    ///
+    /// Returns an error if this IoApic doesn't have that line.
+    pub fn redirection_entry(&mut self, ioapic_irq: u8) -> Result<RedirectionEntry, &'static str> {
+        self.check_irq(ioapic_irq)?;
+        let low_index: u32 = 0x10 + ((ioapic_irq as u32) * 2);
+        let low = self.read_reg(low_index);
+        let high = self.read_reg(low_index + 1);
+        Ok(RedirectionEntry {
+            vector: (low & 0xff) as u8,
+            polarity: if low & (1 << 13) == 0 { Polarity::ActiveHigh } else { Polarity::ActiveLow },
+            trigger: if low & (1 << 15) == 0 { TriggerMode::Edge } else { TriggerMode::Level },
+            masked: low & (1 << 16) != 0,
+            destination: (high >> 24) as u8,
+        })
     }
 
     /// Set IRQ to an interrupt vector.
@@ -140,12 +329,14 @@ impl IoApic {
     ///   if it is larger than 255.
     ///   This is because the IOAPIC only supports redirecting interrupts to APICs
     ///   with IDs that fit within 8-bit values.
+    /// * Returns `Err` if this IoApic doesn't have the given `ioapic_irq` line.
     pub fn set_irq(
         &mut self,
         ioapic_irq: u8,
         apic_id: ApicId,
         irq_vector: u8,
     ) -> Result<(), &'static str> {
+        self.check_irq(ioapic_irq)?;
         if apic_id.value() > u8::MAX as u32 {
             log::error!("Cannot set IOAPIC redirection table {} -> {} for APIC ID {} larger than 255",
                 ioapic_irq, irq_vector, apic_id.value(),
@@ -175,4 +366,33 @@ impl IoApic {
 
         Ok(())
     }
+
+    /// Sets IRQ to an interrupt vector, like [`IoApic::set_irq()`],
+    /// and also sets the `polarity` and `trigger` mode of the IRQ line.
+    ///
+    /// [`IoApic::set_irq()`] leaves the polarity and trigger mode as the firmware set them,
+    /// which is only correct if no ACPI interrupt source override applies to this line.
+    pub fn set_irq_with_mode(
+        &mut self,
+        ioapic_irq: u8,
+        apic_id: ApicId,
+        irq_vector: u8,
+        polarity: Polarity,
+        trigger: TriggerMode,
+    ) -> Result<(), &'static str> {
+        // Mask the line while changing its trigger mode to avoid a spurious interrupt.
+        self.mask_irq(ioapic_irq)?;
+        let low_index: u32 = 0x10 + ((ioapic_irq as u32) * 2);
+        let mut low = self.read_reg(low_index);
+        match polarity {
+            Polarity::ActiveHigh => low &= !(1 << 13),
+            Polarity::ActiveLow  => low |= 1 << 13,
+        }
+        match trigger {
+            TriggerMode::Edge  => low &= !(1 << 15),
+            TriggerMode::Level => low |= 1 << 15,
+        }
+        self.write_reg(low_index, low);
+        self.set_irq(ioapic_irq, apic_id, irq_vector)
+    }
 }
@@ -139,6 +139,8 @@ fn set_command_bit(
     let bits = command as u32;
     let cmd = if bit_value { tmp | bits } else { tmp & (!bits) };
     iommu.regs.gcommand.write(cmd);
-    while !condition(GlobalStatus::from_bits_truncate(iommu.regs.gstatus.read())) {}
+    while !condition(GlobalStatus::from_bits_truncate(iommu.regs.gstatus.read())) {
+        core::hint::spin_loop();
+    }
     Ok(())
 }
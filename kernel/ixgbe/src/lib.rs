@@ -546,6 +546,7 @@ impl IxgbeNic {
         let mut timer_expired_smbi = false;
         let mut smbi_bit = 1;
         while smbi_bit != 0 {
+            core::hint::spin_loop();
             smbi_bit = regs.swsm.read() & SWSM_SMBI;
             let end = hpet_ref.get_counter();
 
@@ -567,6 +568,7 @@ impl IxgbeNic {
         let mut swesmbi_bit = 0;
         let mut timer_expired_swesmbi = false;
         while swesmbi_bit == 0 {
+            core::hint::spin_loop();
             swesmbi_bit = (regs.swsm.read() & SWSM_SWESMBI) >> 1;
             let end = hpet_ref.get_counter();
 
@@ -670,7 +672,9 @@ impl IxgbeNic {
         regs1.eimc.write(DISABLE_INTERRUPTS);
 
         //wait for eeprom auto read completion
-        while !regs3.eec.read().get_bit(EEC_AUTO_RD as u8){}
+        while !regs3.eec.read().get_bit(EEC_AUTO_RD as u8) {
+            core::hint::spin_loop();
+        }
 
         //read MAC address
         debug!("Ixgbe: MAC address low: {:#X}", regs_mac.ral.read());
@@ -680,6 +684,7 @@ impl IxgbeNic {
         let mut val = regs2.rdrxctl.read();
         let dmaidone_bit = 1 << 3;
         while val & dmaidone_bit != dmaidone_bit {
+            core::hint::spin_loop();
             val = regs2.rdrxctl.read();
         }
 
@@ -795,7 +800,9 @@ impl IxgbeNic {
             rxq.rxdctl.write(val | RX_Q_ENABLE);
 
             //make sure queue is enabled
-            while rxq.rxdctl.read() & RX_Q_ENABLE == 0 {}
+            while rxq.rxdctl.read() & RX_Q_ENABLE == 0 {
+                core::hint::spin_loop();
+            }
         
             // set bit 12 to 0
             let val = rxq.dca_rxctrl.read();
@@ -883,7 +890,9 @@ impl IxgbeNic {
             txq.txdctl.write(val | TX_Q_ENABLE); 
 
             //make sure queue is enabled
-            while txq.txdctl.read() & TX_Q_ENABLE == 0 {} 
+            while txq.txdctl.read() & TX_Q_ENABLE == 0 {
+                core::hint::spin_loop();
+            }
 
             tx_descs_all_queues.push(tx_descs);
         }
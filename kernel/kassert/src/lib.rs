@@ -0,0 +1,267 @@
+//! Kernel assertions for invariants that should hold, with a policy chosen per call site.
+//!
+//! Unlike `assert!`, a failed kernel assertion doesn't necessarily panic.
+//! Each call site picks one of three policies by using the corresponding macro:
+//! * [`kassert!`]: [`Policy::Fatal`], which panics,
+//!   for invariants whose violation leaves the system in an unusable state.
+//! * [`kassert_once!`]: [`Policy::LogOnce`], which logs the first failure and only counts later ones,
+//!   for invariants whose violation the caller can recover from.
+//! * [`kassert_count!`]: [`Policy::CountOnly`], which only counts failures,
+//!   for invariants that are cheap to check in hot paths but not worth reporting individually.
+//!
+//! Every call site has its own hit counter, and the sites that have failed at least once
+//! can be enumerated via [`for_each_failed_site()`], e.g., to report them as metrics.
+//!
+//! Assertions never allocate: the message is only formatted, via [`format_args!`],
+//! when it's actually logged, and a call site is recorded in a lock-free list.
+//! Thus, assertions can be used in interrupt context.
+//!
+//! ## Build profiles
+//! The cost of assertions is controlled by this crate's features, not by each call site:
+//! * By default, e.g., in release builds, every policy is active, but a count-only assertion
+//!   compiles to a single branch on its condition, with the counting done out of line.
+//! * With the `debug` feature, count-only assertions also log their first failure.
+//! * With the `bench` feature, every assertion is compiled out and its condition isn't evaluated,
+//!   so conditions must not have side effects.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::format;
+use core::{fmt, ptr, sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering}};
+use log::{Level, Record};
+
+/// Whether assertions are checked at all, i.e., whether the `bench` feature is disabled.
+#[doc(hidden)]
+pub const ENABLED: bool = !cfg!(feature = "bench");
+
+/// What happens when a kernel assertion fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Panic.
+    Fatal,
+    /// Log the first failure at the `Error` level, and only count later ones.
+    LogOnce,
+    /// Only count failures, but log the first one at the `Warn` level if the `debug` feature is enabled.
+    CountOnly,
+}
+
+/// The head of the list of call sites that have failed at least once, most recent first.
+static FAILED_SITES: AtomicPtr<Site> = AtomicPtr::new(ptr::null_mut());
+
+/// A single call site of a kernel assertion, which is a `static` created by the assertion macros.
+pub struct Site {
+    policy: Policy,
+    module_path: &'static str,
+    file: &'static str,
+    line: u32,
+    hits: AtomicU64,
+    /// Whether this site has been added to the list of failed sites.
+    linked: AtomicBool,
+    /// The next site in the list of failed sites.
+    next: AtomicPtr<Site>,
+}
+
+impl Site {
+    #[doc(hidden)]
+    pub const fn new(policy: Policy, module_path: &'static str, file: &'static str, line: u32) -> Site {
+        Site {
+            policy,
+            module_path,
+            file,
+            line,
+            hits: AtomicU64::new(0),
+            linked: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Returns the policy of this call site.
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /// Returns the module path of this call site, e.g., `task::scheduler`.
+    pub fn module_path(&self) -> &'static str {
+        self.module_path
+    }
+
+    /// Returns the source file and line of this call site.
+    pub fn location(&self) -> (&'static str, u32) {
+        (self.file, self.line)
+    }
+
+    /// Returns the number of times this call site's assertion has failed.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Records a failure of this call site's assertion and applies its policy.
+    ///
+    /// This is invoked by the assertion macros and is kept out of line,
+    /// such that a passing assertion costs only a branch.
+    #[doc(hidden)]
+    #[cold]
+    #[inline(never)]
+    pub fn fail(&'static self, args: fmt::Arguments) {
+        let previous_hits = self.hits.fetch_add(1, Ordering::Relaxed);
+        if previous_hits == 0 {
+            self.link();
+        }
+        match self.policy {
+            Policy::Fatal => panic!("kernel assertion failed at {}:{}: {}", self.file, self.line, args),
+            Policy::LogOnce if previous_hits == 0 => self.log(Level::Error, args),
+            Policy::CountOnly if previous_hits == 0 && cfg!(feature = "debug") => self.log(Level::Warn, args),
+            _ => { }
+        }
+    }
+
+    /// Logs the given failure message, attributed to this call site rather than to this crate.
+    fn log(&self, level: Level, args: fmt::Arguments) {
+        log::logger().log(&Record::builder()
+            .args(format_args!("kernel assertion failed: {} (further failures are only counted)", args))
+            .level(level)
+            .target(self.module_path)
+            .module_path_static(Some(self.module_path))
+            .file_static(Some(self.file))
+            .line(Some(self.line))
+            .build()
+        );
+    }
+
+    /// Pushes this site onto the list of failed sites, unless it's already there.
+    fn link(&'static self) {
+        if self.linked.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let this = self as *const Site as *mut Site;
+        let mut head = FAILED_SITES.load(Ordering::Acquire);
+        loop {
+            self.next.store(head, Ordering::Relaxed);
+            match FAILED_SITES.compare_exchange_weak(head, this, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Site {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Site")
+            .field("policy", &self.policy)
+            .field("location", &format_args!("{}:{}", self.file, self.line))
+            .field("hits", &self.hits())
+            .finish()
+    }
+}
+
+/// Returns an iterator over every call site that has failed at least once, most recent first.
+pub fn failed_sites() -> impl Iterator<Item = &'static Site> {
+    let mut next = FAILED_SITES.load(Ordering::Acquire);
+    core::iter::from_fn(move || {
+        // SAFE: only `&'static Site`s are ever added to the list, and they're never removed.
+        let site = unsafe { next.as_ref() }?;
+        next = site.next.load(Ordering::Acquire);
+        Some(site)
+    })
+}
+
+/// Invokes `report` with a metric name and the hit count of every call site that has failed at least once.
+///
+/// Each name is the site's module path and line, e.g., `task.scheduler.l214`,
+/// which suits the [`metrics`](../metrics/index.html) naming scheme.
+/// This allocates, so it must not be invoked in interrupt context.
+pub fn for_each_failed_site(report: &mut dyn FnMut(&str, u64)) {
+    for site in failed_sites() {
+        let name = format!("{}.l{}", site.module_path.replace("::", "."), site.line);
+        report(&name, site.hits());
+    }
+}
+
+/// Asserts that a condition holds, panicking if it doesn't; see [`Policy::Fatal`].
+///
+/// Accepts an optional message with format arguments, like `assert!`.
+/// Evaluates to whether the condition held, which is always `true` with the `bench` feature.
+#[macro_export]
+macro_rules! kassert {
+    ($($args:tt)+) => { $crate::__kassert_impl!($crate::Policy::Fatal, $($args)+) };
+}
+
+/// Asserts that a condition holds, logging only its first failure; see [`Policy::LogOnce`].
+///
+/// Accepts an optional message with format arguments, like `assert!`.
+/// Evaluates to whether the condition held, so the caller can recover from a failure;
+/// with the `bench` feature, it's always `true`.
+#[macro_export]
+macro_rules! kassert_once {
+    ($($args:tt)+) => { $crate::__kassert_impl!($crate::Policy::LogOnce, $($args)+) };
+}
+
+/// Asserts that a condition holds, only counting its failures; see [`Policy::CountOnly`].
+///
+/// Accepts an optional message with format arguments, like `assert!`.
+/// Evaluates to whether the condition held, which is always `true` with the `bench` feature.
+#[macro_export]
+macro_rules! kassert_count {
+    ($($args:tt)+) => { $crate::__kassert_impl!($crate::Policy::CountOnly, $($args)+) };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __kassert_impl {
+    ($policy:expr, $cond:expr $(,)?) => {
+        $crate::__kassert_impl!($policy, $cond, "{}", stringify!($cond))
+    };
+    ($policy:expr, $cond:expr, $($arg:tt)+) => {{
+        let held = !$crate::ENABLED || $cond;
+        if !held {
+            static SITE: $crate::Site = $crate::Site::new($policy, module_path!(), file!(), line!());
+            SITE.fail(format_args!($($arg)+));
+        }
+        held
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passing_assertions_are_not_recorded() {
+        assert!(kassert!(1 + 1 == 2));
+        assert!(kassert_once!(true, "never fails"));
+        assert!(failed_sites().all(|site| site.module_path() != "never_recorded"));
+    }
+
+    #[test]
+    fn test_failures_are_counted_per_site() {
+        fn check(value: u32) -> bool {
+            kassert_count!(value < 10, "value {} was too large", value)
+        }
+        assert!(check(1));
+        assert!(!check(10));
+        assert!(!check(11));
+        let (file, line) = failed_sites()
+            .find(|site| site.policy() == Policy::CountOnly && site.hits() == 2)
+            .expect("the failed site wasn't recorded")
+            .location();
+        assert_eq!(file, file!());
+        assert!(line > 0);
+    }
+
+    #[test]
+    fn test_log_once_continues() {
+        for _ in 0..3 {
+            assert!(!kassert_once!(false, "recoverable"));
+        }
+        assert!(failed_sites().any(|site| site.policy() == Policy::LogOnce && site.hits() == 3));
+    }
+
+    #[test]
+    #[should_panic(expected = "kernel assertion failed")]
+    fn test_fatal_panics() {
+        kassert!(false, "fatal");
+    }
+}
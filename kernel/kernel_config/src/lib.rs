@@ -2,4 +2,5 @@
 
 pub mod memory;
 pub mod time;
-pub mod display;
\ No newline at end of file
+pub mod display;
+pub mod units;
\ No newline at end of file
@@ -8,23 +8,25 @@
 //!        of an upcoming new page table.
 //! * 507 down to 0: available for general usage.
 
+use crate::units::Bytes;
+
 // On x86_64, addresses must be sign-extended.
 // On theseus, we choose to have all addresses
-// with the sign bit set, i.e. the 16 most
-// important bits must be set.
+// with the sign bit set, i.e. the bits above
+// the `VIRTUAL_ADDRESS_BITS` must be set.
 #[cfg(target_arch = "x86_64")]
 const fn canonicalize(addr: usize) -> usize {
-    addr | 0xFFFF_0000_0000_0000
+    addr | !VIRTUAL_ADDRESS_MASK
 }
 
-// On aarch64, the 16 most important bits must
-// match the address space ID (ASID) of the
+// On aarch64, the bits above the `VIRTUAL_ADDRESS_BITS`
+// must match the address space ID (ASID) of the
 // address space they belong to. In Theseus,
 // our ASID is currently zero: these bits must
 // be cleared.
 #[cfg(target_arch = "aarch64")]
 const fn canonicalize(addr: usize) -> usize {
-    addr & !0xFFFF_0000_0000_0000
+    addr & VIRTUAL_ADDRESS_MASK
 }
 
 /// 64-bit architecture results in 8 bytes per address.
@@ -43,6 +45,16 @@ pub const P2_INDEX_SHIFT: usize = P1_INDEX_SHIFT + 9;
 pub const P3_INDEX_SHIFT: usize = P2_INDEX_SHIFT + 9;
 /// Value: 27. Shift the Page number (not the address!) by this to get the P4 index.
 pub const P4_INDEX_SHIFT: usize = P3_INDEX_SHIFT + 9;
+/// Value: 36. Shift the Page number (not the address!) by this to get the P5 index,
+/// which only exists with 5-level paging.
+pub const P5_INDEX_SHIFT: usize = P4_INDEX_SHIFT + 9;
+
+/// Value: 48. The number of meaningful bits in a virtual address with 4-level paging,
+/// which is the only paging depth that Theseus's virtual memory layout supports.
+/// The remaining upper bits of a canonical virtual address are fixed; see `canonicalize()`.
+pub const VIRTUAL_ADDRESS_BITS: usize = PAGE_SHIFT + P5_INDEX_SHIFT;
+/// The meaningful bits of a virtual address with 4-level paging; see [`VIRTUAL_ADDRESS_BITS`].
+const VIRTUAL_ADDRESS_MASK: usize = (1 << VIRTUAL_ADDRESS_BITS) - 1;
 
 /// Value: 512 GiB.
 pub const ADDRESSABILITY_PER_P4_ENTRY: usize = 1 << (PAGE_SHIFT + P4_INDEX_SHIFT);
@@ -63,6 +75,9 @@ pub const KERNEL_HEAP_P4_INDEX: usize = ENTRIES_PER_PAGE_TABLE - 3;
 /// Value: 508. The 508th entry is used to temporarily recursively map the P4 root page table frame
 ///             of an upcoming (new) page table such that it can be accessed and modified.
 pub const UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX: usize = ENTRIES_PER_PAGE_TABLE - 4;
+/// Value: 507. The 507th entry is used for stacks that are placed at random addresses,
+///             such that their location can't be guessed from that of other allocations.
+pub const RANDOMIZED_STACK_P4_INDEX: usize = ENTRIES_PER_PAGE_TABLE - 5;
 
 
 pub const MAX_PAGE_NUMBER: usize = MAX_VIRTUAL_ADDRESS / PAGE_SIZE;
@@ -99,13 +114,23 @@ pub const RECURSIVE_P4_START: usize = canonicalize(RECURSIVE_P4_INDEX << (P4_IND
 pub const KERNEL_HEAP_START: usize = canonicalize(KERNEL_HEAP_P4_INDEX << (P4_INDEX_SHIFT + PAGE_SHIFT));
 
 #[cfg(not(debug_assertions))]
-pub const KERNEL_HEAP_INITIAL_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+pub const KERNEL_HEAP_INITIAL_SIZE: Bytes = Bytes::mib(64);
 #[cfg(debug_assertions)]
-pub const KERNEL_HEAP_INITIAL_SIZE: usize = 256 * 1024 * 1024; // 256 MiB, debug builds require more heap space.
+pub const KERNEL_HEAP_INITIAL_SIZE: Bytes = Bytes::mib(256); // debug builds require more heap space.
 
 /// The kernel heap is allowed to grow to fill the entirety of its P4 entry.
-pub const KERNEL_HEAP_MAX_SIZE: usize = ADDRESSABILITY_PER_P4_ENTRY;
+pub const KERNEL_HEAP_MAX_SIZE: Bytes = Bytes(ADDRESSABILITY_PER_P4_ENTRY);
+
+const _: () = assert!(
+    KERNEL_HEAP_INITIAL_SIZE.value() <= KERNEL_HEAP_MAX_SIZE.value(),
+    "the kernel heap's initial size exceeds its maximum size",
+);
+const _: () = assert!(KERNEL_HEAP_INITIAL_SIZE.value() % PAGE_SIZE == 0, "the kernel heap's initial size must be page-aligned");
 
 /// The start of the virtual address range covered by the 508th P4 entry,
 /// i.e., [`UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX`];
 pub const UPCOMING_PAGE_TABLE_RECURSIVE_P4_START: usize = canonicalize(UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX << (P4_INDEX_SHIFT + PAGE_SHIFT));
+
+/// The start of the virtual address range covered by the 507th P4 entry,
+/// i.e., [`RANDOMIZED_STACK_P4_INDEX`];
+pub const RANDOMIZED_STACK_START: usize = canonicalize(RANDOMIZED_STACK_P4_INDEX << (P4_INDEX_SHIFT + PAGE_SHIFT));
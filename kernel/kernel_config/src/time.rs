@@ -1,15 +1,93 @@
+use core::time::Duration;
+use crate::units::{Hertz, Milliseconds};
 
 
-/// the chosen interrupt frequency (in Hertz) of the PIT clock 
-pub const CONFIG_PIT_FREQUENCY_HZ: u32 = 1000; 
+/// The frequency of the PIT's input clock, which is divided down to produce its interrupts.
+pub const PIT_INPUT_FREQUENCY: Hertz = Hertz(1_193_182);
 
-/// the chosen interrupt frequency (in Hertz) of the RTC.
+/// the chosen interrupt frequency of the PIT clock 
+pub const CONFIG_PIT_FREQUENCY_HZ: Hertz = Hertz(1000);
+
+/// the chosen interrupt frequency of the RTC.
 /// valid values are powers of 2, from 2 Hz up to 8192 Hz
 /// see [change_rtc_frequency()](rtc/)
-pub const CONFIG_RTC_FREQUENCY_HZ: usize = 128;
+pub const CONFIG_RTC_FREQUENCY_HZ: Hertz = Hertz(128);
+
+/// The timeslice period, specified in milliseconds.
+pub const CONFIG_TIMESLICE_PERIOD_MS: Milliseconds = Milliseconds(8);
+
+/// The timeslice period.
+pub const CONFIG_TIMESLICE_PERIOD: Duration = CONFIG_TIMESLICE_PERIOD_MS.as_duration();
+
+/// The heartbeat period.
+pub const CONFIG_HEARTBEAT_PERIOD: Duration = Duration::from_secs(10);
+
+/// Returns the frequency of the timer interrupt that fires once per timeslice of the given `period`,
+/// e.g., [`CONFIG_TIMESLICE_PERIOD`] or the period overridden on the kernel command line.
+pub const fn timeslice_frequency(period: Duration) -> Hertz {
+    Hertz::from_period(period)
+}
+
+/// Returns the number of timer ticks, i.e., timeslices of the given `period`, in the given `window`,
+/// rounded down but at least 1.
+///
+/// This is used to measure windows of time in units of timer ticks, e.g., [`CONFIG_IRQ_STORM_WINDOW`].
+pub const fn timeslice_ticks_in(window: Duration, period: Duration) -> u64 {
+    let period = period.as_nanos();
+    let ticks = if period == 0 { 0 } else { window.as_nanos() / period };
+    if ticks == 0 { 1 } else if ticks > u64::MAX as u128 { u64::MAX } else { ticks as u64 }
+}
+
+/// Returns whether the PIT can be programmed to fire its interrupt at the given `frequency`,
+/// i.e., whether the divisor of its input clock fits in its 16-bit reload register.
+pub const fn is_valid_pit_frequency(frequency: Hertz) -> bool {
+    match frequency.divisor_of(PIT_INPUT_FREQUENCY) {
+        Some(divisor) => divisor >= 1 && divisor <= u16::MAX as u32,
+        None => false,
+    }
+}
+
+/// Returns whether the RTC can be programmed to fire its interrupt at the given `frequency`.
+pub const fn is_valid_rtc_frequency(frequency: Hertz) -> bool {
+    frequency.value().is_power_of_two() && frequency.value() >= 2 && frequency.value() <= 8192
+}
+
+// Validate the relationships between the above constants at compile time.
+const _: () = assert!(is_valid_pit_frequency(CONFIG_PIT_FREQUENCY_HZ), "CONFIG_PIT_FREQUENCY_HZ doesn't fit the PIT's 16-bit divisor");
+const _: () = assert!(
+    is_valid_pit_frequency(timeslice_frequency(CONFIG_TIMESLICE_PERIOD)),
+    "the timeslice frequency doesn't fit the PIT's 16-bit divisor, which drives the scheduler in PIC mode",
+);
+const _: () = assert!(is_valid_rtc_frequency(CONFIG_RTC_FREQUENCY_HZ), "CONFIG_RTC_FREQUENCY_HZ must be a power of 2 from 2 to 8192 Hz");
+const _: () = assert!(
+    CONFIG_TIMESLICE_PERIOD.as_nanos() < CONFIG_HEARTBEAT_PERIOD.as_nanos(),
+    "the heartbeat period must be longer than a timeslice",
+);
+
+/// The default rate (in interrupts per second) above which a single interrupt vector
+/// is considered to be storming. This can be changed at runtime.
+pub const CONFIG_IRQ_STORM_THRESHOLD_PER_SEC: u32 = 10_000;
+
+/// The length of each window over which interrupt arrival rates are measured
+/// for storm detection. This is rounded down to a multiple of the timeslice period.
+pub const CONFIG_IRQ_STORM_WINDOW: Duration = Duration::from_millis(250);
+
+/// How long an interrupt vector must continuously exceed the storm threshold
+/// before it is considered to be storming and is masked.
+pub const CONFIG_IRQ_STORM_SUSTAIN: Duration = Duration::from_secs(1);
+
+/// The length of each window over which the share of each CPU's time spent in
+/// interrupt handlers and deferred interrupt tasks is measured.
+/// This is rounded down to a multiple of the timeslice period.
+pub const CONFIG_SYSTEM_TIME_WINDOW: Duration = Duration::from_millis(250);
+
+/// The default share of a CPU's time, in percent, that deferred interrupt tasks may use
+/// before they yield to other tasks. This can be changed at runtime, and `0` disables the cap.
+pub const CONFIG_DEFERRED_WORK_CAP_PERCENT: u32 = 50;
 
-/// The timeslice period, specified in microseconds.
-pub const CONFIG_TIMESLICE_PERIOD_MICROSECONDS: u32 = 8000; // 8ms
+/// How often the TSCs of all CPUs are re-checked for drift relative to the bootstrap CPU.
+pub const CONFIG_TSC_DRIFT_CHECK_PERIOD: Duration = Duration::from_secs(10);
 
-/// the heartbeat period in milliseconds
-pub const CONFIG_HEARTBEAT_PERIOD_MS: usize = 10000;
\ No newline at end of file
+/// The maximum drift between the TSCs of two CPUs after compensating for their boot-time offsets,
+/// beyond which the TSC is considered unreliable for comparing timestamps across CPUs.
+pub const CONFIG_TSC_MAX_DRIFT: Duration = Duration::from_micros(5);
@@ -0,0 +1,143 @@
+//! Typed wrappers for configuration values that would otherwise be bare integers
+//! whose units are only given by their names, e.g., frequencies and memory sizes.
+//!
+//! All conversions are `const` such that configuration constants can be derived
+//! from (and validated against) each other at compile time.
+//! Time periods are expressed with [`Duration`] or [`Milliseconds`].
+
+use core::fmt;
+use core::time::Duration;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// A frequency in Hertz, i.e., events per second.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hertz(pub u32);
+
+impl Hertz {
+    /// Returns the frequency of an event that occurs once every `period`,
+    /// rounded down to a whole number of Hertz.
+    ///
+    /// A zero `period` saturates to `u32::MAX` Hertz.
+    pub const fn from_period(period: Duration) -> Hertz {
+        let nanos = period.as_nanos();
+        if nanos == 0 {
+            return Hertz(u32::MAX);
+        }
+        let hz = NANOS_PER_SEC / nanos;
+        Hertz(if hz > u32::MAX as u128 { u32::MAX } else { hz as u32 })
+    }
+
+    /// Returns the value of this frequency in Hertz.
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the time between two consecutive events at this frequency,
+    /// or `None` if this frequency is zero.
+    pub const fn period(self) -> Option<Duration> {
+        if self.0 == 0 {
+            return None;
+        }
+        Some(Duration::from_nanos((NANOS_PER_SEC / self.0 as u128) as u64))
+    }
+
+    /// Returns the number of events at this frequency that occur within `duration`,
+    /// rounded down, e.g., the number of timer ticks per timeslice.
+    pub const fn ticks_in(self, duration: Duration) -> u64 {
+        (duration.as_nanos() * self.0 as u128 / NANOS_PER_SEC) as u64
+    }
+
+    /// Returns the divisor that derives this frequency from the given `input` clock,
+    /// rounded down, or `None` if this frequency is zero.
+    ///
+    /// This is the value that programmable timers like the PIT are loaded with.
+    pub const fn divisor_of(self, input: Hertz) -> Option<u32> {
+        if self.0 == 0 {
+            None
+        } else {
+            Some(input.0 / self.0)
+        }
+    }
+}
+
+impl fmt::Display for Hertz {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} Hz", self.0)
+    }
+}
+
+/// A time period in whole milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Milliseconds(pub u64);
+
+impl Milliseconds {
+    /// Returns the given `duration` in whole milliseconds, rounded down.
+    pub const fn from_duration(duration: Duration) -> Milliseconds {
+        Milliseconds(duration.as_millis() as u64)
+    }
+
+    /// Returns the value of this period in milliseconds.
+    pub const fn value(self) -> u64 {
+        self.0
+    }
+
+    /// Returns this period as a `Duration`.
+    pub const fn as_duration(self) -> Duration {
+        Duration::from_millis(self.0)
+    }
+
+    /// Returns the frequency of an event that occurs once every period of this length.
+    pub const fn frequency(self) -> Hertz {
+        Hertz::from_period(self.as_duration())
+    }
+}
+
+impl fmt::Display for Milliseconds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ms", self.0)
+    }
+}
+
+/// A size of memory in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bytes(pub usize);
+
+impl Bytes {
+    /// Returns the given number of kibibytes (1024 bytes).
+    pub const fn kib(kib: usize) -> Bytes {
+        Bytes(kib * 1024)
+    }
+
+    /// Returns the given number of mebibytes (1024 KiB).
+    pub const fn mib(mib: usize) -> Bytes {
+        Bytes(mib * 1024 * 1024)
+    }
+
+    /// Returns the given number of gibibytes (1024 MiB).
+    pub const fn gib(gib: usize) -> Bytes {
+        Bytes(gib * 1024 * 1024 * 1024)
+    }
+
+    /// Returns the value of this size in bytes.
+    pub const fn value(self) -> usize {
+        self.0
+    }
+
+    /// Returns the number of pages of `page_size` bytes needed to hold this size, rounded up.
+    pub const fn in_pages(self, page_size: usize) -> usize {
+        (self.0 + page_size - 1) / page_size
+    }
+}
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const KIB: usize = 1024;
+        match self.0 {
+            b if b != 0 && b % (KIB * KIB * KIB) == 0 => write!(f, "{} GiB", b / (KIB * KIB * KIB)),
+            b if b != 0 && b % (KIB * KIB) == 0 => write!(f, "{} MiB", b / (KIB * KIB)),
+            b if b != 0 && b % KIB == 0 => write!(f, "{} KiB", b / KIB),
+            b => write!(f, "{} bytes", b),
+        }
+    }
+}
@@ -4,6 +4,7 @@
 #![feature(abi_x86_interrupt)]
 
 use core::sync::atomic::{AtomicBool, Ordering};
+use irq_safety::hold_interrupts;
 use keycodes_ascii::{Keycode, KeyboardModifiers, KEY_RELEASED_OFFSET, KeyAction, KeyEvent};
 use log::{error, warn, debug};
 use once_cell::unsync::Lazy;
@@ -11,11 +12,14 @@ use spin::Once;
 use mpmc::Queue;
 use event_types::Event;
 use ps2::{PS2Keyboard, KeyboardType, LEDState, ScancodeSet};
+
+pub use ps2::{RepeatDelay, RepeatRate};
 use x86_64::structures::idt::InterruptStackFrame;
 
-/// The first PS/2 port for the keyboard is connected directly to IRQ 1.
+/// The first PS/2 port for the keyboard is connected directly to ISA IRQ 1.
+const PS2_KEYBOARD_ISA_IRQ: u8 = 0x1;
 /// Because we perform the typical PIC remapping, the remapped IRQ vector number is 0x21.
-const PS2_KEYBOARD_IRQ: u8 = interrupts::IRQ_BASE_OFFSET + 0x1;
+const PS2_KEYBOARD_IRQ: u8 = interrupts::IRQ_BASE_OFFSET + PS2_KEYBOARD_ISA_IRQ;
 
 // TODO: avoid unsafe static mut
 static mut KBD_MODIFIERS: Lazy<KeyboardModifiers> = Lazy::new(KeyboardModifiers::new);
@@ -56,7 +60,7 @@ pub fn init(keyboard: PS2Keyboard<'static>, keyboard_queue_producer: Queue<Event
     keyboard.set_keyboard_scancode_set(ScancodeSet::Set1)?;
 
     // Register the interrupt handler
-    interrupts::register_interrupt(PS2_KEYBOARD_IRQ, ps2_keyboard_handler).map_err(|e| {
+    interrupts::register_isa_interrupt(PS2_KEYBOARD_ISA_IRQ, ps2_keyboard_handler).map_err(|e| {
         error!("PS/2 keyboard IRQ {PS2_KEYBOARD_IRQ:#X} was already in use by handler {e:#X}! Sharing IRQs is currently unsupported.");
         "PS/2 keyboard IRQ was already in use! Sharing IRQs is currently unsupported."
     })?;
@@ -67,6 +71,18 @@ pub fn init(keyboard: PS2Keyboard<'static>, keyboard_queue_producer: Queue<Event
     Ok(())
 }
 
+/// Sets how long a key must be held down before it starts repeating (`delay`),
+/// and how many times per second it repeats after that (`rate`).
+///
+/// Returns an error if the keyboard hasn't been initialized
+/// or didn't acknowledge the command.
+pub fn set_typematic(delay: RepeatDelay, rate: RepeatRate) -> Result<(), &'static str> {
+    let params = KEYBOARD.get().ok_or("the PS/2 keyboard hasn't been initialized")?;
+    // The keyboard's ACK must be polled for here rather than consumed by the keyboard interrupt handler.
+    let _held_interrupts = hold_interrupts();
+    params.keyboard.set_keyboard_typematic(delay, rate)
+}
+
 /// The interrupt handler for a PS/2-connected keyboard, registered at IRQ 0x21.
 extern "x86-interrupt" fn ps2_keyboard_handler(_stack_frame: InterruptStackFrame) {
     // Some of the scancodes are "extended", which means they generate two different interrupts,
@@ -75,6 +91,7 @@ extern "x86-interrupt" fn ps2_keyboard_handler(_stack_frame: InterruptStackFrame
 
     if let Some(KeyboardInterruptParams { keyboard, queue }) = KEYBOARD.get() {
         let scan_code = keyboard.read_scancode();
+        random::pool::add_keyboard_timing(scan_code);
         let extended = EXTENDED_SCANCODE.load(Ordering::SeqCst);
 
         // 0xE0 indicates an extended scancode, so we must wait for the next interrupt to get the actual scancode
@@ -9,6 +9,9 @@
 //! Early log messages (before memory management is initialized) are saved
 //! to a static fixed-sized buffer such that they are not lost and
 //! can be retrieved once logging sinks are ready to be used.
+//!
+//! Repeated log records are deduplicated and rate limited per call site;
+//! see [`ThrottleConfig`] and [`bypass_throttling()`].
 
 #![no_std]
 #![feature(trait_alias)]
@@ -27,6 +30,12 @@ use alloc::{sync::Arc, vec::Vec};
 
 #[cfg(mirror_log_to_vga)]
 pub use mirror_log::set_log_mirror_function;
+pub use throttle::{
+    bypass_throttling, set_throttle_clock, set_throttle_config, throttle_config, throttle_stats,
+    SiteStats, ThrottleBypass, ThrottleConfig, MAX_TRACKED_SITES, REPEAT_FLUSH_INTERVAL,
+};
+
+mod throttle;
 
 /// By default, Theseus will print all log levels, including `Trace` and above.
 pub const DEFAULT_LOG_LEVEL: Level = Level::Trace;
@@ -221,6 +230,25 @@ impl DummyLogger {
     }
 }
 
+impl DummyLogger {
+    /// Writes a logger-generated marker line attributed to the call site at `file:line`,
+    /// e.g., to report records that were suppressed or collapsed by throttling.
+    fn write_marker(&self, file: &str, line: u32, args: fmt::Arguments) {
+        let _result = self.write_fmt(format_args!("{}[W] {}:{}: {}{}",
+            LogColor::Yellow.as_terminal_string(),
+            file,
+            line,
+            args,
+            LogColor::Reset.as_terminal_string(),
+        ));
+
+        #[cfg(mirror_log_to_vga)]
+        if let Some(func) = mirror_log::get_log_mirror_function() {
+            func(format_args!("[W] {}:{}: {}", file, line, args));
+        }
+    }
+}
+
 impl Log for DummyLogger {
     #[inline(always)]
     fn enabled(&self, metadata: &Metadata) -> bool {
@@ -232,6 +260,23 @@ impl Log for DummyLogger {
             return;
         }
 
+        let verdict = throttle::check(record);
+        if let Some(repeated) = verdict.repeated {
+            self.write_marker(repeated.file, repeated.line, format_args!(
+                "---- previous message repeated {} more times ----", repeated.count,
+            ));
+        }
+        if !verdict.emit {
+            return;
+        }
+        let file_loc = record.file().unwrap_or("??");
+        let line_loc = record.line().unwrap_or(0);
+        if verdict.suppressed > 0 {
+            self.write_marker(file_loc, line_loc, format_args!(
+                "---- {} messages suppressed from this site ----", verdict.suppressed,
+            ));
+        }
+
         let (level_str, color) = match record.level() {
             Level::Error => ("[E] ", LogColor::Red),
             Level::Warn =>  ("[W] ", LogColor::Yellow),
@@ -239,8 +284,6 @@ impl Log for DummyLogger {
             Level::Debug => ("[D] ", LogColor::Green),
             Level::Trace => ("[T] ", LogColor::Purple),
         };
-        let file_loc = record.file().unwrap_or("??");
-        let line_loc = record.line().unwrap_or(0);
         let _result = self.write_fmt(
             format_args!("{}{}{}:{}: {}{}",
                 color.as_terminal_string(),
@@ -268,7 +311,12 @@ impl Log for DummyLogger {
     }
 
     fn flush(&self) {
-        // flushing the log is a no-op, since there is no write buffering.
+        // There is no write buffering, but repeats of the last record may still be pending.
+        if let Some(repeated) = throttle::take_repeats() {
+            self.write_marker(repeated.file, repeated.line, format_args!(
+                "---- previous message repeated {} more times ----", repeated.count,
+            ));
+        }
     }
 }
 
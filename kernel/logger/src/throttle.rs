@@ -0,0 +1,323 @@
+//! Throttling and deduplication of log records, such that a storm of
+//! repeated diagnostics cannot starve the rest of the system's output.
+//!
+//! Two mechanisms are applied to each record, in order:
+//! 1. **Deduplication**: a record whose call site and formatted content are identical
+//!    to the previously-emitted record is collapsed into a repeat count.
+//!    That count is emitted once a different record is logged,
+//!    or once [`REPEAT_FLUSH_INTERVAL`] has passed since the last emission.
+//! 2. **Rate limiting**: each call site has a token bucket, configured by [`ThrottleConfig`].
+//!    Records from a call site whose bucket is empty are suppressed, and the number suppressed
+//!    is reported in a marker emitted before the next record allowed from that site.
+//!
+//! The throttle state is fixed-size and never allocates, and a record is never
+//! blocked waiting for it: if the state is already locked (e.g., by a log statement
+//! that recursively logs, or by another CPU), the record is emitted unthrottled.
+
+use core::{fmt, mem, time::Duration};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::vec::Vec;
+use crossbeam_utils::atomic::AtomicCell;
+use log::Record;
+use sync_irq::IrqSafeMutex;
+
+/// The maximum number of call sites whose rate is limited individually.
+///
+/// Records from call sites beyond this limit are still deduplicated,
+/// but are not rate limited.
+pub const MAX_TRACKED_SITES: usize = 64;
+
+/// How long repeats of an identical record are collapsed before their count is emitted.
+pub const REPEAT_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The number of milli-tokens in one token of a call site's bucket.
+const MILLIS_PER_TOKEN: u64 = 1000;
+
+static THROTTLE: IrqSafeMutex<ThrottleState> = IrqSafeMutex::new(ThrottleState::new());
+
+/// The number of outstanding [`ThrottleBypass`] guards.
+static BYPASS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The clock used to refill token buckets and to flush repeat counts.
+///
+/// If `None`, records are deduplicated but not rate limited.
+static THROTTLE_CLOCK: AtomicCell<Option<fn() -> Duration>> = AtomicCell::new(None);
+const _: () = assert!(AtomicCell::<Option<fn() -> Duration>>::is_lock_free());
+
+/// The configuration of per-call-site rate limiting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThrottleConfig {
+    /// Whether records are deduplicated and rate limited at all.
+    pub enabled: bool,
+    /// The maximum number of records that a call site can emit in a burst.
+    pub burst: u32,
+    /// The number of records per second that a call site can sustainably emit.
+    pub per_second: u32,
+}
+
+impl ThrottleConfig {
+    /// The default configuration: bursts of 100 records, then 20 records per second.
+    pub const DEFAULT: ThrottleConfig = ThrottleConfig {
+        enabled: true,
+        burst: 100,
+        per_second: 20,
+    };
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Per-call-site logging statistics, as returned by [`throttle_stats()`].
+#[derive(Clone, Copy, Debug)]
+pub struct SiteStats {
+    pub file: &'static str,
+    pub line: u32,
+    /// The number of records from this site that were emitted.
+    pub logged: u64,
+    /// The number of records from this site that were suppressed by rate limiting.
+    pub suppressed: u64,
+    /// The number of records from this site that were collapsed as repeats.
+    pub deduplicated: u64,
+}
+
+/// Sets the configuration of per-call-site rate limiting.
+pub fn set_throttle_config(config: ThrottleConfig) {
+    THROTTLE.lock().config = config;
+}
+
+/// Returns the current configuration of per-call-site rate limiting.
+pub fn throttle_config() -> ThrottleConfig {
+    THROTTLE.lock().config
+}
+
+/// Sets the clock used to rate limit log records and to flush repeat counts.
+///
+/// The given function must not itself log anything in the common case,
+/// so this should only be set once a clock source is available.
+/// Until then, log records are deduplicated but not rate limited.
+pub fn set_throttle_clock(func: fn() -> Duration) {
+    THROTTLE_CLOCK.store(Some(func));
+}
+
+/// Returns the logging statistics of every call site that has been tracked so far.
+pub fn throttle_stats() -> Vec<SiteStats> {
+    THROTTLE.lock().sites.iter().flatten().map(|site| site.stats).collect()
+}
+
+/// Disables throttling of all log records until the returned guard is dropped.
+///
+/// This is intended for panic and fatal exception paths, whose diagnostics
+/// must never be suppressed or collapsed.
+pub fn bypass_throttling() -> ThrottleBypass {
+    BYPASS_COUNT.fetch_add(1, Ordering::Relaxed);
+    ThrottleBypass(())
+}
+
+/// A guard that disables throttling of log records while it exists;
+/// see [`bypass_throttling()`].
+#[must_use = "throttling is only bypassed until this guard is dropped"]
+pub struct ThrottleBypass(());
+
+impl Drop for ThrottleBypass {
+    fn drop(&mut self) {
+        BYPASS_COUNT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The number of identical records that were collapsed after a previously-emitted record.
+pub(crate) struct Repeated {
+    pub file: &'static str,
+    pub line: u32,
+    pub count: u64,
+}
+
+/// What the logger should emit for a given record.
+pub(crate) struct Verdict {
+    /// Repeats of the previously-emitted record, to be reported before anything else.
+    pub repeated: Option<Repeated>,
+    /// The number of records suppressed from this record's call site,
+    /// to be reported before this record.
+    pub suppressed: u64,
+    /// Whether this record itself should be emitted.
+    pub emit: bool,
+}
+
+impl Verdict {
+    const EMIT: Verdict = Verdict { repeated: None, suppressed: 0, emit: true };
+}
+
+/// Decides whether the given `record` should be emitted, updating the throttle state.
+pub(crate) fn check(record: &Record) -> Verdict {
+    if BYPASS_COUNT.load(Ordering::Relaxed) > 0 {
+        return Verdict::EMIT;
+    }
+    let Some(mut state) = THROTTLE.try_lock() else {
+        return Verdict::EMIT;
+    };
+    let state = &mut *state;
+    if !state.config.enabled {
+        return Verdict::EMIT;
+    }
+
+    let file = record.file_static().unwrap_or("??");
+    let line = record.line().unwrap_or(0);
+    let hash = {
+        let mut hasher = Fnv1a::new();
+        let _ = fmt::write(&mut hasher, *record.args());
+        hasher.0
+    };
+    // If the clock itself logs, that nested record is emitted unthrottled
+    // because the throttle state is already locked.
+    let now = THROTTLE_CLOCK.load().map(|clock| clock());
+    let mut verdict = Verdict::EMIT;
+
+    if let Some(last) = state.last.as_mut() {
+        if last.file == file && last.line == line && last.hash == hash {
+            last.repeats += 1;
+            if let Some(site) = find_site(&mut state.sites, file, line, &state.config, now) {
+                site.stats.deduplicated += 1;
+            }
+            if let (Some(now), Some(since)) = (now, last.since) {
+                if now.saturating_sub(since) >= REPEAT_FLUSH_INTERVAL {
+                    verdict.repeated = last.take_repeats(now);
+                }
+            }
+            verdict.emit = false;
+            return verdict;
+        }
+        verdict.repeated = last.take_repeats(now.unwrap_or_default());
+    }
+
+    if let Some(site) = find_site(&mut state.sites, file, line, &state.config, now) {
+        if !site.take_token(&state.config, now) {
+            site.stats.suppressed += 1;
+            site.unreported += 1;
+            verdict.emit = false;
+            return verdict;
+        }
+        site.stats.logged += 1;
+        verdict.suppressed = mem::take(&mut site.unreported);
+    }
+    state.last = Some(LastRecord { file, line, hash, repeats: 0, since: now });
+    verdict
+}
+
+/// Takes the repeat count of the previously-emitted record, if any repeats are pending.
+pub(crate) fn take_repeats() -> Option<Repeated> {
+    let now = THROTTLE_CLOCK.load().map(|clock| clock()).unwrap_or_default();
+    THROTTLE.try_lock()?.last.as_mut()?.take_repeats(now)
+}
+
+struct ThrottleState {
+    config: ThrottleConfig,
+    sites: [Option<Site>; MAX_TRACKED_SITES],
+    /// The most recently emitted record.
+    last: Option<LastRecord>,
+}
+
+impl ThrottleState {
+    const fn new() -> Self {
+        const INIT: Option<Site> = None;
+        Self {
+            config: ThrottleConfig::DEFAULT,
+            sites: [INIT; MAX_TRACKED_SITES],
+            last: None,
+        }
+    }
+}
+
+struct LastRecord {
+    file: &'static str,
+    line: u32,
+    hash: u64,
+    /// The number of identical records collapsed since this record or its repeats were last emitted.
+    repeats: u64,
+    /// When this record or its repeats were last emitted, if a clock is available.
+    since: Option<Duration>,
+}
+
+impl LastRecord {
+    fn take_repeats(&mut self, now: Duration) -> Option<Repeated> {
+        if self.since.is_some() {
+            self.since = Some(now);
+        }
+        match mem::take(&mut self.repeats) {
+            0 => None,
+            count => Some(Repeated { file: self.file, line: self.line, count }),
+        }
+    }
+}
+
+struct Site {
+    stats: SiteStats,
+    /// The number of milli-tokens in this site's bucket.
+    tokens: u64,
+    /// When this site's bucket was last refilled.
+    refilled: Option<Duration>,
+    /// The number of suppressed records not yet reported in a marker.
+    unreported: u64,
+}
+
+impl Site {
+    /// Refills this site's bucket and takes one token from it.
+    ///
+    /// Returns `true` if a token was available or if there is no clock to refill with.
+    fn take_token(&mut self, config: &ThrottleConfig, now: Option<Duration>) -> bool {
+        let Some(now) = now else { return true };
+        let capacity = config.burst as u64 * MILLIS_PER_TOKEN;
+        let elapsed = self.refilled.map_or(Duration::ZERO, |then| now.saturating_sub(then));
+        let refill = elapsed.as_nanos() * config.per_second as u128 / 1_000_000;
+        self.tokens = capacity.min(self.tokens.saturating_add(refill.min(u64::MAX as u128) as u64));
+        self.refilled = Some(now);
+        if self.tokens >= MILLIS_PER_TOKEN {
+            self.tokens -= MILLIS_PER_TOKEN;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Finds the tracked call site at `file:line`, tracking it if there is room.
+fn find_site<'s>(
+    sites: &'s mut [Option<Site>; MAX_TRACKED_SITES],
+    file: &'static str,
+    line: u32,
+    config: &ThrottleConfig,
+    now: Option<Duration>,
+) -> Option<&'s mut Site> {
+    let index = sites.iter()
+        .position(|s| s.as_ref().map_or(true, |s| s.stats.line == line && s.stats.file == file))?;
+    let slot = &mut sites[index];
+    if slot.is_none() {
+        *slot = Some(Site {
+            stats: SiteStats { file, line, logged: 0, suppressed: 0, deduplicated: 0 },
+            tokens: config.burst as u64 * MILLIS_PER_TOKEN,
+            refilled: now,
+            unreported: 0,
+        });
+    }
+    slot.as_mut()
+}
+
+/// A [`fmt::Write`]r that computes the FNV-1a hash of everything written to it.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl fmt::Write for Fnv1a {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! Decoding of the `IA32_MCi_STATUS` MSR of a machine check bank,
+//! based on the architectural MCA error codes in Section 15.9 of the Intel SDM, Volume 3.
+//!
+//! Only the architectural parts of the status are decoded;
+//! the model-specific error code and other information are shown in raw form.
+
+use core::fmt;
+use cpu::CpuId;
+
+/// The contents of a machine check bank's `IA32_MCi_STATUS` MSR.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BankStatus(pub u64);
+
+impl BankStatus {
+    /// The status holds a valid error.
+    pub const VAL: u64 = 1 << 63;
+    /// An error occurred while a previous one was still held in this bank.
+    pub const OVER: u64 = 1 << 62;
+    /// The error was not corrected by the processor.
+    pub const UC: u64 = 1 << 61;
+    /// Reporting of the error was enabled, i.e., it could have signaled a machine check exception.
+    pub const EN: u64 = 1 << 60;
+    /// `IA32_MCi_MISC` holds additional information about the error.
+    pub const MISCV: u64 = 1 << 59;
+    /// `IA32_MCi_ADDR` holds the address at which the error occurred.
+    pub const ADDRV: u64 = 1 << 58;
+    /// The processor context may have been corrupted by the error, so it can't be restarted reliably.
+    pub const PCC: u64 = 1 << 57;
+
+    /// Returns `true` if this status holds a valid error.
+    pub const fn is_valid(self) -> bool { self.0 & Self::VAL != 0 }
+    /// Returns `true` if this error was corrected by the processor.
+    pub const fn is_corrected(self) -> bool { self.0 & Self::UC == 0 }
+    /// Returns `true` if a previous error was lost because this bank still held this one.
+    pub const fn overflowed(self) -> bool { self.0 & Self::OVER != 0 }
+    /// Returns `true` if `IA32_MCi_ADDR` is valid for this error.
+    pub const fn addr_valid(self) -> bool { self.0 & Self::ADDRV != 0 }
+    /// Returns `true` if `IA32_MCi_MISC` is valid for this error.
+    pub const fn misc_valid(self) -> bool { self.0 & Self::MISCV != 0 }
+    /// Returns `true` if the processor context may have been corrupted by this error.
+    pub const fn context_corrupt(self) -> bool { self.0 & Self::PCC != 0 }
+
+    /// Returns the architectural MCA error code in bits `[0:16)`.
+    pub const fn error_code(self) -> McaErrorCode { McaErrorCode(self.0 as u16) }
+
+    /// Returns the model-specific error code in bits `[16:32)`.
+    pub const fn model_specific_code(self) -> u16 { (self.0 >> 16) as u16 }
+
+    /// Returns the number of corrected errors counted in bits `[38:53)`,
+    /// which is only maintained by processors that support CMCI.
+    pub const fn corrected_count(self) -> u16 { ((self.0 >> 38) & 0x7FFF) as u16 }
+}
+
+/// An architectural MCA error code, as held in bits `[0:16)` of `IA32_MCi_STATUS`.
+///
+/// Its [`Display`](fmt::Display) implementation describes the error in human-readable form,
+/// e.g., "L2 data cache error (data read)".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct McaErrorCode(pub u16);
+
+impl McaErrorCode {
+    /// The bit that indicates corrected errors are being filtered, which is ignored when decoding.
+    const FILTER: u16 = 1 << 12;
+
+    /// Returns the error code without its filtering bit.
+    const fn code(self) -> u16 {
+        self.0 & !Self::FILTER
+    }
+}
+
+/// The cache level encoded in the `LL` sub-field of a compound error code.
+fn level(ll: u16) -> &'static str {
+    match ll & 0b11 {
+        0b00 => "L0",
+        0b01 => "L1",
+        0b10 => "L2",
+        _ => "generic",
+    }
+}
+
+/// The transaction type encoded in the `TT` sub-field of a compound error code.
+fn transaction_type(tt: u16) -> &'static str {
+    match tt & 0b11 {
+        0b00 => "instruction",
+        0b01 => "data",
+        _ => "generic",
+    }
+}
+
+/// The request type encoded in the `RRRR` sub-field of a compound error code.
+fn request(rrrr: u16) -> &'static str {
+    match rrrr & 0b1111 {
+        0b0000 => "generic error",
+        0b0001 => "generic read",
+        0b0010 => "generic write",
+        0b0011 => "data read",
+        0b0100 => "data write",
+        0b0101 => "instruction fetch",
+        0b0110 => "prefetch",
+        0b0111 => "eviction",
+        0b1000 => "snoop",
+        _ => "unknown request",
+    }
+}
+
+/// The memory transaction type encoded in the `MMM` sub-field of a memory controller error code.
+fn memory_transaction(mmm: u16) -> &'static str {
+    match mmm & 0b111 {
+        0b000 => "generic undefined request",
+        0b001 => "memory read",
+        0b010 => "memory write",
+        0b011 => "address/command error",
+        0b100 => "memory scrubbing",
+        _ => "unknown request",
+    }
+}
+
+/// The participation encoded in the `PP` sub-field of a bus or interconnect error code.
+fn participation(pp: u16) -> &'static str {
+    match pp & 0b11 {
+        0b00 => "local processor originated request",
+        0b01 => "local processor responded to request",
+        0b10 => "local processor observed error as third party",
+        _ => "generic",
+    }
+}
+
+impl fmt::Display for McaErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self.code();
+        match code {
+            // Simple error codes.
+            0x0000 => write!(f, "no error"),
+            0x0001 => write!(f, "unclassified error"),
+            0x0002 => write!(f, "microcode ROM parity error"),
+            0x0003 => write!(f, "external error (from another processor or the chipset)"),
+            0x0004 => write!(f, "functional redundancy check error"),
+            0x0005 => write!(f, "internal parity error"),
+            0x0006 => write!(f, "SMM handler code access violation"),
+            0x0400 => write!(f, "internal timer error"),
+            0x0401 ..= 0x07FF => write!(f, "internal unclassified error ({:#06X})", code),
+            // Compound error codes, from the most to the least specific encoding.
+            _ if code & 0xEFFC == 0x000C => write!(f, "{} cache hierarchy error", level(code)),
+            _ if code & 0xEFF0 == 0x0010 => write!(
+                f, "{} {} TLB error", level(code), transaction_type(code >> 2),
+            ),
+            _ if code & 0xEF80 == 0x0080 => {
+                write!(f, "memory controller error ({}", memory_transaction(code >> 4))?;
+                match code & 0xF {
+                    0xF => write!(f, ", unspecified channel)"),
+                    channel => write!(f, ", channel {})", channel),
+                }
+            }
+            _ if code & 0xEF00 == 0x0100 => write!(
+                f, "{} {} cache error ({})", level(code), transaction_type(code >> 2), request(code >> 4),
+            ),
+            _ if code & 0xE800 == 0x0800 => write!(
+                f, "{} bus/interconnect error ({}, {}{})",
+                level(code),
+                participation(code >> 9),
+                request(code >> 4),
+                if code & (1 << 8) != 0 { ", timed out" } else { "" },
+            ),
+            _ => write!(f, "unknown error ({:#06X})", code),
+        }
+    }
+}
+
+/// A valid error read from a machine check bank.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BankRecord {
+    /// The CPU whose bank held the error.
+    pub cpu: CpuId,
+    /// The index of the bank that held the error.
+    pub bank: u8,
+    /// The contents of the bank's `IA32_MCi_STATUS` MSR.
+    pub status: BankStatus,
+    /// The contents of the bank's `IA32_MCi_ADDR` MSR, if valid.
+    pub addr: Option<u64>,
+    /// The contents of the bank's `IA32_MCi_MISC` MSR, if valid.
+    pub misc: Option<u64>,
+}
+
+impl fmt::Display for BankRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "CPU {} bank {}: {} {}",
+            self.cpu, self.bank,
+            if self.status.is_corrected() { "corrected" } else { "uncorrected" },
+            self.status.error_code(),
+        )?;
+        if self.status.context_corrupt() {
+            write!(f, ", processor context corrupt")?;
+        }
+        if self.status.overflowed() {
+            write!(f, ", previous errors lost")?;
+        }
+        write!(f, " [status {:#018X}", self.status.0)?;
+        if let Some(addr) = self.addr {
+            write!(f, ", addr {:#X}", addr)?;
+        }
+        if let Some(misc) = self.misc {
+            write!(f, ", misc {:#X}", misc)?;
+        }
+        write!(f, "]")
+    }
+}
@@ -0,0 +1,327 @@
+//! Support for the x86 Machine Check Architecture (MCA).
+//!
+//! Each CPU has a number of machine check banks, as reported by `IA32_MCG_CAP`,
+//! each of which reports errors detected by one hardware unit, e.g., a cache or memory controller.
+//! Uncorrected errors raise a machine check exception (`#MC`), whose handler in `exceptions_full`
+//! invokes [`dump_banks()`] to print the decoded contents of all valid banks before halting.
+//! Corrected errors, e.g., single-bit ECC errors, are only recorded in the banks without any exception,
+//! so [`init()`] starts a task on each CPU that polls its banks every [`POLL_PERIOD`],
+//! then logs, counts, and clears every valid error it finds.
+//!
+//! The number of errors found in each bank is reported in the `mca` metrics group
+//! and, along with the most recent errors, in the `/mca` file.
+//!
+//! MCA support is detected at runtime via `CPUID`; on CPUs or VMs without it,
+//! no machine check MSRs are accessed at all.
+
+#![no_std]
+
+extern crate alloc;
+
+mod decode;
+
+pub use decode::{BankRecord, BankStatus, McaErrorCode};
+
+use alloc::{collections::VecDeque, format, string::String, sync::Arc};
+use core::{fmt::Write, sync::atomic::{AtomicU64, AtomicUsize, Ordering}};
+use cpu::CpuId;
+use fs_node::{DirRef, File, FileOrDir, FsNode, WeakDirRef};
+use io::{ByteReader, ByteWriter, IoError, KnownLength};
+use log::{error, info, warn};
+use memory::MappedPages;
+use msr::{IA32_MC0_ADDR, IA32_MC0_CTL, IA32_MC0_MISC, IA32_MC0_STATUS, IA32_MCG_CAP, IA32_MCG_CTL, IA32_MCG_STATUS};
+use raw_cpuid::CpuId as X86CpuIdInstr;
+use spin::Mutex;
+use time::Duration;
+use x86_64::registers::{control::{Cr4, Cr4Flags}, model_specific::Msr};
+
+/// The name of the file in the root directory that reports machine check errors.
+pub const MCA_FILE_NAME: &str = "mca";
+
+/// How often each CPU's machine check banks are polled for corrected errors.
+pub const POLL_PERIOD: Duration = Duration::from_secs(5);
+
+/// The maximum number of banks that can be tracked, which is the most that `IA32_MCG_CAP` can report.
+const MAX_BANKS: usize = 256;
+
+/// The number of most recent errors kept for the `/mca` file.
+const RECENT_ERRORS: usize = 32;
+
+/// The bank count field of `IA32_MCG_CAP`.
+const MCG_CAP_COUNT: u64 = 0xFF;
+/// Whether `IA32_MCG_CTL` is present, in `IA32_MCG_CAP`.
+const MCG_CAP_CTL_P: u64 = 1 << 8;
+/// Whether corrected machine check interrupts (CMCI) are supported, in `IA32_MCG_CAP`.
+const MCG_CAP_CMCI_P: u64 = 1 << 10;
+
+/// The largest number of banks reported by any CPU, or `0` until MCA has been initialized.
+static BANK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of corrected errors found in each bank across all CPUs.
+static CORRECTED: [AtomicU64; MAX_BANKS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; MAX_BANKS]
+};
+/// The number of uncorrected errors found by polling each bank across all CPUs,
+/// i.e., those that didn't raise a machine check exception.
+static UNCORRECTED: [AtomicU64; MAX_BANKS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; MAX_BANKS]
+};
+
+/// The most recent errors found by polling, oldest first.
+static RECENT: Mutex<VecDeque<BankRecord>> = Mutex::new(VecDeque::new());
+
+/// Returns `true` if this CPU supports both machine check exceptions and the machine check architecture.
+pub fn is_supported() -> bool {
+    X86CpuIdInstr::new()
+        .get_feature_info()
+        .map_or(false, |features| features.has_mce() && features.has_mca())
+}
+
+/// Enables machine check reporting on every CPU and starts polling each CPU's banks
+/// for corrected errors, then creates the `/mca` file and registers the `mca` metrics group.
+///
+/// Errors that are already present in the banks, e.g., from before a warm reboot, are logged first.
+/// If this CPU doesn't support MCA, this only logs that and does nothing else.
+pub fn init() -> Result<(), &'static str> {
+    if !is_supported() {
+        info!("Machine check architecture is not supported, skipping MCA initialization");
+        return Ok(());
+    }
+
+    for cpu in cpu::cpus() {
+        spawn::new_task_builder(poll_loop, cpu)
+            .name(format!("mca_poll_{cpu}"))
+            .pin_on_cpu(cpu)
+            .spawn()?;
+    }
+
+    let file = Arc::new(Mutex::new(McaFile)) as fs_node::FileRef;
+    root::get_root().lock().insert(FileOrDir::File(file))?;
+    metrics::register_group("mca", report_metrics)
+}
+
+/// Returns the value of `IA32_MCG_CAP` on the current CPU.
+fn mcg_cap() -> u64 {
+    unsafe { Msr::new(IA32_MCG_CAP).read() }
+}
+
+/// Returns the number of machine check banks on the current CPU.
+fn bank_count() -> usize {
+    (mcg_cap() & MCG_CAP_COUNT) as usize
+}
+
+/// Returns the `IA32_MCi_*` MSR of the given bank, where `mc0` is the MSR for bank 0.
+fn bank_msr(mc0: u32, bank: usize) -> Msr {
+    Msr::new(mc0 + 4 * bank as u32)
+}
+
+/// Returns `true` if bank 0 must not be enabled by software on this CPU,
+/// which is the case for Intel P6-family processors before Nehalem,
+/// where its enable bits are managed by the platform firmware.
+fn skip_bank_zero_ctl() -> bool {
+    let cpuid = X86CpuIdInstr::new();
+    let is_intel = cpuid.get_vendor_info().map_or(false, |vendor| vendor.as_str() == "GenuineIntel");
+    cpuid.get_feature_info().map_or(false, |features| {
+        is_intel && features.family_id() == 6 && features.model_id() < 0x1A
+    })
+}
+
+/// Enables all machine check banks on the current CPU and then machine check exceptions,
+/// after logging and clearing any errors that the banks already hold.
+///
+/// Returns the number of banks on the current CPU.
+fn enable_current_cpu() -> usize {
+    let cap = mcg_cap();
+    let banks = (cap & MCG_CAP_COUNT) as usize;
+    BANK_COUNT.fetch_max(banks, Ordering::Relaxed);
+
+    // Errors held in the banks at this point occurred before this boot or during early boot.
+    let found = poll_current_cpu();
+    if found > 0 {
+        warn!("MCA: found {} error(s) logged on CPU {} before boot", found, cpu::current_cpu());
+    }
+
+    let skip_bank_zero = skip_bank_zero_ctl();
+    unsafe {
+        if cap & MCG_CAP_CTL_P != 0 {
+            Msr::new(IA32_MCG_CTL).write(u64::MAX);
+        }
+        for bank in 0..banks {
+            if bank == 0 && skip_bank_zero {
+                continue;
+            }
+            bank_msr(IA32_MC0_CTL, bank).write(u64::MAX);
+        }
+        Cr4::update(|flags| flags.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION));
+    }
+    banks
+}
+
+/// Reads the given bank on the current CPU, returning its error if it holds a valid one.
+fn read_bank(bank: usize) -> Option<BankRecord> {
+    let status = BankStatus(unsafe { bank_msr(IA32_MC0_STATUS, bank).read() });
+    if !status.is_valid() {
+        return None;
+    }
+    let addr = status.addr_valid().then(|| unsafe { bank_msr(IA32_MC0_ADDR, bank).read() });
+    let misc = status.misc_valid().then(|| unsafe { bank_msr(IA32_MC0_MISC, bank).read() });
+    Some(BankRecord { cpu: cpu::current_cpu(), bank: bank as u8, status, addr, misc })
+}
+
+/// Checks every bank of the current CPU for errors, logging, counting, and clearing each one it finds.
+///
+/// This is invoked periodically on each CPU once MCA has been initialized,
+/// but may also be invoked at any other time, e.g., to check for an error immediately.
+/// Returns the number of errors found.
+pub fn poll_current_cpu() -> usize {
+    if !is_supported() {
+        return 0;
+    }
+    let mut found = 0;
+    for bank in 0..bank_count().min(MAX_BANKS) {
+        let Some(record) = read_bank(bank) else { continue };
+        // Clearing the status frees the bank to record the next error.
+        unsafe { bank_msr(IA32_MC0_STATUS, bank).write(0) };
+        found += 1;
+
+        if record.status.is_corrected() {
+            CORRECTED[bank].fetch_add(1, Ordering::Relaxed);
+            warn!("MCA: {}", record);
+        } else {
+            UNCORRECTED[bank].fetch_add(1, Ordering::Relaxed);
+            error!("MCA: {}", record);
+        }
+        let mut recent = RECENT.lock();
+        if recent.len() >= RECENT_ERRORS {
+            recent.pop_front();
+        }
+        recent.push_back(record);
+    }
+    found
+}
+
+/// Invokes the given `func` with every valid error held in the current CPU's banks, without clearing them,
+/// and returns the value of `IA32_MCG_STATUS`, or `None` if MCA isn't supported.
+///
+/// This is intended for the machine check exception handler, so it neither allocates nor locks.
+pub fn dump_banks(mut func: impl FnMut(&BankRecord)) -> Option<u64> {
+    if !is_supported() {
+        return None;
+    }
+    for bank in 0..bank_count() {
+        if let Some(record) = read_bank(bank) {
+            func(&record);
+        }
+    }
+    Some(unsafe { Msr::new(IA32_MCG_STATUS).read() })
+}
+
+/// The entry point of the polling task, which must be pinned to the given CPU.
+fn poll_loop(cpu: CpuId) {
+    let banks = enable_current_cpu();
+    let cmci = mcg_cap() & MCG_CAP_CMCI_P != 0;
+    info!("MCA: enabled {} machine check banks on CPU {} (CMCI supported: {})", banks, cpu, cmci);
+    loop {
+        if sleep::sleep(POLL_PERIOD).is_err() {
+            return;
+        }
+        poll_current_cpu();
+    }
+}
+
+/// Returns the number of corrected and uncorrected errors found by polling the given bank across all CPUs.
+pub fn error_counts(bank: usize) -> (u64, u64) {
+    match (CORRECTED.get(bank), UNCORRECTED.get(bank)) {
+        (Some(corrected), Some(uncorrected)) => (corrected.load(Ordering::Relaxed), uncorrected.load(Ordering::Relaxed)),
+        _ => (0, 0),
+    }
+}
+
+/// Returns the most recent errors found by polling, oldest first.
+pub fn recent_errors() -> VecDeque<BankRecord> {
+    RECENT.lock().clone()
+}
+
+/// Reports the number of corrected and uncorrected errors found in each bank
+/// as `bank<N>.corrected` and `bank<N>.uncorrected`, for use with [`metrics::register_group()`].
+fn report_metrics(report: &mut dyn FnMut(&str, u64)) {
+    for bank in 0..BANK_COUNT.load(Ordering::Relaxed) {
+        let (corrected, uncorrected) = error_counts(bank);
+        report(&format!("bank{bank}.corrected"), corrected);
+        report(&format!("bank{bank}.uncorrected"), uncorrected);
+    }
+}
+
+/// Returns a human-readable summary of the errors found in each bank and the most recent errors.
+pub fn report() -> String {
+    let mut out = String::new();
+    if !is_supported() {
+        let _ = writeln!(out, "Machine check architecture: not supported");
+        return out;
+    }
+    let banks = BANK_COUNT.load(Ordering::Relaxed);
+    let _ = writeln!(out, "Machine check architecture: {banks} banks, polled every {:?}", POLL_PERIOD);
+    let _ = writeln!(out, "{:<6} {:>12} {:>12}", "BANK", "CORRECTED", "UNCORRECTED");
+    for bank in 0..banks {
+        let (corrected, uncorrected) = error_counts(bank);
+        let _ = writeln!(out, "{bank:<6} {corrected:>12} {uncorrected:>12}");
+    }
+    let recent = RECENT.lock();
+    let _ = writeln!(out, "Recent errors ({}):", recent.len());
+    for record in recent.iter() {
+        let _ = writeln!(out, "  {record}");
+    }
+    out
+}
+
+/// A lazily-generated file that reports the machine check errors found so far.
+struct McaFile;
+
+impl FsNode for McaFile {
+    fn get_name(&self) -> String {
+        String::from(MCA_FILE_NAME)
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        Some(root::get_root().clone())
+    }
+
+    fn set_parent_dir(&mut self, _: WeakDirRef) {
+        // do nothing
+    }
+}
+
+impl ByteReader for McaFile {
+    fn read_at(&mut self, buf: &mut [u8], offset: usize) -> Result<usize, IoError> {
+        let output = report();
+        if offset > output.len() {
+            return Err(IoError::InvalidInput);
+        }
+        let count = core::cmp::min(buf.len(), output.len() - offset);
+        buf[..count].copy_from_slice(&output.as_bytes()[offset..(offset + count)]);
+        Ok(count)
+    }
+}
+
+impl ByteWriter for McaFile {
+    fn write_at(&mut self, _buffer: &[u8], _offset: usize) -> Result<usize, IoError> {
+        Err(IoError::from("the machine check report is read-only"))
+    }
+    fn flush(&mut self) -> Result<(), IoError> { Ok(()) }
+}
+
+impl KnownLength for McaFile {
+    fn len(&self) -> usize {
+        report().len()
+    }
+}
+
+impl File for McaFile {
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("the machine check report is autogenerated, cannot be memory mapped")
+    }
+}
@@ -0,0 +1,157 @@
+//! Per-owner accounting of the memory frames backing anonymous mappings.
+//!
+//! Every [`MappedPages`] created by [`Mapper::map_allocated_pages()`] charges its pages
+//! to the [`MemoryAccount`] of the current owner, which is typically the current task,
+//! and releases that charge when it is dropped.
+//! If the charge would exceed the account's limit, the mapping fails with
+//! [`QUOTA_EXCEEDED`] just like any other allocation failure.
+//!
+//! This crate doesn't know about tasks, so the `task` crate registers
+//! a callback that returns the current owner's account via [`set_current_account_func()`].
+//! Until then, mappings are not charged to anyone.
+//!
+//! [`MappedPages`]: crate::MappedPages
+//! [`Mapper::map_allocated_pages()`]: crate::Mapper::map_allocated_pages
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Once;
+
+/// The error returned when an allocation would exceed its owner's memory limit.
+pub const QUOTA_EXCEEDED: &str = "memory quota exceeded";
+
+/// The callback that returns the memory account of the current owner, if any.
+static CURRENT_ACCOUNT_FUNC: Once<fn() -> Option<Arc<MemoryAccount>>> = Once::new();
+
+/// Sets the function callback that returns the [`MemoryAccount`]
+/// that newly-mapped memory should be charged to.
+pub fn set_current_account_func(func: fn() -> Option<Arc<MemoryAccount>>) {
+    CURRENT_ACCOUNT_FUNC.call_once(|| func);
+}
+
+/// Tracks the number of pages charged to a single owner and that owner's limit.
+#[derive(Debug)]
+pub struct MemoryAccount {
+    used_pages: AtomicUsize,
+    /// The maximum number of pages that can be charged, where `usize::MAX` means unlimited.
+    limit_pages: AtomicUsize,
+}
+
+impl MemoryAccount {
+    /// Creates a new account with no pages charged to it and no limit.
+    pub const fn new() -> MemoryAccount {
+        MemoryAccount {
+            used_pages: AtomicUsize::new(0),
+            limit_pages: AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    /// Returns the number of pages currently charged to this account.
+    pub fn used_pages(&self) -> usize {
+        self.used_pages.load(Ordering::Relaxed)
+    }
+
+    /// Returns the maximum number of pages that can be charged to this account,
+    /// or `None` if it is unlimited.
+    pub fn limit_pages(&self) -> Option<usize> {
+        match self.limit_pages.load(Ordering::Relaxed) {
+            usize::MAX => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Sets the maximum number of pages that can be charged to this account,
+    /// or removes the limit if `None`.
+    ///
+    /// Lowering the limit below the current usage doesn't free anything;
+    /// it only causes further charges to fail.
+    pub fn set_limit_pages(&self, limit: Option<usize>) {
+        self.limit_pages.store(limit.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    /// Charges `num_pages` to this account, failing if that would exceed its limit.
+    fn try_charge(&self, num_pages: usize) -> Result<(), &'static str> {
+        let limit = self.limit_pages.load(Ordering::Relaxed);
+        self.used_pages
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(num_pages).filter(|new| *new <= limit)
+            })
+            .map(|_| ())
+            .map_err(|_| QUOTA_EXCEEDED)
+    }
+
+    /// Charges `num_pages` to this account regardless of its limit.
+    fn force_charge(&self, num_pages: usize) {
+        self.used_pages.fetch_add(num_pages, Ordering::Relaxed);
+    }
+
+    /// Releases `num_pages` previously charged to this account.
+    fn uncharge(&self, num_pages: usize) {
+        let _ = self.used_pages.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            Some(used.saturating_sub(num_pages))
+        });
+    }
+}
+
+impl Default for MemoryAccount {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A number of pages charged to a [`MemoryAccount`], which are released when this is dropped.
+#[derive(Debug)]
+pub(crate) struct MemoryCharge {
+    account: Arc<MemoryAccount>,
+    num_pages: usize,
+}
+
+impl MemoryCharge {
+    /// Charges `num_pages` to the current owner's account.
+    ///
+    /// Returns `Ok(None)` if there is no current owner to charge.
+    pub(crate) fn for_current_owner(num_pages: usize) -> Result<Option<MemoryCharge>, &'static str> {
+        let Some(account) = CURRENT_ACCOUNT_FUNC.get().and_then(|func| func()) else {
+            return Ok(None);
+        };
+        account.try_charge(num_pages)?;
+        Ok(Some(MemoryCharge { account, num_pages }))
+    }
+
+    /// Moves this charge to the given `account`, ignoring that account's limit.
+    pub(crate) fn transfer_to(&mut self, account: Arc<MemoryAccount>) {
+        account.force_charge(self.num_pages);
+        self.account.uncharge(self.num_pages);
+        self.account = account;
+    }
+
+    /// Returns the account that this charge is billed to.
+    pub(crate) fn account(&self) -> &Arc<MemoryAccount> {
+        &self.account
+    }
+
+    /// Returns `true` if both charges are billed to the same account.
+    pub(crate) fn same_account(&self, other: &MemoryCharge) -> bool {
+        Arc::ptr_eq(&self.account, &other.account)
+    }
+
+    /// Absorbs the given charge, which must be billed to the same account, into this one.
+    pub(crate) fn absorb(&mut self, other: MemoryCharge) {
+        debug_assert!(self.same_account(&other));
+        self.num_pages += other.num_pages;
+        core::mem::forget(other);
+    }
+
+    /// Splits off `num_pages` of this charge into a new charge on the same account.
+    pub(crate) fn split_off(&mut self, num_pages: usize) -> MemoryCharge {
+        let num_pages = num_pages.min(self.num_pages);
+        self.num_pages -= num_pages;
+        MemoryCharge { account: self.account.clone(), num_pages }
+    }
+}
+
+impl Drop for MemoryCharge {
+    fn drop(&mut self) {
+        self.account.uncharge(self.num_pages);
+    }
+}
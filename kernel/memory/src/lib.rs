@@ -4,6 +4,18 @@
 //! interface that unifies the usage of arbitrary memory regions
 //! with that of Rust's safe type system and lifetimes.
 //!
+//! ## Accessing physical memory
+//! Theseus intentionally does not map all of physical memory into a fixed "direct map" window,
+//! so there is no `phys_to_virt()` that simply adds an offset to a physical address.
+//! Every virtual-to-physical mapping is owned by exactly one [`MappedPages`] object,
+//! which guarantees that no two mappings alias the same frame unknowingly.
+//!
+//! To access arbitrary physical memory, e.g., an ACPI table or a DMA descriptor,
+//! map it with [`map_frame_range()`] (or [`Mapper::map_allocated_pages_to()`])
+//! and keep the resulting [`MappedPages`] for as long as it's needed.
+//! To go in the other direction, [`translate()`] returns the physical address
+//! that a virtual address is currently mapped to.
+//!
 //! ## Acknowledgments
 //! Some of the internal page table management code was based on
 //! Philipp Oppermann's [blog_os], but has since changed significantly.
@@ -16,11 +28,15 @@
 
 extern crate alloc;
 
+mod accounting;
 mod paging;
+pub use self::accounting::{MemoryAccount, QUOTA_EXCEEDED, set_current_account_func};
 pub use self::paging::{
     PageTable, Mapper, Mutability, Mutable, Immutable,
     MappedPages, BorrowedMappedPages, BorrowedSliceMappedPages,
-    translate,
+    MappedRegion, translate, page_flags, is_mapped, page_table_frame_count,
+    AddressRegion, Permissions, PageMapping, PageTableStats, page_table_stats, mappings_in,
+    FREED_FRAME_POISON,
 };
 
 pub use memory_structs::*;
@@ -40,6 +56,7 @@ pub use page_allocator::{
 pub use frame_allocator::{
     AllocatedFrames,
     UnmappedFrames,
+    FrameKind,
     allocate_frames_deferred,
     allocate_frames_by_bytes_deferred,
     allocate_frames,
@@ -47,13 +64,24 @@ pub use frame_allocator::{
     allocate_frames_by_bytes,
     allocate_frames_by_bytes_at,
     dump_frame_allocator_state,
+    free_frame_count,
+    FragmentationReport,
+    FRAGMENTATION_SIZE_CLASSES,
+    fragmentation_report,
+    coalesce_free_frames,
+    mark_reclaimable_after_boot,
+    reclaim_boot_frames,
 };
 
 #[cfg(target_arch = "x86_64")]
-use memory_x86_64::{tlb_flush_virt_addr, tlb_flush_all, get_p4, find_section_memory_bounds, get_vga_mem_addr};
+use memory_x86_64::{tlb_flush_virt_addr, tlb_flush_all, get_p4, find_section_memory_bounds, get_vga_mem_addr, active_paging_depth};
+#[cfg(target_arch = "x86_64")]
+pub use memory_x86_64::{cache_flush_range, enable_global_pages};
 
 #[cfg(target_arch = "aarch64")]
 use memory_aarch64::{tlb_flush_virt_addr, tlb_flush_all, get_p4, find_section_memory_bounds};
+#[cfg(target_arch = "aarch64")]
+pub use memory_aarch64::cache_flush_range;
 
 pub use pte_flags::*;
 
@@ -94,13 +122,22 @@ pub struct MemoryManagementInfo {
 }
 
 /// Mapping flags that can be used to map MMIO registers.
+///
+/// On x86_64, these mappings are global, as device registers are mapped identically in every page table.
 pub const MMIO_FLAGS: PteFlags = PteFlags::from_bits_truncate(
     PteFlags::new().bits()
     | PteFlags::VALID.bits()
     | PteFlags::WRITABLE.bits()
     | PteFlags::DEVICE_MEMORY.bits()
+    | MMIO_GLOBAL_BITS
 );
 
+/// Global pages are only enabled on x86_64.
+#[cfg(target_arch = "x86_64")]
+const MMIO_GLOBAL_BITS: u64 = PteFlags::GLOBAL.bits();
+#[cfg(not(target_arch = "x86_64"))]
+const MMIO_GLOBAL_BITS: u64 = 0;
+
 /// Mapping flags that can be used to map DMA (Direct Memory Access) memory.
 pub const DMA_FLAGS: PteFlags = PteFlags::from_bits_truncate(
     PteFlags::new().bits()
@@ -125,12 +162,17 @@ pub fn create_contiguous_mapping<F: Into<PteFlagsArch>>(
     let allocated_pages = allocate_pages_by_bytes(size_in_bytes).ok_or("memory::create_contiguous_mapping(): couldn't allocate contiguous pages!")?;
     let allocated_frames = allocate_frames_by_bytes(size_in_bytes).ok_or("memory::create_contiguous_mapping(): couldn't allocate contiguous frames!")?;
     let starting_phys_addr = allocated_frames.start_address();
-    let mp = kernel_mmi_ref.lock().page_table.map_allocated_pages_to(allocated_pages, allocated_frames, flags)?;
+    let mp = kernel_mmi_ref.lock().page_table.map_allocated_pages_to_kind(
+        allocated_pages, allocated_frames, flags, FrameKind::Ram,
+    )?;
     Ok((mp, starting_phys_addr))
 }
 
 
 /// A convenience function that maps randomly-allocated pages to the given range of frames.
+///
+/// This is intended for device memory, e.g., MMIO registers;
+/// in debug builds, it asserts that the given frames don't lie within general-purpose RAM.
 /// 
 /// # Locking / Deadlock
 /// Currently, this function acquires the lock on the frame allocator and the kernel's `MemoryManagementInfo` instance.
@@ -144,7 +186,9 @@ pub fn map_frame_range<F: Into<PteFlagsArch>>(
     let allocated_pages = allocate_pages_by_bytes(size_in_bytes).ok_or("memory::map_range(): couldn't allocate contiguous pages!")?;
     let allocated_frames = allocate_frames_by_bytes_at(start_address, size_in_bytes)
         .map_err(|_| "memory::map_range(): couldn't allocate contiguous frames!")?;
-    kernel_mmi_ref.lock().page_table.map_allocated_pages_to(allocated_pages, allocated_frames, flags)
+    kernel_mmi_ref.lock().page_table.map_allocated_pages_to_kind(
+        allocated_pages, allocated_frames, flags, FrameKind::Device,
+    )
 }
 
 
@@ -166,6 +210,51 @@ pub fn create_mapping<F: Into<PteFlagsArch>>(
 }
 
 
+/// Allocates `num_frames` contiguous frames and fills them with zeros.
+///
+/// The frame allocator makes no promises about the contents of the frames it returns,
+/// which may still hold data from whoever last used them, e.g., another task.
+/// Use this instead of [`allocate_frames()`] if the frames must not expose such data,
+/// or if the caller assumes they are zeroed.
+///
+/// # Locking / Deadlock
+/// Currently, this function acquires the lock on the frame allocator and the kernel's `MemoryManagementInfo` instance.
+/// Thus, the caller should ensure that the locks on those two variables are not held when invoking this function.
+pub fn allocate_zeroed_frames(num_frames: usize) -> Result<AllocatedFrames, &'static str> {
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("allocate_zeroed_frames(): KERNEL_MMI was not yet initialized!")?;
+    let frames = allocate_frames(num_frames).ok_or("memory::allocate_zeroed_frames(): couldn't allocate frames!")?;
+    kernel_mmi_ref.lock().page_table.zero_frames(frames)
+}
+
+/// Allocates a single frame filled with zeros. See [`allocate_zeroed_frames()`].
+pub fn allocate_zeroed_frame() -> Result<AllocatedFrames, &'static str> {
+    allocate_zeroed_frames(1)
+}
+
+
+/// A convenience function that temporarily maps the given inactive `PageTable`
+/// such that the closure `f` can modify it, returning the closure's result.
+///
+/// This is a wrapper around [`PageTable::with()`] that uses the kernel's page table,
+/// which is always the currently-active one, so callers don't need to lock it themselves.
+/// For example, `f` can return the virtual address of a mapping it created in `other_table`.
+///
+/// # Locking / Deadlock
+/// Currently, this function acquires the lock on the kernel's `MemoryManagementInfo` instance
+/// for the duration of `f`.
+/// Thus, the caller should ensure that lock is not held when invoking this function,
+/// and `f` must not call any function that acquires it, e.g., [`create_mapping()`].
+pub fn with_inactive_table<F, R>(
+    other_table: &mut PageTable,
+    f: F,
+) -> Result<R, &'static str>
+    where F: FnOnce(&mut Mapper) -> R
+{
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("with_inactive_table(): KERNEL_MMI was not yet initialized!")?;
+    kernel_mmi_ref.lock().page_table.with(other_table, |other_mapper, _| Ok(f(other_mapper)))
+}
+
+
 /// Creates an identity mapping at a random available virtual and physical address.
 ///
 /// The returned `MappedPages` is guaranteed to have virtual pages mapped to physical frames
@@ -290,6 +379,17 @@ pub fn init(
     boot_info: &impl BootInformation,
     kernel_stack_start: VirtualAddress,
 ) -> Result<InitialMemoryMappings, &'static str> {
+    // Theseus's virtual memory layout and page table code assume 4-level paging.
+    #[cfg(target_arch = "x86_64")] {
+        let depth = active_paging_depth();
+        if depth != PagingDepth::ACTIVE {
+            log::error!("The bootloader enabled {}-level paging, but Theseus only supports {}-level paging",
+                depth.levels(), PagingDepth::ACTIVE.levels(),
+            );
+            return Err("the bootloader enabled 5-level paging (LA57), which Theseus doesn't support");
+        }
+    }
+
     let low_memory_frames   = FrameRange::from_phys_addr(PhysicalAddress::zero(), 0x10_0000); // suggested by most OS developers
     
     // Now set up the list of free regions and reserved regions so we can initialize the frame allocator.
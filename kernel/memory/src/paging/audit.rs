@@ -0,0 +1,288 @@
+//! Audits the mappings of the active page table, e.g., to find memory bloat
+//! or to check that every kernel section is mapped with only the permissions it needs.
+//!
+//! The page table hierarchy is walked one table at a time:
+//! each table's entries are copied through the recursive mapping while holding the kernel's
+//! MMI lock, which is released before the copied entries are examined or the next table is walked.
+//! Thus, an audit never blocks other mappings for longer than it takes to copy one table,
+//! at the cost of not being an atomic snapshot of the whole hierarchy.
+
+use alloc::{vec, vec::Vec};
+use core::fmt;
+use crate::{get_kernel_mmi_ref, VirtualAddress, PhysicalAddress};
+use super::table::{Table, TableLevel};
+use pte_flags::{PteFlagsArch, PTE_FRAME_MASK};
+use kernel_config::memory::{
+    PAGE_SIZE, ENTRIES_PER_PAGE_TABLE, P2_INDEX_SHIFT,
+    KERNEL_TEXT_P4_INDEX, KERNEL_HEAP_P4_INDEX, RANDOMIZED_STACK_P4_INDEX,
+    RECURSIVE_P4_INDEX, UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX,
+};
+
+/// The regions of the virtual address space, as laid out in [`kernel_config::memory`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressRegion {
+    /// The higher-half kernel, i.e., its text, rodata, and data sections.
+    KernelText,
+    /// The kernel heap.
+    KernelHeap,
+    /// The randomly-placed task stacks.
+    RandomizedStacks,
+    /// Every other part of the address space, e.g., identity mappings and MMIO regions.
+    Other,
+}
+
+impl AddressRegion {
+    /// All regions, in the order used to index [`PageTableStats::pages_by_region`].
+    pub const ALL: [AddressRegion; 4] = [
+        AddressRegion::KernelText,
+        AddressRegion::KernelHeap,
+        AddressRegion::RandomizedStacks,
+        AddressRegion::Other,
+    ];
+
+    /// Returns the region containing the given `VirtualAddress`.
+    pub fn containing(vaddr: VirtualAddress) -> AddressRegion {
+        match vaddr.page_table_index(4) {
+            KERNEL_TEXT_P4_INDEX => AddressRegion::KernelText,
+            KERNEL_HEAP_P4_INDEX => AddressRegion::KernelHeap,
+            RANDOMIZED_STACK_P4_INDEX => AddressRegion::RandomizedStacks,
+            _ => AddressRegion::Other,
+        }
+    }
+
+    /// Returns a short human-readable name of this region.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AddressRegion::KernelText => "kernel text",
+            AddressRegion::KernelHeap => "kernel heap",
+            AddressRegion::RandomizedStacks => "stacks",
+            AddressRegion::Other => "other",
+        }
+    }
+}
+
+/// The effective permissions of a mapped page, which account for the entries
+/// at every page table level used to translate it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permissions {
+    ReadOnly,
+    ReadWrite,
+    ReadExecute,
+    /// Writable and executable, which violates W^X.
+    ReadWriteExecute,
+}
+
+impl Permissions {
+    /// All permissions, in the order used to index [`PageTableStats::pages_by_permissions`].
+    pub const ALL: [Permissions; 4] = [
+        Permissions::ReadOnly,
+        Permissions::ReadWrite,
+        Permissions::ReadExecute,
+        Permissions::ReadWriteExecute,
+    ];
+
+    pub fn new(writable: bool, executable: bool) -> Permissions {
+        match (writable, executable) {
+            (false, false) => Permissions::ReadOnly,
+            (true, false)  => Permissions::ReadWrite,
+            (false, true)  => Permissions::ReadExecute,
+            (true, true)   => Permissions::ReadWriteExecute,
+        }
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Permissions::ReadOnly => "R--",
+            Permissions::ReadWrite => "RW-",
+            Permissions::ReadExecute => "R-X",
+            Permissions::ReadWriteExecute => "RWX",
+        })
+    }
+}
+
+/// A single page table entry that maps a (possibly huge) page.
+///
+/// See [`mappings_in()`].
+#[derive(Clone, Copy, Debug)]
+pub struct PageMapping {
+    pub start: VirtualAddress,
+    /// 4KiB for a regular page, or 2MiB or 1GiB for a huge page.
+    pub size_in_bytes: usize,
+    pub frame: PhysicalAddress,
+    /// The flags of the lowest-level entry that maps this page.
+    pub flags: PteFlagsArch,
+    pub permissions: Permissions,
+}
+
+/// Statistics about the mappings of the active page table.
+///
+/// See [`page_table_stats()`].
+#[derive(Clone, Debug, Default)]
+pub struct PageTableStats {
+    /// The number of mapped pages in each region, indexed in the order of [`AddressRegion::ALL`].
+    ///
+    /// Pages are counted in units of 4KiB, such that a 2MiB huge page counts as 512 pages.
+    pub pages_by_region: [usize; AddressRegion::ALL.len()],
+    /// The number of mapped pages with each permission, indexed in the order of [`Permissions::ALL`].
+    ///
+    /// Pages are counted in units of 4KiB, such that a 2MiB huge page counts as 512 pages.
+    pub pages_by_permissions: [usize; Permissions::ALL.len()],
+    /// The number of regular 4KiB pages that are mapped.
+    pub pages_4k: usize,
+    /// The number of 2MiB huge pages that are mapped.
+    pub pages_2m: usize,
+    /// The number of 1GiB huge pages that are mapped.
+    pub pages_1g: usize,
+    /// Every page that is both writable and executable, which should be none.
+    pub writable_executable: Vec<PageMapping>,
+    /// The number of frames used by the page tables themselves, including the top-level P4 table.
+    pub page_table_frames: usize,
+}
+
+/// Walks the active page table and returns statistics about all of its mappings.
+///
+/// The recursive P4 entries used to access the page tables themselves are skipped.
+pub fn page_table_stats() -> Result<PageTableStats, &'static str> {
+    let mut stats = PageTableStats::default();
+    let page_table_frames = walk(0, usize::MAX, &mut |mapping| {
+        let pages = mapping.size_in_bytes / PAGE_SIZE;
+        let region = AddressRegion::containing(mapping.start);
+        if let Some(i) = AddressRegion::ALL.iter().position(|r| *r == region) {
+            stats.pages_by_region[i] += pages;
+        }
+        if let Some(i) = Permissions::ALL.iter().position(|p| *p == mapping.permissions) {
+            stats.pages_by_permissions[i] += pages;
+        }
+        match mapping.size_in_bytes {
+            PAGE_SIZE => stats.pages_4k += 1,
+            HUGE_2M => stats.pages_2m += 1,
+            _ => stats.pages_1g += 1,
+        }
+        if mapping.permissions == Permissions::ReadWriteExecute {
+            stats.writable_executable.push(mapping);
+        }
+    })?;
+    stats.page_table_frames = page_table_frames;
+    Ok(stats)
+}
+
+/// Walks the active page table and returns every mapping that intersects
+/// the `size_in_bytes` bytes of virtual memory starting at `start`, in order of address.
+pub fn mappings_in(start: VirtualAddress, size_in_bytes: usize) -> Result<Vec<PageMapping>, &'static str> {
+    if size_in_bytes == 0 {
+        return Ok(Vec::new());
+    }
+    let last = start.value().saturating_add(size_in_bytes - 1);
+    let mut mappings = Vec::new();
+    walk(start.value(), last, &mut |mapping| mappings.push(mapping))?;
+    Ok(mappings)
+}
+
+const HUGE_2M: usize = PAGE_SIZE << P2_INDEX_SHIFT;
+
+/// Invokes `visit` for every page mapped by the active page table
+/// that intersects the virtual addresses from `first` to `last`, inclusive.
+///
+/// Returns the number of page tables that were walked.
+fn walk(first: usize, last: usize, visit: &mut dyn FnMut(PageMapping)) -> Result<usize, &'static str> {
+    walk_table(&mut [0; 4], 0, &mut [PteFlagsArch::new(); 4], first, last, visit)
+}
+
+/// Walks the page table at the given `level` (0 for P4, 3 for P1)
+/// that is reached through the first `level` of the given `indices`.
+///
+/// `ancestor_flags` holds the flags of the entries that led to this table.
+fn walk_table(
+    indices: &mut [usize; 4],
+    level: usize,
+    ancestor_flags: &mut [PteFlagsArch; 4],
+    first: usize,
+    last: usize,
+    visit: &mut dyn FnMut(PageMapping),
+) -> Result<usize, &'static str> {
+    let Some(entries) = copy_table(&indices[..level])? else {
+        // This table was unmapped after its parent table was copied.
+        return Ok(0);
+    };
+    let mut tables = 1;
+    let entry_size = PAGE_SIZE << (9 * (3 - level));
+
+    for (i, &value) in entries.iter().enumerate() {
+        if level == 0 && (i == RECURSIVE_P4_INDEX || i == UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX) {
+            continue;
+        }
+        let flags = PteFlagsArch::from_bits_truncate(value & !PTE_FRAME_MASK);
+        if !flags.is_valid() {
+            continue;
+        }
+        indices[level] = i;
+        indices[level + 1 ..].fill(0);
+        let start = VirtualAddress::from_page_table_indices(*indices, 0);
+        if start.value() > last || start.value() + (entry_size - 1) < first {
+            continue;
+        }
+        ancestor_flags[level] = flags;
+
+        if level == 3 || is_huge(level, &flags) {
+            let flags_used = &ancestor_flags[..=level];
+            let permissions = Permissions::new(
+                flags_used.iter().all(|f| f.is_writable()),
+                flags_used.iter().all(|f| f.is_executable()),
+            );
+            visit(PageMapping {
+                start,
+                size_in_bytes: entry_size,
+                frame: PhysicalAddress::new_canonical((value & PTE_FRAME_MASK) as usize),
+                flags,
+                permissions,
+            });
+        } else {
+            tables += walk_table(indices, level + 1, ancestor_flags, first, last, visit)?;
+        }
+    }
+    Ok(tables)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn is_huge(level: usize, flags: &PteFlagsArch) -> bool {
+    (level == 1 || level == 2) && flags.is_huge()
+}
+
+#[cfg(target_arch = "aarch64")]
+fn is_huge(_level: usize, _flags: &PteFlagsArch) -> bool {
+    false
+}
+
+/// Copies the raw entries of the active page table at the given `indices`,
+/// e.g., `[]` for the P4 table or `[p4_index, p3_index]` for a P2 table.
+///
+/// Returns `None` if that table is no longer mapped.
+fn copy_table(indices: &[usize]) -> Result<Option<Vec<u64>>, &'static str> {
+    fn copy<L: TableLevel>(table: &Table<L>, entries: &mut [u64]) {
+        for (i, entry) in entries.iter_mut().enumerate() {
+            *entry = table[i].value();
+        }
+    }
+
+    // Allocate before taking the MMI lock, as growing the heap may need to map pages.
+    let mut entries = vec![0; ENTRIES_PER_PAGE_TABLE];
+    let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("page table audit: KERNEL_MMI was not yet initialized!")?;
+    let kernel_mmi = kernel_mmi_ref.lock();
+    let p4 = kernel_mmi.page_table.p4();
+    let found = match *indices {
+        [] => Some(copy(p4, &mut entries)),
+        [i4] => p4.next_table(i4).map(|p3| copy(p3, &mut entries)),
+        [i4, i3] => p4.next_table(i4)
+            .and_then(|p3| p3.next_table(i3))
+            .map(|p2| copy(p2, &mut entries)),
+        [i4, i3, i2] => p4.next_table(i4)
+            .and_then(|p3| p3.next_table(i3))
+            .and_then(|p2| p2.next_table(i2))
+            .map(|p1| copy(p1, &mut entries)),
+        _ => None,
+    };
+    drop(kernel_mmi);
+    Ok(found.map(|_| entries))
+}
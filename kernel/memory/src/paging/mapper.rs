@@ -7,6 +7,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use alloc::{sync::Arc, vec::Vec};
 use core::{
     borrow::{Borrow, BorrowMut},
     cmp::Ordering,
@@ -19,21 +20,26 @@ use core::{
 };
 use log::{error, warn, debug, trace};
 use memory_structs::{PageSize, Page4K};
-use crate::{BROADCAST_TLB_SHOOTDOWN_FUNC, VirtualAddress, PhysicalAddress, Page, Frame, FrameRange, AllocatedPages, AllocatedFrames, UnmappedFrames}; 
+use crate::accounting::{MemoryAccount, MemoryCharge};
+use crate::{BROADCAST_TLB_SHOOTDOWN_FUNC, VirtualAddress, PhysicalAddress, Page, Frame, FrameRange, FrameKind, PageRange, AllocatedPages, AllocatedFrames, UnmappedFrames}; 
 use crate::paging::{
     get_current_p4,
     table::{P4, UPCOMING_P4, Table, Level4},
 };
 use pte_flags::PteFlagsArch;
 use spin::Once;
-use kernel_config::memory::PAGE_SIZE;
+use kernel_config::memory::{
+    PAGE_SIZE, ENTRIES_PER_PAGE_TABLE, P2_INDEX_SHIFT, P3_INDEX_SHIFT,
+    RECURSIVE_P4_INDEX, UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX,
+};
 use super::tlb_flush_virt_addr;
 use zerocopy::FromBytes;
 use page_table_entry::UnmapResult;
 use owned_borrowed_trait::{OwnedOrBorrowed, Owned, Borrowed};
 
-#[cfg(target_arch = "x86_64")]
-use kernel_config::memory::ENTRIES_PER_PAGE_TABLE;
+/// The 32-bit pattern that the frames of a dropped `MappedPages` are filled with in debug builds,
+/// which makes any use of a freed frame's stale contents recognizable.
+pub const FREED_FRAME_POISON: u32 = 0xDEAD_F4EE;
 
 /// This is a private callback used to convert `UnmappedFrameRange` into `UnmappedFrames`.
 /// 
@@ -60,6 +66,34 @@ pub fn translate(virtual_address: VirtualAddress) -> Option<PhysicalAddress> {
     Mapper::from_current().translate(virtual_address)
 }
 
+/// A convenience function to get the flags of the page table entry that maps
+/// the given virtual address in the currently-active page table.
+///
+/// Returns `None` if the given virtual address is not mapped.
+pub fn page_flags(virtual_address: VirtualAddress) -> Option<PteFlagsArch> {
+    Mapper::from_current().page_flags(virtual_address)
+}
+
+/// A convenience function to check whether the given `page` is mapped
+/// in the currently-active page table.
+///
+/// See [`Mapper::is_mapped()`].
+pub fn is_mapped(page: Page) -> bool {
+    Mapper::from_current().is_mapped(page)
+}
+
+/// A contiguous range of virtual memory whose pages are all mapped
+/// with the same effective permissions.
+///
+/// See [`Mapper::mapped_regions()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MappedRegion {
+    pub start: VirtualAddress,
+    pub size_in_bytes: usize,
+    pub writable: bool,
+    pub executable: bool,
+}
+
 pub struct Mapper {
     p4: Unique<Table<Level4>>,
     /// The Frame contaning the top-level P4 page table.
@@ -134,6 +168,97 @@ impl Mapper {
         );
     }
 
+    /// Returns all regions of virtual memory mapped by this page table,
+    /// in which adjacent pages with the same effective permissions are merged.
+    ///
+    /// A page's effective permissions account for the entries at every page table level
+    /// used to translate it, e.g., a page is only writable if all of those entries are writable.
+    /// The recursive P4 entries used to access the page tables themselves are skipped.
+    pub fn mapped_regions(&self) -> Vec<MappedRegion> {
+        fn vaddr(p4_index: usize, p3_index: usize, p2_index: usize, p1_index: usize) -> VirtualAddress {
+            VirtualAddress::from_page_table_indices([p4_index, p3_index, p2_index, p1_index], 0)
+        }
+        fn add(regions: &mut Vec<MappedRegion>, start: VirtualAddress, size_in_bytes: usize, flags: [PteFlagsArch; 4]) {
+            let writable = flags.iter().all(|f| f.is_writable());
+            let executable = flags.iter().all(|f| f.is_executable());
+            if let Some(last) = regions.last_mut() {
+                if last.start + last.size_in_bytes == start
+                    && last.writable == writable
+                    && last.executable == executable
+                {
+                    last.size_in_bytes += size_in_bytes;
+                    return;
+                }
+            }
+            regions.push(MappedRegion { start, size_in_bytes, writable, executable });
+        }
+
+        let mut regions = Vec::new();
+        let p4 = self.p4();
+        for i4 in 0..ENTRIES_PER_PAGE_TABLE {
+            if i4 == RECURSIVE_P4_INDEX || i4 == UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX {
+                continue;
+            }
+            let Some(p3) = p4.next_table(i4) else { continue };
+            let f4 = p4[i4].flags();
+            for i3 in 0..ENTRIES_PER_PAGE_TABLE {
+                let f3 = p3[i3].flags();
+                #[cfg(target_arch = "x86_64")]
+                if f3.is_valid() && f3.is_huge() {
+                    let size = PAGE_SIZE << P3_INDEX_SHIFT;
+                    add(&mut regions, vaddr(i4, i3, 0, 0), size, [f4, f3, f3, f3]);
+                    continue;
+                }
+                let Some(p2) = p3.next_table(i3) else { continue };
+                for i2 in 0..ENTRIES_PER_PAGE_TABLE {
+                    let f2 = p2[i2].flags();
+                    #[cfg(target_arch = "x86_64")]
+                    if f2.is_valid() && f2.is_huge() {
+                        let size = PAGE_SIZE << P2_INDEX_SHIFT;
+                        add(&mut regions, vaddr(i4, i3, i2, 0), size, [f4, f3, f2, f2]);
+                        continue;
+                    }
+                    let Some(p1) = p2.next_table(i2) else { continue };
+                    for i1 in 0..ENTRIES_PER_PAGE_TABLE {
+                        let f1 = p1[i1].flags();
+                        if f1.is_valid() {
+                            add(&mut regions, vaddr(i4, i3, i2, i1), PAGE_SIZE, [f4, f3, f2, f1]);
+                        }
+                    }
+                }
+            }
+        }
+        regions
+    }
+
+    /// Logs all regions of virtual memory mapped by this page table and their permissions,
+    /// as an `info` message per region.
+    ///
+    /// Returns the number of regions that are both writable and executable,
+    /// which are logged as errors instead.
+    pub fn dump_mappings(&self) -> usize {
+        let mut writable_executable = 0;
+        for region in self.mapped_regions() {
+            let perms = match (region.writable, region.executable) {
+                (true, true)   => "RWX",
+                (true, false)  => "RW-",
+                (false, true)  => "R-X",
+                (false, false) => "R--",
+            };
+            if region.writable && region.executable {
+                writable_executable += 1;
+                error!("{:#018X} - {:#018X} ({:#X} bytes) {}: violates W^X",
+                    region.start, region.start + region.size_in_bytes, region.size_in_bytes, perms,
+                );
+            } else {
+                log::info!("{:#018X} - {:#018X} ({:#X} bytes) {}",
+                    region.start, region.start + region.size_in_bytes, region.size_in_bytes, perms,
+                );
+            }
+        }
+        writable_executable
+    }
+
     /// Translates a `VirtualAddress` to a `PhysicalAddress` by walking the page tables.
     pub fn translate(&self, virtual_address: VirtualAddress) -> Option<PhysicalAddress> {
         // get the frame number of the page containing the given virtual address,
@@ -142,6 +267,122 @@ impl Mapper {
             .map(|frame| frame.start_address() + virtual_address.page_offset())
     }
 
+    /// Returns the flags of the lowest-level page table entry that maps the given `VirtualAddress`,
+    /// i.e., the P1 entry for a regular 4K page, or the P2/P3 entry for a huge page.
+    ///
+    /// Returns `None` if the given `VirtualAddress` is not mapped.
+    pub fn page_flags(&self, virtual_address: VirtualAddress) -> Option<PteFlagsArch> {
+        let page = Page::containing_address(virtual_address);
+        let p3 = self.p4().next_table(page.p4_index())?;
+
+        #[cfg(target_arch = "x86_64")] {
+            let p3_entry = &p3[page.p3_index()];
+            if p3_entry.pointed_frame().is_some() && p3_entry.flags().is_huge() {
+                return Some(p3_entry.flags());
+            }
+        }
+        let p2 = p3.next_table(page.p3_index())?;
+        #[cfg(target_arch = "x86_64")] {
+            let p2_entry = &p2[page.p2_index()];
+            if p2_entry.pointed_frame().is_some() && p2_entry.flags().is_huge() {
+                return Some(p2_entry.flags());
+            }
+        }
+        let p1_entry = &p2.next_table(page.p2_index())?[page.p1_index()];
+        p1_entry.pointed_frame().map(|_| p1_entry.flags())
+    }
+
+    /// Returns whether the given `page` is currently mapped, i.e., present.
+    ///
+    /// This is cheaper than checking whether [`Mapper::translate()`] returns `Some`,
+    /// as it stops at the first non-present entry and doesn't compute the physical address.
+    /// A page that is part of a huge page is considered mapped.
+    pub fn is_mapped(&self, page: Page) -> bool {
+        let Some(p3) = self.p4().next_table(page.p4_index()) else { return false };
+
+        #[cfg(target_arch = "x86_64")] {
+            let p3_flags = p3[page.p3_index()].flags();
+            if p3_flags.is_valid() && p3_flags.is_huge() {
+                return true;
+            }
+        }
+        let Some(p2) = p3.next_table(page.p3_index()) else { return false };
+        #[cfg(target_arch = "x86_64")] {
+            let p2_flags = p2[page.p2_index()].flags();
+            if p2_flags.is_valid() && p2_flags.is_huge() {
+                return true;
+            }
+        }
+        p2.next_table(page.p2_index())
+            .is_some_and(|p1| p1[page.p1_index()].flags().is_valid())
+    }
+
+    /// Deallocates every P1, P2, and P3 page table along the way to the given `pages`
+    /// that has become empty, e.g., because those pages were just unmapped.
+    ///
+    /// Only page tables that this mapper allocated itself are deallocated.
+    /// The caller must have already flushed the mappings of `pages` from the TLB on all CPUs.
+    ///
+    /// Returns the number of page table frames that were deallocated.
+    fn free_empty_page_tables(&mut self, pages: &PageRange) -> usize {
+        let mut freed = 0;
+        let mut previous_p1 = None;
+        for page in pages.clone() {
+            // Each P1 table covers many consecutive pages, so only check it once.
+            let p1 = (page.p4_index(), page.p3_index(), page.p2_index());
+            if previous_p1 == Some(p1) {
+                continue;
+            }
+            previous_p1 = Some(p1);
+
+            let Some(p3) = self.p4_mut().next_table_mut(page.p4_index()) else { continue };
+            if let Some(p2) = p3.next_table_mut(page.p3_index()) {
+                freed += p2.free_next_table_if_empty(page.p2_index()) as usize;
+            }
+            freed += p3.free_next_table_if_empty(page.p3_index()) as usize;
+            freed += self.p4_mut().free_next_table_if_empty(page.p4_index()) as usize;
+        }
+        freed
+    }
+
+    /// Changes the flags of the P1 entry that currently maps the given 4K `page` to `new_flags`,
+    /// without unmapping it or changing which frame it maps to.
+    ///
+    /// As with [`MappedPages::remap()`], the entry's existing `EXCLUSIVE` flag is preserved
+    /// regardless of `new_flags`, and the entry remains present (valid).
+    /// The page's TLB entry is flushed on this CPU and on all others.
+    ///
+    /// Note that this doesn't update the flags reported by [`MappedPages::flags()`]
+    /// for the `MappedPages` that covers this page.
+    ///
+    /// Returns an error if the given `page` is not currently mapped,
+    /// or if it is mapped as part of a huge page.
+    pub fn remap<F: Into<PteFlagsArch>>(&mut self, page: Page, new_flags: F) -> Result<(), &'static str> {
+        let p1 = self.p4_mut()
+            .next_table_mut(page.p4_index())
+            .and_then(|p3| p3.next_table_mut(page.p3_index()))
+            .and_then(|p2| p2.next_table_mut(page.p2_index()))
+            .ok_or("remap(): page is not mapped, or is part of a huge page")?;
+        let entry = &mut p1[page.p1_index()];
+        if entry.pointed_frame().is_none() {
+            return Err("remap(): page is not mapped");
+        }
+
+        let new_flags = new_flags.into()
+            .exclusive(entry.flags().is_exclusive())
+            .valid(true);
+        if new_flags == entry.flags() {
+            return Ok(());
+        }
+        entry.set_flags(new_flags);
+
+        tlb_flush_virt_addr(page.start_address());
+        if let Some(func) = BROADCAST_TLB_SHOOTDOWN_FUNC.get() {
+            func(PageRange::new(page, page));
+        }
+        Ok(())
+    }
+
     /// Translates a virtual memory `Page` to a physical memory `Frame` by walking the page tables.
     ///
     /// Note that this only supports translating a 4K page into a 4K frame,
@@ -299,6 +540,7 @@ impl Mapper {
                 page_table_p4: self.target_p4,
                 pages,
                 flags: actual_flags,
+                charge: None,
             },
             frames,
         ))
@@ -329,11 +571,40 @@ impl Mapper {
         Ok(mapped_pages)
     }
 
+    /// Similar to [`Self::map_allocated_pages_to()`], but in debug builds,
+    /// additionally asserts that the given `frames` are of the given [`FrameKind`].
+    ///
+    /// This catches mis-typed mappings early, e.g., a device's MMIO region
+    /// being mapped as if it were RAM, or a RAM frame being mapped as device memory.
+    pub fn map_allocated_pages_to_kind<P, FL>(
+        &mut self,
+        pages: AllocatedPages /* <P> */,
+        frames: AllocatedFrames<P>,
+        flags: FL,
+        kind: FrameKind,
+    ) -> Result<MappedPages, &'static str>
+    where 
+        P: PageSize,
+        FL: Into<PteFlagsArch>,
+    {
+        debug_assert!(
+            frame_allocator::frames_are_kind(
+                &FrameRange::from_phys_addr(frames.start_address(), frames.size_in_bytes()),
+                kind,
+            ),
+            "map_allocated_pages_to_kind(): frames {:X?} are not {:?} frames", frames, kind,
+        );
+        self.map_allocated_pages_to(pages, frames, flags)
+    }
+
 
     /// Maps the given 4K-sized `AllocatedPages` to randomly chosen (allocated) physical frames.
     ///
     /// Consumes the given `AllocatedPages` and returns a `MappedPages` object which contains those `AllocatedPages`.
     ///
+    /// The new frames are charged to the current owner's [`MemoryAccount`], if any,
+    /// and this fails with [`QUOTA_EXCEEDED`](crate::QUOTA_EXCEEDED) if that would exceed its limit.
+    ///
     /// ## Note on huge pages
     /// This function only supports 4K-sized pages, not huge pages.
     /// To use huge pages, you must provide the huge frames and call [`Self::map_allocated_pages_to()`].
@@ -343,6 +614,10 @@ impl Mapper {
         flags: FL,
     ) -> Result<MappedPages, &'static str> {
         let flags = flags.into();
+        // Pages accessible to userspace must never expose the prior contents of their frames.
+        if flags.contains(PteFlagsArch::_USER_ACCESSIBLE) {
+            return self.map_allocated_pages_zeroed(pages, flags);
+        }
         let higher_level_flags = flags.adjust_for_higher_level_pte();
 
         // Only the lowest-level P1 entry can be considered exclusive, and only because
@@ -351,6 +626,8 @@ impl Mapper {
             .valid(true)
             .exclusive(true);
 
+        let charge = MemoryCharge::for_current_owner(pages.size_in_pages())?;
+
         for page in pages.range().clone() {
             let af = frame_allocator::allocate_frames(1).ok_or("map_allocated_pages(): couldn't allocate new frame, out of memory")?;
 
@@ -373,8 +650,67 @@ impl Mapper {
             page_table_p4: self.target_p4,
             pages,
             flags: actual_flags,
+            charge,
         })
     }
+
+    /// Similar to [`Self::map_allocated_pages()`], but guarantees that the new frames
+    /// are filled with zeros before they are accessible with the given `flags`.
+    ///
+    /// The frame allocator makes no promises about the contents of the frames it returns,
+    /// which may still hold data from whoever last used them.
+    /// This is always used to map pages that are accessible to userspace.
+    ///
+    /// This `Mapper` must be for the currently-active page table,
+    /// since the frames are zeroed through the new mapping.
+    pub fn map_allocated_pages_zeroed<FL: Into<PteFlagsArch>>(
+        &mut self,
+        pages: AllocatedPages,
+        flags: FL,
+    ) -> Result<MappedPages, &'static str> {
+        if self.target_p4 != get_current_p4() {
+            return Err("map_allocated_pages_zeroed(): can only map pages into the currently-active page table");
+        }
+        let flags = flags.into();
+
+        // We must temporarily map the new pages as writable and inaccessible to userspace,
+        // since we're about to zero them.
+        let mut kernel_flags = flags.writable(true);
+        kernel_flags.remove(PteFlagsArch::_USER_ACCESSIBLE);
+        let mut mapped_pages = self.map_allocated_pages(pages, kernel_flags)?;
+        let size_in_bytes = mapped_pages.size_in_bytes();
+        mapped_pages.as_slice_mut::<u8>(0, size_in_bytes)?.fill(0);
+
+        mapped_pages.remap(self, flags)?;
+        Ok(mapped_pages)
+    }
+
+    /// Fills the given `frames` with zeros by temporarily mapping them,
+    /// and then returns them.
+    ///
+    /// This `Mapper` must be for the currently-active page table.
+    pub fn zero_frames(&mut self, frames: AllocatedFrames) -> Result<AllocatedFrames, &'static str> {
+        if self.target_p4 != get_current_p4() {
+            return Err("zero_frames(): can only map frames into the currently-active page table");
+        }
+        use crate::paging::allocate_pages;
+        let pages = allocate_pages(frames.size_in_frames()).ok_or("zero_frames(): couldn't allocate pages")?;
+        let (mut mapped_pages, frames) = self.internal_map_to(
+            pages,
+            Owned(frames),
+            PteFlagsArch::new().valid(true).writable(true),
+        )?;
+        // The mapping now owns the frames, which are given back to us when it's unmapped below.
+        // If anything fails before then, dropping the mapping deallocates them.
+        mem::forget(frames);
+
+        let size_in_bytes = mapped_pages.size_in_bytes();
+        mapped_pages.as_slice_mut::<u8>(0, size_in_bytes)?.fill(0);
+
+        let (_pages, frames) = mapped_pages.unmap_into_parts(self)
+            .map_err(|_| "zero_frames(): failed to unmap the zeroed frames")?;
+        frames.ok_or("BUG: zero_frames(): failed to take back the unmapped frames")
+    }
 }
 
 // This implementation block contains a hacky function for non-bijective mappings 
@@ -503,6 +839,8 @@ pub struct MappedPages {
     pages: AllocatedPages,
     // The PTE flags that define the page permissions of this mapping.
     flags: PteFlagsArch,
+    /// The pages of this mapping charged to their owner's memory account, if any.
+    charge: Option<MemoryCharge>,
 }
 static_assertions::assert_not_impl_any!(MappedPages: DerefMut, Clone);
 impl Deref for MappedPages {
@@ -520,6 +858,7 @@ impl MappedPages {
             page_table_p4: Frame::containing_address(PhysicalAddress::zero()),
             pages: AllocatedPages::empty(),
             flags: PteFlagsArch::new(),
+            charge: None,
         }
     }
 
@@ -528,6 +867,23 @@ impl MappedPages {
         self.flags
     }
 
+    /// Returns the memory account that this mapping's frames are charged to, if any.
+    pub fn memory_account(&self) -> Option<&Arc<MemoryAccount>> {
+        self.charge.as_ref().map(MemoryCharge::account)
+    }
+
+    /// Moves the charge for this mapping's frames to the given `account`,
+    /// regardless of that account's limit.
+    ///
+    /// This is used when memory is allocated by one owner on behalf of another,
+    /// e.g., a new task's stack, which is allocated before that task exists.
+    /// It has no effect if this mapping isn't charged to any account.
+    pub fn set_memory_account(&mut self, account: Arc<MemoryAccount>) {
+        if let Some(charge) = self.charge.as_mut() {
+            charge.transfer_to(account);
+        }
+    }
+
     /// Merges the given `MappedPages` object `mp` into this `MappedPages` object (`self`).
     ///
     /// For example, if you have the following `MappedPages` objects:    
@@ -542,6 +898,9 @@ impl MappedPages {
     /// then a tuple including an error message and the original `mp` will be returned,
     /// which prevents the `mp` from being dropped. 
     /// 
+    /// Upon success, all of the merged pages are charged to the memory account of this mapping,
+    /// or to no account if this mapping isn't charged to one.
+    /// 
     /// # Note
     /// No remapping actions or page reallocations will occur on either a failure or a success.
     pub fn merge(&mut self, mut mp: MappedPages) -> Result<(), (&'static str, MappedPages)> {
@@ -568,6 +927,15 @@ impl MappedPages {
             return Err(("failed to merge MappedPages that weren't virtually contiguous", mp));
         }
 
+        // If this mapping isn't charged, `mp`'s charge is simply released.
+        let mp_charge = mp.charge.take();
+        if let (Some(charge), Some(mut mp_charge)) = (self.charge.as_mut(), mp_charge) {
+            if !charge.same_account(&mp_charge) {
+                mp_charge.transfer_to(charge.account().clone());
+            }
+            charge.absorb(mp_charge);
+        }
+
         // Ensure the existing mapping doesn't run its drop handler and unmap its pages.
         mem::forget(mp); 
         Ok(())
@@ -583,6 +951,7 @@ impl MappedPages {
     /// * If `at_page == self.pages.end + 1`, the second returned `MappedPages` object will be empty.
     /// 
     /// Returns an `Err` containing this `MappedPages` (`self`) if `at_page` is not within its bounds.
+    /// Each returned `MappedPages` is charged for its own pages to the same account as this one.
     /// 
     /// # Note
     /// No remapping actions or page reallocations will occur on either a failure or a success.
@@ -593,19 +962,26 @@ impl MappedPages {
         let alloc_pages_owned = core::mem::replace(&mut self.pages, AllocatedPages::empty());
 
         match alloc_pages_owned.split(at_page) {
-            Ok((first_ap, second_ap)) => Ok((
-                MappedPages {
-                    page_table_p4: self.page_table_p4,
-                    pages: first_ap,
-                    flags: self.flags,
-                },
-                MappedPages {
-                    page_table_p4: self.page_table_p4,
-                    pages: second_ap,
-                    flags: self.flags,
-                }
-                // When returning here, `self` will be dropped, but it's empty so it has no effect.
-            )),
+            Ok((first_ap, second_ap)) => {
+                let mut first_charge = self.charge.take();
+                let second_charge = first_charge.as_mut()
+                    .map(|c| c.split_off(second_ap.size_in_pages()));
+                Ok((
+                    MappedPages {
+                        page_table_p4: self.page_table_p4,
+                        pages: first_ap,
+                        flags: self.flags,
+                        charge: first_charge,
+                    },
+                    MappedPages {
+                        page_table_p4: self.page_table_p4,
+                        pages: second_ap,
+                        flags: self.flags,
+                        charge: second_charge,
+                    }
+                    // When returning here, `self` will be dropped, but it's empty so it has no effect.
+                ))
+            }
             Err(orig_ap) => {
                 // Upon error, restore the `self.pages` (`AllocatedPages`) that we took ownership of.
                 self.pages = orig_ap;
@@ -828,12 +1204,49 @@ impl MappedPages {
             }
         }
 
+        // Now that no CPU can use the unmapped pages anymore,
+        // the page tables that only served to map them can be deallocated too.
+        active_table_mapper.free_empty_page_tables(self.pages.range());
+
         // Ensure that we return at least some frame range, even if we broke out of the above loop early.
         Ok(first_frame_range.map(|f| f.into_allocated_frames())
             .or(current_frame_range.map(|f| f.into_allocated_frames())))
     }
 
 
+    /// Fills the RAM frames that this `MappedPages` exclusively owns with [`FREED_FRAME_POISON`],
+    /// as they're about to be deallocated.
+    ///
+    /// This only happens in debug builds, such that any use of a freed frame's stale contents,
+    /// e.g., through a leftover non-exclusive mapping or a DMA buffer that's still in use,
+    /// reads the poison pattern instead of plausible-looking data.
+    /// Read-only mappings and device memory are left untouched.
+    #[cfg(debug_assertions)]
+    fn poison(&mut self, active_table_mapper: &Mapper) {
+        if self.size_in_pages() == 0
+            || !self.flags.is_exclusive()
+            || !self.flags.is_writable()
+            || self.flags.is_device_memory()
+            || active_table_mapper.target_p4 != self.page_table_p4
+        {
+            return;
+        }
+        for page in self.pages.range().clone() {
+            let is_ram = active_table_mapper.translate_page(page).map_or(false, |frame|
+                frame_allocator::frames_are_kind(&FrameRange::new(frame, frame), FrameKind::Ram)
+            );
+            if is_ram {
+                // SAFETY: the page is mapped writable to a RAM frame that this `MappedPages` exclusively owns,
+                //         and it's being dropped, so nothing else can access the page.
+                let words = unsafe {
+                    slice::from_raw_parts_mut(page.start_address().value() as *mut u32, PAGE_SIZE / mem::size_of::<u32>())
+                };
+                words.fill(FREED_FRAME_POISON);
+            }
+        }
+    }
+
+
     /// Reinterprets this `MappedPages`'s underlying memory region as a struct of the given type `T`,
     /// i.e., overlays a struct on top of this mapped memory region. 
     /// 
@@ -1114,6 +1527,8 @@ impl Drop for MappedPages {
         // }
         
         let mut mapper = Mapper::from_current();
+        #[cfg(debug_assertions)]
+        self.poison(&mapper);
         if let Err(e) = self.unmap(&mut mapper) {
             error!("MappedPages::drop(): failed to unmap, error: {:?}", e);
         }
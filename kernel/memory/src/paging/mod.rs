@@ -10,6 +10,7 @@
 mod temporary_page;
 mod mapper;
 mod table;
+mod audit;
 
 pub use page_table_entry::PageTableEntry;
 
@@ -19,8 +20,10 @@ pub use self::{
     temporary_page::TemporaryPage,
     mapper::{
         Mapper, MappedPages, BorrowedMappedPages, BorrowedSliceMappedPages,
-        Mutability, Mutable, Immutable, translate,
+        MappedRegion, Mutability, Mutable, Immutable, translate, page_flags, is_mapped,
+        FREED_FRAME_POISON,
     },
+    audit::{AddressRegion, Permissions, PageMapping, PageTableStats, page_table_stats, mappings_in},
 };
 
 use core::{
@@ -44,7 +47,7 @@ use kernel_config::memory::{RECURSIVE_P4_INDEX, PAGE_SIZE, UPCOMING_PAGE_TABLE_R
 use memory_aarch64::set_as_active_page_table_root;
 
 #[cfg(target_arch = "x86_64")]
-use super::get_vga_mem_addr;
+use super::{get_vga_mem_addr, enable_global_pages};
 
 /// A top-level root (P4) page table.
 /// 
@@ -201,7 +204,8 @@ impl PageTable {
 
         #[cfg(target_arch = "aarch64")] {
             set_as_active_page_table_root(new_table.physical_address());
-            // This is only required on aarch64, as setting CR3 on x86_64 flushes the TLB.
+            // This is only required on aarch64, as setting CR3 on x86_64 flushes the TLB,
+            // except for global pages, which are identical in every page table.
             tlb_flush_all();
         }
     }
@@ -214,6 +218,17 @@ impl PageTable {
 }
 
 
+/// Returns the number of frames currently in use as lower-level (P3, P2, and P1) page tables.
+///
+/// A page table frame is counted from when it's allocated upon mapping a page that needs it
+/// until it's deallocated upon unmapping the last page that it maps.
+/// This doesn't count top-level (P4) page tables, which are owned by their [`PageTable`],
+/// nor the page tables that were set up by the bootloader.
+pub fn page_table_frame_count() -> usize {
+    table::PAGE_TABLE_FRAMES.load(core::sync::atomic::Ordering::Relaxed)
+}
+
+
 /// Returns the current top-level (P4) root page table frame.
 pub fn get_current_p4() -> Frame<Page4K> {
     Frame::containing_address(get_p4())
@@ -279,7 +294,7 @@ pub fn init(
         // place the kernel in the higher half. 
         //
         // These identity mappings are short-lived; they are unmapped later after all other CPUs are brought up
-        // but before we start running applications.
+        // but before we start running applications. Thus, unlike the higher-half mappings, they aren't global.
 
         debug!("{:X?}", aggregated_section_memory_bounds);
 
@@ -306,7 +321,7 @@ pub fn init(
             init_end_phys.value() - init_start_phys.value(),
         )?;
         let init_identity_mapped_pages: NoDrop<MappedPages> = NoDrop::new( unsafe {
-            Mapper::map_to_non_exclusive(new_mapper, init_pages_identity, &init_frames, init_flags)?
+            Mapper::map_to_non_exclusive(new_mapper, init_pages_identity, &init_frames, init_flags.global(false))?
         });
         let mut init_mapped_pages = new_mapper.map_allocated_pages_to(init_pages, init_frames, init_flags)?;
 
@@ -317,7 +332,7 @@ pub fn init(
             text_end_phys.value() - text_start_phys.value(),
         )?;
         let text_identity_mapped_pages: NoDrop<MappedPages> = NoDrop::new( unsafe {
-            Mapper::map_to_non_exclusive(new_mapper, text_pages_identity, &text_frames, text_flags)?
+            Mapper::map_to_non_exclusive(new_mapper, text_pages_identity, &text_frames, text_flags.global(false))?
         });
         init_mapped_pages.merge(new_mapper.map_allocated_pages_to(text_pages, text_frames, text_flags)?).map_err(|(error, _)| error)?;
         let text_mapped_pages = NoDrop::new(init_mapped_pages);
@@ -329,7 +344,7 @@ pub fn init(
             rodata_end_phys.value() - rodata_start_phys.value(),
         )?;
         let rodata_identity_mapped_pages: NoDrop<MappedPages> = NoDrop::new( unsafe {
-            Mapper::map_to_non_exclusive(new_mapper, rodata_pages_identity, &rodata_frames, rodata_flags)?
+            Mapper::map_to_non_exclusive(new_mapper, rodata_pages_identity, &rodata_frames, rodata_flags.global(false))?
         });
         let rodata_mapped_pages = NoDrop::new(new_mapper.map_allocated_pages_to(rodata_pages, rodata_frames, rodata_flags)?);
 
@@ -340,7 +355,7 @@ pub fn init(
             data_end_phys.value() - data_start_phys.value(),
         )?;
         let data_identity_mapped_pages: NoDrop<MappedPages> = NoDrop::new( unsafe {
-            Mapper::map_to_non_exclusive(new_mapper, data_pages_identity, &data_frames, data_flags)?
+            Mapper::map_to_non_exclusive(new_mapper, data_pages_identity, &data_frames, data_flags.global(false))?
         });
         let data_mapped_pages = NoDrop::new(new_mapper.map_allocated_pages_to(data_pages, data_frames, data_flags)?);
 
@@ -429,6 +444,19 @@ pub fn init(
     page_table.switch(&new_table); 
     // The old page_table set up during bootstrap will be dropped here. It's no longer being used.
 
+    // Now that the kernel's global mappings are active, keep them in the TLB across `cr3` reloads.
+    #[cfg(target_arch = "x86_64")]
+    if !enable_global_pages() {
+        debug!("This CPU doesn't support global pages (PGE)");
+    }
+
+    // Each kernel section is now mapped with only the permissions it needs,
+    // so no page should be both writable and executable.
+    if new_table.mapped_regions().iter().any(|region| region.writable && region.executable) {
+        new_table.dump_mappings();
+        return Err("the new kernel page table has writable and executable mappings, violating W^X");
+    }
+
     // Return the new page table because that's the one that should be used by the kernel in future mappings. 
     Ok(InitialMemoryMappings {
         page_table: new_table,
@@ -9,16 +9,13 @@
 
 use core::ops::{Index, IndexMut};
 use core::marker::PhantomData;
-use super::PageTableEntry;
-use crate::VirtualAddress;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use super::{PageTableEntry, mapper::INTO_UNMAPPED_FRAMES_FUNC};
+use crate::{VirtualAddress, Page, PageRange, FrameRange, tlb_flush_virt_addr};
 use pte_flags::PteFlagsArch;
 use kernel_config::memory::{
     ENTRIES_PER_PAGE_TABLE,
     PAGE_SHIFT,
-    P1_INDEX_SHIFT,
-    P2_INDEX_SHIFT,
-    P3_INDEX_SHIFT,
-    P4_INDEX_SHIFT,
     RECURSIVE_P4_INDEX,
     UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX,
 };
@@ -41,11 +38,9 @@ use zerocopy::FromBytes;
 /// See these links for more info: 
 /// * <http://forum.osdev.org/viewtopic.php?f=1&p=176913>
 /// * <http://forum.osdev.org/viewtopic.php?f=15&t=25545>
-pub(crate) const P4: *mut Table<Level4> = VirtualAddress::new_canonical(
-    RECURSIVE_P4_INDEX << (PAGE_SHIFT + P1_INDEX_SHIFT)
-    | RECURSIVE_P4_INDEX << (PAGE_SHIFT + P2_INDEX_SHIFT)
-    | RECURSIVE_P4_INDEX << (PAGE_SHIFT + P3_INDEX_SHIFT)
-    | RECURSIVE_P4_INDEX << (PAGE_SHIFT + P4_INDEX_SHIFT)
+pub(crate) const P4: *mut Table<Level4> = VirtualAddress::from_page_table_indices(
+    [RECURSIVE_P4_INDEX; 4],
+    0,
 ).value() as *mut _;
 
 
@@ -59,14 +54,17 @@ pub(crate) const P4: *mut Table<Level4> = VirtualAddress::new_canonical(
 /// of the page table being accessed, at all four levels of paging.
 ///
 /// Thus, the value of this should be `0o177777_774_774_774_774_0000` (octal) on x86_64.
-pub(crate) const UPCOMING_P4: *mut Table<Level4> = VirtualAddress::new_canonical(
-    UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX << (PAGE_SHIFT + P1_INDEX_SHIFT)
-    | UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX << (PAGE_SHIFT + P2_INDEX_SHIFT)
-    | UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX << (PAGE_SHIFT + P3_INDEX_SHIFT)
-    | UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX << (PAGE_SHIFT + P4_INDEX_SHIFT)
+pub(crate) const UPCOMING_P4: *mut Table<Level4> = VirtualAddress::from_page_table_indices(
+    [UPCOMING_PAGE_TABLE_RECURSIVE_P4_INDEX; 4],
+    0,
 ).value() as *mut _;
 
 
+/// The number of frames currently in use as P3, P2, or P1 page tables
+/// that were allocated by [`Table::next_table_create()`].
+pub(super) static PAGE_TABLE_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+
 #[derive(FromBytes)]
 pub struct Table<L: TableLevel> {
     entries: [PageTableEntry; ENTRIES_PER_PAGE_TABLE],
@@ -80,6 +78,11 @@ impl<L: TableLevel> Table<L> {
             entry.zero();
         }
     }
+
+    /// Returns `true` if no entry in this page table frame is in use.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.iter().all(PageTableEntry::is_unused)
+    }
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -141,13 +144,50 @@ impl<L: HierarchicalLevel> Table<L> {
             let af = frame_allocator::allocate_frames(1).expect("next_table_create(): no frames available");
             self[index].set_entry(
                 af.as_allocated_frame(),
-                flags.valid(true).writable(true), // must be valid and writable on x86_64
+                // must be valid and writable on x86_64, and marked exclusive because this entry owns the new table's frame.
+                flags.valid(true).writable(true).exclusive(true),
             );
             self.next_table_mut(index).unwrap().zero();
-            core::mem::forget(af); // we currently forget frames allocated as page table frames since we don't yet have a way to track them.
+            // The new table's frame is deallocated by `free_next_table_if_empty()` instead.
+            core::mem::forget(af);
+            PAGE_TABLE_FRAMES.fetch_add(1, Ordering::Relaxed);
         }
         self.next_table_mut(index).unwrap()
     }
+
+    /// Deallocates the next lowest-level page table at the given `index` if none of its entries are in use
+    /// and it was allocated by [`Self::next_table_create()`], clearing its entry in this table.
+    ///
+    /// This flushes the next table's own recursive mapping from the TLB on all CPUs,
+    /// but the caller must have already flushed the mappings that were made by the next table.
+    ///
+    /// Returns `true` if the next table was deallocated.
+    pub(crate) fn free_next_table_if_empty(&mut self, index: usize) -> bool {
+        if !self[index].flags().is_exclusive() || !self.next_table(index).is_some_and(|next| next.is_empty()) {
+            return false;
+        }
+        let (Some(next_table_vaddr), Some(frame), Some(into_unmapped_frames)) = (
+            self.next_table_address(index),
+            self[index].pointed_frame(),
+            INTO_UNMAPPED_FRAMES_FUNC.get(),
+        ) else {
+            return false;
+        };
+
+        self[index].zero();
+        tlb_flush_virt_addr(next_table_vaddr);
+        #[cfg(not(bm_map))]
+        {
+            if let Some(func) = crate::BROADCAST_TLB_SHOOTDOWN_FUNC.get() {
+                let page = Page::containing_address(next_table_vaddr);
+                func(PageRange::new(page, page));
+            }
+        }
+        // No CPU can access the next table anymore, so its frame can be reused.
+        drop(into_unmapped_frames(FrameRange::new(frame, frame)).into_allocated_frames());
+        PAGE_TABLE_FRAMES.fetch_sub(1, Ordering::Relaxed);
+        true
+    }
 }
 
 impl<L: TableLevel> Index<usize> for Table<L> {
@@ -74,6 +74,34 @@ pub fn tlb_flush_by_theseus_asid() {
 #[cfg(any(doc, target_arch = "aarch64"))]
 pub use tlb_flush_by_theseus_asid as tlb_flush_all;
 
+/// Cleans and invalidates all data cache lines that contain any byte of
+/// the `size_in_bytes` bytes of virtual memory starting at `vaddr`,
+/// up to the Point of Coherency.
+///
+/// DC CIVAC => data cache clean and invalidate by virtual address to Point of Coherency
+///
+/// This is needed when sharing cacheable memory with a device that doesn't snoop
+/// the CPU caches: before the device reads memory that the CPU wrote,
+/// and before the CPU reads memory that the device wrote.
+#[cfg(any(doc, target_arch = "aarch64"))]
+pub fn cache_flush_range(vaddr: VirtualAddress, size_in_bytes: usize) {
+    if size_in_bytes == 0 {
+        return;
+    }
+    // Bits [19:16] of CTR_EL0 are DminLine, the log2 of the
+    // number of 4-byte words in the smallest data cache line.
+    let ctr_el0: usize;
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr_el0) };
+    let line_size = 4 << ((ctr_el0 >> 16) & 0xF);
+
+    let start = vaddr.value() & !(line_size - 1);
+    let end = vaddr.value() + size_in_bytes;
+    for line in (start .. end).step_by(line_size) {
+        unsafe { asm!("dc civac, {}", in(reg) line) };
+    }
+    unsafe { barrier::dsb(barrier::SY) };
+}
+
 /// Returns the current top-level page table address.
 ///
 /// We use TTBR0 in Theseus to store the
@@ -126,13 +154,18 @@ pub fn configure_translation_registers() {
     unsafe {
         // The MAIR register holds up to 8 memory profiles;
         // each profile describes cacheability of the memory.
-        // In Theseus, we currently use two profiles: one for
-        // device memory (non-cacheable) and one for normal
-        // memory (the usual RAM memory, cacheable).
+        // In Theseus, we currently use three profiles: one for
+        // device memory (non-cacheable), one for normal
+        // memory (the usual RAM memory, cacheable), and one for
+        // normal non-cacheable memory (e.g., framebuffers).
         //
         // For more information on MAIR, See section D17.2.97
         // of [DDI0487l.a](https://l0.pm/arm-ddi0487l.a.pdf).
         MAIR_EL1.write(
+            // Attribute 2 - Non-cacheable normal memory.
+            MAIR_EL1::Attr2_Normal_Outer::NonCacheable +
+            MAIR_EL1::Attr2_Normal_Inner::NonCacheable +
+
             // Attribute 1 - Device.
             MAIR_EL1::Attr1_Device::nonGathering_nonReordering_EarlyWriteAck +
 
@@ -352,6 +385,10 @@ where
 
         debug!("Looking at loaded section {} at {:#X}, size {:#X}", section.name(), section.start(), section.len());
         let flags = convert_to_pte_flags(&section);
+        if flags.is_writable() && flags.is_executable() {
+            error!("Section {} at {:#X} is both writable and executable", section.name(), section.start());
+            return Err("Kernel ELF Section was both writable and executable, violating W^X");
+        }
 
         let mut start_virt_addr = VirtualAddress::new(section.start().value())
             .ok_or("section had invalid starting virtual address")?;
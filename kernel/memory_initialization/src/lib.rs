@@ -4,7 +4,7 @@
 extern crate alloc;
 
 use log::{error, debug};
-use memory::{MmiRef, MappedPages, VirtualAddress, InitialMemoryMappings, EarlyIdentityMappedPages};
+use memory::{MmiRef, MappedPages, VirtualAddress, FrameRange, InitialMemoryMappings, EarlyIdentityMappedPages};
 use kernel_config::memory::{KERNEL_HEAP_START, KERNEL_HEAP_INITIAL_SIZE};
 use boot_info::{BootInformation, Module};
 use alloc::{
@@ -90,13 +90,13 @@ pub fn init_memory_management(
 
     // Initialize the kernel heap.
     let heap_start = KERNEL_HEAP_START;
-    let heap_initial_size = KERNEL_HEAP_INITIAL_SIZE;
+    let heap_initial_size = KERNEL_HEAP_INITIAL_SIZE.value();
     
     let heap_mapped_pages = {
         let pages = memory::allocate_pages_by_bytes_at(VirtualAddress::new_canonical(heap_start), heap_initial_size)?;
         debug!("Initial heap starts at: {:#X}, size: {:#X}, pages: {:?}", heap_start, heap_initial_size, pages);
         let heap_mp = page_table.map_allocated_pages(pages, HEAP_FLAGS).map_err(|e| {
-            error!("Failed to map kernel heap memory pages, {} bytes starting at virtual address {:#X}. Error: {:?}",
+            error!("Failed to map kernel heap memory pages, {} starting at virtual address {:#X}. Error: {:?}",
                 KERNEL_HEAP_INITIAL_SIZE, KERNEL_HEAP_START, e
             );
             "Failed to map the kernel heap memory. Perhaps the KERNEL_HEAP_INITIAL_SIZE \
@@ -125,6 +125,17 @@ pub fn init_memory_management(
         .collect::<Result<Vec<_>, _>>() // collect the `Vec<Result<...>>` into `Result<Vec<...>>`
         .map_err(|_e| "BUG: Bootloader module had invalid non-UTF8 name (cmdline) string")?;
 
+    // The boot info and the modules are only needed during boot, so once they're no longer in use,
+    // e.g., after a compressed modules archive has been extracted, their frames can be reclaimed.
+    let boot_info_frames = boot_info.start()
+        .and_then(|vaddr| kernel_mmi_ref.lock().page_table.translate(vaddr))
+        .map(|paddr| FrameRange::from_phys_addr(paddr, boot_info.len()));
+    let module_frames = boot_info.modules()
+        .map(|m| FrameRange::from_phys_addr(m.start(), m.len()));
+    for frames in boot_info_frames.into_iter().chain(module_frames) {
+        memory::mark_reclaimable_after_boot(frames);
+    }
+
     // Now that we've recorded the rest of the necessary boot info, we can drop the boot_info_mapped_pages.
     // This frees up those frames such that future code can exclusively map and access those pages/frames.
     drop(boot_info_mapped_pages);
@@ -21,7 +21,10 @@ use core::{
     marker::{ConstParamTy, PhantomData},
     ops::{Add, AddAssign, Deref, DerefMut, Sub, SubAssign},
 };
-use kernel_config::memory::{MAX_PAGE_NUMBER, MAX_VIRTUAL_ADDRESS, PAGE_SIZE, ENTRIES_PER_PAGE_TABLE};
+use kernel_config::memory::{
+    MAX_PAGE_NUMBER, MAX_VIRTUAL_ADDRESS, PAGE_SIZE, PAGE_SHIFT, ENTRIES_PER_PAGE_TABLE,
+    P1_INDEX_SHIFT, P2_INDEX_SHIFT, P3_INDEX_SHIFT, P4_INDEX_SHIFT,
+};
 use zerocopy::FromBytes;
 use paste::paste;
 use derive_more::*;
@@ -192,34 +195,83 @@ macro_rules! implement_address {
     };
 }
 
+/// The depth of the page table hierarchy, which determines
+/// how many bits of a virtual address are meaningful.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PagingDepth {
+    /// 4-level paging, with 48-bit virtual addresses.
+    FourLevel,
+    /// 5-level paging, with 57-bit virtual addresses, e.g., LA57 on x86_64.
+    FiveLevel,
+}
+
+impl PagingDepth {
+    /// The paging depth that Theseus's virtual memory layout is built for.
+    ///
+    /// All canonicality checks of [`VirtualAddress`] use this depth.
+    /// Booting with a different active paging depth is unsupported.
+    pub const ACTIVE: PagingDepth = PagingDepth::FourLevel;
+
+    /// Returns the number of page table levels.
+    pub const fn levels(self) -> usize {
+        match self {
+            PagingDepth::FourLevel => 4,
+            PagingDepth::FiveLevel => 5,
+        }
+    }
+
+    /// Returns the number of meaningful bits in a virtual address.
+    pub const fn virtual_address_bits(self) -> usize {
+        PAGE_SHIFT + self.levels() * P2_INDEX_SHIFT
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 mod canonical_address {
-    const CANONICAL_VIRT_ADDR_MASK: usize = 0x0000_7FFF_FFFF_FFFF;
+    use super::PagingDepth;
+
     const CANONICAL_PHYS_ADDR_MASK: usize = 0x000F_FFFF_FFFF_FFFF;
 
+    /// The highest canonical virtual address in the lower half of the address space,
+    /// right below the hole of non-canonical addresses, e.g., `0x0000_7FFF_FFFF_FFFF` with 4-level paging.
+    pub const CANONICAL_LOW_MAX: usize = (1 << (PagingDepth::ACTIVE.virtual_address_bits() - 1)) - 1;
+
+    /// The lowest canonical virtual address in the higher half of the address space,
+    /// right above the hole of non-canonical addresses, e.g., `0xFFFF_8000_0000_0000` with 4-level paging.
+    pub const CANONICAL_HIGH_MIN: usize = !CANONICAL_LOW_MAX;
+
     /// Returns whether the given virtual address value is canonical.
     ///
-    /// On x86_64, virtual addresses must have their 16 most-significant bits
-    /// be sign-extended from bit 47.
+    /// On x86_64, virtual addresses must have their upper bits
+    /// be sign-extended from the most-significant meaningful bit,
+    /// which is bit 47 with 4-level paging.
     #[inline]
     pub const fn is_canonical_virtual_address(virt_addr: usize) -> bool {
-        let upper17 = virt_addr & !CANONICAL_VIRT_ADDR_MASK;
-        upper17 == 0 || upper17 == !CANONICAL_VIRT_ADDR_MASK
+        is_canonical_virtual_address_at(virt_addr, PagingDepth::ACTIVE)
     }
 
     /// Returns a canonicalized instance of the given virtual address value.
     ///
-    /// On x86_64, virtual addresses must have their 16 most-significant bits
-    /// be sign-extended from bit 47.
+    /// On x86_64, virtual addresses must have their upper bits
+    /// be sign-extended from the most-significant meaningful bit,
+    /// which is bit 47 with 4-level paging.
     #[inline]
     pub const fn canonicalize_virtual_address(virt_addr: usize) -> usize {
-        // match virt_addr.get_bit(47) {
-        //     false => virt_addr.set_bits(48..64, 0),
-        //     true =>  virt_addr.set_bits(48..64, 0xffff),
-        // };
+        sign_extend_virtual_address(virt_addr, PagingDepth::ACTIVE)
+    }
+
+    /// Returns whether the given virtual address value is canonical with the given paging `depth`.
+    #[inline]
+    pub const fn is_canonical_virtual_address_at(virt_addr: usize, depth: PagingDepth) -> bool {
+        sign_extend_virtual_address(virt_addr, depth) == virt_addr
+    }
 
-        // The below code is semantically equivalent to the above, but it works in const functions.
-        ((virt_addr << 16) as isize >> 16) as usize
+    /// Sign-extends the given virtual address value from its most-significant meaningful bit
+    /// with the given paging `depth`, e.g., from bit 47 to bits `[48:63]` with 4-level paging.
+    #[inline]
+    pub const fn sign_extend_virtual_address(virt_addr: usize, depth: PagingDepth) -> usize {
+        let unused_bits = usize::BITS as usize - depth.virtual_address_bits();
+        ((virt_addr << unused_bits) as isize >> unused_bits) as usize
     }
 
     /// Returns whether the given phyiscal address value is canonical.
@@ -243,6 +295,8 @@ mod canonical_address {
 
 #[cfg(target_arch = "aarch64")]
 mod canonical_address {
+    use super::PagingDepth;
+
     const CANONICAL_VIRT_ADDR_MASK: usize = 0x0000_FFFF_FFFF_FFFF;
     const CANONICAL_PHYS_ADDR_MASK: usize = 0x0000_FFFF_FFFF_FFFF;
 
@@ -272,6 +326,13 @@ mod canonical_address {
         virt_addr & CANONICAL_VIRT_ADDR_MASK
     }
 
+    /// Returns whether the given virtual address value is canonical with the given paging `depth`,
+    /// i.e., whether the bits above its meaningful bits are cleared.
+    #[inline]
+    pub const fn is_canonical_virtual_address_at(virt_addr: usize, depth: PagingDepth) -> bool {
+        virt_addr >> depth.virtual_address_bits() == 0
+    }
+
     /// Returns whether the given physical address value is canonical.
     ///
     /// On aarch64, Theseus configures the MMU to use 48-bit physical addresses.
@@ -292,6 +353,27 @@ mod canonical_address {
 }
 
 use canonical_address::*;
+pub use canonical_address::is_canonical_virtual_address_at;
+#[cfg(target_arch = "x86_64")]
+pub use canonical_address::{sign_extend_virtual_address, CANONICAL_HIGH_MIN, CANONICAL_LOW_MAX};
+
+/// Returns whether the given address value is a canonical virtual address,
+/// e.g., on x86_64, whether it lies outside the hole between
+/// [`CANONICAL_LOW_MAX`] and [`CANONICAL_HIGH_MIN`].
+///
+/// This is meant for checking addresses from untrusted sources, e.g., ELF files,
+/// before converting them into a [`VirtualAddress`].
+pub const fn is_canonical(addr: usize) -> bool {
+    is_canonical_virtual_address(addr)
+}
+
+/// Sign-extends the given address value from its most-significant meaningful bit,
+/// which maps addresses in the non-canonical hole onto the nearest canonical half,
+/// e.g., `CANONICAL_LOW_MAX + 1` onto [`CANONICAL_HIGH_MIN`].
+#[cfg(target_arch = "x86_64")]
+pub const fn sign_extend(addr: usize) -> VirtualAddress {
+    VirtualAddress(sign_extend_virtual_address(addr, PagingDepth::ACTIVE))
+}
 
 implement_address!(
     VirtualAddress,
@@ -302,6 +384,46 @@ implement_address!(
     page
 );
 
+impl VirtualAddress {
+    /// Creates a new `VirtualAddress` if the given address value is canonical
+    /// with the given paging `depth`, which may differ from [`PagingDepth::ACTIVE`].
+    ///
+    /// Note that all other `VirtualAddress` functions assume [`PagingDepth::ACTIVE`].
+    pub const fn new_checked(virt_addr: usize, depth: PagingDepth) -> Option<VirtualAddress> {
+        if is_canonical_virtual_address_at(virt_addr, depth) { Some(VirtualAddress(virt_addr)) } else { None }
+    }
+
+    /// Returns whether the given address value is canonical with [`PagingDepth::ACTIVE`].
+    pub const fn is_canonical(virt_addr: usize) -> bool {
+        is_canonical_virtual_address(virt_addr)
+    }
+
+    /// Returns the 9-bit index into the page table at the given `level` (1 for P1, up to 5 for P5)
+    /// that this `VirtualAddress` is translated through.
+    pub const fn page_table_index(&self, level: usize) -> usize {
+        page_table_index(self.0 >> PAGE_SHIFT, level)
+    }
+
+    /// Returns the canonical `VirtualAddress` that is translated through the given entries
+    /// of the P4, P3, P2, and P1 page tables, in that order, plus the given `page_offset`.
+    pub const fn from_page_table_indices(indices: [usize; 4], page_offset: usize) -> VirtualAddress {
+        let [p4_index, p3_index, p2_index, p1_index] = indices;
+        VirtualAddress::new_canonical(
+            (p4_index << P4_INDEX_SHIFT
+            | p3_index << P3_INDEX_SHIFT
+            | p2_index << P2_INDEX_SHIFT
+            | p1_index << P1_INDEX_SHIFT) << PAGE_SHIFT
+            | page_offset
+        )
+    }
+}
+
+/// Returns the 9-bit index into the page table at the given `level` (1 for P1, up to 5 for P5)
+/// that the page with the given `page_number` is translated through.
+const fn page_table_index(page_number: usize, level: usize) -> usize {
+    (page_number >> ((level - 1) * P2_INDEX_SHIFT)) & (ENTRIES_PER_PAGE_TABLE - 1)
+}
+
 implement_address!(
     PhysicalAddress,
     "physical",
@@ -535,17 +657,17 @@ implement_page_frame!(Frame, "physical", "p", PhysicalAddress);
 impl<P: PageSize> Page<P> {
     /// Returns the 9-bit part of this `Page`'s [`VirtualAddress`] that is the index into the P4 page table entries list.
     pub const fn p4_index(&self) -> usize {
-        (self.number >> 27) & 0x1FF
+        page_table_index(self.number, 4)
     }
 
     /// Returns the 9-bit part of this `Page`'s [`VirtualAddress`] that is the index into the P3 page table entries list.
     pub const fn p3_index(&self) -> usize {
-        (self.number >> 18) & 0x1FF
+        page_table_index(self.number, 3)
     }
 
     /// Returns the 9-bit part of this `Page`'s [`VirtualAddress`] that is the index into the P2 page table entries list.
     pub const fn p2_index(&self) -> usize {
-        (self.number >> 9) & 0x1FF
+        page_table_index(self.number, 2)
     }
 
     /// Returns the 9-bit part of this `Page`'s [`VirtualAddress`] that is the index into the P1 page table entries list.
@@ -553,7 +675,7 @@ impl<P: PageSize> Page<P> {
     /// Using this returned `usize` value as an index into the P1 entries list will give you the final PTE,
     /// from which you can extract the mapped [`Frame`]  using `PageTableEntry::pointed_frame()`.
     pub const fn p1_index(&self) -> usize {
-        self.number & 0x1FF
+        page_table_index(self.number, 1)
     }
 }
 
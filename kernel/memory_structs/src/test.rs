@@ -216,4 +216,76 @@ fn test_chunk_subtraction() {
     // original page num = 512. 512 + 1 = 1024 (which is the next huge page)
     assert_eq!((page_2mb - 1).number(), 512);
     assert_eq!((page_1gb - 1).number(), 262144);
-}
\ No newline at end of file
+}
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn canonical_boundaries_4_level() {
+    assert_eq!(CANONICAL_LOW_MAX, 0x0000_7FFF_FFFF_FFFF);
+    assert_eq!(CANONICAL_HIGH_MIN, 0xFFFF_8000_0000_0000);
+    assert!(VirtualAddress::new(0x0000_7FFF_FFFF_FFFF).is_some());
+    assert!(VirtualAddress::new(0x0000_8000_0000_0000).is_none());
+    assert!(VirtualAddress::new(0xFFFF_7FFF_FFFF_FFFF).is_none());
+    assert!(VirtualAddress::new(0xFFFF_8000_0000_0000).is_some());
+    assert_eq!(VirtualAddress::new_canonical(0x0000_8000_0000_0000).value(), 0xFFFF_8000_0000_0000);
+    assert_eq!(VirtualAddress::new_canonical(0xFFFF_7FFF_FFFF_FFFF).value(), 0x0000_7FFF_FFFF_FFFF);
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn canonical_hole() {
+    assert!(is_canonical(0));
+    assert!(is_canonical(CANONICAL_LOW_MAX));
+    assert!(!is_canonical(CANONICAL_LOW_MAX + 1));
+    assert!(!is_canonical(CANONICAL_HIGH_MIN - 1));
+    assert!(is_canonical(CANONICAL_HIGH_MIN));
+    assert!(is_canonical(usize::MAX));
+
+    assert_eq!(sign_extend(CANONICAL_LOW_MAX).value(), CANONICAL_LOW_MAX);
+    assert_eq!(sign_extend(CANONICAL_LOW_MAX + 1).value(), CANONICAL_HIGH_MIN);
+    assert_eq!(sign_extend(CANONICAL_HIGH_MIN - 1).value(), CANONICAL_LOW_MAX);
+    assert_eq!(sign_extend(CANONICAL_HIGH_MIN).value(), CANONICAL_HIGH_MIN);
+    assert_eq!(sign_extend(0x0000_8000_0000_1234).value(), 0xFFFF_8000_0000_1234);
+    for addr in [0, CANONICAL_LOW_MAX + 1, CANONICAL_HIGH_MIN - 1, usize::MAX] {
+        assert!(is_canonical(sign_extend(addr).value()));
+        assert_eq!(sign_extend(addr), VirtualAddress::new_canonical(addr));
+    }
+}
+
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn canonical_boundaries_5_level() {
+    let depth = PagingDepth::FiveLevel;
+    assert_eq!(depth.virtual_address_bits(), 57);
+    assert!(VirtualAddress::new_checked(0x00FF_FFFF_FFFF_FFFF, depth).is_some());
+    assert!(VirtualAddress::new_checked(0x0100_0000_0000_0000, depth).is_none());
+    assert!(VirtualAddress::new_checked(0xFEFF_FFFF_FFFF_FFFF, depth).is_none());
+    assert!(VirtualAddress::new_checked(0xFF00_0000_0000_0000, depth).is_some());
+    // Canonical with 5-level paging, but not with 4-level paging.
+    assert!(VirtualAddress::new_checked(0x0000_8000_0000_0000, depth).is_some());
+    assert!(VirtualAddress::new_checked(0x0000_8000_0000_0000, PagingDepth::FourLevel).is_none());
+    assert_eq!(sign_extend_virtual_address(0x0100_0000_0000_0000, depth), 0xFF00_0000_0000_0000);
+    assert_eq!(sign_extend_virtual_address(0x01FF_0000_0000_0000, depth), 0xFFFF_0000_0000_0000);
+}
+
+#[test]
+fn page_table_indices() {
+    let top = VirtualAddress::new_canonical(0xFFFF_FF80_0000_0000);
+    assert_eq!(top.page_table_index(4), 511);
+    assert_eq!(top.page_table_index(3), 0);
+    assert_eq!(Page::containing_address(top).p4_index(), 511);
+
+    let vaddr = VirtualAddress::from_page_table_indices([1, 2, 3, 4], 0x5);
+    assert_eq!(vaddr.value(), (1 << 39) | (2 << 30) | (3 << 21) | (4 << 12) | 0x5);
+    let page = Page::containing_address(vaddr);
+    assert_eq!((page.p4_index(), page.p3_index(), page.p2_index(), page.p1_index()), (1, 2, 3, 4));
+    assert_eq!(vaddr.page_table_index(1), 4);
+
+    #[cfg(target_arch = "x86_64")] {
+        let recursive = VirtualAddress::from_page_table_indices([510; 4], 0);
+        assert_eq!(recursive.value(), 0o177777_776_776_776_776_0000);
+        // The P5 index is only meaningful with 5-level paging.
+        let high = VirtualAddress::new_checked(0xFFFF_0000_0000_0000, PagingDepth::FiveLevel).unwrap();
+        assert_eq!(high.page_table_index(5), 511);
+        assert_eq!(high.page_table_index(4), 0);
+    }
+}
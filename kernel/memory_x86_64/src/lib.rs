@@ -10,8 +10,8 @@ use log::{debug, error};
 use pte_flags::PteFlags;
 
 use kernel_config::memory::KERNEL_OFFSET;
-use memory_structs::{PhysicalAddress, VirtualAddress};
-use x86_64::{registers::control::Cr3, instructions::tlb};
+use memory_structs::{PagingDepth, PhysicalAddress, VirtualAddress};
+use x86_64::{registers::control::{Cr3, Cr4, Cr4Flags}, instructions::tlb};
 
 
 /// The address bounds and mapping flags of a section's memory region.
@@ -85,6 +85,10 @@ where
 
         debug!("Looking at loaded section {} at {:#X}, size {:#X}", section.name(), section.start(), section.len());
         let flags = convert_to_pte_flags(&section);
+        if flags.is_writable() && flags.is_executable() {
+            error!("Section {} at {:#X} is both writable and executable", section.name(), section.start());
+            return Err("Kernel ELF Section was both writable and executable, violating W^X");
+        }
 
         let mut start_virt_addr = VirtualAddress::new(section.start().value())
             .ok_or("section had invalid starting virtual address")?;
@@ -273,11 +277,82 @@ pub fn tlb_flush_virt_addr(vaddr: VirtualAddress) {
     tlb::flush(x86_64::VirtAddr::new_truncate(vaddr.value() as u64));
 }
 
-/// Flushes the whole TLB. 
+/// Flushes the whole TLB, except for global pages.
+///
+/// This reloads `cr3`, which doesn't flush pages mapped with [`PteFlags::GLOBAL`]
+/// once [`enable_global_pages()`] has been called,
+/// so changes to a global page must be flushed via [`tlb_flush_virt_addr()`].
 pub fn tlb_flush_all() {
     tlb::flush_all();
 }
 
+/// Enables global pages (`CR4.PGE`) on the current CPU, if supported.
+///
+/// Afterwards, pages mapped with [`PteFlags::GLOBAL`] are no longer flushed from the TLB
+/// when `cr3` is reloaded, e.g., when switching page tables.
+/// This must be invoked on every CPU.
+///
+/// Returns `true` if global pages are supported and now enabled.
+pub fn enable_global_pages() -> bool {
+    use core::arch::x86_64::__cpuid;
+    const CPUID_PGE_SUPPORTED: u32 = 1 << 13;
+
+    // SAFE: CPUID leaf 1 is supported on every x86_64 CPU.
+    let leaf_1 = unsafe { __cpuid(1) };
+    if leaf_1.edx & CPUID_PGE_SUPPORTED == 0 {
+        return false;
+    }
+    // SAFE: enabling PGE doesn't change any translations, and setting it flushes the entire TLB.
+    unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::PAGE_GLOBAL)) };
+    true
+}
+
+/// Writes back and invalidates all cache lines that contain any byte of
+/// the `size_in_bytes` bytes of virtual memory starting at `vaddr`.
+///
+/// This uses `clflush` on each cache line, which reaches every cache in the coherence domain.
+/// If `clflush` isn't supported, this falls back to `wbinvd`, which writes back
+/// and invalidates the entire cache of the current CPU and is very slow.
+///
+/// This is only needed when sharing cacheable memory with a device that doesn't snoop
+/// the CPU caches, which is rare on x86_64.
+pub fn cache_flush_range(vaddr: VirtualAddress, size_in_bytes: usize) {
+    use core::arch::x86_64::{__cpuid, _mm_clflush, _mm_mfence};
+
+    /// The size in bytes of the cache line flushed by `clflush`, or `0` if unsupported.
+    static CLFLUSH_LINE_SIZE: spin::Once<usize> = spin::Once::new();
+    const CPUID_CLFLUSH_SUPPORTED: u32 = 1 << 19;
+
+    if size_in_bytes == 0 {
+        return;
+    }
+    let line_size = *CLFLUSH_LINE_SIZE.call_once(|| {
+        // SAFE: CPUID leaf 1 is supported on every x86_64 CPU.
+        let leaf_1 = unsafe { __cpuid(1) };
+        if leaf_1.edx & CPUID_CLFLUSH_SUPPORTED != 0 {
+            (((leaf_1.ebx >> 8) & 0xFF) as usize) * 8
+        } else {
+            0
+        }
+    });
+
+    if line_size == 0 {
+        // SAFE: `wbinvd` has no effect on memory contents, only on where they are cached.
+        unsafe { core::arch::asm!("wbinvd", options(nostack, preserves_flags)) };
+        return;
+    }
+    let start = vaddr.value() & !(line_size - 1);
+    let end = vaddr.value() + size_in_bytes;
+    // SAFE: `clflush` doesn't modify memory contents, and the given range is mapped.
+    unsafe {
+        _mm_mfence();
+        for line in (start .. end).step_by(line_size) {
+            _mm_clflush(line as *const u8);
+        }
+        _mm_mfence();
+    }
+}
+
 /// Returns the current top-level page table address.
 pub fn get_p4() -> PhysicalAddress {
     PhysicalAddress::new_canonical(
@@ -285,10 +360,23 @@ pub fn get_p4() -> PhysicalAddress {
     )
 }
 
+/// Returns the paging depth currently used by this CPU,
+/// which is 5-level paging if the bootloader enabled LA57.
+pub fn active_paging_depth() -> PagingDepth {
+    if Cr4::read().contains(Cr4Flags::L5_PAGING) {
+        PagingDepth::FiveLevel
+    } else {
+        PagingDepth::FourLevel
+    }
+}
+
 /// Converts the given multiboot2 section's flags into `PteFlags`.
+///
+/// Kernel sections are mapped identically in every page table, so they are mapped as global.
 fn convert_to_pte_flags(section: &impl ElfSection) -> PteFlags {
     use boot_info::ElfSectionFlags;
     PteFlags::new()
+        .global(true)
         .valid(section.flags().contains(ElfSectionFlags::ALLOCATED))
         .writable(section.flags().contains(ElfSectionFlags::WRITABLE))
         .executable(section.flags().contains(ElfSectionFlags::EXECUTABLE))
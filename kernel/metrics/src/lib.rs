@@ -0,0 +1,185 @@
+//! A registry of named kernel metrics, and a stable, machine-readable snapshot of them
+//! for automated test runs to consume instead of scraping human-oriented log lines.
+//!
+//! Subsystems register their own metrics, so this crate needn't know about each of them:
+//! * a counter is a `static` [`AtomicU64`], registered via [`register_counter()`],
+//! * a gauge is a function that computes the current value, registered via [`register_gauge()`],
+//! * a group is a function that reports any number of metrics whose names aren't known in advance,
+//!   e.g., one per call site of something, registered via [`register_group()`], and
+//! * a one-off value, e.g., a boot stage timing or a test result, is set via
+//!   [`set_value()`] or [`set_label()`].
+//!
+//! [`emit_snapshot()`] writes a snapshot of every metric to the logger's serial output.
+//!
+//! ## Snapshot format, version 1
+//! ```text
+//! === BEGIN THESEUS METRICS v1 ===
+//! sched.context_switches=48213
+//! test.test_remap=pass
+//! === END THESEUS METRICS (2 entries) ===
+//! ```
+//! * The header line names the format version, which is incremented
+//!   upon any change to this format that would break an existing consumer.
+//! * Each line between the header and footer is one `key=value` entry, without spaces.
+//! * Keys are unique, non-empty, and consist only of `a-z`, `0-9`, `_`, `-`, and `.`,
+//!   where `.` separates the name of the subsystem from the name of the metric.
+//! * Values are either an unsigned decimal integer or a label made of
+//!   `a-z`, `A-Z`, `0-9`, `_`, `-`, and `.`, which never starts with a digit.
+//! * Entries appear in the order they were registered or first set.
+//! * The footer gives the number of entries, so a truncated snapshot can be detected.
+//!
+//! Consumers should ignore keys they don't recognize, because new metrics may be added
+//! without incrementing the format version.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt::Write, sync::atomic::{AtomicU64, Ordering}};
+use spin::Mutex;
+
+/// The version of the snapshot format, as written in its header line.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The source of a metric's value.
+#[derive(Clone, Copy)]
+enum Source {
+    Counter(&'static AtomicU64),
+    Gauge(fn() -> u64),
+    Group(fn(&mut dyn FnMut(&str, u64))),
+    Value(u64),
+    Label(&'static str),
+}
+
+/// All metrics, in the order they were registered or first set.
+static METRICS: Mutex<Vec<(String, Source)>> = Mutex::new(Vec::new());
+
+/// Registers the given `counter` under the given `name`.
+///
+/// Returns an error if `name` is invalid or already in use.
+pub fn register_counter(name: &str, counter: &'static AtomicU64) -> Result<(), &'static str> {
+    register(name, Source::Counter(counter))
+}
+
+/// Registers the given `gauge` function, which returns the metric's current value,
+/// under the given `name`.
+///
+/// The gauge is invoked whenever a snapshot is taken, so it must not
+/// itself take a snapshot or register any metrics.
+///
+/// Returns an error if `name` is invalid or already in use.
+pub fn register_gauge(name: &str, gauge: fn() -> u64) -> Result<(), &'static str> {
+    register(name, Source::Gauge(gauge))
+}
+
+/// Registers the given `group` function, which reports any number of related metrics
+/// by invoking its argument with each metric's name suffix and current value.
+///
+/// Each reported metric is named `<name>.<suffix>`. Suffixes must be unique within the group;
+/// metrics with an invalid suffix are left out of snapshots.
+/// Like a gauge, the group is invoked whenever a snapshot is taken.
+///
+/// Returns an error if `name` is invalid or already in use.
+pub fn register_group(name: &str, group: fn(&mut dyn FnMut(&str, u64))) -> Result<(), &'static str> {
+    register(name, Source::Group(group))
+}
+
+/// Sets the metric with the given `name` to the given integer `value`,
+/// adding it if it doesn't already exist.
+///
+/// Returns an error if `name` is invalid or in use by a counter, gauge, or group.
+pub fn set_value(name: &str, value: u64) -> Result<(), &'static str> {
+    set(name, Source::Value(value))
+}
+
+/// Sets the metric with the given `name` to the given `label`, e.g., `"pass"` or `"fail"`,
+/// adding it if it doesn't already exist.
+///
+/// Returns an error if `name` or `label` is invalid, or if `name` is in use by a counter, gauge, or group.
+pub fn set_label(name: &str, label: &'static str) -> Result<(), &'static str> {
+    let mut chars = label.chars();
+    let is_valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '-' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if !is_valid {
+        return Err("invalid metric label");
+    }
+    set(name, Source::Label(label))
+}
+
+/// Returns a snapshot of every metric, in the format described in the [crate-level docs](crate).
+pub fn snapshot() -> String {
+    // Gauges may acquire other locks, so they're invoked after releasing the registry lock.
+    let metrics = METRICS.lock().clone();
+    let mut out = String::new();
+    let mut entries = metrics.len();
+    let _ = writeln!(out, "=== BEGIN THESEUS METRICS v{} ===", FORMAT_VERSION);
+    for (name, source) in metrics.iter() {
+        let _ = match source {
+            Source::Group(group) => {
+                entries -= 1;
+                group(&mut |suffix, value| {
+                    if validate_name(suffix).is_ok() {
+                        let _ = writeln!(out, "{}.{}={}", name, suffix, value);
+                        entries += 1;
+                    }
+                });
+                Ok(())
+            }
+            Source::Counter(counter) => writeln!(out, "{}={}", name, counter.load(Ordering::Relaxed)),
+            Source::Gauge(gauge)     => writeln!(out, "{}={}", name, gauge()),
+            Source::Value(value)     => writeln!(out, "{}={}", name, value),
+            Source::Label(label)     => writeln!(out, "{}={}", name, label),
+        };
+    }
+    let _ = writeln!(out, "=== END THESEUS METRICS ({} entries) ===", entries);
+    out
+}
+
+/// Writes a [`snapshot()`] of every metric to the logger's outputs, e.g., the serial port.
+///
+/// The whole snapshot is written at once, so log records from other CPUs
+/// cannot be interleaved within it.
+pub fn emit_snapshot() -> Result<(), &'static str> {
+    logger::write_str(&snapshot())
+        .map_err(|_| "couldn't write the metrics snapshot to the logger")
+}
+
+fn register(name: &str, source: Source) -> Result<(), &'static str> {
+    validate_name(name)?;
+    let mut metrics = METRICS.lock();
+    if metrics.iter().any(|(n, _)| n == name) {
+        log::error!("A metric named {:?} was already registered", name);
+        return Err("a metric with that name was already registered");
+    }
+    metrics.push((name.to_string(), source));
+    Ok(())
+}
+
+fn set(name: &str, source: Source) -> Result<(), &'static str> {
+    validate_name(name)?;
+    let mut metrics = METRICS.lock();
+    match metrics.iter_mut().find(|(n, _)| n == name) {
+        Some((_, Source::Counter(_) | Source::Gauge(_) | Source::Group(_))) => {
+            Err("cannot set a metric that is a registered counter, gauge, or group")
+        }
+        Some((_, existing)) => {
+            *existing = source;
+            Ok(())
+        }
+        None => {
+            metrics.push((name.to_string(), source));
+            Ok(())
+        }
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), &'static str> {
+    let is_valid = !name.is_empty() && name.chars().all(|c|
+        c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-' || c == '.'
+    );
+    if is_valid { Ok(()) } else { Err("invalid metric name") }
+}
@@ -188,7 +188,9 @@ impl ConnectX5Nic {
         init_segment.set_physical_address_of_cmdq(cmdq_starting_phys_addr)?;
 
         // Read initializing field from initialization segment until it is cleared
-        while init_segment.device_is_initializing() {}
+        while init_segment.device_is_initializing() {
+            core::hint::spin_loop();
+        }
         trace!("initializing field is cleared.");
 
         // Execute ENABLE_HCA command
@@ -1215,7 +1215,9 @@ impl CommandQueue {
 
     /// Waits for ownership bit to be cleared, and then returns the command delivery status and the command return status.
     pub fn wait_for_command_completion(&self, command: &Command<{CmdState::Posted}>) {
-        while self.entries[command.entry_num].owned_by_hw() {}
+        while self.entries[command.entry_num].owned_by_hw() {
+            core::hint::spin_loop();
+        }
     }
 
     pub fn get_command_status(&mut self, command: Command<{CmdState::Completed}>) -> Result<CommandCompletionStatus, CommandQueueError> {
@@ -41,6 +41,11 @@ pub fn handle_ap_cores(
     kernel_mmi_ref: &MmiRef,
     _multicore_info: MulticoreBringupInfo,
 ) -> Result<u32, &'static str> {
+    if !boot_args::boot_args().smp {
+        log::warn!("Skipping AP bringup because the `smp=off` boot option was given");
+        return Ok(0);
+    }
+
     let mut online_secondary_cpus = 0;
 
     // This ApTrampolineData & MmuConfig will be read and written to
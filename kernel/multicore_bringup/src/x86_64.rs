@@ -7,7 +7,7 @@ use core::{
 use spin::Mutex;
 use volatile::Volatile;
 use zerocopy::FromBytes;
-use memory::{VirtualAddress, PhysicalAddress, MappedPages, PteFlags, MmiRef};
+use memory::{VirtualAddress, PhysicalAddress, FrameRange, MappedPages, PteFlags, MmiRef};
 use kernel_config::{memory::{PAGE_SIZE, PAGE_SHIFT, KERNEL_STACK_SIZE_IN_PAGES}, display::FRAMEBUFFER_MAX_RESOLUTION};
 use apic::{LocalApic, get_lapics, current_cpu, has_x2apic, bootstrap_cpu, cpu_count};
 use ap_start::{kstart_ap, AP_READY_FLAG};
@@ -177,10 +177,22 @@ pub struct MulticoreBringupInfo {
 /// * `max_framebuffer_resolution`: the maximum resolution `(width, height)` of the graphical framebuffer
 ///    that an AP should request from the BIOS when it boots up in 16-bit real mode.
 ///    If `None`, there will be no maximum.
+///
+/// If the system fell back to the legacy PIC (see [`apic::init_pic_mode()`]),
+/// no APs are booted, because they can be neither started nor sent IPIs without a Local APIC.
 pub fn handle_ap_cores(
     kernel_mmi_ref: &MmiRef,
     multicore_info: MulticoreBringupInfo,
 ) -> Result<u32, &'static str> {
+    if let Some(reason) = apic::pic_fallback_reason() {
+        warn!("Skipping AP bringup (nosmp is implied) because the system is in PIC mode: {}", reason);
+        return Ok(0);
+    }
+    if !boot_args::boot_args().smp {
+        warn!("Skipping AP bringup because the `smp=off` boot option was given");
+        return Ok(0);
+    }
+
     let MulticoreBringupInfo {
         ap_start_realmode_begin,
         ap_start_realmode_end,
@@ -346,6 +358,13 @@ pub fn handle_ap_cores(
         }
         iter += 1;
     }
+
+    // The trampoline and AP startup code are only needed while APs are booting,
+    // so their frames can be given back to the general-purpose allocator later.
+    memory::mark_reclaimable_after_boot(FrameRange::from_phys_addr(
+        PhysicalAddress::new_canonical(TRAMPOLINE),
+        PAGE_SIZE + ap_startup_size_in_bytes,
+    ));
     
     Ok(ap_count)  
 }
@@ -509,4 +528,9 @@ fn bring_up_ap(
         spin_loop();
     }
     info!(" AP {} is in Rust code. Ready!", new_apic_id);
+
+    match tsc::synchronize_cpu(new_apic_id) {
+        Ok(sync) => info!(" AP {} TSC offset: {} cycles (round trip {} cycles)", new_apic_id, sync.offset, sync.round_trip),
+        Err(e) => warn!(" AP {} TSC could not be synchronized: {}", new_apic_id, e),
+    }
 }
@@ -98,7 +98,7 @@ const HEAP_GROWTH_AMOUNT: usize = 2;
 fn initialize_multiple_heaps() -> Result<MultipleHeaps, &'static str> {
     let mut multiple_heaps = MultipleHeaps::empty();
 
-    for (apic_id, _lapic) in apic::get_lapics().iter() {
+    for apic_id in apic::cpu_ids() {
         init_individual_heap(apic_id.value() as usize, &mut multiple_heaps)?;
     }
 
@@ -257,9 +257,14 @@ if #[cfg(unsafe_heap)] {
 ///
 /// This implementation uses the current CPU ID as the key,
 /// but this can easily be replaced with another value, e.g., Task ID.
+/// If the current CPU's ID isn't known yet, early in its bringup,
+/// this falls back to the bootstrap CPU's heap, which is safe to share as every heap is locked.
 #[inline(always)] 
 fn get_key() -> usize {
-    apic::current_cpu().value() as usize
+    apic::try_current_cpu()
+        .or_else(apic::bootstrap_cpu)
+        .unwrap_or(apic::INVALID_APIC_ID)
+        .value() as usize
 }
 
 // The LockedHeap struct definition changes depending on the slabmalloc version used.
@@ -322,7 +327,7 @@ if #[cfg(unsafe_heap)] {
                 #[cfg(not(unsafe_large_allocations))]
                 large_allocations: IrqSafeMutex::new(RBTree::new(LargeAllocationAdapter::new())),
 
-                end: IrqSafeMutex::new(VirtualAddress::new_canonical(KERNEL_HEAP_START + KERNEL_HEAP_INITIAL_SIZE)),
+                end: IrqSafeMutex::new(VirtualAddress::new_canonical(KERNEL_HEAP_START + KERNEL_HEAP_INITIAL_SIZE.value())),
 
                 mp: Once::new()
             }
@@ -386,7 +391,7 @@ if #[cfg(unsafe_heap)] {
                 #[cfg(not(unsafe_large_allocations))]
                 large_allocations: IrqSafeMutex::new(RBTree::new(LargeAllocationAdapter::new())),
 
-                end: IrqSafeMutex::new(VirtualAddress::new_canonical(KERNEL_HEAP_START + KERNEL_HEAP_INITIAL_SIZE))
+                end: IrqSafeMutex::new(VirtualAddress::new_canonical(KERNEL_HEAP_START + KERNEL_HEAP_INITIAL_SIZE.value()))
             }
         }
 
@@ -434,7 +439,7 @@ if #[cfg(unsafe_heap)] {
                 #[cfg(not(unsafe_large_allocations))]
                 large_allocations: IrqSafeMutex::new(RBTree::new(LargeAllocationAdapter::new())),
 
-                end: IrqSafeMutex::new(VirtualAddress::new_canonical(KERNEL_HEAP_START + KERNEL_HEAP_INITIAL_SIZE))
+                end: IrqSafeMutex::new(VirtualAddress::new_canonical(KERNEL_HEAP_START + KERNEL_HEAP_INITIAL_SIZE.value()))
             }
         }
 
@@ -15,6 +15,7 @@ pub extern "C" fn rust_entry(boot_info_vaddr: usize, double_fault_stack_top: usi
 fn inner(boot_info_vaddr: usize, double_fault_stack_top: usize) -> Result<(), &'static str> {
     VirtualAddress::new(boot_info_vaddr)
         .ok_or("BUG: multiboot2 info virtual address is invalid")?;
+    boot_info::multiboot2::check_info_pointer(boot_info_vaddr)?;
     let boot_info = unsafe { multiboot2::load(boot_info_vaddr) }
         .ok()
         .ok_or("BUG: failed to load multiboot 2 info")?;
@@ -22,6 +22,7 @@
 extern crate panic_entry;
 
 use core::ops::DerefMut;
+use boot_info::Severity;
 use captain::MulticoreBringupInfo;
 use memory::VirtualAddress;
 use mod_mgmt::parse_nano_core::NanoCoreItems;
@@ -65,6 +66,8 @@ macro_rules! try_exit {
 fn shutdown(msg: core::fmt::Arguments) -> ! {
     println!("Theseus is shutting down, msg: {}", msg);
     log::error!("Theseus is shutting down, msg: {}", msg);
+    #[cfg(target_arch = "x86_64")]
+    pit_clock_basic::speaker::fatal_beep(pit_clock_basic::speaker::BeepCode::BootFailure);
 
     // TODO: handle shutdowns properly with ACPI commands
     panic!("{}", msg);
@@ -110,6 +113,27 @@ where
         println!("nano_core(): initialized early IDT with exception handlers.");
     }
 
+    // Check the boot information before anything else consumes it.
+    let report = boot_info.validate();
+    for problem in report.problems() {
+        match problem.severity() {
+            Severity::Fatal => log::error!("boot information: {}", problem),
+            Severity::Warning => log::warn!("boot information: {}", problem),
+        }
+    }
+    if report.num_unrecorded() > 0 {
+        log::warn!("boot information: {} more problems were not recorded", report.num_unrecorded());
+    }
+    if report.is_fatal() {
+        return Err("the bootloader-provided boot information is malformed; see the problems logged above");
+    }
+
+    // Parse the boot options on the kernel command line, which override some compile-time defaults.
+    let boot_args = boot_args::init(boot_info.command_line().unwrap_or(""));
+    if let Some(level) = boot_args.log_level {
+        logger::set_log_level(level);
+    }
+
     // If the bootloader already mapped the framebuffer for us, then we can use it now.
     if let Some(ref fb_info) = boot_info.framebuffer_info() && fb_info.is_mapped() {
         early_printer::init(fb_info, None).unwrap_or_else(|_e|
@@ -132,7 +156,7 @@ where
     // On aarch64, serial port access requires memory mapping.
     #[cfg(target_arch = "aarch64")] {
         let logger_ports = [take_serial_port(SerialPortAddress::COM1)];
-        logger::early_init(None, IntoIterator::into_iter(logger_ports).flatten());
+        logger::early_init(boot_args.log_level, IntoIterator::into_iter(logger_ports).flatten());
         log::info!("initialized early logger with aarch64 serial ports.");
         println!("nano_core(): initialized early logger with aarch64 serial ports.");
     }
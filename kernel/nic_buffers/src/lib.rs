@@ -1,4 +1,8 @@
 //! Defines buffers that are used to send and receive packets.
+//!
+//! Both buffer types are backed by a [`DmaBuffer`], so they must be handed to
+//! the NIC with `prepare_for_device()` and returned to the CPU with
+//! `complete_from_device()` before their contents can be accessed again.
 
 #![no_std]
 
@@ -6,17 +10,18 @@ extern crate alloc;
 #[macro_use] extern crate log;
 extern crate memory;
 extern crate mpmc;
+extern crate dma_buffer;
 
 use core::ops::{Deref, DerefMut};
 use alloc::vec::Vec;
-use memory::{PhysicalAddress, MappedPages, PteFlags, create_contiguous_mapping};
+use memory::{PhysicalAddress, MappedPages};
+use dma_buffer::{DmaBuffer, DmaDirection, DmaOwner};
 
 /// A buffer that stores a packet to be transmitted through the NIC
 /// and is guaranteed to be contiguous in physical memory. 
 /// Auto-dereferences into a byte slice that represents its underlying memory. 
 pub struct TransmitBuffer {
-    mp: MappedPages,
-    phys_addr: PhysicalAddress,
+    buf: DmaBuffer,
     length: u16,
 }
 
@@ -24,19 +29,26 @@ impl TransmitBuffer {
     /// Creates a new TransmitBuffer with the specified size in bytes.
     /// The size is a `u16` because that is the maximum size of an NIC transmit buffer. 
     pub fn new(size_in_bytes: u16) -> Result<TransmitBuffer, &'static str> {
-        let (mp, starting_phys_addr) = create_contiguous_mapping(
-            size_in_bytes as usize,
-            PteFlags::new().writable(true).device_memory(true),
-        )?;
         Ok(TransmitBuffer {
-            mp,
-            phys_addr: starting_phys_addr,
+            buf: DmaBuffer::new(size_in_bytes as usize)?,
             length: size_in_bytes,
         })
     }
 
     pub fn phys_addr(&self) -> PhysicalAddress {
-        self.phys_addr
+        self.buf.phys_addr()
+    }
+
+    /// Hands this buffer to the NIC to be transmitted.
+    ///
+    /// The buffer's contents can't be accessed until [`Self::complete_from_device()`] is invoked.
+    pub fn prepare_for_device(&mut self) {
+        self.buf.prepare_for_device(DmaDirection::ToDevice);
+    }
+
+    /// Returns this buffer to the CPU once the NIC has finished transmitting it.
+    pub fn complete_from_device(&mut self) {
+        self.buf.complete_from_device();
     }
 
     pub fn length(&self) -> u16 {
@@ -63,7 +75,7 @@ impl Deref for TransmitBuffer {
         // We checked that the mapped pages are >= to self.length during initialisation.
         // There can be no overflows since length is a u16, nor can there be alignment
         // issues because we are operating on u8s.
-        self.mp.as_slice(0, self.length.into()).unwrap() 
+        self.buf.as_slice(0, self.length.into()).unwrap() 
     }
 }
 
@@ -73,7 +85,7 @@ impl DerefMut for TransmitBuffer {
         // and that they are writable. There can be no overflows since length is
         // a u16, nor can there be alignment issues because we are operating on
         // u8s.
-        self.mp.as_slice_mut(0, self.length.into()).unwrap()
+        self.buf.as_slice_mut(0, self.length.into()).unwrap()
     }
 }
 
@@ -83,8 +95,7 @@ impl DerefMut for TransmitBuffer {
 /// Auto-dereferences into a byte slice that represents its underlying memory. 
 /// When dropped, its underlying memory is automatically returned to the NIC driver for future reuse.
 pub struct ReceiveBuffer {
-    mp: MappedPages,
-    phys_addr: PhysicalAddress,
+    buf: DmaBuffer,
     length: u16,
     pool: &'static mpmc::Queue<ReceiveBuffer>,
 }
@@ -92,6 +103,8 @@ pub struct ReceiveBuffer {
 impl ReceiveBuffer {
     /// Creates a new ReceiveBuffer with the given `MappedPages`, `PhysicalAddress`, and `length`. 
     /// When this ReceiveBuffer object is dropped, it will be returned to the given `pool`.
+    ///
+    /// The given `mp` must be mapped to physically-contiguous memory starting at `phys_addr`.
     pub fn new(mp: MappedPages, phys_addr: PhysicalAddress, length: u16, pool: &'static mpmc::Queue<ReceiveBuffer>) -> Result<ReceiveBuffer, &'static str> {
         if usize::from(length) > mp.size_in_bytes() {
             Err("mapped pages too small")
@@ -99,8 +112,7 @@ impl ReceiveBuffer {
             Err("mapped pages aren't writable")
         } else {
             Ok(ReceiveBuffer {
-                mp,
-                phys_addr,
+                buf: DmaBuffer::from_contiguous_mapping(mp, phys_addr)?,
                 length,
                 pool,
             })
@@ -108,7 +120,19 @@ impl ReceiveBuffer {
     }
 
     pub fn phys_addr(&self) -> PhysicalAddress {
-        self.phys_addr
+        self.buf.phys_addr()
+    }
+
+    /// Hands this buffer to the NIC such that a packet can be received into it.
+    ///
+    /// The buffer's contents can't be accessed until [`Self::complete_from_device()`] is invoked.
+    pub fn prepare_for_device(&mut self) {
+        self.buf.prepare_for_device(DmaDirection::FromDevice);
+    }
+
+    /// Returns this buffer to the CPU once the NIC has finished receiving a packet into it.
+    pub fn complete_from_device(&mut self) {
+        self.buf.complete_from_device();
     }
 
     pub fn length(&self) -> u16 {
@@ -135,7 +159,7 @@ impl Deref for ReceiveBuffer {
         // We checked that the mapped pages are >= to self.length during initialisation.
         // There can be no overflows since length is a u16, nor can there be alignment
         // issues because we are operating on u8s.
-        self.mp.as_slice(0, usize::from(self.length)).unwrap()
+        self.buf.as_slice(0, usize::from(self.length)).unwrap()
     }
 }
 
@@ -145,22 +169,27 @@ impl DerefMut for ReceiveBuffer {
         // and that they are writable. There can be no overflows since length is
         // a u16, nor can there be alignment issues because we are operating on
         // u8s.
-        self.mp.as_slice_mut(0, usize::from(self.length)).unwrap()
+        self.buf.as_slice_mut(0, usize::from(self.length)).unwrap()
     }
 }
 
 impl Drop for ReceiveBuffer {
     fn drop(&mut self) {
+        // A buffer still owned by the NIC is only dropped when its receive queue is torn down,
+        // after which the NIC can no longer write into it, so it's safe to reclaim it.
+        if self.buf.owner() != DmaOwner::Cpu {
+            self.buf.complete_from_device();
+        }
+
         // trace!("ReceiveBuffer::drop(): length: {:5}, phys_addr: {:#X}, vaddr: {:#X}", self.length,  self.phys_addr, self.mp.start_address());
 
         // We need to return this ReceiveBuffer to its memory pool. We use a clever trick here:
         // Since we cannot move this receive buffer out of `self` because it's borrowed, 
         // we construct a new ReceiveBuffer object that is identical to this one being dropped,
-        // and do an in-place replacement of its `MappedPages` object with an empty MP object,
-        // allowing us to take ownership of the real MP object and put it into the new_rb. 
+        // and do an in-place replacement of its `DmaBuffer` object with an empty one,
+        // allowing us to take ownership of the real `DmaBuffer` object and put it into the new_rb. 
         let new_rb = ReceiveBuffer {
-            mp: core::mem::replace(&mut self.mp, MappedPages::empty()),
-            phys_addr: self.phys_addr,
+            buf: core::mem::replace(&mut self.buf, DmaBuffer::empty()),
             length: 0,
             pool: self.pool,
         };
@@ -168,10 +197,10 @@ impl Drop for ReceiveBuffer {
 
         // Now, we can add the new receive buffer to the pool 
         if let Err(_e) = self.pool.push(new_rb) {
-            error!("NIC: couldn't return dropped ReceiveBuffer to pool, buf length: {}, phys_addr: {:#X}", _e.length, _e.phys_addr);
+            error!("NIC: couldn't return dropped ReceiveBuffer to pool, buf length: {}, phys_addr: {:#X}", _e.length, _e.phys_addr());
         }
 
-        // `self` will be automatically dropped now, which only has the empty MP object.
+        // `self` will be automatically dropped now, which only has the empty `DmaBuffer` object.
     }
 }
 
@@ -12,6 +12,7 @@ extern crate intel_ethernet;
 extern crate nic_buffers;
 extern crate volatile;
 extern crate nic_queues;
+extern crate dma_buffer;
 
 use alloc::vec::Vec;
 use intel_ethernet::descriptors::{RxDescriptor, TxDescriptor};
@@ -64,7 +65,7 @@ pub fn init_rx_queue<T: RxDescriptor, S:RxQueueRegisters>(num_desc: usize, rx_bu
     for rd in rx_descs.iter_mut()
     {
         // obtain or create a receive buffer for each rx_desc
-        let rx_buf = rx_buffer_pool.pop()
+        let mut rx_buf = rx_buffer_pool.pop()
             .ok_or("Couldn't obtain a ReceiveBuffer from the pool")
             .or_else(|_e| {
                 create_contiguous_mapping(buffer_size, MMIO_FLAGS)
@@ -72,7 +73,9 @@ pub fn init_rx_queue<T: RxDescriptor, S:RxQueueRegisters>(num_desc: usize, rx_bu
                         ReceiveBuffer::new(buf_mapped, buf_paddr, buffer_size as u16, rx_buffer_pool)
                     )
             })?;
+        rx_buf.prepare_for_device();
         let paddr_buf = rx_buf.phys_addr();
+        dma_buffer::assert_live_dma_address(paddr_buf, buffer_size);
         rx_bufs_in_use.push(rx_buf); 
 
 
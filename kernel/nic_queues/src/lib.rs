@@ -14,6 +14,7 @@ extern crate memory;
 extern crate intel_ethernet;
 extern crate nic_buffers;
 extern crate cpu;
+extern crate dma_buffer;
 
 use alloc::{
     vec::Vec,
@@ -94,7 +95,7 @@ impl<S: RxQueueRegisters, T: RxDescriptor> RxQueue<S,T> {
             // Now that we are "removing" the current receive buffer from the list of receive buffers that the NIC can use,
             // (because we're saving it for higher layers to use),
             // we need to obtain a new `ReceiveBuffer` and set it up such that the NIC will use it for future receivals.
-            let new_receive_buf = match self.rx_buffer_pool.pop() {
+            let mut new_receive_buf = match self.rx_buffer_pool.pop() {
                 Some(rx_buf) => rx_buf,
                 None => {
                     warn!("NIC RX BUF POOL WAS EMPTY.... reallocating! This means that no task is consuming the accumulated received ethernet frames.");
@@ -106,12 +107,15 @@ impl<S: RxQueueRegisters, T: RxDescriptor> RxQueue<S,T> {
             };
 
             // actually tell the NIC about the new receive buffer, and that it's ready for use now
+            new_receive_buf.prepare_for_device();
+            dma_buffer::assert_live_dma_address(new_receive_buf.phys_addr(), self.rx_buffer_size_bytes as usize);
             self.rx_descs[cur].set_packet_address(new_receive_buf.phys_addr());
 
             // Swap in the new receive buffer at the index corresponding to this current rx_desc's receive buffer,
             // getting back the receive buffer that is part of the received ethernet frame
             self.rx_bufs_in_use.push(new_receive_buf);
             let mut current_rx_buf = self.rx_bufs_in_use.swap_remove(cur); 
+            current_rx_buf.complete_from_device();
             current_rx_buf.set_length(length as u16)?; // set the ReceiveBuffer's length to the size of the actual packet received
             receive_buffers_in_frame.push(current_rx_buf);
 
@@ -161,7 +165,9 @@ impl<S: TxQueueRegisters, T: TxDescriptor> TxQueue<S,T> {
     /// 
     /// # Arguments:
     /// * `transmit_buffer`: buffer containing the packet to be sent
-    pub fn send_on_queue(&mut self, transmit_buffer: TransmitBuffer) {
+    pub fn send_on_queue(&mut self, mut transmit_buffer: TransmitBuffer) {
+        transmit_buffer.prepare_for_device();
+        dma_buffer::assert_live_dma_address(transmit_buffer.phys_addr(), transmit_buffer.length() as usize);
         self.tx_descs[self.tx_cur as usize].send(transmit_buffer.phys_addr(), transmit_buffer.length());
         // update the tx_cur value to hold the next free descriptor
         let old_cur = self.tx_cur;
@@ -171,6 +177,7 @@ impl<S: TxQueueRegisters, T: TxDescriptor> TxQueue<S,T> {
         self.regs.set_tdt(self.tx_cur as u32);
         // Wait for the packet to be sent
         self.tx_descs[old_cur as usize].wait_for_packet_tx();
+        transmit_buffer.complete_from_device();
     }
 }
 
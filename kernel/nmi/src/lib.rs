@@ -0,0 +1,370 @@
+//! Dispatches non-maskable interrupts (NMIs) sent between CPUs to the subsystems that sent them.
+//!
+//! There is only one NMI vector, but several subsystems need to interrupt other CPUs
+//! with an NMI: TLB shootdowns, the gdb stub freezing other CPUs, halting other CPUs
+//! after a fatal error, probing a stuck CPU, and profilers that sample code
+//! running with interrupts disabled. Moreover, the hardware coalesces NMIs that arrive
+//! while one is already pending, so a single NMI may carry several requests.
+//!
+//! Thus, each CPU has an NMI mailbox, consisting of a word of pending [`NmiReason`]s
+//! and a payload for each reason. [`send_nmi()`] and [`send_nmi_to_others()`] write the payload
+//! and set the reason in the target CPU's mailbox before sending the NMI.
+//! The NMI handler invokes [`handle_nmi()`], which claims all pending reasons and dispatches
+//! each of them, either internally or to the handler registered for it via [`register_handler()`].
+//! An NMI without a pending reason came from hardware, e.g., a performance counter overflow
+//! or a memory parity error, which the NMI handler must handle itself;
+//! see [`hardware_nmi_status()`].
+//!
+//! Everything here may run in NMI context, which can interrupt any code,
+//! so it never acquires locks or allocates memory.
+//! NMIs can also nest: an exception within the NMI handler returns via `iret`,
+//! which unblocks NMIs. A nested NMI only leaves its reasons in this CPU's mailbox,
+//! which the outer invocation of [`handle_nmi()`] then dispatches.
+
+#![no_std]
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+use apic::LapicIpiDestination;
+use cpu::CpuId;
+use x86_64::{
+    instructions::{hlt, interrupts, port::Port},
+    structures::idt::InterruptStackFrame,
+};
+
+/// The maximum number of CPUs that can receive NMIs via [`send_nmi()`].
+const MAX_CPUS: usize = 256;
+/// How many times [`probe()`] spins while waiting for the probed CPU to respond.
+const PROBE_TIMEOUT_SPINS: usize = 100_000_000;
+
+/// The reason that an NMI was sent to a CPU.
+///
+/// When several reasons are pending at once, they are dispatched in the order listed here,
+/// such that the reasons that never return, e.g., [`NmiReason::Halt`], are dispatched last.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NmiReason {
+    /// Flush entries from the TLB for a TLB shootdown.
+    TlbShootdown = 0,
+    /// Take a sample of the interrupted context for a profiler.
+    ProfileSample = 1,
+    /// Report the interrupted instruction and stack pointers; see [`probe()`].
+    Probe = 2,
+    /// Freeze until released, e.g., while the gdb stub is active.
+    Freeze = 3,
+    /// Halt forever, e.g., after a fatal error on another CPU.
+    Halt = 4,
+}
+
+impl NmiReason {
+    /// All reasons, in the order that they are dispatched.
+    const ALL: [NmiReason; NUM_REASONS] = [
+        NmiReason::TlbShootdown,
+        NmiReason::ProfileSample,
+        NmiReason::Probe,
+        NmiReason::Freeze,
+        NmiReason::Halt,
+    ];
+
+    /// Returns this reason's bit in a mailbox's pending word.
+    const fn bit(self) -> u32 {
+        1 << self as u8
+    }
+
+    /// Returns whether this reason is handled within this crate
+    /// rather than by a handler registered via [`register_handler()`].
+    const fn is_builtin(self) -> bool {
+        matches!(self, NmiReason::Probe | NmiReason::Halt)
+    }
+}
+
+const NUM_REASONS: usize = 5;
+
+/// A handler for an [`NmiReason`], which receives the interrupted context
+/// and the payload that was sent along with that reason.
+///
+/// It runs in NMI context, so it must not acquire any locks that may be held by the code
+/// it interrupted (including logging or printing) and must not allocate memory.
+pub type NmiHandler = fn(&InterruptStackFrame, u64);
+
+/// The handler registered for each reason, stored as the address of an [`NmiHandler`],
+/// or `0` if none is registered.
+static HANDLERS: [AtomicUsize; NUM_REASONS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE: AtomicUsize = AtomicUsize::new(0);
+    [NONE; NUM_REASONS]
+};
+
+/// The number of CPUs that have halted due to [`NmiReason::Halt`].
+static HALTED_CPUS: AtomicU32 = AtomicU32::new(0);
+
+/// A CPU's NMI mailbox.
+struct Mailbox {
+    /// The bits of the reasons that were sent to this CPU but not yet dispatched.
+    pending: AtomicU32,
+    /// The payload of the most recent NMI sent for each reason.
+    payloads: [AtomicU64; NUM_REASONS],
+    /// The number of NMIs that were sent to this CPU.
+    sent: AtomicU32,
+    /// The value of `sent` that this CPU's last NMI accounted for.
+    /// Only accessed by this CPU.
+    accounted: AtomicU32,
+    /// The number of NMIs sent to this CPU that may still arrive after their reasons
+    /// were already dispatched, because they were sent while this CPU was handling an NMI.
+    /// Only accessed by this CPU.
+    stale: AtomicU32,
+    /// Whether this CPU is currently in [`handle_nmi()`].
+    handling: AtomicBool,
+    /// The number of times this CPU has responded to [`NmiReason::Probe`].
+    probe_count: AtomicU64,
+    /// The instruction pointer that this CPU's most recent probe interrupted.
+    probe_instruction_pointer: AtomicU64,
+    /// The stack pointer that this CPU's most recent probe interrupted.
+    probe_stack_pointer: AtomicU64,
+}
+
+static MAILBOXES: [Mailbox; MAX_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Mailbox = Mailbox {
+        pending: AtomicU32::new(0),
+        payloads: [ZERO; NUM_REASONS],
+        sent: AtomicU32::new(0),
+        accounted: AtomicU32::new(0),
+        stale: AtomicU32::new(0),
+        handling: AtomicBool::new(false),
+        probe_count: AtomicU64::new(0),
+        probe_instruction_pointer: AtomicU64::new(0),
+        probe_stack_pointer: AtomicU64::new(0),
+    };
+    [EMPTY; MAX_CPUS]
+};
+
+fn mailbox(cpu: CpuId) -> Option<&'static Mailbox> {
+    MAILBOXES.get(cpu.value() as usize)
+}
+
+impl Mailbox {
+    /// Posts the given `reason` and `payload` to this mailbox,
+    /// which must be followed by sending an NMI to its CPU.
+    fn post(&self, reason: NmiReason, payload: u64) {
+        self.payloads[reason as usize].store(payload, Ordering::Relaxed);
+        self.pending.fetch_or(reason.bit(), Ordering::Release);
+        self.sent.fetch_add(1, Ordering::Release);
+    }
+}
+
+
+/// Registers `handler` to be invoked whenever an NMI is received for the given `reason`.
+///
+/// Returns an error if a handler is already registered for `reason`,
+/// or if `reason` is handled within this crate.
+pub fn register_handler(reason: NmiReason, handler: NmiHandler) -> Result<(), &'static str> {
+    if reason.is_builtin() {
+        return Err("nmi::register_handler(): this reason cannot have a registered handler");
+    }
+    HANDLERS[reason as usize]
+        .compare_exchange(0, handler as usize, Ordering::AcqRel, Ordering::Acquire)
+        .map(|_| ())
+        .map_err(|_| "nmi::register_handler(): a handler is already registered for this reason")
+}
+
+/// Sends an NMI for the given `reason` to the given `cpu`, which may be the current CPU.
+///
+/// The `payload` is passed to the handler for `reason`.
+/// If the same reason is sent to a CPU again before that CPU has handled it,
+/// both are handled once, with the most recent payload.
+pub fn send_nmi(cpu: CpuId, reason: NmiReason, payload: u64) -> Result<(), &'static str> {
+    let mailbox = mailbox(cpu).ok_or("nmi::send_nmi(): the CPU's ID is too large to have a mailbox")?;
+    let my_lapic = apic::get_my_apic().ok_or("nmi::send_nmi(): this CPU has no Local APIC")?;
+    mailbox.post(reason, payload);
+    my_lapic.write().send_nmi_ipi(LapicIpiDestination::One(cpu.into()));
+    Ok(())
+}
+
+/// Sends an NMI for the given `reason` to all CPUs except the current one.
+///
+/// See [`send_nmi()`] for how the `payload` is handled.
+///
+/// Returns the number of other CPUs that the NMI was sent to.
+pub fn send_nmi_to_others(reason: NmiReason, payload: u64) -> Result<u32, &'static str> {
+    let my_lapic = apic::get_my_apic().ok_or("nmi::send_nmi_to_others(): this CPU has no Local APIC")?;
+    let me = cpu::current_cpu();
+    let mut others = 0;
+    for cpu in cpu::cpus().filter(|&cpu| cpu != me) {
+        if let Some(mailbox) = mailbox(cpu) {
+            mailbox.post(reason, payload);
+            others += 1;
+        }
+    }
+    if others > 0 {
+        my_lapic.write().send_nmi_ipi(LapicIpiDestination::AllButMe);
+    }
+    Ok(others)
+}
+
+/// Handles an NMI received by the current CPU by dispatching all reasons pending in its mailbox.
+///
+/// This must be invoked from the NMI handler before any hardware NMI sources are checked.
+///
+/// ## Return
+/// * `true` if this NMI was sent via [`send_nmi()`] or [`send_nmi_to_others()`],
+///   in which case it has been handled.
+/// * `false` if this NMI came from hardware.
+pub fn handle_nmi(stack_frame: &InterruptStackFrame) -> bool {
+    let Some(mailbox) = cpu::try_current_cpu().and_then(mailbox) else {
+        return false;
+    };
+    // The number of NMIs sent to this CPU must be read before claiming its pending reasons,
+    // so that every NMI counted here has already posted its reason.
+    let sent = mailbox.sent.load(Ordering::Acquire);
+    let unaccounted = sent.wrapping_sub(mailbox.accounted.swap(sent, Ordering::Relaxed));
+
+    if mailbox.handling.swap(true, Ordering::Acquire) {
+        // This NMI is nested within another invocation of this function,
+        // which will dispatch any reasons posted for it.
+        return unaccounted > 0 || mailbox.pending.load(Ordering::Acquire) != 0;
+    }
+
+    let mut dispatched = false;
+    loop {
+        let pending = mailbox.pending.swap(0, Ordering::Acquire);
+        if pending == 0 {
+            mailbox.handling.store(false, Ordering::Release);
+            // A nested NMI may have posted its reason after the above check.
+            if mailbox.pending.load(Ordering::Acquire) == 0
+                || mailbox.handling.swap(true, Ordering::Acquire)
+            {
+                break;
+            }
+            continue;
+        }
+        for reason in NmiReason::ALL {
+            if pending & reason.bit() != 0 {
+                let payload = mailbox.payloads[reason as usize].load(Ordering::Relaxed);
+                dispatch(reason, payload, mailbox, stack_frame);
+            }
+        }
+        dispatched = true;
+    }
+
+    if dispatched {
+        // This NMI accounts for one of the NMIs sent since the last one;
+        // the others were either coalesced with it or are still latched,
+        // in which case they will arrive with no pending reason.
+        mailbox.stale.store(unaccounted.saturating_sub(1), Ordering::Relaxed);
+        return true;
+    }
+    if unaccounted > 0 {
+        // The reason for this NMI was already dispatched by a previous NMI.
+        return true;
+    }
+    mailbox.stale
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |stale| stale.checked_sub(1))
+        .is_ok()
+}
+
+fn dispatch(reason: NmiReason, payload: u64, mailbox: &Mailbox, stack_frame: &InterruptStackFrame) {
+    match reason {
+        NmiReason::Probe => {
+            mailbox.probe_instruction_pointer.store(stack_frame.instruction_pointer.as_u64(), Ordering::Relaxed);
+            mailbox.probe_stack_pointer.store(stack_frame.stack_pointer.as_u64(), Ordering::Relaxed);
+            mailbox.probe_count.fetch_add(1, Ordering::Release);
+        }
+        NmiReason::Halt => {
+            HALTED_CPUS.fetch_add(1, Ordering::AcqRel);
+            interrupts::disable();
+            loop { hlt(); }
+        }
+        _ => {
+            let handler = HANDLERS[reason as usize].load(Ordering::Acquire);
+            if handler != 0 {
+                // SAFETY: non-zero values were stored by `register_handler()` from an `NmiHandler`.
+                let handler: NmiHandler = unsafe { core::mem::transmute(handler) };
+                handler(stack_frame, payload);
+            }
+        }
+    }
+}
+
+
+/// The context of another CPU at the moment it was interrupted by [`probe()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProbeResult {
+    /// The address of the instruction that the probed CPU was about to execute.
+    pub instruction_pointer: usize,
+    /// The probed CPU's stack pointer.
+    pub stack_pointer: usize,
+}
+
+/// Probes what the given `cpu` is currently executing by sending it an NMI,
+/// which works even if that CPU is stuck with interrupts disabled.
+///
+/// Returns an error if `cpu` is the current CPU or didn't respond in time,
+/// e.g., because it is already handling another NMI.
+pub fn probe(cpu: CpuId) -> Result<ProbeResult, &'static str> {
+    if cpu::try_current_cpu() == Some(cpu) {
+        return Err("nmi::probe(): cannot probe the current CPU");
+    }
+    let mailbox = mailbox(cpu).ok_or("nmi::probe(): the CPU's ID is too large to have a mailbox")?;
+    let count = mailbox.probe_count.load(Ordering::Acquire);
+    send_nmi(cpu, NmiReason::Probe, 0)?;
+
+    let mut spins = 0;
+    while mailbox.probe_count.load(Ordering::Acquire) == count {
+        if spins >= PROBE_TIMEOUT_SPINS {
+            return Err("nmi::probe(): the CPU didn't respond to the NMI");
+        }
+        spin_loop();
+        spins += 1;
+    }
+    Ok(ProbeResult {
+        instruction_pointer: mailbox.probe_instruction_pointer.load(Ordering::Relaxed) as usize,
+        stack_pointer: mailbox.probe_stack_pointer.load(Ordering::Relaxed) as usize,
+    })
+}
+
+/// Returns the number of CPUs that have halted after receiving [`NmiReason::Halt`].
+pub fn halted_cpus() -> u32 {
+    HALTED_CPUS.load(Ordering::Acquire)
+}
+
+
+/// The sources of a hardware NMI that are reported by the system control port (`0x61`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HardwareNmiStatus {
+    /// A memory parity error or a PCI system error (`SERR#`) occurred.
+    pub parity_error: bool,
+    /// An I/O channel check error (`IOCHK#`) occurred, e.g., an uncorrectable bus error.
+    pub io_check_error: bool,
+}
+
+impl HardwareNmiStatus {
+    /// Returns a description of the cause of the hardware NMI.
+    pub fn description(&self) -> &'static str {
+        match (self.parity_error, self.io_check_error) {
+            (true, true) => "memory parity or PCI system error, and I/O channel check error",
+            (true, false) => "memory parity or PCI system error",
+            (false, true) => "I/O channel check error",
+            (false, false) => "unknown cause",
+        }
+    }
+}
+
+/// Reads the sources of a hardware NMI from the system control port.
+///
+/// This is a single port read, so it is safe to invoke in NMI context.
+pub fn hardware_nmi_status() -> HardwareNmiStatus {
+    const SYSTEM_CONTROL_PORT_B: u16 = 0x61;
+    const PARITY_ERROR: u8 = 1 << 7;
+    const IO_CHECK_ERROR: u8 = 1 << 6;
+    // SAFETY: reading this port has no side effects.
+    let status: u8 = unsafe { Port::new(SYSTEM_CONTROL_PORT_B).read() };
+    HardwareNmiStatus {
+        parity_error: status & PARITY_ERROR != 0,
+        io_check_error: status & IO_CHECK_ERROR != 0,
+    }
+}
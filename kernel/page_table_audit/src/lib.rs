@@ -0,0 +1,143 @@
+//! Reports statistics about the mappings of the kernel's page table
+//! and audits them for pages that are both writable and executable (W^X violations).
+//!
+//! The audit is run once at boot, after the kernel's mappings have reached their final permissions,
+//! and its report is available at any time by reading the `/page_tables` file.
+//! See [`memory::page_table_stats()`] for how the page tables are walked.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, sync::Arc};
+use core::fmt::Write;
+use fs_node::{DirRef, File, FileOrDir, FsNode, WeakDirRef};
+use io::{ByteReader, ByteWriter, IoError, KnownLength};
+use log::{info, warn};
+use memory::{AddressRegion, MappedPages, PageMapping, PageTableStats, Permissions, VirtualAddress};
+use spin::Mutex;
+
+/// The name of the file in the root directory that reports the page table statistics.
+pub const PAGE_TABLES_FILE_NAME: &str = "page_tables";
+
+/// Audits the kernel's page table and creates the `/page_tables` file.
+///
+/// Any page that is both writable and executable is logged as a warning,
+/// but doesn't cause this to fail.
+pub fn init() -> Result<(), &'static str> {
+    let stats = memory::page_table_stats()?;
+    for line in format_stats(&stats).lines() {
+        info!("{line}");
+    }
+    for mapping in &stats.writable_executable {
+        warn!("page table audit: {} violates W^X", format_mapping(mapping));
+    }
+    if !stats.writable_executable.is_empty() {
+        warn!("page table audit: {} pages are both writable and executable", stats.writable_executable.len());
+    }
+
+    let file = Arc::new(Mutex::new(PageTablesFile)) as fs_node::FileRef;
+    root::get_root().lock().insert(FileOrDir::File(file))?;
+    Ok(())
+}
+
+/// Returns a human-readable summary of the mappings of the kernel's page table.
+pub fn report() -> Result<String, &'static str> {
+    memory::page_table_stats().map(|stats| format_stats(&stats))
+}
+
+/// Returns a human-readable list of the mappings that intersect
+/// the `size_in_bytes` bytes of virtual memory starting at `start`.
+pub fn dump(start: VirtualAddress, size_in_bytes: usize) -> Result<String, &'static str> {
+    let mut out = String::new();
+    for mapping in memory::mappings_in(start, size_in_bytes)? {
+        let _ = writeln!(out, "{}", format_mapping(&mapping));
+    }
+    if out.is_empty() {
+        let _ = writeln!(out, "no mappings intersect {:#X} - {:#X}", start, start.value().saturating_add(size_in_bytes));
+    }
+    Ok(out)
+}
+
+/// Formats the given page table statistics as a human-readable summary.
+pub fn format_stats(stats: &PageTableStats) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "Mapped pages (in 4KiB units) by region:");
+    for (region, pages) in AddressRegion::ALL.iter().zip(stats.pages_by_region) {
+        let _ = writeln!(out, "    {:<12} {:>10}", region.name(), pages);
+    }
+    let _ = writeln!(out, "Mapped pages (in 4KiB units) by permissions:");
+    for (permissions, pages) in Permissions::ALL.iter().zip(stats.pages_by_permissions) {
+        let _ = writeln!(out, "    {:<12} {:>10}{}",
+            permissions,
+            pages,
+            if *permissions == Permissions::ReadWriteExecute && pages > 0 { "  <-- violates W^X" } else { "" },
+        );
+    }
+    let _ = writeln!(out, "Mapped pages by size: {} 4KiB, {} 2MiB, {} 1GiB", stats.pages_4k, stats.pages_2m, stats.pages_1g);
+    let _ = writeln!(out, "Page table frames: {} ({} KiB)", stats.page_table_frames, stats.page_table_frames * memory::PAGE_SIZE / 1024);
+    out
+}
+
+/// Formats the given mapping as a single line.
+pub fn format_mapping(mapping: &PageMapping) -> String {
+    let mut out = String::new();
+    let _ = write!(out, "{:#018X} - {:#018X} -> {:#014X} {:>8} KiB  {}  {:?}",
+        mapping.start,
+        mapping.start.value() + (mapping.size_in_bytes - 1),
+        mapping.frame,
+        mapping.size_in_bytes / 1024,
+        mapping.permissions,
+        mapping.flags,
+    );
+    out
+}
+
+
+/// A lazily-generated file that reports the current page table statistics.
+struct PageTablesFile;
+
+impl FsNode for PageTablesFile {
+    fn get_name(&self) -> String {
+        String::from(PAGE_TABLES_FILE_NAME)
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        Some(root::get_root().clone())
+    }
+
+    fn set_parent_dir(&mut self, _: WeakDirRef) {
+        // do nothing
+    }
+}
+
+impl ByteReader for PageTablesFile {
+    fn read_at(&mut self, buf: &mut [u8], offset: usize) -> Result<usize, IoError> {
+        let output = report().map_err(IoError::from)?;
+        if offset > output.len() {
+            return Err(IoError::InvalidInput);
+        }
+        let count = core::cmp::min(buf.len(), output.len() - offset);
+        buf[..count].copy_from_slice(&output.as_bytes()[offset..(offset + count)]);
+        Ok(count)
+    }
+}
+
+impl ByteWriter for PageTablesFile {
+    fn write_at(&mut self, _buffer: &[u8], _offset: usize) -> Result<usize, IoError> {
+        Err(IoError::from("the page table report is read-only"))
+    }
+    fn flush(&mut self) -> Result<(), IoError> { Ok(()) }
+}
+
+impl KnownLength for PageTablesFile {
+    fn len(&self) -> usize {
+        report().map_or(0, |output| output.len())
+    }
+}
+
+impl File for PageTablesFile {
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("the page table report is autogenerated, cannot be memory mapped")
+    }
+}
@@ -12,7 +12,8 @@
 
 extern crate alloc;
 
-use core::panic::PanicInfo;
+use core::{fmt::Write, panic::PanicInfo};
+use fixed_writer::FixedWriter;
 use log::error;
 
 #[cfg(target_arch = "x86_64")]
@@ -59,9 +60,21 @@ fn panic_entry_point(info: &PanicInfo) -> ! {
     };
 
     if let Err(_e) = res {
-        error!("Halting due to early panic: {}", info);
-        // basic early panic printing with no dependencies
-        println!("\nHalting due to early panic: {}", info);
+        {
+            // The heap may not exist yet or may be the cause of this panic, so don't allocate here.
+            let _no_alloc = heap::forbid_allocation();
+            let _bypass = logger::bypass_throttling();
+            let mut msg = FixedWriter::<512>::new();
+            let _ = write!(msg, "Halting due to early panic: {}", info);
+            error!("{}", msg);
+            // basic early panic printing with no dependencies
+            println!("\n{}", msg);
+        }
+        flush_devices_best_effort();
+        // The system can't continue, so stop the other CPUs too rather than let them
+        // keep running on top of whatever state this panic left behind.
+        #[cfg(target_arch = "x86_64")]
+        let _ = nmi::send_nmi_to_others(nmi::NmiReason::Halt, 0);
     }
 
     // If we failed to handle the panic, there's not really much we can do about it,
@@ -73,6 +86,28 @@ fn panic_entry_point(info: &PanicInfo) -> ! {
 
 
 
+/// Gives devices a chance to write out the data they hold before the system halts,
+/// reporting each one that couldn't be flushed.
+///
+/// This runs after the panic message is printed, because device shutdown hooks may allocate.
+/// They never block or yield the CPU, though, so this cannot hang.
+fn flush_devices_best_effort() {
+    let _bypass = logger::bypass_throttling();
+    let ran = device_manager::registry::shutdown_best_effort(|name, outcome| {
+        if outcome != device_manager::registry::FlushOutcome::Flushed {
+            let mut msg = FixedWriter::<128>::new();
+            let _ = write!(msg, "Couldn't flush {} before halting: {:?}", name, outcome);
+            error!("{}", msg);
+            println!("{}", msg);
+        }
+    });
+    if !ran {
+        error!("Couldn't flush any devices before halting: the shutdown hooks were locked");
+        println!("Couldn't flush any devices before halting: the shutdown hooks were locked");
+    }
+}
+
+
 /// Typically this would be an entry point in the unwinding procedure, in which a stack frame is unwound. 
 /// However, in Theseus we use our own unwinding flow which is simpler.
 /// 
@@ -128,7 +163,13 @@ extern "C" fn _Unwind_Resume(_arg: usize) -> ! {
 /// This is the callback entry point that gets invoked when the heap allocator runs out of memory.
 #[alloc_error_handler]
 #[cfg(not(test))]
-fn oom(_layout: core::alloc::Layout) -> ! {
-    error!("\n(oom) Out of Heap Memory! requested allocation: {:?}", _layout);
-    panic!("\n(oom) Out of Heap Memory! requested allocation: {:?}", _layout);
+fn oom(layout: core::alloc::Layout) -> ! {
+    // The heap is exhausted, so the report must be formatted without allocating.
+    let mut report = FixedWriter::<128>::new();
+    {
+        let _no_alloc = heap::forbid_allocation();
+        let _ = write!(report, "(oom) Out of Heap Memory! requested allocation: {:?}", layout);
+        error!("\n{}", report);
+    }
+    panic!("\n{}", report);
 }
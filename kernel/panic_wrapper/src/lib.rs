@@ -14,6 +14,10 @@ use task::{KillReason, PanicInfoOwned};
 
 #[cfg(target_arch = "x86_64")]
 use log::{error, warn};
+#[cfg(target_arch = "x86_64")]
+use core::fmt::{self, Write};
+#[cfg(target_arch = "x86_64")]
+use fixed_writer::FixedWriter;
 
 /// Performs the standard panic handling routine, which involves the following:
 /// 
@@ -23,7 +27,12 @@ use log::{error, warn};
 /// 
 /// Returns `Ok(())` if everything ran successfully, and `Err` otherwise.
 pub fn panic_wrapper(panic_info: &PanicInfo) -> Result<(), &'static str> {
-    trace!("at top of panic_wrapper: {:?}", panic_info);
+    // None of the diagnostics below may be suppressed or collapsed by log throttling.
+    let bypass = logger::bypass_throttling();
+    {
+        let _no_alloc = heap::forbid_allocation();
+        trace!("at top of panic_wrapper: {:?}", panic_info);
+    }
     log_panic_entry (panic_info);
     // fault_log::print_fault_log();
 
@@ -38,12 +47,11 @@ pub fn panic_wrapper(panic_info: &PanicInfo) -> Result<(), &'static str> {
                     let symbol_offset = stack_frame_iter.namespace().get_section_containing_address(
                         memory::VirtualAddress::new_canonical(stack_frame.call_site_address() as usize),
                         false
-                    ).map(|(sec, offset)| (sec.name.clone(), offset));
-                    if let Some((symbol_name, offset)) = symbol_offset {
-                        error!("  {:>#018X} in {} + {:#X}", stack_frame.call_site_address(), symbol_name, offset);
-                    } else {
-                        error!("  {:>#018X} in ??", stack_frame.call_site_address());
-                    }
+                    );
+                    print_stack_frame(
+                        stack_frame.call_site_address(),
+                        symbol_offset.as_ref().map(|(sec, offset)| (&*sec.name, *offset)),
+                    );
                     true
                 },
                 None,
@@ -68,13 +76,11 @@ pub fn panic_wrapper(panic_info: &PanicInfo) -> Result<(), &'static str> {
             stack_trace_frame_pointers::stack_trace_using_frame_pointers(
                 &mmi.page_table,
                 &mut |_frame_pointer, instruction_pointer: memory::VirtualAddress| {
-                    let symbol_offset = namespace.get_section_containing_address(instruction_pointer, false)
-                        .map(|(sec, offset)| (sec.name.clone(), offset));
-                    if let Some((symbol_name, offset)) = symbol_offset {
-                        error!("  {:>#018X} in {} + {:#X}", instruction_pointer, symbol_name, offset);
-                    } else {
-                        error!("  {:>#018X} in ??", instruction_pointer);
-                    }
+                    let symbol_offset = namespace.get_section_containing_address(instruction_pointer, false);
+                    print_stack_frame(
+                        instruction_pointer,
+                        symbol_offset.as_ref().map(|(sec, offset)| (&*sec.name, *offset)),
+                    );
                     true
                 },
                 None,
@@ -96,6 +102,12 @@ pub fn panic_wrapper(panic_info: &PanicInfo) -> Result<(), &'static str> {
         debug!("No kill handler callback in Task {:?}", task::get_my_current_task());
     }
 
+    #[cfg(target_arch = "x86_64")]
+    pit_clock_basic::speaker::fatal_beep(pit_clock_basic::speaker::BeepCode::Panic);
+
+    // Unwinding doesn't return here, so stop bypassing throttling now.
+    drop(bypass);
+
     // Start the unwinding process. Not yet supported on aarch64
     #[cfg(not(target_arch = "x86_64"))] {
         Err("Unwinding is currently only supported on x86_64")
@@ -115,3 +127,16 @@ pub fn panic_wrapper(panic_info: &PanicInfo) -> Result<(), &'static str> {
         }
     }
 }
+
+/// Prints one frame of a stack trace without allocating,
+/// as this may be invoked when the heap is exhausted or corrupted.
+#[cfg(target_arch = "x86_64")]
+fn print_stack_frame(address: impl fmt::UpperHex, symbol_offset: Option<(&str, usize)>) {
+    let _no_alloc = heap::forbid_allocation();
+    let mut line = FixedWriter::<256>::new();
+    let _ = match symbol_offset {
+        Some((symbol_name, offset)) => write!(line, "  {:>#018X} in {} + {:#X}", address, symbol_name, offset),
+        None => write!(line, "  {:>#018X} in ??", address),
+    };
+    error!("{}", line);
+}
@@ -0,0 +1,146 @@
+//! A human-readable dump of a PCI device's configuration space, for driver bring-up.
+
+use alloc::string::String;
+use core::fmt::{self, Write};
+use crate::{PciDevice, PciRegister};
+
+/// The size in bytes of the standard (non-extended) PCI configuration space.
+pub const PCI_CONFIG_SPACE_SIZE: usize = 256;
+
+/// The number of dwords in the standard PCI configuration space.
+const CONFIG_SPACE_DWORDS: usize = PCI_CONFIG_SPACE_SIZE / 4;
+
+/// The header type of a general device, i.e., an endpoint.
+const HEADER_TYPE_GENERAL: u8 = 0x00;
+/// The header type of a PCI-to-PCI bridge.
+const HEADER_TYPE_PCI_BRIDGE: u8 = 0x01;
+/// The header type of a PCI-to-CardBus bridge.
+const HEADER_TYPE_CARDBUS_BRIDGE: u8 = 0x02;
+/// The bit in the header type register that indicates a multi-function device.
+const HEADER_TYPE_MULTI_FUNCTION: u8 = 0x80;
+
+/// Reads the entire 256-byte configuration space of the given device
+/// and returns a dump of it, with the standard header fields decoded.
+///
+/// The header fields are decoded according to the device's header type:
+/// type 0 (general device) and type 1 (PCI-to-PCI bridge) have different layouts
+/// after the first 16 bytes. Other header types are shown only as a hex dump.
+///
+/// This only reads the configuration space, so it has no effect on the device.
+pub fn dump_config(dev: &PciDevice) -> String {
+    let mut dwords = [0u32; CONFIG_SPACE_DWORDS];
+    for (i, dword) in dwords.iter_mut().enumerate() {
+        // Every offset used here is 4-byte aligned, so this cannot fail.
+        *dword = dev.pci_config_read_u32((i * 4) as u8).unwrap_or(u32::MAX);
+    }
+    let mut out = String::new();
+    let _ = writeln!(out, "PCI device {} configuration space:", dev.location);
+    let _ = format_config(&mut out, &ConfigSpace(dwords));
+    out
+}
+
+/// A snapshot of the 256-byte PCI configuration space, as an array of dwords.
+pub(crate) struct ConfigSpace(pub(crate) [u32; CONFIG_SPACE_DWORDS]);
+
+impl ConfigSpace {
+    fn read(&self, offset: u8, size_in_bytes: u8) -> u32 {
+        let PciRegister { index, span } = PciRegister::from_offset(offset, size_in_bytes);
+        span.extract(self.0[index as usize])
+    }
+    fn u8(&self, offset: u8) -> u8 { self.read(offset, 1) as u8 }
+    fn u16(&self, offset: u8) -> u16 { self.read(offset, 2) as u16 }
+    fn u32(&self, offset: u8) -> u32 { self.read(offset, 4) }
+}
+
+/// Writes the decoded header fields and a hex dump of the given configuration space.
+pub(crate) fn format_config(out: &mut String, config: &ConfigSpace) -> fmt::Result {
+    let header_type = config.u8(0x0E);
+    let layout = match header_type & !HEADER_TYPE_MULTI_FUNCTION {
+        HEADER_TYPE_GENERAL => "general device",
+        HEADER_TYPE_PCI_BRIDGE => "PCI-to-PCI bridge",
+        HEADER_TYPE_CARDBUS_BRIDGE => "PCI-to-CardBus bridge",
+        _ => "unknown",
+    };
+
+    writeln!(out, "  Vendor ID:          {:#06X}    Device ID:        {:#06X}", config.u16(0x00), config.u16(0x02))?;
+    writeln!(out, "  Command:            {:#06X}    Status:           {:#06X}", config.u16(0x04), config.u16(0x06))?;
+    writeln!(out, "  Class:              {:#04X}      Subclass:         {:#04X}", config.u8(0x0B), config.u8(0x0A))?;
+    writeln!(out, "  Prog IF:            {:#04X}      Revision ID:      {:#04X}", config.u8(0x09), config.u8(0x08))?;
+    writeln!(out, "  Cache line size:    {:#04X}      Latency timer:    {:#04X}", config.u8(0x0C), config.u8(0x0D))?;
+    writeln!(out, "  Header type:        {:#04X} ({}{})",
+        header_type, layout,
+        if header_type & HEADER_TYPE_MULTI_FUNCTION != 0 { ", multi-function" } else { "" },
+    )?;
+    writeln!(out, "  BIST:               {:#04X}", config.u8(0x0F))?;
+
+    match header_type & !HEADER_TYPE_MULTI_FUNCTION {
+        HEADER_TYPE_GENERAL => format_general_header(out, config)?,
+        HEADER_TYPE_PCI_BRIDGE => format_bridge_header(out, config)?,
+        _ => writeln!(out, "  (the rest of this header type's layout is not decoded)")?,
+    }
+
+    writeln!(out, "  Hex dump:")?;
+    for row in 0..16u8 {
+        write!(out, "    {:02x}:", row * 16)?;
+        for col in 0..16u8 {
+            write!(out, " {:02x}", config.u8(row * 16 + col))?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Writes the fields of a type 0 (general device) header, from offset 0x10 onwards.
+fn format_general_header(out: &mut String, config: &ConfigSpace) -> fmt::Result {
+    for bar in 0..6u8 {
+        writeln!(out, "  BAR{}:               {:#010X}", bar, config.u32(0x10 + bar * 4))?;
+    }
+    writeln!(out, "  CardBus CIS:        {:#010X}", config.u32(0x28))?;
+    writeln!(out, "  Subsystem vendor:   {:#06X}    Subsystem ID:     {:#06X}", config.u16(0x2C), config.u16(0x2E))?;
+    writeln!(out, "  Expansion ROM:      {:#010X}", config.u32(0x30))?;
+    format_capabilities_pointer(out, config)?;
+    format_interrupt(out, config)?;
+    writeln!(out, "  Min grant:          {:#04X}      Max latency:      {:#04X}", config.u8(0x3E), config.u8(0x3F))
+}
+
+/// Writes the fields of a type 1 (PCI-to-PCI bridge) header, from offset 0x10 onwards.
+fn format_bridge_header(out: &mut String, config: &ConfigSpace) -> fmt::Result {
+    for bar in 0..2u8 {
+        writeln!(out, "  BAR{}:               {:#010X}", bar, config.u32(0x10 + bar * 4))?;
+    }
+    writeln!(out, "  Primary bus:        {:#04X}      Secondary bus:    {:#04X}", config.u8(0x18), config.u8(0x19))?;
+    writeln!(out, "  Subordinate bus:    {:#04X}      Sec. latency:     {:#04X}", config.u8(0x1A), config.u8(0x1B))?;
+    writeln!(out, "  I/O base:           {:#04X}      I/O limit:        {:#04X}", config.u8(0x1C), config.u8(0x1D))?;
+    writeln!(out, "  Secondary status:   {:#06X}", config.u16(0x1E))?;
+    writeln!(out, "  Memory base:        {:#06X}    Memory limit:     {:#06X}", config.u16(0x20), config.u16(0x22))?;
+    writeln!(out, "  Prefetch base:      {:#06X}    Prefetch limit:   {:#06X}", config.u16(0x24), config.u16(0x26))?;
+    writeln!(out, "  Prefetch base hi:   {:#010X}  Prefetch limit hi: {:#010X}", config.u32(0x28), config.u32(0x2C))?;
+    writeln!(out, "  I/O base hi:        {:#06X}    I/O limit hi:     {:#06X}", config.u16(0x30), config.u16(0x32))?;
+    format_capabilities_pointer(out, config)?;
+    writeln!(out, "  Expansion ROM:      {:#010X}", config.u32(0x38))?;
+    format_interrupt(out, config)?;
+    writeln!(out, "  Bridge control:     {:#06X}", config.u16(0x3E))
+}
+
+/// Writes the capabilities pointer, which is only valid if bit 4 of the status register is set.
+fn format_capabilities_pointer(out: &mut String, config: &ConfigSpace) -> fmt::Result {
+    const CAPABILITIES_VALID: u16 = 1 << 4;
+    if config.u16(0x06) & CAPABILITIES_VALID != 0 {
+        writeln!(out, "  Capabilities ptr:   {:#04X}", config.u8(0x34) & 0xFC)
+    } else {
+        writeln!(out, "  Capabilities ptr:   none")
+    }
+}
+
+/// Writes the interrupt line and pin, which have the same offsets in type 0 and type 1 headers.
+fn format_interrupt(out: &mut String, config: &ConfigSpace) -> fmt::Result {
+    let pin = match config.u8(0x3D) {
+        0 => "none",
+        1 => "INTA#",
+        2 => "INTB#",
+        3 => "INTC#",
+        4 => "INTD#",
+        _ => "invalid",
+    };
+    writeln!(out, "  Interrupt line:     {:#04X}      Interrupt pin:    {}", config.u8(0x3C), pin)
+}
@@ -29,6 +29,14 @@
 
 extern crate alloc;
 
+mod dump;
+mod routing;
+#[cfg(test)]
+mod test;
+
+pub use dump::{dump_config, PCI_CONFIG_SPACE_SIZE};
+pub use routing::{IntxRoute, IntxRouteSource, set_intx_route, clear_intx_routes};
+
 use log::*;
 use core::{fmt, ops::{Deref, DerefMut}, mem::size_of, task::Waker};
 use alloc::vec::Vec;
@@ -86,19 +94,30 @@ struct PciRegister {
 }
 impl PciRegister {
     const fn from_offset(raw_offset: u8, size_in_bytes: u8) -> Self {
-        let index = raw_offset >> 2;
-        match (size_in_bytes, raw_offset & 0b11) {
-            (1, 0) => PciRegister { index, span: Byte0 },
-            (1, 1) => PciRegister { index, span: Byte1 },
-            (1, 2) => PciRegister { index, span: Byte2 },
-            (1, 3) => PciRegister { index, span: Byte3 },
-            (2, 0) => PciRegister { index, span: Word0 },
-            (2, 2) => PciRegister { index, span: Word1 },
-            (4, 0) => PciRegister { index, span: FullDword },
+        match Self::try_from_offset(raw_offset, size_in_bytes) {
+            Some(register) => register,
             // Throw a const panic (compile error) for invalid values.
-            _ => panic!("Invalid PciRegister specification"),
+            None => panic!("Invalid PciRegister specification"),
         }
     }
+
+    /// Returns the register of the given size at the given byte offset,
+    /// or `None` if such a register would not be naturally aligned,
+    /// i.e., if it would straddle two 4-byte chunks.
+    const fn try_from_offset(raw_offset: u8, size_in_bytes: u8) -> Option<Self> {
+        let index = raw_offset >> 2;
+        let span = match (size_in_bytes, raw_offset & 0b11) {
+            (1, 0) => Byte0,
+            (1, 1) => Byte1,
+            (1, 2) => Byte2,
+            (1, 3) => Byte3,
+            (2, 0) => Word0,
+            (2, 2) => Word1,
+            (4, 0) => FullDword,
+            _ => return None,
+        };
+        Some(PciRegister { index, span })
+    }
 }
 
 /// A macro for easily defining PCI registers using offsets from the PCI spec.
@@ -147,6 +166,9 @@ pci_register!(PCI_INTERRUPT_PIN,       0x3D, 1);
 pci_register!(PCI_MIN_GRANT,           0x3E, 1);
 pci_register!(PCI_MAX_LATENCY,         0x3F, 1);
 
+// The below registers only exist in the header of PCI-to-PCI bridges (header type 0x01).
+pci_register!(PCI_BRIDGE_SECONDARY_BUS, 0x19, 1);
+
 const PCI_COMMAND_INT_DISABLED: u16 = 1 << 10;
 
 #[repr(u8)]
@@ -195,6 +217,8 @@ static PCI_CONFIG_SPACE: Mutex<Once<PciConfigSpace>> = Mutex::new(Once::new());
 #[cfg(target_arch = "aarch64")]
 const BASE_OFFSET: u32 = 0;
 
+/// One of the four legacy interrupt (INTx) pins of a PCI slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum InterruptPin {
     A,
     B,
@@ -420,6 +444,21 @@ impl RegisterSpan {
         }
     }
 
+    /// Returns the value of the register with this span within the given 4-byte chunk.
+    const fn extract(self, dword: u32) -> u32 {
+        let (mask, shift) = self.get_mask_and_bitshift();
+        (dword & mask) >> shift
+    }
+
+    /// Returns the given 4-byte chunk with the register of this span replaced by `value`.
+    ///
+    /// Bits of `value` that don't fit within this span are discarded
+    /// rather than overwriting neighboring registers.
+    const fn insert(self, dword: u32, value: u32) -> u32 {
+        let (mask, shift) = self.get_mask_and_bitshift();
+        (dword & !mask) | ((value << shift) & mask)
+    }
+
     const fn width_in_bytes(self) -> usize {
         match self {
             FullDword => size_of::<u32>(),
@@ -450,7 +489,6 @@ impl PciLocation {
     /// Read the value of the given `register` in the PCI Configuration Space.
     fn pci_read_raw(&self, register: PciRegister) -> u32 {
         let PciRegister { index, span } = register;
-        let (mask, shift) = span.get_mask_and_bitshift();
         const U32_BYTES: u32 = size_of::<u32>() as u32;
 
         let dword_address = BASE_OFFSET
@@ -476,7 +514,7 @@ impl PciLocation {
             dword_value = config_space[dword_index].read();
         }
 
-        (dword_value & mask) >> shift
+        span.extract(dword_value)
     }
 
     /// Read a 4-bytes register from the PCI Configuration Space.
@@ -533,11 +571,7 @@ impl PciLocation {
                 if matches!(span, FullDword) {
                     value
                 } else {
-                    let mut dword = $read_initial_value;
-                    let (mask, shift) = span.get_mask_and_bitshift();
-                    dword &= !mask;
-                    dword |= value << shift;
-                    dword
+                    span.insert($read_initial_value, value)
                 }
             }
         }
@@ -596,6 +630,30 @@ impl PciLocation {
         self.pci_write_raw(register, value as _)
     }
 
+    /// Reads the byte at the given `offset` in this device's PCI Configuration Space.
+    pub fn pci_config_read_u8(&self, offset: u8) -> u8 {
+        // Every byte offset is a valid one-byte register.
+        self.pci_read_8(PciRegister::from_offset(offset, 1))
+    }
+
+    /// Reads the 16-bit word at the given `offset` in this device's PCI Configuration Space.
+    ///
+    /// Returns an error if `offset` isn't 2-byte aligned, as such a word would span two dwords.
+    pub fn pci_config_read_u16(&self, offset: u8) -> Result<u16, &'static str> {
+        let register = PciRegister::try_from_offset(offset, 2)
+            .ok_or("pci_config_read_u16: offset must be 2-byte aligned")?;
+        Ok(self.pci_read_16(register))
+    }
+
+    /// Reads the 32-bit dword at the given `offset` in this device's PCI Configuration Space.
+    ///
+    /// Returns an error if `offset` isn't 4-byte aligned.
+    pub fn pci_config_read_u32(&self, offset: u8) -> Result<u32, &'static str> {
+        let register = PciRegister::try_from_offset(offset, 4)
+            .ok_or("pci_config_read_u32: offset must be 4-byte aligned")?;
+        Ok(self.pci_read_32(register))
+    }
+
     /// Sets the PCI device's bit 3 in the command portion, which is apparently needed to activate DMA (??)
     pub fn pci_set_command_bus_master_bit(&self) {
         let value = self.pci_read_16(PCI_COMMAND);
@@ -929,23 +987,30 @@ impl PciDevice {
         map_frame_range(mem_base, mem_size as usize, MMIO_FLAGS)
     }
 
+    /// Reads and returns this PCI device's interrupt line register,
+    /// i.e., the legacy IRQ that the firmware routed its INTx pin to, or `0xFF` if unknown.
+    ///
+    /// See [`PciDevice::intx_route()`] for the global system interrupt that the pin is connected to.
+    pub fn interrupt_line(&self) -> u8 {
+        self.pci_read_8(PCI_INTERRUPT_LINE)
+    }
+
+    /// Reads and returns this PCI device's interrupt pin register,
+    /// i.e., `1` through `4` for INTA# through INTD#, or `0` if it doesn't use INTx.
+    pub fn interrupt_pin(&self) -> u8 {
+        self.pci_read_8(PCI_INTERRUPT_PIN)
+    }
+
     /// Reads and returns this PCI device's INTx line and INTx pin registers.
     ///
     /// Returns an error if this PCI device's INTx pin value is invalid (greater than 4).
     pub fn pci_get_intx_info(&self) -> Result<(Option<u8>, Option<InterruptPin>), &'static str> {
-        let int_line = match self.pci_read_8(PCI_INTERRUPT_LINE) {
+        let int_line = match self.interrupt_line() {
             0xff => None,
             other => Some(other),
         };
-
-        let int_pin = match self.pci_read_8(PCI_INTERRUPT_PIN) {
-            0 => None,
-            1 => Some(InterruptPin::A),
-            2 => Some(InterruptPin::B),
-            3 => Some(InterruptPin::C),
-            4 => Some(InterruptPin::D),
-            _ => return Err("pci_get_interrupt_info: Invalid Register Value for Interrupt Pin"),
-        };
+        let int_pin = InterruptPin::from_register(self.interrupt_pin())
+            .map_err(|_| "pci_get_interrupt_info: Invalid Register Value for Interrupt Pin")?;
 
         Ok((int_line, int_pin))
     }
@@ -973,21 +1038,39 @@ impl PciDevice {
     /// Returns the previous interrupt waker for this device, if there was one.
     pub fn set_intx_waker(&'static self, waker: Waker) -> Result<Option<Waker>, &'static str> {
 
-        // On x86, we don't yet support querying the ACPI tables to properly determine
-        // which interrupt numbers are used for legacy PCI interrupts.
-        // As a workaround, we lazily register these handlers when a driver
-        // calls this function, as by that time we're sure that
-        // the interrupt number in the device's config space is correct.
+        // On x86, we lazily register these handlers when a driver calls this function,
+        // as by that time we're sure that the device's interrupt routing is known.
+        // If the firmware-assigned interrupt line is used, the IoApics already route
+        // that legacy IRQ like all ISA IRQs. Otherwise, we must route the device's GSI ourselves.
         #[cfg(target_arch = "x86_64")] {
-            let int_num = match self.pci_get_intx_info() {
-                Ok((Some(irq), _pin)) => (irq + IRQ_BASE_OFFSET) as InterruptNumber,
+            let route = match self.intx_route() {
+                Ok(Some(route)) => route,
                 _ => {
                     log::error!("Failed to get INTx info for PCI device {:?}", self);
                     return Err("PciDevice::set_intx_waker() failed to get INTx info");
                 }
             };
 
-            init_intx_handler(int_num)?;
+            match route.source {
+                IntxRouteSource::InterruptLine(irq) => {
+                    init_intx_handler((irq + IRQ_BASE_OFFSET) as InterruptNumber)?;
+                }
+                IntxRouteSource::RoutingTable => {
+                    let int_num = route.gsi.checked_add(IRQ_BASE_OFFSET as u32)
+                        .and_then(|v| u8::try_from(v).ok())
+                        .filter(|&v| v != apic::APIC_SPURIOUS_INTERRUPT_IRQ)
+                        .ok_or("PciDevice::set_intx_waker(): GSI is too large for an interrupt vector")?;
+                    init_intx_handler(int_num)?;
+                    let bsp = apic::bootstrap_cpu().ok_or("couldn't get BSP's APIC ID")?;
+                    ioapic::set_gsi_with_mode(
+                        route.gsi,
+                        bsp,
+                        int_num,
+                        if route.active_low { ioapic::Polarity::ActiveLow } else { ioapic::Polarity::ActiveHigh },
+                        if route.level_triggered { ioapic::TriggerMode::Level } else { ioapic::TriggerMode::Edge },
+                    )?;
+                }
+            }
         }
 
         // On aarch64, we *do* know the interrupt numbers statically,
@@ -0,0 +1,194 @@
+//! Routing of legacy PCI interrupts (INTx) to global system interrupts (GSIs).
+//!
+//! Each PCI function signals INTx on one of its slot's four interrupt pins, INTA# through INTD#.
+//! A PCI-to-PCI bridge forwards the pins of the devices behind it onto its own pins,
+//! rotated by each device's slot number, i.e., pin `(pin + slot) % 4` (the "swizzle").
+//! Only the root bus's pins are wired to the interrupt controller, as described by
+//! the ACPI `_PRT` (PCI Routing Table) method of the host bridge.
+//!
+//! Theseus cannot evaluate AML methods such as `_PRT`, so its entries must be supplied
+//! via [`set_intx_route()`] by whoever knows them.
+//! [`PciDevice::intx_route()`] swizzles a device's pin up through every bridge above it
+//! until it finds a matching entry. If there is none, it falls back to the device's
+//! interrupt line register, which the firmware programs with the legacy IRQ
+//! that it routed the device's pin to.
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::{
+    InterruptPin, PciDevice, PciLocation, get_pci_buses,
+    MAX_PCI_BUSES, PCI_BRIDGE_SECONDARY_BUS, PCI_HEADER_TYPE,
+};
+
+/// The header type of a PCI-to-PCI bridge, ignoring the multi-function bit.
+const HEADER_TYPE_PCI_BRIDGE: u8 = 0x01;
+/// The value of the interrupt line register meaning that the pin isn't connected to any IRQ.
+const INTERRUPT_LINE_UNKNOWN: u8 = 0xFF;
+
+/// The known entries of the root bus's PCI Routing Table (`_PRT`), see [`set_intx_route()`].
+static ROUTING_TABLE: Mutex<Vec<RoutingTableEntry>> = Mutex::new(Vec::new());
+
+struct RoutingTableEntry {
+    bus: u8,
+    slot: u8,
+    pin: InterruptPin,
+    gsi: u32,
+}
+
+/// Where an [`IntxRoute`] was obtained from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntxRouteSource {
+    /// An entry registered via [`set_intx_route()`].
+    RoutingTable,
+    /// The device's interrupt line register, as programmed by the firmware.
+    ///
+    /// On x86, this is a legacy ISA IRQ number that the IoApics
+    /// already route to vector `IRQ_BASE_OFFSET + line`.
+    InterruptLine(u8),
+}
+
+/// The global system interrupt that a PCI device's INTx pin is connected to.
+///
+/// Returned by [`PciDevice::intx_route()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IntxRoute {
+    /// The global system interrupt (GSI), i.e., the input of the IoApics that the pin drives.
+    pub gsi: u32,
+    /// Whether the interrupt is asserted when the line is low, which is the default for PCI.
+    pub active_low: bool,
+    /// Whether the interrupt is level-triggered, which is the default for PCI.
+    pub level_triggered: bool,
+    pub source: IntxRouteSource,
+}
+
+/// Records that the given interrupt `pin` of the given `slot` on `bus` is connected to `gsi`,
+/// as given by an entry of an ACPI `_PRT` that refers to a GSI directly.
+///
+/// Such interrupts are always active-low and level-triggered.
+/// An existing entry for the same pin is replaced.
+pub fn set_intx_route(bus: u8, slot: u8, pin: InterruptPin, gsi: u32) {
+    let mut table = ROUTING_TABLE.lock();
+    match table.iter_mut().find(|e| e.bus == bus && e.slot == slot && e.pin == pin) {
+        Some(entry) => entry.gsi = gsi,
+        None => table.push(RoutingTableEntry { bus, slot, pin, gsi }),
+    }
+}
+
+/// Removes all entries recorded by [`set_intx_route()`].
+pub fn clear_intx_routes() {
+    ROUTING_TABLE.lock().clear();
+}
+
+impl InterruptPin {
+    /// Returns the pin with the given value of the interrupt pin register,
+    /// `None` if the value is `0` (the device doesn't use INTx),
+    /// or an error if the value is invalid (greater than 4).
+    pub const fn from_register(value: u8) -> Result<Option<InterruptPin>, &'static str> {
+        match value {
+            0 => Ok(None),
+            1 => Ok(Some(InterruptPin::A)),
+            2 => Ok(Some(InterruptPin::B)),
+            3 => Ok(Some(InterruptPin::C)),
+            4 => Ok(Some(InterruptPin::D)),
+            _ => Err("invalid value of the PCI interrupt pin register"),
+        }
+    }
+
+    /// Returns the pin of a PCI-to-PCI bridge that this pin of a device
+    /// in the given `slot` behind the bridge is forwarded to.
+    pub const fn swizzle(self, slot: u8) -> InterruptPin {
+        match (self as u8 + slot % 4) % 4 {
+            0 => InterruptPin::A,
+            1 => InterruptPin::B,
+            2 => InterruptPin::C,
+            _ => InterruptPin::D,
+        }
+    }
+}
+
+impl PciDevice {
+    /// Returns the global system interrupt that this device's INTx pin is connected to,
+    /// or `None` if this device doesn't use INTx.
+    ///
+    /// This device's pin is swizzled through every PCI-to-PCI bridge above it
+    /// until it matches an entry recorded by [`set_intx_route()`].
+    /// If none matches, the legacy IRQ in this device's interrupt line register is used,
+    /// which the firmware routed the pin to.
+    ///
+    /// Returns an error if the pin isn't routed anywhere.
+    pub fn intx_route(&self) -> Result<Option<IntxRoute>, &'static str> {
+        let Some(mut pin) = InterruptPin::from_register(self.interrupt_pin())? else {
+            return Ok(None);
+        };
+
+        let table = ROUTING_TABLE.lock();
+        if !table.is_empty() {
+            let mut location = self.location;
+            // Bound the walk in case of misconfigured bridges that form a cycle.
+            for _ in 0..MAX_PCI_BUSES {
+                let entry = table.iter()
+                    .find(|e| e.bus == location.bus && e.slot == location.slot && e.pin == pin);
+                if let Some(entry) = entry {
+                    return Ok(Some(IntxRoute {
+                        gsi: entry.gsi,
+                        active_low: true,
+                        level_triggered: true,
+                        source: IntxRouteSource::RoutingTable,
+                    }));
+                }
+                match parent_bridge(location.bus)? {
+                    Some(bridge) => {
+                        pin = pin.swizzle(location.slot);
+                        location = bridge;
+                    }
+                    None => break,
+                }
+            }
+        }
+        drop(table);
+
+        let line = self.interrupt_line();
+        if line == INTERRUPT_LINE_UNKNOWN {
+            return Err("PCI device's INTx pin isn't routed to any IRQ");
+        }
+        Ok(Some(route_from_interrupt_line(line)))
+    }
+}
+
+/// Returns the location of the PCI-to-PCI bridge whose secondary bus is `bus`,
+/// or `None` if `bus` is a root bus.
+fn parent_bridge(bus: u8) -> Result<Option<PciLocation>, &'static str> {
+    let bridge = get_pci_buses()?.iter()
+        .flat_map(|b| b.devices.iter())
+        .find(|d| {
+            d.pci_read_8(PCI_HEADER_TYPE) & 0x7F == HEADER_TYPE_PCI_BRIDGE
+                && d.pci_read_8(PCI_BRIDGE_SECONDARY_BUS) == bus
+                && d.bus != bus
+        });
+    Ok(bridge.map(|d| d.location))
+}
+
+/// Returns the route of the legacy IRQ given by a device's interrupt line register.
+///
+/// On x86, the IRQ is translated by any ACPI interrupt source override for it.
+/// Otherwise, the usual PCI signaling (active-low, level-triggered) is assumed.
+fn route_from_interrupt_line(line: u8) -> IntxRoute {
+    let source = IntxRouteSource::InterruptLine(line);
+
+    #[cfg(target_arch = "x86_64")]
+    if let Some(isa) = ioapic::isa_irq_route(line).filter(|r| r.overridden) {
+        return IntxRoute {
+            gsi: isa.gsi,
+            active_low: isa.polarity == ioapic::Polarity::ActiveLow,
+            level_triggered: isa.trigger == ioapic::TriggerMode::Level,
+            source,
+        };
+    }
+
+    IntxRoute {
+        gsi: line as u32,
+        active_low: true,
+        level_triggered: true,
+        source,
+    }
+}
@@ -0,0 +1,153 @@
+//! Tests register decoding and dumping of the PCI configuration space against a mock config space.
+
+extern crate std;
+
+use super::*;
+use alloc::string::String;
+use dump::{ConfigSpace, format_config};
+
+/// The first four dwords of a mock configuration space header, in little-endian order.
+const MOCK_CONFIG_SPACE: [u32; 4] = [
+    0x1234_8086, // device ID 0x1234, vendor ID 0x8086
+    0x0010_0507, // status 0x0010, command 0x0507
+    0x0201_0003, // class 0x02, subclass 0x01, prog IF 0x00, revision 0x03
+    0x00FF_4010, // BIST 0x00, header type 0xFF, latency timer 0x40, cache line size 0x10
+];
+
+/// Reads the register of the given size at the given offset from the mock config space.
+fn mock_read(offset: u8, size_in_bytes: u8) -> Option<u32> {
+    let PciRegister { index, span } = PciRegister::try_from_offset(offset, size_in_bytes)?;
+    Some(span.extract(MOCK_CONFIG_SPACE[index as usize]))
+}
+
+#[test]
+fn read_dword() {
+    assert_eq!(mock_read(0x00, 4), Some(0x1234_8086));
+    assert_eq!(mock_read(0x04, 4), Some(0x0010_0507));
+}
+
+#[test]
+fn read_low_and_high_words() {
+    assert_eq!(mock_read(0x00, 2), Some(0x8086));
+    assert_eq!(mock_read(0x02, 2), Some(0x1234));
+    assert_eq!(mock_read(0x04, 2), Some(0x0507));
+    assert_eq!(mock_read(0x06, 2), Some(0x0010));
+}
+
+#[test]
+fn read_each_byte() {
+    assert_eq!(mock_read(0x08, 1), Some(0x03));
+    assert_eq!(mock_read(0x09, 1), Some(0x00));
+    assert_eq!(mock_read(0x0A, 1), Some(0x01));
+    assert_eq!(mock_read(0x0B, 1), Some(0x02));
+    assert_eq!(mock_read(0x0D, 1), Some(0x40));
+    assert_eq!(mock_read(0x0E, 1), Some(0xFF));
+}
+
+#[test]
+fn misaligned_offsets_are_rejected() {
+    assert_eq!(mock_read(0x01, 2), None);
+    assert_eq!(mock_read(0x03, 2), None);
+    assert_eq!(mock_read(0x02, 4), None);
+    assert_eq!(mock_read(0x05, 4), None);
+    assert_eq!(mock_read(0x00, 3), None);
+}
+
+#[test]
+fn insert_preserves_neighboring_registers() {
+    let dword = MOCK_CONFIG_SPACE[1];
+    assert_eq!(Word0.insert(dword, 0x0403), 0x0010_0403);
+    assert_eq!(Word1.insert(dword, 0xFFFF), 0xFFFF_0507);
+    assert_eq!(Byte1.insert(dword, 0xAB), 0x0010_AB07);
+    // Bits beyond the register's width must not spill into the next register.
+    assert_eq!(Byte0.insert(dword, 0x1FF), 0x0010_05FF);
+    assert_eq!(Word0.insert(dword, 0xF_0000), 0x0010_0000);
+}
+
+/// Returns a mock 256-byte config space whose first dwords are the given `header`.
+fn mock_config_space(header: &[u32]) -> ConfigSpace {
+    let mut dwords = [0u32; 64];
+    dwords[..header.len()].copy_from_slice(header);
+    ConfigSpace(dwords)
+}
+
+fn dump(config: &ConfigSpace) -> String {
+    let mut out = String::new();
+    format_config(&mut out, config).unwrap();
+    out
+}
+
+#[test]
+fn dump_general_device_header() {
+    let mut header = [0u32; 16];
+    header[..4].copy_from_slice(&[0x100E_8086, 0x0010_0007, 0x0200_0003, 0x0000_0000]);
+    header[4] = 0xFEBC_0000;      // BAR0
+    header[11] = 0x1234_8086;     // subsystem ID 0x1234, subsystem vendor 0x8086
+    header[13] = 0x0000_00DC;     // capabilities pointer
+    header[15] = 0x0000_010B;     // interrupt pin A, interrupt line 11
+    let out = dump(&mock_config_space(&header));
+
+    assert!(out.contains("Vendor ID:          0x8086    Device ID:        0x100E"));
+    assert!(out.contains("Header type:        0x00 (general device)"));
+    assert!(out.contains("BAR0:               0xFEBC0000"));
+    assert!(out.contains("BAR5:"));
+    assert!(out.contains("Subsystem vendor:   0x8086    Subsystem ID:     0x1234"));
+    assert!(out.contains("Capabilities ptr:   0xDC"));
+    assert!(out.contains("Interrupt line:     0x0B      Interrupt pin:    INTA#"));
+    assert!(!out.contains("Primary bus"));
+    // The hex dump shows every byte in little-endian order, 16 bytes per row.
+    assert!(out.contains("    00: 86 80 0e 10 07 00 10 00 03 00 00 02 00 00 00 00"));
+    assert!(out.contains("    f0: 00 00"));
+}
+
+#[test]
+fn dump_bridge_header() {
+    let mut header = [0u32; 16];
+    header[..4].copy_from_slice(&[0x2448_8086, 0x0000_0007, 0x0604_0001, 0x0081_0000]);
+    header[6] = 0x0005_0200;      // subordinate bus 5, secondary bus 2, primary bus 0
+    header[8] = 0xFEAF_FEA0;      // memory limit 0xFEAF, memory base 0xFEA0
+    header[15] = 0x0003_0000;     // bridge control 0x0003, no interrupt pin
+    let out = dump(&mock_config_space(&header));
+
+    assert!(out.contains("Header type:        0x81 (PCI-to-PCI bridge, multi-function)"));
+    assert!(out.contains("Primary bus:        0x00      Secondary bus:    0x02"));
+    assert!(out.contains("Subordinate bus:    0x05"));
+    assert!(out.contains("Memory base:        0xFEA0    Memory limit:     0xFEAF"));
+    assert!(out.contains("Capabilities ptr:   none"));
+    assert!(out.contains("Interrupt pin:    none"));
+    assert!(out.contains("Bridge control:     0x0003"));
+    // A bridge only has two BARs, and its layout has no subsystem IDs.
+    assert!(out.contains("BAR1:"));
+    assert!(!out.contains("BAR2:"));
+    assert!(!out.contains("Subsystem"));
+}
+
+#[test]
+fn dump_unknown_header_type_is_only_hex() {
+    let out = dump(&mock_config_space(&[0x0000_1180, 0, 0x0607_0000, 0x0002_0000]));
+    assert!(out.contains("Header type:        0x02 (PCI-to-CardBus bridge)"));
+    assert!(out.contains("not decoded"));
+    assert!(!out.contains("BAR0:"));
+    assert!(out.contains("Hex dump:"));
+}
+
+#[test]
+fn decode_interrupt_pin_register() {
+    assert_eq!(InterruptPin::from_register(0), Ok(None));
+    assert_eq!(InterruptPin::from_register(1), Ok(Some(InterruptPin::A)));
+    assert_eq!(InterruptPin::from_register(4), Ok(Some(InterruptPin::D)));
+    assert!(InterruptPin::from_register(5).is_err());
+}
+
+#[test]
+fn swizzle_rotates_pin_by_slot() {
+    use InterruptPin::*;
+    // A device in slot 0 keeps its pin, and every slot thereafter rotates it by one more.
+    assert_eq!(A.swizzle(0), A);
+    assert_eq!(A.swizzle(1), B);
+    assert_eq!(B.swizzle(2), D);
+    assert_eq!(D.swizzle(1), A);
+    assert_eq!(C.swizzle(31), B);
+    // Slots four apart share the same rotation.
+    assert_eq!(B.swizzle(5), B.swizzle(1));
+}
@@ -21,6 +21,12 @@ pub const IRQ_BASE_OFFSET: u8 = 0x20;
 /// The IRQ number reserved for spurious PIC interrupts (as recommended by OS dev wiki).
 pub const PIC_SPURIOUS_INTERRUPT_IRQ: u8 = IRQ_BASE_OFFSET + 0x7;
 
+/// The IRQ number at which the slave PIC delivers its spurious interrupts.
+///
+/// Unlike [`PIC_SPURIOUS_INTERRUPT_IRQ`], this line is also used by real devices,
+/// e.g., the secondary ATA bus.
+pub const PIC_SPURIOUS_SLAVE_INTERRUPT_IRQ: u8 = IRQ_BASE_OFFSET + 0xF;
+
 /// Command sent to read the Interrupt Request Register.
 const CMD_IRR: u8 = 0x0A;
 
@@ -207,6 +213,55 @@ impl ChainedPics {
         self.pics.iter().any(|p| p.handles_interrupt(interrupt_id))
     }
 
+    /// Masks (disables) or unmasks (enables) the single IRQ line that is
+    /// mapped to the given `interrupt_id`, leaving all other lines unchanged.
+    ///
+    /// Does nothing if `interrupt_id` isn't handled by either PIC.
+    pub fn set_irq_masked(&self, interrupt_id: u8, masked: bool) {
+        if let Some(pic) = self.pics.iter().find(|p| p.handles_interrupt(interrupt_id)) {
+            let bit = 1 << (interrupt_id - pic.offset);
+            let mask = pic.data.read();
+            let mask = if masked { mask | bit } else { mask & !bit };
+            // SAFE: we are guaranteed to have initialized this structure in its constructor.
+            unsafe { pic.data.write(mask); }
+            io_wait();
+        }
+    }
+
+    /// Returns the current interrupt masks of the master and slave PIC, in that order.
+    ///
+    /// As in [`ChainedPics::mask_irqs()`], a set bit means that IRQ line is masked (disabled).
+    pub fn read_masks(&self) -> (u8, u8) {
+        (self.pics[0].data.read(), self.pics[1].data.read())
+    }
+
+    /// Returns whether the IRQ line mapped to the given `interrupt_id`
+    /// is currently marked as in service in its PIC's ISR.
+    ///
+    /// This reads only the ISR of the PIC that handles `interrupt_id`,
+    /// and returns `false` if `interrupt_id` isn't handled by either PIC.
+    pub fn is_in_service(&self, interrupt_id: u8) -> bool {
+        match self.pics.iter().find(|p| p.handles_interrupt(interrupt_id)) {
+            Some(pic) => {
+                // SAFE: just reading a PIC register, no harm can be done.
+                let isr = unsafe {
+                    pic.command.write(CMD_ISR);
+                    pic.command.read()
+                };
+                isr & (1 << (interrupt_id - pic.offset)) != 0
+            }
+            None => false,
+        }
+    }
+
+    /// Acknowledges a spurious interrupt from the slave PIC by notifying only the master PIC.
+    ///
+    /// The slave PIC didn't mark its spurious interrupt as in service, so it must not
+    /// receive an EOI, but the master PIC did mark the cascade line (IRQ 2) as in service.
+    pub fn notify_end_of_spurious_slave_interrupt(&self) {
+        self.pics[0].end_of_interrupt();
+    }
+
     /// Figure out which (if any) PICs in our chain need to know about this
     /// interrupt.  This is tricky, because all interrupts from `pics[1]`
     /// get chained through `pics[0]`.
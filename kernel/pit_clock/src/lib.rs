@@ -1,6 +1,7 @@
 //! Full support for the Programmable Interval Timer (PIT) system clock.
 //! 
-//! This crate allows one to enable/configure PIT interrupts.
+//! This crate allows one to enable/configure PIT interrupts
+//! and to count down precise one-shot intervals, e.g., for calibrating other timers.
 //! For a simpler crate that just allows PIT-based waiting, use `pit_clock_basic`.
 
 #![no_std]
@@ -11,47 +12,96 @@ extern crate port_io;
 extern crate pit_clock_basic;
 extern crate interrupts;
 extern crate x86_64;
+extern crate kernel_config;
 
 use port_io::Port;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64::structures::idt::InterruptStackFrame;
+use kernel_config::{time::is_valid_pit_frequency, units::Hertz};
 
 pub use pit_clock_basic::pit_wait;
 use pit_clock_basic::*;
 
-/// The PIT Channel 0 is connected directly to IRQ 0.
+/// The PIT Channel 0 is connected directly to ISA IRQ 0,
+/// though many chipsets route that to GSI 2 of the IOAPIC instead.
+pub const PIT_CHANNEL_0_ISA_IRQ: u8 = 0x0;
 /// Because we perform the typical PIC remapping, the remapped IRQ vector number is 0x20.
-#[allow(clippy::identity_op)]
-const PIT_CHANNEL_0_IRQ: u8 = interrupts::IRQ_BASE_OFFSET + 0x0;
+const PIT_CHANNEL_0_IRQ: u8 = interrupts::IRQ_BASE_OFFSET + PIT_CHANNEL_0_ISA_IRQ;
 
 
-/// Configures the PIT to fire an interrupt at the given frequency (in Hz).
+/// Configures the PIT to fire an interrupt at the given frequency.
 /// 
 /// Only Channel 0 of the PIT is directly connected to an IRQ, so it must be used.
 /// Note that Channel 1 does not exist and Channel 2 is reserved for non-interrupt timer usage.
 /// 
 /// ## Arguments
-/// * `freq_hertz`: the frequency in Hz of the desired PIT interrupt.
+/// * `freq`: the frequency of the desired PIT interrupt.
 ///    The minimum value is 19 Hz, which is based on the fact that the timer register
 ///    cannot be loaded with a value larger than `u16::MAX` (65535),
 ///    and that the value loaded into the register is a divisor value.
-///    That divisor value is the default timer frequency 1193182 divided by `freq_hertz`.
-pub fn enable_interrupts(freq_hertz: u32) -> Result<(), &'static str> {
-    let divisor = PIT_DEFAULT_DIVIDEND_HZ / freq_hertz;
-    if divisor > u16::MAX as u32 {
-        error!("The chosen PIT frequency ({} Hz) is too small, it must be {} Hz or greater!", 
-            freq_hertz, PIT_MINIMUM_FREQ
-        );
-        return Err("The chosen PIT frequency is too small, it must be 19 Hz or greated")
-    }
+///    That divisor value is the default timer frequency 1193182 divided by `freq`.
+///    Constant frequencies can be checked at compile time with [`is_valid_pit_frequency()`].
+pub fn enable_interrupts(freq: Hertz) -> Result<(), &'static str> {
+    let divisor = divisor_for(freq)?;
 
     // Register the interrupt handler
-    match interrupts::register_interrupt(PIT_CHANNEL_0_IRQ, pit_timer_handler) {
+    match interrupts::register_isa_interrupt(PIT_CHANNEL_0_ISA_IRQ, pit_timer_handler) {
         Ok(_) => { /* success, do nothing */}
         Err(handler) if handler == pit_timer_handler as usize => { /* already registered, do nothing */ }
         Err(_other) => return Err(" PIT clock IRQ was already in use; sharing IRQs is currently unsupported"),
     }
 
+    program_channel_0(divisor);
+    Ok(())
+}
+
+/// Configures the PIT's Channel 0 to periodically fire its interrupt at the given frequency,
+/// without registering a handler for that interrupt.
+///
+/// This is for callers that handle the PIT interrupt themselves,
+/// e.g., the scheduler's timer interrupt when the system has no usable Local APIC timer.
+/// The handler must be registered beforehand at ISA IRQ [`PIT_CHANNEL_0_ISA_IRQ`].
+///
+/// See [`enable_interrupts()`] for valid values of `freq`.
+pub fn start_periodic(freq: Hertz) -> Result<(), &'static str> {
+    let divisor = divisor_for(freq)?;
+    program_channel_0(divisor);
+    Ok(())
+}
+
+/// Counts down `count` ticks of the PIT's `PIT_DEFAULT_DIVIDEND_HZ` input clock once,
+/// blocking until the countdown completes, and returns the achieved interval in nanoseconds.
+///
+/// This is a precise delay reference for calibrating other timers, e.g., the APIC timer or TSC,
+/// so the caller should hold interrupts to avoid observing the end of the countdown late.
+/// It uses PIT Channel 2 in mode 0 (interrupt on terminal count) rather than Channel 0,
+/// so the periodic scheduler tick configured by [`start_periodic()`] or [`enable_interrupts()`]
+/// keeps running undisturbed throughout.
+///
+/// A `count` of `0` returns immediately with an interval of `0`.
+pub fn oneshot(count: u16) -> u64 {
+    pit_clock_basic::channel2_oneshot_ticks(count);
+    ticks_to_nanos(count)
+}
+
+/// Returns the duration in nanoseconds of the given number of PIT input clock ticks.
+pub const fn ticks_to_nanos(ticks: u16) -> u64 {
+    ticks as u64 * 1_000_000_000 / PIT_DEFAULT_DIVIDEND_HZ as u64
+}
+
+/// Returns the divisor for the given frequency, which may only be known at runtime,
+/// e.g., a timeslice period overridden on the kernel command line.
+fn divisor_for(freq: Hertz) -> Result<u32, &'static str> {
+    if !is_valid_pit_frequency(freq) {
+        error!("The chosen PIT frequency ({}) is invalid, it must be from {} Hz to {} Hz!", 
+            freq, PIT_MINIMUM_FREQ, PIT_DEFAULT_DIVIDEND_HZ
+        );
+        return Err("The chosen PIT frequency is invalid, it must be from 19 Hz to 1193182 Hz")
+    }
+    freq.divisor_of(kernel_config::time::PIT_INPUT_FREQUENCY).ok_or("BUG: PIT frequency was zero")
+}
+
+fn program_channel_0(divisor: u32) {
     // SAFE because we're simply configuring the PIT clock, and the code below is correct.
     unsafe {
         PIT_COMMAND.lock().write(0x36); // 0x36: see this: http://www.osdever.net/bkerndev/Docs/pit.htm
@@ -62,13 +112,11 @@ pub fn enable_interrupts(freq_hertz: u32) -> Result<(), &'static str> {
         let _ignore: u8 = Port::new(0x60).read();
         PIT_CHANNEL_0.lock().write((divisor >> 8) as u8);
     }
-
-    Ok(())
 }
 
 
 extern "x86-interrupt" fn pit_timer_handler(_stack_frame: InterruptStackFrame) {
-    static PIT_TICKS: AtomicUsize = AtomicUsize::new(0);
+    static PIT_TICKS: AtomicU64 = AtomicU64::new(0);
     let ticks = PIT_TICKS.fetch_add(1, Ordering::Acquire);
     trace!("PIT timer interrupt, ticks: {}", ticks);
 
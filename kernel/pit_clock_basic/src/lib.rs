@@ -2,12 +2,18 @@
 //! 
 //! Use the `pit_clock` crate for a more fully-featured PIT interface,
 //! including enabling interrupts.
+//!
+//! This crate is the sole owner of PIT Channel 2, which it uses both for polled delays
+//! and, in the [`speaker`] module, to drive the PC speaker.
 
 #![no_std]
 
 extern crate spin;
 #[macro_use] extern crate log;
 extern crate port_io;
+extern crate kernel_config;
+
+pub mod speaker;
 
 use port_io::Port;
 use spin::Mutex;
@@ -17,18 +23,121 @@ pub const CHANNEL0: u16 = 0x40;
 /// Port for Channel 1, which does not exist and should NOT be used.
 const _CHANNEL1: u16 = 0x41;
 /// Port for Channel 2; technically the speaker for beeps,
-/// but is also used for waiting in `pit_wait()`.
+/// but is also used for waiting in `pit_wait()`, `channel2_oneshot()`, and `channel2_oneshot_ticks()`.
 const CHANNEL2: u16 = 0x42;
 /// Port for the PIT command register. 
 const COMMAND_REGISTER: u16 = 0x43;
+/// System control port B, whose low bits control PIT Channel 2 and the PC speaker.
+/// Its other bits are unrelated status and NMI control bits that must be preserved.
+const SYSTEM_CONTROL_PORT_B: u16 = 0x61;
+
+/// Port B bit 0: Channel 2's gate input; Channel 2 only counts while this is set.
+const PORT_B_CHANNEL2_GATE: u8 = 1 << 0;
+/// Port B bit 1: connects Channel 2's output to the PC speaker.
+const PORT_B_SPEAKER_ENABLE: u8 = 1 << 1;
+/// Port B bit 5 (read-only): the current level of Channel 2's output.
+const PORT_B_CHANNEL2_OUTPUT: u8 = 1 << 5;
+/// Port B's upper four bits are read-only status bits, which must be written as zero.
+const PORT_B_WRITABLE_MASK: u8 = 0x0F;
 
-/// the timer's default frequency is 1.19 MHz
-pub const PIT_DEFAULT_DIVIDEND_HZ: u32 = 1193182;
-pub const PIT_MINIMUM_FREQ:        u32 = 19;
+/// Channel 2 command: access mode lobyte/hibyte, mode 0 (interrupt on terminal count), 16-bit binary.
+const CHANNEL2_ONESHOT_COMMAND: u8 = 0b10110000;
+/// Channel 2 command: access mode lobyte/hibyte, mode 3 (square wave generator), 16-bit binary.
+const CHANNEL2_SQUARE_WAVE_COMMAND: u8 = 0b10110110;
+
+/// the timer's default frequency is 1.19 MHz; see [`kernel_config::time::PIT_INPUT_FREQUENCY`].
+pub const PIT_DEFAULT_DIVIDEND_HZ: u32 = kernel_config::time::PIT_INPUT_FREQUENCY.value();
+/// The lowest frequency whose divisor fits in the PIT's 16-bit reload register.
+pub const PIT_MINIMUM_FREQ:        u32 = PIT_DEFAULT_DIVIDEND_HZ / u16::MAX as u32 + 1;
 
 pub static PIT_COMMAND:   Mutex<Port<u8>> = Mutex::new( Port::new(COMMAND_REGISTER) );
 pub static PIT_CHANNEL_0: Mutex<Port<u8>> = Mutex::new( Port::new(CHANNEL0) );
-static PIT_CHANNEL_2: Mutex<Port<u8>> = Mutex::new( Port::new(CHANNEL2) );
+
+/// The sole owner of PIT Channel 2 and of the bits of system control port B that control it.
+///
+/// Channel 2 is used both as a polled delay reference and to drive the PC speaker,
+/// so every use of it must hold this lock to avoid corrupting the other's configuration.
+static CHANNEL_2: Mutex<Channel2> = Mutex::new(Channel2 {
+    data: Port::new(CHANNEL2),
+    port_b: Port::new(SYSTEM_CONTROL_PORT_B),
+});
+
+struct Channel2 {
+    data: Port<u8>,
+    port_b: Port<u8>,
+}
+
+impl Channel2 {
+    /// Writes the given `command` for Channel 2 and then its 16-bit `reload` value.
+    fn program(&self, command: u8, reload: u16) {
+        // SAFE because we're simply configuring PIT Channel 2, which we exclusively own.
+        unsafe {
+            PIT_COMMAND.lock().write(command);
+            // must write the low byte first and then the high byte
+            self.data.write(reload as u8);
+            // read from PS/2 port 0x60, which acts as a short delay and acknowledges the status register
+            let _ignore: u8 = Port::<u8>::new(0x60).read();
+            self.data.write((reload >> 8) as u8);
+        }
+    }
+
+    /// Sets Channel 2's gate and speaker enable bits in port B,
+    /// preserving the other writable bits.
+    fn set_gate_and_speaker(&self, gate: bool, speaker: bool) {
+        let mut value = self.port_b.read() & PORT_B_WRITABLE_MASK
+            & !(PORT_B_CHANNEL2_GATE | PORT_B_SPEAKER_ENABLE);
+        if gate {
+            value |= PORT_B_CHANNEL2_GATE;
+        }
+        if speaker {
+            value |= PORT_B_SPEAKER_ENABLE;
+        }
+        // SAFE because only the bits controlling PIT Channel 2 are changed.
+        unsafe { self.port_b.write(value); }
+    }
+
+    /// Returns the current level of Channel 2's output.
+    fn output(&self) -> bool {
+        self.port_b.read() & PORT_B_CHANNEL2_OUTPUT != 0
+    }
+
+    /// Counts down `reload` PIT ticks, waiting until the count reaches zero.
+    fn oneshot(&self, reload: u16) {
+        self.set_gate_and_speaker(false, false);
+        self.program(CHANNEL2_ONESHOT_COMMAND, reload);
+        // In mode 0, the output goes low once programmed and high upon reaching zero.
+        self.set_gate_and_speaker(true, false);
+        while !self.output() {
+            core::hint::spin_loop();
+        }
+        self.set_gate_and_speaker(false, false);
+    }
+
+    /// Starts generating a square wave with the given `reload` value,
+    /// optionally connected to the PC speaker.
+    fn start_square_wave(&self, reload: u16, speaker: bool) {
+        self.set_gate_and_speaker(false, false);
+        self.program(CHANNEL2_SQUARE_WAVE_COMMAND, reload);
+        self.set_gate_and_speaker(true, speaker);
+    }
+
+    /// Waits for the given number of periods of the current square wave.
+    fn wait_periods(&self, periods: u64) {
+        for _ in 0..periods {
+            while self.output() {
+                core::hint::spin_loop();
+            }
+            while !self.output() {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Stops Channel 2 and disconnects it from the PC speaker.
+    fn stop(&self) {
+        self.set_gate_and_speaker(false, false);
+    }
+}
 
 
 /// Waits (blocking) for the given number of `microseconds` using the PIT Channel 2.
@@ -44,30 +153,59 @@ pub fn pit_wait(microseconds: u32) -> Result<(), &'static str> {
         return Err("microsecond value was too large");
     }
 
+    let channel2 = CHANNEL_2.lock();
+    let port_61 = &channel2.port_b;
+
     // SAFE because we're simply configuring the PIT clock, and the code below is correct.
     unsafe {
-        let port_60 = Port::<u8>::new(0x60);
-        let port_61 = Port::<u8>::new(0x61);
-
         // see code example: https://wiki.osdev.org/APIC_timer
-        let port_61_val = port_61.read(); 
+        let port_61_val = port_61.read() & PORT_B_WRITABLE_MASK;
         port_61.write(port_61_val & 0xFD | 0x1); // sets the speaker channel 2 to be controlled by PIT hardware
-        PIT_COMMAND.lock().write(0b10110010); // channel 2, access mode: lobyte/hibyte, hardware-retriggerable one shot mode, 16-bit binary (not BCD)
-
-        // set frequency; must write the low byte first and then the high byte
-        PIT_CHANNEL_2.lock().write(divisor as u8);
-        // read from PS/2 port 0x60, which acts as a short delay and acknowledges the status register
-        let _ignore: u8 = port_60.read();
-        PIT_CHANNEL_2.lock().write((divisor >> 8) as u8);
+        // channel 2, access mode: lobyte/hibyte, hardware-retriggerable one shot mode, 16-bit binary (not BCD)
+        channel2.program(0b10110010, divisor as u16);
         
         // reset PIT one-shot counter
-        let port_61_val = port_61.read() & 0xFE;
+        let port_61_val = port_61.read() & PORT_B_WRITABLE_MASK & 0xFE;
         port_61.write(port_61_val); // clear bit 0
         port_61.write(port_61_val | 0x1); // set bit 0
         // here, PIT channel 2 timer has started counting
         
         // wait for PIT timer to reach 0, which is tested by checking bit 5
-        while port_61.read() & 0x20 != 0 { }
+        while port_61.read() & 0x20 != 0 {
+            core::hint::spin_loop();
+        }
         Ok(())
     }
 }
+
+/// Waits (blocking) for the given number of `microseconds` by polling PIT Channel 2
+/// as a one-shot countdown timer (mode 0).
+///
+/// This doesn't affect PIT Channel 0, so it can be used as a delay reference,
+/// e.g., to calibrate other timers, even while Channel 0 drives scheduling interrupts.
+///
+/// ## Arguments
+/// * `microseconds`: the number of microseconds to wait, max value 54925.
+pub fn channel2_oneshot(microseconds: u32) -> Result<(), &'static str> {
+    let reload = microseconds as u64 * PIT_DEFAULT_DIVIDEND_HZ as u64 / 1_000_000;
+    if reload > u16::MAX as u64 {
+        error!("channel2_oneshot(): the chosen wait time {}us is too large, max value is {}!",
+            microseconds, u16::MAX as u64 * 1_000_000 / PIT_DEFAULT_DIVIDEND_HZ as u64,
+        );
+        return Err("microsecond value was too large");
+    }
+    channel2_oneshot_ticks(reload as u16);
+    Ok(())
+}
+
+/// Waits (blocking) for the given number of PIT `ticks`, each of which lasts
+/// `1 / PIT_DEFAULT_DIVIDEND_HZ` seconds, by polling PIT Channel 2
+/// as a one-shot countdown timer (mode 0).
+///
+/// Like [`channel2_oneshot()`], this doesn't affect PIT Channel 0.
+/// A value of `0` returns immediately.
+pub fn channel2_oneshot_ticks(ticks: u16) {
+    if ticks != 0 {
+        CHANNEL_2.lock().oneshot(ticks);
+    }
+}
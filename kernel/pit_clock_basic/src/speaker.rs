@@ -0,0 +1,139 @@
+//! Beeps from the PC speaker, driven by PIT Channel 2.
+//!
+//! Beep durations are measured by counting periods of Channel 2's own square wave,
+//! so beeping doesn't depend on any other clock source or on interrupts.
+//!
+//! Upon a fatal error, a distinct [`BeepCode`] can be emitted, which identifies the error
+//! on a headless machine without a serial console. This is disabled by default;
+//! it can be enabled at build time with `THESEUS_CONFIG=beep_on_fatal`,
+//! or at runtime with [`set_fatal_beeps()`].
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::{Channel2, CHANNEL_2, PIT_DEFAULT_DIVIDEND_HZ};
+
+/// The square wave frequency used to time the silent gaps of a beep pattern,
+/// which is inaudible because the speaker is disconnected.
+const SILENCE_TIMING_HZ: u32 = 1000;
+
+/// Whether [`fatal_beep()`] emits beep codes.
+static FATAL_BEEPS_ENABLED: AtomicBool = AtomicBool::new(cfg!(beep_on_fatal));
+
+/// The beep codes emitted upon fatal errors by [`fatal_beep()`].
+///
+/// | Code            | Pattern                                         |
+/// |-----------------|-------------------------------------------------|
+/// | `Panic`         | three short high beeps                          |
+/// | `DoubleFault`   | one long low beep, then two short high beeps    |
+/// | `BootFailure`   | two long low beeps                              |
+///
+/// Because a boot failure ends in a panic, its code is followed by the `Panic` code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BeepCode {
+    /// A task panicked.
+    Panic,
+    /// A CPU took a double fault.
+    DoubleFault,
+    /// The kernel failed to boot.
+    BootFailure,
+}
+
+impl BeepCode {
+    /// Returns this code's beep pattern, as a list of `(frequency_hz, duration_ms)` pairs.
+    ///
+    /// A frequency of zero denotes a silent gap.
+    pub const fn pattern(self) -> &'static [(u32, u32)] {
+        const SHORT: u32 = 150;
+        const LONG: u32 = 600;
+        const GAP: u32 = 150;
+        const HIGH: u32 = 1000;
+        const LOW: u32 = 400;
+        match self {
+            BeepCode::Panic => &[(HIGH, SHORT), (0, GAP), (HIGH, SHORT), (0, GAP), (HIGH, SHORT)],
+            BeepCode::DoubleFault => &[(LOW, LONG), (0, GAP), (HIGH, SHORT), (0, GAP), (HIGH, SHORT)],
+            BeepCode::BootFailure => &[(LOW, LONG), (0, GAP), (LOW, LONG)],
+        }
+    }
+}
+
+/// Sets whether [`fatal_beep()`] emits beep codes.
+pub fn set_fatal_beeps(enabled: bool) {
+    FATAL_BEEPS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether [`fatal_beep()`] emits beep codes.
+pub fn fatal_beeps() -> bool {
+    FATAL_BEEPS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Beeps at the given frequency for the given duration, blocking until it's done.
+///
+/// ## Arguments
+/// * `frequency_hz`: the frequency of the beep, from 19 Hz up to 596591 Hz,
+///    or zero for silence.
+/// * `duration_ms`: how long to beep for, in milliseconds.
+pub fn beep(frequency_hz: u32, duration_ms: u32) -> Result<(), &'static str> {
+    beep_pattern(&[(frequency_hz, duration_ms)])
+}
+
+/// Beeps the given pattern, blocking until it's done.
+///
+/// The `pattern` is a list of `(frequency_hz, duration_ms)` pairs, as in [`beep()`].
+pub fn beep_pattern(pattern: &[(u32, u32)]) -> Result<(), &'static str> {
+    let reloads = validate(pattern)?;
+    play(&CHANNEL_2.lock(), pattern, reloads);
+    Ok(())
+}
+
+/// Beeps the given [`BeepCode`], if fatal beeps are enabled; see [`set_fatal_beeps()`].
+///
+/// This is intended for fatal error paths, so it never blocks on PIT Channel 2:
+/// if Channel 2 is in use, e.g., by a delay on another CPU, nothing is beeped.
+pub fn fatal_beep(code: BeepCode) {
+    if !fatal_beeps() {
+        return;
+    }
+    let pattern = code.pattern();
+    if let (Ok(reloads), Some(channel2)) = (validate(pattern), CHANNEL_2.try_lock()) {
+        play(&channel2, pattern, reloads);
+    }
+}
+
+/// Checks that every frequency in the given `pattern` can be generated by Channel 2.
+///
+/// Returns an iterator over each entry's Channel 2 reload value,
+/// or `None` for silent entries.
+fn validate(pattern: &[(u32, u32)]) -> Result<impl Iterator<Item = Option<u16>> + '_, &'static str> {
+    for &(frequency_hz, _) in pattern {
+        if frequency_hz != 0 && reload_value(frequency_hz).is_none() {
+            error!("speaker: cannot beep at {} Hz", frequency_hz);
+            return Err("beep frequency must be between 19 Hz and 596591 Hz");
+        }
+    }
+    Ok(pattern.iter().map(|&(frequency_hz, _)| reload_value(frequency_hz)))
+}
+
+/// Returns the Channel 2 reload value for a square wave of the given frequency,
+/// if it can be generated.
+fn reload_value(frequency_hz: u32) -> Option<u16> {
+    let reload = PIT_DEFAULT_DIVIDEND_HZ.checked_div(frequency_hz)?;
+    // Mode 3 requires a reload value of at least 2.
+    if (2..=u16::MAX as u32).contains(&reload) {
+        Some(reload as u16)
+    } else {
+        None
+    }
+}
+
+fn play(channel2: &Channel2, pattern: &[(u32, u32)], reloads: impl Iterator<Item = Option<u16>>) {
+    let silence = reload_value(SILENCE_TIMING_HZ).unwrap();
+    for (&(_, duration_ms), reload) in pattern.iter().zip(reloads) {
+        let (reload, audible) = match reload {
+            Some(reload) => (reload, true),
+            None => (silence, false),
+        };
+        let actual_hz = (PIT_DEFAULT_DIVIDEND_HZ / reload as u32) as u64;
+        channel2.start_square_wave(reload, audible);
+        channel2.wait_periods(actual_hz * duration_ms as u64 / 1000);
+    }
+    channel2.stop();
+}
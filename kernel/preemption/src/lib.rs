@@ -56,10 +56,7 @@ fn hold_preemption_internal<const DISABLE_TIMER: bool>() -> PreemptionGuard {
     if DISABLE_TIMER && guard.preemption_was_enabled {
         // log::trace!(" CPU {}:   disabling local timer interrupt", cpu_id);
         #[cfg(target_arch = "x86_64")]
-        apic::get_my_apic()
-            .expect("BUG: hold_preemption() couldn't get local APIC")
-            .write()
-            .enable_lvt_timer(false);
+        enable_local_timer(false);
     } else if prev_val == u8::MAX {
         // Overflow occurred and the counter value wrapped around, which is a bug.
         panic!("BUG: Overflow occurred in the preemption counter for CPU {}", cpu_id);
@@ -131,10 +128,7 @@ impl Drop for PreemptionGuard {
         if prev_val == 1 {
             // log::trace!("CPU {}: re-enabling local timer interrupt", cpu_id);
             #[cfg(target_arch = "x86_64")]
-            apic::get_my_apic()
-                .expect("BUG: PreemptionGuard::drop() couldn't get local APIC")
-                .write()
-                .enable_lvt_timer(true);
+            enable_local_timer(true);
         } else if prev_val == 0 {
             // Underflow occurred and the counter value wrapped around, which is a bug.
             panic!("BUG: Underflow occurred in the preemption counter for CPU {}", cpu_id);
@@ -150,3 +144,34 @@ impl Drop for PreemptionGuard {
 pub fn preemption_enabled() -> bool {
     PREEMPTION_COUNT.load() == 0
 }
+
+/// Re-enables preemption on this CPU, regardless of how many [`PreemptionGuard`]s are outstanding.
+///
+/// This is only used when exiting a task that can't be unwound,
+/// in which case the guards held in its abandoned stack frames will never be dropped.
+///
+/// # Safety
+/// Every outstanding `PreemptionGuard` on this CPU must belong to the current task,
+/// which must never run again, as dropping one of them would underflow the counter.
+pub unsafe fn force_enable_preemption() {
+    while PREEMPTION_COUNT.load() > 0 {
+        let prev_val = PREEMPTION_COUNT.fetch_sub(1);
+        if prev_val == 1 {
+            #[cfg(target_arch = "x86_64")]
+            enable_local_timer(true);
+        }
+    }
+}
+
+/// Enables or disables the local timer interrupt used for preemptive task switching on this CPU.
+///
+/// In PIC mode, there is no Local APIC timer; the single CPU's timer interrupt
+/// comes from the PIT, and the scheduler already ignores it while preemption is disabled.
+#[cfg(target_arch = "x86_64")]
+fn enable_local_timer(enable: bool) {
+    match apic::get_my_apic() {
+        Some(my_apic) => my_apic.write().enable_lvt_timer(enable),
+        None if apic::pic_fallback_reason().is_some() => { }
+        None => panic!("BUG: preemption couldn't get local APIC to enable/disable its timer"),
+    }
+}
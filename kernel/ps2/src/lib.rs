@@ -129,6 +129,11 @@ pub fn init() -> Result<&'static PS2Controller, &'static str> {
     Ok(PS2_CONTROLLER.call_once(|| controller))
 }
 
+/// Returns the system-wide PS/2 controller, if it has been initialized.
+pub fn controller() -> Option<&'static PS2Controller> {
+    PS2_CONTROLLER.get()
+}
+
 /// An initialized PS/2 controller that can be used to communicate with
 /// legacy PS/2 keyboard and mouse devices.
 pub struct PS2Controller {
@@ -529,6 +534,15 @@ impl<'c> PS2Keyboard<'c> {
             .map_err(|_| "failed to set the keyboard led")
     }
 
+    /// Set the typematic (key repeat) behavior of the keyboard:
+    /// how long a key must be held before it starts repeating,
+    /// and how quickly it repeats after that.
+    pub fn set_keyboard_typematic(&self, delay: RepeatDelay, rate: RepeatRate) -> Result<(), &'static str> {
+        self.command_to_keyboard(HostToKeyboardCommandOrData::KeyboardCommand(SetRepeatRateAndDelay))
+            .and_then(|_| self.command_to_keyboard(HostToKeyboardCommandOrData::Typematic(delay, rate)))
+            .map_err(|_| "failed to set the keyboard typematic rate and delay")
+    }
+
     /// Set the active scancode set currently used by the keyboard.
     /// 
     /// TODO:      set Set2, if Get == 2, return
@@ -716,6 +730,11 @@ pub enum HostToControllerCommand {
     // /// 0 = pulse line, 1 = don't pulse line; Bit 0 corresponds to the "reset" line.
     // /// The other output lines don't have a standard/defined purpose.
     // PulseOutputLineLowFor6ms = 0xF0,
+
+    /// pulses only the "reset" output line low, which resets the whole system
+    ///
+    /// Note: this is the common special case of `PulseOutputLineLowFor6ms` above
+    PulseResetLine = 0xFE,
 }
 
 // see https://wiki.osdev.org/%228042%22_PS/2_Controller#Status_Register
@@ -773,6 +792,8 @@ impl From<WritableData> for u8 {
                     KeyboardCommand(c) => c as u8,
                     LEDState(l) => u8::from_ne_bytes(l.into_bytes()),
                     ScancodeSet(s) => s as u8,
+                    // bits 0-4 are the repeat rate, bits 5-6 are the delay, bit 7 must be zero
+                    Typematic(delay, rate) => (delay as u8) << 5 | rate as u8,
                 }
                 HostToDevice::Mouse(value) => match value {
                     MouseCommand(c) => c as u8,
@@ -857,7 +878,8 @@ pub enum HostToKeyboardCommandOrData {
     KeyboardCommand(HostToKeyboardCommand),
     LEDState(LEDState),
     ScancodeSet(ScancodeSet),
-    //TODO: Typematic, Scancode
+    Typematic(RepeatDelay, RepeatRate),
+    //TODO: Scancode
 }
 
 #[derive(Debug, Clone)]
@@ -897,6 +919,40 @@ pub struct LEDState {
     pub caps_lock: bool,
 }
 
+/// How long a key must be held down before it starts repeating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RepeatDelay {
+    Ms250 = 0,
+    /// the usual power-on default
+    Ms500 = 1,
+    Ms750 = 2,
+    Ms1000 = 3,
+}
+
+/// How many times per second a held-down key repeats.
+///
+/// The keyboard supports 32 rates between 30 and 2 repeats per second;
+/// only the ones closest to whole numbers are listed here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RepeatRate {
+    Hz30 = 0x00,
+    Hz24 = 0x02,
+    Hz20 = 0x04,
+    Hz16 = 0x07,
+    Hz15 = 0x08,
+    Hz12 = 0x0A,
+    /// the usual power-on default is 10.9 Hz, which lies between this and [RepeatRate::Hz12]
+    Hz10 = 0x0C,
+    Hz8 = 0x0F,
+    Hz6 = 0x12,
+    Hz5 = 0x14,
+    Hz4 = 0x17,
+    Hz3 = 0x1A,
+    Hz2 = 0x1F,
+}
+
 // Note: with hardware translation on, these would be:
 // Set1 = 0x43, Set2 = 0x41, Set3 = 0x3f
 // but we're not using scancode translation.
@@ -24,6 +24,11 @@
 //! see [`PteFlagsAarch64::from()`] for more information.
 //! 
 //! See the docs for [`PteFlagsAarch64`] for its assumptions about system configuration.
+//!
+//! ## Memory types
+//! The caching behavior of a mapping is specified by a [`MemoryType`],
+//! which can be set via the arch-specific `memory_type()` builder method,
+//! e.g., [`PteFlagsX86_64::memory_type()`].
 
 #![no_std]
 #![feature(doc_cfg)]
@@ -116,10 +121,11 @@ bitflags! {
         ///   or is mapped differently across different address spaces,
         ///   and thus be flushed out of the TLB when switching address spaces (page tables).
         ///
-        /// This is not used in Theseus, as it has a single address space.
+        /// On x86_64, Theseus sets this for kernel mappings so that they survive `cr3` reloads;
+        /// global pages are still flushed individually when they are changed or unmapped.
         //
         // This DOES require a conversion for aarch64, but not for x86_64.
-        const _GLOBAL = GLOBAL_BIT.bits();
+        const GLOBAL = GLOBAL_BIT.bits();
 
         /// * If set, this page is not executable.
         /// * If not set, this page is executable.
@@ -137,6 +143,9 @@ bitflags! {
         ///   that we **know** are bijective (1-to-1 virtual-to-physical) mappings. 
         ///   This allows Theseus to safely deallocate the frame mapped by this page
         ///   once this page table entry is unmapped. 
+        ///   The page table implementation also sets it for P4-, P3-, and P2-level PTEs
+        ///   that point to a page table frame it allocated, such that it can deallocate
+        ///   that page table once it becomes empty.
         /// * If not set, the frame mapped by this page is not owned exclusively
         ///   and thus cannot be safely deallocated when this page is unmapped.
         //
@@ -145,12 +154,34 @@ bitflags! {
     }
 }
 
+/// The caching behavior of a mapped region of memory.
+///
+/// This can be set via the arch-specific `memory_type()` builder method,
+/// which translates it into the appropriate PTE bits:
+/// the PAT index bits (`PWT`, `PCD`, and `PAT`) on x86_64,
+/// or the MAIR index on aarch64.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MemoryType {
+    /// Normal, fully-cacheable memory. This is the default for RAM.
+    WriteBack,
+    /// Memory that is never cached, neither for reads nor writes,
+    /// and whose accesses are not reordered. This should be used for MMIO registers.
+    Uncacheable,
+    /// Memory that is not cached, but whose writes may be buffered and combined
+    /// into larger bursts. This is ideal for framebuffers and other write-mostly device memory,
+    /// but must not be used for MMIO registers with read or write side effects.
+    ///
+    /// On x86_64, this falls back to [`MemoryType::Uncacheable`] if the
+    /// Page Attribute Table isn't supported.
+    WriteCombining,
+}
+
 // The bits defined below have different semantics on x86_64 vs aarch64.
 // These are the ones that require special handling during From/Into conversions.
 cfg_if!{ if #[cfg(target_arch = "x86_64")] {
     const DEVICE_MEMORY_BITS: PteFlagsX86_64 = PteFlagsX86_64::DEVICE_MEMORY;
     const WRITABLE_BIT:       PteFlagsX86_64 = PteFlagsX86_64::WRITABLE;
-    const GLOBAL_BIT:         PteFlagsX86_64 = PteFlagsX86_64::_GLOBAL;
+    const GLOBAL_BIT:         PteFlagsX86_64 = PteFlagsX86_64::GLOBAL;
 } else if #[cfg(target_arch = "aarch64")] {
     const DEVICE_MEMORY_BITS: PteFlagsAarch64 = PteFlagsAarch64::DEVICE_MEMORY;
     const WRITABLE_BIT:       PteFlagsAarch64 = PteFlagsAarch64::READ_ONLY;
@@ -248,6 +279,17 @@ impl PteFlags {
         self
     }
 
+    /// Returns a copy of this `PteFlags` with the `GLOBAL` bit set or cleared.
+    ///
+    /// * If `enable` is `true`, this page's TLB entries will be kept when switching page tables.
+    /// * If `enable` is `false`, this page's TLB entries will be flushed when switching page tables,
+    ///   which is the default.
+    #[must_use]
+    pub fn global(mut self, enable: bool) -> Self {
+        self.set(Self::GLOBAL, enable);
+        self
+    }
+
     /// Returns a copy of this `PteFlags` with the `ACCESSED` bit set or cleared.
     ///
     /// Typically this is used to clear the `ACCESSED` bit, in order to indicate
@@ -308,4 +350,8 @@ impl PteFlags {
     pub const fn is_exclusive(&self) -> bool {
         self.contains(Self::EXCLUSIVE)
     }
+
+    pub const fn is_global(&self) -> bool {
+        self.contains(Self::GLOBAL)
+    }
 }
@@ -1,6 +1,6 @@
 //! The aarch64-specific definitions of PTE flags.
 
-use crate::PteFlags;
+use crate::{MemoryType, PteFlags};
 use bitflags::bitflags;
 
 /// A mask for the bits of a page table entry that contain the physical frame address.
@@ -30,6 +30,7 @@ bitflags! {
     /// * The system has been configured to use only a single translation stage, Stage 1.
     /// * The [MAIR] index 0 has a Normal + Outer Shareable entry.
     /// * The [MAIR] index 1 has a "DEVICE nGnRE" entry.
+    /// * The [MAIR] index 2 has a Normal + Non-cacheable entry.
     ///
     /// [MAIR]: https://docs.rs/cortex-a/latest/cortex_a/registers/MAIR_EL1/index.html
     #[doc(cfg(target_arch = "aarch64"))]
@@ -64,8 +65,14 @@ bitflags! {
         const DEVICE_MEMORY      = Self::_MAIR_INDEX_1.bits();
         /// Indicates the page's cacheability is described by MAIR Index 2.
         ///
-        /// This is unused in Theseus.
+        /// Theseus uses this index for "normal" but non-cacheable memory.
         const _MAIR_INDEX_2      = 2 << 2;
+        /// This page maps normal memory that isn't cached, e.g., a framebuffer.
+        /// Unlike device memory, accesses to it may be gathered and reordered,
+        /// which is the aarch64 equivalent of write-combining.
+        ///
+        /// Theseus uses `MAIR_INDEX_2` for this type of memory.
+        const NON_CACHEABLE_MEMORY = Self::_MAIR_INDEX_2.bits();
         /// Indicates the page's cacheability is described by MAIR Index 3.
         ///
         /// This is unused in Theseus.
@@ -281,6 +288,22 @@ impl PteFlagsAarch64 {
         self
     }
 
+    /// Returns a copy of this `PteFlagsAarch64` with the MAIR index bits set
+    /// to the index that Theseus uses for the given `memory_type`.
+    ///
+    /// This overrides any prior use of [`Self::device_memory()`].
+    #[must_use]
+    #[doc(alias("cache", "cacheable", "write-combining", "MAIR"))]
+    pub fn memory_type(mut self, memory_type: MemoryType) -> Self {
+        self.remove(PteFlagsAarch64::_MAIR_INDEX_7);
+        self.insert(match memory_type {
+            MemoryType::WriteBack => PteFlagsAarch64::NORMAL_MEMORY,
+            MemoryType::Uncacheable => PteFlagsAarch64::DEVICE_MEMORY,
+            MemoryType::WriteCombining => PteFlagsAarch64::NON_CACHEABLE_MEMORY,
+        });
+        self
+    }
+
     /// Returns a copy of this `PteFlagsAarch64` with the `EXCLUSIVE` bit set or cleared.
     ///
     /// * If `enable` is `true`, this page will exclusively map its frame.
@@ -373,10 +396,10 @@ impl PteFlagsAarch64 {
     ///   * P4, P3, and P2 entries should never set `NOT_EXECUTABLE`,
     ///     only the lowest-level P1 entry should.
     /// * Clears the `EXCLUSIVE` bit.
-    ///   * Currently, we do not use the `EXCLUSIVE` bit for P4, P3, or P2 entries,
-    ///     because another page table frame may re-use it (create another alias to it)
-    ///     without our page table implementation knowing about it.
-    ///   * Only P1-level PTEs can map a frame exclusively.
+    ///   * Callers cannot map a frame exclusively via a P4, P3, or P2 entry,
+    ///     only via the lowest-level P1 entry.
+    ///   * The page table implementation sets it on a P4, P3, or P2 entry
+    ///     only if it allocated the next-level page table frame itself.
     /// * Sets the `ACCESSED` bit, since Theseus currently does not use it
     ///   and aarch64 will throw an Access Flag Fault if it is not set.
     /// * Sets the `PAGE_DESCRIPTOR` bit, since Theseus currently does not
@@ -1,6 +1,6 @@
 //! The x86_64-specific definitions of PTE flags.
 
-use crate::PteFlags;
+use crate::{MemoryType, PteFlags};
 use bitflags::bitflags;
 
 /// A mask for the bits of a page table entry that contain the physical frame address.
@@ -99,8 +99,10 @@ bitflags! {
         ///   or is mapped differently across different address spaces,
         ///   and thus be flushed out of the TLB when switching address spaces (page tables).
         ///
-        /// Note: Theseus is a single address space system, so this flag makes no difference.
-        const _GLOBAL            = 1 <<  8;
+        /// This is only honored if `CR4.PGE` is enabled, in which case a `cr3` reload
+        /// doesn't flush this page's TLB entries; only `invlpg` or toggling `CR4.PGE` does.
+        /// It is ignored in entries that point to a lower-level page table.
+        const GLOBAL             = 1 <<  8;
 
         // Note: Theseus currently only supports setting PAT bits for P1-level PTEs.
         //
@@ -186,6 +188,25 @@ impl PteFlagsX86_64 {
         self
     }
 
+    /// Returns a copy of this `PteFlagsX86_64` with the PAT index bits set
+    /// to the slot of Theseus's fixed Page Attribute Table for the given `memory_type`.
+    ///
+    /// This overrides any prior use of [`Self::device_memory()`] or [`Self::pat_index()`].
+    /// If PAT isn't supported, [`MemoryType::WriteCombining`] falls back to
+    /// [`MemoryType::Uncacheable`], as only the PWT and PCD bits are meaningful.
+    #[must_use]
+    #[doc(alias("cache", "cacheable", "write-combining", "PAT"))]
+    pub fn memory_type(self, memory_type: MemoryType) -> Self {
+        use page_attribute_table::MemoryCachingType;
+        let caching_type = match memory_type {
+            MemoryType::WriteBack => MemoryCachingType::WriteBack,
+            MemoryType::Uncacheable => MemoryCachingType::Uncacheable,
+            MemoryType::WriteCombining if page_attribute_table::is_supported() => MemoryCachingType::WriteCombining,
+            MemoryType::WriteCombining => MemoryCachingType::Uncacheable,
+        };
+        self.pat_index(caching_type.pat_slot_index())
+    }
+
     /// Returns a copy of this `PteFlagsX86_64` with the `EXCLUSIVE` bit set or cleared.
     ///
     /// * If `enable` is `true`, this page will exclusively map its frame.
@@ -272,20 +293,39 @@ impl PteFlagsX86_64 {
     ///   * P4, P3, and P2 entries should never set `NOT_EXECUTABLE`,
     ///     only the lowest-level P1 entry should.
     /// * Clears the `EXCLUSIVE` bit.
-    ///   * Currently, we do not use the `EXCLUSIVE` bit for P4, P3, or P2 entries,
-    ///     because another page table frame may re-use it (create another alias to it)
-    ///     without our page table implementation knowing about it.
-    ///   * Only P1-level PTEs can map a frame exclusively.
+    ///   * Callers cannot map a frame exclusively via a P4, P3, or P2 entry,
+    ///     only via the lowest-level P1 entry.
+    ///   * The page table implementation sets it on a P4, P3, or P2 entry
+    ///     only if it allocated the next-level page table frame itself.
     /// * Clears the PAT index value, as we only support PAT on P1-level PTEs.
+    /// * Clears the `GLOBAL` bit.
+    ///   * The recursive mapping accesses page tables through their higher-level entries,
+    ///     which must be flushed by a `cr3` reload when those page tables change.
     /// * Sets the `VALID` bit, as every P4, P3, and P2 entry must be valid.
     #[must_use]
     pub fn adjust_for_higher_level_pte(self) -> Self {
         self.executable(true)
             .exclusive(false)
             .pat_index(0)
+            .global(false)
             .valid(true)
     }
 
+    /// Returns a copy of this `PteFlagsX86_64` with the `GLOBAL` bit set or cleared.
+    ///
+    /// * If `enable` is `true`, this page's TLB entries will be kept across `cr3` reloads.
+    /// * If `enable` is `false`, this page's TLB entries will be flushed by `cr3` reloads,
+    ///   which is the default.
+    #[must_use]
+    pub fn global(mut self, enable: bool) -> Self {
+        self.set(Self::GLOBAL, enable);
+        self
+    }
+
+    pub const fn is_global(&self) -> bool {
+        self.contains(Self::GLOBAL)
+    }
+
     /// Returns a copy of this `PteFlagsX86_64` with the PAT index bits
     /// set to the value specifying the given `pat_slot`.
     ///
@@ -14,21 +14,60 @@
 //! An error will be logged if the `TSC` is used as it is not a high quality
 //! source of randomness.
 //!
+//! After that, entropy is continuously collected from the timing of interrupts
+//! and keyboard events into per-CPU pools; see the [`pool`] module.
+//! Once enough new samples have been collected, the next request for randomness
+//! folds the per-CPU pools into a global pool and reseeds the CSPRNG from its own output,
+//! the global pool, and `RDSEED` or `RDRAND` if available.
+//! The health of each source can be inspected with [`stats()`].
+//!
 //! If a consumer requires one-off randomness, [`next_u32`], [`next_u64`], or
 //! [`fill_bytes`] should be used. Otherwise, [`init_rng`] should be used to
 //! seed a local PRNG, which can then be used as a source of randomness. Using a
 //! local PRNG avoids contention on the global CSPRNG and allows for PRNGs
 //! better suited for the task (e.g. non-crypto PRNGs).
+//! A reproducible sequence, e.g., for tests, can be obtained from [`deterministic_rng`].
 
 #![no_std]
 
-use rand_chacha::{
-    rand_core::{RngCore, SeedableRng},
-    ChaCha20Rng,
-};
-use spin::mutex::Mutex;
+pub mod pool;
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use rand_chacha::{ChaCha20Rng, ChaCha8Rng};
+use rdrand::{RdRand, RdSeed};
+use spin::mutex::{Mutex, MutexGuard};
+
+pub use rand_chacha::rand_core::{Error, RngCore, SeedableRng};
 
-pub use rand_chacha::rand_core::Error;
+/// The number of new samples in the per-CPU pools after which the CSPRNG is reseeded.
+const RESEED_SAMPLES: u64 = 1024;
+/// The number of times a hardware source is retried before it's considered to have failed.
+///
+/// `RDSEED` in particular fails transiently when its entropy is exhausted.
+const HARDWARE_RETRIES: usize = 10;
+
+/// The number of 64-bit words obtained from `RDSEED`.
+static RDSEED_WORDS: AtomicU64 = AtomicU64::new(0);
+/// The number of failed attempts to obtain a word from `RDSEED`.
+static RDSEED_FAILURES: AtomicU64 = AtomicU64::new(0);
+/// The number of 64-bit words obtained from `RDRAND`.
+static RDRAND_WORDS: AtomicU64 = AtomicU64::new(0);
+/// The number of failed attempts to obtain a word from `RDRAND`.
+static RDRAND_FAILURES: AtomicU64 = AtomicU64::new(0);
+/// Whether the CSPRNG was initially seeded from the TSC.
+static TSC_SEEDED: AtomicBool = AtomicBool::new(false);
+/// The number of times the CSPRNG has been reseeded.
+static RESEEDS: AtomicU64 = AtomicU64::new(0);
+/// The total number of per-CPU pool samples at the last reseed.
+static FOLDED_SAMPLES: AtomicU64 = AtomicU64::new(0);
+
+/// The global CSPRNG, along with the global entropy pool it's reseeded from.
+struct State {
+    csprng: ChaCha20Rng,
+    pool: [u64; 4],
+    rdseed: Option<RdSeed>,
+    rdrand: Option<RdRand>,
+}
 
 lazy_static::lazy_static! {
     /// The global random number generator.
@@ -40,55 +79,70 @@ lazy_static::lazy_static! {
     ///
     /// Using a single global CSPRNG allows us to feed it with entropy from
     /// device drivers and such.
-    static ref CSPRNG: Mutex<ChaCha20Rng> = {
-        let seed = rdseed_seed()
-            .or_else(rdrand_seed)
+    static ref CSPRNG: Mutex<State> = {
+        let rdseed = RdSeed::new().ok();
+        let rdrand = RdRand::new().ok();
+        let seed = rdseed.and_then(rdseed_seed)
+            .or_else(|| rdrand.and_then(rdrand_seed))
             .unwrap_or_else(tsc_seed);
-        Mutex::new(ChaCha20Rng::from_seed(seed))
+        Mutex::new(State {
+            csprng: ChaCha20Rng::from_seed(seed),
+            pool: [0; 4],
+            rdseed,
+            rdrand,
+        })
     };
 }
 
-/// Tries to generate a 32 byte seed using the RDSEED x86 instruction.
-fn rdseed_seed() -> Option<[u8; 32]> {
-    match rdrand::RdSeed::new() {
-        Ok(mut generator) => {
-            let mut seed = [0; 32];
-            match generator.try_fill_bytes(&mut seed) {
-                Ok(_) => {
-                    log::info!("using RDSEED for CSPRNG seed");
-                    Some(seed)
-                }
-                Err(_) => {
-                    log::warn!("failed to generate seed from RDSEED");
-                    None
-                }
+/// Fills `dest` from the given hardware `generator`, retrying each word on transient failures.
+///
+/// The number of words obtained and failed attempts are counted in `words` and `failures`.
+fn fill_from_hardware<R: RngCore>(
+    generator: &mut R,
+    dest: &mut [u8],
+    words: &AtomicU64,
+    failures: &AtomicU64,
+) -> Result<(), Error> {
+    for chunk in dest.chunks_mut(8) {
+        let mut result = Ok(());
+        for _ in 0..HARDWARE_RETRIES {
+            result = generator.try_fill_bytes(chunk);
+            if result.is_ok() {
+                break;
             }
+            failures.fetch_add(1, Ordering::Relaxed);
+        }
+        result?;
+        words.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Tries to generate a 32 byte seed using the RDSEED x86 instruction.
+fn rdseed_seed(mut generator: RdSeed) -> Option<[u8; 32]> {
+    let mut seed = [0; 32];
+    match fill_from_hardware(&mut generator, &mut seed, &RDSEED_WORDS, &RDSEED_FAILURES) {
+        Ok(_) => {
+            log::info!("using RDSEED for CSPRNG seed");
+            Some(seed)
         }
         Err(_) => {
-            log::warn!("failed to initialise RDSEED");
+            log::warn!("failed to generate seed from RDSEED");
             None
         }
     }
 }
 
 /// Tries to generate a 32 byte seed using the RDRAND x86 instruction.
-fn rdrand_seed() -> Option<[u8; 32]> {
-    match rdrand::RdRand::new() {
-        Ok(mut generator) => {
-            let mut seed = [0; 32];
-            match generator.try_fill_bytes(&mut seed) {
-                Ok(_) => {
-                    log::info!("using RDRAND for CSPRNG seed");
-                    Some(seed)
-                }
-                Err(_) => {
-                    log::warn!("failed to generate seed from RDRAND");
-                    None
-                }
-            }
+fn rdrand_seed(mut generator: RdRand) -> Option<[u8; 32]> {
+    let mut seed = [0; 32];
+    match fill_from_hardware(&mut generator, &mut seed, &RDRAND_WORDS, &RDRAND_FAILURES) {
+        Ok(_) => {
+            log::info!("using RDRAND for CSPRNG seed");
+            Some(seed)
         }
         Err(_) => {
-            log::warn!("failed to initialise RDRAND");
+            log::warn!("failed to generate seed from RDRAND");
             None
         }
     }
@@ -108,16 +162,58 @@ fn tsc_seed() -> [u8; 32] {
 
     // The TSC isn't a high quality source of randomness.
     log::error!("using TSC for CSPRNG seed - this is not ok");
+    TSC_SEEDED.store(true, Ordering::Relaxed);
     seed
 }
 
+/// Locks the global CSPRNG, reseeding it first if enough new entropy has been collected.
+fn csprng() -> MutexGuard<'static, State> {
+    let mut state = CSPRNG.lock();
+    let total = pool::total_samples();
+    if total.wrapping_sub(FOLDED_SAMPLES.load(Ordering::Relaxed)) >= RESEED_SAMPLES {
+        FOLDED_SAMPLES.store(total, Ordering::Relaxed);
+        state.reseed();
+    }
+    state
+}
+
+impl State {
+    /// Folds the per-CPU pools into the global pool and reseeds the CSPRNG.
+    ///
+    /// The new seed is the CSPRNG's own output XORed with the global pool
+    /// and with a hardware random word, if available.
+    /// Each input alone is enough to make the new seed unpredictable,
+    /// so a weak or compromised source can't reduce its quality.
+    fn reseed(&mut self) {
+        pool::fold_into(&mut self.pool);
+
+        let mut seed = [0; 32];
+        self.csprng.fill_bytes(&mut seed);
+        let mut hardware = [0; 32];
+        let hardware_ok = self.rdseed.as_mut().map_or(false, |rdseed|
+            fill_from_hardware(rdseed, &mut hardware, &RDSEED_WORDS, &RDSEED_FAILURES).is_ok()
+        ) || self.rdrand.as_mut().map_or(false, |rdrand|
+            fill_from_hardware(rdrand, &mut hardware, &RDRAND_WORDS, &RDRAND_FAILURES).is_ok()
+        );
+        for (i, chunk) in seed.chunks_mut(8).enumerate() {
+            let mut word = u64::from_ne_bytes(chunk.try_into().unwrap()) ^ self.pool[i];
+            if hardware_ok {
+                word ^= u64::from_ne_bytes(hardware[i * 8 .. (i + 1) * 8].try_into().unwrap());
+            }
+            chunk.copy_from_slice(&word.to_ne_bytes());
+        }
+        self.csprng = ChaCha20Rng::from_seed(seed);
+        RESEEDS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 /// Returns a random [`u32`].
 ///
 /// Consider using [`init_rng`] if calling this function in a loop, or if you
 /// don't require cryptographically secure random numbers.
 pub fn next_u32() -> u32 {
-    let mut csprng = CSPRNG.lock();
-    csprng.next_u32()
+    let mut state = csprng();
+    state.csprng.next_u32()
 }
 
 /// Returns a random [`u64`].
@@ -125,8 +221,8 @@ pub fn next_u32() -> u32 {
 /// Consider using [`init_rng`] if calling this function in a loop, or if you
 /// don't require cryptographically secure random numbers.
 pub fn next_u64() -> u64 {
-    let mut csprng = CSPRNG.lock();
-    csprng.next_u64()
+    let mut state = csprng();
+    state.csprng.next_u64()
 }
 
 /// Fills `dest` with random data.
@@ -134,8 +230,8 @@ pub fn next_u64() -> u64 {
 /// Consider using [`init_rng`] if calling this function in a loop, or if you
 /// don't require cryptographically secure random numbers.
 pub fn fill_bytes(dest: &mut [u8]) {
-    let mut csprng = CSPRNG.lock();
-    csprng.fill_bytes(dest);
+    let mut state = csprng();
+    state.csprng.fill_bytes(dest);
 }
 
 /// Initialises a `T` RNG.
@@ -147,6 +243,81 @@ pub fn init_rng<T>() -> Result<T, Error>
 where
     T: SeedableRng,
 {
-    let mut csprng = CSPRNG.lock();
-    T::from_rng(&mut *csprng)
+    let mut state = csprng();
+    T::from_rng(&mut state.csprng)
+}
+
+/// The PRNG returned by [`deterministic_rng`].
+pub type DeterministicRng = ChaCha8Rng;
+
+/// Returns a PRNG that always produces the same sequence for the same `seed`.
+///
+/// This is useful for reproducible tests and simulations,
+/// but must never be used where unpredictability matters.
+pub fn deterministic_rng(seed: u64) -> DeterministicRng {
+    DeterministicRng::seed_from_u64(seed)
+}
+
+/// The contributions of a hardware random number instruction.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HardwareSourceStats {
+    /// Whether this CPU supports the instruction.
+    pub supported: bool,
+    /// The number of 64-bit words obtained from it.
+    pub words: u64,
+    /// The number of attempts that failed, e.g., because its entropy was exhausted.
+    pub failures: u64,
+}
+
+/// A snapshot of the health of each entropy source, as returned by [`stats()`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EntropyStats {
+    /// The number of interrupt timing samples added to the per-CPU pools.
+    pub interrupt_samples: u64,
+    /// The number of keyboard event timing samples added to the per-CPU pools.
+    pub keyboard_samples: u64,
+    /// The number of samples added since the CSPRNG was last reseeded.
+    pub unfolded_samples: u64,
+    /// The number of times the CSPRNG has been reseeded.
+    pub reseeds: u64,
+    /// Whether the CSPRNG was initially seeded from the TSC,
+    /// because neither `RDSEED` nor `RDRAND` worked.
+    pub tsc_seeded: bool,
+    /// The contributions of `RDSEED`.
+    pub rdseed: HardwareSourceStats,
+    /// The contributions of `RDRAND`.
+    pub rdrand: HardwareSourceStats,
+}
+
+/// Returns a snapshot of the health of each entropy source.
+pub fn stats() -> EntropyStats {
+    let (rdseed_supported, rdrand_supported) = {
+        let state = CSPRNG.lock();
+        (state.rdseed.is_some(), state.rdrand.is_some())
+    };
+    let total = pool::total_samples();
+    let keyboard_samples = pool::KEYBOARD_SAMPLES.load(Ordering::Relaxed);
+    EntropyStats {
+        interrupt_samples: total.saturating_sub(keyboard_samples),
+        keyboard_samples,
+        unfolded_samples: total.saturating_sub(FOLDED_SAMPLES.load(Ordering::Relaxed)),
+        reseeds: RESEEDS.load(Ordering::Relaxed),
+        tsc_seeded: TSC_SEEDED.load(Ordering::Relaxed),
+        rdseed: HardwareSourceStats {
+            supported: rdseed_supported,
+            words: RDSEED_WORDS.load(Ordering::Relaxed),
+            failures: RDSEED_FAILURES.load(Ordering::Relaxed),
+        },
+        rdrand: HardwareSourceStats {
+            supported: rdrand_supported,
+            words: RDRAND_WORDS.load(Ordering::Relaxed),
+            failures: RDRAND_FAILURES.load(Ordering::Relaxed),
+        },
+    }
+}
+
+/// Returns an iterator over the ID of each CPU that has contributed entropy
+/// and the number of samples in its pool.
+pub fn cpu_pool_samples() -> impl Iterator<Item = (u32, u64)> {
+    pool::pools_in_use().map(|(cpu, pool)| (cpu as u32, pool.samples()))
 }
@@ -0,0 +1,125 @@
+//! Per-CPU entropy pools that collect timing jitter from interrupts and keyboard events.
+//!
+//! Each CPU has a single 64-bit pool that only it writes to, so adding a sample
+//! costs one `RDTSCP` instruction and a relaxed load and store, with no atomic read-modify-write
+//! and no shared cache line. `RDTSCP` also returns the current CPU's ID from the `IA32_TSC_AUX` MSR,
+//! which avoids a separate `RDMSR`.
+//!
+//! Each sample is mixed into its CPU's pool with [`mix()`]:
+//! ```text
+//! pool = (pool.rotate_left(ROTATION) ^ sample) * MULTIPLIER   (mod 2^64)
+//! ```
+//! For a fixed sample, the rotation, XOR, and multiplication by an odd constant are each
+//! a bijection on the pool's 64 bits, so mixing in a sample never discards entropy that
+//! the pool already holds. The rotation and multiplication spread the low bits of each sample,
+//! which is where the jitter is, across the whole pool.
+//!
+//! The per-CPU pools are folded into the global pool by [`fold_into()`] on the request path,
+//! rather than from interrupt context.
+
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+
+/// The maximum number of CPUs that have their own pool.
+///
+/// CPUs with a higher ID share a pool with a lower-numbered CPU, which is harmless
+/// because a lost update only means that a sample's entropy isn't added.
+pub(crate) const MAX_CPUS: usize = 256;
+/// The number of bits by which a pool is rotated before a sample is mixed in.
+const ROTATION: u32 = 7;
+/// The odd multiplier of [`mix()`], which is `2^64` divided by the golden ratio.
+const MULTIPLIER: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// A single CPU's entropy pool.
+///
+/// Each pool occupies its own cache line such that CPUs don't contend with each other.
+#[repr(align(64))]
+pub(crate) struct Pool {
+    value: AtomicU64,
+    samples: AtomicU64,
+}
+
+/// The entropy pool of each CPU, indexed by its ID.
+pub(crate) static POOLS: [Pool; MAX_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Pool = Pool { value: AtomicU64::new(0), samples: AtomicU64::new(0) };
+    [EMPTY; MAX_CPUS]
+};
+/// One more than the highest pool index that has received a sample,
+/// such that folding only needs to visit pools that are in use.
+pub(crate) static POOLS_IN_USE: AtomicU16 = AtomicU16::new(0);
+/// The number of samples contributed by keyboard events, which are also counted in each pool.
+pub(crate) static KEYBOARD_SAMPLES: AtomicU64 = AtomicU64::new(0);
+
+/// Mixes one `sample` into the given `pool` value, as described in the [module docs](self).
+#[inline(always)]
+pub const fn mix(pool: u64, sample: u64) -> u64 {
+    (pool.rotate_left(ROTATION) ^ sample).wrapping_mul(MULTIPLIER)
+}
+
+/// Adds the timing of an interrupt at the given `vector` to the current CPU's pool.
+///
+/// This is called for every interrupt when it's acknowledged, so it must remain cheap.
+#[inline(always)]
+pub fn add_interrupt_timing(vector: u8) {
+    add_sample(vector as u64);
+}
+
+/// Adds the timing of a keyboard event with the given `scancode` to the current CPU's pool.
+///
+/// Keyboard events are rare but driven by a human, so their timing is a good source of entropy
+/// even on machines whose other interrupts are very regular.
+pub fn add_keyboard_timing(scancode: u8) {
+    KEYBOARD_SAMPLES.fetch_add(1, Ordering::Relaxed);
+    add_sample(scancode as u64);
+}
+
+/// Mixes the current timestamp, tagged with the given `event`, into the current CPU's pool.
+///
+/// Only the current CPU writes to its pool, but an interrupt may arrive between
+/// the load and store below, in which case that interrupt's sample is lost.
+/// That is harmless, so we avoid the cost of an atomic read-modify-write.
+#[inline(always)]
+fn add_sample(event: u64) {
+    let mut cpu = 0;
+    // SAFE: `RDTSCP` is supported by all CPUs that Theseus runs on, and has no side effects.
+    let tsc = unsafe { core::arch::x86_64::__rdtscp(&mut cpu) };
+    let index = cpu as usize % MAX_CPUS;
+    let pool = &POOLS[index];
+    let value = pool.value.load(Ordering::Relaxed);
+    pool.value.store(mix(value, tsc ^ (event << 56)), Ordering::Relaxed);
+    if pool.samples.fetch_add(1, Ordering::Relaxed) == 0 {
+        POOLS_IN_USE.fetch_max(index as u16 + 1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the total number of samples added to all pools since boot.
+pub(crate) fn total_samples() -> u64 {
+    pools_in_use().map(|(_, pool)| pool.samples.load(Ordering::Relaxed)).sum()
+}
+
+/// Returns an iterator over the index and pool of each pool that has received a sample.
+pub(crate) fn pools_in_use() -> impl Iterator<Item = (usize, &'static Pool)> {
+    POOLS[..POOLS_IN_USE.load(Ordering::Relaxed) as usize]
+        .iter()
+        .enumerate()
+        .filter(|(_, pool)| pool.samples.load(Ordering::Relaxed) != 0)
+}
+
+impl Pool {
+    /// Returns the number of samples that have been added to this pool since boot.
+    pub(crate) fn samples(&self) -> u64 {
+        self.samples.load(Ordering::Relaxed)
+    }
+}
+
+/// Folds every per-CPU pool into the given global pool.
+///
+/// Each per-CPU pool is mixed into one word of the global pool, rotating through its words.
+/// The per-CPU pools are not cleared, as they're only ever mixed onwards;
+/// the CSPRNG that's reseeded from the global pool is what makes its output unpredictable.
+pub(crate) fn fold_into(global: &mut [u64; 4]) {
+    for (i, (_, pool)) in pools_in_use().enumerate() {
+        let word = &mut global[i % global.len()];
+        *word = mix(*word, pool.value.load(Ordering::Relaxed));
+    }
+}
@@ -6,34 +6,116 @@
 // extern crate alloc;
 #[macro_use] extern crate lazy_static;
 extern crate port_io;
-extern crate irq_safety;
-extern crate spin;
+extern crate sync_irq;
 extern crate state_store;
 #[macro_use] extern crate log;
 extern crate x86_64;
 
 use port_io::Port;
-use irq_safety::hold_interrupts;
-use core::sync::atomic::{AtomicUsize, Ordering};
-use spin::Mutex;
+use core::sync::atomic::{AtomicU64, Ordering};
+use sync_irq::IrqSafeMutex;
 // use spin::Once;
 use state_store::{get_state, insert_state, SSCached};
 
 
-//standard port to write to on CMOS to select registers
-const CMOS_WRITE_PORT: u16 = 0x70;
-//standard port to read register values from on CMOS or write to to change settings
-const CMOS_READ_PORT: u16 = 0x71;
+/// The standard CMOS port used to select a register (and to set the NMI-disable bit).
+const CMOS_SELECT_PORT: u16 = 0x70;
+/// The standard CMOS port used to read or write the value of the selected register.
+const CMOS_DATA_PORT: u16 = 0x71;
 
-//used to select register
-static CMOS_WRITE: Mutex<Port<u8>> = Mutex::new( Port::new(CMOS_WRITE_PORT));
-//used to change cmos settings
-static CMOS_WRITE_SETTINGS: Mutex<Port<u8>> = Mutex::new(Port::new(CMOS_READ_PORT));
-//used to read from cmos register
-static CMOS_READ: Mutex<Port<u8>> = Mutex::new( Port::new(CMOS_READ_PORT));
+/// The bit in the CMOS register selector that disables NMIs while set.
+const CMOS_NMI_DISABLE: u8 = 0x80;
+/// The register left selected after each CMOS access, as recommended,
+/// because the RTC may be left in an undefined state if another register stays selected.
+const CMOS_DEFAULT_REGISTER: u8 = 0x0D;
 
+/// RTC status register A, which contains the "update in progress" bit and the rate divider.
+const RTC_STATUS_A: u8 = 0x0A;
+/// RTC status register B, which contains the interrupt enable bits.
+const RTC_STATUS_B: u8 = 0x0B;
+/// RTC status register C, which contains the pending interrupt flags.
+/// Reading it acknowledges the RTC interrupt.
+const RTC_STATUS_C: u8 = 0x0C;
+/// The "update in progress" bit of status register A.
+const RTC_UPDATE_IN_PROGRESS: u8 = 0x80;
+/// The periodic interrupt enable bit of status register B.
+const RTC_PERIODIC_INTERRUPT_ENABLE: u8 = 0x40;
 
-type RtcTicks = AtomicUsize;
+
+/// The ISA IRQ number of the RTC periodic interrupt.
+/// Because we perform the typical PIC remapping, the remapped IRQ vector number is 0x28.
+pub const RTC_ISA_IRQ: u8 = 0x8;
+
+
+/// The CMOS select and data ports, which must only be accessed together.
+struct CmosPorts {
+    select: Port<u8>,
+    data: Port<u8>,
+}
+
+impl CmosPorts {
+    /// Selects the given `register` with NMIs disabled for the duration of the access.
+    fn select(&self, register: u8) {
+        unsafe { self.select.write(register | CMOS_NMI_DISABLE); }
+    }
+
+    /// Re-selects the default register with NMIs re-enabled,
+    /// which must be done at the end of every access.
+    fn deselect(&self) {
+        unsafe { self.select.write(CMOS_DEFAULT_REGISTER); }
+    }
+}
+
+/// All accesses to the CMOS ports must go through this lock.
+///
+/// Port `0x70` selects which register port `0x71` accesses,
+/// so an access interleaved with another one on a different CPU, or with the RTC
+/// interrupt handler on the same CPU, would read or write the wrong register.
+/// This lock is IRQ-safe, so it cannot be interrupted by the RTC interrupt handler.
+static CMOS: IrqSafeMutex<CmosPorts> = IrqSafeMutex::new(CmosPorts {
+    select: Port::new(CMOS_SELECT_PORT),
+    data: Port::new(CMOS_DATA_PORT),
+});
+
+
+/// Reads the value of the given CMOS `register`.
+///
+/// NMIs are disabled while the register is selected and re-enabled afterwards.
+pub fn cmos_read(register: u8) -> u8 {
+    let cmos = CMOS.lock();
+    cmos.select(register);
+    let value = cmos.data.read();
+    cmos.deselect();
+    value
+}
+
+/// Writes the given `value` to the given CMOS `register`.
+///
+/// NMIs are disabled while the register is selected and re-enabled afterwards.
+pub fn cmos_write(register: u8, value: u8) {
+    let cmos = CMOS.lock();
+    cmos.select(register);
+    unsafe { cmos.data.write(value); }
+    cmos.deselect();
+}
+
+/// Atomically reads the given CMOS `register`, passes its value to `f`,
+/// and then writes the value returned by `f` back to that register.
+///
+/// Returns the previous value of the register.
+pub fn cmos_modify(register: u8, f: impl FnOnce(u8) -> u8) -> u8 {
+    let cmos = CMOS.lock();
+    cmos.select(register);
+    let prev = cmos.data.read();
+    // Reading the data port may reset the selected register, so select it again.
+    cmos.select(register);
+    unsafe { cmos.data.write(f(prev)); }
+    cmos.deselect();
+    prev
+}
+
+
+type RtcTicks = AtomicU64;
 lazy_static! {
     static ref RTC_TICKS: SSCached<RtcTicks> = {
         insert_state(RtcTicks::new(0));
@@ -57,27 +139,9 @@ pub type RtcInterruptFunction = fn(Option<usize>);
 // }
 
 
-//write a u8 to the CMOS port (0x70)
-fn write_cmos(value: u8) {
-    unsafe{
-        CMOS_WRITE.lock().write(value)
-    }
-}
-
-
-//read a u8 from CMOS port 0x71
-fn read_cmos() -> u8{
-    CMOS_READ.lock().read()
-}
-
-
-
 //returns true if update in progress, false otherwise
-fn is_update_in_progress() -> bool{
-    //writing to this register causes cmos to output 1 if rtc update in progress 
-    write_cmos(0x0A);
-    let is_in_progress: bool = read_cmos() == 1;
-    is_in_progress
+fn is_update_in_progress() -> bool {
+    cmos_read(RTC_STATUS_A) & RTC_UPDATE_IN_PROGRESS != 0
 }
 
 
@@ -85,15 +149,28 @@ fn is_update_in_progress() -> bool{
 fn read_register(register: u8) -> u8{
     
     //waits for "update in progress" signal to finish in order to read correct values
-    while is_update_in_progress() {}
-    write_cmos(register);
+    while is_update_in_progress() {
+        core::hint::spin_loop();
+    }
 
     //converts bcd value to binary value which is what is used for printing 
-    let bcd = read_cmos();
+    let bcd = cmos_read(register);
     
     (bcd/16)*10 + (bcd & 0xf)
 }
 
+/// Returns the seconds field of the RTC's current time, without waiting for an update to finish.
+///
+/// Returns `None` if the RTC is currently updating its time, in which case the caller should try again later.
+/// Unlike [`read_rtc()`], this never spins, so it can be used in interrupt handlers.
+pub fn try_read_seconds() -> Option<u8> {
+    if is_update_in_progress() {
+        return None;
+    }
+    let bcd = cmos_read(0x00);
+    Some((bcd/16)*10 + (bcd & 0xf))
+}
+
 /// A timestamp obtained from the real-time clock.
 #[derive(Debug)]
 pub struct RtcTime {
@@ -134,34 +211,39 @@ pub fn read_rtc() -> RtcTime {
 }
 
 /// Returns the current RTC tick count.
-pub fn get_rtc_ticks() -> Option<usize> {
+///
+/// To measure the ticks elapsed between two counts, use `later.wrapping_sub(earlier)`.
+pub fn get_rtc_ticks() -> Option<u64> {
     RTC_TICKS.get().map(|ticks| ticks.load(Ordering::Acquire))
 }
 
 /// turn on IRQ 8 (mapped to 0x28), rtc begins sending interrupts 
-pub fn enable_rtc_interrupt()
-{
-    let _held_interrupts = hold_interrupts();
-
-    write_cmos(0x0C);
-    read_cmos();
-    //select cmos register 0x8B
-    write_cmos(0x8B);
+pub fn enable_rtc_interrupt() {
+    // Clear any pending interrupt first, otherwise none will be raised.
+    rtc_ack_irq();
+    cmos_modify(RTC_STATUS_B, |prev| prev | RTC_PERIODIC_INTERRUPT_ENABLE);
+    trace!("RTC Enabled!");
+}
 
-    //value needed to turn on bit 6 of register B
-    let prev = read_cmos();
+/// turn off the RTC's periodic interrupt.
+pub fn disable_rtc_interrupt() {
+    cmos_modify(RTC_STATUS_B, |prev| prev & !RTC_PERIODIC_INTERRUPT_ENABLE);
+    rtc_ack_irq();
+}
 
-    //we want it to go back to register 0x8B, it was reset when read
-    write_cmos(0x8B);
+/// Acknowledges the RTC interrupt by reading status register C,
+/// which must be done in the RTC interrupt handler for subsequent interrupts to fire.
+///
+/// Returns the interrupt flags in register C.
+pub fn rtc_ack_irq() -> u8 {
+    cmos_read(RTC_STATUS_C)
+}
 
-    //here we don't use the cmos_write function because that only writes to port 0x70, in this case we need to write to 0x71
-    //writing to 0x71 because not selecting register, setting rtc
-    unsafe{
-        CMOS_WRITE_SETTINGS.lock().write(prev | 0x40); 
+/// Counts one RTC periodic interrupt; this should be called from the RTC interrupt handler.
+pub fn handle_rtc_interrupt() {
+    if let Some(ticks) = RTC_TICKS.get() {
+        ticks.fetch_add(1, Ordering::AcqRel);
     }
-
-    trace!("RTC Enabled!");
-    // here: _held_interrupts falls out of scope, re-enabling interrupts if they were previously enabled.
 }
 
 
@@ -195,19 +277,9 @@ pub fn set_rtc_frequency(rate: usize) -> Result<(), InvalidRtcRate> {
     // formula is "rate = 32768 Hz >> (dividor - 1)"
     let dividor: u8 = log2(rate) as u8 + 2; 
 
-    let _held_interrupts = hold_interrupts();
-
     // bottom 4 bits of register A are the "rate dividor", setting them to rate we want without altering top 4 bits
-    write_cmos(0x8A);
-    let prev = read_cmos();
-    write_cmos(0x8A); 
-
-    unsafe{
-        CMOS_WRITE_SETTINGS.lock().write((prev & 0xF0) | dividor);
-    }
+    cmos_modify(RTC_STATUS_A, |prev| (prev & 0xF0) | dividor);
 
     trace!("RTC frequency changed to {} Hz!", rate);
     Ok(())
-    
-    // here: _held_interrupts falls out of scope, re-enabling interrupts if they were previously enabled.
 }
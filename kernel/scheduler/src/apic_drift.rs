@@ -0,0 +1,186 @@
+//! Compensation for the drift of the Local APIC timers that drive the scheduler's timer ticks.
+//!
+//! Each CPU's APIC timer period is calibrated once at boot against the PIT,
+//! so it's slightly off due to calibration error and drifts as the timer's input clock varies.
+//! Thus, [`on_tick()`] compares the bootstrap CPU's ticks against the RTC, whose seconds are
+//! crystal-accurate: near the end of each second, it polls the RTC for the start of the next one
+//! and compares the ticks counted since a baseline second against the number expected in that time.
+//!
+//! Once the baseline spans at least [`MIN_BASELINE_SECS`] and the measured drift exceeds the
+//! resolution of the measurement (two ticks over the baseline), the period of every CPU's APIC timer
+//! is corrected by the measured drift and a new baseline is started.
+//! See [`drift()`] for the current measurement.
+
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU32, AtomicU64, AtomicU8, Ordering};
+
+/// The maximum number of CPUs whose APIC timers can be corrected.
+const MAX_TRACKED_CPUS: usize = 256;
+/// The minimum number of seconds over which drift is measured before it's corrected.
+pub const MIN_BASELINE_SECS: u64 = 10;
+/// The largest correction that is applied, in parts per million.
+///
+/// Larger measured drifts are assumed to be errors, e.g., because the RTC was changed.
+const MAX_CORRECTION_PPM: i64 = 50_000;
+/// A value of `LAST_SECOND` indicating that no RTC second was observed yet.
+const NO_SECOND: u8 = u8::MAX;
+
+/// The number of ticks on the bootstrap CPU, including lost ticks.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// The value of `TICKS` at which to start polling the RTC for the start of the next second.
+static NEXT_POLL_TICK: AtomicU64 = AtomicU64::new(0);
+/// Whether the RTC was polled at least once since `NEXT_POLL_TICK` without observing a new second,
+/// i.e., whether the start of the next second will be observed within one tick.
+static POLLING: AtomicBool = AtomicBool::new(false);
+/// The seconds field of the RTC when it was last polled.
+static LAST_SECOND: AtomicU8 = AtomicU8::new(NO_SECOND);
+/// Whether the current baseline started at an observed start of an RTC second.
+static BASELINE_VALID: AtomicBool = AtomicBool::new(false);
+/// The value of `TICKS` at the start of the baseline second.
+static BASELINE_TICKS: AtomicU64 = AtomicU64::new(0);
+/// The number of seconds elapsed since the baseline second started.
+static BASELINE_SECS: AtomicU64 = AtomicU64::new(0);
+/// The number of seconds over which drift was measured since boot.
+static OBSERVED_SECS: AtomicU64 = AtomicU64::new(0);
+/// The drift measured over the current baseline, in parts per million.
+static MEASURED_PPM: AtomicI64 = AtomicI64::new(0);
+/// The correction applied to the period of every CPU's APIC timer, in parts per million.
+static CORRECTION_PPM: AtomicI32 = AtomicI32::new(0);
+/// The number of times `CORRECTION_PPM` was changed.
+static CORRECTIONS: AtomicU32 = AtomicU32::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO_U32: AtomicU32 = AtomicU32::new(0);
+/// The value of `CORRECTIONS` when each CPU last applied `CORRECTION_PPM` to its APIC timer.
+static APPLIED_CORRECTIONS: [AtomicU32; MAX_TRACKED_CPUS] = [ZERO_U32; MAX_TRACKED_CPUS];
+
+/// The drift of the bootstrap CPU's APIC timer relative to the RTC.
+///
+/// See [`drift()`].
+#[derive(Clone, Copy, Debug)]
+pub struct TimerDrift {
+    /// The drift measured over the current baseline, in parts per million,
+    /// after the current correction was applied.
+    ///
+    /// Positive values mean that ticks arrived too often, i.e., the tick clock runs fast.
+    pub measured_ppm: i64,
+    /// The number of seconds in the current baseline.
+    /// The resolution of `measured_ppm` improves as this grows.
+    pub baseline_secs: u64,
+    /// The correction applied to the period of every CPU's APIC timer, in parts per million.
+    ///
+    /// Positive values lengthen the period.
+    pub correction_ppm: i32,
+    /// The number of times the correction was changed since boot.
+    pub corrections: u32,
+    /// The number of seconds over which drift was measured since boot.
+    pub observed_secs: u64,
+}
+
+/// Returns the drift of the bootstrap CPU's APIC timer relative to the RTC,
+/// or `None` if none has been measured yet, e.g., because the system is in PIC mode.
+pub fn drift() -> Option<TimerDrift> {
+    let observed_secs = OBSERVED_SECS.load(Ordering::Relaxed);
+    if observed_secs == 0 {
+        return None;
+    }
+    Some(TimerDrift {
+        measured_ppm: MEASURED_PPM.load(Ordering::Relaxed),
+        baseline_secs: BASELINE_SECS.load(Ordering::Relaxed),
+        correction_ppm: CORRECTION_PPM.load(Ordering::Relaxed),
+        corrections: CORRECTIONS.load(Ordering::Relaxed),
+        observed_secs,
+    })
+}
+
+/// Records `ticks` APIC timer ticks on the current CPU, and applies any new correction to its APIC timer.
+///
+/// This must only be called from the timer interrupt handler when it is driven by the APIC timer.
+pub(crate) fn on_tick(ticks: u64) {
+    apply_correction();
+    if !apic::is_bootstrap_cpu() {
+        return;
+    }
+
+    let now = TICKS.fetch_add(ticks, Ordering::Relaxed) + ticks;
+    if now < NEXT_POLL_TICK.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(second) = rtc::try_read_seconds() else {
+        return;
+    };
+    let last = LAST_SECOND.swap(second, Ordering::Relaxed);
+    let was_polling = POLLING.swap(second == last, Ordering::Relaxed);
+    if second == last {
+        return;
+    }
+
+    // If the new second started before we began polling, or more than one second elapsed,
+    // we don't know when exactly it started, so it can't be used to measure drift.
+    // Instead, keep polling on every tick until the start of a second is observed.
+    let elapsed_secs = (second as u64 + 60).wrapping_sub(last as u64) % 60;
+    if last == NO_SECOND || !was_polling || elapsed_secs != 1 {
+        BASELINE_VALID.store(false, Ordering::Relaxed);
+        return;
+    }
+
+    let period_nanos = (boot_args::timeslice_period().as_nanos() as u64).max(1);
+    let ticks_per_sec = (1_000_000_000 / period_nanos).max(1);
+    // Start polling again a tenth of a second before the next second is expected to start,
+    // which tolerates a timer that runs up to 10% fast.
+    NEXT_POLL_TICK.store(now + ticks_per_sec - ticks_per_sec / 10, Ordering::Relaxed);
+    if !BASELINE_VALID.swap(true, Ordering::Relaxed) {
+        start_baseline(now);
+        return;
+    }
+
+    let baseline_secs = BASELINE_SECS.fetch_add(1, Ordering::Relaxed) + 1;
+    OBSERVED_SECS.fetch_add(1, Ordering::Relaxed);
+    let expected_nanos = baseline_secs as i128 * 1_000_000_000;
+    let actual_nanos = (now - BASELINE_TICKS.load(Ordering::Relaxed)) as i128 * period_nanos as i128;
+    let drift_ppm = ((actual_nanos - expected_nanos) * 1_000_000 / expected_nanos) as i64;
+    MEASURED_PPM.store(drift_ppm, Ordering::Relaxed);
+
+    if baseline_secs < MIN_BASELINE_SECS {
+        return;
+    }
+    // The start of each second is observed within one tick, so each measurement may be off by two ticks.
+    let resolution_ppm = (2 * period_nanos as i128 * 1_000_000 / expected_nanos) as i64;
+    if drift_ppm.abs() <= resolution_ppm {
+        return;
+    }
+    if drift_ppm.abs() > MAX_CORRECTION_PPM {
+        log::warn!("APIC timer drift of {} ppm relative to the RTC is implausible, ignoring it", drift_ppm);
+        start_baseline(now);
+        return;
+    }
+
+    // Ticks that arrive too often mean that the timer's period is too short, so lengthen it by the drift.
+    let correction = (CORRECTION_PPM.load(Ordering::Relaxed) as i64 + drift_ppm)
+        .clamp(-MAX_CORRECTION_PPM, MAX_CORRECTION_PPM) as i32;
+    log::info!("APIC timer drifted {} ppm from the RTC over {} s, correcting its period by {} ppm",
+        drift_ppm, baseline_secs, correction,
+    );
+    CORRECTION_PPM.store(correction, Ordering::Relaxed);
+    CORRECTIONS.fetch_add(1, Ordering::Release);
+    start_baseline(now);
+}
+
+/// Starts a new baseline at the start of the current RTC second, which was observed at tick `now`.
+fn start_baseline(now: u64) {
+    BASELINE_TICKS.store(now, Ordering::Relaxed);
+    BASELINE_SECS.store(0, Ordering::Relaxed);
+}
+
+/// Applies the current correction to this CPU's APIC timer, if it hasn't been applied yet.
+fn apply_correction() {
+    let Some(applied) = APPLIED_CORRECTIONS.get(cpu::current_cpu().value() as usize) else {
+        return;
+    };
+    let corrections = CORRECTIONS.load(Ordering::Acquire);
+    if applied.swap(corrections, Ordering::Relaxed) == corrections {
+        return;
+    }
+    if let Some(lapic) = apic::get_my_apic() {
+        lapic.write().set_timer_correction(CORRECTION_PPM.load(Ordering::Relaxed));
+    }
+}
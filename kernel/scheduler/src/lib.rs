@@ -4,6 +4,16 @@
 //! This crate also defines the timer interrupt handler used for preemptive
 //! task switching on each CPU. In [`init()`], it registers that handler
 //! with the [`interrupts`] subsystem.
+//! On x86_64 systems without a usable Local APIC, i.e., in PIC mode,
+//! that timer interrupt comes from the PIT instead of the Local APIC timer.
+//! That handler also detects timer ticks that were lost while interrupts were disabled;
+//! see the [`lost_ticks`] module.
+//! On x86_64, it also corrects the drift of each CPU's Local APIC timer relative to the RTC;
+//! see the [`apic_drift`] module.
+//! It also samples the number of runnable tasks for the system's load average;
+//! see the [`loadavg`] module and [`load_average()`].
+//! Once initialized, the wakeup latency of every task is measured;
+//! see the [`task::latency`] module.
 //!
 //! The actual task switching logic is implemented in the [`task`] crate.
 //! This crate re-exports that main [`schedule()`] function for convenience,
@@ -14,15 +24,33 @@
 #![no_std]
 #![cfg_attr(target_arch = "x86_64", feature(abi_x86_interrupt))]
 
+extern crate alloc;
+
+#[cfg(target_arch = "x86_64")]
+pub mod apic_drift;
+pub mod loadavg;
+pub mod lost_ticks;
+
+use core::sync::atomic::{AtomicU64, Ordering};
 use interrupts::{self, CPU_LOCAL_TIMER_IRQ, interrupt_handler, eoi, EoiBehaviour};
 
+pub use loadavg::{load_average, LoadAverage};
+
 /// Re-exports for convenience and legacy compatibility.
-pub use task::scheduler::{inherit_priority, priority, schedule, set_priority};
+pub use task::scheduler::{
+    inherit_priority, migrate_task, nice, offline_cpu, online_cpu, online_cpus, priority, schedule, set_priority,
+};
+
+/// The number of timer interrupts for preemptive task switching handled on all CPUs.
+static TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
 
 
 /// Initializes the scheduler on this system using the policy set at compiler time.
 ///
-/// Also registers a timer interrupt handler for preemptive scheduling.
+/// Also registers a timer interrupt handler for preemptive scheduling,
+/// and starts measuring wakeup latency, which requires a monotonic clock source.
+/// On x86_64 in PIC mode, that handler is registered for the PIT's interrupt instead,
+/// and the PIT is programmed to fire it once per timeslice.
 ///
 /// Currently, there is a single scheduler policy for the whole system.
 /// The policy is selected by specifying a Rust `cfg` value at build time, like so:
@@ -30,6 +58,22 @@ pub use task::scheduler::{inherit_priority, priority, schedule, set_priority};
 /// - `make THESEUS_CONFIG=epoch_scheduler`: epoch scheduler
 /// - `make THESEUS_CONFIG=priority_scheduler`: priority scheduler
 pub fn init() -> Result<(), &'static str> {
+    register_metrics()?;
+    task::latency::enable();
+
+    #[cfg(target_arch = "x86_64")]
+    if apic::INTERRUPT_CHIP.load() == apic::InterruptChip::PIC {
+        interrupts::register_isa_interrupt(
+            pit_clock::PIT_CHANNEL_0_ISA_IRQ,
+            timer_tick_handler,
+        ).map_err(|_handler| {
+            log::error!("BUG: the PIT interrupt was already registered to handler {_handler:#X}");
+            "BUG: the PIT interrupt was already registered to a handler"
+        })?;
+        let timeslice_frequency = kernel_config::time::timeslice_frequency(boot_args::timeslice_period());
+        return pit_clock::start_periodic(timeslice_frequency);
+    }
+
     #[cfg(target_arch = "x86_64")] {
         interrupts::register_interrupt(
             CPU_LOCAL_TIMER_IRQ,
@@ -52,41 +96,78 @@ interrupt_handler!(timer_tick_handler, _, _stack_frame, {
     #[cfg(target_arch = "aarch64")]
     generic_timer_aarch64::set_next_timer_interrupt(get_timeslice_ticks());
 
+    #[cfg(target_arch = "x86_64")]
+    let interrupted_ip = _stack_frame.instruction_pointer.as_u64();
+    #[cfg(not(target_arch = "x86_64"))]
+    let interrupted_ip = 0;
+    // Ticks that were coalesced into this one while interrupts were disabled still count,
+    // and any sleep deadlines that expired in the meantime are all handled below.
+    let lost = lost_ticks::on_tick(interrupted_ip);
+    let _ticks = TIMER_TICKS.fetch_add(1 + lost, Ordering::Relaxed);
+    #[cfg(target_arch = "x86_64")]
+    if apic::INTERRUPT_CHIP.load() != apic::InterruptChip::PIC {
+        apic_drift::on_tick(1 + lost);
+    }
     // tick count, only used for debugging
     if false {
-        use core::sync::atomic::{AtomicUsize, Ordering};
-        static CPU_LOCAL_TIMER_TICKS: AtomicUsize = AtomicUsize::new(0);
-        let _ticks = CPU_LOCAL_TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
         log::info!("(CPU {}) CPU-LOCAL TIMER HANDLER! TICKS = {}", cpu::current_cpu(), _ticks);
     }
 
+    loadavg::on_tick();
+
     // Inform the `sleep` crate that it should update its inner tick count
     // in order to unblock any tasks that are done sleeping.
     sleep::unblock_sleeping_tasks();
 
+    // Check for interrupt storms and update this CPU's system time once per measurement window.
+    #[cfg(target_arch = "x86_64")] {
+        interrupts::storm::tick();
+        interrupts::system_time::tick();
+    }
+
     // We must acknowledge the interrupt *before* the end of this handler
     // because we switch tasks here, which doesn't return.
-    eoi(CPU_LOCAL_TIMER_IRQ);
+    // Any timer interrupt that arrives before that task switch completes is coalesced into this one.
+    eoi(timer_irq());
 
-    schedule();
+    task::scheduler::schedule_from_timer();
 
     EoiBehaviour::HandlerSentEoi
 });
 
 
+/// Registers the scheduler's metrics, as reported in [`metrics::snapshot()`].
+fn register_metrics() -> Result<(), &'static str> {
+    metrics::register_counter("sched.timer_ticks", &TIMER_TICKS)?;
+    metrics::register_group("sched.lost_ticks", lost_ticks::report)?;
+    metrics::register_group("sched.latency", task::latency::report)?;
+    metrics::register_group("sched.load_average", loadavg::report)?;
+    metrics::register_gauge("sched.context_switches", task::context_switch_count)?;
+    metrics::register_gauge("sched.empty_runqueue_events", || {
+        cpu::cpus().filter_map(task::scheduler::empty_runqueue_count).sum()
+    })
+}
+
+/// Returns the interrupt number of the timer interrupt used for preemptive task switching.
+fn timer_irq() -> interrupts::InterruptNumber {
+    #[cfg(target_arch = "x86_64")]
+    if apic::INTERRUPT_CHIP.load() == apic::InterruptChip::PIC {
+        return interrupts::IRQ_BASE_OFFSET + pit_clock::PIT_CHANNEL_0_ISA_IRQ;
+    }
+    CPU_LOCAL_TIMER_IRQ
+}
+
 /// Returns the (cached) number of system timer ticks needed for the scheduling timeslice interval.
 ///
 /// This is only needed on aarch64 because it only effectively offers a one-shot timer;
 /// x86_64 can be configured once as a recurring periodic timer.
 #[cfg(target_arch = "aarch64")]
 fn get_timeslice_ticks() -> u64 {
-    use kernel_config::time::CONFIG_TIMESLICE_PERIOD_MICROSECONDS;
-
     static TIMESLICE_TICKS: spin::Once<u64> = spin::Once::new();
 
     *TIMESLICE_TICKS.call_once(|| {
-        let timeslice_femtosecs = (CONFIG_TIMESLICE_PERIOD_MICROSECONDS as u64) * 1_000_000_000;
-        let tick_period_femtosecs = generic_timer_aarch64::timer_period_femtoseconds();
-        timeslice_femtosecs / tick_period_femtosecs
+        let timeslice_femtosecs = boot_args::timeslice_period().as_nanos() * 1_000_000;
+        let tick_period_femtosecs = generic_timer_aarch64::timer_period_femtoseconds() as u128;
+        (timeslice_femtosecs / tick_period_femtosecs) as u64
     })
 }
@@ -0,0 +1,163 @@
+//! A Unix-style load average, i.e., exponentially-weighted moving averages
+//! of the number of runnable tasks over the last 1, 5, and 15 minutes.
+//!
+//! On each timer tick, [`on_tick()`] records the number of runnable tasks in that CPU's run queue,
+//! not counting its idle task. Once every [`SAMPLE_INTERVAL`], the first CPU to tick
+//! sums the most recent counts of all CPUs and folds that total into each average.
+//! Thus, the averages don't depend on the timer's frequency, and intervals that were missed entirely,
+//! e.g., because timer ticks were lost, are accounted for as if the total hadn't changed.
+//!
+//! To avoid floating point in the kernel, the averages are kept in fixed point
+//! with [`FSHIFT`] fractional bits, like on Linux.
+//! A CPU whose scheduler was locked when its timer ticked keeps its previous count,
+//! and a CPU that has stopped ticking, e.g., because it was taken offline, keeps its last count.
+
+use core::{fmt, sync::atomic::{AtomicU64, AtomicUsize, Ordering}};
+use time::{Duration, Instant, Monotonic};
+
+/// The maximum number of CPUs whose runnable tasks can be counted.
+const MAX_TRACKED_CPUS: usize = 256;
+
+/// The number of fractional bits of a fixed-point load value.
+pub const FSHIFT: u32 = 11;
+/// A load of `1.0` in fixed point.
+pub const FIXED_1: u64 = 1 << FSHIFT;
+
+/// How often the number of runnable tasks is folded into the averages.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The decay factor of each average per [`SAMPLE_INTERVAL`] in fixed point,
+/// i.e., `FIXED_1 / e^(SAMPLE_INTERVAL / period)` for periods of 1, 5, and 15 minutes.
+const DECAY: [u64; 3] = [1884, 2014, 2037];
+
+/// The most missed intervals that are accounted for at once, after which all averages have converged.
+const MAX_MISSED_INTERVALS: u64 = 720;
+
+/// The number of runnable tasks in each CPU's run queue as of its most recent timer tick.
+static RUNNABLE: [AtomicUsize; MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_TRACKED_CPUS]
+};
+
+/// The time of the next sample, in nanoseconds since [`Instant::ZERO`],
+/// or `0` if no CPU has ticked yet.
+static NEXT_SAMPLE_NANOS: AtomicU64 = AtomicU64::new(0);
+
+/// The 1, 5, and 15-minute averages in fixed point.
+static AVERAGES: [AtomicU64; 3] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; 3]
+};
+
+/// The load average of the system, in fixed point with [`FSHIFT`] fractional bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LoadAverage {
+    /// The average number of runnable tasks over the last minute.
+    pub one: u64,
+    /// The average number of runnable tasks over the last 5 minutes.
+    pub five: u64,
+    /// The average number of runnable tasks over the last 15 minutes.
+    pub fifteen: u64,
+}
+
+impl LoadAverage {
+    /// Returns the given fixed-point load value in hundredths, rounded to the nearest one,
+    /// e.g., `152` for a load of `1.52`.
+    pub const fn hundredths(load: u64) -> u64 {
+        (load * 100 + FIXED_1 / 2) >> FSHIFT
+    }
+}
+
+/// Formats the averages like `uptime` does, e.g., `0.52 0.31 0.10`.
+impl fmt::Display for LoadAverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, load) in [self.one, self.five, self.fifteen].into_iter().enumerate() {
+            let hundredths = Self::hundredths(load);
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}.{:02}", hundredths / 100, hundredths % 100)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the current load average of the system.
+pub fn load_average() -> LoadAverage {
+    LoadAverage {
+        one: AVERAGES[0].load(Ordering::Relaxed),
+        five: AVERAGES[1].load(Ordering::Relaxed),
+        fifteen: AVERAGES[2].load(Ordering::Relaxed),
+    }
+}
+
+/// Returns the total number of runnable tasks across all CPUs, as of each CPU's most recent timer tick.
+pub fn runnable_tasks() -> usize {
+    cpu::cpus()
+        .filter_map(|cpu| RUNNABLE.get(cpu.value() as usize))
+        .map(|runnable| runnable.load(Ordering::Relaxed))
+        .sum()
+}
+
+/// Records the number of runnable tasks on the current CPU,
+/// and folds the total of all CPUs into the averages if a sample is due.
+///
+/// This must be invoked from the timer interrupt handler.
+pub(crate) fn on_tick() {
+    let cpu = cpu::current_cpu().value() as usize;
+    if let (Some(runnable), Some(count)) = (RUNNABLE.get(cpu), task::scheduler::current_runnable_count()) {
+        runnable.store(count, Ordering::Relaxed);
+    }
+
+    let now = time::now::<Monotonic>().duration_since(Instant::ZERO).as_nanos() as u64;
+    let interval = SAMPLE_INTERVAL.as_nanos() as u64;
+    let next_sample = NEXT_SAMPLE_NANOS.load(Ordering::Relaxed);
+    if next_sample == 0 {
+        let _ = NEXT_SAMPLE_NANOS.compare_exchange(0, now + interval, Ordering::Relaxed, Ordering::Relaxed);
+        return;
+    }
+    if now < next_sample {
+        return;
+    }
+    // Every interval that has fully elapsed since the scheduled sample is folded in as well.
+    let intervals = (now - next_sample) / interval + 1;
+    // Only the CPU that advances the next sample time performs this sample.
+    if NEXT_SAMPLE_NANOS.compare_exchange(
+        next_sample,
+        next_sample + intervals * interval,
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+    ).is_err() {
+        return;
+    }
+
+    let active = runnable_tasks() as u64 * FIXED_1;
+    for (average, decay) in AVERAGES.iter().zip(DECAY) {
+        let mut load = average.load(Ordering::Relaxed);
+        for _ in 0..intervals.min(MAX_MISSED_INTERVALS) {
+            load = fold(load, decay, active);
+        }
+        average.store(load, Ordering::Relaxed);
+    }
+}
+
+/// Folds the fixed-point number of `active` tasks into the given fixed-point `load`,
+/// which decays by the given fixed-point factor, rounding towards the number of active tasks.
+const fn fold(load: u64, decay: u64, active: u64) -> u64 {
+    let mut new_load = load * decay + active * (FIXED_1 - decay);
+    if active >= load {
+        new_load += FIXED_1 - 1;
+    }
+    new_load / FIXED_1
+}
+
+/// Reports the 1, 5, and 15-minute load averages in hundredths as `1min`, `5min`, and `15min`,
+/// for use with [`metrics::register_group()`].
+pub(crate) fn report(report: &mut dyn FnMut(&str, u64)) {
+    let load = load_average();
+    report("1min", LoadAverage::hundredths(load.one));
+    report("5min", LoadAverage::hundredths(load.five));
+    report("15min", LoadAverage::hundredths(load.fifteen));
+}
@@ -0,0 +1,81 @@
+//! Detection and accounting of timer ticks lost while a CPU had interrupts disabled.
+//!
+//! If a CPU keeps interrupts disabled for longer than one timeslice period,
+//! e.g., during a long ATA PIO transfer, a TLB shootdown wait, or while frozen by the GDB stub,
+//! the timer interrupts that should have fired in the meantime are coalesced into a single one.
+//! On each timer tick, [`on_tick()`] therefore compares the time elapsed since that CPU's
+//! previous tick against the timeslice period, and if more than 1.5 periods have elapsed,
+//! it counts the missing ticks as lost on that CPU.
+//!
+//! For each CPU, this also records the instruction pointer at which the late tick was delivered,
+//! which is usually just past the end of the interrupt-disabled region responsible for it.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use cpu::CpuId;
+use time::{Instant, Monotonic};
+
+/// The maximum number of CPUs whose lost ticks can be tracked.
+const MAX_TRACKED_CPUS: usize = 256;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO_U64: AtomicU64 = AtomicU64::new(0);
+
+/// The time of each CPU's previous timer tick, in nanoseconds since [`Instant::ZERO`],
+/// or `0` if that CPU hasn't ticked yet.
+static LAST_TICK_NANOS: [AtomicU64; MAX_TRACKED_CPUS] = [ZERO_U64; MAX_TRACKED_CPUS];
+/// The total number of timer ticks lost on each CPU.
+static LOST_TICKS: [AtomicU64; MAX_TRACKED_CPUS] = [ZERO_U64; MAX_TRACKED_CPUS];
+/// The instruction pointer at which the most recent late tick was delivered on each CPU,
+/// or `0` if none was delivered yet or it couldn't be determined.
+static LAST_LATE_TICK_IP: [AtomicU64; MAX_TRACKED_CPUS] = [ZERO_U64; MAX_TRACKED_CPUS];
+
+/// Records a timer tick on the current CPU, which interrupted the code at `interrupted_ip`.
+///
+/// Returns the number of ticks that were lost since this CPU's previous tick, if any.
+pub(crate) fn on_tick(interrupted_ip: u64) -> u64 {
+    let cpu = cpu::current_cpu().value() as usize;
+    let (Some(last_tick), Some(lost_ticks)) = (LAST_TICK_NANOS.get(cpu), LOST_TICKS.get(cpu)) else {
+        return 0;
+    };
+    let now = time::now::<Monotonic>().duration_since(Instant::ZERO).as_nanos() as u64;
+    let previous = last_tick.swap(now, Ordering::Relaxed);
+    if previous == 0 {
+        return 0;
+    }
+
+    let period = (boot_args::timeslice_period().as_nanos() as u64).max(1);
+    let elapsed = now.saturating_sub(previous);
+    // Timer interrupts jitter a bit, so a tick is only lost once 1.5 periods have elapsed.
+    if elapsed.saturating_mul(2) <= period.saturating_mul(3) {
+        return 0;
+    }
+    // Round to the nearest number of periods, minus the tick that did arrive.
+    let lost = (elapsed + period / 2) / period - 1;
+    lost_ticks.fetch_add(lost, Ordering::Relaxed);
+    LAST_LATE_TICK_IP[cpu].store(interrupted_ip, Ordering::Relaxed);
+    lost
+}
+
+/// Returns the total number of timer ticks lost on the given CPU.
+pub fn lost_ticks(cpu: CpuId) -> u64 {
+    LOST_TICKS.get(cpu.value() as usize).map_or(0, |lost| lost.load(Ordering::Relaxed))
+}
+
+/// Returns the instruction pointer at which the most recent late timer tick
+/// was delivered on the given CPU, if any.
+///
+/// This is usually just past the end of the interrupt-disabled region that delayed the tick.
+pub fn last_late_tick_ip(cpu: CpuId) -> Option<u64> {
+    LAST_LATE_TICK_IP.get(cpu.value() as usize)
+        .map(|ip| ip.load(Ordering::Relaxed))
+        .filter(|ip| *ip != 0)
+}
+
+/// Reports the total number of lost ticks on each CPU as `cpu<N>`,
+/// for use with [`metrics::register_group()`].
+pub(crate) fn report(report: &mut dyn FnMut(&str, u64)) {
+    for cpu in cpu::cpus() {
+        let name = alloc::format!("cpu{}", cpu.value());
+        report(&name, lost_ticks(cpu));
+    }
+}
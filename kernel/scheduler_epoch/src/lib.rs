@@ -135,6 +135,13 @@ impl task::scheduler::Scheduler for Scheduler {
         self.queue.len()
     }
 
+    fn runnable_count(&self) -> usize {
+        self.queue
+            .iter()
+            .filter(|epoch_task| epoch_task.task.is_runnable() && !epoch_task.task.is_an_idle_task)
+            .count()
+    }
+
     fn remove(&mut self, task: &TaskRef) -> bool {
         let mut task_index = None;
         for (i, t) in self.queue.iter().enumerate() {
@@ -58,6 +58,10 @@ impl task::scheduler::Scheduler for Scheduler {
         self.queue.len()
     }
 
+    fn runnable_count(&self) -> usize {
+        self.queue.iter().filter(|priority_task| priority_task.task.is_runnable()).count()
+    }
+
     fn remove(&mut self, task: &TaskRef) -> bool {
         let old_len = self.queue.len();
         self.queue
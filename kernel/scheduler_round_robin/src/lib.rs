@@ -1,6 +1,10 @@
 //! This crate picks the next task in round robin fashion.
 //! Each time the task at the front of the queue is picked.
 //! This task is then moved to the back of the queue.
+//!
+//! Tasks with a positive nice value are passed over a proportional number of times
+//! before being picked: a task with a nice value of `n` is picked at most once
+//! for every `n + 1` times it is eligible, as tracked by a per-task deficit counter.
 
 #![no_std]
 
@@ -12,7 +16,7 @@ use task::TaskRef;
 
 pub struct Scheduler {
     idle_task: TaskRef,
-    queue: VecDeque<TaskRef>,
+    queue: VecDeque<RoundRobinTaskRef>,
 }
 
 impl Scheduler {
@@ -24,16 +28,37 @@ impl Scheduler {
     }
 }
 
+/// A task on the round robin run queue, along with its deficit counter.
+struct RoundRobinTaskRef {
+    task: TaskRef,
+    /// The number of times this task has been passed over due to its nice value
+    /// since it was last picked.
+    deficit: u8,
+}
+
 impl task::scheduler::Scheduler for Scheduler {
     fn next(&mut self) -> TaskRef {
-        if let Some((task_index, _)) = self
-            .queue
-            .iter()
-            .enumerate()
-            .find(|(_, task)| task.is_runnable())
-        {
-            let task = self.queue.swap_remove_front(task_index).unwrap();
-            self.queue.push_back(task.clone());
+        let mut first_runnable = None;
+        let mut chosen = None;
+        for (i, entry) in self.queue.iter_mut().enumerate() {
+            if !entry.task.is_runnable() {
+                continue;
+            }
+            first_runnable.get_or_insert(i);
+            if entry.deficit >= entry.task.nice().max(0) as u8 {
+                chosen = Some(i);
+                break;
+            }
+            entry.deficit += 1;
+        }
+
+        // If every runnable task was passed over, pick the first one anyway
+        // rather than idling while there is work to do.
+        if let Some(task_index) = chosen.or(first_runnable) {
+            let mut entry = self.queue.swap_remove_front(task_index).unwrap();
+            entry.deficit = 0;
+            let task = entry.task.clone();
+            self.queue.push_back(entry);
             task
         } else {
             self.idle_task.clone()
@@ -44,14 +69,18 @@ impl task::scheduler::Scheduler for Scheduler {
         self.queue.len()
     }
 
+    fn runnable_count(&self) -> usize {
+        self.queue.iter().filter(|entry| entry.task.is_runnable()).count()
+    }
+
     fn add(&mut self, task: TaskRef) {
-        self.queue.push_back(task);
+        self.queue.push_back(RoundRobinTaskRef { task, deficit: 0 });
     }
 
     fn remove(&mut self, task: &TaskRef) -> bool {
         let mut task_index = None;
         for (i, t) in self.queue.iter().enumerate() {
-            if t == task {
+            if &t.task == task {
                 task_index = Some(i);
                 break;
             }
@@ -70,10 +99,10 @@ impl task::scheduler::Scheduler for Scheduler {
     }
 
     fn drain(&mut self) -> Box<dyn Iterator<Item = TaskRef> + '_> {
-        Box::new(self.queue.drain(..))
+        Box::new(self.queue.drain(..).map(|entry| entry.task))
     }
 
     fn tasks(&self) -> Vec<TaskRef> {
-        self.queue.clone().into()
+        self.queue.iter().map(|entry| entry.task.clone()).collect()
     }
 }
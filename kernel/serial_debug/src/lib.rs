@@ -0,0 +1,296 @@
+//! A tiny command interface on a serial port, which allows a headless system, e.g., in CI,
+//! to be inspected without a keyboard, a framebuffer, or a shell.
+//!
+//! When enabled via the `serialdebug=comN` boot option, [`start()`] claims that serial port's
+//! input and spawns a task that reads one line at a time and runs it as a command,
+//! which is a single word optionally followed by arguments:
+//! * `help`: lists these commands.
+//! * `tasks`: lists every task's ID, runstate, current CPU, and name.
+//! * `mem`: shows the number of free physical frames and the tasks using the most memory.
+//!   `mem frag` instead shows how fragmented the free physical memory is.
+//! * `irqstats`: shows the interrupt metrics and each CPU's share of time spent handling interrupts.
+//! * `backtrace`: prints a backtrace of the command task itself, which checks that unwinding works.
+//! * `reboot`: flushes devices in dependency order, then resets the machine.
+//!
+//! Because this serial port's input is claimed, the `console` crate won't start a shell on it.
+//!
+//! Input is accumulated in a fixed-size line buffer and each command writes its output
+//! directly to the serial port, so the command loop itself doesn't allocate.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, sync::Arc};
+use core::fmt::{self, Write};
+use log::info;
+use serial_port::{get_serial_port, DataChunk, SerialPort, SerialPortAddress};
+use sync_channel::Receiver;
+use sync_irq::IrqSafeMutex;
+use task::JoinableTaskRef;
+
+/// The maximum length of a command line; further input on that line is discarded.
+const MAX_LINE_LEN: usize = 64;
+/// The prompt printed before each command line.
+const PROMPT: &str = "debug> ";
+/// The number of tasks listed by the `mem` command.
+const TOP_MEMORY_CONSUMERS: usize = 5;
+/// The maximum number of stack frames printed by the `backtrace` command.
+#[cfg(target_arch = "x86_64")]
+const MAX_BACKTRACE_FRAMES: usize = 64;
+
+/// The function signature of a command, which writes its output to the given serial port.
+///
+/// The second argument is the rest of the command line after the command's name, with surrounding whitespace trimmed.
+type Command = fn(&mut Output, &str) -> fmt::Result;
+
+/// The name, function, and description of each command.
+const COMMANDS: &[(&str, Command, &str)] = &[
+    ("help",      help,      "list these commands"),
+    ("tasks",     tasks,     "list all tasks"),
+    ("mem",       mem,       "show free memory and the tasks using the most memory; `mem frag` shows fragmentation"),
+    ("irqstats",  irqstats,  "show interrupt statistics"),
+    ("backtrace", backtrace, "print a backtrace of this command task"),
+    ("reboot",    reboot,    "flush devices and reset the machine"),
+];
+
+
+/// Starts the serial debug command interface on the serial port
+/// given by the `serialdebug` boot option, if any.
+///
+/// Returns the newly-spawned command task, or `None` if the interface isn't enabled.
+pub fn start() -> Result<Option<JoinableTaskRef>, &'static str> {
+    let address = match boot_args::boot_args().serial_debug {
+        None => return Ok(None),
+        Some(1) => SerialPortAddress::COM1,
+        Some(2) => SerialPortAddress::COM2,
+        Some(3) => SerialPortAddress::COM3,
+        Some(_) => SerialPortAddress::COM4,
+    };
+    start_on(address).map(Some)
+}
+
+/// Starts the serial debug command interface on the given serial port.
+///
+/// That serial port must already be initialized and mustn't have a data sender yet,
+/// i.e., no other task may be receiving its input, such as a console's shell.
+pub fn start_on(address: SerialPortAddress) -> Result<JoinableTaskRef, &'static str> {
+    let port = get_serial_port(address)
+        .ok_or("the serial debug port was not initialized, e.g., because gdbstub took it")?
+        .clone();
+    let (sender, receiver) = sync_channel::new_channel(16);
+    port.lock()
+        .set_data_sender(sender)
+        .map_err(|_| "the serial debug port's input was already claimed")?;
+
+    info!("Starting the serial debug command interface on {:?}", address);
+    spawn::new_task_builder(command_loop, (port, receiver))
+        .name(format!("serial_debug_{address:?}"))
+        .spawn()
+}
+
+/// Writes to a serial port, locking it only for each individual write
+/// such that other writers, e.g., the logger on a shared port, aren't starved.
+struct Output(Arc<IrqSafeMutex<SerialPort>>);
+
+impl Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.lock().write_str(s)
+    }
+}
+
+/// The entry point for the command task, which reads and runs one command line at a time.
+fn command_loop(
+    (port, receiver): (Arc<IrqSafeMutex<SerialPort>>, Receiver<DataChunk>),
+) -> Result<(), &'static str> {
+    let mut out = Output(port);
+    let mut line = [0u8; MAX_LINE_LEN];
+    let mut len = 0;
+    let mut last_byte = 0;
+    // Output errors are ignored throughout, as there's nowhere else to report them.
+    let _ = write!(out, "\nTheseus serial debug interface; type `help` for a list of commands.\n{PROMPT}");
+
+    loop {
+        let DataChunk { data, len: chunk_len } = receiver.receive()
+            .map_err(|_| "couldn't receive input from the serial debug port")?;
+
+        for &byte in &data[..chunk_len as usize] {
+            match byte {
+                // Terminals may send either `\r`, `\n`, or both to end a line.
+                b'\n' if last_byte == b'\r' => { }
+                b'\r' | b'\n' => {
+                    let _ = out.write_str("\n");
+                    // Only printable ASCII characters are ever stored in the line buffer.
+                    let command = core::str::from_utf8(&line[..len]).unwrap_or_default();
+                    let _ = run_command(&mut out, command.trim());
+                    len = 0;
+                    let _ = out.write_str(PROMPT);
+                }
+                // Backspace or delete
+                0x08 | 0x7F => if len > 0 {
+                    len -= 1;
+                    let _ = out.write_str("\x08 \x08");
+                }
+                b' ' ..= b'~' if len < MAX_LINE_LEN => {
+                    line[len] = byte;
+                    len += 1;
+                    let _ = out.write_char(byte as char);
+                }
+                _ => { }
+            }
+            last_byte = byte;
+        }
+    }
+}
+
+/// Runs the given `command` line, which does nothing if it's empty.
+fn run_command(out: &mut Output, command: &str) -> fmt::Result {
+    if command.is_empty() {
+        return Ok(());
+    }
+    let (command, args) = command.split_once(' ').unwrap_or((command, ""));
+    match COMMANDS.iter().find(|(name, ..)| *name == command) {
+        Some((_, func, _)) => func(out, args.trim()),
+        None => writeln!(out, "unknown command {:?}; type `help` for a list of commands", command),
+    }
+}
+
+fn help(out: &mut Output, _args: &str) -> fmt::Result {
+    for (name, _, description) in COMMANDS {
+        writeln!(out, "  {:<10} {}", name, description)?;
+    }
+    Ok(())
+}
+
+fn tasks(out: &mut Output, _args: &str) -> fmt::Result {
+    writeln!(out, "{:<6} {:<12} {:<4} NAME", "ID", "RUNSTATE", "CPU")?;
+    for (id, task) in task::all_tasks() {
+        let Some(task) = task.upgrade() else { continue };
+        // The runstate and CPU are formatted first such that their widths are respected.
+        let runstate = format!("{:?}", task.runstate());
+        let cpu = task.running_on_cpu().map(|cpu| format!("{cpu}")).unwrap_or_else(|| "-".into());
+        writeln!(out, "{:<6} {:<12} {:<4} {}", id, runstate, cpu, task.name)?;
+    }
+    Ok(())
+}
+
+fn mem(out: &mut Output, args: &str) -> fmt::Result {
+    match args {
+        "" => { }
+        "frag" => return mem_frag(out),
+        _ => return writeln!(out, "usage: mem [frag]"),
+    }
+    let free_frames = memory::free_frame_count();
+    writeln!(out, "free frames: {} ({} KiB)", free_frames, free_frames * memory::PAGE_SIZE / 1024)?;
+    writeln!(out, "top {} tasks by memory usage:", TOP_MEMORY_CONSUMERS)?;
+    for (task, usage) in task::top_memory_consumers(TOP_MEMORY_CONSUMERS) {
+        writeln!(out, "  {:<6} {:>10} KiB  {}", task.id, usage / 1024, task.name)?;
+    }
+    Ok(())
+}
+
+/// Shows how fragmented the free general-purpose physical memory is,
+/// including a histogram of free extents by their size in frames.
+fn mem_frag(out: &mut Output) -> fmt::Result {
+    let report = memory::fragmentation_report();
+    writeln!(out, "{}", report)?;
+    writeln!(out, "{:>12}  EXTENTS", "FRAMES")?;
+    for (class, &count) in report.extents_by_size.iter().enumerate().filter(|(_, &count)| count != 0) {
+        if class == memory::FRAGMENTATION_SIZE_CLASSES - 1 {
+            writeln!(out, "{:>12}  {}", format!(">= {}", 1usize << class), count)?;
+        } else {
+            writeln!(out, "{:>12}  {}", format!("{}-{}", 1usize << class, (2usize << class) - 1), count)?;
+        }
+    }
+    Ok(())
+}
+
+fn irqstats(out: &mut Output, _args: &str) -> fmt::Result {
+    for metric in metrics::snapshot().lines().filter(|l| l.starts_with("irq.")) {
+        writeln!(out, "{}", metric)?;
+    }
+    #[cfg(target_arch = "x86_64")]
+    for cpu in cpu::cpus() {
+        let Some(time) = interrupts::system_time::system_time(cpu) else { continue };
+        writeln!(out, "cpu {}: {}.{}% in interrupt handlers, {}.{}% in deferred interrupt tasks, {} throttles",
+            cpu,
+            time.irq_permille / 10, time.irq_permille % 10,
+            time.deferred_permille / 10, time.deferred_permille % 10,
+            time.throttles,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn backtrace(out: &mut Output, _args: &str) -> fmt::Result {
+    let mut result = Ok(());
+    let trace_result = stack_trace::stack_trace(
+        &mut |stack_frame, stack_frame_iter| {
+            let address = stack_frame.call_site_address();
+            let symbol_offset = stack_frame_iter.namespace().get_section_containing_address(
+                memory::VirtualAddress::new_canonical(address as usize),
+                false,
+            );
+            result = match symbol_offset {
+                Some((section, offset)) => writeln!(out, "  {:>#018X} in {} + {:#X}", address, &*section.name, offset),
+                None => writeln!(out, "  {:>#018X} in ??", address),
+            };
+            result.is_ok()
+        },
+        Some(MAX_BACKTRACE_FRAMES),
+    );
+    result?;
+    if let Err(e) = trace_result {
+        writeln!(out, "backtrace stopped early: {}", e)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn backtrace(out: &mut Output, _args: &str) -> fmt::Result {
+    writeln!(out, "backtraces aren't yet supported on this architecture")
+}
+
+fn reboot(out: &mut Output, _args: &str) -> fmt::Result {
+    writeln!(out, "flushing devices...")?;
+    for (name, outcome) in device_manager::registry::shutdown_all() {
+        if outcome != device_manager::registry::FlushOutcome::Flushed {
+            writeln!(out, "  couldn't flush {}: {:?}", name, outcome)?;
+        }
+    }
+    writeln!(out, "rebooting...")?;
+    reset_machine();
+    writeln!(out, "couldn't reset the machine")
+}
+
+/// Resets the machine by pulsing the reset line via the PS/2 controller,
+/// falling back to a triple fault if that doesn't work.
+#[cfg(target_arch = "x86_64")]
+fn reset_machine() {
+    use core::time::Duration;
+    use x86_64::{instructions::tables::lidt, structures::DescriptorTablePointer, VirtAddr};
+
+    /// How long to wait for the PS/2 controller to reset the machine.
+    const RESET_TIMEOUT: Duration = Duration::from_millis(100);
+
+    if let Some(controller) = ps2::controller() {
+        controller.write_command(ps2::HostToControllerCommand::PulseResetLine);
+        let start = time::Instant::now();
+        while start.elapsed() < RESET_TIMEOUT {
+            core::hint::spin_loop();
+        }
+    }
+
+    // With an empty IDT, any exception becomes a triple fault, which resets the machine.
+    let empty_idt = DescriptorTablePointer { limit: 0, base: VirtAddr::zero() };
+    // SAFE: this intentionally brings down the whole system.
+    unsafe {
+        lidt(&empty_idt);
+        core::arch::asm!("int3");
+    }
+}
+
+/// Resetting the machine isn't yet supported on this architecture.
+#[cfg(not(target_arch = "x86_64"))]
+fn reset_machine() { }
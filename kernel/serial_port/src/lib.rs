@@ -24,6 +24,10 @@ use deferred_interrupt_tasks::InterruptRegistrationError;
 pub use serial_port_basic::{
     SerialPortAddress,
     SerialPortInterruptEvent,
+    DataBits,
+    Parity,
+    StopBits,
+    FifoTriggerLevel,
     SerialPort as SerialPortBasic,
     take_serial_port as take_serial_port_basic,
 };
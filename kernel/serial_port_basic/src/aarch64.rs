@@ -96,7 +96,9 @@ impl SerialPort {
     ///
     /// This writes the byte directly with no special cases, e.g., new lines.
     pub fn out_byte(&mut self, byte: u8) {
-        while !self.ready_to_transmit() { }
+        while !self.ready_to_transmit() {
+            core::hint::spin_loop();
+        }
         self.inner.as_mut().unwrap().write_byte(byte);
     }
 
@@ -107,3 +107,46 @@ pub enum SerialPortInterruptEvent {
     ErrorOrBreak     = 1 << 2,
     StatusChange     = 1 << 3,
 }
+
+/// The number of data bits in each character sent or received on a serial port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DataBits {
+    Five  = 0b00,
+    Six   = 0b01,
+    Seven = 0b10,
+    Eight = 0b11,
+}
+
+/// The parity bit sent after each character on a serial port, if any.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Parity {
+    None  = 0b000,
+    Odd   = 0b001,
+    Even  = 0b011,
+    /// The parity bit is always 1.
+    Mark  = 0b101,
+    /// The parity bit is always 0.
+    Space = 0b111,
+}
+
+/// The number of stop bits sent after each character on a serial port.
+///
+/// With [`DataBits::Five`], `Two` actually results in one and a half stop bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum StopBits {
+    One = 0,
+    Two = 1,
+}
+
+/// The number of bytes in a serial port's receive FIFO that triggers a "data received" interrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FifoTriggerLevel {
+    Bytes1  = 0b00 << 6,
+    Bytes4  = 0b01 << 6,
+    Bytes8  = 0b10 << 6,
+    Bytes14 = 0b11 << 6,
+}
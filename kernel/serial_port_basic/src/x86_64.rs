@@ -1,5 +1,5 @@
 use core::{convert::TryFrom, fmt, str::FromStr};
-use super::{TriState, SerialPortInterruptEvent};
+use super::{TriState, SerialPortInterruptEvent, DataBits, Parity, StopBits, FifoTriggerLevel};
 use port_io::Port;
 
 /// The base port I/O addresses for COM serial ports.
@@ -49,6 +49,23 @@ impl TryFrom<u16> for SerialPortAddress {
     }
 }
 
+/// The highest baud rate supported by a standard serial port,
+/// which is the rate that a baud rate divisor of 1 results in.
+pub const MAX_BAUD_RATE: u32 = 115200;
+
+/// The baud rate that a serial port is initialized with by [`SerialPort::new()`].
+pub const DEFAULT_BAUD_RATE: u32 = 38400;
+
+/// The line control register bit that exposes the baud rate divisor latch (DLAB)
+/// through the data and interrupt enable registers.
+const LINE_CONTROL_DLAB: u8 = 1 << 7;
+/// The line control register bit that holds the transmit line low (a break condition).
+const LINE_CONTROL_BREAK: u8 = 1 << 6;
+/// The FIFO control register bits that enable the FIFOs and clear both the receive and transmit FIFOs.
+const FIFO_CONTROL_ENABLE_AND_CLEAR: u8 = 0x07;
+/// The line status register bit that is set once the transmit FIFO and shift register are both empty.
+const LINE_STATUS_TRANSMITTER_EMPTY: u8 = 1 << 6;
+
 // The E9 port can be used with the Bochs emulator for extra debugging info.
 // const PORT_E9: u16 = 0xE9; // for use with bochs
 // static E9: Port<u8> = Port::new(PORT_E9); // see Bochs's port E9 hack
@@ -108,7 +125,7 @@ impl SerialPort {
     /// Note: if you are experiencing problems with serial port behavior,
     /// try enabling the loopback test part of this function to see if that passes.
     pub fn new(base_port: u16) -> SerialPort {
-        let serial = SerialPort {
+        let mut serial = SerialPort {
             data:                       Port::new(base_port    ),
             interrupt_enable:           Port::new(base_port + 1),
             interrupt_id_fifo_control:  Port::new(base_port + 2),
@@ -119,30 +136,20 @@ impl SerialPort {
             _scratch:                   Port::new(base_port + 7),
         };
 
+        // Before doing anything, disable interrupts for this serial port.
         // SAFE: we are just accessing this serial port's registers.
-        unsafe {
-            // Before doing anything, disable interrupts for this serial port.
-            serial.interrupt_enable.write(0x00);
-
-            // Enter DLAB mode so we can set the baud rate divisor
-            serial.line_control.write(0x80);
-            // Set baud rate to 38400, which requires a divisor value of `3`. 
-            // To do this, we enter DLAB mode (to se the baud rate divisor),
-            // the write the low byte of the divisor to the data register (DLL)
-            // and the high byte to the interrupt enable register (DLH).
-            serial.data.write(0x03);
-            serial.interrupt_enable.write(0x00);
-
-            // Exit DLAB mode. At the same time, set the data word length to 8 bits,
-            // also specifying no parity and one stop bit. This is known as "8N1" mode.
-            serial.line_control.write(0x03);
-
-            // Enable the FIFO queues (buffers in hardware) and clear both the transmit and receive queues.
-            // Also, set an interrupt threshold of 14 (0xC) bytes, which is the maximum value.
-            // Note that serial ports will fire an interrupt if there is a "small delay"
-            // between bytes, so we don't always have to wait for 14 entire bytes to arrive.
-            serial.interrupt_id_fifo_control.write(0xC7);
+        unsafe { serial.interrupt_enable.write(0x00); }
+
+        serial.set_line_control(DataBits::Eight, Parity::None, StopBits::One);
+        // Cannot fail, as the default baud rate is a valid one.
+        let _ = serial.set_baud(DEFAULT_BAUD_RATE);
+        // Set an interrupt threshold of 14 bytes, which is the maximum value.
+        // Note that serial ports will fire an interrupt if there is a "small delay"
+        // between bytes, so we don't always have to wait for 14 entire bytes to arrive.
+        serial.set_fifo(Some(FifoTriggerLevel::Bytes14));
 
+        // SAFE: we are just accessing this serial port's registers.
+        unsafe {
             // Mark the data terminal as ready, signal request to send
             // and enable auxilliary output #2 (used as interrupt line for CPU)
             serial.modem_control.write(0x0B);
@@ -174,6 +181,84 @@ impl SerialPort {
 
     }
 
+    /// Sets this serial port's baud rate, i.e., the number of bits it sends and receives per second.
+    ///
+    /// The given `rate` must evenly divide [`MAX_BAUD_RATE`] by a 16-bit divisor,
+    /// as the baud rate is configured by a divisor of that maximum rate,
+    /// e.g., 9600, 19200, 38400, 57600, or 115200.
+    ///
+    /// This waits for all pending bytes to be transmitted before changing the baud rate,
+    /// such that they aren't garbled.
+    pub fn set_baud(&mut self, rate: u32) -> Result<(), &'static str> {
+        if rate == 0 || MAX_BAUD_RATE % rate != 0 {
+            return Err("the baud rate must evenly divide the maximum baud rate of 115200");
+        }
+        let divisor = u16::try_from(MAX_BAUD_RATE / rate)
+            .map_err(|_| "the baud rate is too low to be configured")?;
+        self.wait_for_transmitter_empty();
+        let line_control = self.line_control.read();
+        // SAFE: we are just accessing this serial port's registers.
+        // While DLAB is set, the data and interrupt enable registers hold
+        // the low and high bytes of the divisor, respectively.
+        unsafe {
+            self.line_control.write(line_control | LINE_CONTROL_DLAB);
+            self.data.write(divisor as u8);
+            self.interrupt_enable.write((divisor >> 8) as u8);
+            self.line_control.write(line_control & !LINE_CONTROL_DLAB);
+        }
+        Ok(())
+    }
+
+    /// Returns this serial port's current baud rate.
+    pub fn baud(&mut self) -> u32 {
+        let line_control = self.line_control.read();
+        // SAFE: we are just accessing this serial port's registers; see `set_baud()`.
+        let divisor = unsafe {
+            self.line_control.write(line_control | LINE_CONTROL_DLAB);
+            let divisor = u16::from_le_bytes([self.data.read(), self.interrupt_enable.read()]);
+            self.line_control.write(line_control & !LINE_CONTROL_DLAB);
+            divisor
+        };
+        MAX_BAUD_RATE / (divisor.max(1) as u32)
+    }
+
+    /// Sets the format of each character sent and received by this serial port,
+    /// e.g., `(DataBits::Eight, Parity::None, StopBits::One)` for "8N1" mode.
+    ///
+    /// This waits for all pending bytes to be transmitted before changing the format,
+    /// such that they aren't garbled.
+    pub fn set_line_control(&mut self, data_bits: DataBits, parity: Parity, stop_bits: StopBits) {
+        self.wait_for_transmitter_empty();
+        let break_condition = self.line_control.read() & LINE_CONTROL_BREAK;
+        let line_control = break_condition
+            | (parity as u8) << 3
+            | (stop_bits as u8) << 2
+            | data_bits as u8;
+        // SAFE: we are just accessing this serial port's registers.
+        unsafe { self.line_control.write(line_control); }
+    }
+
+    /// Enables this serial port's receive and transmit FIFOs with the given receive `trigger_level`,
+    /// or disables them if `None`, such that each byte is received and transmitted one at a time.
+    ///
+    /// Either way, this clears both FIFOs, dropping any bytes that were received but not yet read.
+    pub fn set_fifo(&mut self, trigger_level: Option<FifoTriggerLevel>) {
+        self.wait_for_transmitter_empty();
+        let fifo_control = match trigger_level {
+            Some(level) => FIFO_CONTROL_ENABLE_AND_CLEAR | level as u8,
+            None => 0,
+        };
+        // SAFE: we are just accessing this serial port's registers.
+        unsafe { self.interrupt_id_fifo_control.write(fifo_control); }
+    }
+
+    /// Blocks until every byte written to this serial port has been fully transmitted.
+    fn wait_for_transmitter_empty(&self) {
+        while self.line_status.read() & LINE_STATUS_TRANSMITTER_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+    }
+
     /// Enable or disable interrupts on this serial port for various events.
     pub fn enable_interrupt(&mut self, event: SerialPortInterruptEvent, enable: bool) {
         let existing = self.interrupt_enable.read();
@@ -212,7 +297,9 @@ impl SerialPort {
     ///
     /// This writes the byte directly with no special cases, e.g., new lines.
     pub fn out_byte(&mut self, byte: u8) {
-        while !self.ready_to_transmit() { }
+        while !self.ready_to_transmit() {
+            core::hint::spin_loop();
+        }
 
         // SAFE: we're just writing to the serial port, which has already been initialized.
         unsafe { 
@@ -232,7 +319,9 @@ impl SerialPort {
 
     /// Read one byte from the serial port, blocking until data is available.
     pub fn in_byte(&mut self) -> u8 {
-        while !self.data_available() { }
+        while !self.data_available() {
+            core::hint::spin_loop();
+        }
         self.data.read() 
     }
 
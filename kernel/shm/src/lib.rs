@@ -0,0 +1,197 @@
+//! Named shared memory segments, which let tasks exchange bulk data without copying it.
+//!
+//! A segment is created with [`create()`], which allocates and zeroes its frames
+//! and registers it under a name. Any task can then [`map()`] that segment by name,
+//! which maps its frames into a new range of pages and returns a [`SharedMapping`].
+//!
+//! Each segment owns its frames, which are mapped as non-exclusive by every mapping,
+//! such that unmapping one never deallocates them.
+//! Instead, every mapping holds a reference to its segment, and the segment's frames
+//! are deallocated once it has been [`remove()`]d and its last mapping was dropped,
+//! e.g., when the task that owned the mapping exited.
+//! Each mapping broadcasts a TLB shootdown when it's dropped,
+//! so no CPU can still access a segment's frames after they're deallocated,
+//! even if the segment was removed while it was mapped elsewhere.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::debug;
+use memory::{AllocatedFrames, Mapper, MappedPages, PteFlags, VirtualAddress};
+use spin::Mutex;
+
+/// The registry of segments that can be mapped by name.
+static SEGMENTS: Mutex<BTreeMap<String, Arc<Segment>>> = Mutex::new(BTreeMap::new());
+/// The number of segments whose frames haven't yet been deallocated,
+/// including those that were removed but are still mapped.
+static LIVE_SEGMENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Which mappings of a segment may write to it, as decided by the task that created it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Only the creating task may map the segment as writable.
+    ReadOnly,
+    /// Any task may map the segment as writable.
+    ReadWrite,
+}
+
+/// A named region of physical memory that can be mapped by multiple tasks.
+#[derive(Debug)]
+pub struct Segment {
+    name: String,
+    frames: AllocatedFrames,
+    size_in_bytes: usize,
+    access: Access,
+    creator: usize,
+}
+
+impl Segment {
+    /// Returns the name that this segment was created with.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the size of this segment, which is its requested size rounded up to a whole page.
+    pub fn size_in_bytes(&self) -> usize {
+        self.size_in_bytes
+    }
+
+    /// Returns which mappings of this segment may write to it.
+    pub fn access(&self) -> Access {
+        self.access
+    }
+
+    /// Returns the ID of the task that created this segment.
+    pub fn creator(&self) -> usize {
+        self.creator
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        debug!("shm: deallocating segment {:?} {:?}", self.name, self.frames);
+        LIVE_SEGMENTS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A mapping of a shared memory segment, which is unmapped when dropped.
+#[derive(Debug)]
+pub struct SharedMapping {
+    // The pages must be unmapped before the reference to the segment is dropped,
+    // which may deallocate its frames, so this field must be declared first.
+    pages: MappedPages,
+    segment: Arc<Segment>,
+}
+
+impl SharedMapping {
+    /// Returns the virtual address at which the segment is mapped.
+    pub fn start_address(&self) -> VirtualAddress {
+        self.pages.start_address()
+    }
+
+    /// Returns the segment that is mapped.
+    pub fn segment(&self) -> &Segment {
+        &self.segment
+    }
+
+    /// Returns whether this mapping may write to the segment.
+    pub fn is_writable(&self) -> bool {
+        self.pages.flags().is_writable()
+    }
+
+    /// Returns the contents of the segment.
+    pub fn as_slice(&self) -> &[u8] {
+        // The segment is a whole number of pages, so this can't fail.
+        self.pages.as_slice(0, self.segment.size_in_bytes).unwrap_or(&[])
+    }
+
+    /// Returns the contents of the segment mutably,
+    /// or an error if this mapping isn't writable.
+    ///
+    /// Other tasks that have mapped the same segment can access these contents concurrently,
+    /// so they must agree on how to synchronize, e.g., by placing atomics at the start of the segment.
+    pub fn as_slice_mut(&mut self) -> Result<&mut [u8], &'static str> {
+        self.pages.as_slice_mut(0, self.segment.size_in_bytes)
+    }
+}
+
+/// Creates a segment of at least `size_in_bytes` zeroed bytes under the given `name`.
+///
+/// The current task becomes the segment's creator, and `access` determines
+/// whether other tasks may map it as writable.
+///
+/// Returns an error if a segment with that name already exists or memory couldn't be allocated.
+pub fn create(name: &str, size_in_bytes: usize, access: Access) -> Result<(), &'static str> {
+    if size_in_bytes == 0 {
+        return Err("shm::create(): a segment must not be empty");
+    }
+    let creator = task::get_my_current_task_id();
+    let frames = memory::allocate_frames_by_bytes(size_in_bytes)
+        .ok_or("shm::create(): couldn't allocate frames")?;
+    let size_in_bytes = frames.size_in_frames() * memory::PAGE_SIZE;
+
+    // Zero the frames through a temporary mapping, such that no stale data is shared.
+    let mut zeroing = map_frames(&frames, true)?;
+    zeroing.as_slice_mut::<u8>(0, size_in_bytes)?.fill(0);
+    drop(zeroing);
+
+    let mut segments = SEGMENTS.lock();
+    if segments.contains_key(name) {
+        return Err("shm::create(): a segment with that name already exists");
+    }
+    LIVE_SEGMENTS.fetch_add(1, Ordering::Relaxed);
+    let segment = Segment { name: String::from(name), frames, size_in_bytes, access, creator };
+    segments.insert(String::from(name), Arc::new(segment));
+    Ok(())
+}
+
+/// Maps the segment with the given `name` into a new range of pages.
+///
+/// A `writable` mapping is only permitted if the segment was created with [`Access::ReadWrite`]
+/// or if the current task created it.
+pub fn map(name: &str, writable: bool) -> Result<SharedMapping, &'static str> {
+    let segment = SEGMENTS.lock().get(name).cloned().ok_or("shm::map(): no segment with that name exists")?;
+    if writable && segment.access == Access::ReadOnly && task::get_my_current_task_id() != segment.creator {
+        return Err("shm::map(): the segment can only be mapped as writable by its creator");
+    }
+    let pages = map_frames(&segment.frames, writable)?;
+    Ok(SharedMapping { pages, segment })
+}
+
+/// Removes the segment with the given `name`, such that it can no longer be mapped
+/// and its name can be reused.
+///
+/// Existing mappings of the segment remain valid, and its frames are only deallocated
+/// once all of them have been dropped.
+pub fn remove(name: &str) -> Result<(), &'static str> {
+    SEGMENTS.lock().remove(name)
+        .map(|_| ())
+        .ok_or("shm::remove(): no segment with that name exists")
+}
+
+/// Returns the names of all segments that can currently be mapped.
+pub fn list() -> alloc::vec::Vec<String> {
+    SEGMENTS.lock().keys().cloned().collect()
+}
+
+/// Returns the number of segments whose frames haven't yet been deallocated,
+/// including those that were removed but are still mapped.
+pub fn live_segments() -> usize {
+    LIVE_SEGMENTS.load(Ordering::Relaxed)
+}
+
+/// Maps the given `frames` non-exclusively into newly-allocated pages.
+fn map_frames(frames: &AllocatedFrames, writable: bool) -> Result<MappedPages, &'static str> {
+    let pages = memory::allocate_pages(frames.size_in_frames())
+        .ok_or("shm: couldn't allocate pages")?;
+    let kernel_mmi_ref = memory::get_kernel_mmi_ref().ok_or("shm: KERNEL_MMI was not yet initialized")?;
+    let flags = PteFlags::new().valid(true).writable(writable);
+    // SAFETY: the frames are owned by a segment that outlives every mapping of them,
+    // and the contents of a segment are only ever accessed as plain bytes.
+    unsafe {
+        Mapper::map_to_non_exclusive(&mut kernel_mmi_ref.lock().page_table, pages, frames, flags)
+    }
+}
@@ -1,12 +1,12 @@
 //! Provides APIs for tasks to sleep for specified time durations.
 //!
 //! Key functions:
-//! * The [`sleep`] function delays the current task for a given number of ticks.
+//! * The [`sleep`] function delays the current task for a given [`Duration`].
 //! * The [`sleep_until`] function delays the current task until a specific moment in the future.
 //! * The [`sleep_periodic`] function allows for tasks to be delayed for periodic intervals
 //!  of time and can be used to implement a period task.
-//!
-//! TODO: use regular time-keeping abstractions like Duration and Instant.
+//! * The [`sleep_interruptible`] function is like [`sleep`], but returns early
+//!   if another task unblocks the sleeping task with [`WakeReason::Interrupted`].
 
 #![no_std]
 extern crate task;
@@ -17,9 +17,9 @@ extern crate time;
 extern crate crossbeam_utils;
 
 use core::task::Waker;
-use alloc::collections::binary_heap::BinaryHeap;
+use alloc::{boxed::Box, collections::binary_heap::BinaryHeap};
 use sync_irq::IrqSafeMutex;
-use task::{get_my_current_task, TaskRef, RunState};
+use task::{get_my_current_task, CleanupGuard, CleanupReason, TaskRef, RunState, WakeReason};
 use crossbeam_utils::atomic::AtomicCell;
 use time::{now, Instant, Monotonic};
 
@@ -42,7 +42,10 @@ impl Action {
     fn act(self) {
         match self {
             Action::Sync(task) => {
-                task.unblock().expect("failed to unblock sleeping task");
+                // A task that was killed while sleeping has nothing to wake up.
+                if task.unblock_with_reason(WakeReason::TimedOut).is_err() && !task.has_exited() {
+                    panic!("failed to unblock sleeping task");
+                }
             },
             Action::Async(waker) => waker.wake(),
         }
@@ -96,45 +99,118 @@ fn add_to_delayed_tasklist(new_node: SleepingTaskNode) {
     }
 }
 
-/// Remove the next task from the delayed task list and unblock that task
-fn remove_next_task_from_delayed_tasklist() {
+/// Removes all entries for the task with the given `task_id` from the delayed task list,
+/// e.g., because that task was killed while sleeping.
+fn cancel_sleep(task_id: usize) {
     let mut delayed_tasklist = DELAYED_TASKLIST.lock();
-    if let Some(SleepingTaskNode { action, .. }) = delayed_tasklist.pop() {
-        action.act();
-
-        match delayed_tasklist.peek() {
-            Some(SleepingTaskNode { resume_time, .. }) => 
-                NEXT_DELAYED_TASK_UNBLOCK_TIME.store(*resume_time),
-            None => NEXT_DELAYED_TASK_UNBLOCK_TIME.store(Instant::MAX),
-        }
+    delayed_tasklist.retain(|node| !matches!(&node.action, Action::Sync(task) if task.id == task_id));
+    match delayed_tasklist.peek() {
+        Some(SleepingTaskNode { resume_time, .. }) =>
+            NEXT_DELAYED_TASK_UNBLOCK_TIME.store(*resume_time),
+        None => NEXT_DELAYED_TASK_UNBLOCK_TIME.store(Instant::MAX),
     }
 }
 
 /// Remove all tasks that have been delayed but are able to be unblocked now.
-pub fn unblock_sleeping_tasks() {
+///
+/// Every deadline that has expired is handled in this single pass, including several
+/// that should have expired at successive timer ticks that were lost while interrupts were disabled.
+///
+/// Returns the number of expired deadlines that were handled.
+pub fn unblock_sleeping_tasks() -> usize {
     let time = now::<Monotonic>();
-    while time > NEXT_DELAYED_TASK_UNBLOCK_TIME.load() {
-        remove_next_task_from_delayed_tasklist();
+    if time <= NEXT_DELAYED_TASK_UNBLOCK_TIME.load() {
+        return 0;
     }
+
+    let mut delayed_tasklist = DELAYED_TASKLIST.lock();
+    let mut expired = 0;
+    while delayed_tasklist.peek().is_some_and(|node| time > node.resume_time) {
+        if let Some(SleepingTaskNode { action, .. }) = delayed_tasklist.pop() {
+            action.act();
+            expired += 1;
+        }
+    }
+    match delayed_tasklist.peek() {
+        Some(SleepingTaskNode { resume_time, .. }) =>
+            NEXT_DELAYED_TASK_UNBLOCK_TIME.store(*resume_time),
+        None => NEXT_DELAYED_TASK_UNBLOCK_TIME.store(Instant::MAX),
+    }
+    expired
 }
 
-/// Blocks the current task by putting it to sleep for `duration` ticks.
+/// Blocks the current task by putting it to sleep for the given `duration`.
 ///
 /// Returns the current task's run state if it can't be blocked.
 pub fn sleep(duration: Duration) -> Result<(), RunState> {
+    match sleep_internal(duration, false) {
+        Ok(()) | Err(SleepError::Interrupted) => Ok(()),
+        Err(SleepError::InvalidRunState(runstate)) => Err(runstate),
+    }
+}
+
+/// The error returned by [`sleep_interruptible()`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SleepError {
+    /// The sleep was interrupted before the full duration elapsed.
+    Interrupted,
+    /// The current task couldn't be blocked because it was in the given run state.
+    InvalidRunState(RunState),
+}
+
+/// Blocks the current task by putting it to sleep for the given `duration`,
+/// unless another task interrupts it first via
+/// [`unblock_with_reason(WakeReason::Interrupted)`](task::Task::unblock_with_reason).
+///
+/// Unlike [`sleep()`], this ignores spurious wakeups, i.e., those without a [`WakeReason`].
+pub fn sleep_interruptible(duration: Duration) -> Result<(), SleepError> {
+    sleep_internal(duration, true)
+}
+
+fn sleep_internal(duration: Duration, interruptible: bool) -> Result<(), SleepError> {
     let current_time = now::<Monotonic>();
     let resume_time = current_time + duration;
 
     let current_task = get_my_current_task().unwrap();
+    // If this task is killed while sleeping, its timer must be cancelled.
+    let task_id = current_task.id;
+    let _cancel_guard = CleanupGuard::new(Box::new(move |_: CleanupReason| cancel_sleep(task_id)));
+
+    // Discard any stale reason from an earlier wakeup that was never taken.
+    current_task.take_wake_reason();
     // Add the current task to the delayed tasklist and then block it.
     add_to_delayed_tasklist(SleepingTaskNode{action: Action::Sync(current_task.clone()), resume_time});
-    current_task.block()?;
-    task::schedule();
-    Ok(())
+    loop {
+        current_task.block().map_err(SleepError::InvalidRunState)?;
+        // If this task was woken before it blocked, that wakeup didn't unblock it.
+        let reason = match current_task.take_wake_reason() {
+            Some(reason) => {
+                let _ = current_task.unblock();
+                Some(reason)
+            }
+            None => {
+                task::schedule();
+                current_task.take_wake_reason()
+            }
+        };
+        match reason {
+            Some(WakeReason::TimedOut) => return Ok(()),
+            Some(WakeReason::Interrupted) if interruptible => {
+                cancel_sleep(task_id);
+                return Err(SleepError::Interrupted);
+            }
+            // A spurious wakeup, so keep sleeping until the timer expires.
+            _ if interruptible => continue,
+            // A non-interruptible sleep ends upon any wakeup, so its timer is no longer needed.
+            _ => {
+                cancel_sleep(task_id);
+                return Ok(());
+            }
+        }
+    }
 }
 
-/// Blocks the current task by putting it to sleep until a specific tick count is reached,
-/// given by `resume_time`.
+/// Blocks the current task by putting it to sleep until the given `resume_time`.
 ///
 /// Returns the current task's run state if it can't be blocked.
 pub fn sleep_until(resume_time: Instant) -> Result<(), RunState> {
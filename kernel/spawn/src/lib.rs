@@ -28,6 +28,7 @@ use log::{error, info, debug};
 use cpu::CpuId;
 use debugit::debugit;
 use spin::Mutex;
+use kernel_config::memory::KERNEL_STACK_SIZE_IN_PAGES;
 use memory::{get_kernel_mmi_ref, MmiRef};
 use stack::Stack;
 use task::{Task, TaskRef, RestartInfo, RunState, JoinableTaskRef, ExitableTaskRef, FailureCleanupFunction};
@@ -38,6 +39,8 @@ use fs_node::FileOrDir;
 use preemption::{hold_preemption, PreemptionGuard};
 use no_drop::NoDrop;
 
+pub use cpu_topology::Placement;
+
 #[cfg(simd_personality)]
 use task::SimdExt;
 
@@ -185,6 +188,28 @@ pub fn new_task_builder<F, A, R>(
     TaskBuilder::new(func, argument)
 }
 
+/// Spawns a new thread of the current task that starts at the given entry point function `func`
+/// and will be passed the given `argument`.
+///
+/// The new thread joins the current task's group, sharing its memory account;
+/// see [`Task::join_group()`] for more details.
+/// It's otherwise spawned like any other task, with its own stack guarded by an unmapped page.
+///
+/// This is a shortcut for [`new_task_builder()`] with [`TaskBuilder::thread_of()`] the current task.
+pub fn create_thread<F, A, R>(
+    func: F,
+    argument: A
+) -> Result<JoinableTaskRef, &'static str>
+    where A: Send + 'static,
+          R: Send + 'static,
+          F: FnOnce(A) -> R,
+{
+    let current = task::get_my_current_task().ok_or("spawn::create_thread(): couldn't get current task")?;
+    new_task_builder(func, argument)
+        .thread_of(current)
+        .spawn()
+}
+
 
 /// Every executable application must have an entry function named "main".
 const ENTRY_POINT_SECTION_NAME: &str = "main";
@@ -261,6 +286,14 @@ pub fn new_application_task_builder(
             Ok(None)
         }
     ));
+
+    // Application tasks' stacks are placed at random addresses,
+    // such that an application can't predict where other tasks' stacks are.
+    let stack = {
+        let kernel_mmi_ref = get_kernel_mmi_ref().ok_or("couldn't get_kernel_mmi_ref")?;
+        stack::alloc_stack_randomized(KERNEL_STACK_SIZE_IN_PAGES, &mut kernel_mmi_ref.lock().page_table)
+    }.ok_or("spawn::new_application_task_builder(): couldn't allocate stack")?;
+    tb = tb.stack(stack);
     
     Ok(tb)
 }
@@ -285,6 +318,9 @@ pub struct TaskBuilder<F, A, R> {
     stack: Option<Stack>,
     parent: Option<TaskRef>,
     pin_on_cpu: Option<CpuId>,
+    placement: Option<Placement>,
+    memory_limit: Option<usize>,
+    group_leader: Option<TaskRef>,
     blocked: bool,
     idle: bool,
     post_build_function: Option<Box<
@@ -311,6 +347,9 @@ impl<F, A, R> TaskBuilder<F, A, R>
             stack: None,
             parent: None,
             pin_on_cpu: None,
+            placement: None,
+            memory_limit: None,
+            group_leader: None,
             blocked: false,
             idle: false,
             post_build_function: None,
@@ -353,6 +392,34 @@ impl<F, A, R> TaskBuilder<F, A, R>
         self
     }
 
+    /// Place the new Task on the least busy CPU that satisfies the given topology hint,
+    /// e.g., on a CPU that shares a cache with another Task it frequently communicates with.
+    ///
+    /// Unlike [`pin_on_cpu()`](Self::pin_on_cpu), this only affects the initial choice of CPU.
+    /// This is ignored if the new Task is also pinned to a CPU.
+    pub fn placement(mut self, placement: Placement) -> TaskBuilder<F, A, R> {
+        self.placement = Some(placement);
+        self
+    }
+
+    /// Limit the amount of memory, in bytes, that can be charged to the new Task.
+    ///
+    /// Allocations made by the new Task beyond this limit fail with [`memory::QUOTA_EXCEEDED`].
+    /// The new Task's stack counts towards this limit.
+    pub fn memory_limit(mut self, limit_in_bytes: usize) -> TaskBuilder<F, A, R> {
+        self.memory_limit = Some(limit_in_bytes);
+        self
+    }
+
+    /// Spawn the new Task as a thread in the same group as the given `leader` task,
+    /// such that memory charged to the new Task counts towards the group's shared memory account.
+    ///
+    /// If a [`memory_limit()`](Self::memory_limit) is also set, it applies to the whole group.
+    pub fn thread_of(mut self, leader: TaskRef) -> TaskBuilder<F, A, R> {
+        self.group_leader = Some(leader);
+        self
+    }
+
     /// Mark this new Task as a SIMD-enabled Task 
     /// that can run SIMD instructions and use SIMD registers.
     #[cfg(simd_personality)]
@@ -392,6 +459,12 @@ impl<F, A, R> TaskBuilder<F, A, R>
         let exposed = ExposedTask { task: new_task };
         exposed.inner().lock().pinned_cpu = self.pin_on_cpu;
         let ExposedTask { task: mut new_task } = exposed;    
+        if let Some(leader) = self.group_leader.as_ref() {
+            new_task.join_group(leader);
+        }
+        if let Some(limit) = self.memory_limit {
+            new_task.set_memory_limit(Some(limit));
+        }
 
         #[cfg(simd_personality)] {  
             new_task.simd = self.simd;
@@ -446,6 +519,8 @@ impl<F, A, R> TaskBuilder<F, A, R>
         if !self.idle {
             if let Some(cpu) = self.pin_on_cpu {
                 task::scheduler::add_task_to(cpu, task_ref.clone());
+            } else if let Some(placement) = self.placement {
+                task::scheduler::add_task_with_placement(task_ref.clone(), placement);
             } else {
                 task::scheduler::add_task(task_ref.clone());
             }
@@ -675,9 +750,14 @@ where
 {
     // The first time a task runs, its entry function `task_wrapper()` is
     // jumped to from the `task_switch()` function, right after the context
-    // switch occured. However, we set the context of the new task to have
-    // interrupts enabled (in `ContextRegular::new`), so interrupts are enabled
-    // as soon as the new task is switched to.
+    // switch occured. The context of the new task has interrupts disabled
+    // (see `ContextRegular::new`), so they remain disabled until the
+    // post-context switch actions below restore this task's saved interrupt state,
+    // which is always "enabled" for a new task.
+    debug_assert!(
+        !irq_safety::interrupts_enabled(),
+        "BUG: task_wrapper: interrupts were enabled upon a new task's first entry"
+    );
 
     let task_entry_func;
     let task_arg;
@@ -32,6 +32,41 @@ pub fn alloc_stack(
     inner_alloc_stack(pages, page_table)
 }
 
+/// The number of random addresses tried by [`alloc_stack_randomized()`]
+/// before it falls back to allocating a stack at any address.
+const RANDOMIZED_STACK_ATTEMPTS: usize = 4;
+
+/// Allocates a new stack at a random address and maps it to the active page table.
+///
+/// The stack is placed at a random page-aligned address within the address range
+/// reserved for randomized stacks, i.e., [`RANDOMIZED_STACK_P4_INDEX`],
+/// such that its location can't be predicted from that of other stacks or allocations.
+/// If no randomly-chosen address is free, this falls back to [`alloc_stack()`].
+///
+/// Like [`alloc_stack()`], this also reserves an unmapped guard page beneath the stack.
+///
+/// [`RANDOMIZED_STACK_P4_INDEX`]: kernel_config::memory::RANDOMIZED_STACK_P4_INDEX
+pub fn alloc_stack_randomized(
+    size_in_pages: usize,
+    page_table: &mut Mapper, 
+) -> Option<Stack> {
+    #[cfg(target_arch = "x86_64")] {
+        use kernel_config::memory::{ADDRESSABILITY_PER_P4_ENTRY, RANDOMIZED_STACK_START};
+
+        let region_pages = ADDRESSABILITY_PER_P4_ENTRY / PAGE_SIZE;
+        let possible_starts = region_pages.checked_sub(size_in_pages + 1)? as u64 + 1;
+        for _ in 0 .. RANDOMIZED_STACK_ATTEMPTS {
+            let page_offset = (random::next_u64() % possible_starts) as usize;
+            let start = VirtualAddress::new_canonical(RANDOMIZED_STACK_START + page_offset * PAGE_SIZE);
+            if let Ok(pages) = page_allocator::allocate_pages_at(start, size_in_pages + 1) {
+                return inner_alloc_stack(pages, page_table);
+            }
+        }
+        warn!("alloc_stack_randomized(): no random address was free, falling back to any address");
+    }
+    alloc_stack(size_in_pages, page_table)
+}
+
 /// The inner implementation of stack allocation. 
 /// 
 /// `pages` is the combined `AllocatedPages` object that holds
@@ -23,7 +23,7 @@ cfg_if! { if #[cfg(frame_pointers)] {
 extern crate alloc;
 extern crate memory;
 
-use memory::{PageTable, VirtualAddress};
+use memory::{Page, PageTable, VirtualAddress};
 
 
 /// Get a stack trace using the frame pointer registers (RBP on x86_64).
@@ -62,7 +62,9 @@ pub fn stack_trace_using_frame_pointers(
         // the stack contains the return address (of the caller) right before the current frame pointer
         if let Some(rip_ptr) = rbp.checked_add(core::mem::size_of::<usize>()) {
             if let (Some(rbp_vaddr), Some(rip_ptr)) = (VirtualAddress::new(rbp), VirtualAddress::new(rip_ptr)) {
-                if current_page_table.translate(rbp_vaddr).is_some() && current_page_table.translate(rip_ptr).is_some() {
+                if current_page_table.is_mapped(Page::containing_address(rbp_vaddr))
+                    && current_page_table.is_mapped(Page::containing_address(rip_ptr))
+                {
                     // SAFE: the address was checked above using page table walks
                     let rip = unsafe { *(rip_ptr.value() as *const usize) };
                     if rip == 0 {
@@ -45,6 +45,15 @@
 //! }
 //! ```
 //! 
+//! Devices may also keep [`IoStats`] about the requests they complete
+//! and report [`SmartData`] about their health.
+//!
+//! Devices may be removed while the system is running, e.g., hot-swapped drives.
+//! A removed device is [detached](StorageDevice::detach) rather than dropped,
+//! as other crates may still hold references to it, so that their I/O fails gracefully
+//! with [`IoError::DeviceRemoved`](io::IoError::DeviceRemoved). Layers built atop a device, such as block caches,
+//! implement [`StorageDeviceDependent`] so that they can be detached from it first.
+//!
 //! # Limitations
 //! 
 //! Note that if other crates are using a storage device through a block cache, 
@@ -57,10 +66,18 @@ extern crate alloc;
 extern crate spin;
 #[macro_use] extern crate downcast_rs;
 extern crate io;
+extern crate time;
+
+mod smart;
+mod stats;
+
+pub use smart::{SmartAttribute, SmartData, SmartHealth};
+pub use stats::{IoKind, IoStats, IoStatsSnapshot, IoTimer};
 
 use alloc::{
     boxed::Box,
     sync::Arc,
+    vec::Vec,
 };
 use spin::Mutex;
 use downcast_rs::Downcast;
@@ -79,6 +96,33 @@ pub trait StorageController {
     /// but Rust does not permit casts from `&Arc<Mutex<Struct>>` to `&Arc<Mutex<Trait>>`,
     /// it only supports casts from `Arc<Mutex<Struct>>` to `Arc<Mutex<Trait>>`.
     fn devices<'c>(&'c self) -> Box<(dyn Iterator<Item = StorageDeviceRef> + 'c)>;
+
+    /// Re-probes this controller for attached devices, e.g., after a drive was plugged in or unplugged.
+    ///
+    /// Devices that are no longer present are [detached](StorageDevice::detach)
+    /// and forgotten by this controller, and newly-found devices are added to it.
+    ///
+    /// Returns an error if this controller doesn't support rescanning.
+    fn rescan(&mut self) -> Result<DeviceChanges, &'static str> {
+        Err("this storage controller does not support rescanning")
+    }
+
+    /// Forgets the given `device`, such that it's no longer returned by [`devices()`](Self::devices).
+    ///
+    /// The device should already have been [detached](StorageDevice::detach).
+    /// Returns `false` if the device isn't attached to this controller.
+    fn remove_device(&mut self, _device: &StorageDeviceRef) -> bool {
+        false
+    }
+}
+
+/// The devices that were found or lost by [`StorageController::rescan()`].
+#[derive(Default)]
+pub struct DeviceChanges {
+    /// The devices that were newly found.
+    pub added: Vec<StorageDeviceRef>,
+    /// The devices that are no longer present, which have already been detached.
+    pub removed: Vec<StorageDeviceRef>,
 }
 
 /// A trait object wrapped in an Arc and Mutex that allows 
@@ -100,9 +144,48 @@ pub type StorageControllerRef = Arc<Mutex<dyn StorageController + Send>>;
 pub trait StorageDevice: BlockIo + BlockReader + BlockWriter + KnownLength + Downcast {
 	/// Returns the total size of this device, given in number of blocks (sectors).
     fn size_in_blocks(&self) -> usize;
+
+    /// Returns the I/O statistics that this device keeps, if any.
+    ///
+    /// The statistics are shared such that they can be read without locking this device.
+    fn io_stats(&self) -> Option<Arc<IoStats>> {
+        None
+    }
+
+    /// Reads this device's current SMART health data.
+    ///
+    /// Returns an error if this device doesn't support SMART.
+    fn smart_data(&mut self) -> Result<SmartData, &'static str> {
+        Err("this device does not support SMART")
+    }
+
+    /// Detaches this device from its hardware because it was (or is about to be) removed,
+    /// after which all of its I/O fails with [`IoError::DeviceRemoved`](io::IoError::DeviceRemoved).
+    ///
+    /// This must not fail or block on the hardware, as the device may already be gone.
+    fn detach(&mut self);
+
+    /// Returns `true` if this device was [detached](Self::detach).
+    fn is_detached(&self) -> bool;
 }
 impl_downcast!(StorageDevice);
 
 /// A trait object wrapped in an Arc and Mutex that allows 
 /// arbitrary storage devices to be shared in a thread-safe manner.
 pub type StorageDeviceRef = Arc<Mutex<dyn StorageDevice + Send>>;
+
+
+/// A layer built atop a storage device, such as a block cache or a mounted filesystem,
+/// which must be detached from that device before the device can be safely removed.
+pub trait StorageDeviceDependent: Send + Sync {
+    /// Returns a short description of this layer, used in error messages.
+    fn name(&self) -> &str;
+
+    /// Writes back any data that this layer holds for its device, then detaches from it,
+    /// after which all of this layer's I/O must fail with [`IoError::DeviceRemoved`](io::IoError::DeviceRemoved).
+    ///
+    /// If `force` is `false`, this fails without detaching if the layer is still in use,
+    /// e.g., if it has open files, or if its data couldn't be written back.
+    /// If `force` is `true`, this must detach regardless, discarding any unwritten data.
+    fn detach(&self, force: bool) -> Result<(), &'static str>;
+}
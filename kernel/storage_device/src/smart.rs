@@ -0,0 +1,66 @@
+//! Device-independent representation of SMART (Self-Monitoring, Analysis and Reporting Technology)
+//! health data reported by a storage device.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The overall health assessment that a device reports for itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmartHealth {
+    /// No attribute has crossed its failure threshold.
+    Passed,
+    /// At least one attribute has crossed its failure threshold, so failure may be imminent.
+    ThresholdExceeded,
+    /// The device didn't report a recognizable health status.
+    Unknown,
+}
+
+impl fmt::Display for SmartHealth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SmartHealth::Passed => "PASSED",
+            SmartHealth::ThresholdExceeded => "FAILING (threshold exceeded)",
+            SmartHealth::Unknown => "unknown",
+        })
+    }
+}
+
+/// A single vendor-defined SMART attribute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SmartAttribute {
+    pub id: u8,
+    /// The normalized current value, typically from 1 to 253, where higher is better.
+    pub current: u8,
+    /// The lowest normalized value ever recorded.
+    pub worst: u8,
+    /// The raw, vendor-specific value, e.g., a count of sectors or hours.
+    pub raw: u64,
+}
+
+impl SmartAttribute {
+    /// Returns the commonly-used name of this attribute, if it is well-known.
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self.id {
+            1   => "Raw Read Error Rate",
+            5   => "Reallocated Sector Count",
+            9   => "Power-On Hours",
+            10  => "Spin Retry Count",
+            12  => "Power Cycle Count",
+            187 => "Reported Uncorrectable Errors",
+            194 => "Temperature (Celsius)",
+            196 => "Reallocation Event Count",
+            197 => "Current Pending Sector Count",
+            198 => "Offline Uncorrectable Sector Count",
+            199 => "UltraDMA CRC Error Count",
+            _ => return None,
+        })
+    }
+}
+
+/// The SMART data read from a device.
+#[derive(Clone, Debug)]
+pub struct SmartData {
+    pub health: SmartHealth,
+    /// The device's attributes, in the order it reported them.
+    pub attributes: Vec<SmartAttribute>,
+}
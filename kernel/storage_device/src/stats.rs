@@ -0,0 +1,159 @@
+//! Cumulative I/O statistics for a storage device.
+//!
+//! Each request costs two timestamps and a few atomic operations,
+//! and no locks are acquired, so statistics are always kept.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use time::Instant;
+
+/// The type of transfer performed by an I/O request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoKind {
+    Read,
+    Write,
+}
+
+/// Cumulative I/O statistics that a storage device, or a layer above it, maintains.
+///
+/// This is typically shared via an `Arc` such that it can be read
+/// without locking the device itself.
+#[derive(Debug, Default)]
+pub struct IoStats {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    sectors_read: AtomicU64,
+    sectors_written: AtomicU64,
+    errors: AtomicU64,
+    retries: AtomicU64,
+    total_latency_nanos: AtomicU64,
+    max_latency_nanos: AtomicU64,
+    in_flight: AtomicUsize,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+impl IoStats {
+    /// Creates a new set of statistics with all counters at zero.
+    pub fn new() -> IoStats {
+        IoStats::default()
+    }
+
+    /// Marks the submission of a new request, which is then in flight
+    /// until the returned [`IoTimer`] is finished or dropped.
+    pub fn start(&self) -> IoTimer<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        IoTimer { stats: self, start: Instant::now() }
+    }
+
+    /// Records that a request had to be retried, e.g., after resetting a hung device.
+    pub fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a block was found in a cache above this device.
+    pub fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a block was not found in a cache above this device.
+    pub fn record_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns a point-in-time copy of these statistics.
+    ///
+    /// The counters are read individually, so they may be slightly inconsistent
+    /// with each other if requests complete while this is running.
+    pub fn snapshot(&self) -> IoStatsSnapshot {
+        IoStatsSnapshot {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            sectors_read: self.sectors_read.load(Ordering::Relaxed),
+            sectors_written: self.sectors_written.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            total_latency_nanos: self.total_latency_nanos.load(Ordering::Relaxed),
+            max_latency_nanos: self.max_latency_nanos.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Measures the latency of one in-flight request, from its submission to its completion.
+///
+/// Dropping this without calling [`finish()`](Self::finish) marks the request
+/// as no longer in flight without counting it.
+#[must_use = "the request is only counted once `finish()` is called"]
+pub struct IoTimer<'s> {
+    stats: &'s IoStats,
+    start: Instant,
+}
+
+impl IoTimer<'_> {
+    /// Marks the completion of this request, which transferred `sectors` sectors
+    /// if it succeeded, and records its latency.
+    pub fn finish(self, kind: IoKind, sectors: usize, succeeded: bool) {
+        let latency_nanos = self.start.elapsed().as_nanos() as u64;
+        let stats = self.stats;
+        if succeeded {
+            let (count, sector_count) = match kind {
+                IoKind::Read => (&stats.reads, &stats.sectors_read),
+                IoKind::Write => (&stats.writes, &stats.sectors_written),
+            };
+            count.fetch_add(1, Ordering::Relaxed);
+            sector_count.fetch_add(sectors as u64, Ordering::Relaxed);
+        } else {
+            stats.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        stats.total_latency_nanos.fetch_add(latency_nanos, Ordering::Relaxed);
+        stats.max_latency_nanos.fetch_max(latency_nanos, Ordering::Relaxed);
+    }
+}
+
+impl Drop for IoTimer<'_> {
+    fn drop(&mut self) {
+        self.stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A point-in-time copy of a device's [`IoStats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IoStatsSnapshot {
+    /// The number of read requests that completed successfully.
+    pub reads: u64,
+    /// The number of write requests that completed successfully.
+    pub writes: u64,
+    pub sectors_read: u64,
+    pub sectors_written: u64,
+    /// The number of requests that failed.
+    pub errors: u64,
+    /// The number of requests that were retried.
+    pub retries: u64,
+    /// The sum of the latencies of all completed requests, including failed ones.
+    pub total_latency_nanos: u64,
+    pub max_latency_nanos: u64,
+    /// The number of requests currently in flight, i.e., the current queue depth.
+    pub in_flight: usize,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+impl IoStatsSnapshot {
+    /// Returns the number of completed requests, including failed ones.
+    pub fn completed(&self) -> u64 {
+        self.reads + self.writes + self.errors
+    }
+
+    /// Returns the average latency of all completed requests, if any have completed.
+    pub fn average_latency_nanos(&self) -> Option<u64> {
+        self.total_latency_nanos.checked_div(self.completed())
+    }
+
+    /// Returns the fraction of cache lookups that were hits, in tenths of a percent,
+    /// if there were any lookups.
+    pub fn cache_hit_permille(&self) -> Option<u64> {
+        (self.cache_hits * 1000).checked_div(self.cache_hits + self.cache_misses)
+    }
+}
@@ -1,27 +1,61 @@
 //! Manages and handles initialization of all storage devices
 //! and storage controllers in the system.
+//!
+//! Devices can be added or removed at runtime: [`rescan()`] re-probes all controllers
+//! for devices that were plugged in or unplugged, and [`remove_device()`] safely removes
+//! a device by first detaching the layers built atop it; see [`register_dependent()`].
 
 #![no_std]
 
-extern crate alloc;
+#[macro_use] extern crate alloc;
 #[macro_use] extern crate log;
 extern crate spin;
 extern crate pci;
 extern crate ata;
+extern crate async_block_io;
 extern crate storage_device;
+extern crate fs_node;
+extern crate io;
+extern crate memory;
+extern crate root;
+extern crate events;
+extern crate metrics;
+extern crate time;
 
 use alloc::{
+    string::String,
     vec::Vec,
-    sync::Arc,
+    sync::{Arc, Weak},
 };
+use core::fmt::Write;
 use spin::Mutex;
 use pci::PciDevice;
+use fs_node::{DirRef, File, FileOrDir, FsNode, WeakDirRef};
+use io::{BlockWriter, ByteReader, ByteWriter, IoError, KnownLength};
+use memory::MappedPages;
+use time::Instant;
 
 pub use storage_device::*;
 
+/// The name of the file in the root directory that reports the I/O statistics of all storage devices.
+pub const DISK_STATS_FILE_NAME: &str = "diskstats";
+
+/// The name of the [`events`] event posted when a new storage device appears.
+///
+/// The posting's argument is the new device's index in [`storage_devices()`].
+pub const DEVICE_ADDED_EVENT: &str = "storage.device_added";
+/// The name of the [`events`] event posted when a storage device is removed.
+///
+/// The posting's argument is the removed device's former index in [`storage_devices()`].
+pub const DEVICE_REMOVED_EVENT: &str = "storage.device_removed";
+
 /// A list of all of the available and initialized storage controllers that exist on this system.
 static STORAGE_CONTROLLERS: Mutex<Vec<StorageControllerRef>> = Mutex::new(Vec::new());
 
+/// The layers built atop storage devices, in order of registration,
+/// each with the address of the device it depends on.
+static DEPENDENTS: Mutex<Vec<(usize, Weak<dyn StorageDeviceDependent>)>> = Mutex::new(Vec::new());
+
 /// Returns an iterator over all initialized storage controllers on this system.
 /// 
 /// This function requires allocation, as it currently clones the list of storage controllers,\
@@ -44,6 +78,185 @@ pub fn storage_devices() -> impl Iterator<Item = StorageDeviceRef> {
 }
 
 
+/// Returns the address that identifies the given `device` in [`DEPENDENTS`].
+fn device_addr(device: &StorageDeviceRef) -> usize {
+    Arc::as_ptr(device) as *const () as usize
+}
+
+/// Returns the index of the given `device` in [`storage_devices()`], if it's still attached.
+fn device_index(device: &StorageDeviceRef) -> Option<usize> {
+    storage_devices().position(|d| device_addr(&d) == device_addr(device))
+}
+
+/// Registers a layer built atop the given `device`, e.g., a block cache or filesystem,
+/// which will be [detached](StorageDeviceDependent::detach) before that device is removed.
+///
+/// Layers are detached in the reverse order of their registration,
+/// so a layer must be registered after any layers that it is built atop.
+/// A layer is unregistered once it is dropped or its device is removed.
+pub fn register_dependent(device: &StorageDeviceRef, dependent: Weak<dyn StorageDeviceDependent>) {
+    let mut dependents = DEPENDENTS.lock();
+    dependents.retain(|(_, d)| d.strong_count() > 0);
+    dependents.push((device_addr(device), dependent));
+}
+
+/// Detaches all layers registered atop the given `device`, newest first,
+/// and unregisters those that were detached.
+///
+/// Unless `force` is `true`, this stops at the first layer that fails to detach,
+/// leaving it and all older layers attached.
+fn detach_dependents(device: &StorageDeviceRef, force: bool) -> Result<(), &'static str> {
+    let addr = device_addr(device);
+    // Detaching may block on I/O, so it's done without holding the lock on `DEPENDENTS`.
+    let dependents: Vec<_> = DEPENDENTS.lock()
+        .iter()
+        .filter(|(a, _)| *a == addr)
+        .filter_map(|(_, d)| d.upgrade())
+        .collect();
+    let mut result = Ok(());
+    let mut detached = Vec::new();
+    for dependent in dependents.iter().rev() {
+        match dependent.detach(force) {
+            Ok(()) => detached.push(Arc::as_ptr(dependent) as *const () as usize),
+            Err(e) => {
+                warn!("Failed to detach {} from a storage device: {}", dependent.name(), e);
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    DEPENDENTS.lock().retain(|(a, d)|
+        d.strong_count() > 0 && !(*a == addr && detached.contains(&(d.as_ptr() as *const () as usize)))
+    );
+    result
+}
+
+/// Safely removes the given `device` from the system, e.g., before it's unplugged.
+///
+/// This proceeds in order, failing before the device is detached if any step fails:
+/// 1. All layers registered atop the device are [detached](StorageDeviceDependent::detach),
+///    which writes back their data and fails if they're still in use.
+/// 2. The device's write cache is flushed.
+/// 3. The device is [detached](StorageDevice::detach), after which all of its I/O fails
+///    with [`IoError::DeviceRemoved`], and it's forgotten by its controller.
+///
+/// As the device is detached while it's locked, no synchronous requests can be in progress;
+/// asynchronous requests submitted afterwards fail, and requests already outstanding
+/// are completed (or failed) by the device's driver.
+///
+/// If `force` is `true`, the device is removed even if any of the first two steps fail,
+/// in which case layers still in use fail their subsequent I/O, and any unwritten data is lost.
+///
+/// Upon success, the [`DEVICE_REMOVED_EVENT`] is posted.
+pub fn remove_device(device: &StorageDeviceRef, force: bool) -> Result<(), &'static str> {
+    let index = device_index(device).ok_or("the storage device is not attached to this system")?;
+    let controller = storage_controllers()
+        .find(|c| c.lock().devices().any(|d| device_addr(&d) == device_addr(device)))
+        .ok_or("the storage device is not attached to this system")?;
+
+    if let Err(e) = detach_dependents(device, force) {
+        if !force {
+            return Err(e);
+        }
+    }
+    {
+        let mut locked_device = device.lock();
+        if let Err(e) = BlockWriter::flush(&mut *locked_device) {
+            warn!("Failed to flush storage device {} before removing it: {:?}", index, e);
+            if !force {
+                return Err("failed to flush the storage device");
+            }
+        }
+        locked_device.detach();
+    }
+    controller.lock().remove_device(device);
+    info!("Removed storage device {}", index);
+    events::post(DEVICE_REMOVED_EVENT, index);
+    Ok(())
+}
+
+/// Re-probes all storage controllers for devices that were plugged in or unplugged,
+/// and returns the devices that were found and lost.
+///
+/// Devices that are no longer present are already gone, so the layers atop them
+/// are forcibly detached, discarding any unwritten data.
+/// The [`DEVICE_REMOVED_EVENT`] and [`DEVICE_ADDED_EVENT`] are posted for each lost and found device,
+/// respectively. Controllers that don't support rescanning are skipped.
+pub fn rescan() -> DeviceChanges {
+    let previous_devices: Vec<StorageDeviceRef> = storage_devices().collect();
+    let mut all_changes = DeviceChanges::default();
+    for controller in storage_controllers() {
+        // Release the controller's lock before detaching layers, which may access its devices.
+        let changes = controller.lock().rescan();
+        if let Ok(changes) = changes {
+            all_changes.added.extend(changes.added);
+            all_changes.removed.extend(changes.removed);
+        }
+    }
+
+    for device in &all_changes.removed {
+        let _ = detach_dependents(device, true);
+        let former_index = previous_devices.iter().position(|d| device_addr(d) == device_addr(device));
+        if let Some(index) = former_index {
+            info!("Storage device {} was removed", index);
+            events::post(DEVICE_REMOVED_EVENT, index);
+        }
+    }
+    for device in &all_changes.added {
+        if let Some(index) = device_index(device) {
+            info!("Storage device {} was added", index);
+            events::post(DEVICE_ADDED_EVENT, index);
+        }
+    }
+    all_changes
+}
+
+
+/// Quiesces all storage devices for shutdown, after which they reject new reads and writes,
+/// and then cancels all outstanding block I/O, which may never complete otherwise.
+///
+/// If `poll_only` is `true`, subsequent waits for a busy device never yield the CPU.
+pub fn quiesce(poll_only: bool) {
+    ata::quiesce(poll_only);
+    let cancelled = async_block_io::cancel_all();
+    if cancelled > 0 {
+        info!("Cancelled {} outstanding block I/O operations for shutdown", cancelled);
+    }
+}
+
+/// Flushes the write cache of every storage device, giving up once the `deadline` has passed.
+///
+/// This never blocks on a lock: a locked controller or device is polled until the `deadline`.
+/// Returns an error if any device couldn't be flushed, after attempting to flush all of them.
+pub fn flush_all(deadline: Instant) -> Result<(), &'static str> {
+    /// Polls the given `mutex` until it's acquired or the `deadline` has passed.
+    fn lock_by<T: ?Sized>(mutex: &Mutex<T>, deadline: Instant) -> Result<spin::MutexGuard<'_, T>, &'static str> {
+        loop {
+            if let Some(guard) = mutex.try_lock() {
+                return Ok(guard);
+            }
+            if Instant::now() >= deadline {
+                return Err("timed out waiting for a storage device lock");
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    let mut result = Ok(());
+    for controller in lock_by(&STORAGE_CONTROLLERS, deadline)?.iter() {
+        let controller = lock_by(controller, deadline)?;
+        for device in controller.devices() {
+            let flushed = lock_by(&device, deadline)
+                .and_then(|mut device| BlockWriter::flush(&mut *device).map_err(|_e| "failed to flush a storage device"));
+            if let Err(e) = flushed {
+                error!("Failed to flush storage device: {}", e);
+                result = Err(e);
+            }
+        }
+    }
+    result
+}
+
 /// Attempts to handle the initialization of the given `PciDevice`,
 /// if it is a recognized storage device.
 /// 
@@ -57,7 +270,12 @@ pub fn init_device(pci_device: &PciDevice) -> Result<Option<StorageControllerRef
         info!("IDE controller PCI device found at: {:?}", pci_device.location);
         let ide_controller = ata::IdeController::new(pci_device)?;
         let storage_controller_ref: StorageControllerRef = Arc::new(Mutex::new(ide_controller));
+        let first_new_device = storage_devices().count();
         STORAGE_CONTROLLERS.lock().push(Arc::clone(&storage_controller_ref));
+        let num_new_devices = storage_controller_ref.lock().devices().count();
+        for index in first_new_device .. first_new_device + num_new_devices {
+            events::post(DEVICE_ADDED_EVENT, index);
+        }
         Some(storage_controller_ref)
     } 
     // Here: in the future, handle other supported storage devices
@@ -67,3 +285,103 @@ pub fn init_device(pci_device: &PciDevice) -> Result<Option<StorageControllerRef
     
     Ok(storage_controller)
 }
+
+
+/// Creates the `/diskstats` file, which reports the I/O statistics of all storage devices,
+/// and registers the totals of those statistics as metrics.
+pub fn init_stats_file() -> Result<(), &'static str> {
+    let file = Arc::new(Mutex::new(DiskStatsFile)) as fs_node::FileRef;
+    root::get_root().lock().insert(FileOrDir::File(file))?;
+    metrics::register_gauge("disk.reads", || total_io_stat(|stats| stats.reads))?;
+    metrics::register_gauge("disk.writes", || total_io_stat(|stats| stats.writes))?;
+    metrics::register_gauge("disk.errors", || total_io_stat(|stats| stats.errors))?;
+    Ok(())
+}
+
+/// Sums the given statistic across all storage devices that track I/O statistics.
+fn total_io_stat(stat: fn(&IoStatsSnapshot) -> u64) -> u64 {
+    storage_devices()
+        .filter_map(|device| device.lock().io_stats().map(|stats| stat(&stats.snapshot())))
+        .sum()
+}
+
+/// Returns a human-readable table of the I/O statistics of every storage device.
+///
+/// Devices are numbered in the order returned by [`storage_devices()`].
+/// Latencies are in microseconds, and the cache hit ratio only covers reads
+/// made through a `BlockCache` atop the device.
+pub fn report() -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{:<5} {:>10} {:>10} {:>12} {:>12} {:>7} {:>7} {:>10} {:>10} {:>8} {:>7}",
+        "DISK", "READS", "WRITES", "SECTORS_RD", "SECTORS_WR", "ERRORS", "RETRIES",
+        "AVG_US", "MAX_US", "INFLIGHT", "HIT%",
+    );
+    for (i, device) in storage_devices().enumerate() {
+        let stats = match device.lock().io_stats() {
+            Some(stats) => stats.snapshot(),
+            None => {
+                let _ = writeln!(out, "{:<5} (no statistics)", i);
+                continue;
+            }
+        };
+        let avg = stats.average_latency_nanos()
+            .map_or_else(|| String::from("-"), |nanos| format!("{}", nanos / 1000));
+        let hit = stats.cache_hit_permille()
+            .map_or_else(|| String::from("-"), |pm| format!("{}.{}", pm / 10, pm % 10));
+        let _ = writeln!(out, "{:<5} {:>10} {:>10} {:>12} {:>12} {:>7} {:>7} {:>10} {:>10} {:>8} {:>7}",
+            i, stats.reads, stats.writes, stats.sectors_read, stats.sectors_written,
+            stats.errors, stats.retries, avg, stats.max_latency_nanos / 1000,
+            stats.in_flight, hit,
+        );
+    }
+    out
+}
+
+
+/// A lazily-generated file that reports the current I/O statistics of all storage devices.
+struct DiskStatsFile;
+
+impl FsNode for DiskStatsFile {
+    fn get_name(&self) -> String {
+        String::from(DISK_STATS_FILE_NAME)
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        Some(root::get_root().clone())
+    }
+
+    fn set_parent_dir(&mut self, _: WeakDirRef) {
+        // do nothing
+    }
+}
+
+impl ByteReader for DiskStatsFile {
+    fn read_at(&mut self, buf: &mut [u8], offset: usize) -> Result<usize, IoError> {
+        let output = report();
+        if offset > output.len() {
+            return Err(IoError::InvalidInput);
+        }
+        let count = core::cmp::min(buf.len(), output.len() - offset);
+        buf[..count].copy_from_slice(&output.as_bytes()[offset..(offset + count)]);
+        Ok(count)
+    }
+}
+
+impl ByteWriter for DiskStatsFile {
+    fn write_at(&mut self, _buffer: &[u8], _offset: usize) -> Result<usize, IoError> {
+        Err(IoError::from("the disk statistics report is read-only"))
+    }
+    fn flush(&mut self) -> Result<(), IoError> { Ok(()) }
+}
+
+impl KnownLength for DiskStatsFile {
+    fn len(&self) -> usize {
+        report().len()
+    }
+}
+
+impl File for DiskStatsFile {
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("the disk statistics report is autogenerated, cannot be memory mapped")
+    }
+}
@@ -3,7 +3,7 @@ use preemption::hold_preemption_no_timer_disable;
 use mpmc_queue::Queue;
 use sync::DeadlockPrevention;
 use sync_spin::Spin;
-use task::{get_my_current_task, TaskRef};
+use task::{get_my_current_task, TaskRef, WakeReason};
 
 /// A condition variable.
 ///
@@ -93,7 +93,7 @@ where
                 None => return false,
             };
 
-            if task.unblock().is_ok() {
+            if task.unblock_with_reason(WakeReason::Condition).is_ok() {
                 return true;
             }
         }
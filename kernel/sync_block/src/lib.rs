@@ -1,10 +1,18 @@
 #![feature(negative_impls, let_chains)]
 #![no_std]
 
+extern crate alloc;
+
 mod condvar;
 
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{
+    mem::size_of,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use alloc::boxed::Box;
 use sync::{spin, MutexFlavor, RwLockFlavor};
+use task::{CleanupGuard, CleanupReason};
 use wait_queue::WaitQueue;
 
 pub use condvar::Condvar;
@@ -29,11 +37,14 @@ impl MutexFlavor for Block {
     const INIT: Self::LockData = Self::LockData {
         queue: WaitQueue::new(),
         holder: AtomicUsize::new(0),
+        poisoned: AtomicBool::new(false),
     };
 
     type LockData = MutexData;
 
-    type Guard = ();
+    /// Registers the current task as the mutex holder,
+    /// such that the mutex is released if that task is killed while holding it.
+    type Guard = Option<CleanupGuard>;
 
     #[inline]
     fn try_lock<'a, T>(
@@ -56,9 +67,11 @@ impl MutexFlavor for Block {
         // than an atomic bool. A non-zero value would represent the task ID of the
         // holder, and a zero would represent the unlocked state. However, this
         // would be very hard to integrate with the current sync API.
-        data.holder
-            .store(task::get_my_current_task_id(), Ordering::Release);
-        Some((guard, ()))
+        let holder_id = task::get_my_current_task_id();
+        data.holder.store(holder_id, Ordering::Release);
+        let abandoned = AbandonedLock::new(mutex, data, holder_id);
+        let owner_guard = CleanupGuard::new(Box::new(move |_: CleanupReason| abandoned.release()));
+        Some((guard, owner_guard))
     }
 
     #[inline]
@@ -128,6 +141,110 @@ impl MutexFlavor for Block {
 pub struct MutexData {
     queue: WaitQueue,
     holder: AtomicUsize,
+    poisoned: AtomicBool,
+}
+
+/// A type-erased reference to a locked mutex that is released
+/// if the task holding it exits or is killed without unlocking it.
+struct AbandonedLock {
+    /// Storage for a (possibly wide) `*const spin::Mutex<T>`.
+    mutex: [usize; 2],
+    /// Force-unlocks the `spin::Mutex<T>` stored in `mutex`.
+    force_unlock: unsafe fn(&[usize; 2]),
+    data: *const MutexData,
+    holder_id: usize,
+}
+
+// SAFETY: the pointers in an `AbandonedLock` are only dereferenced
+// while the holder task still owns the mutex, which keeps it alive.
+unsafe impl Send for AbandonedLock {}
+
+impl AbandonedLock {
+    fn new<T: ?Sized>(mutex: &spin::Mutex<T>, data: &MutexData, holder_id: usize) -> Self {
+        unsafe fn force_unlock<T: ?Sized>(storage: &[usize; 2]) {
+            let mutex = ptr::read(storage.as_ptr() as *const *const spin::Mutex<T>);
+            (*mutex).force_unlock();
+        }
+
+        assert!(size_of::<*const spin::Mutex<T>>() <= size_of::<[usize; 2]>());
+        let mut storage = [0usize; 2];
+        unsafe { ptr::write(storage.as_mut_ptr() as *mut *const spin::Mutex<T>, mutex) };
+        AbandonedLock {
+            mutex: storage,
+            force_unlock: force_unlock::<T>,
+            data,
+            holder_id,
+        }
+    }
+
+    /// Unlocks and poisons the mutex, and wakes the next waiting task.
+    fn release(self) {
+        // SAFETY: the holder task never released the mutex, so it must still exist.
+        let data = unsafe { &*self.data };
+        if data.holder.load(Ordering::Acquire) != self.holder_id {
+            return;
+        }
+        log::warn!("releasing mutex abandoned by task {}", self.holder_id);
+        data.poisoned.store(true, Ordering::Release);
+        data.holder.store(0, Ordering::Release);
+        unsafe { (self.force_unlock)(&self.mutex) };
+        data.queue.notify_one();
+    }
+}
+
+/// An error returned by [`MutexExt::lock_checked()`] if the mutex is poisoned,
+/// i.e., a previous holder task was killed while holding it.
+///
+/// The contained guard can be used to access the (possibly inconsistent) data anyway.
+pub struct PoisonError<G> {
+    guard: G,
+}
+
+impl<G> PoisonError<G> {
+    /// Returns the guard that was acquired despite the mutex being poisoned.
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+}
+
+impl<G> core::fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+/// Additional methods for a blocking [`Mutex`] that deal with poisoning.
+///
+/// A mutex becomes poisoned when the task holding it is killed without releasing it,
+/// in which case the mutex is forcibly unlocked such that waiting tasks aren't blocked forever.
+pub trait MutexExt<T: ?Sized> {
+    /// Returns `true` if this mutex is poisoned.
+    fn is_poisoned(&self) -> bool;
+
+    /// Clears the poisoned state of this mutex.
+    fn clear_poison(&self);
+
+    /// Acquires this mutex, returning an error containing the guard if it is poisoned.
+    fn lock_checked(&self) -> Result<MutexGuard<'_, T>, PoisonError<MutexGuard<'_, T>>>;
+}
+
+impl<T: ?Sized> MutexExt<T> for Mutex<T> {
+    fn is_poisoned(&self) -> bool {
+        self.lock_data().poisoned.load(Ordering::Acquire)
+    }
+
+    fn clear_poison(&self) {
+        self.lock_data().poisoned.store(false, Ordering::Release);
+    }
+
+    fn lock_checked(&self) -> Result<MutexGuard<'_, T>, PoisonError<MutexGuard<'_, T>>> {
+        let guard = self.lock();
+        if self.is_poisoned() {
+            Err(PoisonError { guard })
+        } else {
+            Ok(guard)
+        }
+    }
 }
 
 impl RwLockFlavor for Block {
@@ -19,7 +19,8 @@ impl<T> Mutex<T> {
 
     #[allow(clippy::result_unit_err)]
     pub fn lock(&self) -> Result<MutexGuard<T>, ()> {
-        Ok(self.inner.lock())
+        use crate::MutexExt;
+        self.inner.lock_checked().map_err(|_| ())
     }
 }
 
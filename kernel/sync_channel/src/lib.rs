@@ -20,14 +20,16 @@ extern crate crossbeam_utils;
 extern crate core2;
 extern crate sync;
 extern crate sync_spin;
+extern crate task;
 
-use alloc::sync::Arc;
+use alloc::{boxed::Box, sync::Arc};
 use mpmc::Queue as MpmcQueue;
 use wait_queue::WaitQueue;
 use crossbeam_utils::atomic::AtomicCell;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use sync::DeadlockPrevention;
 use sync_spin::Spin;
+use task::{CleanupGuard, CleanupReason};
 
 /// Create a new channel that allows senders and receivers to 
 /// asynchronously exchange messages via an internal intermediary buffer.
@@ -68,8 +70,8 @@ pub fn new_channel_with<T: Send, P: DeadlockPrevention>(
         receiver_count: AtomicUsize::new(1),
     });
     (
-        Sender { channel: channel.clone() },
-        Receiver { channel },
+        Sender { owner: EndpointOwner::new(&channel, Endpoint::Sender), channel: channel.clone() },
+        Receiver { owner: EndpointOwner::new(&channel, Endpoint::Receiver), channel },
     )
 }
 
@@ -147,7 +149,7 @@ impl <T: Send, P: DeadlockPrevention> Channel<T, P> {
         if channel.sender_count.fetch_add(1, Ordering::SeqCst) == 0 {
             channel.channel_status.store(ChannelStatus::Connected);
         }
-        Sender { channel: channel.clone() }
+        Sender { owner: EndpointOwner::new(channel, Endpoint::Sender), channel: channel.clone() }
     }
     
     /// Returns another `Receiver` endpoint connected to the given channel.
@@ -158,13 +160,125 @@ impl <T: Send, P: DeadlockPrevention> Channel<T, P> {
         if channel.receiver_count.fetch_add(1, Ordering::SeqCst) == 0 {
             channel.channel_status.store(ChannelStatus::Connected);
         }
-        Receiver { channel: channel.clone() }
+        Receiver { owner: EndpointOwner::new(channel, Endpoint::Receiver), channel: channel.clone() }
+    }
+
+    /// Removes a `Sender` endpoint from this channel.
+    ///
+    /// This decrements the channel's sender count.
+    /// If there are no more senders, the channel is marked as disconnected
+    /// and all of the waiting `Receiver`s are notified.
+    fn remove_sender(&self) {
+        if self.sender_count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.channel_status.store(ChannelStatus::SenderDisconnected);
+            self.waiting_receivers.notify_all();
+        }
+    }
+
+    /// Removes a `Receiver` endpoint from this channel.
+    ///
+    /// This decrements the channel's receiver count.
+    /// If there are no more receivers, the channel is marked as disconnected
+    /// and all of the waiting `Sender`s are notified.
+    fn remove_receiver(&self) {
+        if self.receiver_count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.channel_status.store(ChannelStatus::ReceiverDisconnected);
+            self.waiting_senders.notify_all();
+        }
+    }
+}
+
+/// The two kinds of channel endpoints.
+#[derive(Clone, Copy)]
+enum Endpoint {
+    Sender,
+    Receiver,
+}
+
+/// Ensures that a channel endpoint is removed from its channel exactly once:
+/// either when the endpoint is dropped, or when the task that created it is killed,
+/// since a killed task never drops the objects on its stack.
+///
+/// Note that the cleanup hook is registered with the task that created the endpoint,
+/// not any task that the endpoint may later be moved to.
+/// Thus, the hook does nothing if that task exits normally,
+/// as any endpoints it still has registered must have been moved elsewhere.
+struct EndpointOwner {
+    removed: Arc<AtomicBool>,
+    _hook: Option<CleanupGuard>,
+}
+
+impl EndpointOwner {
+    fn new<T: Send, P: DeadlockPrevention>(channel: &Arc<Channel<T, P>>, kind: Endpoint) -> EndpointOwner {
+        let removed = Arc::new(AtomicBool::new(false));
+        let endpoint = ErasedEndpoint::new(channel, kind);
+        let hook_removed = removed.clone();
+        let hook = CleanupGuard::new(Box::new(move |reason: CleanupReason| {
+            if reason == CleanupReason::Killed && !hook_removed.swap(true, Ordering::SeqCst) {
+                endpoint.remove();
+            }
+        }));
+        EndpointOwner { removed, _hook: hook }
+    }
+
+    /// Returns `true` if the endpoint should now be removed from its channel,
+    /// i.e., if it hasn't already been removed on behalf of a killed task.
+    fn should_remove(&self) -> bool {
+        !self.removed.swap(true, Ordering::SeqCst)
+    }
+}
+
+/// A type-erased reference to a channel, used by the cleanup hook in an [`EndpointOwner`]
+/// to remove an endpoint from that channel.
+struct ErasedEndpoint {
+    /// A pointer obtained from `Arc::<Channel<T, P>>::into_raw()`.
+    channel: *const (),
+    /// Removes the endpoint from the channel pointed to by `channel`.
+    remove: unsafe fn(*const ()),
+    /// Drops the `Arc` reference to the channel pointed to by `channel`.
+    drop_channel: unsafe fn(*const ()),
+}
+
+// SAFETY: `Channel` is only accessed through its `Sync` methods.
+unsafe impl Send for ErasedEndpoint {}
+
+impl ErasedEndpoint {
+    fn new<T: Send, P: DeadlockPrevention>(channel: &Arc<Channel<T, P>>, kind: Endpoint) -> ErasedEndpoint {
+        unsafe fn remove_sender<T: Send, P: DeadlockPrevention>(ptr: *const ()) {
+            (*(ptr as *const Channel<T, P>)).remove_sender()
+        }
+        unsafe fn remove_receiver<T: Send, P: DeadlockPrevention>(ptr: *const ()) {
+            (*(ptr as *const Channel<T, P>)).remove_receiver()
+        }
+        unsafe fn drop_channel<T: Send, P: DeadlockPrevention>(ptr: *const ()) {
+            drop(Arc::from_raw(ptr as *const Channel<T, P>))
+        }
+
+        ErasedEndpoint {
+            channel: Arc::into_raw(channel.clone()) as *const (),
+            remove: match kind {
+                Endpoint::Sender => remove_sender::<T, P>,
+                Endpoint::Receiver => remove_receiver::<T, P>,
+            },
+            drop_channel: drop_channel::<T, P>,
+        }
+    }
+
+    fn remove(&self) {
+        unsafe { (self.remove)(self.channel) }
+    }
+}
+
+impl Drop for ErasedEndpoint {
+    fn drop(&mut self) {
+        unsafe { (self.drop_channel)(self.channel) }
     }
 }
 
 /// The sender (transmit) side of a channel.
 pub struct Sender<T: Send, P: DeadlockPrevention = Spin> {
     channel: Arc<Channel<T, P>>,
+    owner: EndpointOwner,
 }
 
 impl<T:Send, P: DeadlockPrevention> Clone for Sender<T, P> {
@@ -348,6 +462,7 @@ impl <T: Send, P: DeadlockPrevention> Sender<T, P> {
 /// The receiver side of a channel.
 pub struct Receiver<T: Send, P: DeadlockPrevention = Spin> {
     channel: Arc<Channel<T, P>>,
+    owner: EndpointOwner,
 }
 
 impl<T: Send, P: DeadlockPrevention> Clone for Receiver<T, P> {
@@ -474,12 +589,12 @@ impl <T: Send, P: DeadlockPrevention> Receiver<T, P> {
 
     /// Receives objects placing them in a buffer and returning the number of objects received.
     ///
-    /// This method does not block.
+    /// This method does not block; it returns `Ok(0)` if no objects are available.
     pub fn try_receive_buf(&self, buf: &mut [T]) -> Result<usize, Error> {
         for (idx, item) in buf.iter_mut().enumerate() {
             *item = match self.try_receive() {
                 Ok(byte) => byte,
-                Err(Error::WouldBlock) => return Ok(idx + 1),
+                Err(Error::WouldBlock) => return Ok(idx),
                 Err(e) => return Err(e),
             };
         }
@@ -503,9 +618,8 @@ impl <T: Send, P: DeadlockPrevention> Receiver<T, P> {
 impl<T: Send, P: DeadlockPrevention> Drop for Receiver<T, P> {
     fn drop(&mut self) {
         // trace!("Dropping a receiver");
-        if self.channel.receiver_count.fetch_sub(1, Ordering::SeqCst) == 1 {
-            self.channel.channel_status.store(ChannelStatus::ReceiverDisconnected);
-            self.channel.waiting_senders.notify_all();
+        if self.owner.should_remove() {
+            self.channel.remove_receiver();
         }
     }
 }
@@ -515,9 +629,8 @@ impl<T: Send, P: DeadlockPrevention> Drop for Receiver<T, P> {
 impl<T: Send, P: DeadlockPrevention> Drop for Sender<T, P> {
     fn drop(&mut self) {
         // trace!("Dropping a sender");
-        if self.channel.sender_count.fetch_sub(1, Ordering::SeqCst) == 1 {
-            self.channel.channel_status.store(ChannelStatus::SenderDisconnected);
-            self.channel.waiting_receivers.notify_all();
+        if self.owner.should_remove() {
+            self.channel.remove_sender();
         }
     }
 }
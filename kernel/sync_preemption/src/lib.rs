@@ -25,6 +25,8 @@ impl sync::DeadlockPrevention for DisablePreemption {
 
     const EXPENSIVE: bool = true;
 
+    const TRACK_HOLDER: bool = true;
+
     #[inline]
     fn enter() -> Self::Guard {
         hold_preemption()
@@ -0,0 +1,219 @@
+//! Measurement of scheduler wakeup latency, i.e., the time from when a task
+//! becomes runnable until it's actually switched in.
+//!
+//! Once [`enable()`] has been called, each task records when it's unblocked or
+//! spawned as runnable, and the task switching routine records the elapsed time
+//! when it switches that task in. Tasks that were merely preempted aren't measured.
+//!
+//! Latencies are recorded into log2-sized buckets of a histogram per CPU and per
+//! [`PriorityClass`], such that the latency of high-priority tasks is visible separately.
+//!
+//! The timestamps come from the monotonic clock, which is consistent across CPUs
+//! (e.g., the TSC with each CPU's offset compensated) and isn't affected by
+//! lost timer ticks, so a task that's woken on one CPU and switched in on another
+//! is measured correctly. Any remaining skew that would make a latency negative
+//! is clamped to zero.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use cpu::CpuId;
+use time::{Duration, Instant};
+
+/// The maximum number of CPUs whose wakeup latency can be tracked.
+const MAX_TRACKED_CPUS: usize = 256;
+
+/// The number of buckets in each histogram.
+pub const NUM_BUCKETS: usize = 16;
+
+/// The log2 of the upper bound in nanoseconds of the first bucket, i.e., about 8 µs.
+const FIRST_BUCKET_SHIFT: u32 = 13;
+
+/// The number of [`PriorityClass`]es.
+const NUM_CLASSES: usize = 3;
+
+/// The priority class of a task, as determined by its nice value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriorityClass {
+    /// Tasks with a negative nice value.
+    High = 0,
+    /// Tasks with the default nice value of `0`.
+    Normal = 1,
+    /// Tasks with a positive nice value.
+    Low = 2,
+}
+
+impl PriorityClass {
+    /// All priority classes, from highest to lowest.
+    pub const ALL: [PriorityClass; NUM_CLASSES] = [PriorityClass::High, PriorityClass::Normal, PriorityClass::Low];
+
+    /// Returns the priority class of a task with the given nice value.
+    pub const fn from_nice(nice: i8) -> PriorityClass {
+        if nice < 0 {
+            PriorityClass::High
+        } else if nice == 0 {
+            PriorityClass::Normal
+        } else {
+            PriorityClass::Low
+        }
+    }
+
+    /// Returns the name of this priority class in lowercase.
+    pub const fn name(self) -> &'static str {
+        match self {
+            PriorityClass::High => "high",
+            PriorityClass::Normal => "normal",
+            PriorityClass::Low => "low",
+        }
+    }
+}
+
+/// The counters of the histogram of one priority class on one CPU.
+struct Counters {
+    buckets: [AtomicU64; NUM_BUCKETS],
+    max_nanos: AtomicU64,
+}
+
+impl Counters {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: Counters = {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+        Counters { buckets: [ZERO; NUM_BUCKETS], max_nanos: ZERO }
+    };
+}
+
+/// The wakeup latency histograms of each CPU, indexed by CPU ID and then by [`PriorityClass`].
+static HISTOGRAMS: [[Counters; NUM_CLASSES]; MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const CPU: [Counters; NUM_CLASSES] = [Counters::ZERO; NUM_CLASSES];
+    [CPU; MAX_TRACKED_CPUS]
+};
+
+/// A snapshot of a wakeup latency histogram.
+///
+/// Bucket `0` counts latencies below [`Histogram::bucket_upper_bound(0)`](Histogram::bucket_upper_bound),
+/// each following bucket covers twice the range of the previous one,
+/// and the last bucket counts every latency above that, i.e., above about 134 ms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    pub buckets: [u64; NUM_BUCKETS],
+    /// The highest latency recorded.
+    pub max: Duration,
+}
+
+impl Histogram {
+    /// Returns the exclusive upper bound of the latencies counted in the given bucket,
+    /// or `None` for the last bucket, which is unbounded.
+    pub const fn bucket_upper_bound(bucket: usize) -> Option<Duration> {
+        if bucket + 1 >= NUM_BUCKETS {
+            None
+        } else {
+            Some(Duration::from_nanos(1 << (FIRST_BUCKET_SHIFT + bucket as u32)))
+        }
+    }
+
+    /// Returns the total number of latencies recorded.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns an upper bound on the given `percentile` (from `0` to `100`) of the recorded latencies,
+    /// i.e., the upper bound of the bucket that contains it, or `None` if nothing was recorded.
+    ///
+    /// If the percentile falls into the last bucket, the highest latency recorded is returned.
+    pub fn percentile(&self, percentile: u64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        // The rank of the percentile, rounded up such that p100 is the last latency recorded.
+        let rank = (count * percentile.min(100)).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (bucket, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Some(Self::bucket_upper_bound(bucket).map_or(self.max, |bound| bound.min(self.max)));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// Adds the latencies recorded in `other` to this histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (bucket, n) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += n;
+        }
+        self.max = self.max.max(other.max);
+    }
+}
+
+/// Starts measuring wakeup latency.
+///
+/// This must only be called once a monotonic clock source has been registered.
+pub fn enable() {
+    task_struct::enable_wakeup_timestamps();
+}
+
+/// Records the wakeup latency of a task that's being switched in on the given CPU,
+/// which became runnable at `runnable_since`.
+pub(crate) fn record(cpu: CpuId, nice: i8, runnable_since: Instant) {
+    let Some(counters) = HISTOGRAMS.get(cpu.value() as usize) else { return };
+    let counters = &counters[PriorityClass::from_nice(nice) as usize];
+    let nanos = Instant::now().duration_since(runnable_since).as_nanos() as u64;
+    counters.buckets[bucket_of(nanos)].fetch_add(1, Ordering::Relaxed);
+    counters.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+}
+
+/// Returns the index of the bucket that counts the given latency in nanoseconds.
+const fn bucket_of(nanos: u64) -> usize {
+    let log2 = 63 - (nanos | 1).leading_zeros();
+    let bucket = (log2 + 1).saturating_sub(FIRST_BUCKET_SHIFT) as usize;
+    if bucket < NUM_BUCKETS { bucket } else { NUM_BUCKETS - 1 }
+}
+
+/// Returns the wakeup latency histogram of the given priority class on the given CPU,
+/// or `None` if that CPU isn't tracked.
+pub fn histogram(cpu: CpuId, class: PriorityClass) -> Option<Histogram> {
+    let counters = &HISTOGRAMS.get(cpu.value() as usize)?[class as usize];
+    let mut histogram = Histogram {
+        max: Duration::from_nanos(counters.max_nanos.load(Ordering::Relaxed)),
+        ..Histogram::default()
+    };
+    for (bucket, counter) in histogram.buckets.iter_mut().zip(&counters.buckets) {
+        *bucket = counter.load(Ordering::Relaxed);
+    }
+    Some(histogram)
+}
+
+/// Returns the wakeup latency histogram of the given priority class across all CPUs.
+pub fn total_histogram(class: PriorityClass) -> Histogram {
+    let mut total = Histogram::default();
+    for histogram in cpu::cpus().filter_map(|cpu| histogram(cpu, class)) {
+        total.merge(&histogram);
+    }
+    total
+}
+
+/// Clears all wakeup latency histograms, e.g., to compare latency before and after a change.
+pub fn reset() {
+    for counters in HISTOGRAMS.iter().flatten() {
+        for bucket in &counters.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        counters.max_nanos.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Reports the 50th and 99th percentile and the highest wakeup latency in microseconds
+/// of each priority class across all CPUs, as `<class>.p50_us`, `<class>.p99_us`, and `<class>.max_us`,
+/// for use with `metrics::register_group()`.
+pub fn report(report: &mut dyn FnMut(&str, u64)) {
+    for class in PriorityClass::ALL {
+        let histogram = total_histogram(class);
+        let p50 = histogram.percentile(50).unwrap_or_default();
+        let p99 = histogram.percentile(99).unwrap_or_default();
+        report(&alloc::format!("{}.count", class.name()), histogram.count());
+        report(&alloc::format!("{}.p50_us", class.name()), p50.as_micros() as u64);
+        report(&alloc::format!("{}.p99_us", class.name()), p99.as_micros() as u64);
+        report(&alloc::format!("{}.max_us", class.name()), histogram.max.as_micros() as u64);
+    }
+}
@@ -15,7 +15,8 @@
 //!    * [`get_my_current_task()`] returns a cloned reference to the current task
 //!      and is thus slightly more expensive [`with_current_task()`].
 //!    * [`get_my_current_task_id()`] is fastest if you just want the ID of the current task.
-//!      Note that it is fairly expensive to obtain a task reference from a task ID.
+//!      A task reference can be obtained from a task ID with [`get_task()`],
+//!      which is a constant-time lookup.
 //! 2. Register a kill handler for the current task -- [`set_kill_handler()`].
 //! 3. Yield the current CPU and schedule in another task -- [`schedule()`].
 //! 4. Switch from the current task to another specific "next" task -- [`task_switch()`].
@@ -30,11 +31,13 @@
 
 extern crate alloc;
 
+pub mod latency;
 pub mod scheduler;
+mod tasklist;
 
 use alloc::{
     boxed::Box,
-    collections::BTreeMap,
+    collections::BTreeSet,
     format,
     sync::{Arc, Weak}, vec::Vec,
 };
@@ -44,51 +47,163 @@ use core::{
     fmt,
     hash::{Hash, Hasher},
     ops::Deref,
-    sync::atomic::{AtomicBool, fence, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, fence, Ordering},
     task::Waker,
 };
 use cpu::CpuId;
-use irq_safety::hold_interrupts;
+use kassert::{kassert_count, kassert_once};
 use log::error;
 use environment::Environment;
-use memory::MmiRef;
+use memory::{MemoryAccount, MmiRef};
 use no_drop::NoDrop;
 use preemption::PreemptionGuard;
 use spin::Mutex;
-use sync_irq::IrqSafeMutex;
 use stack::Stack;
 use task_struct::ExposedTask;
 
 
 // Re-export main types from `task_struct`.
 pub use task_struct::{
+    CleanupHook, CleanupHookId, CleanupReason,
     ExitValue, InheritedStates, KillHandler, KillReason,
-    PanicInfoOwned, RestartInfo, RunState, Task,
+    PanicInfoOwned, RestartInfo, RunState, Task, WakeReason, MIN_NICE, MAX_NICE,
+    task_id_generation, task_id_index,
 };
 #[cfg(simd_personality)]
 pub use task_struct::SimdExt;
 pub use scheduler::schedule;
 
 
-/// The list of all Tasks in the system.
-static TASKLIST: IrqSafeMutex<BTreeMap<usize, TaskRef>> = IrqSafeMutex::new(BTreeMap::new());
-
 /// Returns a `WeakTaskRef` (shared reference) to the `Task` specified by the given `task_id`.
+///
+/// This returns `None` if that task has been reaped, even if a newer task
+/// has since reused the same slot in the task list.
 pub fn get_task(task_id: usize) -> Option<WeakTaskRef> {
-    TASKLIST.lock().get(&task_id).map(TaskRef::downgrade)
+    tasklist::get_weak(task_id)
 }
 
 /// Returns a list containing a snapshot of all tasks that currently exist.
 ///
 /// # Usage Notes
 /// * This is an expensive and slow function, so it should be used rarely.
+///   However, it doesn't block the creation or removal of other tasks.
 /// * The existence of a task in the returned list does not mean the task will continue to exist
 ///   at any point in the future, hence the return type of `WeakTaskRef` instead of `TaskRef`.
 pub fn all_tasks() -> Vec<(usize, WeakTaskRef)> {
-    let tasklist = TASKLIST.lock();
-    let mut v = Vec::with_capacity(tasklist.len());
-    v.extend(tasklist.iter().map(|(id, t)| (*id, t.downgrade())));
-    v
+    tasklist::snapshot()
+}
+
+/// Returns up to `count` existing tasks that have the most memory charged to them,
+/// sorted from most to least memory used, along with their memory usage in bytes.
+///
+/// As the threads in a group share their memory usage, only one task per group is returned.
+///
+/// This is intended for diagnostics, e.g., when reporting an out-of-memory condition,
+/// and is as expensive as [`all_tasks()`].
+pub fn top_memory_consumers(count: usize) -> Vec<(TaskRef, usize)> {
+    let mut tasks: Vec<(TaskRef, usize)> = all_tasks()
+        .into_iter()
+        .filter_map(|(_id, weak)| weak.upgrade())
+        .map(|t| { let usage = t.memory_usage(); (t, usage) })
+        .collect();
+    tasks.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+    let mut groups = BTreeSet::new();
+    tasks.retain(|(t, _)| groups.insert(t.group_id()));
+    tasks.truncate(count);
+    tasks
+}
+
+/// Returns all existing tasks in the group of threads with the given ID.
+///
+/// This is as expensive as [`all_tasks()`].
+pub fn group_members(group_id: usize) -> Vec<TaskRef> {
+    all_tasks()
+        .into_iter()
+        .filter_map(|(_id, weak)| weak.upgrade())
+        .filter(|t| t.group_id() == group_id)
+        .collect()
+}
+
+/// Kills every task in the group of threads with the given ID with [`KillReason::Requested`],
+/// except for the current task and tasks that have already exited.
+///
+/// Returns the number of tasks that were killed.
+pub fn kill_group(group_id: usize) -> usize {
+    let current_id = with_current_task(|t| t.id).ok();
+    group_members(group_id)
+        .into_iter()
+        .filter(|t| Some(t.id) != current_id)
+        .filter(|t| t.kill(KillReason::Requested).is_ok())
+        .count()
+}
+
+/// Returns the memory account of the current task, to which new memory should be charged.
+///
+/// This is registered as the `memory` crate's current account callback.
+fn current_memory_account() -> Option<Arc<MemoryAccount>> {
+    with_current_task(|t| Arc::clone(t.memory_account())).ok()
+}
+
+/// Invokes the given `func` with the locks held by the current task, if any.
+///
+/// This is registered as the `sync` crate's held locks callback.
+fn with_current_held_locks(func: &mut dyn FnMut(&sync::HeldLocks)) {
+    // Interrupts are held such that an interrupt handler can't modify the list concurrently.
+    let _held_interrupts = irq_safety::hold_interrupts();
+    let _ = with_current_task(|t| func(t.held_locks()));
+}
+
+/// Exits the current task without unwinding its stack, e.g., because it caused
+/// an exception and unwinding is disabled or unsupported.
+///
+/// As the destructors of the objects on its stack will never run, this instead:
+/// 1. Forcibly releases every IRQ-safe and preemption-safe lock that the current task
+///    is recorded as holding, such that other tasks can't deadlock on them.
+///    The data those locks protect is left as is, even if it was mid-update.
+/// 2. Kills the current task with the given `reason`, which runs its cleanup hooks
+///    (e.g., to release its blocking locks) and wakes any task that is joining it.
+/// 3. Removes it from its runqueue, reaps it if it's orphaned,
+///    and re-enables preemption, which its abandoned guards would otherwise keep disabled.
+/// 4. Schedules in another task, never to return.
+///
+/// Locks held in the current task's stack frames must not be used by anything that runs
+/// before this function is invoked, e.g., a kill handler.
+pub fn force_unwind_and_exit(reason: KillReason) -> ! {
+    let _held_interrupts = irq_safety::hold_interrupts();
+    let Some(current) = get_my_current_task() else {
+        // This only happens early in the boot process, before tasking was initialized.
+        error!("BUG: force_unwind_and_exit(): couldn't get current task");
+        loop { core::hint::spin_loop() }
+    };
+
+    let mut released = 0;
+    while let Some(lock) = current.held_locks().pop() {
+        // SAFETY: the lock was acquired by this task, which will never run again.
+        unsafe { lock.force_release() };
+        released += 1;
+    }
+    if released > 0 {
+        log::warn!("force_unwind_and_exit(): forcibly released {} locks held by {:?}", released, current);
+    }
+
+    if let Err(e) = current.kill(reason) {
+        error!("force_unwind_and_exit(): couldn't kill {:?}: {}", current, e);
+    }
+    scheduler::remove_task_from_current(&current);
+    if !current.is_joinable() {
+        let _exit_value = current.reap_exit_value();
+    }
+    drop(current);
+
+    // SAFETY: the current task will never run again, so none of its preemption guards will be dropped.
+    unsafe { preemption::force_enable_preemption() };
+    // ****************************************************
+    // NOTE: nothing below here is guaranteed to run again!
+    // ****************************************************
+
+    scheduler::schedule();
+    error!("BUG: force_unwind_and_exit(): task was rescheduled after being dead!");
+    loop { core::hint::spin_loop() }
 }
 
 
@@ -166,8 +281,7 @@ impl TaskRef {
         }));
 
         // Add the new TaskRef to the global task list.
-        let _existing_task = TASKLIST.lock().insert(taskref.id, taskref.clone());
-        assert!(_existing_task.is_none(), "BUG: TASKLIST contained a task with the same ID");
+        tasklist::insert(taskref.clone());
 
         JoinableTaskRef { task: taskref }
     }
@@ -202,6 +316,10 @@ impl TaskRef {
     /// to ensure proper cleanup before the task is actually fully killed.
     /// **
     /// 
+    /// Until then, resources held by the killed task are released by invoking
+    /// its [cleanup hooks](Task::register_cleanup). If this task is currently running
+    /// on another CPU, this waits for it to be descheduled before invoking those hooks.
+    /// 
     /// # Locking / Deadlock
     /// This method obtains a writable lock on the underlying Task's inner state.
     /// 
@@ -223,6 +341,13 @@ impl TaskRef {
         if self.has_exited() {
             return Err("BUG: task was already exited! (did not overwrite its existing exit value)");
         }
+        let is_current = with_current_task(|t| t == self).unwrap_or(false);
+
+        // If this task is exiting by itself, release any resources it still holds
+        // before its exit value is published, such that a joining task observes them as released.
+        if is_current {
+            self.0.task.run_cleanup_hooks(CleanupReason::Exited);
+        }
         {
             *self.0.exit_value_mailbox.lock() = Some(val);
             self.0.task.runstate().store(RunState::Exited);
@@ -236,18 +361,23 @@ impl TaskRef {
             if let Some(waker) = self.0.task.inner().lock().waker.take() {
                 waker.wake();
             }
+        }
 
-            // Corner case: if the task isn't currently running (as with killed tasks), 
-            // we must clean it up now rather than in `task_switch()`, as it will never be scheduled in again.
-            if !self.is_running() {
-                todo!("Unhandled scenario: internal_exit(): task {:?} wasn't running \
-                    but its current task TLS variable needs to be cleaned up!", &self.0.task.task);
-                // Note: we cannot call `deinit_current_task()` here because if this task
-                //       isn't running, then it's definitely not the current task.
-                //
-                // let _taskref_in_tls = deinit_current_task();
-                // drop(_taskref_in_tls);
+        // This task was killed by another task, so we act as its reaper.
+        if !is_current {
+            // Now that it has been marked as exited, it will never be scheduled in again,
+            // so wait for it to finish its current timeslice (if running on another CPU)
+            // before releasing the resources it holds.
+            while self.is_running() {
+                core::hint::spin_loop();
             }
+            scheduler::remove_task(self);
+            self.0.task.run_cleanup_hooks(CleanupReason::Killed);
+
+            // TODO: the `TaskRef` in this task's current task TLS variable must be dropped,
+            //       but we cannot call `deinit_current_task()` here because
+            //       this task isn't running, so it's definitely not the current task.
+            //       Until this is supported, the killed task's struct is leaked.
         }
         Ok(())
     }
@@ -263,10 +393,10 @@ impl TaskRef {
     /// nothing is done and `None` is returned.
     ///
     /// # Locking / Deadlock
-    /// Obtains the lock on the system task list.
+    /// Obtains the lock on this task's slot in the system task list.
     fn reap_exit_value(&self) -> Option<ExitValue> {
         if self.0.task.runstate().compare_exchange(RunState::Exited, RunState::Reaped).is_ok() {
-            TASKLIST.lock().remove(&self.id);
+            tasklist::remove(self.id);
             self.0.exit_value_mailbox.lock().take()
         } else {
             None
@@ -404,7 +534,7 @@ impl JoinableTaskRef {
             let curr_task = get_my_current_task().ok_or("join(): couldn't get current task")?;
             let task_to_block = curr_task.clone();
             let wake_action = move || {
-                let _ = curr_task.unblock();
+                let _ = curr_task.unblock_with_reason(WakeReason::Condition);
             };
             let (waker, blocker) = waker_generic::new_waker(wake_action);
             self.set_waker(waker);
@@ -528,7 +658,19 @@ impl ExitableTaskRef {
         }
     }
 
-    /// Perform any actions needed after a context switch.
+    /// The function invoked after every task switch, which lets the `crash_context` crate
+/// swap per-task debugging state without this crate depending on it.
+static TASK_SWITCH_DEBUG_FUNC: spin::Once<fn()> = spin::Once::new();
+
+/// Sets the function that is invoked after each task switch
+/// in the context of the newly-current task, with preemption disabled.
+///
+/// This function must be fast, as it adds to the latency of every task switch.
+pub fn set_task_switch_debug_func(func: fn()) {
+    TASK_SWITCH_DEBUG_FUNC.call_once(|| func);
+}
+
+/// Perform any actions needed after a context switch.
     /// 
     /// Currently this only does two things:
     /// 1. Drops any data that the original previous task (before the context switch)
@@ -587,6 +729,56 @@ pub fn take_kill_handler() -> Option<KillHandler> {
         .flatten()
 }
 
+/// Registers a cleanup hook for the current `Task`; see [`Task::register_cleanup()`].
+///
+/// # Locking / Deadlock
+/// Obtains the lock on this `Task`'s inner state in order to mutate it.
+pub fn register_cleanup(hook: CleanupHook) -> Result<CleanupHookId, &'static str> {
+    with_current_task(|t| t.register_cleanup(hook))
+        .map_err(|_| "couldn't get current task")
+}
+
+/// A guard that keeps a cleanup hook registered with a `Task` for as long as it exists.
+///
+/// Dropping this guard unregisters the hook *without* invoking it,
+/// which indicates that the associated resource was released normally.
+/// If the task exits or is killed while this guard still exists,
+/// e.g., because the guard was leaked or its task's stack was never unwound,
+/// the hook is invoked to release that resource.
+pub struct CleanupGuard {
+    task: TaskRef,
+    id: CleanupHookId,
+}
+impl CleanupGuard {
+    /// Registers the given `hook` with the current task and returns a guard for it.
+    ///
+    /// Returns `None` if the current task cannot be obtained, e.g., during early boot.
+    pub fn new(hook: CleanupHook) -> Option<CleanupGuard> {
+        with_current_task_and_value(
+            |t, hook| CleanupGuard { task: t.clone(), id: t.register_cleanup(hook) },
+            hook,
+        ).ok()
+    }
+
+    /// Returns the task that this guard's cleanup hook is registered with.
+    pub fn task(&self) -> &TaskRef {
+        &self.task
+    }
+}
+impl Drop for CleanupGuard {
+    fn drop(&mut self) {
+        self.task.unregister_cleanup(self.id);
+    }
+}
+impl fmt::Debug for CleanupGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CleanupGuard")
+            .field("task", &self.task.id)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
 /// Switches from the current task to the given `next` task.
 ///
 /// ## Arguments
@@ -594,6 +786,15 @@ pub fn take_kill_handler() -> Option<KillHandler> {
 /// * `cpu_id`: the ID of the current CPU.
 /// * `preemption_guard`: a guard that is used to ensure preemption is disabled
 ///    for the duration of this task switch operation.
+/// * `interrupts_were_enabled`: whether interrupts should be enabled when the current task
+///    is next switched to, i.e., whether they were enabled before the scheduler was invoked.
+///
+/// ## Interrupts
+/// Interrupts must be disabled when this is called, and they remain disabled
+/// until the context switch has completed, as described in [`scheduler::schedule()`].
+/// If a task switch occurs, the newly-current task's saved interrupt state is restored
+/// by [`post_context_switch_action()`] before this returns (in the context of that task).
+/// If no task switch occurs, the caller is responsible for restoring the interrupt state.
 ///
 /// ## Important Note about Control Flow
 /// If this is the first time that `next` task has been switched to,
@@ -620,13 +821,18 @@ pub fn task_switch(
     next: TaskRef,
     cpu_id: CpuId,
     preemption_guard: PreemptionGuard,
+    interrupts_were_enabled: bool,
 ) -> (bool, PreemptionGuard) {
+    debug_assert!(
+        !irq_safety::interrupts_enabled(),
+        "BUG: task_switch(): interrupts must be disabled before switching tasks"
+    );
 
     // We use the `with_current_task_and_value()` closure here in order to ensure that
     // the borrowed reference to the current task is guaranteed to be dropped
     // *before* the actual context switch operation occurs.
     let result = with_current_task_tls_slot_mut(
-        |curr, p_guard| task_switch_inner(curr, next, cpu_id, p_guard),
+        |curr, p_guard| task_switch_inner(curr, next, cpu_id, p_guard, interrupts_were_enabled),
         preemption_guard,
     );
     
@@ -751,6 +957,7 @@ fn task_switch_inner(
     next: TaskRef,
     cpu_id: CpuId,
     preemption_guard: PreemptionGuard,
+    interrupts_were_enabled: bool,
 ) -> Result<TaskSwitchInnerRet, (bool, PreemptionGuard)> {
     let Some(curr) = curr_task_tls_slot.as_ref() else {
         error!("BUG: task_switch_inner(): couldn't get current task");
@@ -758,27 +965,30 @@ fn task_switch_inner(
     };
 
     // No need to task switch if the next task is the same as the current task.
+    // It may have been blocked and unblocked before it could switch out,
+    // which isn't a wakeup whose latency can be measured.
     if curr.id == next.id {
+        let _ = curr.take_runnable_since();
         return Err((false, preemption_guard));
     }
 
     // log::trace!("task_switch [0]: (CPU {}) prev {:?}, next {:?}, interrupts?: {}", cpu_id, curr, next, irq_safety::interrupts_enabled());
 
-    // These conditions are checked elsewhere, but can be re-enabled if we want to be extra strict.
-    // if !next.is_runnable() {
-    //     error!("BUG: Skipping task_switch due to scheduler bug: chosen 'next' Task was not Runnable! Current: {:?}, Next: {:?}", curr, next);
-    //     return (false, preemption_guard);
-    // }
-    // if next.is_running() {
-    //     error!("BUG: Skipping task_switch due to scheduler bug: chosen 'next' Task was already running on CPU {}!\nCurrent: {:?} Next: {:?}", cpu_id, curr, next);
-    //     return (false, preemption_guard);
-    // }
-    // if let Some(pc) = next.pinned_cpu() {
-    //     if pc != cpu_id {
-    //         error!("BUG: Skipping task_switch due to scheduler bug: chosen 'next' Task was pinned to CPU {:?} but scheduled on CPU {}!\n\tCurrent: {:?}, Next: {:?}", pc, cpu_id, curr, next);
-    //         return (false, preemption_guard);
-    //     }
-    // }
+    // The scheduler should only ever choose a runnable task that isn't running on another CPU
+    // and isn't pinned to another CPU. The chosen task may be blocked by another CPU
+    // after it was chosen, so that is only counted, but the other two are scheduler bugs
+    // that would corrupt the chosen task if we switched to it.
+    kassert_count!(next.is_runnable(), "chosen next task {:?} was not runnable", next);
+    let next_is_valid = kassert_once!(
+        !next.is_running(),
+        "chosen next task {:?} was already running on CPU {:?}", next, next.running_on_cpu(),
+    ) && kassert_once!(
+        next.pinned_cpu().map_or(true, |pinned_cpu| pinned_cpu == cpu_id),
+        "chosen next task {:?} was pinned to CPU {:?} but scheduled on CPU {}", next, next.pinned_cpu(), cpu_id,
+    );
+    if !next_is_valid {
+        return Err((false, preemption_guard));
+    }
 
     // Note that because userspace support is currently disabled, this will never happen.
     // // Change the privilege stack (RSP0) in the TSS.
@@ -790,13 +1000,11 @@ fn task_switch_inner(
     //         (kstack.bottom(), kstack.size_in_bytes())
     //     };
     //     let new_tss_rsp0 = stack_bottom + (stack_size / 2); // the middle half of the stack
-    //     if tss::tss_set_rsp0(new_tss_rsp0).is_ok() { 
-    //         // debug!("task_switch [2]: new_tss_rsp = {:#X}", new_tss_rsp0);
-    //     } else {
-    //         error!("task_switch(): failed to set CPU {} TSS RSP0, aborting task switch!", cpu_id);
-    //         return (false, preemption_guard);
-    //     }
+    //     // This cannot fail, so there's no need to abort the task switch midway.
+    //     tss::tss_set_rsp0(new_tss_rsp0);
+    //     // debug!("task_switch [2]: new_tss_rsp = {:#X}", new_tss_rsp0);
     // }
+    // // The userspace entry path should then call `tss::debug_assert_rsp0_within(&kstack)`.
 
     // // Switch page tables. 
     // // Since there is only a single address space (as userspace support is currently disabled),
@@ -819,14 +1027,29 @@ fn task_switch_inner(
     //     }
     // }
 
+    // Save the current task's intended interrupt state and load the next task's,
+    // which is restored after the context switch in `post_context_switch_action()`.
     let prev_task_saved_sp: *mut usize = {
         let mut inner = curr.0.task.inner().lock(); // ensure the lock is released
+        inner.saved_interrupts_enabled = interrupts_were_enabled;
         (&mut inner.saved_sp) as *mut usize
     };
-    let next_task_saved_sp: usize = {
+    let (next_task_saved_sp, next_interrupts_enabled) = {
         let inner = next.0.task.inner().lock(); // ensure the lock is released
-        inner.saved_sp
+        (inner.saved_sp, inner.saved_interrupts_enabled)
     };
+    TASK_SWITCH_INTERRUPTS_ENABLED.set_guarded(next_interrupts_enabled, &preemption_guard);
+
+    // Mark the start of a context switch on this CPU; see `CONTEXT_SWITCH_SEQ`.
+    if let Some(seq) = context_switch_seq(cpu_id) {
+        seq.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // The current task may have been woken up before it could switch out, in which case
+    // it's still runnable and its next switch-in would be a preemption, not a wakeup.
+    // This must happen before it's marked as no longer running, after which it may be
+    // woken up for real by another CPU.
+    let _ = curr.take_runnable_since();
 
     // Mark the current task as no longer running
     curr.0.task.running_on_cpu().store(None.into());
@@ -860,14 +1083,15 @@ fn task_switch_inner(
     // TLS variables for the current task (if exited), since the below call to 
     // `set_as_current_task()` will change the currently active TLS area on this CPU.
     //
-    // We briefly disable interrupts below to ensure that any interrupt handlers that may run
-    // on this CPU during the schedule/task_switch routines cannot observe inconsistencies
-    // in task runstates, e.g., when an interrupt handler accesses the current task context.
-    {
-        let _held_interrupts = hold_interrupts();
-        next.0.task.running_on_cpu().store(Some(cpu_id).into());
-        next.set_as_current_task();
-        drop(_held_interrupts);
+    // Interrupts are disabled for the entire task switch, so no interrupt handler
+    // on this CPU can observe inconsistencies in task runstates here,
+    // e.g., when an interrupt handler accesses the current task context.
+    next.0.task.running_on_cpu().store(Some(cpu_id).into());
+    next.set_as_current_task();
+
+    // Record how long the next task waited to run since it was woken up or spawned, if it was.
+    if let Some(runnable_since) = next.take_runnable_since() && !next.is_an_idle_task {
+        latency::record(cpu_id, next.nice(), runnable_since);
     }
 
     // Move the preemption guard into CPU-local storage such that we can retrieve it
@@ -882,21 +1106,84 @@ fn task_switch_inner(
 
 /// Perform any actions needed after a context switch.
 ///
-/// Currently this only does two things:
+/// Currently this does the following:
 /// 1. Drops any data that the original previous task (before the context switch)
 ///    prepared for us to drop.
 /// 2. Obtains the preemption guard such that preemption can be re-enabled
 ///    when it is appropriate to do so.
+/// 3. Allows the next timer interrupt on this CPU to invoke the scheduler;
+///    see [`scheduler::schedule_from_timer()`].
+/// 4. Invokes the task switch debug function, if one was set
+///    via [`set_task_switch_debug_func()`].
+/// 5. Restores the newly-current task's saved interrupt state,
+///    which completes the task switch.
+///
+/// This must be invoked with interrupts disabled, and is the only place
+/// where they are re-enabled after a task switch.
 fn post_context_switch_action() -> PreemptionGuard {
+    debug_assert!(
+        !irq_safety::interrupts_enabled(),
+        "BUG: post_context_switch_action(): interrupts were enabled during a task switch"
+    );
     let guard_1 = preemption::hold_preemption();
     let guard_2 = TASK_SWITCH_PREEMPTION_GUARD
         .replace_guarded(None, &guard_1)
         .expect("BUG: post_context_switch_action: no PreemptionGuard existed");
     // Doesn't really matter which guard we use.
     DROP_AFTER_TASK_SWITCH.set_guarded(None, &guard_2);
+    // Mark the end of the context switch on this CPU; see `CONTEXT_SWITCH_SEQ`.
+    if let Some(seq) = context_switch_seq(guard_2.cpu_id()) {
+        seq.fetch_add(1, Ordering::SeqCst);
+    }
+    scheduler::timer_schedule_completed(guard_2.cpu_id());
+    // Swap in the newly-current task's debugging state, e.g., whether branches are recorded.
+    if let Some(func) = TASK_SWITCH_DEBUG_FUNC.get() {
+        func();
+    }
+    if TASK_SWITCH_INTERRUPTS_ENABLED.replace_guarded(false, &guard_2) {
+        irq_safety::enable_interrupts();
+    }
     guard_2
 }
 
+/// The maximum number of CPUs whose context switches are tracked in [`CONTEXT_SWITCH_SEQ`].
+const MAX_TRACKED_CPUS: usize = 256;
+
+/// A sequence counter of context switches on each CPU, indexed by CPU ID,
+/// which is odd while a context switch is in progress on that CPU.
+///
+/// A task is marked as no longer running *before* the context switch routine
+/// saves its registers (including any SIMD/FP registers) onto its stack,
+/// so this is used to wait until a task's register context has been fully saved
+/// before that task can be run on a different CPU.
+static CONTEXT_SWITCH_SEQ: [AtomicUsize; MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; MAX_TRACKED_CPUS]
+};
+
+fn context_switch_seq(cpu_id: CpuId) -> Option<&'static AtomicUsize> {
+    CONTEXT_SWITCH_SEQ.get(cpu_id.value() as usize)
+}
+
+/// Returns the number of context switches that have completed on all CPUs since boot.
+pub fn context_switch_count() -> u64 {
+    CONTEXT_SWITCH_SEQ.iter()
+        .map(|seq| (seq.load(Ordering::Relaxed) / 2) as u64)
+        .sum()
+}
+
+/// Waits until any context switch that is in progress on the given CPU has completed.
+fn wait_for_context_switch(cpu_id: CpuId) {
+    let Some(seq) = context_switch_seq(cpu_id) else { return };
+    let start = seq.load(Ordering::SeqCst);
+    if start % 2 == 1 {
+        while seq.load(Ordering::SeqCst) == start {
+            core::hint::spin_loop();
+        }
+    }
+}
+
 
 /// The preemption guard that was used for safe task switching on each CPU.
 ///
@@ -907,6 +1194,13 @@ fn post_context_switch_action() -> PreemptionGuard {
 #[cls::cpu_local(stores_guard = PreemptionGuard)]
 static TASK_SWITCH_PREEMPTION_GUARD: Option<PreemptionGuard> = None;
 
+/// Whether interrupts should be enabled once the current task switch on each CPU completes.
+///
+/// This is the next task's saved interrupt state, which is loaded right before a context switch
+/// and then restored right after the context switch ends, from within the next task.
+#[cls::cpu_local]
+static TASK_SWITCH_INTERRUPTS_ENABLED: bool = false;
+
 /// Data that should be dropped after switching away from a task that has exited.
 ///
 /// Currently, this contains the previous Task's `TaskRef` removed from its TLS area;
@@ -919,7 +1213,7 @@ pub use tls_current_task::*;
 /// A private module to ensure the below TLS variables aren't modified directly.
 mod tls_current_task {
     use core::{cell::{Cell, RefCell}, ops::Deref};
-    use super::{TASKLIST, TaskRef, ExitableTaskRef};
+    use super::{tasklist, TaskRef, ExitableTaskRef};
 
     /// The TLS area that holds the current task's ID.
     #[thread_local]
@@ -982,6 +1276,14 @@ mod tls_current_task {
 
     /// Returns the unique ID of the current task.
     pub fn get_my_current_task_id() -> usize {
+        current_id()
+    }
+
+    /// Returns the unique ID of the current task, which is never reused within a boot.
+    ///
+    /// This is lock-free and doesn't touch the current task's `TaskRef`,
+    /// so it's cheap enough to use in logging.
+    pub fn current_id() -> usize {
         CURRENT_TASK_ID.get()
     }
 
@@ -1013,11 +1315,9 @@ mod tls_current_task {
             }
             t
         } else {
-            TASKLIST.lock()
-                .get(&current_task_id)
-                .cloned()
+            tasklist::get(current_task_id)
                 .ok_or_else(|| {
-                    log::error!("Couldn't find current_task_id {} in TASKLIST", current_task_id);
+                    log::error!("Couldn't find current_task_id {} in the task list", current_task_id);
                     InitCurrentTaskError::NotInTasklist(current_task_id)
                 })?
         };
@@ -1097,6 +1397,8 @@ pub fn bootstrap_task(
     let namespace = mod_mgmt::get_initial_kernel_namespace()
         .ok_or("Must initalize kernel CrateNamespace (mod_mgmt) before the tasking subsystem.")?
         .clone();
+    memory::set_current_account_func(current_memory_account);
+    sync::held::set_with_current_held_locks_func(with_current_held_locks);
     let env = Arc::new(Mutex::new(Environment::default()));
     let mut bootstrap_task = Task::new(
         Some(stack.into_inner()),
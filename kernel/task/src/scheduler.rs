@@ -1,7 +1,9 @@
 use alloc::{boxed::Box, sync::Arc, vec::Vec};
-use core::ptr;
+use core::{ptr, sync::atomic::{AtomicBool, AtomicU64, Ordering}};
 
 use cpu::CpuId;
+use cpu_topology::Placement;
+use preemption::PreemptionGuard;
 use spin::Mutex;
 use sync_preemption::PreemptionSafeMutex;
 
@@ -26,14 +28,86 @@ static SCHEDULER: Option<Arc<ConcurrentScheduler>> = None;
 
 type ConcurrentScheduler = PreemptionSafeMutex<dyn Scheduler>;
 
+/// Whether a timer interrupt handler on each CPU has invoked the scheduler
+/// and its task switch has not yet completed, indexed by CPU ID.
+///
+/// See [`schedule_from_timer()`].
+static TIMER_SCHEDULE_IN_PROGRESS: [AtomicBool; crate::MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FALSE: AtomicBool = AtomicBool::new(false);
+    [FALSE; crate::MAX_TRACKED_CPUS]
+};
+
+/// Whether tasking has been initialized on all CPUs; see [`set_tasking_initialized()`].
+static TASKING_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// The number of times each CPU's scheduler found no runnable task,
+/// not even its idle task, indexed by CPU ID.
+///
+/// See [`empty_runqueue_count()`].
+static EMPTY_RUNQUEUE_EVENTS: [AtomicU64; crate::MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; crate::MAX_TRACKED_CPUS]
+};
+
+/// Whether an empty runqueue has already been reported on each CPU, indexed by CPU ID.
+static EMPTY_RUNQUEUE_REPORTED: [AtomicBool; crate::MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FALSE: AtomicBool = AtomicBool::new(false);
+    [FALSE; crate::MAX_TRACKED_CPUS]
+};
+
+/// Whether each CPU has been taken offline, indexed by CPU ID; see [`offline_cpu()`].
+static OFFLINE: [AtomicBool; crate::MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FALSE: AtomicBool = AtomicBool::new(false);
+    [FALSE; crate::MAX_TRACKED_CPUS]
+};
+
+/// Tasks that must run on an offline CPU, which are held here (and thus not run)
+/// until that CPU is brought back online.
+static PARKED: Mutex<Vec<(CpuId, TaskRef)>> = Mutex::new(Vec::new());
+
+/// Serializes taking CPUs offline and bringing them back online.
+///
+/// This is separate from [`PARKED`] because taking a CPU offline waits for its tasks
+/// to be switched out, and those tasks may add other tasks to that CPU in the meantime.
+static HOTPLUG: Mutex<()> = Mutex::new(());
+
 /// Yields the current CPU by selecting a new `Task` to run next,
 /// and then switches to that new `Task`.
 ///
 /// The new "next" `Task` to run will be selected by the currently-active
 /// scheduler policy.
 ///
-/// Preemption will be disabled while this function runs,
-/// but interrupts are not disabled because it is not necessary.
+/// ## Interrupts and preemption
+/// This may be invoked with interrupts enabled or disabled.
+/// The task switch follows this protocol with respect to interrupts:
+/// 1. Upon entry, this records whether interrupts were enabled as the current task's
+///    intended interrupt state, and then disables interrupts (and preemption).
+/// 2. Interrupts remain disabled from that point, through the selection of the next task,
+///    until the context switch has completed, such that no interrupt can be taken
+///    on this CPU while the current task's state is only partially saved
+///    or the next task's state is only partially restored.
+/// 3. The context switch saves the current task's intended interrupt state
+///    and loads the next task's saved interrupt state.
+/// 4. The next task restores its own interrupt state right after the context switch,
+///    in the post-context switch actions; for a new task, this occurs in the
+///    spawn trampoline, which always starts with interrupts disabled.
+///
+/// Thus, when this returns, interrupts are enabled if and only if
+/// they were enabled when this was invoked, regardless of which tasks ran in between.
+/// Violations of this protocol are caught by debug assertions.
+///
+/// ## Empty runqueue
+/// If the scheduler finds no runnable task, not even this CPU's idle task,
+/// the current task keeps running until tasking has been initialized
+/// (see [`set_tasking_initialized()`]).
+/// After that, this is a bug, so the runqueue contents are reported once per CPU
+/// and this CPU is parked with interrupts enabled until a runnable task
+/// is added to its runqueue, rather than spinning through the scheduler.
+/// Either way, the event is counted in [`empty_runqueue_count()`].
 ///
 /// ## Return
 /// * `true` if a new task was selected and switched to.
@@ -41,30 +115,190 @@ type ConcurrentScheduler = PreemptionSafeMutex<dyn Scheduler>;
 ///   continue running.
 #[doc(alias("yield"))]
 pub fn schedule() -> bool {
+    let interrupts_were_enabled = irq_safety::interrupts_enabled();
+    irq_safety::disable_interrupts();
+
     let preemption_guard = preemption::hold_preemption();
     // If preemption was not previously enabled (before we disabled it above),
     // then we shouldn't perform a task switch here.
     if !preemption_guard.preemption_was_enabled() {
         // trace!("Note: preemption was disabled on CPU {}, skipping scheduler.", cpu::current_cpu());
+        drop(preemption_guard);
+        restore_interrupts(interrupts_were_enabled);
         return false;
     }
 
     let cpu_id = preemption_guard.cpu_id();
 
-    let next_task = SCHEDULER.update_guarded(
+    let mut next_task = SCHEDULER.update_guarded(
         |scheduler| scheduler.as_ref().unwrap().lock().next(),
         &preemption_guard,
     );
+    if !next_task.is_runnable() {
+        match handle_empty_runqueue(cpu_id, &preemption_guard) {
+            Some(task) => next_task = task,
+            None => {
+                drop(preemption_guard);
+                restore_interrupts(interrupts_were_enabled);
+                return false;
+            }
+        }
+    }
 
     let (did_switch, recovered_preemption_guard) =
-        super::task_switch(next_task, cpu_id, preemption_guard);
+        super::task_switch(next_task, cpu_id, preemption_guard, interrupts_were_enabled);
 
     // log::trace!("AFTER TASK_SWITCH CALL (CPU {}) new current: {:?}, interrupts are {}", cpu_id, super::get_my_current_task(), irq_safety::interrupts_enabled());
 
     drop(recovered_preemption_guard);
+    if did_switch {
+        // This task's saved interrupt state was already restored after the context switch.
+        debug_assert_eq!(
+            irq_safety::interrupts_enabled(),
+            interrupts_were_enabled,
+            "BUG: schedule(): a task switch did not restore this task's interrupt state"
+        );
+    } else {
+        restore_interrupts(interrupts_were_enabled);
+    }
     did_switch
 }
 
+/// Handles this CPU's scheduler having found no runnable task, not even its idle task.
+///
+/// Returns the runnable task that this CPU was parked until, or `None` if tasking
+/// has not yet been initialized and the current task should keep running.
+fn handle_empty_runqueue(cpu_id: CpuId, preemption_guard: &PreemptionGuard) -> Option<TaskRef> {
+    let index = cpu_id.value() as usize;
+    if let Some(count) = EMPTY_RUNQUEUE_EVENTS.get(index) {
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+    if !TASKING_INITIALIZED.load(Ordering::Acquire) {
+        return None;
+    }
+
+    let already_reported = EMPTY_RUNQUEUE_REPORTED.get(index)
+        .map_or(false, |reported| reported.swap(true, Ordering::Relaxed));
+    if !already_reported {
+        let runqueue = SCHEDULER.update_guarded(
+            |scheduler| scheduler.as_ref().unwrap().lock().tasks(),
+            preemption_guard,
+        );
+        log::error!(
+            "BUG: CPU {} found no runnable task, not even its idle task; \
+            parking it until a task is added. Runqueue: {:?}",
+            cpu_id, runqueue,
+        );
+    }
+
+    loop {
+        wait_for_interrupt();
+        let next_task = SCHEDULER.update_guarded(
+            |scheduler| scheduler.as_ref().unwrap().lock().next(),
+            preemption_guard,
+        );
+        if next_task.is_runnable() {
+            return Some(next_task);
+        }
+    }
+}
+
+/// Enables interrupts, waits for one to arrive and be handled, and then disables interrupts again.
+///
+/// This must be invoked with interrupts disabled.
+fn wait_for_interrupt() {
+    #[cfg(target_arch = "x86_64")] {
+        // SAFETY: `sti` takes effect only after the following `hlt`,
+        // so an interrupt cannot be taken in between and then missed by `hlt`.
+        unsafe { core::arch::asm!("sti; hlt; cli", options(nomem, nostack)) };
+    }
+    #[cfg(target_arch = "aarch64")] {
+        // SAFETY: `wfi` wakes up upon a pending interrupt even while interrupts are masked,
+        // which is then taken once interrupts are enabled below.
+        unsafe { core::arch::asm!("wfi", options(nomem, nostack, preserves_flags)) };
+        irq_safety::enable_interrupts();
+        irq_safety::disable_interrupts();
+    }
+}
+
+/// Marks tasking as initialized on all CPUs.
+///
+/// From then on, a CPU whose scheduler finds no runnable task, not even its idle task,
+/// is parked rather than continuing to run its current task; see [`schedule()`].
+pub fn set_tasking_initialized() {
+    TASKING_INITIALIZED.store(true, Ordering::Release);
+}
+
+/// Returns the number of times the given CPU's scheduler found no runnable task,
+/// not even its idle task.
+///
+/// Returns `None` if the given CPU is not tracked.
+pub fn empty_runqueue_count(cpu_id: CpuId) -> Option<u64> {
+    EMPTY_RUNQUEUE_EVENTS.get(cpu_id.value() as usize)
+        .map(|count| count.load(Ordering::Relaxed))
+}
+
+/// Returns the number of runnable tasks in the current CPU's run queue, not counting its idle task.
+///
+/// Returns `None` if this CPU's scheduler is currently locked, e.g., by the code that
+/// this interrupted, or hasn't been set up yet.
+/// This never blocks and shouldn't allocate, so it can be invoked from an interrupt handler.
+pub fn current_runnable_count() -> Option<usize> {
+    let preemption_guard = preemption::hold_preemption();
+    SCHEDULER.update_guarded(
+        |scheduler| scheduler.as_ref()?.try_lock().map(|scheduler| scheduler.runnable_count()),
+        &preemption_guard,
+    )
+}
+
+/// Re-enables interrupts if they were enabled before [`schedule()`] disabled them.
+fn restore_interrupts(interrupts_were_enabled: bool) {
+    if interrupts_were_enabled {
+        irq_safety::enable_interrupts();
+    }
+}
+
+/// Invokes the scheduler from a timer interrupt handler,
+/// coalescing any nested timer interrupts that arrive during the resulting task switch.
+///
+/// A timer interrupt handler must send its EOI *before* invoking the scheduler,
+/// because the task switch doesn't return until the current task is next scheduled in.
+/// Thus, another timer interrupt may arrive on this CPU before the task switch completes;
+/// rather than invoking the scheduler again, that nested timer interrupt is ignored.
+/// The next timer interrupt after the next task has been switched in
+/// will invoke the scheduler as usual.
+///
+/// This must be invoked with interrupts disabled, i.e., from an interrupt handler.
+///
+/// ## Return
+/// * `true` if a new task was selected and switched to.
+/// * `false` if no new task was selected, or if this was a nested timer interrupt.
+pub fn schedule_from_timer() -> bool {
+    let cpu_id = cpu::current_cpu();
+    let Some(in_progress) = TIMER_SCHEDULE_IN_PROGRESS.get(cpu_id.value() as usize) else {
+        return schedule();
+    };
+    if in_progress.swap(true, Ordering::AcqRel) {
+        return false;
+    }
+    let did_switch = schedule();
+    // If a task switch occurred, the flag was already cleared on the CPU we are now running on
+    // once this task was switched back in, which may differ from `cpu_id` due to migration.
+    if !did_switch {
+        in_progress.store(false, Ordering::Release);
+    }
+    did_switch
+}
+
+/// Marks the end of any task switch invoked by [`schedule_from_timer()`] on the given CPU.
+///
+/// This is invoked in the context of the newly-current task after every task switch.
+pub(crate) fn timer_schedule_completed(cpu_id: CpuId) {
+    if let Some(in_progress) = TIMER_SCHEDULE_IN_PROGRESS.get(cpu_id.value() as usize) {
+        in_progress.store(false, Ordering::Release);
+    }
+}
+
 /// Sets the scheduler policy for the given CPU.
 pub fn set_policy<T>(cpu_id: CpuId, scheduler: T)
 where
@@ -103,25 +337,75 @@ where
 }
 
 /// Adds the given task to the least busy run queue.
+///
+/// Among equally busy CPUs, this prefers one whose SMT siblings are the least busy,
+/// such that tasks fill idle physical cores before sharing a core with a busy task.
 pub fn add_task(task: TaskRef) {
+    add_task_with_placement_internal(task, None);
+}
+
+/// Adds the given task to the least busy run queue that satisfies the given placement hint.
+///
+/// If no CPU with a run queue satisfies the hint, e.g., because the topology of
+/// the CPU in the hint is unknown, this falls back to [`add_task()`].
+///
+/// Returns the CPU whose run queue the task was added to.
+pub fn add_task_with_placement(task: TaskRef, placement: Placement) -> CpuId {
+    add_task_with_placement_internal(task, Some(placement))
+}
+
+fn add_task_with_placement_internal(task: TaskRef, placement: Option<Placement>) -> CpuId {
     let locked = SCHEDULERS.lock();
 
-    let mut min_busyness = usize::MAX;
-    let mut least_busy_index = None;
+    let busyness = locked.iter()
+        .filter(|(cpu, _)| !is_offline(*cpu))
+        .map(|(cpu, scheduler)| (*cpu, scheduler.lock().busyness()))
+        .collect::<Vec<_>>();
+    let busyness_of = |cpu: CpuId| busyness.iter()
+        .find(|(c, _)| *c == cpu)
+        .map_or(0, |(_, b)| *b);
+    // The combined busyness of the given CPU's SMT siblings.
+    let sibling_busyness = |cpu: CpuId| cpu_topology::smt_siblings(cpu)
+        .into_iter()
+        .map(busyness_of)
+        .sum::<usize>();
+    let least_busy = |allowed: &dyn Fn(CpuId) -> bool| busyness.iter()
+        .enumerate()
+        .filter(|(_, (cpu, _))| allowed(*cpu))
+        .min_by_key(|(_, (cpu, busyness))| {
+            let siblings = sibling_busyness(*cpu);
+            if placement == Some(Placement::Spread) {
+                (busyness + siblings, *busyness)
+            } else {
+                (*busyness, siblings)
+            }
+        })
+        .map(|(i, _)| i);
+
+    let index = placement
+        .and_then(|placement| least_busy(&|cpu| placement.allows(cpu)))
+        .or_else(|| least_busy(&|_| true))
+        .expect("BUG: there are no run queues to add a task to");
 
-    for (i, (_, scheduler)) in locked.iter().enumerate() {
-        let busyness = scheduler.lock().busyness();
-        if busyness < min_busyness {
-            least_busy_index = Some(i);
-            min_busyness = busyness;
-        }
+    let cpu = busyness[index].0;
+    if let Some((_, scheduler)) = locked.iter().find(|(c, _)| *c == cpu) {
+        scheduler.lock().add(task);
     }
-
-    locked[least_busy_index.unwrap()].1.lock().add(task);
+    cpu
 }
 
 /// Adds the given task to the specified CPU's run queue.
+///
+/// If that CPU is offline, the task isn't run until that CPU is brought back online.
 pub fn add_task_to(cpu_id: CpuId, task: TaskRef) {
+    if is_offline(cpu_id) {
+        let mut parked = PARKED.lock();
+        // Check again now that the CPU can't be brought back online concurrently.
+        if is_offline(cpu_id) {
+            parked.push((cpu_id, task));
+            return;
+        }
+    }
     for (cpu, scheduler) in SCHEDULERS.lock().iter() {
         if *cpu == cpu_id {
             scheduler.lock().add(task);
@@ -161,6 +445,175 @@ pub fn remove_task_from_current(task: &TaskRef) -> bool {
     SCHEDULER.update(|scheduler| scheduler.as_ref().unwrap().lock().remove(task))
 }
 
+/// Migrates the given task from CPU `from`'s run queue to CPU `to`'s run queue.
+///
+/// If the task is currently running on `from`, this waits until it has been
+/// switched out and its register context has been completely saved.
+/// SIMD/FP registers are saved eagerly on every context switch (there is no
+/// lazy FPU ownership), so once the switch out of the task has completed,
+/// its FP state is safely on its stack and no IPI to `from` is needed.
+///
+/// If the task is pinned to `from`, it is re-pinned to `to`.
+///
+/// Returns an error if the task is the current task, is pinned to a CPU
+/// other than `from`, or isn't on `from`'s run queue,
+/// or if `to` has no run queue or is offline.
+pub fn migrate_task(task: &TaskRef, from: CpuId, to: CpuId) -> Result<(), &'static str> {
+    if from == to {
+        return Ok(());
+    }
+    if crate::with_current_task(|curr| curr == task).unwrap_or(false) {
+        return Err("cannot migrate the current task");
+    }
+    if task.pinned_cpu().is_some_and(|pinned| pinned != from) {
+        return Err("task is pinned to a different CPU");
+    }
+    if !SCHEDULERS.lock().iter().any(|(cpu, _)| *cpu == to) {
+        return Err("destination CPU has no run queue");
+    }
+    if is_offline(to) {
+        return Err("destination CPU is offline");
+    }
+    if !remove_task_from(task, from) {
+        return Err("task was not on the source CPU's run queue");
+    }
+
+    // The task is no longer on any run queue, so once it stops running on `from`
+    // it won't be scheduled again until we add it to `to`.
+    while task.running_on_cpu() == Some(from) {
+        core::hint::spin_loop();
+    }
+    // A task is marked as not running before its registers have been saved.
+    crate::wait_for_context_switch(from);
+
+    {
+        let mut inner = task.0.task.inner().lock();
+        if inner.pinned_cpu.is_some() {
+            inner.pinned_cpu = Some(to);
+        }
+    }
+    add_task_to(to, task.clone());
+    Ok(())
+}
+
+/// Returns whether the given CPU has been taken offline by [`offline_cpu()`].
+fn is_offline(cpu_id: CpuId) -> bool {
+    OFFLINE.get(cpu_id.value() as usize).is_some_and(|offline| offline.load(Ordering::Acquire))
+}
+
+/// Returns the CPUs that have a run queue and haven't been taken offline,
+/// i.e., those that new tasks can be scheduled on.
+pub fn online_cpus() -> Vec<CpuId> {
+    SCHEDULERS.lock().iter()
+        .map(|(cpu, _)| *cpu)
+        .filter(|cpu| !is_offline(*cpu))
+        .collect()
+}
+
+/// Takes the given CPU offline, such that it only runs its idle task.
+///
+/// New tasks are no longer placed on this CPU, and every task on its run queue
+/// (other than its idle task) is migrated to the least busy online CPU.
+/// Tasks pinned to this CPU are instead removed from its run queue and held
+/// until it's brought back online by [`online_cpu()`], as are tasks that are later added to it.
+/// The CPU itself keeps running its idle task, which halts it until the next interrupt,
+/// so it can be brought back online without booting it again.
+///
+/// Returns the number of tasks that were migrated to other CPUs.
+///
+/// Returns an error if the CPU is the bootstrap CPU, which handles system-wide duties,
+/// is the current CPU, has no run queue, or is already offline.
+pub fn offline_cpu(cpu_id: CpuId) -> Result<usize, &'static str> {
+    let _hotplug = HOTPLUG.lock();
+    if cpu::bootstrap_cpu() == Some(cpu_id) {
+        return Err("the bootstrap CPU cannot be taken offline");
+    }
+    if cpu::current_cpu() == cpu_id {
+        return Err("the current CPU cannot be taken offline");
+    }
+    let scheduler = SCHEDULERS.lock().iter()
+        .find(|(cpu, _)| *cpu == cpu_id)
+        .map(|(_, scheduler)| Arc::clone(scheduler))
+        .ok_or("the CPU has no run queue")?;
+    let offline = OFFLINE.get(cpu_id.value() as usize).ok_or("the CPU ID is too large")?;
+    if offline.swap(true, Ordering::AcqRel) {
+        return Err("the CPU is already offline");
+    }
+
+    // Tasks may still be added to this CPU's run queue by a concurrent `add_task_to()`
+    // that checked whether it was offline just before it was marked as such,
+    // so repeat until there's nothing left to move.
+    let mut migrated = 0;
+    loop {
+        let tasks = scheduler.lock().tasks();
+        let mut moved_any = false;
+        for task in tasks.into_iter().filter(|t| !t.is_an_idle_task) {
+            if task.pinned_cpu() == Some(cpu_id) {
+                if !scheduler.lock().remove(&task) {
+                    continue;
+                }
+                // It won't be scheduled again, but may still be running until its timeslice ends.
+                while task.running_on_cpu() == Some(cpu_id) {
+                    core::hint::spin_loop();
+                }
+                crate::wait_for_context_switch(cpu_id);
+                PARKED.lock().push((cpu_id, task));
+                moved_any = true;
+            } else {
+                let Some(to) = least_busy_online_cpu() else {
+                    log::error!("BUG: offline_cpu(): there are no online CPUs to migrate tasks to");
+                    return Ok(migrated);
+                };
+                match migrate_task(&task, cpu_id, to) {
+                    Ok(()) => {
+                        migrated += 1;
+                        moved_any = true;
+                    }
+                    // The task may have exited and been removed from the run queue in the meantime.
+                    Err(e) => log::warn!("offline_cpu(): couldn't migrate task {} from CPU {}: {}", &*task, cpu_id, e),
+                }
+            }
+        }
+        if !moved_any {
+            break;
+        }
+    }
+    Ok(migrated)
+}
+
+/// Brings the given CPU back online after it was taken offline by [`offline_cpu()`],
+/// such that new tasks can be placed on it again.
+///
+/// Tasks that were held because they must run on this CPU are added back to its run queue,
+/// but tasks that were migrated away from it stay where they are.
+///
+/// Returns the number of tasks that were added back to its run queue.
+pub fn online_cpu(cpu_id: CpuId) -> Result<usize, &'static str> {
+    let _hotplug = HOTPLUG.lock();
+    let mut parked = PARKED.lock();
+    let offline = OFFLINE.get(cpu_id.value() as usize).ok_or("the CPU ID is too large")?;
+    if !offline.swap(false, Ordering::AcqRel) {
+        return Err("the CPU is not offline");
+    }
+    let (resumed, still_parked): (Vec<_>, Vec<_>) = parked.drain(..).partition(|(cpu, _)| *cpu == cpu_id);
+    *parked = still_parked;
+    drop(parked);
+
+    let count = resumed.len();
+    for (_, task) in resumed {
+        add_task_to(cpu_id, task);
+    }
+    Ok(count)
+}
+
+/// Returns the least busy CPU that is online.
+fn least_busy_online_cpu() -> Option<CpuId> {
+    SCHEDULERS.lock().iter()
+        .filter(|(cpu, _)| !is_offline(*cpu))
+        .min_by_key(|(_, scheduler)| scheduler.lock().busyness())
+        .map(|(cpu, _)| *cpu)
+}
+
 /// A task scheduler.
 pub trait Scheduler: Send + Sync + 'static {
     /// Returns the next task to run.
@@ -173,6 +626,14 @@ pub trait Scheduler: Send + Sync + 'static {
     /// representing a busier scheduler.
     fn busyness(&self) -> usize;
 
+    /// Returns the number of runnable tasks in the run queue, not counting idle tasks,
+    /// e.g., for computing the load average.
+    ///
+    /// This is invoked from the timer interrupt handler, so implementations should not allocate.
+    fn runnable_count(&self) -> usize {
+        self.tasks().iter().filter(|task| task.is_runnable() && !task.is_an_idle_task).count()
+    }
+
     /// Removes a task from the run queue.
     fn remove(&mut self, task: &TaskRef) -> bool;
 
@@ -230,6 +691,23 @@ pub fn set_priority(task: &TaskRef, priority: u8) -> bool {
     false
 }
 
+/// Adjusts the current task's nice value by `delta`,
+/// e.g., a background task can lower its own share of CPU time
+/// by passing a positive `delta`.
+///
+/// The resulting nice value is clamped to the range `[MIN_NICE, MAX_NICE]`.
+/// Unlike a priority, a nice value doesn't strictly reorder tasks;
+/// it only biases how often a scheduler that supports it selects the task.
+///
+/// Returns the current task's new nice value,
+/// or `None` if the current task could not be obtained.
+pub fn nice(delta: i8) -> Option<i8> {
+    super::with_current_task(|current_task| {
+        current_task.set_nice(current_task.nice().saturating_add(delta))
+    })
+    .ok()
+}
+
 /// Returns the busyness of the scheduler on the given CPU,
 /// in which higher values indicate a busier scheduler.
 pub fn busyness(cpu_id: CpuId) -> Option<usize> {
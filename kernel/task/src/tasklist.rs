@@ -0,0 +1,85 @@
+//! The system-wide list of all tasks, stored as a generational slot map.
+//!
+//! Each task lives in the slot given by the index part of its ID
+//! (see [`task_struct::task_id_index()`]), and a lookup by ID only succeeds
+//! if the task in that slot has exactly that ID, i.e., the same generation.
+//! Thus, a stale ID of a task that has since been reaped will never match
+//! a newer task that reuses the same slot.
+//!
+//! Slots are allocated in fixed-size chunks that are never freed or moved,
+//! so there is no global lock: lookups, insertions, and removals only lock
+//! the one slot they access, and iteration only locks one slot at a time.
+
+use alloc::{boxed::Box, vec::Vec};
+use spin::Once;
+use sync_irq::IrqSafeRwLock;
+use task_struct::task_id_index;
+use super::{TaskRef, WeakTaskRef};
+
+/// The number of slots in each chunk.
+const SLOTS_PER_CHUNK: usize = 256;
+/// The maximum number of chunks, which limits the number of tasks that can exist at once.
+const MAX_CHUNKS: usize = 1024;
+
+type TaskSlot = IrqSafeRwLock<Option<TaskRef>>;
+
+/// The chunks of task slots, each of which is allocated when first needed.
+static CHUNKS: [Once<Box<[TaskSlot]>>; MAX_CHUNKS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const UNALLOCATED: Once<Box<[TaskSlot]>> = Once::new();
+    [UNALLOCATED; MAX_CHUNKS]
+};
+
+/// Returns the slot for the given task `id`, if it has been allocated.
+fn slot(id: usize) -> Option<&'static TaskSlot> {
+    let index = task_id_index(id);
+    let chunk = CHUNKS.get(index / SLOTS_PER_CHUNK)?.get()?;
+    Some(&chunk[index % SLOTS_PER_CHUNK])
+}
+
+/// Adds the given task to its slot in the task list, allocating that slot if needed.
+pub(crate) fn insert(taskref: TaskRef) {
+    let index = task_id_index(taskref.id);
+    let chunk = CHUNKS.get(index / SLOTS_PER_CHUNK)
+        .expect("BUG: too many tasks exist at once for the task list")
+        .call_once(|| (0..SLOTS_PER_CHUNK).map(|_| IrqSafeRwLock::new(None)).collect());
+    let mut slot = chunk[index % SLOTS_PER_CHUNK].write();
+    assert!(slot.is_none(), "BUG: the task list slot for a new task was already occupied");
+    *slot = Some(taskref);
+}
+
+/// Removes the task with the given `id` from the task list, if it exists.
+pub(crate) fn remove(id: usize) -> Option<TaskRef> {
+    let mut slot = slot(id)?.write();
+    if slot.as_ref().is_some_and(|t| t.id == id) {
+        slot.take()
+    } else {
+        None
+    }
+}
+
+/// Returns a strong reference to the task with the given `id`, if it exists.
+pub(crate) fn get(id: usize) -> Option<TaskRef> {
+    slot(id)?.read().as_ref().filter(|t| t.id == id).cloned()
+}
+
+/// Returns a weak reference to the task with the given `id`, if it exists.
+pub(crate) fn get_weak(id: usize) -> Option<WeakTaskRef> {
+    slot(id)?.read().as_ref().filter(|t| t.id == id).map(TaskRef::downgrade)
+}
+
+/// Returns a snapshot of all tasks in the task list.
+///
+/// This never blocks task creation or removal for longer than it takes to read one slot.
+/// Tasks that exist for the entire duration of this call are always included.
+pub(crate) fn snapshot() -> Vec<(usize, WeakTaskRef)> {
+    let mut tasks = Vec::new();
+    for chunk in CHUNKS.iter().filter_map(Once::get) {
+        for slot in chunk.iter() {
+            if let Some(t) = slot.read().as_ref() {
+                tasks.push((t.id, t.downgrade()));
+            }
+        }
+    }
+    tasks
+}
@@ -255,14 +255,20 @@ impl TaskFile {
         } else {
             " "
         };  
+        let memory = format!("{} KiB", taskref.memory_usage() / 1024);
+        let limit = taskref.memory_limit()
+            .map(|limit| format!("{} KiB", limit / 1024))
+            .unwrap_or_else(|| String::from("none"));
 
-        format!("{0:<10} {1}\n{2:<10} {3}\n{4:<10} {5:?}\n{6:<10} {7}\n{8:<10} {9}\n{10:<10} {11:<10}", 
+        format!("{0:<10} {1}\n{2:<10} {3}\n{4:<10} {5:?}\n{6:<10} {7}\n{8:<10} {9}\n{10:<10} {11:<10}\n{12:<10} {13}\n{14:<10} {15}", 
             "name", taskref.name,
             "task id", taskref.id,
             "runstate", taskref.runstate(),
             "cpu", cpu,
             "pinned", pinned,
-            "task type", task_type
+            "task type", task_type,
+            "memory", memory,
+            "mem limit", limit,
         )
     }
 }
@@ -11,13 +11,15 @@
 
 extern crate alloc;
 
+mod task_id;
+pub use task_id::{task_id_generation, task_id_index, TASK_ID_INDEX_BITS};
 use core::{
     any::Any,
     fmt,
     hash::{Hash, Hasher},
     ops::Deref,
     panic::PanicInfo,
-    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicI8, AtomicUsize, Ordering},
     task::Waker,
 };
 use alloc::{
@@ -25,22 +27,68 @@ use alloc::{
     format,
     string::String,
     sync::Arc,
+    vec::Vec,
 };
 use cpu::{CpuId, OptionalCpuId};
 use crossbeam_utils::atomic::AtomicCell;
+use sync::HeldLocks;
 use sync_irq::IrqSafeMutex;
 use log::{warn, trace};
-use memory::MmiRef;
+use memory::{MemoryAccount, MmiRef};
 use stack::Stack;
-use kernel_config::memory::KERNEL_STACK_SIZE_IN_PAGES;
+use kernel_config::memory::{KERNEL_STACK_SIZE_IN_PAGES, PAGE_SIZE};
 use mod_mgmt::{AppCrateRef, CrateNamespace, TlsDataImage};
 use environment::Environment;
 use spin::Mutex;
+use time::Instant;
+
+/// The lowest (most favorable) nice value that a `Task` can have.
+pub const MIN_NICE: i8 = -20;
+/// The highest (least favorable) nice value that a `Task` can have.
+pub const MAX_NICE: i8 = 19;
+
+/// Whether tasks record when they become runnable; see [`enable_wakeup_timestamps()`].
+static WAKEUP_TIMESTAMPS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Makes every `Task` record the time at which it becomes runnable,
+/// which can later be obtained via [`Task::take_runnable_since()`].
+///
+/// This must only be called once a monotonic clock source has been registered,
+/// as it's read every time a task is unblocked or spawned from then on.
+pub fn enable_wakeup_timestamps() {
+    WAKEUP_TIMESTAMPS_ENABLED.store(true, Ordering::Relaxed);
+}
 
 /// The function signature of the callback that will be invoked when a `Task`
 /// panics or otherwise fails, e.g., a machine exception occurs.
 pub type KillHandler = Box<dyn Fn(&KillReason) + Send>;
 
+/// The function signature of a callback that releases a resource held by a `Task`
+/// when that `Task` exits or is killed without having released it.
+///
+/// See [`Task::register_cleanup()`].
+pub type CleanupHook = Box<dyn FnOnce(CleanupReason) + Send>;
+
+/// The reason that a `Task`'s [`CleanupHook`]s are being invoked.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CleanupReason {
+    /// The `Task` ran to completion or was cleaned up by unwinding,
+    /// so its hooks are being invoked in the context of that `Task` itself.
+    ///
+    /// Any resources that are still registered at this point were either leaked
+    /// (e.g., via `mem::forget`) or moved to another task.
+    Exited,
+    /// The `Task` was killed asynchronously by another task,
+    /// so its hooks are being invoked by that other task on its behalf,
+    /// after the killed `Task` has been permanently descheduled.
+    Killed,
+}
+
+/// An identifier for a [`CleanupHook`] registered with a `Task`,
+/// which can be used to unregister it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CleanupHookId(usize);
+
 /// Just like `core::panic::PanicInfo`, but with owned String types instead of &str references.
 #[derive(Debug, Default)]
 pub struct PanicInfoOwned {
@@ -142,6 +190,24 @@ pub enum RunState {
 }
 
 
+/// The reason that a blocked `Task` was unblocked, as recorded by the task that unblocked it.
+///
+/// A blocking primitive reads this upon wakeup (via [`Task::take_wake_reason()`])
+/// to determine whether it should return successfully or with an error.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WakeReason {
+    /// The condition that the task was waiting for was satisfied.
+    Condition,
+    /// The task's wait expired before its condition was satisfied.
+    TimedOut,
+    /// The task's wait was interrupted by another task, e.g., to cancel it.
+    ///
+    /// Only interruptible blocking primitives honor this;
+    /// others treat it as a spurious wakeup and continue waiting.
+    Interrupted,
+}
+
+
 #[cfg(simd_personality)]
 /// The supported levels of SIMD extensions that a `Task` can use.
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -179,6 +245,12 @@ pub struct RestartInfo {
 pub struct TaskInner {
     /// the saved stack pointer value, used for task switching.
     pub saved_sp: usize,
+    /// Whether interrupts should be enabled when this task is next switched to.
+    ///
+    /// This is saved by the task switch routine when switching away from this task,
+    /// since interrupts are always disabled during a task switch.
+    /// See `task::scheduler::schedule()` for the full protocol.
+    pub saved_interrupts_enabled: bool,
     /// The kernel stack, which all `Task`s must have in order to execute.
     pub kstack: Stack,
     /// Whether or not this task is pinned to a certain CPU.
@@ -195,6 +267,8 @@ pub struct TaskInner {
     pub restart_info: Option<RestartInfo>,
     /// The waker that is awoken when this task completes.
     pub waker: Option<Waker>,
+    /// The cleanup hooks registered for this task, in order of registration.
+    cleanup_hooks: Vec<(CleanupHookId, CleanupHook)>,
 }
 
 
@@ -213,7 +287,8 @@ pub struct Task {
     /// This must not be public because it permits interior mutability of key task states.
     inner: IrqSafeMutex<TaskInner>,
 
-    /// The unique identifier of this Task.
+    /// The unique identifier of this Task, which is a generational index
+    /// into the system-wide task list; see [`task_id_index()`] and [`task_id_generation()`].
     pub id: usize,
     /// The simple name of this Task.
     pub name: String,
@@ -228,12 +303,51 @@ pub struct Task {
     ///
     /// This is not public because it permits interior mutability.
     runstate: AtomicCell<RunState>,
+    /// Why this task was most recently unblocked, if that hasn't yet been taken
+    /// by the blocking primitive it was waiting in.
+    ///
+    /// This is not public because it permits interior mutability.
+    wake_reason: AtomicCell<Option<WakeReason>>,
+    /// When this task most recently became runnable after being blocked or spawned,
+    /// or [`Instant::ZERO`] if that hasn't happened since it was last switched in.
+    ///
+    /// This is only recorded once [`enable_wakeup_timestamps()`] has been called.
+    ///
+    /// This is not public because it permits interior mutability.
+    runnable_since: AtomicCell<Instant>,
     /// Whether the task is suspended.
     ///
     /// This is only triggered by a Ctrl + Z in the terminal.
     ///
     /// This is not public because it permits interior mutability.
     suspended: AtomicBool,
+    /// The nice value of this task, in the range `[MIN_NICE, MAX_NICE]`.
+    ///
+    /// Higher values cause schedulers that support it to select this task less often.
+    ///
+    /// This is not public because it permits interior mutability.
+    nice: AtomicI8,
+    /// The memory charged to this task, i.e., the frames backing the stack, heap growth,
+    /// and other anonymous mappings that were allocated while this task was running,
+    /// along with this task's optional limit on that memory.
+    ///
+    /// This is not public because it permits interior mutability.
+    memory_account: Arc<MemoryAccount>,
+    /// The ID of the task that leads this task's group of threads,
+    /// which is this task's own ID unless it was spawned as a thread of another task.
+    ///
+    /// All tasks in a group share the same memory account; see [`Task::join_group()`].
+    group_id: usize,
+    /// Whether this task has opted into recording extra hardware debugging context,
+    /// e.g., a record of its most recent branches, so that its crash reports are more detailed.
+    ///
+    /// This is not public because it permits interior mutability.
+    deep_crash_context: AtomicBool,
+    /// The IRQ-safe and preemption-safe locks currently held by this task,
+    /// which are forcibly released if this task is exited without unwinding.
+    ///
+    /// This is only modified by this task itself.
+    held_locks: HeldLocks,
     /// Memory management details: page tables, mappings, allocators, etc.
     /// This is shared among all other tasks in the same address space.
     pub mmi: MmiRef, 
@@ -259,6 +373,8 @@ pub struct Task {
 // Ensure that atomic fields in the `Tast` struct are actually lock-free atomics.
 const _: () = assert!(AtomicCell::<OptionalCpuId>::is_lock_free());
 const _: () = assert!(AtomicCell::<RunState>::is_lock_free());
+const _: () = assert!(AtomicCell::<Option<WakeReason>>::is_lock_free());
+const _: () = assert!(AtomicCell::<Instant>::is_lock_free());
 
 impl fmt::Debug for Task {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -307,17 +423,17 @@ impl Task {
         stack: Option<Stack>,
         states_to_inherit: InheritedStates,
     ) -> Result<Task, &'static str> {
-        /// The counter of task IDs. We start at `1` such that `0` can be used 
-        /// as a task ID that indicates the absence of a task, e.g., in sync primitives. 
-        static TASKID_COUNTER: AtomicUsize = AtomicUsize::new(1);
-
         let (mmi, namespace, env, app_crate) = states_to_inherit.into_tuple();
-        let kstack = stack
+        let mut kstack = stack
             .or_else(|| stack::alloc_stack(KERNEL_STACK_SIZE_IN_PAGES, &mut mmi.lock().page_table))
             .ok_or("couldn't allocate stack for new Task!")?;
 
-        // TODO: re-use old task IDs again, instead of simply blindly counting up.
-        let task_id = TASKID_COUNTER.fetch_add(1, Ordering::Relaxed);
+        // The stack was charged to the task that's creating this one, so move it over to this new task.
+        let memory_account = Arc::new(MemoryAccount::new());
+        kstack.set_memory_account(Arc::clone(&memory_account));
+
+        // Task IDs are never 0, such that 0 can indicate the absence of a task.
+        let task_id = task_id::allocate_task_id();
 
         // Obtain a new copied instance of the TLS data image for this task.
         let tls_area = namespace.get_tls_initializer_data();
@@ -325,18 +441,28 @@ impl Task {
         Ok(Task {
             inner: IrqSafeMutex::new(TaskInner {
                 saved_sp: 0,
+                // A new task's entry trampoline enables interrupts once it has started.
+                saved_interrupts_enabled: true,
                 kstack,
                 pinned_cpu: None,
                 kill_handler: None,
                 env,
                 restart_info: None,
                 waker: None,
+                cleanup_hooks: Vec::new(),
             }),
             id: task_id,
             name: format!("task_{task_id}"),
             running_on_cpu: AtomicCell::new(None.into()),
             runstate: AtomicCell::new(RunState::Initing),
+            wake_reason: AtomicCell::new(None),
+            runnable_since: AtomicCell::new(Instant::ZERO),
             suspended: AtomicBool::new(false),
+            nice: AtomicI8::new(0),
+            memory_account,
+            group_id: task_id,
+            deep_crash_context: AtomicBool::new(false),
+            held_locks: HeldLocks::new(),
             mmi,
             is_an_idle_task: false,
             app_crate,
@@ -446,6 +572,67 @@ impl Task {
         func(self.inner.lock().restart_info.as_ref())
     }
 
+    /// Registers the given `hook` to be invoked when this `Task` exits or is killed.
+    ///
+    /// This should be used by types that represent a resource held by this `Task`,
+    /// e.g., a lock or an armed timer, such that the resource is released even if
+    /// this `Task` is killed before it can release the resource itself.
+    /// Such types should [unregister] the hook once they release the resource normally.
+    ///
+    /// Hooks are invoked in reverse order of registration.
+    /// They may be invoked with preemption disabled, so they must not block.
+    ///
+    /// # Locking / Deadlock
+    /// Obtains the lock on this `Task`'s inner state in order to mutate it.
+    ///
+    /// [unregister]: Task::unregister_cleanup
+    pub fn register_cleanup(&self, hook: CleanupHook) -> CleanupHookId {
+        static CLEANUP_HOOK_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let id = CleanupHookId(CLEANUP_HOOK_ID_COUNTER.fetch_add(1, Ordering::Relaxed));
+        self.inner.lock().cleanup_hooks.push((id, hook));
+        id
+    }
+
+    /// Removes the cleanup hook with the given `id` from this `Task` without invoking it.
+    ///
+    /// Returns `true` if the hook was found and removed, or `false` if it
+    /// has already been invoked or was registered with a different task.
+    ///
+    /// # Locking / Deadlock
+    /// Obtains the lock on this `Task`'s inner state in order to mutate it.
+    pub fn unregister_cleanup(&self, id: CleanupHookId) -> bool {
+        let mut inner = self.inner.lock();
+        // Resources are usually released in reverse order of acquisition, so search from the end.
+        if let Some(index) = inner.cleanup_hooks.iter().rposition(|(i, _)| *i == id) {
+            inner.cleanup_hooks.remove(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Removes all cleanup hooks from this `Task` and invokes them
+    /// with the given `reason`, in reverse order of registration.
+    ///
+    /// This is only intended to be called by the task exit and kill routines.
+    ///
+    /// # Locking / Deadlock
+    /// Obtains the lock on this `Task`'s inner state in order to take the hooks,
+    /// but does not hold it while invoking them.
+    #[doc(hidden)]
+    pub fn run_cleanup_hooks(&self, reason: CleanupReason) {
+        // A hook may register or unregister other hooks, so we loop until none remain.
+        loop {
+            let hooks = core::mem::take(&mut self.inner.lock().cleanup_hooks);
+            if hooks.is_empty() {
+                break;
+            }
+            for (_id, hook) in hooks.into_iter().rev() {
+                hook(reason);
+            }
+        }
+    }
+
     /// Returns `true` if this `Task` has been exited, i.e.,
     /// if its `RunState` is either `Exited` or `Reaped`.
     pub fn has_exited(&self) -> bool {
@@ -508,6 +695,7 @@ impl Task {
         use RunState::{Blocked, Runnable};
 
         if self.runstate.compare_exchange(Blocked, Runnable).is_ok() {
+            self.mark_runnable_since_now();
             Ok(Blocked)
         } else if self.runstate.compare_exchange(Runnable, Runnable).is_ok() {
             // warn!("Unblocked an already runnable task: {:?}", self);
@@ -517,6 +705,25 @@ impl Task {
         }
     }
     
+    /// Unblocks this `Task` like [`unblock()`](Self::unblock), first recording why it was woken.
+    ///
+    /// If a reason was already recorded and has not yet been taken by the woken task,
+    /// e.g., because its wait timed out at the same time as it was interrupted,
+    /// the earlier reason is kept.
+    pub fn unblock_with_reason(&self, reason: WakeReason) -> Result<RunState, RunState> {
+        let _ = self.wake_reason.compare_exchange(None, Some(reason));
+        self.unblock()
+    }
+
+    /// Takes and clears the reason that this `Task` was most recently unblocked.
+    ///
+    /// A blocking primitive should call this once before blocking, to discard a stale reason,
+    /// and again after waking up. `None` means that the task was woken without a reason,
+    /// e.g., via [`unblock()`](Self::unblock), which should be treated as a spurious wakeup.
+    pub fn take_wake_reason(&self) -> Option<WakeReason> {
+        self.wake_reason.swap(None)
+    }
+
     /// Makes this `Task` `Runnable` if it is a newly-spawned and fully initialized task.
     ///
     /// This is a special case only to be used when spawning a new task that
@@ -526,12 +733,29 @@ impl Task {
     /// the current runstate on error.
     pub fn make_inited_task_runnable(&self) -> Result<RunState, RunState> {
         if self.runstate.compare_exchange(RunState::Initing, RunState::Runnable).is_ok() {
+            self.mark_runnable_since_now();
             Ok(RunState::Initing)
         } else {
             Err(self.runstate.load())
         }
     }
 
+    /// Records that this `Task` just became runnable, if wakeup timestamps are enabled.
+    fn mark_runnable_since_now(&self) {
+        if WAKEUP_TIMESTAMPS_ENABLED.load(Ordering::Relaxed) {
+            self.runnable_since.store(Instant::now());
+        }
+    }
+
+    /// Takes and clears the time at which this `Task` most recently became runnable
+    /// after being blocked or spawned, if it hasn't been switched in since then.
+    ///
+    /// This is used by the task switching routine to measure wakeup latency;
+    /// see [`enable_wakeup_timestamps()`].
+    pub fn take_runnable_since(&self) -> Option<Instant> {
+        Some(self.runnable_since.swap(Instant::ZERO)).filter(|since| *since != Instant::ZERO)
+    }
+
     /// Suspends this `Task`.
     pub fn suspend(&self) {
         self.suspended.store(true, Ordering::Release);
@@ -548,6 +772,90 @@ impl Task {
     pub fn is_suspended(&self) -> bool {
         self.suspended.load(Ordering::Acquire)
     }
+
+    /// Returns this `Task`'s nice value.
+    ///
+    /// A task's nice value is `0` by default; higher values indicate that
+    /// the task should be selected to run less often.
+    pub fn nice(&self) -> i8 {
+        self.nice.load(Ordering::Relaxed)
+    }
+
+    /// Sets this `Task`'s nice value, clamped to the range `[MIN_NICE, MAX_NICE]`.
+    ///
+    /// Returns the new nice value.
+    pub fn set_nice(&self, nice: i8) -> i8 {
+        let nice = nice.clamp(MIN_NICE, MAX_NICE);
+        self.nice.store(nice, Ordering::Relaxed);
+        nice
+    }
+
+    /// Returns the memory account that tracks the memory charged to this `Task`.
+    pub fn memory_account(&self) -> &Arc<MemoryAccount> {
+        &self.memory_account
+    }
+
+    /// Returns the number of bytes of memory currently charged to this `Task`,
+    /// or to its whole group of threads if it has joined one.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_account.used_pages() * PAGE_SIZE
+    }
+
+    /// Returns this `Task`'s memory limit in bytes, or `None` if it is unlimited.
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_account.limit_pages().map(|pages| pages.saturating_mul(PAGE_SIZE))
+    }
+
+    /// Sets this `Task`'s memory limit in bytes, rounded up to a whole number of pages,
+    /// or removes the limit if `None`.
+    ///
+    /// Once this `Task`'s usage reaches its limit, further memory allocations made
+    /// while it is running fail with [`memory::QUOTA_EXCEEDED`].
+    pub fn set_memory_limit(&self, limit_in_bytes: Option<usize>) {
+        self.memory_account.set_limit_pages(
+            limit_in_bytes.map(|bytes| bytes.div_ceil(PAGE_SIZE))
+        );
+    }
+
+    /// Returns the ID of the task that leads this `Task`'s group of threads.
+    ///
+    /// This is this `Task`'s own ID unless it joined another task's group upon being spawned.
+    pub fn group_id(&self) -> usize {
+        self.group_id
+    }
+
+    /// Makes this `Task`, which hasn't been spawned yet, a thread in the same group as `leader`.
+    ///
+    /// Threads in a group share the memory account of their group leader,
+    /// so memory charged to any of them counts towards the limit of the whole group,
+    /// and [`Task::memory_usage()`] reports the usage of the whole group.
+    /// This `Task`'s stack is charged to that shared account instead of its own.
+    ///
+    /// As all tasks already share a single address space, threads don't share anything else
+    /// that other tasks don't; each still has its own stack and TLS area.
+    pub fn join_group(&mut self, leader: &Task) {
+        self.group_id = leader.group_id;
+        self.memory_account = Arc::clone(&leader.memory_account);
+        let memory_account = Arc::clone(&self.memory_account);
+        self.inner_mut().kstack.set_memory_account(memory_account);
+    }
+
+    /// Returns whether this `Task` has opted into recording deep crash context.
+    pub fn deep_crash_context(&self) -> bool {
+        self.deep_crash_context.load(Ordering::Relaxed)
+    }
+
+    /// Sets whether this `Task` records deep crash context, e.g., its most recent branches.
+    ///
+    /// This takes effect the next time this `Task` is switched to.
+    pub fn set_deep_crash_context(&self, enable: bool) {
+        self.deep_crash_context.store(enable, Ordering::Relaxed);
+    }
+
+    /// Returns the IRQ-safe and preemption-safe locks currently held by this `Task`.
+    pub fn held_locks(&self) -> &HeldLocks {
+        &self.held_locks
+    }
 }
 
 impl Drop for Task {
@@ -563,6 +871,9 @@ impl Drop for Task {
             warn!("While dropping task {:?}, its kill handler callback was still present. Removing it now.", self);
             drop(kill_handler);
         }
+
+        // This task can no longer be in the task list, so its slot can be reused.
+        task_id::free_task_id(self.id);
     }
 }
 
@@ -0,0 +1,66 @@
+//! Allocation of task IDs, which are generational indices into the system-wide task list.
+//!
+//! A task ID packs together two parts:
+//! * the *index* of the task's slot in the task list, in the lower [`TASK_ID_INDEX_BITS`] bits, and
+//! * the *generation* of that slot, in the remaining upper bits,
+//!   which is incremented every time the slot's index is freed and reused.
+//!
+//! Thus, indices are densely reused, but an ID is never reused within a boot:
+//! once a slot's generation reaches its maximum value, that slot is retired.
+//! Code that caches a task ID can look it up later without mistaking a newer task
+//! that reused the same slot for the original task, because their generations differ.
+//!
+//! Index `0` is never allocated, such that a task ID of `0` can be used
+//! to indicate the absence of a task, e.g., in sync primitives.
+//! The first task to use each slot has a generation of `0`,
+//! so its ID is numerically identical to its index.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use sync_irq::IrqSafeMutex;
+
+/// The number of lower bits of a task ID that hold its slot index.
+pub const TASK_ID_INDEX_BITS: u32 = 32;
+const INDEX_MASK: usize = (1 << TASK_ID_INDEX_BITS) - 1;
+const GENERATION_MASK: usize = usize::MAX >> TASK_ID_INDEX_BITS;
+
+/// The next never-before-used slot index.
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(1);
+
+/// The IDs that can be handed out next, one per freed slot index,
+/// each of which already has its slot's generation incremented.
+static FREE_IDS: IrqSafeMutex<Vec<usize>> = IrqSafeMutex::new(Vec::new());
+
+/// Returns the slot index part of the given task ID.
+#[inline]
+pub const fn task_id_index(id: usize) -> usize {
+    id & INDEX_MASK
+}
+
+/// Returns the generation part of the given task ID.
+#[inline]
+pub const fn task_id_generation(id: usize) -> usize {
+    id >> TASK_ID_INDEX_BITS
+}
+
+/// Allocates a new unique task ID, reusing a freed slot index if possible.
+pub(crate) fn allocate_task_id() -> usize {
+    if let Some(id) = FREE_IDS.lock().pop() {
+        return id;
+    }
+    let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+    assert!(index <= INDEX_MASK, "BUG: ran out of task ID slot indices");
+    index
+}
+
+/// Frees the given task ID such that its slot index can be reused by a new task,
+/// with the next generation of that slot.
+///
+/// If the slot has exhausted all of its generations, it is retired instead.
+pub(crate) fn free_task_id(id: usize) {
+    let generation = task_id_generation(id);
+    if generation == GENERATION_MASK {
+        return;
+    }
+    FREE_IDS.lock().push(((generation + 1) << TASK_ID_INDEX_BITS) | task_id_index(id));
+}
@@ -274,7 +274,9 @@ pub trait EarlySleeper: ClockSource<ClockType = Monotonic> {
     /// interrupts.
     fn sleep(duration: Duration) {
         let start = Self::now();
-        while Self::now() < start + duration {}
+        while Self::now() < start + duration {
+            core::hint::spin_loop();
+        }
     }
 }
 
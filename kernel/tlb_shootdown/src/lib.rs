@@ -27,6 +27,11 @@ static TLB_SHOOTDOWN_IPI_PAGES: IrqSafeRwLock<Option<PageRange>> = IrqSafeRwLock
 pub fn init() {
     memory::set_broadcast_tlb_shootdown_cb(broadcast_tlb_shootdown);
 
+    #[cfg(target_arch = "x86_64")]
+    nmi::register_handler(nmi::NmiReason::TlbShootdown, |_stack_frame, _payload| {
+        handle_tlb_shootdown_ipi();
+    }).unwrap();
+
     #[cfg(target_arch = "aarch64")]
     interrupts::setup_tlb_shootdown_handler(tlb_shootdown_ipi_handler).unwrap();
 }
@@ -90,13 +95,10 @@ fn broadcast_tlb_shootdown(pages_to_invalidate: PageRange) {
     *TLB_SHOOTDOWN_IPI_PAGES.write() = Some(pages_to_invalidate);
     TLB_SHOOTDOWN_IPI_COUNT.store(cpu_count - 1, Ordering::Relaxed); // -1 to exclude this core 
 
-    #[cfg(target_arch = "x86_64")] {
-        let my_lapic = apic::get_my_apic()
-            .expect("BUG: broadcast_tlb_shootdown(): couldn't get LocalApic");
-
-        // use NMI, since it will interrupt everyone forcibly and result in the fastest handling
-        my_lapic.write().send_nmi_ipi(apic::LapicIpiDestination::AllButMe); // send IPI to all other cores but this one
-    }
+    // use NMI, since it will interrupt everyone forcibly and result in the fastest handling
+    #[cfg(target_arch = "x86_64")]
+    nmi::send_nmi_to_others(nmi::NmiReason::TlbShootdown, 0)
+        .expect("BUG: broadcast_tlb_shootdown(): couldn't send NMIs");
 
     #[cfg(target_arch = "aarch64")]
     interrupts::broadcast_tlb_shootdown_ipi();
@@ -1,15 +1,28 @@
 #![no_std]
 
+mod sync;
+
+use core::sync::atomic::{AtomicU64, Ordering};
 use log::info;
 use time::{Instant, Period};
 
+pub use sync::{
+    global_timestamp, synchronize_cpu, check_drift, respond_to_sync,
+    tsc_offset, last_drift, max_observed_drift, is_reliable, SyncMeasurement,
+};
+
+/// The period of the TSC in femtoseconds, or zero if it hasn't been calculated yet.
+static TSC_PERIOD: AtomicU64 = AtomicU64::new(0);
+
 pub struct Tsc;
 
 impl time::ClockSource for Tsc {
     type ClockType = time::Monotonic;
 
+    /// Returns the current TSC value adjusted by this CPU's offset,
+    /// such that instants taken on different CPUs are comparable.
     fn now() -> Instant {
-        Instant::new(tsc_value())
+        Instant::new(global_timestamp())
     }
 }
 
@@ -25,17 +38,36 @@ pub fn get_tsc_period() -> Option<Period> {
 
     let increments = end.checked_sub(start)?;
     let tsc_period = Period::new(PIT_WAIT_FEMTOSECONDS / increments);
+    TSC_PERIOD.store(tsc_period.into(), Ordering::Relaxed);
 
     info!("TSC period calculated by PIT is: {tsc_period}");
 
     Some(tsc_period)
 }
 
+/// Converts a number of TSC cycles into nanoseconds,
+/// or returns `None` if the TSC period hasn't been calculated yet.
+pub fn cycles_to_nanos(cycles: u64) -> Option<u64> {
+    match TSC_PERIOD.load(Ordering::Relaxed) {
+        0 => None,
+        period => Some((u128::from(cycles) * u128::from(period) / 1_000_000) as u64),
+    }
+}
+
 #[doc(hidden)]
 pub fn tsc_value() -> u64 {
-    let mut _aux = 0;
+    rdtscp().0
+}
+
+/// Returns the current TSC value along with the contents of `IA32_TSC_AUX`,
+/// which the `apic` crate sets to the ID of the current CPU.
+///
+/// Both values are read by a single instruction, so they always refer to the same CPU.
+fn rdtscp() -> (u64, u32) {
+    let mut aux = 0;
     // SAFETY: Reading the TSC value is a platform-specific intrinsic that has no
     // side effects or dangerous behavior, and is supported on all modern x86_64
     // hardware.
-    unsafe { core::arch::x86_64::__rdtscp(&mut _aux) }
+    let value = unsafe { core::arch::x86_64::__rdtscp(&mut aux) };
+    (value, aux)
 }
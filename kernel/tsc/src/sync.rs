@@ -0,0 +1,258 @@
+//! Detection of and compensation for TSCs that aren't synchronized across CPUs.
+//!
+//! On some hardware (and on QEMU with certain `-cpu` flags), the TSCs of different CPUs
+//! start counting at different times or tick at slightly different rates,
+//! so raw TSC values read on different CPUs can't be compared directly.
+//!
+//! When each AP is booted, the BSP and that AP exchange timestamps through a single
+//! shared cache line in a tight loop, similar to a simplified PTP exchange.
+//! Each round measures the round-trip time and the AP's offset from the midpoint
+//! of that round trip; the offset from the round with the shortest round trip
+//! is recorded for that AP.
+//! [`global_timestamp()`] subtracts the current CPU's offset from its TSC value,
+//! yielding a timeline that is comparable across all CPUs.
+//! Offsets are relative to the BSP, whose offset is always zero.
+//!
+//! Offsets are indexed by the CPU ID that the `apic` crate stores in `IA32_TSC_AUX`,
+//! which `rdtscp` returns together with the timestamp. Thus, the lookup is correct
+//! even if the current task is migrated to another CPU while reading the timestamp.
+//!
+//! The same exchange can be repeated later via [`check_drift()`] to detect TSCs
+//! that drift apart over time. If any CPU drifts further than
+//! [`CONFIG_TSC_MAX_DRIFT`] from its recorded offset, the TSC is marked as unreliable.
+//! Subsystems that compare timestamps across CPUs should check [`is_reliable()`]
+//! and fall back to a system-wide timer (e.g., the HPET or PIT) if it returns `false`.
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+};
+use kernel_config::time::CONFIG_TSC_MAX_DRIFT;
+use log::warn;
+use crate::{cycles_to_nanos, rdtscp, tsc_value};
+
+/// The maximum number of CPUs whose offsets can be recorded.
+/// Offsets of CPUs with higher IDs are never compensated.
+const MAX_CPUS: usize = 256;
+
+/// The number of timestamp exchanges performed in each handshake.
+const ROUNDS: u64 = 16;
+
+/// How many TSC cycles either side of a handshake waits for the other side
+/// before giving up, which is roughly one second on modern hardware.
+const TIMEOUT_CYCLES: u64 = 1 << 32;
+
+/// The state shared between the two CPUs taking part in a handshake.
+///
+/// This is aligned to a cache line such that the exchange isn't slowed down
+/// by false sharing with unrelated data.
+#[repr(align(64))]
+struct SyncLine {
+    /// Set by the responder once it is ready to respond to requests.
+    ready: AtomicBool,
+    /// The most recent round requested by the initiator.
+    request: AtomicU64,
+    /// The most recent round answered by the responder.
+    response: AtomicU64,
+    /// The responder's TSC value at the time it answered the most recent round.
+    responder_tsc: AtomicU64,
+}
+
+static SYNC_LINE: SyncLine = SyncLine {
+    ready: AtomicBool::new(false),
+    request: AtomicU64::new(0),
+    response: AtomicU64::new(0),
+    responder_tsc: AtomicU64::new(0),
+};
+
+/// Whether a handshake is currently in progress; only one may run at a time.
+static HANDSHAKE_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// The offset of each CPU's TSC from the BSP's TSC, in cycles.
+static OFFSETS: [AtomicI64; MAX_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicI64 = AtomicI64::new(0);
+    [ZERO; MAX_CPUS]
+};
+
+/// Whether an offset has been measured for each CPU.
+static SYNCHRONIZED: [AtomicBool; MAX_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FALSE: AtomicBool = AtomicBool::new(false);
+    [FALSE; MAX_CPUS]
+};
+
+/// The drift of each CPU's TSC from its recorded offset when it was last checked, in cycles.
+static LAST_DRIFT: [AtomicU64; MAX_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; MAX_CPUS]
+};
+
+/// The largest drift observed on any CPU, in cycles.
+static MAX_OBSERVED_DRIFT: AtomicU64 = AtomicU64::new(0);
+
+/// Whether TSC values from different CPUs can be compared after compensating for their offsets.
+static RELIABLE: AtomicBool = AtomicBool::new(true);
+
+/// The result of a single handshake between two CPUs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyncMeasurement {
+    /// The responder's TSC minus the initiator's TSC, in cycles.
+    pub offset: i64,
+    /// The round-trip time of the exchange that `offset` was derived from, in cycles.
+    ///
+    /// The true offset lies within half of this value of `offset`.
+    pub round_trip: u64,
+}
+
+/// Returns the current TSC value, adjusted by the current CPU's offset
+/// such that timestamps read on different CPUs are comparable.
+pub fn global_timestamp() -> u64 {
+    let (tsc, cpu) = rdtscp();
+    tsc.wrapping_sub(offset_of(cpu) as u64)
+}
+
+/// Returns the recorded offset of the given CPU's TSC from the BSP's TSC, in cycles,
+/// or `None` if no offset has been measured for that CPU.
+pub fn tsc_offset(cpu: u32) -> Option<i64> {
+    let index = cpu as usize;
+    SYNCHRONIZED.get(index)
+        .filter(|synchronized| synchronized.load(Ordering::Acquire))
+        .map(|_| OFFSETS[index].load(Ordering::Relaxed))
+}
+
+/// Returns the drift of the given CPU's TSC from its recorded offset
+/// as of the last call to [`check_drift()`] for that CPU, in cycles.
+pub fn last_drift(cpu: u32) -> Option<u64> {
+    tsc_offset(cpu)?;
+    LAST_DRIFT.get(cpu as usize).map(|drift| drift.load(Ordering::Relaxed))
+}
+
+/// Returns the largest drift observed on any CPU so far, in cycles.
+pub fn max_observed_drift() -> u64 {
+    MAX_OBSERVED_DRIFT.load(Ordering::Relaxed)
+}
+
+/// Returns whether timestamps from [`global_timestamp()`] can be compared across CPUs.
+pub fn is_reliable() -> bool {
+    RELIABLE.load(Ordering::Relaxed)
+}
+
+/// Measures the offset of the given CPU's TSC and records it for use by [`global_timestamp()`].
+///
+/// This must be invoked on the BSP while the CPU being synchronized invokes [`respond_to_sync()`].
+/// The caller must not be migrated to another CPU while this is running.
+pub fn synchronize_cpu(cpu: u32) -> Result<SyncMeasurement, &'static str> {
+    let index = cpu as usize;
+    if index >= MAX_CPUS {
+        return Err("CPU ID is too large to record its TSC offset");
+    }
+    let measurement = handshake()?;
+    OFFSETS[index].store(measurement.offset, Ordering::Relaxed);
+    LAST_DRIFT[index].store(0, Ordering::Relaxed);
+    SYNCHRONIZED[index].store(true, Ordering::Release);
+    Ok(measurement)
+}
+
+/// Re-measures the offset of the given CPU's TSC and returns how far it has drifted
+/// from the offset recorded by [`synchronize_cpu()`], in cycles.
+///
+/// If the drift exceeds [`CONFIG_TSC_MAX_DRIFT`], a warning is logged
+/// and the TSC is marked as unreliable.
+///
+/// This must be invoked on the BSP while the given CPU invokes [`respond_to_sync()`].
+/// The caller must not be migrated to another CPU while this is running.
+pub fn check_drift(cpu: u32) -> Result<u64, &'static str> {
+    let recorded = tsc_offset(cpu).ok_or("CPU's TSC was never synchronized")?;
+    let measurement = handshake()?;
+    let drift = measurement.offset.abs_diff(recorded);
+    LAST_DRIFT[cpu as usize].store(drift, Ordering::Relaxed);
+    MAX_OBSERVED_DRIFT.fetch_max(drift, Ordering::Relaxed);
+
+    let max_drift_nanos = CONFIG_TSC_MAX_DRIFT.as_nanos() as u64;
+    if let Some(drift_nanos) = cycles_to_nanos(drift) {
+        if drift_nanos > max_drift_nanos && RELIABLE.swap(false, Ordering::Relaxed) {
+            warn!("CPU {cpu} TSC drifted by {drift_nanos} ns (limit {max_drift_nanos} ns); \
+                marking the TSC as unreliable for cross-CPU timestamps");
+        }
+    }
+    Ok(drift)
+}
+
+/// Answers the timestamp requests of a handshake initiated by
+/// [`synchronize_cpu()`] or [`check_drift()`] on the BSP.
+///
+/// This must be invoked on the CPU being measured,
+/// and the caller must not be migrated to another CPU while this is running.
+pub fn respond_to_sync() -> Result<(), &'static str> {
+    SYNC_LINE.ready.store(true, Ordering::Release);
+    for round in 1..=ROUNDS {
+        wait_until(|| SYNC_LINE.request.load(Ordering::Acquire) == round)?;
+        SYNC_LINE.responder_tsc.store(tsc_value(), Ordering::Relaxed);
+        SYNC_LINE.response.store(round, Ordering::Release);
+    }
+    Ok(())
+}
+
+/// Returns the recorded offset of the given CPU, or zero if there is none.
+fn offset_of(cpu: u32) -> i64 {
+    OFFSETS.get(cpu as usize).map_or(0, |offset| offset.load(Ordering::Relaxed))
+}
+
+/// Performs the initiator's side of a handshake with the CPU running [`respond_to_sync()`],
+/// returning the responder's offset relative to the BSP.
+fn handshake() -> Result<SyncMeasurement, &'static str> {
+    if HANDSHAKE_IN_PROGRESS.swap(true, Ordering::Acquire) {
+        return Err("a TSC synchronization handshake is already in progress");
+    }
+    let result = exchange_timestamps().map(|measurement| SyncMeasurement {
+        // Make the offset relative to the BSP in case this isn't running on the BSP.
+        offset: measurement.offset.wrapping_add(offset_of(rdtscp().1)),
+        ..measurement
+    });
+    // Reset the shared line such that the next responder doesn't observe stale rounds.
+    SYNC_LINE.ready.store(false, Ordering::Relaxed);
+    SYNC_LINE.request.store(0, Ordering::Release);
+    HANDSHAKE_IN_PROGRESS.store(false, Ordering::Release);
+    result
+}
+
+/// Exchanges timestamps with the responder for several rounds and returns
+/// the measurement with the shortest round trip, which is the most accurate.
+fn exchange_timestamps() -> Result<SyncMeasurement, &'static str> {
+    SYNC_LINE.response.store(0, Ordering::Relaxed);
+    wait_until(|| SYNC_LINE.ready.load(Ordering::Acquire))?;
+
+    let mut best: Option<SyncMeasurement> = None;
+    for round in 1..=ROUNDS {
+        let start = tsc_value();
+        SYNC_LINE.request.store(round, Ordering::Release);
+        wait_until(|| SYNC_LINE.response.load(Ordering::Acquire) == round)?;
+        let end = tsc_value();
+        let responder_tsc = SYNC_LINE.responder_tsc.load(Ordering::Relaxed);
+
+        let round_trip = end.wrapping_sub(start);
+        let midpoint = start.wrapping_add(round_trip / 2);
+        let measurement = SyncMeasurement {
+            offset: responder_tsc.wrapping_sub(midpoint) as i64,
+            round_trip,
+        };
+        if best.map_or(true, |best| measurement.round_trip < best.round_trip) {
+            best = Some(measurement);
+        }
+    }
+    best.ok_or("BUG: TSC synchronization performed no rounds")
+}
+
+/// Spins until `condition` returns `true`, or returns an error after [`TIMEOUT_CYCLES`].
+fn wait_until(condition: impl Fn() -> bool) -> Result<(), &'static str> {
+    let start = tsc_value();
+    while !condition() {
+        if tsc_value().wrapping_sub(start) > TIMEOUT_CYCLES {
+            return Err("timed out waiting for the other CPU during TSC synchronization");
+        }
+        spin_loop();
+    }
+    Ok(())
+}
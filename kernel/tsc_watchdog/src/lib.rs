@@ -0,0 +1,148 @@
+//! Periodically re-checks that the TSCs of all CPUs remain synchronized.
+//!
+//! The offset of each AP's TSC is measured once when that AP boots (see the `tsc` crate).
+//! This crate spawns a watchdog task on the BSP that repeats that measurement
+//! every [`CONFIG_TSC_DRIFT_CHECK_PERIOD`] to detect TSCs that drift apart over time,
+//! which marks the TSC as unreliable if the drift is too large.
+//!
+//! The results are logged once at boot and are available at any time
+//! by reading the `/tsc_sync` file.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::String, sync::Arc};
+use core::fmt::Write;
+use cpu::CpuId;
+use fs_node::{DirRef, File, FileOrDir, FsNode, WeakDirRef};
+use io::{ByteReader, ByteWriter, IoError, KnownLength};
+use kernel_config::time::CONFIG_TSC_DRIFT_CHECK_PERIOD;
+use log::{error, info};
+use memory::MappedPages;
+use spin::Mutex;
+
+/// The name of the file in the root directory that reports the TSC synchronization state.
+pub const TSC_SYNC_FILE_NAME: &str = "tsc_sync";
+
+/// Reports the boot-time TSC synchronization results, creates the `/tsc_sync` file,
+/// and starts the watchdog task that periodically re-checks for drift.
+pub fn init() -> Result<(), &'static str> {
+    for line in report().lines() {
+        info!("{line}");
+    }
+
+    let file = Arc::new(Mutex::new(TscSyncFile)) as fs_node::FileRef;
+    root::get_root().lock().insert(FileOrDir::File(file))?;
+
+    let bsp = cpu::bootstrap_cpu().ok_or("tsc_watchdog::init(): couldn't get ID of bootstrap CPU")?;
+    spawn::new_task_builder(watchdog_loop, bsp)
+        .name(String::from("tsc_watchdog"))
+        .pin_on_cpu(bsp)
+        .spawn()?;
+    Ok(())
+}
+
+/// Returns a human-readable summary of the TSC offset and drift of every CPU.
+pub fn report() -> String {
+    let bsp = cpu::bootstrap_cpu();
+    let max_drift = tsc::max_observed_drift();
+    let mut out = String::new();
+    let _ = writeln!(out, "TSC reliable across CPUs: {}", if tsc::is_reliable() { "yes" } else { "no" });
+    let _ = writeln!(out, "Max observed TSC drift: {} cycles{}", max_drift, nanos_suffix(max_drift));
+    let _ = writeln!(out, "{:<6} {:>20} {:>20}", "CPU", "OFFSET (cycles)", "LAST DRIFT (cycles)");
+    for cpu in cpu::cpus() {
+        let id = cpu.value();
+        let (offset, drift) = if Some(cpu) == bsp {
+            (String::from("0 (BSP)"), String::from("-"))
+        } else {
+            (
+                tsc::tsc_offset(id).map_or_else(|| String::from("unknown"), |offset| format!("{offset}")),
+                tsc::last_drift(id).map_or_else(|| String::from("-"), |drift| format!("{drift}")),
+            )
+        };
+        let _ = writeln!(out, "{id:<6} {offset:>20} {drift:>20}");
+    }
+    out
+}
+
+fn nanos_suffix(cycles: u64) -> String {
+    tsc::cycles_to_nanos(cycles)
+        .map(|nanos| format!(" ({nanos} ns)"))
+        .unwrap_or_default()
+}
+
+/// The entry point of the watchdog task, which must be pinned to the given bootstrap CPU.
+fn watchdog_loop(bsp: CpuId) {
+    // Once the TSC is unreliable, there's nothing left to detect.
+    while tsc::is_reliable() {
+        if sleep::sleep(CONFIG_TSC_DRIFT_CHECK_PERIOD).is_err() {
+            return;
+        }
+        for cpu in cpu::cpus().filter(|cpu| *cpu != bsp) {
+            if let Err(e) = check_cpu(cpu) {
+                error!("tsc_watchdog: failed to check TSC drift of CPU {cpu}: {e}");
+            }
+        }
+    }
+}
+
+/// Re-measures the drift of the given CPU's TSC by running a responder task pinned to it.
+fn check_cpu(cpu: CpuId) -> Result<u64, &'static str> {
+    let responder = spawn::new_task_builder(|_: ()| tsc::respond_to_sync(), ())
+        .name(format!("tsc_sync_responder_{cpu}"))
+        .pin_on_cpu(cpu)
+        .spawn()?;
+    let drift = tsc::check_drift(cpu.value());
+    responder.join()?;
+    drift
+}
+
+
+/// A lazily-generated file that reports the current TSC synchronization state.
+struct TscSyncFile;
+
+impl FsNode for TscSyncFile {
+    fn get_name(&self) -> String {
+        String::from(TSC_SYNC_FILE_NAME)
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        Some(root::get_root().clone())
+    }
+
+    fn set_parent_dir(&mut self, _: WeakDirRef) {
+        // do nothing
+    }
+}
+
+impl ByteReader for TscSyncFile {
+    fn read_at(&mut self, buf: &mut [u8], offset: usize) -> Result<usize, IoError> {
+        let output = report();
+        if offset > output.len() {
+            return Err(IoError::InvalidInput);
+        }
+        let count = core::cmp::min(buf.len(), output.len() - offset);
+        buf[..count].copy_from_slice(&output.as_bytes()[offset..(offset + count)]);
+        Ok(count)
+    }
+}
+
+impl ByteWriter for TscSyncFile {
+    fn write_at(&mut self, _buffer: &[u8], _offset: usize) -> Result<usize, IoError> {
+        Err(IoError::from("the TSC synchronization report is read-only"))
+    }
+    fn flush(&mut self) -> Result<(), IoError> { Ok(()) }
+}
+
+impl KnownLength for TscSyncFile {
+    fn len(&self) -> usize {
+        report().len()
+    }
+}
+
+impl File for TscSyncFile {
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("the TSC synchronization report is autogenerated, cannot be memory mapped")
+    }
+}
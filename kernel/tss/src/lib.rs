@@ -1,50 +1,215 @@
 #![no_std]
 
+extern crate alloc;
+
+use alloc::boxed::Box;
 use x86_64::structures::tss::TaskStateSegment;
 use atomic_linked_list::atomic_map::AtomicMap;
 use spin::Mutex;
 use memory::VirtualAddress;
 use cpu::CpuId;
+use stack::Stack;
+use core::{mem::size_of, ptr, sync::atomic::{AtomicPtr, Ordering}};
 
 /// The index of the double fault stack in a TaskStateSegment (TSS)
 pub const DOUBLE_FAULT_IST_INDEX: usize = 0;
 
 /// The TSS list, one per CPU.
-static TSS: AtomicMap<CpuId, Mutex<TaskStateSegment>> = AtomicMap::new();
+///
+/// Each TSS is individually heap-allocated and never freed, because the GDT's TSS descriptor
+/// refers to its address directly. Replacing a CPU's entry in this map thus never moves
+/// or overwrites a TSS that a GDT descriptor may still point to.
+static TSS: AtomicMap<CpuId, &'static CpuTss> = AtomicMap::new();
+
+/// The maximum number of CPUs whose TSS can be created.
+const MAX_TRACKED_CPUS: usize = 256;
+
+/// A direct pointer to each CPU's TSS, indexed by CPU ID.
+///
+/// This is the fast path for [`tss_set_rsp0()`], which runs on every switch to a userspace task,
+/// so it must neither look up the [`TSS`] map nor lock the TSS.
+/// Each pointer is established in [`create_tss()`] and remains valid forever,
+/// because the [`TSS`] map owns each TSS and never frees it.
+static TSS_PTRS: [AtomicPtr<TaskStateSegment>; MAX_TRACKED_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NULL: AtomicPtr<TaskStateSegment> = AtomicPtr::new(ptr::null_mut());
+    [NULL; MAX_TRACKED_CPUS]
+};
 
+/// A CPU's TSS, along with the stacks that its entries point to.
+///
+/// The stacks are owned here such that they remain mapped for as long as the TSS exists.
+pub struct CpuTss {
+    tss: Mutex<TaskStateSegment>,
+    double_fault_stack: Stack,
+    privilege_stack: Stack,
+}
+
+impl CpuTss {
+    /// Returns the TSS itself.
+    pub fn tss(&self) -> &Mutex<TaskStateSegment> {
+        &self.tss
+    }
+
+    /// Returns the stack that the TSS's double fault IST entry points to.
+    pub fn double_fault_stack(&self) -> &Stack {
+        &self.double_fault_stack
+    }
+
+    /// Returns the stack that the TSS's privilege stack 0 (RSP0) entry initially points to.
+    pub fn privilege_stack(&self) -> &Stack {
+        &self.privilege_stack
+    }
+}
+
+/// Returns the TSS of the given CPU, if one has been created.
+pub fn get_tss(cpu_id: CpuId) -> Option<&'static CpuTss> {
+    TSS.get(&cpu_id).copied()
+}
+
+
+/// Returns a pointer to the current CPU's TSS, as cached by [`create_tss()`].
+///
+/// Returns `None` if the current CPU's ID isn't known yet, early in its bringup.
+#[inline(always)]
+fn current_tss_ptr() -> Option<*mut TaskStateSegment> {
+    let tss = TSS_PTRS.get(cpu::current_cpu().value() as usize)?.load(Ordering::Acquire);
+    // Every CPU creates its TSS during its bringup, which fails if that's not possible.
+    debug_assert!(!tss.is_null(), "BUG: the current CPU has no TSS");
+    Some(tss).filter(|tss| !tss.is_null())
+}
 
 /// Sets the current CPU's TSS privilege stack 0 (RSP0) entry, which points to the stack that 
 /// the x86_64 hardware automatically switches to when transitioning from Ring 3 -> Ring 0.
 /// Should be set to an address within the current userspace task's kernel stack.
 /// WARNING: If set incorrectly, the OS will crash upon an interrupt from userspace into kernel space!!
-pub fn tss_set_rsp0(new_privilege_stack_top: VirtualAddress) -> Result<(), &'static str> {
-    let cpu_id = cpu::current_cpu();
-    let mut tss_entry = TSS.get(&cpu_id).ok_or_else(|| {
-        log::error!("tss_set_rsp0(): couldn't find TSS for CPU {}", cpu_id);
-        "No TSS for the current CPU" 
-    })?.lock();
-    tss_entry.privilege_stack_table[0] = x86_64::VirtAddr::new(new_privilege_stack_top.value() as u64);
-    // log::trace!("tss_set_rsp0: new TSS {:?}", tss_entry);
-    Ok(())
+///
+/// This is a single store through the pointer to this CPU's TSS that was cached
+/// when it was created, so it cannot fail and takes a bounded amount of time,
+/// which makes it usable in the middle of a context switch.
+/// It must be invoked with preemption disabled, such that the current CPU doesn't change.
+///
+/// This does nothing if the current CPU's ID isn't known yet, early in its bringup,
+/// as no userspace task can run on that CPU until then.
+#[inline]
+pub fn tss_set_rsp0(new_privilege_stack_top: VirtualAddress) {
+    let Some(tss) = current_tss_ptr() else { return };
+    // SAFETY: `tss` points to this CPU's TSS, which is never freed.
+    // Only this CPU writes its own RSP0 entry; other accesses to the TSS are read-only.
+    // The TSS is packed, so its entries may be unaligned.
+    unsafe {
+        let rsp0 = ptr::addr_of_mut!((*tss).privilege_stack_table) as *mut x86_64::VirtAddr;
+        rsp0.write_unaligned(x86_64::VirtAddr::new(new_privilege_stack_top.value() as u64));
+    }
 }
 
+/// Returns the current CPU's TSS privilege stack 0 (RSP0) entry; see [`tss_set_rsp0()`].
+///
+/// Returns `None` if the current CPU's ID isn't known yet, early in its bringup.
+pub fn tss_rsp0() -> Option<VirtualAddress> {
+    let tss = current_tss_ptr()?;
+    // SAFETY: `tss` points to this CPU's TSS, which is never freed.
+    let rsp0 = unsafe {
+        (ptr::addr_of!((*tss).privilege_stack_table) as *const x86_64::VirtAddr).read_unaligned()
+    };
+    Some(VirtualAddress::new_canonical(rsp0.as_u64() as usize))
+}
+
+/// In debug builds, asserts that the current CPU's RSP0 points within the given kernel stack.
+///
+/// This should be invoked on the path that enters userspace, right before the transition,
+/// because a stale RSP0 would make the next Ring 3 -> Ring 0 transition
+/// land on another task's stack.
+#[inline]
+pub fn debug_assert_rsp0_within(kstack: &Stack) {
+    if cfg!(debug_assertions) {
+        let Some(rsp0) = tss_rsp0() else { return };
+        assert!(
+            kstack.bottom() < rsp0 && rsp0 <= kstack.top_unusable(),
+            "BUG: TSS RSP0 {:#X} is outside the current task's kernel stack {:#X}..{:#X}",
+            rsp0, kstack.bottom(), kstack.top_unusable(),
+        );
+    }
+}
 
-/// Sets up TSS entry for the given CPU core. 
+
+/// Sets up TSS entry for the given CPU core, which takes ownership of the given stacks.
+///
+/// Before creating the TSS, this verifies that both given stacks are mapped as writable
+/// in the currently-active page table, and afterwards, that the TSS itself is too.
+/// Otherwise, the first interrupt that uses one of those stacks or accesses the TSS
+/// would cause a triple fault with no diagnostic information.
+///
+/// If the given CPU already has a TSS, that old TSS (and its stacks) is kept alive
+/// rather than freed, in case a loaded GDT still refers to it.
+///
+/// This also caches a direct pointer to the new TSS for [`tss_set_rsp0()`],
+/// which is why this fails if the CPU's ID is too large to be cached;
+/// such an error is fatal to that CPU's bringup.
 ///
 /// Returns a reference to a Mutex wrapping the new TSS entry.
 pub fn create_tss(
     cpu_id: CpuId, 
-    double_fault_stack_top_unusable: VirtualAddress, 
-    privilege_stack_top_unusable: VirtualAddress
-) -> &'static Mutex<TaskStateSegment> {
+    double_fault_stack: Stack,
+    privilege_stack: Stack,
+) -> Result<&'static Mutex<TaskStateSegment>, &'static str> {
+    let tss_ptr_slot = TSS_PTRS.get(cpu_id.value() as usize).ok_or_else(|| {
+        log::error!("create_tss(): CPU {} exceeds the maximum of {} CPUs", cpu_id, MAX_TRACKED_CPUS);
+        "create_tss(): the CPU's ID is too large to cache its TSS"
+    })?;
+    check_writable(double_fault_stack.top_usable(), "double fault stack top")?;
+    check_writable(privilege_stack.top_usable(), "privilege stack top")?;
+
+    let tss = new_tss(double_fault_stack.top_unusable(), Some(privilege_stack.top_unusable()));
+    let cpu_tss: &'static CpuTss = Box::leak(Box::new(CpuTss {
+        tss: Mutex::new(tss),
+        double_fault_stack,
+        privilege_stack,
+    }));
+
+    // The GDT's TSS descriptor refers to the TSS's address directly, so it must be mapped as well.
+    let tss_ptr = &mut *cpu_tss.tss.lock() as *mut TaskStateSegment;
+    let tss_start = VirtualAddress::new(tss_ptr as usize)
+        .ok_or("the TSS's address was not canonical")?;
+    check_writable(tss_start, "TSS start")?;
+    check_writable(tss_start + (size_of::<TaskStateSegment>() - 1), "TSS end")?;
+
+    // insert into TSS list, and cache a pointer to it for the fast path
+    TSS.insert(cpu_id, cpu_tss);
+    tss_ptr_slot.store(tss_ptr, Ordering::Release);
+
+    // log::debug!("Created TSS for CPU {}, TSS: {:?}", cpu_id, cpu_tss.tss);
+    Ok(&cpu_tss.tss)
+}
+
+/// Returns a new TSS that uses the given double fault stack and, optionally, privilege stack.
+///
+/// Unlike [`create_tss()`], this neither checks the stacks nor adds the TSS to the per-CPU list,
+/// so it's usable before memory management is initialized.
+pub fn new_tss(
+    double_fault_stack_top_unusable: VirtualAddress,
+    privilege_stack_top_unusable: Option<VirtualAddress>,
+) -> TaskStateSegment {
     let mut tss = TaskStateSegment::new();
     // TSS.RSP0 is used in kernel space after a transition from Ring 3 -> Ring 0
-    tss.privilege_stack_table[0] = x86_64::VirtAddr::new(privilege_stack_top_unusable.value() as u64);
+    if let Some(privilege_stack_top) = privilege_stack_top_unusable {
+        tss.privilege_stack_table[0] = x86_64::VirtAddr::new(privilege_stack_top.value() as u64);
+    }
     tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX] = x86_64::VirtAddr::new(double_fault_stack_top_unusable.value() as u64);
+    tss
+}
 
-    // insert into TSS list
-    TSS.insert(cpu_id, Mutex::new(tss));
-    let tss_ref = TSS.get(&cpu_id).unwrap(); // safe to unwrap since we just added it to the list
-    // log::debug!("Created TSS for CPU {}, TSS: {:?}", cpu_id, tss_ref);
-    tss_ref
-}
\ No newline at end of file
+/// Returns an error if the given `vaddr` is not mapped as writable in the currently-active page table.
+fn check_writable(vaddr: VirtualAddress, what: &'static str) -> Result<(), &'static str> {
+    match memory::page_flags(vaddr) {
+        Some(flags) if flags.is_writable() => Ok(()),
+        Some(flags) => {
+            log::error!("create_tss(): {} at {:#X} was mapped as read-only, flags: {:?}", what, vaddr, flags);
+            Err("create_tss(): the TSS or one of its stacks was mapped as read-only")
+        }
+        None => {
+            log::error!("create_tss(): {} at {:#X} was not mapped", what, vaddr);
+            Err("create_tss(): the TSS or one of its stacks was not mapped")
+        }
+    }
+}
@@ -1,35 +1,62 @@
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use crate::Channel;
-use alloc::vec::Vec;
+use alloc::{boxed::Box, collections::VecDeque, string::String, sync::Arc, vec::Vec};
 use sync_channel::{new_channel, Receiver, Sender};
 use core2::io::Result;
 use sync_block::Mutex;
+use task::{CleanupGuard, CleanupReason};
 
 // FIXME: Ctrl+C, Ctrl+Z, etc.
 
+/// The default number of lines kept in a line discipline's history.
+pub const DEFAULT_HISTORY_DEPTH: usize = 100;
+
+const ERASE: u8 = 0x7f; // DEL (backspace key)
+const BACKSPACE: u8 = 0x8; // ^H
+const WERASE: u8 = 0x17; // ^W
+const KILL: u8 = 0x15; // ^U
+const ESCAPE: u8 = 0x1b;
+
+const INTERRUPT: u8 = 0x3;
+const SUSPEND: u8 = 0x1a;
+
 /// A TTY line discipline.
 ///
 /// The line discipline can be configured based on what application is using the
 /// slave end. Most applications should use the [`sane`](Self::sane) setting,
 /// which handles line editing and echoing to the terminal. Applications that
 /// require more control over the display should use the [`raw`](Self::raw)
-/// setting.
+/// setting, ideally via [`enter_raw_mode`](Self::enter_raw_mode).
 ///
 /// The line discipline's behaviour is documented in terms of Linux `termios`
 /// flags. For more information, visit the [`cfmakeraw`
 /// documentation][cfmakeraw].
 ///
+/// In canonical mode, the following editing keys are supported:
+/// - Backspace (`DEL` or `^H`) erases the last character,
+/// - `^W` erases the last word,
+/// - `^U` erases the whole line, and
+/// - the up and down arrow keys recall older and newer lines from the history.
+///
+/// Each complete line is recorded in the history, which is kept for as long as
+/// the line discipline exists, i.e. across all applications run in its
+/// terminal. Consecutive duplicate lines are only recorded once.
+///
 /// The line discipline prepends a carriage return to all line feeds on output.
 /// This behaviour is equivalent to `ONLCR` on Linux.
 ///
 /// [cfmakeraw]: https://linux.die.net/man/3/cfmakeraw
 pub struct LineDiscipline {
     echo: AtomicBool,
-    /// The input buffer for canonical mode.
+    canonical: AtomicBool,
+    /// Whether the input buffer must be discarded before processing more input,
+    /// e.g. because canonical mode was toggled.
     ///
-    /// If `None`, canonical mode is disabled
-    canonical: Mutex<Option<Vec<u8>>>,
+    /// This allows the mode to be changed without acquiring the editor's lock,
+    /// which is necessary in task cleanup hooks.
+    discard_input: AtomicBool,
+    editor: Mutex<Editor>,
     manager: Sender<Event>,
 }
 
@@ -40,6 +67,51 @@ pub enum Event {
     CtrlZ,
 }
 
+/// The line editing state for canonical mode.
+struct Editor {
+    /// The input buffer of the line being edited.
+    input: Vec<u8>,
+    /// The previously entered lines, oldest first.
+    history: VecDeque<Vec<u8>>,
+    history_depth: usize,
+    /// The index in `history` of the line being edited, if it was recalled.
+    recalled: Option<usize>,
+    /// The line that was being edited before a line was recalled.
+    stashed: Vec<u8>,
+    escape: EscapeState,
+}
+
+/// How much of an escape sequence, e.g. from an arrow key, has been received.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum EscapeState {
+    None,
+    /// Received `ESC`.
+    Escape,
+    /// Received `ESC [`, i.e. the start of a control sequence.
+    ControlSequence,
+}
+
+impl Editor {
+    /// Discards the line being edited.
+    fn clear(&mut self) {
+        self.input.clear();
+        self.recalled = None;
+        self.stashed.clear();
+        self.escape = EscapeState::None;
+    }
+
+    /// Records the given complete `line` in the history.
+    fn record(&mut self, line: &[u8]) {
+        if line.is_empty() || self.history_depth == 0 || self.history.back().map(Vec::as_slice) == Some(line) {
+            return;
+        }
+        if self.history.len() == self.history_depth {
+            self.history.pop_front();
+        }
+        self.history.push_back(line.to_vec());
+    }
+}
+
 impl Default for LineDiscipline {
     /// Equivalent to [`Self::new`].
     fn default() -> Self {
@@ -53,7 +125,16 @@ impl LineDiscipline {
         let (sender, _) = new_channel(16);
         Self {
             echo: AtomicBool::new(true),
-            canonical: Mutex::new(Some(Vec::new())),
+            canonical: AtomicBool::new(true),
+            discard_input: AtomicBool::new(false),
+            editor: Mutex::new(Editor {
+                input: Vec::new(),
+                history: VecDeque::new(),
+                history_depth: DEFAULT_HISTORY_DEPTH,
+                recalled: None,
+                stashed: Vec::new(),
+                escape: EscapeState::None,
+            }),
             manager: sender,
         }
     }
@@ -84,6 +165,24 @@ impl LineDiscipline {
         self.set_canonical(false);
     }
 
+    /// Sets the line discipline to raw mode until the returned guard is dropped
+    /// or the current task exits, whichever comes first.
+    ///
+    /// The line discipline is then reset to [sane](Self::set_sane) defaults,
+    /// such that an application that crashes or is killed while in raw mode
+    /// doesn't leave its terminal unusable.
+    pub fn enter_raw_mode(self: &Arc<Self>) -> RawModeGuard {
+        let discipline = self.clone();
+        let cleanup = CleanupGuard::new(Box::new(move |_: CleanupReason| {
+            discipline.set_sane()
+        }));
+        self.set_raw();
+        RawModeGuard {
+            discipline: self.clone(),
+            _cleanup: cleanup,
+        }
+    }
+
     pub fn echo(&self) -> bool {
         self.echo.load(Ordering::SeqCst)
     }
@@ -99,7 +198,7 @@ impl LineDiscipline {
     ///
     /// This is equivalent to `ICANON | ICRNL` on Linux.
     pub fn canonical(&self) -> bool {
-        self.canonical.lock().is_some()
+        self.canonical.load(Ordering::SeqCst)
     }
 
     pub fn event_receiver(&self) -> Receiver<Event> {
@@ -114,19 +213,35 @@ impl LineDiscipline {
     /// Sets the canonical flag.
     ///
     /// This is equivalent to `ICANON` on Linux.
+    ///
+    /// Any partially edited line is discarded.
     pub fn set_canonical(&self, canonical: bool) {
-        if canonical {
-            *self.canonical.lock() = Some(Vec::new());
-        } else {
-            // TODO: Flush buffer?
-            *self.canonical.lock() = None;
-        }
+        self.canonical.store(canonical, Ordering::SeqCst);
+        self.discard_input.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns the lines in the history, oldest first.
+    pub fn history(&self) -> Vec<String> {
+        self.editor
+            .lock()
+            .history
+            .iter()
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .collect()
     }
 
-    fn clear_input_buf(&self, canonical: Option<&mut Vec<u8>>) {
-        if let Some(input_buf) = canonical {
-            input_buf.clear();
+    /// Sets the maximum number of lines kept in the history, discarding the
+    /// oldest lines if there are more.
+    ///
+    /// The default depth is [`DEFAULT_HISTORY_DEPTH`]. A depth of zero disables
+    /// the history.
+    pub fn set_history_depth(&self, depth: usize) {
+        let mut editor = self.editor.lock();
+        editor.history_depth = depth;
+        while editor.history.len() > depth {
+            editor.history.pop_front();
         }
+        editor.recalled = None;
     }
 
     pub(crate) fn process_input_byte(
@@ -135,8 +250,8 @@ impl LineDiscipline {
         master: &Channel,
         slave: &Channel,
     ) -> Result<()> {
-        let mut canonical = self.canonical.lock();
-        self.process_input_byte_internal(byte, master, slave, (*canonical).as_mut())
+        let mut editor = self.editor.lock();
+        self.process_input_byte_internal(byte, master, slave, &mut editor)
     }
 
     fn process_input_byte_internal(
@@ -144,91 +259,150 @@ impl LineDiscipline {
         byte: u8,
         master: &Channel,
         slave: &Channel,
-        canonical: Option<&mut Vec<u8>>,
+        editor: &mut Editor,
     ) -> Result<()> {
-        const ERASE: u8 = 0x7f; // DEL (backspace key)
-        const WERASE: u8 = 0x17; // ^W
-
-        const INTERRUPT: u8 = 0x3;
-        const SUSPEND: u8 = 0x1a;
+        if self.discard_input.swap(false, Ordering::SeqCst) {
+            editor.clear();
+        }
 
         match byte {
             INTERRUPT => {
                 let _ = self.manager.send(Event::CtrlC);
-                self.clear_input_buf(canonical);
+                editor.clear();
                 return Ok(());
             }
             SUSPEND => {
                 let _ = self.manager.send(Event::CtrlZ);
-                self.clear_input_buf(canonical);
+                editor.clear();
                 return Ok(());
             }
             _ => {}
         }
 
-        // TODO: EOF and EOL
-        // TODO: UTF-8?
-        if self.echo.load(Ordering::SeqCst) {
-            match (byte, &canonical) {
-                // TODO: Also pass-through START and STOP characters
-                (b'\t' | b'\n', _) => {
-                    master.send(byte)?;
-                }
-                (ERASE, Some(input_buf)) => {
-                    if !input_buf.is_empty() {
-                        master.send_all([0x8, b' ', 0x8])?
-                    }
-                }
-                (WERASE, Some(input_buf)) => {
-                    if !input_buf.is_empty() {
-                        // TODO: Cache offset. Currently we're calculating it twice: once here,
-                        // and once if canonical mode is enabled.
-                        let offset = werase(input_buf);
-                        for _ in 0..offset {
-                            master.send_all([0x8, b' ', 0x8])?;
-                        }
+        let echo = self.echo.load(Ordering::SeqCst);
+        if !self.canonical.load(Ordering::SeqCst) {
+            if echo {
+                echo_byte(byte, master)?;
+            }
+            return slave.send(byte);
+        }
+
+        // Escape sequences are consumed by the line editor rather than echoed.
+        match editor.escape {
+            EscapeState::None if byte == ESCAPE => {
+                editor.escape = EscapeState::Escape;
+                return Ok(());
+            }
+            EscapeState::None => {}
+            EscapeState::Escape => {
+                editor.escape = if byte == b'[' {
+                    EscapeState::ControlSequence
+                } else {
+                    EscapeState::None
+                };
+                return Ok(());
+            }
+            EscapeState::ControlSequence => {
+                // Parameter bytes precede the final byte of a control sequence.
+                if (0x40..=0x7e).contains(&byte) {
+                    editor.escape = EscapeState::None;
+                    match byte {
+                        b'A' => self.recall(editor, master, true)?,
+                        b'B' => self.recall(editor, master, false)?,
+                        _ => {}
                     }
                 }
-                (0..=0x1f, _) => {
-                    master.send_all([b'^', byte + 0x40])?;
-                }
-                _ => {
-                    master.send(byte)?;
-                }
+                return Ok(());
             }
         }
 
-        if let Some(input_buf) = canonical {
-            match byte {
-                b'\r' | b'\n' => {
-                    slave.send_all(core::mem::take(input_buf))?;
-                    slave.send(b'\n')?;
-                }
-                ERASE => {
-                    input_buf.pop();
+        // TODO: EOF and EOL
+        // TODO: UTF-8?
+        match byte {
+            b'\r' | b'\n' => {
+                if echo {
+                    master.send_all([b'\r', b'\n'])?;
                 }
-                WERASE => {
-                    for _ in 0..werase(input_buf) {
-                        input_buf.pop();
-                    }
+                let line = core::mem::take(&mut editor.input);
+                editor.record(&line);
+                editor.clear();
+                slave.send_all(line)?;
+                slave.send(b'\n')?;
+            }
+            ERASE | BACKSPACE => self.erase(editor, master, 1)?,
+            WERASE => {
+                let count = werase(&editor.input);
+                self.erase(editor, master, count)?;
+            }
+            KILL => {
+                let count = editor.input.len();
+                self.erase(editor, master, count)?;
+            }
+            _ => {
+                editor.input.push(byte);
+                if echo {
+                    echo_byte(byte, master)?;
                 }
-                _ => input_buf.push(byte),
             }
-        } else {
-            slave.send(byte)?;
         }
         Ok(())
     }
 
+    /// Erases up to `count` characters from the end of the line being edited.
+    fn erase(&self, editor: &mut Editor, master: &Channel, count: usize) -> Result<()> {
+        let count = count.min(editor.input.len());
+        editor.input.truncate(editor.input.len() - count);
+        if self.echo.load(Ordering::SeqCst) {
+            for _ in 0..count {
+                master.send_all([0x8, b' ', 0x8])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the line being edited with an older or newer line from the
+    /// history.
+    ///
+    /// Moving past the newest line restores the line that was being edited
+    /// before any line was recalled.
+    fn recall(&self, editor: &mut Editor, master: &Channel, older: bool) -> Result<()> {
+        let len = editor.history.len();
+        let recalled = match (editor.recalled, older) {
+            (None, true) if len > 0 => Some(len - 1),
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < len => Some(i + 1),
+            (Some(_), false) => None,
+            (None, _) => return Ok(()),
+        };
+        if editor.recalled.is_none() {
+            editor.stashed = core::mem::take(&mut editor.input);
+        }
+        let line = match recalled {
+            Some(i) => editor.history[i].clone(),
+            None => core::mem::take(&mut editor.stashed),
+        };
+        editor.recalled = recalled;
+
+        let count = editor.input.len();
+        self.erase(editor, master, count)?;
+        if self.echo.load(Ordering::SeqCst) {
+            for byte in line.iter() {
+                echo_byte(*byte, master)?;
+            }
+        }
+        editor.input = line;
+        Ok(())
+    }
+
     pub(crate) fn process_input_buf(
         &self,
         buf: &[u8],
         master: &Channel,
         slave: &Channel,
     ) -> Result<()> {
-        let mut canonical = self.canonical.lock();
+        let mut editor = self.editor.lock();
         for byte in buf {
-            self.process_input_byte_internal(*byte, master, slave, (*canonical).as_mut())?;
+            self.process_input_byte_internal(*byte, master, slave, &mut editor)?;
         }
         Ok(())
     }
@@ -249,6 +423,29 @@ impl LineDiscipline {
     }
 }
 
+/// Keeps a [`LineDiscipline`] in raw mode; see
+/// [`LineDiscipline::enter_raw_mode`].
+pub struct RawModeGuard {
+    discipline: Arc<LineDiscipline>,
+    _cleanup: Option<CleanupGuard>,
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        self.discipline.set_sane();
+    }
+}
+
+/// Echoes the given input `byte`, showing control characters in caret notation.
+fn echo_byte(byte: u8, master: &Channel) -> Result<()> {
+    match byte {
+        // TODO: Also pass-through START and STOP characters
+        b'\t' | b'\n' => master.send(byte),
+        0..=0x1f => master.send_all([b'^', byte + 0x40]),
+        _ => master.send(byte),
+    }
+}
+
 /// Returns how many characters need to be removed to erase a word.
 const fn werase(buf: &[u8]) -> usize {
     let len = buf.len();
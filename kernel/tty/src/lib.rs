@@ -10,9 +10,9 @@ extern crate alloc;
 mod channel;
 mod discipline;
 
-pub use discipline::{Event, LineDiscipline};
+pub use discipline::{Event, LineDiscipline, RawModeGuard, DEFAULT_HISTORY_DEPTH};
 
-use alloc::sync::Arc;
+use alloc::{string::String, sync::Arc, vec::Vec};
 use channel::Channel;
 use core2::io::{Read, Result, Write};
 
@@ -149,6 +149,22 @@ impl Slave {
         self.slave.receive_buf(buf)
     }
 
+    /// Reads a line, blocking until a complete line is available, and returns
+    /// it without its trailing line feed.
+    ///
+    /// In canonical mode, the line discipline only delivers a line once it has
+    /// been fully edited, so this returns exactly one line entered by the user.
+    pub fn read_line(&self) -> Result<String> {
+        let mut line = Vec::new();
+        loop {
+            match self.slave.receive()? {
+                b'\n' => break,
+                byte => line.push(byte),
+            }
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
     pub fn try_read(&self, buf: &mut [u8]) -> Result<usize> {
         self.slave.try_receive_buf(buf)
     }
@@ -144,7 +144,9 @@ impl Pl011 {
     ///
     /// Spins until a byte is available in the fifo.
     pub fn read_byte(&self) -> u8 {
-        while !self.has_incoming_data() {}
+        while !self.has_incoming_data() {
+            core::hint::spin_loop();
+        }
         self.regs.uartdr.read() as u8
     }
 
@@ -170,7 +172,9 @@ impl Pl011 {
     ///
     /// Spins until space is available in the fifo.
     pub fn write_byte(&mut self, data: u8) {
-        while !self.is_writeable() {}
+        while !self.is_writeable() {
+            core::hint::spin_loop();
+        }
         self.regs.uartdr.write(data as u32);
     }
 
@@ -15,7 +15,7 @@ pub trait WaitConditionFn = Fn() -> bool;
 
 /// A condition variable that allows multiple `Task`s to wait for a condition to be met,
 /// upon which other `Task`s can notify them.
-/// This is effectively a convenience wrapper around `WaitQueue::wait_until()`.  
+/// This is effectively a convenience wrapper around `WaitQueue::block_on()`.  
 /// 
 /// The condition is specified as an closure that returns a boolean: 
 /// `true` if the condition has been met, `false` if not. 
@@ -53,13 +53,7 @@ impl<F: Fn() -> bool> WaitCondition<F> {
         if (self.condition_fn)() {
             return;
         }
-        self.wait_queue.wait_until(&|| {
-            if (self.condition_fn)() {
-                Some(())
-            } else {
-                None
-            }
-        })
+        self.wait_queue.block_on(&self.condition_fn)
     }
 
     /// This function should be invoked after the wait condition has been met
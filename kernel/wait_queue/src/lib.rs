@@ -5,7 +5,7 @@ use mpmc_queue::Queue;
 use preemption::hold_preemption;
 use sync::DeadlockPrevention;
 use sync_spin::Spin;
-use task::{get_my_current_task, TaskRef};
+use task::{get_my_current_task, TaskRef, WakeReason};
 
 /// A queue of tasks waiting for an event to occur.
 ///
@@ -69,6 +69,27 @@ where
         }
     }
 
+    /// Blocks the current task until the given `condition` returns `true`.
+    ///
+    /// This is a convenience wrapper around [`wait_until()`] for conditions
+    /// that don't produce a value. Whoever makes the condition true must then
+    /// call [`notify_one()`] or [`notify_all()`] on this same wait queue.
+    ///
+    /// The condition is checked while holding this queue's internal lock,
+    /// and the current task is blocked and enqueued under that same lock,
+    /// so a notification cannot be lost in between checking the condition and blocking.
+    /// The condition is re-checked after every wakeup, so spurious wakeups are harmless.
+    ///
+    /// [`wait_until()`]: Self::wait_until
+    /// [`notify_one()`]: Self::notify_one
+    /// [`notify_all()`]: Self::notify_all
+    pub fn block_on<F>(&self, condition: F)
+    where
+        F: Fn() -> bool,
+    {
+        self.wait_until(|| condition().then_some(()))
+    }
+
     /// Notifies the first task in the wait queue.
     ///
     /// If it fails to unblock the first task, it will continue unblocking
@@ -80,7 +101,7 @@ where
                 None => return false,
             };
 
-            if task.unblock().is_ok() {
+            if task.unblock_with_reason(WakeReason::Condition).is_ok() {
                 return true;
             }
         }
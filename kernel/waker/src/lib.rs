@@ -10,7 +10,7 @@
 
 extern crate alloc;
 
-use task::{ScheduleOnDrop, TaskRef};
+use task::{ScheduleOnDrop, TaskRef, WakeReason};
 
 /// Creates a new waker and blocker pair that are associated with each other.
 ///
@@ -20,7 +20,7 @@ pub fn new_waker() -> (core::task::Waker, Blocker) {
         .expect("waker::new_waker(): failed to get current task");
     let task_to_block = curr_task.clone();
     let wake_action = move || {
-        let _ = curr_task.unblock();
+        let _ = curr_task.unblock_with_reason(WakeReason::Condition);
     };
     let (waker, blocker_generic) = waker_generic::new_waker(wake_action);
     (
@@ -0,0 +1,168 @@
+//! Formatted output into a fixed-size buffer, without any heap allocation.
+//!
+//! Unlike `format!()`, a [`FixedWriter`] can be used in contexts where allocating
+//! is impossible or unsafe, e.g., when reporting heap exhaustion or corruption,
+//! or from a panic handler that may have been invoked by the allocator itself.
+//!
+//! Output that doesn't fit into the buffer is truncated at a character boundary,
+//! and the writer remembers that truncation occurred such that it can be marked
+//! when the writer's contents are displayed.
+//!
+//! This crate also offers helpers for commonly-formatted output:
+//! * [`write_rows()`]: a table of name/value rows with aligned values.
+//! * [`write_hex_dump()`]: a hex dump of a byte slice, with offsets.
+
+#![no_std]
+
+#[cfg(test)]
+mod test;
+
+use core::fmt::{self, Write};
+
+/// The marker appended when displaying a [`FixedWriter`] whose output was truncated.
+pub const TRUNCATION_MARKER: &str = "...<truncated>";
+
+/// A [`core::fmt::Write`] implementation over a fixed-size buffer of `N` bytes.
+///
+/// Typically, this lives on the stack, e.g.:
+/// ```
+/// use core::fmt::Write;
+/// let mut w = fixed_writer::FixedWriter::<64>::new();
+/// write!(w, "{} + {} = {}", 1, 2, 1 + 2).unwrap();
+/// assert_eq!(w.as_str(), "1 + 2 = 3");
+/// ```
+///
+/// Writing never fails; output that doesn't fit is discarded and
+/// [`is_truncated()`](Self::is_truncated) will return `true`.
+pub struct FixedWriter<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    truncated: bool,
+}
+
+impl<const N: usize> FixedWriter<N> {
+    /// Returns a new empty `FixedWriter`.
+    pub const fn new() -> Self {
+        FixedWriter { buf: [0; N], len: 0, truncated: false }
+    }
+
+    /// Returns the content written thus far, excluding the truncation marker.
+    pub fn as_str(&self) -> &str {
+        // SAFETY: only complete UTF-8 characters are ever copied into the buffer.
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+
+    /// Returns `true` if some output didn't fit into this writer and was discarded.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Returns the number of bytes written thus far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if nothing has been written yet.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Clears this writer's content, allowing it to be reused.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.truncated = false;
+    }
+}
+
+impl<const N: usize> Default for FixedWriter<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for FixedWriter<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            return Ok(());
+        }
+        let remaining = N - self.len;
+        let mut count = s.len();
+        if count > remaining {
+            // Only copy whole characters so that the content remains valid UTF-8.
+            count = remaining;
+            while !s.is_char_boundary(count) {
+                count -= 1;
+            }
+            self.truncated = true;
+        }
+        self.buf[self.len .. self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
+
+/// Displays the content of this writer, followed by the [`TRUNCATION_MARKER`] if it was truncated.
+impl<const N: usize> fmt::Display for FixedWriter<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())?;
+        if self.truncated {
+            f.write_str(TRUNCATION_MARKER)?;
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> fmt::Debug for FixedWriter<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FixedWriter")
+            .field("content", &self.as_str())
+            .field("truncated", &self.truncated)
+            .finish()
+    }
+}
+
+
+/// Writes a table of name/value rows, one per line, with all values aligned
+/// in a column after the longest name.
+///
+/// For example, `[("free", &12), ("allocated", &4)]` is written as:
+/// ```text
+/// free      : 12
+/// allocated : 4
+/// ```
+pub fn write_rows<W: Write>(w: &mut W, rows: &[(&str, &dyn fmt::Display)]) -> fmt::Result {
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, value) in rows {
+        writeln!(w, "{:<width$} : {}", name, value, width = width)?;
+    }
+    Ok(())
+}
+
+/// The number of bytes shown on each line of a hex dump.
+const HEX_DUMP_BYTES_PER_LINE: usize = 16;
+
+/// Writes a hex dump of the given `bytes`, 16 bytes per line.
+///
+/// Each line begins with the offset of its first byte, starting from `base_offset`,
+/// and ends with the printable ASCII representation of its bytes, e.g.:
+/// ```text
+/// 00001000: 48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 0a 00 00  |Hello, world!...|
+/// ```
+pub fn write_hex_dump<W: Write>(w: &mut W, bytes: &[u8], base_offset: usize) -> fmt::Result {
+    for (i, line) in bytes.chunks(HEX_DUMP_BYTES_PER_LINE).enumerate() {
+        write!(w, "{:08x}:", base_offset + i * HEX_DUMP_BYTES_PER_LINE)?;
+        for byte in line {
+            write!(w, " {:02x}", byte)?;
+        }
+        for _ in line.len() .. HEX_DUMP_BYTES_PER_LINE {
+            w.write_str("   ")?;
+        }
+        w.write_str("  |")?;
+        for byte in line {
+            let c = if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' };
+            w.write_char(c)?;
+        }
+        w.write_str("|\n")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+extern crate std;
+
+use super::*;
+use core::fmt::Write;
+use std::string::ToString;
+
+#[test]
+fn write_fits() {
+    let mut w = FixedWriter::<16>::new();
+    write!(w, "abc{}", 123).unwrap();
+    assert_eq!(w.as_str(), "abc123");
+    assert!(!w.is_truncated());
+    assert_eq!(w.to_string(), "abc123");
+}
+
+#[test]
+fn write_truncates() {
+    let mut w = FixedWriter::<8>::new();
+    write!(w, "0123456789").unwrap();
+    assert_eq!(w.as_str(), "01234567");
+    assert!(w.is_truncated());
+    assert_eq!(w.to_string(), "01234567...<truncated>");
+
+    // Nothing more is written after truncation.
+    write!(w, "x").unwrap();
+    assert_eq!(w.len(), 8);
+
+    w.clear();
+    assert!(w.is_empty());
+    assert!(!w.is_truncated());
+}
+
+#[test]
+fn write_truncates_at_char_boundary() {
+    let mut w = FixedWriter::<4>::new();
+    // Both 'é' and '€' are multi-byte characters; only whole characters are written.
+    write!(w, "abé€").unwrap();
+    assert_eq!(w.as_str(), "abé");
+    let mut w = FixedWriter::<3>::new();
+    write!(w, "abé").unwrap();
+    assert_eq!(w.as_str(), "ab");
+    assert!(w.is_truncated());
+}
+
+#[test]
+fn rows() {
+    let mut w = FixedWriter::<64>::new();
+    write_rows(&mut w, &[("free", &12), ("allocated", &4)]).unwrap();
+    assert_eq!(w.as_str(), "free      : 12\nallocated : 4\n");
+}
+
+#[test]
+fn hex_dump() {
+    let mut w = FixedWriter::<256>::new();
+    write_hex_dump(&mut w, b"Hello, world!\n\0\0ab", 0x1000).unwrap();
+    assert_eq!(
+        w.as_str(),
+        "00001000: 48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 0a 00 00  |Hello, world!...|\n\
+         00001010: 61 62                                            |ab|\n"
+    );
+}
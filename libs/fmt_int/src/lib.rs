@@ -0,0 +1,62 @@
+//! Formatting of integers into caller-provided stack buffers.
+//!
+//! These functions neither allocate nor acquire any locks, and don't use `core::fmt`,
+//! so they can be used to print values from interrupt handlers and other contexts
+//! in which `format!()` is unsafe, e.g., for emitting tick counts, scancodes, and addresses.
+//!
+//! Each buffer type is exactly large enough to hold the longest possible output.
+
+#![no_std]
+
+#[cfg(test)]
+mod test;
+
+/// The length of the longest hexadecimal string produced by [`u64_to_hex()`]:
+/// the `0x` prefix plus 16 digits.
+pub const HEX_BUF_LEN: usize = 2 + 16;
+/// The length of the longest decimal string produced by [`u64_to_dec()`]:
+/// `u64::MAX` has 20 digits.
+pub const DEC_BUF_LEN: usize = 20;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Formats `val` as a `0x`-prefixed lowercase hexadecimal string without leading zeros,
+/// e.g., `0x0` or `0xdeadbeef`.
+///
+/// The returned string is a slice of the given `buf`.
+pub fn u64_to_hex(buf: &mut [u8; HEX_BUF_LEN], mut val: u64) -> &str {
+    let mut start = HEX_BUF_LEN;
+    loop {
+        start -= 1;
+        buf[start] = HEX_DIGITS[(val & 0xF) as usize];
+        val >>= 4;
+        if val == 0 {
+            break;
+        }
+    }
+    start -= 2;
+    buf[start] = b'0';
+    buf[start + 1] = b'x';
+    as_str(&buf[start..])
+}
+
+/// Formats `val` as a decimal string without leading zeros, e.g., `0` or `1234`.
+///
+/// The returned string is a slice of the given `buf`.
+pub fn u64_to_dec(buf: &mut [u8; DEC_BUF_LEN], mut val: u64) -> &str {
+    let mut start = DEC_BUF_LEN;
+    loop {
+        start -= 1;
+        buf[start] = b'0' + (val % 10) as u8;
+        val /= 10;
+        if val == 0 {
+            break;
+        }
+    }
+    as_str(&buf[start..])
+}
+
+fn as_str(bytes: &[u8]) -> &str {
+    // SAFETY: the above functions only write ASCII digits and `x`.
+    unsafe { core::str::from_utf8_unchecked(bytes) }
+}
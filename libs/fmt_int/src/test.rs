@@ -0,0 +1,55 @@
+extern crate std;
+
+use super::*;
+use std::format;
+
+#[test]
+fn hex_zero() {
+    let mut buf = [0; HEX_BUF_LEN];
+    assert_eq!(u64_to_hex(&mut buf, 0), "0x0");
+}
+
+#[test]
+fn hex_max_fills_buffer() {
+    let mut buf = [0; HEX_BUF_LEN];
+    let s = u64_to_hex(&mut buf, u64::MAX);
+    assert_eq!(s, "0xffffffffffffffff");
+    assert_eq!(s.len(), HEX_BUF_LEN);
+}
+
+#[test]
+fn hex_matches_core_fmt() {
+    let mut buf = [0; HEX_BUF_LEN];
+    for val in [1, 0xF, 0x10, 0xdeadbeef, 1 << 63, u64::MAX - 1] {
+        assert_eq!(u64_to_hex(&mut buf, val), format!("{:#x}", val));
+    }
+}
+
+#[test]
+fn dec_zero() {
+    let mut buf = [0; DEC_BUF_LEN];
+    assert_eq!(u64_to_dec(&mut buf, 0), "0");
+}
+
+#[test]
+fn dec_max_fills_buffer() {
+    let mut buf = [0; DEC_BUF_LEN];
+    let s = u64_to_dec(&mut buf, u64::MAX);
+    assert_eq!(s, "18446744073709551615");
+    assert_eq!(s.len(), DEC_BUF_LEN);
+}
+
+#[test]
+fn dec_matches_core_fmt() {
+    let mut buf = [0; DEC_BUF_LEN];
+    for val in [1, 9, 10, 99, 100, 1234567890, 10_000_000_000_000_000_000, u64::MAX - 1] {
+        assert_eq!(u64_to_dec(&mut buf, val), format!("{}", val));
+    }
+}
+
+#[test]
+fn buffer_reuse() {
+    let mut buf = [0; DEC_BUF_LEN];
+    assert_eq!(u64_to_dec(&mut buf, u64::MAX), "18446744073709551615");
+    assert_eq!(u64_to_dec(&mut buf, 42), "42");
+}
@@ -1,5 +1,14 @@
 //! CPU-level input/output instructions, including `inb`, `outb`, etc., and
 //! a high level Rust wrapper.
+//!
+//! This is the only port I/O abstraction in Theseus, and all drivers should use
+//! [`Port`], [`PortReadOnly`], or [`PortWriteOnly`] rather than the raw instructions.
+//! These wrappers share one safety contract:
+//! * Creating a port is safe, as it merely records the port's address.
+//! * Reading from a port is safe.
+//! * Writing to a port is `unsafe`, because writing to an arbitrary port
+//!   can reconfigure hardware in ways that violate memory safety, e.g., by starting DMA.
+//!   The caller must ensure that the port belongs to the device it intends to program.
 
 #![no_std]
 
@@ -20,7 +29,7 @@ pub trait PortIn {
     unsafe fn port_in(port: u16) -> Self;
 }
 
-/// This trait is defined for any type which can be read from a port.
+/// This trait is defined for any type which can be written to a port.
 /// x86 processors support Port IO for `u8`, `u16` and `u32`.
 pub trait PortOut {
     /// Write a value to the specified port.
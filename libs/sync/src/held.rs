@@ -0,0 +1,245 @@
+//! Tracks the locks held by each task, such that they can be forcibly released
+//! if that task is exited without unwinding, e.g., after a machine exception.
+//!
+//! A [`DeadlockPrevention`](crate::DeadlockPrevention) method opts into tracking via
+//! [`TRACK_HOLDER`](crate::DeadlockPrevention::TRACK_HOLDER). Every lock acquired with such a method
+//! is recorded in the current task's [`HeldLocks`] until its guard is dropped.
+//! As this crate can't depend on the task crate, the latter provides the current task's list
+//! via [`set_with_current_held_locks_func()`]; until then, no locks are recorded.
+
+use crate::spin;
+use core::{
+    mem::{self, size_of},
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The maximum number of locks that can be recorded as held by a single task.
+///
+/// Locks acquired while this many are already held are not recorded,
+/// and thus can't be forcibly released.
+pub const MAX_HELD_LOCKS: usize = 16;
+
+/// A type-erased reference to a held lock, which can be used to forcibly release it.
+#[derive(Clone, Copy)]
+pub struct HeldLock {
+    /// Storage for a (possibly wide) pointer to the lock.
+    lock: [usize; 2],
+    /// Releases the lock stored in `lock`.
+    release: unsafe fn(&[usize; 2]),
+}
+
+impl HeldLock {
+    /// Returns a reference to the given locked `mutex`.
+    pub fn mutex<T: ?Sized>(mutex: &spin::Mutex<T>) -> Self {
+        unsafe fn release<T: ?Sized>(storage: &[usize; 2]) {
+            let mutex = ptr::read(storage.as_ptr() as *const *const spin::Mutex<T>);
+            (*mutex).force_unlock();
+        }
+        Self::new(mutex, release::<T>)
+    }
+
+    /// Returns a reference to the given `rw_lock`, which is locked for shared access.
+    pub fn read<T: ?Sized>(rw_lock: &spin::RwLock<T>) -> Self {
+        unsafe fn release<T: ?Sized>(storage: &[usize; 2]) {
+            let rw_lock = ptr::read(storage.as_ptr() as *const *const spin::RwLock<T>);
+            (*rw_lock).force_read_decrement();
+        }
+        Self::new(rw_lock, release::<T>)
+    }
+
+    /// Returns a reference to the given `rw_lock`, which is locked for exclusive access.
+    pub fn write<T: ?Sized>(rw_lock: &spin::RwLock<T>) -> Self {
+        unsafe fn release<T: ?Sized>(storage: &[usize; 2]) {
+            let rw_lock = ptr::read(storage.as_ptr() as *const *const spin::RwLock<T>);
+            (*rw_lock).force_write_unlock();
+        }
+        Self::new(rw_lock, release::<T>)
+    }
+
+    fn new<L: ?Sized>(lock: &L, release: unsafe fn(&[usize; 2])) -> Self {
+        assert!(size_of::<*const L>() <= size_of::<[usize; 2]>());
+        let mut storage = [0usize; 2];
+        unsafe { ptr::write(storage.as_mut_ptr() as *mut *const L, lock) };
+        HeldLock { lock: storage, release }
+    }
+
+    /// Returns the address of the lock.
+    pub fn addr(&self) -> usize {
+        self.lock[0]
+    }
+
+    /// Forcibly releases the lock.
+    ///
+    /// # Safety
+    /// The lock must still be held by the task that acquired it,
+    /// which must never access the data it protects or release it again.
+    pub unsafe fn force_release(self) {
+        (self.release)(&self.lock)
+    }
+
+    fn is_same_as(&self, other: &HeldLock) -> bool {
+        self.lock == other.lock && self.release as usize == other.release as usize
+    }
+
+    fn into_raw(self) -> [usize; 3] {
+        [self.lock[0], self.lock[1], self.release as usize]
+    }
+
+    /// # Safety
+    /// The given value must have been returned by [`HeldLock::into_raw()`].
+    unsafe fn from_raw(raw: [usize; 3]) -> Self {
+        HeldLock {
+            lock: [raw[0], raw[1]],
+            release: mem::transmute::<usize, unsafe fn(&[usize; 2])>(raw[2]),
+        }
+    }
+}
+
+impl core::fmt::Debug for HeldLock {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("HeldLock").field("addr", &(self.addr() as *const ())).finish_non_exhaustive()
+    }
+}
+
+/// The locks currently held by a task, in order of acquisition.
+///
+/// A task's list is only modified by that task itself, with interrupts disabled
+/// by the function given to [`set_with_current_held_locks_func()`],
+/// so it needs no lock of its own.
+pub struct HeldLocks {
+    locks: [[AtomicUsize; 3]; MAX_HELD_LOCKS],
+    len: AtomicUsize,
+}
+
+impl HeldLocks {
+    /// Returns an empty list.
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: [AtomicUsize; 3] = [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)];
+        HeldLocks {
+            locks: [EMPTY; MAX_HELD_LOCKS],
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of locks in this list.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` if this list has no locks.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes and returns the most recently acquired lock in this list.
+    pub fn pop(&self) -> Option<HeldLock> {
+        let len = self.len();
+        let index = len.checked_sub(1)?;
+        let lock = self.get(index);
+        self.len.store(index, Ordering::Relaxed);
+        Some(lock)
+    }
+
+    /// Appends the given lock, returning `false` if this list is full.
+    fn push(&self, lock: HeldLock) -> bool {
+        let len = self.len();
+        if len == MAX_HELD_LOCKS {
+            return false;
+        }
+        self.set(len, lock);
+        self.len.store(len + 1, Ordering::Relaxed);
+        true
+    }
+
+    /// Removes the most recent occurrence of the given lock, returning whether it was found.
+    fn remove(&self, lock: &HeldLock) -> bool {
+        let len = self.len();
+        // Locks are usually released in reverse order of acquisition, so search from the end.
+        let Some(index) = (0..len).rev().find(|i| self.get(*i).is_same_as(lock)) else {
+            return false;
+        };
+        for i in index..len - 1 {
+            self.set(i, self.get(i + 1));
+        }
+        self.len.store(len - 1, Ordering::Relaxed);
+        true
+    }
+
+    fn get(&self, index: usize) -> HeldLock {
+        let slot = &self.locks[index];
+        let raw = [0, 1, 2].map(|i| slot[i].load(Ordering::Relaxed));
+        // SAFETY: every slot below `len` was written by `set()`.
+        unsafe { HeldLock::from_raw(raw) }
+    }
+
+    fn set(&self, index: usize, lock: HeldLock) {
+        for (word, value) in self.locks[index].iter().zip(lock.into_raw()) {
+            word.store(value, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for HeldLocks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Debug for HeldLocks {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_list().entries((0..self.len()).map(|i| self.get(i))).finish()
+    }
+}
+
+/// The function that invokes its argument with the current task's [`HeldLocks`],
+/// stored as a `usize` because function pointers can't be stored atomically.
+static WITH_CURRENT_HELD_LOCKS_FUNC: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the function that invokes its argument with the current task's [`HeldLocks`],
+/// which must do so with interrupts disabled, and mustn't acquire any tracked locks.
+pub fn set_with_current_held_locks_func(func: fn(&mut dyn FnMut(&HeldLocks))) {
+    WITH_CURRENT_HELD_LOCKS_FUNC.store(func as usize, Ordering::Release);
+}
+
+fn with_current_held_locks(func: &mut dyn FnMut(&HeldLocks)) {
+    let raw = WITH_CURRENT_HELD_LOCKS_FUNC.load(Ordering::Acquire);
+    if raw != 0 {
+        // SAFETY: the only non-zero value is a function pointer stored by `set_with_current_held_locks_func()`.
+        let with_current = unsafe { mem::transmute::<usize, fn(&mut dyn FnMut(&HeldLocks))>(raw) };
+        with_current(func);
+    }
+}
+
+/// Records a lock in the current task's [`HeldLocks`] until it is dropped.
+///
+/// This is part of the guard of every lock acquired with a deadlock prevention method,
+/// and must be dropped before that method's own guard, e.g., before interrupts are re-enabled.
+#[derive(Debug)]
+pub struct HeldLockRecord(Option<HeldLock>);
+
+impl HeldLockRecord {
+    /// Records the given `lock` if `track` is `true`.
+    #[inline]
+    pub(crate) fn new(track: bool, lock: impl FnOnce() -> HeldLock) -> Self {
+        if !track {
+            return HeldLockRecord(None);
+        }
+        let lock = lock();
+        let mut recorded = false;
+        with_current_held_locks(&mut |locks| recorded = locks.push(lock));
+        HeldLockRecord(recorded.then_some(lock))
+    }
+}
+
+impl Drop for HeldLockRecord {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(lock) = self.0.take() {
+            with_current_held_locks(&mut |locks| {
+                locks.remove(&lock);
+            });
+        }
+    }
+}
@@ -9,9 +9,11 @@
 
 #![no_std]
 
+pub mod held;
 pub mod mutex;
 pub mod rw_lock;
 
+pub use held::{HeldLock, HeldLockRecord, HeldLocks};
 pub use mutex::{Mutex, MutexFlavor, MutexGuard};
 pub use rw_lock::{RwLock, RwLockFlavor, RwLockReadGuard, RwLockWriteGuard};
 
@@ -33,6 +35,12 @@ pub trait DeadlockPrevention {
     /// attempting to lock the mutex in `try_lock`.
     const EXPENSIVE: bool;
 
+    /// Whether locks acquired with this method are recorded in the current task's
+    /// [`HeldLocks`], such that they can be forcibly released if the task is exited.
+    ///
+    /// See the [`held`] module.
+    const TRACK_HOLDER: bool = false;
+
     /// Enters the deadlock prevention context.
     fn enter() -> Self::Guard;
 }
@@ -45,7 +53,8 @@ where
 
     type LockData = ();
 
-    type Guard = <Self as DeadlockPrevention>::Guard;
+    // The record is dropped first, i.e., before leaving the deadlock prevention context.
+    type Guard = (HeldLockRecord, <Self as DeadlockPrevention>::Guard);
 
     #[inline]
     fn try_lock<'a, T>(
@@ -60,7 +69,10 @@ where
         }
 
         let deadlock_guard = Self::enter();
-        mutex.try_lock().map(|guard| (guard, deadlock_guard))
+        mutex.try_lock().map(|guard| {
+            let record = HeldLockRecord::new(Self::TRACK_HOLDER, || HeldLock::mutex(mutex));
+            (guard, (record, deadlock_guard))
+        })
     }
 
     #[inline]
@@ -74,7 +86,8 @@ where
         loop {
             let deadlock_guard = Self::enter();
             if let Some(guard) = mutex.try_lock_weak() {
-                return (guard, deadlock_guard);
+                let record = HeldLockRecord::new(Self::TRACK_HOLDER, || HeldLock::mutex(mutex));
+                return (guard, (record, deadlock_guard));
             }
             drop(deadlock_guard);
 
@@ -96,7 +109,8 @@ where
 
     type LockData = ();
 
-    type Guard = <Self as DeadlockPrevention>::Guard;
+    // The record is dropped first, i.e., before leaving the deadlock prevention context.
+    type Guard = (HeldLockRecord, <Self as DeadlockPrevention>::Guard);
 
     #[inline]
     fn try_read<'a, T>(
@@ -111,7 +125,10 @@ where
         }
 
         let deadlock_guard = Self::enter();
-        rw_lock.try_read().map(|guard| (guard, deadlock_guard))
+        rw_lock.try_read().map(|guard| {
+            let record = HeldLockRecord::new(Self::TRACK_HOLDER, || HeldLock::read(rw_lock));
+            (guard, (record, deadlock_guard))
+        })
     }
 
     #[inline]
@@ -129,7 +146,10 @@ where
         }
 
         let deadlock_guard = Self::enter();
-        rw_lock.try_write().map(|guard| (guard, deadlock_guard))
+        rw_lock.try_write().map(|guard| {
+            let record = HeldLockRecord::new(Self::TRACK_HOLDER, || HeldLock::write(rw_lock));
+            (guard, (record, deadlock_guard))
+        })
     }
 
     #[inline]
@@ -143,7 +163,8 @@ where
         loop {
             let deadlock_guard = Self::enter();
             if let Some(guard) = rw_lock.try_read() {
-                return (guard, deadlock_guard);
+                let record = HeldLockRecord::new(Self::TRACK_HOLDER, || HeldLock::read(rw_lock));
+                return (guard, (record, deadlock_guard));
             }
             drop(deadlock_guard);
 
@@ -164,7 +185,8 @@ where
         loop {
             let deadlock_guard = Self::enter();
             if let Some(guard) = rw_lock.try_write_weak() {
-                return (guard, deadlock_guard);
+                let record = HeldLockRecord::new(Self::TRACK_HOLDER, || HeldLock::write(rw_lock));
+                return (guard, (record, deadlock_guard));
             }
             drop(deadlock_guard);
 
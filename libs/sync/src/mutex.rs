@@ -105,6 +105,13 @@ where
     pub fn is_locked(&self) -> bool {
         self.inner.is_locked()
     }
+
+    /// Returns a reference to the additional data stored in this mutex by its flavor.
+    #[doc(hidden)]
+    #[inline]
+    pub fn lock_data(&self) -> &F::LockData {
+        &self.data
+    }
 }
 
 impl<T, F> fmt::Debug for Mutex<T, F>